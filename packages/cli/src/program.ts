@@ -367,14 +367,15 @@ export const buildProgram = ({
 
   program
     .command('migrate')
-    .description('Import legacy FileWork artifacts (.FWM directories or .FWZ archives)')
+    .description('Import legacy FileWork artifacts (.FWM, .FWZ) or .eml/.mbox mail')
     .requiredOption('-p, --path <path>', 'Path to the legacy workspace or archive')
-    .option('-t, --type <mode>', 'Artifact type (auto|fwm|fwz)', 'auto')
+    .option('-t, --type <mode>', 'Artifact type (auto|fwm|fwz|eml|mbox)', 'auto')
     .option('-d, --dry-run', 'Parse inputs without persisting changes', false)
     .option('-r, --resume <jobId>', 'Resume a previously started migration job')
     .option('--limit <count>', 'Maximum number of messages to import')
     .option('--since <iso-date>', 'Only import messages created after the provided timestamp')
     .option('--quarantine <dir>', 'Directory to store quarantined artifacts')
+    .option('--encoding <charset>', 'Charset of the legacy documents, e.g. cp437 or iso-8859-15')
     .option('--json', 'Emit machine-readable JSON summaries', false)
     .action(async (cmdOptions) => {
       const options = program.opts<GlobalOptions>();
@@ -395,6 +396,7 @@ export const buildProgram = ({
           limit: limit ?? undefined,
           since: cmdOptions.since ?? undefined,
           quarantine: cmdOptions.quarantine ?? undefined,
+          encoding: cmdOptions.encoding ?? undefined,
         });
 
         const { jobId } = await transport.migration.import(request);
@@ -425,6 +427,9 @@ export const buildProgram = ({
           console.log(JSON.stringify({ type: 'report', jobId, report }, null, 2));
         } else {
           console.log('Migration completed');
+          if (report.diffText) {
+            console.log(report.diffText);
+          }
           console.log(JSON.stringify(report, null, 2));
         }
 
@@ -0,0 +1,639 @@
+//! Typed client for the core-service HTTP API.
+//!
+//! Covers every route of `api/openapi.json` except the `/ws` event channel,
+//! which needs a WebSocket client rather than request/response calls.
+//! Certificate management has no HTTP route and is not reachable from here.
+//! Requests carry the
+//! configured API key or bearer token. Idempotent calls are retried with
+//! linear backoff after a connection error or HTTP 429, 502, 503 or 504;
+//! other calls are never resent, since the first attempt may have taken
+//! effect. Submissions sent with an `Idempotency-Key` count as idempotent, so
+//! a dropped connection no longer risks a duplicate message.
+
+pub mod models;
+
+use std::io::Read;
+use std::thread;
+use std::time::Duration;
+
+use serde::de::DeserializeOwned;
+use serde::Serialize;
+use thiserror::Error;
+
+pub use models::*;
+
+#[derive(Debug, Error)]
+pub enum ClientError {
+    #[error("core-service returned HTTP {status}: {body}")]
+    Http { status: u16, body: String },
+    #[error("failed to reach core-service: {0}")]
+    Transport(String),
+    #[error("failed to decode core-service response: {0}")]
+    Decode(#[from] std::io::Error),
+}
+
+/// Credentials attached to every request.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum Credentials {
+    None,
+    ApiKey(String),
+    Bearer(String),
+}
+
+/// Blocking client for the core-service API.
+#[derive(Clone, Debug)]
+pub struct CoreServiceClient {
+    base_url: String,
+    agent: ureq::Agent,
+    credentials: Credentials,
+    max_retries: u32,
+    backoff: Duration,
+}
+
+impl CoreServiceClient {
+    pub fn new(base_url: impl Into<String>) -> Self {
+        Self {
+            base_url: base_url.into().trim_end_matches('/').to_string(),
+            agent: ureq::AgentBuilder::new()
+                .timeout(Duration::from_secs(30))
+                .build(),
+            credentials: Credentials::None,
+            max_retries: 2,
+            backoff: Duration::from_millis(250),
+        }
+    }
+
+    pub fn with_api_key(mut self, key: impl Into<String>) -> Self {
+        self.credentials = Credentials::ApiKey(key.into());
+        self
+    }
+
+    pub fn with_bearer_token(mut self, token: impl Into<String>) -> Self {
+        self.credentials = Credentials::Bearer(token.into());
+        self
+    }
+
+    pub fn with_retries(mut self, max_retries: u32, backoff: Duration) -> Self {
+        self.max_retries = max_retries;
+        self.backoff = backoff;
+        self
+    }
+
+    pub fn list_folders(&self) -> Result<Vec<Folder>, ClientError> {
+        self.get("/folders")
+    }
+
+    /// Create a custom folder, nested under `parent_id` when given.
+    pub fn create_folder(
+        &self,
+        name: &str,
+        parent_id: Option<&str>,
+    ) -> Result<Folder, ClientError> {
+        let body = FolderRequest {
+            name: name.to_string(),
+            parent_id: parent_id.map(str::to_string),
+        };
+        self.send_json("POST", "/folders", Some(&body))
+    }
+
+    pub fn rename_folder(&self, id: &str, name: &str) -> Result<Folder, ClientError> {
+        let body = FolderRequest {
+            name: name.to_string(),
+            parent_id: None,
+        };
+        self.send_json("PATCH", &format!("/folders/{}", encode(id)), Some(&body))
+    }
+
+    /// Delete a custom folder and its subfolders, returning `false` when it did not exist.
+    pub fn delete_folder(&self, id: &str) -> Result<bool, ClientError> {
+        let path = format!("/folders/{}", encode(id));
+        not_found_as_none(self.send_empty("DELETE", &path, None::<&()>)).map(|done| done.is_some())
+    }
+
+    pub fn list_messages(&self, folder: &str) -> Result<Vec<MessageEnvelope>, ClientError> {
+        self.get(&format!("/messages?folder={}", encode(folder)))
+    }
+
+    pub fn list_threads(&self, folder: &str) -> Result<Vec<Thread>, ClientError> {
+        self.get(&format!("/threads?folder={}", encode(folder)))
+    }
+
+    /// Archive a conversation, returning how many messages were moved.
+    pub fn archive_thread(&self, thread_id: &str) -> Result<u64, ClientError> {
+        let path = format!("/threads/{}/archive", encode(thread_id));
+        let result: ThreadArchiveResult = self.send_json("POST", &path, None::<&()>)?;
+        Ok(result.archived)
+    }
+
+    /// Fetch a message with its reports, returning `None` on 404.
+    pub fn get_message(&self, id: &str) -> Result<Option<Message>, ClientError> {
+        not_found_as_none(self.get(&format!("/messages/{}", encode(id))))
+    }
+
+    /// Delete a message, returning `false` when it did not exist.
+    pub fn delete_message(&self, id: &str) -> Result<bool, ClientError> {
+        let path = format!("/messages/{}", encode(id));
+        not_found_as_none(self.send_empty("DELETE", &path, None::<&()>)).map(|done| done.is_some())
+    }
+
+    pub fn move_message(&self, id: &str, folder_id: &str) -> Result<bool, ClientError> {
+        let path = format!("/messages/{}/move", encode(id));
+        let body = MoveRequest {
+            folder_id: folder_id.to_string(),
+        };
+        not_found_as_none(self.send_empty("POST", &path, Some(&body))).map(|done| done.is_some())
+    }
+
+    pub fn archive_message(&self, id: &str) -> Result<bool, ClientError> {
+        let path = format!("/messages/{}/archive", encode(id));
+        not_found_as_none(self.send_empty("POST", &path, None::<&()>)).map(|done| done.is_some())
+    }
+
+    /// Apply one action to many messages; unknown ids come back in `missing`.
+    pub fn bulk(&self, request: &BulkRequest) -> Result<BulkReport, ClientError> {
+        self.send_json("POST", "/messages/bulk", Some(request))
+    }
+
+    /// Change flags and labels, returning `None` when the message does not exist.
+    pub fn update_flags(
+        &self,
+        id: &str,
+        patch: &FlagsPatch,
+    ) -> Result<Option<MessageFlags>, ClientError> {
+        let path = format!("/messages/{}/flags", encode(id));
+        not_found_as_none(self.send_json("PATCH", &path, Some(patch)))
+    }
+
+    pub fn compose(&self, request: &ComposeRequest) -> Result<SubmitResponse, ClientError> {
+        self.send_json("POST", "/compose", Some(request))
+    }
+
+    pub fn submit(&self, request: &SubmitRequest) -> Result<SubmitResponse, ClientError> {
+        self.send_json("POST", "/submit", Some(request))
+    }
+
+    /// [`compose`](Self::compose) with an `Idempotency-Key`; resending the same
+    /// request with the same key returns the original message id.
+    pub fn compose_idempotent(
+        &self,
+        request: &ComposeRequest,
+        key: &str,
+    ) -> Result<SubmitResponse, ClientError> {
+        let response = self.execute("POST", "/compose", Body::Json(request), Some(key))?;
+        Ok(response.into_json()?)
+    }
+
+    /// [`submit`](Self::submit) with an `Idempotency-Key`.
+    pub fn submit_idempotent(
+        &self,
+        request: &SubmitRequest,
+        key: &str,
+    ) -> Result<SubmitResponse, ClientError> {
+        let response = self.execute("POST", "/submit", Body::Json(request), Some(key))?;
+        Ok(response.into_json()?)
+    }
+
+    /// Submit several messages in one request; results come back in request order.
+    pub fn submit_batch(
+        &self,
+        requests: &[SubmitRequest],
+    ) -> Result<Vec<BatchSubmitResult>, ClientError> {
+        self.send_json("POST", "/submit/batch", Some(&requests))
+    }
+
+    pub fn create_draft(&self, request: &DraftRequest) -> Result<Draft, ClientError> {
+        self.send_json("POST", "/drafts", Some(request))
+    }
+
+    /// Fetch a draft, returning `None` on 404 (including once it was sent).
+    pub fn get_draft(&self, id: &str) -> Result<Option<Draft>, ClientError> {
+        not_found_as_none(self.get(&format!("/drafts/{}", encode(id))))
+    }
+
+    /// Autosave a draft. Fails with HTTP 409 when `base_revision` is stale.
+    pub fn update_draft(&self, id: &str, request: &DraftRequest) -> Result<Draft, ClientError> {
+        self.send_json("PUT", &format!("/drafts/{}", encode(id)), Some(request))
+    }
+
+    pub fn draft_revisions(&self, id: &str) -> Result<Vec<DraftRevision>, ClientError> {
+        self.get(&format!("/drafts/{}/revisions", encode(id)))
+    }
+
+    pub fn send_draft(
+        &self,
+        id: &str,
+        request: &SendDraftRequest,
+    ) -> Result<SubmitResponse, ClientError> {
+        self.send_json(
+            "POST",
+            &format!("/drafts/{}/send", encode(id)),
+            Some(request),
+        )
+    }
+
+    pub fn list_scheduled(&self) -> Result<Vec<QueuedMessage>, ClientError> {
+        self.get("/outbox/scheduled")
+    }
+
+    /// Cancel a scheduled message, returning `false` if it was already dispatched.
+    pub fn cancel_scheduled(&self, id: &str) -> Result<bool, ClientError> {
+        let path = format!("/outbox/scheduled/{}", encode(id));
+        not_found_as_none(self.send_empty("DELETE", &path, None::<&()>)).map(|done| done.is_some())
+    }
+
+    /// Queue depth, holds and dispatch order.
+    pub fn queue(&self) -> Result<QueueStats, ClientError> {
+        self.get("/queue")
+    }
+
+    /// Keep a queued message from dispatch, returning `false` when it is not queued.
+    pub fn hold_queued(&self, id: &str) -> Result<bool, ClientError> {
+        let path = format!("/queue/{}/hold", encode(id));
+        not_found_as_none(self.send_empty("POST", &path, None::<&()>)).map(|done| done.is_some())
+    }
+
+    /// Release a held message, returning `false` when it is not queued.
+    pub fn release_queued(&self, id: &str) -> Result<bool, ClientError> {
+        let path = format!("/queue/{}/release", encode(id));
+        not_found_as_none(self.send_empty("POST", &path, None::<&()>)).map(|done| done.is_some())
+    }
+
+    /// Stop outbound dispatch; submissions keep queueing.
+    pub fn pause_queue(&self) -> Result<(), ClientError> {
+        self.send_empty("POST", "/queue/pause", None::<&()>)
+    }
+
+    pub fn resume_queue(&self) -> Result<(), ClientError> {
+        self.send_empty("POST", "/queue/resume", None::<&()>)
+    }
+
+    /// JSON Schema (draft-07) of [`InterchangeMessage`].
+    pub fn interchange_schema(&self) -> Result<serde_json::Value, ClientError> {
+        self.get("/interchange/schema")
+    }
+
+    pub fn import_interchange(
+        &self,
+        document: &InterchangeMessage,
+    ) -> Result<MessageEnvelope, ClientError> {
+        self.send_json("POST", "/interchange/import", Some(document))
+    }
+
+    /// Export a message as an interchange document, returning `None` on 404.
+    pub fn export_interchange(&self, id: &str) -> Result<Option<InterchangeMessage>, ClientError> {
+        not_found_as_none(self.get(&format!("/messages/{}/interchange", encode(id))))
+    }
+
+    /// Start a migration job; it runs in the background on the service.
+    pub fn start_migration(&self, request: &MigrationRequest) -> Result<String, ClientError> {
+        let job: MigrationJob = self.send_json("POST", "/migration/jobs", Some(request))?;
+        Ok(job.job_id)
+    }
+
+    pub fn list_migrations(&self) -> Result<Vec<MigrationProgress>, ClientError> {
+        self.get("/migration/jobs")
+    }
+
+    pub fn migration_progress(&self, id: &str) -> Result<Option<MigrationProgress>, ClientError> {
+        not_found_as_none(self.get(&format!("/migration/jobs/{}/progress", encode(id))))
+    }
+
+    /// The final report; fails with HTTP 409 while the job is still running.
+    pub fn migration_report(&self, id: &str) -> Result<Option<MigrationReport>, ClientError> {
+        not_found_as_none(self.get(&format!("/migration/jobs/{}/report", encode(id))))
+    }
+
+    pub fn pause_migration(&self, id: &str) -> Result<MigrationProgress, ClientError> {
+        let path = format!("/migration/jobs/{}/pause", encode(id));
+        self.send_json("POST", &path, None::<&()>)
+    }
+
+    pub fn resume_migration(&self, id: &str) -> Result<MigrationProgress, ClientError> {
+        let path = format!("/migration/jobs/{}/resume", encode(id));
+        self.send_json("POST", &path, None::<&()>)
+    }
+
+    /// Cancel a job, returning `None` when it does not exist.
+    pub fn cancel_migration(&self, id: &str) -> Result<Option<MigrationProgress>, ClientError> {
+        let path = format!("/migration/jobs/{}", encode(id));
+        not_found_as_none(self.send_json("DELETE", &path, None::<&()>))
+    }
+
+    pub fn trace_bundle(&self) -> Result<TraceBundle, ClientError> {
+        self.get("/trace/bundle")
+    }
+
+    /// Diagnostics for a support ticket as a ZIP archive.
+    pub fn export_trace(&self) -> Result<Vec<u8>, ClientError> {
+        self.download("GET", "/trace/export", None::<&()>)
+    }
+
+    pub fn folder_stats(&self) -> Result<Vec<FolderStats>, ClientError> {
+        self.get("/folders/stats")
+    }
+
+    /// Generate a report for a stored message; only accepted while the mock
+    /// transport is active.
+    pub fn simulate_report(
+        &self,
+        request: &MockReportRequest,
+    ) -> Result<SimulatedReport, ClientError> {
+        self.send_json("POST", "/admin/mock/reports", Some(request))
+    }
+
+    pub fn list_webhooks(&self) -> Result<Vec<WebhookSubscription>, ClientError> {
+        self.get("/webhooks")
+    }
+
+    pub fn create_webhook(
+        &self,
+        request: &WebhookRequest,
+    ) -> Result<WebhookSubscription, ClientError> {
+        self.send_json("POST", "/webhooks", Some(request))
+    }
+
+    pub fn get_webhook(&self, id: &str) -> Result<Option<WebhookSubscription>, ClientError> {
+        not_found_as_none(self.get(&format!("/webhooks/{}", encode(id))))
+    }
+
+    pub fn update_webhook(
+        &self,
+        id: &str,
+        request: &WebhookRequest,
+    ) -> Result<WebhookSubscription, ClientError> {
+        self.send_json("PUT", &format!("/webhooks/{}", encode(id)), Some(request))
+    }
+
+    /// Delete a subscription, returning `false` when it did not exist.
+    pub fn delete_webhook(&self, id: &str) -> Result<bool, ClientError> {
+        let path = format!("/webhooks/{}", encode(id));
+        not_found_as_none(self.send_empty("DELETE", &path, None::<&()>)).map(|done| done.is_some())
+    }
+
+    /// Profiles loaded from `transport.profilesDir` at startup.
+    pub fn profiles(&self) -> Result<DiscoveryReport, ClientError> {
+        self.get("/profiles")
+    }
+
+    /// Profiles currently installed by the vendor SDK.
+    pub fn discovered_profiles(&self) -> Result<DiscoveryReport, ClientError> {
+        self.get("/transport/profiles/discovered")
+    }
+
+    /// Metrics in the Prometheus text exposition format.
+    pub fn metrics(&self) -> Result<String, ClientError> {
+        let response = self.execute("GET", "/metrics", Body::<()>::Empty, None)?;
+        Ok(response.into_string()?)
+    }
+
+    /// Queue statistics over `window`, e.g. `90m`, `24h` or `30d`.
+    pub fn metrics_history(&self, window: &str) -> Result<MetricsHistoryView, ClientError> {
+        self.get(&format!("/metrics/history?window={}", encode(window)))
+    }
+
+    /// Take a sealed store snapshot.
+    pub fn backup_store(&self) -> Result<Vec<u8>, ClientError> {
+        self.download("POST", "/admin/backup", None::<&()>)
+    }
+
+    /// Replace the store with a snapshot from [`backup_store`](Self::backup_store).
+    pub fn restore_store(&self, snapshot: &[u8]) -> Result<RestoreReport, ClientError> {
+        let body = Body::<()>::Bytes("application/octet-stream", snapshot);
+        let response = self.execute("POST", "/admin/restore", body, None)?;
+        Ok(response.into_json()?)
+    }
+
+    pub fn list_aliases(&self) -> Result<Vec<AliasRecord>, ClientError> {
+        self.get("/gateway/aliases")
+    }
+
+    pub fn create_alias(&self, alias: &AliasRecord) -> Result<AliasRecord, ClientError> {
+        self.send_json("POST", "/gateway/aliases", Some(alias))
+    }
+
+    pub fn update_alias(&self, or_address: &str, email: &str) -> Result<AliasRecord, ClientError> {
+        let body = AliasUpdate {
+            email: email.to_string(),
+        };
+        let path = format!("/gateway/aliases/{}", encode(or_address));
+        self.send_json("PUT", &path, Some(&body))
+    }
+
+    /// Remove an alias, returning `None` when it did not exist.
+    pub fn delete_alias(&self, or_address: &str) -> Result<Option<AliasRecord>, ClientError> {
+        let path = format!("/gateway/aliases/{}", encode(or_address));
+        not_found_as_none(self.send_json("DELETE", &path, None::<&()>))
+    }
+
+    /// The configured domain policy, with the verdict for `domain` when given.
+    pub fn gateway_policy(&self, domain: Option<&str>) -> Result<EffectivePolicy, ClientError> {
+        match domain {
+            Some(domain) => self.get(&format!("/gateway/policy?domain={}", encode(domain))),
+            None => self.get("/gateway/policy"),
+        }
+    }
+
+    /// Matching audit entries, newest first.
+    pub fn query_audit(&self, query: &AuditQuery) -> Result<Vec<AuditRecord>, ClientError> {
+        let mut params = Vec::new();
+        if let Some(actor) = &query.actor {
+            params.push(format!("actor={}", encode(actor)));
+        }
+        if let Some(action) = query.action {
+            let action = serde_json::to_value(action).map_err(std::io::Error::other)?;
+            params.push(format!(
+                "action={}",
+                encode(action.as_str().unwrap_or_default())
+            ));
+        }
+        if let Some(prefix) = &query.target_prefix {
+            params.push(format!("targetPrefix={}", encode(prefix)));
+        }
+        if let Some(since) = query.since {
+            params.push(format!("since={}", encode(&since.to_rfc3339())));
+        }
+        if let Some(until) = query.until {
+            params.push(format!("until={}", encode(&until.to_rfc3339())));
+        }
+        if let Some(limit) = query.limit {
+            params.push(format!("limit={limit}"));
+        }
+        let mut path = "/audit".to_string();
+        if !params.is_empty() {
+            path.push('?');
+            path.push_str(&params.join("&"));
+        }
+        self.get(&path)
+    }
+
+    /// The whole audit log, oldest first, with the hashes that chain it.
+    pub fn export_audit(&self) -> Result<Vec<AuditRecord>, ClientError> {
+        let lines = self.download("GET", "/audit/export", None::<&()>)?;
+        lines
+            .split(|byte| *byte == b'\n')
+            .filter(|line| !line.is_empty())
+            .map(|line| {
+                serde_json::from_slice(line)
+                    .map_err(|err| ClientError::Decode(std::io::Error::other(err)))
+            })
+            .collect()
+    }
+
+    /// Switch the active transport to `mode`.
+    pub fn switch_transport(&self, mode: &str) -> Result<TransportSwitchReport, ClientError> {
+        let body = TransportSwitchRequest {
+            mode: mode.to_string(),
+        };
+        self.send_json("POST", "/admin/transport", Some(&body))
+    }
+
+    /// Pin an observed CA; `fingerprint` may be left out when only one was seen.
+    pub fn confirm_tls_pin(
+        &self,
+        fingerprint: Option<&str>,
+    ) -> Result<PinConfirmation, ClientError> {
+        let body = PinRequest {
+            fingerprint: fingerprint.map(str::to_string),
+        };
+        self.send_json("POST", "/admin/tls/pin", Some(&body))
+    }
+
+    /// Stored support bundles, newest first.
+    pub fn list_support_bundles(&self) -> Result<Vec<SupportBundle>, ClientError> {
+        self.get("/support")
+    }
+
+    /// Download the selected folders (all when empty) as a ZIP archive.
+    pub fn export_mailbox(&self, request: &ExportRequest) -> Result<Vec<u8>, ClientError> {
+        self.download("POST", "/export", Some(request))
+    }
+
+    /// Load an archive produced by [`export_mailbox`](Self::export_mailbox).
+    pub fn import_mailbox(&self, archive: &[u8]) -> Result<ImportReport, ClientError> {
+        let body = Body::<()>::Bytes("application/zip", archive);
+        let response = self.execute("POST", "/import", body, None)?;
+        Ok(response.into_json()?)
+    }
+
+    fn get<T: DeserializeOwned>(&self, path: &str) -> Result<T, ClientError> {
+        self.send_json("GET", path, None::<&()>)
+    }
+
+    fn send_json<B: Serialize, T: DeserializeOwned>(
+        &self,
+        method: &str,
+        path: &str,
+        body: Option<&B>,
+    ) -> Result<T, ClientError> {
+        let response = self.execute(method, path, Body::from(body), None)?;
+        Ok(response.into_json()?)
+    }
+
+    fn download<B: Serialize>(
+        &self,
+        method: &str,
+        path: &str,
+        body: Option<&B>,
+    ) -> Result<Vec<u8>, ClientError> {
+        let response = self.execute(method, path, Body::from(body), None)?;
+        let mut bytes = Vec::new();
+        response.into_reader().read_to_end(&mut bytes)?;
+        Ok(bytes)
+    }
+
+    fn send_empty<B: Serialize>(
+        &self,
+        method: &str,
+        path: &str,
+        body: Option<&B>,
+    ) -> Result<(), ClientError> {
+        self.execute(method, path, Body::from(body), None)
+            .map(|_| ())
+    }
+
+    fn execute<B: Serialize>(
+        &self,
+        method: &str,
+        path: &str,
+        body: Body<'_, B>,
+        idempotency_key: Option<&str>,
+    ) -> Result<ureq::Response, ClientError> {
+        let idempotent = idempotency_key.is_some() || matches!(method, "GET" | "DELETE");
+        let mut attempt = 0;
+        loop {
+            let mut request = self
+                .agent
+                .request(method, &format!("{}{}", self.base_url, path))
+                .set("Accept", "application/json");
+            request = match &self.credentials {
+                Credentials::None => request,
+                Credentials::ApiKey(key) => request.set("X-Api-Key", key),
+                Credentials::Bearer(token) => {
+                    request.set("Authorization", &format!("Bearer {token}"))
+                }
+            };
+            if let Some(key) = idempotency_key {
+                request = request.set("Idempotency-Key", key);
+            }
+            let result = match body {
+                Body::Empty => request.call(),
+                Body::Json(body) => request.send_json(body),
+                Body::Bytes(content_type, bytes) => {
+                    request.set("Content-Type", content_type).send_bytes(bytes)
+                }
+            };
+
+            let retryable = idempotent
+                && match &result {
+                    Ok(_) => false,
+                    Err(ureq::Error::Status(status, _)) => matches!(status, 429 | 502 | 503 | 504),
+                    Err(ureq::Error::Transport(_)) => true,
+                };
+            if retryable && attempt < self.max_retries {
+                attempt += 1;
+                thread::sleep(self.backoff * attempt);
+                continue;
+            }
+
+            return result.map_err(|err| match err {
+                ureq::Error::Status(status, response) => ClientError::Http {
+                    status,
+                    body: response.into_string().unwrap_or_default(),
+                },
+                ureq::Error::Transport(transport) => ClientError::Transport(transport.to_string()),
+            });
+        }
+    }
+}
+
+enum Body<'a, B> {
+    Empty,
+    Json(&'a B),
+    Bytes(&'static str, &'a [u8]),
+}
+
+impl<'a, B> From<Option<&'a B>> for Body<'a, B> {
+    fn from(body: Option<&'a B>) -> Self {
+        body.map_or(Self::Empty, Self::Json)
+    }
+}
+
+fn not_found_as_none<T>(result: Result<T, ClientError>) -> Result<Option<T>, ClientError> {
+    match result {
+        Ok(value) => Ok(Some(value)),
+        Err(ClientError::Http { status: 404, .. }) => Ok(None),
+        Err(err) => Err(err),
+    }
+}
+
+fn encode(segment: &str) -> String {
+    segment
+        .bytes()
+        .map(|byte| match byte {
+            b'A'..=b'Z' | b'a'..=b'z' | b'0'..=b'9' | b'-' | b'_' | b'.' | b'~' => {
+                (byte as char).to_string()
+            }
+            _ => format!("%{byte:02X}"),
+        })
+        .collect()
+}
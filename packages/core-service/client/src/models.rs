@@ -0,0 +1,333 @@
+//! Wire types of `api/openapi.json`.
+//!
+//! Bodies core-service serialises from its own types are re-exported from
+//! it. The rest mirror the shared Zod schemas, whose message and folder
+//! shapes differ from the store records the service keeps.
+
+use std::collections::BTreeMap;
+
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+
+pub use core_service::audit::AuditQuery;
+pub use core_service::backup::RestoreReport;
+pub use core_service::bulk::{BulkAction, BulkRequest};
+pub use core_service::drafts::DraftRevision;
+pub use core_service::export::{ExportRequest, ImportReport};
+pub use core_service::flags::FlagsPatch;
+pub use core_service::gateway::policy::{DomainVerdict, EffectivePolicy};
+pub use core_service::interchange::InterchangeMessage;
+pub use core_service::metrics_history::{MetricsHistoryView, MetricsPoint, Resolution};
+pub use core_service::migration::{
+    DiffDuplicate, DiffMessage, DryRunDiff, EntryCheck, EntryVerification, FolderConflict,
+    MigrationErrorRecord, MigrationMode, MigrationProgress, MigrationReport, MigrationRequest,
+    MigrationStatus, UnmappableAddress,
+};
+pub use core_service::models::{
+    AliasRecord, AuditAction, AuditRecord, Dda, MessageFlags, MessageId, OrName, X400Address,
+};
+pub use core_service::queue::{QueueStats, QueuedMessage};
+pub use core_service::store::FolderStats;
+pub use core_service::submit::BatchOutcome;
+pub use core_service::support::{SupportBundle, SupportMetadata};
+pub use core_service::transport::{DiscoveryReport, PinConfirmation, TransportSwitchReport};
+pub use core_service::webhooks::{WebhookRequest, WebhookSubscription};
+
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Folder {
+    pub id: String,
+    pub name: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub parent_id: Option<String>,
+    #[serde(default)]
+    pub system: bool,
+    pub unread_count: u64,
+    #[serde(default)]
+    pub total_count: u64,
+    #[serde(default)]
+    pub flagged_count: u64,
+    #[serde(default)]
+    pub answered_count: u64,
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub label_counts: BTreeMap<String, u64>,
+}
+
+/// Body of `POST /folders` and `PATCH /folders/{id}`.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct FolderRequest {
+    pub name: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub parent_id: Option<String>,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub enum MessageStatus {
+    Draft,
+    Queued,
+    Sent,
+    Delivered,
+    Read,
+    Failed,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub enum MessagePriority {
+    Normal,
+    NonUrgent,
+    Urgent,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub enum MessageSensitivity {
+    Normal,
+    Personal,
+    Private,
+    Confidential,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct MessageEnvelope {
+    pub id: String,
+    pub subject: String,
+    pub sender: X400Address,
+    pub to: Vec<X400Address>,
+    #[serde(default)]
+    pub cc: Vec<X400Address>,
+    #[serde(default)]
+    pub bcc: Vec<X400Address>,
+    pub folder: String,
+    pub status: MessageStatus,
+    pub priority: MessagePriority,
+    pub sensitivity: MessageSensitivity,
+    pub created_at: DateTime<Utc>,
+    pub updated_at: DateTime<Utc>,
+    pub message_id: String,
+    #[serde(default)]
+    pub flags: MessageFlags,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub thread_id: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub in_reply_to: Option<String>,
+}
+
+/// One conversation of `GET /threads`.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Thread {
+    pub thread_id: String,
+    pub subject: String,
+    pub latest_at: DateTime<Utc>,
+    pub messages: Vec<MessageEnvelope>,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ThreadArchiveResult {
+    pub archived: u64,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Attachment {
+    pub id: String,
+    pub filename: String,
+    pub mime_type: String,
+    pub size: u64,
+}
+
+#[derive(Clone, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct MessageContent {
+    pub text: String,
+    #[serde(default)]
+    pub attachments: Vec<Attachment>,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub enum ReportType {
+    Delivery,
+    NonDelivery,
+    Read,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Report {
+    pub id: String,
+    pub message_id: String,
+    #[serde(rename = "type")]
+    pub kind: ReportType,
+    pub timestamp: DateTime<Utc>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub diagnostic_code: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub supplemental_info: Option<String>,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Message {
+    pub envelope: MessageEnvelope,
+    pub content: MessageContent,
+    #[serde(default)]
+    pub reports: Vec<Report>,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct SubmitRequest {
+    pub envelope: MessageEnvelope,
+    pub content: MessageContent,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub strategy: Option<u8>,
+    /// Send-at time; the message waits in the scheduled outbox until then.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub deferred_until: Option<DateTime<Utc>>,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ComposeRequest {
+    pub sender: X400Address,
+    pub recipients: Vec<X400Address>,
+    pub subject: String,
+    pub body: String,
+    /// Id of the message being answered; the new message joins its thread.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub in_reply_to: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub strategy: Option<u8>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub deferred_until: Option<DateTime<Utc>>,
+}
+
+/// Body of `POST /drafts` and `PUT /drafts/{id}`.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct DraftRequest {
+    pub sender: X400Address,
+    pub recipients: Vec<X400Address>,
+    pub subject: String,
+    pub body: String,
+    /// Revision the edit was based on; the save is refused if the draft moved on.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub base_revision: Option<u32>,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Draft {
+    pub id: String,
+    pub revision: u32,
+    pub updated_at: DateTime<Utc>,
+    pub sender: X400Address,
+    pub recipients: Vec<X400Address>,
+    pub subject: String,
+    pub body: String,
+}
+
+#[derive(Clone, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct SendDraftRequest {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub deferred_until: Option<DateTime<Utc>>,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct SubmitResponse {
+    pub message_id: String,
+    pub queue_reference: String,
+    pub status: String,
+    pub strategy: u8,
+}
+
+/// Per-message result of `POST /submit/batch`.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct BatchSubmitResult {
+    pub index: usize,
+    pub message_id: String,
+    pub outcome: BatchOutcome,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct MoveRequest {
+    pub folder_id: String,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct BulkReport {
+    pub action: String,
+    pub updated: Vec<String>,
+    pub missing: Vec<String>,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct MigrationJob {
+    pub job_id: String,
+}
+
+#[derive(Clone, Debug, Default, PartialEq, Serialize, Deserialize)]
+pub struct TraceBundle {
+    pub entries: Vec<serde_json::Value>,
+}
+
+/// Recipient named in a simulated report.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ReportRecipient {
+    pub country: String,
+    pub organization: String,
+    pub surname: String,
+}
+
+/// Body of `POST /admin/mock/reports`.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct MockReportRequest {
+    pub message_id: String,
+    pub kind: ReportType,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub recipient: Option<ReportRecipient>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub reason: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub diagnostic: Option<String>,
+    /// Hold the report back for this long.
+    #[serde(default)]
+    pub delay_ms: u64,
+}
+
+/// Report generated by `POST /admin/mock/reports`.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct SimulatedReport {
+    pub message_id: String,
+    pub kind: ReportType,
+    #[serde(default)]
+    pub recipient: Option<ReportRecipient>,
+    #[serde(default)]
+    pub reason: Option<String>,
+    #[serde(default)]
+    pub diagnostic: Option<String>,
+    pub timestamp: DateTime<Utc>,
+}
+
+/// Body of `PUT /gateway/aliases/{orAddress}`.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct AliasUpdate {
+    pub email: String,
+}
+
+/// Body of `POST /admin/transport`.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct TransportSwitchRequest {
+    pub mode: String,
+}
+
+/// Body of `POST /admin/tls/pin`.
+#[derive(Clone, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct PinRequest {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub fingerprint: Option<String>,
+}
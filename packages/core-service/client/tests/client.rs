@@ -0,0 +1,273 @@
+use std::io::{BufRead, BufReader, Read, Write};
+use std::net::TcpListener;
+use std::sync::mpsc;
+use std::thread;
+use std::time::Duration;
+
+use core_service_client::{
+    AuditAction, AuditQuery, ClientError, ComposeRequest, CoreServiceClient, ExportRequest,
+    MigrationRequest, OrName, X400Address,
+};
+
+/// Serve the canned responses in order, reporting each request head back to the test.
+fn serve(responses: Vec<&'static str>) -> (String, mpsc::Receiver<String>) {
+    let listener = TcpListener::bind("127.0.0.1:0").expect("bind");
+    let address = format!("http://{}", listener.local_addr().unwrap());
+    let (sender, receiver) = mpsc::channel();
+    thread::spawn(move || {
+        for response in responses {
+            let (mut stream, _) = listener.accept().expect("accept");
+            let mut reader = BufReader::new(stream.try_clone().unwrap());
+            let mut head = String::new();
+            let mut content_length = 0usize;
+            loop {
+                let mut line = String::new();
+                reader.read_line(&mut line).unwrap();
+                if let Some(value) = line.to_ascii_lowercase().strip_prefix("content-length:") {
+                    content_length = value.trim().parse().unwrap();
+                }
+                if line == "\r\n" {
+                    break;
+                }
+                head.push_str(&line);
+            }
+            let mut body = vec![0; content_length];
+            reader.read_exact(&mut body).unwrap();
+            sender.send(head).unwrap();
+            stream.write_all(response.as_bytes()).unwrap();
+        }
+    });
+    (address, receiver)
+}
+
+#[test]
+fn retries_unavailable_and_sends_api_key() {
+    let (address, requests) = serve(vec![
+        "HTTP/1.1 503 Service Unavailable\r\nContent-Length: 0\r\nConnection: close\r\n\r\n",
+        "HTTP/1.1 200 OK\r\nContent-Type: application/json\r\nContent-Length: 47\r\nConnection: close\r\n\r\n[{\"id\":\"inbox\",\"name\":\"Inbox\",\"unreadCount\":2}]",
+    ]);
+    let client = CoreServiceClient::new(address)
+        .with_api_key("secret-key")
+        .with_retries(1, Duration::from_millis(1));
+
+    let folders = client.list_folders().expect("folders");
+    assert_eq!(folders.len(), 1);
+    assert_eq!(folders[0].unread_count, 2);
+
+    let first = requests.recv().unwrap();
+    let second = requests.recv().unwrap();
+    assert!(first.starts_with("GET /folders"));
+    assert!(second
+        .to_ascii_lowercase()
+        .contains("x-api-key: secret-key"));
+}
+
+#[test]
+fn maps_missing_messages_and_errors() {
+    let (address, _requests) = serve(vec![
+        "HTTP/1.1 404 Not Found\r\nContent-Length: 0\r\nConnection: close\r\n\r\n",
+        "HTTP/1.1 401 Unauthorized\r\nContent-Length: 12\r\nConnection: close\r\n\r\nunauthorized",
+    ]);
+    let client = CoreServiceClient::new(address).with_bearer_token("token");
+
+    assert!(client.get_message("msg-1").expect("lookup").is_none());
+    let err = client.trace_bundle().unwrap_err();
+    assert!(matches!(err, ClientError::Http { status: 401, ref body } if body == "unauthorized"));
+}
+
+#[test]
+fn lists_and_cancels_scheduled_messages() {
+    let (address, requests) = serve(vec![
+        "HTTP/1.1 200 OK\r\nContent-Type: application/json\r\nContent-Length: 155\r\nConnection: close\r\n\r\n[{\"messageId\":\"msg-7\",\"priority\":\"high\",\"effectivePriority\":\"high\",\"enqueuedAt\":\"2024-06-03T07:30:00Z\",\"deferredUntil\":\"2024-06-03T08:30:00Z\",\"due\":false}]",
+        "HTTP/1.1 404 Not Found\r\nContent-Length: 0\r\nConnection: close\r\n\r\n",
+    ]);
+    let client = CoreServiceClient::new(address);
+
+    let scheduled = client.list_scheduled().expect("list");
+    assert_eq!(scheduled.len(), 1);
+    assert_eq!(scheduled[0].message_id.0, "msg-7");
+    assert!(!scheduled[0].due);
+    assert!(!client.cancel_scheduled("msg-7").expect("cancel"));
+
+    assert!(requests
+        .recv()
+        .unwrap()
+        .starts_with("GET /outbox/scheduled "));
+    assert!(requests
+        .recv()
+        .unwrap()
+        .starts_with("DELETE /outbox/scheduled/msg-7 "));
+}
+
+#[test]
+fn retries_keyed_submissions_after_a_dropped_connection() {
+    let (address, requests) = serve(vec![
+        "",
+        "HTTP/1.1 200 OK\r\nContent-Type: application/json\r\nContent-Length: 77\r\nConnection: close\r\n\r\n{\"message_id\":\"msg-9\",\"queue_reference\":\"q-9\",\"status\":\"queued\",\"strategy\":0}",
+    ]);
+    let client = CoreServiceClient::new(address).with_retries(1, Duration::from_millis(1));
+    let operator = X400Address {
+        or_name: OrName {
+            c: "DE".into(),
+            o: Some("Modern".into()),
+            surname: Some("Operator".into()),
+            ..OrName::default()
+        },
+        ..X400Address::default()
+    };
+    let request = ComposeRequest {
+        sender: operator.clone(),
+        recipients: vec![operator],
+        subject: "Manifest".into(),
+        body: "Cargo manifest attached".into(),
+        in_reply_to: None,
+        strategy: None,
+        deferred_until: None,
+    };
+
+    let response = client
+        .compose_idempotent(&request, "manifest-42")
+        .expect("compose");
+    assert_eq!(response.message_id, "msg-9");
+
+    for _ in 0..2 {
+        let head = requests.recv().unwrap();
+        assert!(head.starts_with("POST /compose "));
+        assert!(head
+            .to_ascii_lowercase()
+            .contains("idempotency-key: manifest-42"));
+    }
+}
+
+#[test]
+fn does_not_resend_unkeyed_submissions() {
+    let (address, requests) = serve(vec![
+        "HTTP/1.1 503 Service Unavailable\r\nContent-Length: 0\r\nConnection: close\r\n\r\n",
+        "HTTP/1.1 204 No Content\r\nContent-Length: 0\r\nConnection: close\r\n\r\n",
+        "HTTP/1.1 404 Not Found\r\nContent-Length: 0\r\nConnection: close\r\n\r\n",
+    ]);
+    let client = CoreServiceClient::new(address).with_retries(2, Duration::from_millis(1));
+
+    let err = client.pause_queue().unwrap_err();
+    assert!(matches!(err, ClientError::Http { status: 503, .. }));
+    assert!(client.hold_queued("msg-3").expect("hold"));
+    assert!(!client.release_queued("msg-3").expect("release"));
+
+    assert!(requests.recv().unwrap().starts_with("POST /queue/pause "));
+    assert!(requests
+        .recv()
+        .unwrap()
+        .starts_with("POST /queue/msg-3/hold "));
+    assert!(requests
+        .recv()
+        .unwrap()
+        .starts_with("POST /queue/msg-3/release "));
+}
+
+#[test]
+fn downloads_and_uploads_mailbox_archives() {
+    let (address, requests) = serve(vec![
+        "HTTP/1.1 200 OK\r\nContent-Type: application/zip\r\nContent-Length: 4\r\nConnection: close\r\n\r\nPK\x03\x04",
+        "HTTP/1.1 200 OK\r\nContent-Type: application/json\r\nContent-Length: 46\r\nConnection: close\r\n\r\n{\"imported\":2,\"skipped\":0,\"folders\":[\"inbox\"]}",
+    ]);
+    let client = CoreServiceClient::new(address);
+
+    let archive = client
+        .export_mailbox(&ExportRequest {
+            folders: vec!["inbox".into()],
+        })
+        .expect("export");
+    assert_eq!(archive, b"PK\x03\x04");
+    let report = client.import_mailbox(&archive).expect("import");
+    assert_eq!(report.imported, 2);
+
+    assert!(requests.recv().unwrap().starts_with("POST /export "));
+    let import = requests.recv().unwrap();
+    assert!(import.starts_with("POST /import "));
+    assert!(import.contains("application/zip"));
+}
+
+#[test]
+fn starts_and_follows_migration_jobs() {
+    let (address, requests) = serve(vec![
+        "HTTP/1.1 202 Accepted\r\nContent-Type: application/json\r\nContent-Length: 48\r\nConnection: close\r\n\r\n{\"jobId\":\"6f1c2a4e-0000-4000-8000-000000000001\"}",
+        "HTTP/1.1 409 Conflict\r\nContent-Length: 28\r\nConnection: close\r\n\r\nthe job has not finished yet",
+        "HTTP/1.1 404 Not Found\r\nContent-Length: 0\r\nConnection: close\r\n\r\n",
+    ]);
+    let client = CoreServiceClient::new(address);
+
+    let job_id = client
+        .start_migration(&MigrationRequest {
+            path: "/srv/legacy/archive.mbox".into(),
+            dry_run: true,
+            ..MigrationRequest::default()
+        })
+        .expect("start");
+    assert_eq!(job_id, "6f1c2a4e-0000-4000-8000-000000000001");
+    let err = client.migration_report(&job_id).unwrap_err();
+    assert!(matches!(err, ClientError::Http { status: 409, .. }));
+    assert!(client
+        .cancel_migration("missing")
+        .expect("cancel")
+        .is_none());
+
+    assert!(requests
+        .recv()
+        .unwrap()
+        .starts_with("POST /migration/jobs "));
+    assert!(requests
+        .recv()
+        .unwrap()
+        .starts_with(&format!("GET /migration/jobs/{job_id}/report ")));
+    assert!(requests
+        .recv()
+        .unwrap()
+        .starts_with("DELETE /migration/jobs/missing "));
+}
+
+/// A 200 response carrying `body` with the right length.
+fn ok(content_type: &str, body: &str) -> &'static str {
+    Box::leak(
+        format!(
+            "HTTP/1.1 200 OK\r\nContent-Type: {content_type}\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{body}",
+            body.len()
+        )
+        .into_boxed_str(),
+    )
+}
+
+#[test]
+fn queries_exports_the_audit_log_and_restores_backups() {
+    let record = r#"{"sequence":1,"timestamp":"2024-03-01T09:00:00Z","actor":"ops","action":"store.backup","target":"store","status":null,"detail":null,"previousHash":"00","hash":"ab"}"#;
+    let (address, requests) = serve(vec![
+        ok("application/json", &format!("[{record}]")),
+        ok("application/x-ndjson", &format!("{record}\n{record}\n")),
+        ok(
+            "application/json",
+            r#"{"takenAt":"2024-03-01T09:00:00Z","messages":3,"queued":1,"folders":2}"#,
+        ),
+    ]);
+    let client = CoreServiceClient::new(address);
+
+    let entries = client
+        .query_audit(&AuditQuery {
+            action: Some(AuditAction::StoreBackup),
+            target_prefix: Some("message/".into()),
+            limit: Some(10),
+            ..AuditQuery::default()
+        })
+        .expect("query");
+    assert_eq!(entries[0].actor, "ops");
+    assert_eq!(client.export_audit().expect("export").len(), 2);
+    let report = client.restore_store(b"sealed").expect("restore");
+    assert_eq!(report.messages, 3);
+
+    assert!(requests
+        .recv()
+        .unwrap()
+        .starts_with("GET /audit?action=store.backup&targetPrefix=message%2F&limit=10 "));
+    assert!(requests.recv().unwrap().starts_with("GET /audit/export "));
+    let restore = requests.recv().unwrap();
+    assert!(restore.starts_with("POST /admin/restore "));
+    assert!(restore.contains("application/octet-stream"));
+}
@@ -0,0 +1,287 @@
+//! Access log of API commands for security review.
+//!
+//! [`crate::ipc`] records every command a client sends once the reply is
+//! written: the caller's key, the command `type` as the route (method
+//! `COMMAND`), the reply status, latency and reply size. Entries go to daily `access-YYYY-MM-DD.jsonl` files under
+//! `accessLog.path`, separate from the tracing output, and files older than
+//! `accessLog.retentionDays` are pruned. Query strings are reduced to their
+//! parameter names and client addresses are truncated to their network unless
+//! `accessLog.anonymizeIp` is off. `GET /admin/access-log` serves
+//! [`AccessLog::query`].
+
+use std::fs::{self, OpenOptions};
+use std::io::{self, BufRead, BufReader, Write};
+use std::net::IpAddr;
+use std::path::PathBuf;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use chrono::{DateTime, NaiveDate, Utc};
+use serde::{Deserialize, Serialize};
+use tracing::warn;
+
+use crate::clock::SharedClock;
+use crate::config::AccessLogConfig;
+
+/// Command details handed over by the connection once the reply is written.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct AccessRecord<'a> {
+    pub principal: Option<&'a str>,
+    pub method: &'a str,
+    pub path: &'a str,
+    pub status: u16,
+    pub latency: Duration,
+    pub bytes: u64,
+    pub remote: Option<IpAddr>,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct AccessLogEntry {
+    pub timestamp: DateTime<Utc>,
+    pub principal: Option<String>,
+    pub method: String,
+    pub route: String,
+    pub status: u16,
+    pub latency_ms: u64,
+    pub bytes: u64,
+    pub remote: Option<String>,
+}
+
+/// Filters accepted by the query endpoint; unset fields match everything.
+#[derive(Clone, Debug, Default, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct AccessLogQuery {
+    pub principal: Option<String>,
+    pub route_prefix: Option<String>,
+    pub status: Option<u16>,
+    pub since: Option<DateTime<Utc>>,
+    pub until: Option<DateTime<Utc>>,
+    pub limit: Option<usize>,
+}
+
+impl AccessLogQuery {
+    fn matches(&self, entry: &AccessLogEntry) -> bool {
+        self.principal
+            .as_ref()
+            .is_none_or(|principal| entry.principal.as_ref() == Some(principal))
+            && self
+                .route_prefix
+                .as_ref()
+                .is_none_or(|prefix| entry.route.starts_with(prefix.as_str()))
+            && self.status.is_none_or(|status| entry.status == status)
+            && self.since.is_none_or(|since| entry.timestamp >= since)
+            && self.until.is_none_or(|until| entry.timestamp < until)
+    }
+}
+
+const DEFAULT_QUERY_LIMIT: usize = 500;
+
+#[derive(Clone)]
+pub struct AccessLog {
+    config: AccessLogConfig,
+    directory: PathBuf,
+    write_lock: Arc<Mutex<()>>,
+    clock: SharedClock,
+}
+
+impl AccessLog {
+    pub fn new(config: AccessLogConfig) -> Self {
+        Self {
+            directory: PathBuf::from(&config.path),
+            config,
+            write_lock: Arc::new(Mutex::new(())),
+            clock: SharedClock::default(),
+        }
+    }
+
+    pub fn with_clock(mut self, clock: SharedClock) -> Self {
+        self.clock = clock;
+        self
+    }
+
+    /// Append one request; failures are logged and never affect the response.
+    pub fn record(&self, record: AccessRecord<'_>) {
+        if !self.config.enabled {
+            return;
+        }
+        let entry = AccessLogEntry {
+            timestamp: self.clock.now(),
+            principal: record.principal.map(str::to_string),
+            method: record.method.to_string(),
+            route: redact_query(record.path),
+            status: record.status,
+            latency_ms: u64::try_from(record.latency.as_millis()).unwrap_or(u64::MAX),
+            bytes: record.bytes,
+            remote: record.remote.map(|remote| {
+                if self.config.anonymize_ip {
+                    anonymize(remote)
+                } else {
+                    remote.to_string()
+                }
+            }),
+        };
+        if let Err(err) = self.append(&entry) {
+            warn!(target = "access_log", "failed to write access log: {err}");
+        }
+    }
+
+    fn append(&self, entry: &AccessLogEntry) -> io::Result<()> {
+        let _guard = self.write_lock.lock();
+        fs::create_dir_all(&self.directory)?;
+        let path = self.directory.join(format!(
+            "access-{}.jsonl",
+            entry.timestamp.format("%Y-%m-%d")
+        ));
+        let mut file = OpenOptions::new().create(true).append(true).open(path)?;
+        let mut line = serde_json::to_vec(entry).map_err(io::Error::other)?;
+        line.push(b'\n');
+        file.write_all(&line)
+    }
+
+    /// Delete daily files past the retention period, returning how many were removed.
+    pub fn prune(&self) -> io::Result<usize> {
+        let cutoff = self.clock.now().date_naive()
+            - chrono::Duration::days(i64::from(self.config.retention_days));
+        let mut removed = 0;
+        for (date, path) in self.files()? {
+            if date < cutoff {
+                fs::remove_file(path)?;
+                removed += 1;
+            }
+        }
+        Ok(removed)
+    }
+
+    /// Matching entries, newest first.
+    pub fn query(&self, query: &AccessLogQuery) -> io::Result<Vec<AccessLogEntry>> {
+        let limit = query.limit.unwrap_or(DEFAULT_QUERY_LIMIT);
+        let mut files = self.files()?;
+        files.sort_by_key(|(date, _)| std::cmp::Reverse(*date));
+        let mut results = Vec::new();
+        for (date, path) in files {
+            if query.since.is_some_and(|since| date < since.date_naive())
+                || query.until.is_some_and(|until| date > until.date_naive())
+            {
+                continue;
+            }
+            let mut entries = Vec::new();
+            for line in BufReader::new(fs::File::open(path)?).lines() {
+                if let Ok(entry) = serde_json::from_str::<AccessLogEntry>(&line?) {
+                    if query.matches(&entry) {
+                        entries.push(entry);
+                    }
+                }
+            }
+            results.extend(entries.into_iter().rev());
+            if results.len() >= limit {
+                break;
+            }
+        }
+        results.truncate(limit);
+        Ok(results)
+    }
+
+    fn files(&self) -> io::Result<Vec<(NaiveDate, PathBuf)>> {
+        let entries = match fs::read_dir(&self.directory) {
+            Ok(entries) => entries,
+            Err(err) if err.kind() == io::ErrorKind::NotFound => return Ok(Vec::new()),
+            Err(err) => return Err(err),
+        };
+        Ok(entries
+            .filter_map(|entry| entry.ok().map(|entry| entry.path()))
+            .filter_map(|path| {
+                let name = path.file_name()?.to_str()?;
+                let date = name.strip_prefix("access-")?.strip_suffix(".jsonl")?;
+                let date = NaiveDate::parse_from_str(date, "%Y-%m-%d").ok()?;
+                Some((date, path))
+            })
+            .collect())
+    }
+}
+
+/// Keep query parameter names but drop their values, which may carry tokens.
+fn redact_query(path: &str) -> String {
+    match path.split_once('?') {
+        Some((route, query)) => {
+            let names = query
+                .split('&')
+                .filter(|pair| !pair.is_empty())
+                .map(|pair| pair.split('=').next().unwrap_or_default())
+                .collect::<Vec<_>>();
+            format!("{route}?{}", names.join("&"))
+        }
+        None => path.to_string(),
+    }
+}
+
+fn anonymize(address: IpAddr) -> String {
+    match address {
+        IpAddr::V4(v4) => {
+            let [a, b, c, _] = v4.octets();
+            format!("{a}.{b}.{c}.0/24")
+        }
+        IpAddr::V6(v6) => {
+            let segments = v6.segments();
+            format!("{:x}:{:x}:{:x}::/48", segments[0], segments[1], segments[2])
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::clock::ManualClock;
+    use chrono::TimeZone;
+
+    #[test]
+    fn writes_redacted_entries_and_prunes_by_retention() {
+        let dir = tempfile::tempdir().unwrap();
+        let clock = ManualClock::new(Utc.with_ymd_and_hms(2024, 5, 1, 9, 0, 0).unwrap());
+        let log = AccessLog::new(AccessLogConfig {
+            enabled: true,
+            path: dir.path().to_string_lossy().into_owned(),
+            retention_days: 2,
+            anonymize_ip: true,
+        })
+        .with_clock(SharedClock::new(clock.clone()));
+
+        let record = |path, status| AccessRecord {
+            principal: Some("ops"),
+            method: "GET",
+            path,
+            status,
+            latency: Duration::from_millis(12),
+            bytes: 512,
+            remote: Some("192.168.10.77".parse().unwrap()),
+        };
+        log.record(record("/messages?folder=inbox&token=s3cret", 200));
+        clock.advance(chrono::Duration::days(3));
+        log.record(record("/admin/config", 403));
+        log.record(AccessRecord {
+            principal: None,
+            remote: Some("2001:db8:85a3::8a2e:370:7334".parse().unwrap()),
+            ..record("/status", 200)
+        });
+
+        let all = log.query(&AccessLogQuery::default()).unwrap();
+        assert_eq!(all.len(), 3);
+        assert_eq!(all[0].route, "/status");
+        assert_eq!(all[0].remote.as_deref(), Some("2001:db8:85a3::/48"));
+        assert_eq!(all[2].route, "/messages?folder&token");
+        assert_eq!(all[2].remote.as_deref(), Some("192.168.10.0/24"));
+
+        let denied = log
+            .query(&AccessLogQuery {
+                principal: Some("ops".into()),
+                status: Some(403),
+                ..AccessLogQuery::default()
+            })
+            .unwrap();
+        assert_eq!(denied.len(), 1);
+        assert_eq!(denied[0].route, "/admin/config");
+
+        assert_eq!(log.prune().unwrap(), 1);
+        assert_eq!(log.query(&AccessLogQuery::default()).unwrap().len(), 2);
+    }
+}
@@ -0,0 +1,727 @@
+//! Content-addressed attachment blobs.
+//!
+//! With `database.useSqlcipher` the blobs are sealed like the database:
+//! each file is split into 64 KiB chunks, each sealed with AES-256-GCM under
+//! a key derived from `database.sqlcipherKey`. A chunk's nonce carries its
+//! index and its associated data holds the file header, plaintext size
+//! included, and marks the last chunk, so chunks cannot be reordered or
+//! dropped and the size cannot be changed unnoticed. Chunks are sealed and
+//! opened one at a time; range requests open only the chunks they cover.
+//!
+//! Plain blobs are named by the SHA-256 of their content. Sealed blobs are
+//! named by an HMAC-SHA256 of that digest under a key derived from the
+//! encryption key ([`AttachmentStore::blob_id`]), so a file name does not
+//! tell which content it holds. Blobs written before encryption was turned
+//! on are still read as they are until [`AttachmentStore::seal_plaintext`]
+//! rewrites them.
+
+use std::collections::BTreeMap;
+use std::fs::{self, File};
+use std::io::{self, Read, Seek, SeekFrom, Write};
+use std::num::NonZeroU32;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+
+use ring::aead::{Aad, LessSafeKey, Nonce, UnboundKey, AES_256_GCM, NONCE_LEN};
+use ring::rand::{SecureRandom, SystemRandom};
+use ring::{hmac, pbkdf2};
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use thiserror::Error;
+
+const CHUNK_SIZE: usize = 64 * 1024;
+
+const MAGIC: &[u8; 5] = b"X4AT1";
+/// Random nonce prefix of a sealed file; the chunk index fills the rest.
+const PREFIX_LEN: usize = NONCE_LEN - 4;
+/// Magic, nonce prefix and plaintext size.
+const HEADER_LEN: usize = MAGIC.len() + PREFIX_LEN + 8;
+const TAG_LEN: usize = 16;
+const KEY_SALT: &[u8] = b"x400-core attachments";
+const PBKDF2_ROUNDS: u32 = 100_000;
+/// Signed with the encryption key to derive the key blob names are keyed by.
+const NAME_KEY_LABEL: &[u8] = b"x400-core attachment names";
+
+#[derive(Debug, Error)]
+pub enum AttachmentError {
+    #[error("attachment storage failure: {0}")]
+    Io(#[from] io::Error),
+    #[error("attachment {0} not found")]
+    NotFound(String),
+    #[error("malformed Range header: {0}")]
+    InvalidRange(String),
+    #[error("requested range not satisfiable for {0} bytes")]
+    RangeNotSatisfiable(u64),
+    #[error("no randomness available to seal the attachment")]
+    Random,
+}
+
+/// Metadata describing an attachment blob written to disk.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct StoredAttachment {
+    /// File name of the blob, see [`AttachmentStore::blob_id`].
+    pub id: String,
+    pub name: String,
+    pub size: u64,
+    pub sha256: String,
+}
+
+/// Inclusive byte range requested through an HTTP `Range` header.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct ByteRange {
+    pub start: u64,
+    pub end: u64,
+}
+
+impl ByteRange {
+    /// Parse a single-range `bytes=` header against a blob of `total` bytes.
+    ///
+    /// Supports `bytes=a-b`, open-ended `bytes=a-`, and suffix `bytes=-n` forms.
+    pub fn parse(header: &str, total: u64) -> Result<Self, AttachmentError> {
+        let invalid = || AttachmentError::InvalidRange(header.to_string());
+        let spec = header.trim().strip_prefix("bytes=").ok_or_else(invalid)?;
+        if spec.contains(',') {
+            return Err(invalid());
+        }
+        let (start, end) = spec.split_once('-').ok_or_else(invalid)?;
+        let (start, end) = match (start.trim(), end.trim()) {
+            ("", suffix) => {
+                let suffix: u64 = suffix.parse().map_err(|_| invalid())?;
+                if suffix == 0 {
+                    return Err(AttachmentError::RangeNotSatisfiable(total));
+                }
+                (total.saturating_sub(suffix), total.saturating_sub(1))
+            }
+            (start, "") => (
+                start.parse().map_err(|_| invalid())?,
+                total.saturating_sub(1),
+            ),
+            (start, end) => {
+                let start: u64 = start.parse().map_err(|_| invalid())?;
+                let end: u64 = end.parse().map_err(|_| invalid())?;
+                if start > end {
+                    return Err(invalid());
+                }
+                (start, end.min(total.saturating_sub(1)))
+            }
+        };
+        if total == 0 || start >= total {
+            return Err(AttachmentError::RangeNotSatisfiable(total));
+        }
+        Ok(Self { start, end })
+    }
+
+    pub fn length(&self) -> u64 {
+        self.end - self.start + 1
+    }
+}
+
+/// Reader over a stored blob that never holds more than one chunk in memory.
+pub struct AttachmentStream {
+    reader: Box<dyn Read + Send>,
+    pub total: u64,
+    pub range: Option<ByteRange>,
+}
+
+impl AttachmentStream {
+    /// Number of bytes the stream will yield.
+    pub fn content_length(&self) -> u64 {
+        self.range.map(|range| range.length()).unwrap_or(self.total)
+    }
+
+    /// Value for the `Content-Range` response header on partial responses.
+    pub fn content_range(&self) -> Option<String> {
+        self.range
+            .map(|range| format!("bytes {}-{}/{}", range.start, range.end, self.total))
+    }
+}
+
+impl Read for AttachmentStream {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        self.reader.read(buf)
+    }
+}
+
+/// Content-addressed attachment storage rooted below the service data directory.
+#[derive(Clone)]
+pub struct AttachmentStore {
+    base: Arc<PathBuf>,
+    key: Option<Arc<LessSafeKey>>,
+    names: Option<Arc<hmac::Key>>,
+    rng: SystemRandom,
+}
+
+impl AttachmentStore {
+    pub fn new(path: impl Into<PathBuf>) -> Self {
+        Self {
+            base: Arc::new(path.into()),
+            key: None,
+            names: None,
+            rng: SystemRandom::new(),
+        }
+    }
+
+    /// Seal blobs written from now on with a key derived from `secret`.
+    pub fn with_encryption(mut self, secret: &str) -> Self {
+        let mut key = [0u8; 32];
+        pbkdf2::derive(
+            pbkdf2::PBKDF2_HMAC_SHA256,
+            NonZeroU32::new(PBKDF2_ROUNDS).expect("non-zero rounds"),
+            KEY_SALT,
+            secret.as_bytes(),
+            &mut key,
+        );
+        let names = hmac::sign(&hmac::Key::new(hmac::HMAC_SHA256, &key), NAME_KEY_LABEL);
+        self.names = Some(Arc::new(hmac::Key::new(hmac::HMAC_SHA256, names.as_ref())));
+        let key = UnboundKey::new(&AES_256_GCM, &key).expect("32-byte AES key");
+        self.key = Some(Arc::new(LessSafeKey::new(key)));
+        self
+    }
+
+    pub fn is_encrypted(&self) -> bool {
+        self.key.is_some()
+    }
+
+    /// Id the blob with content digest `sha256` is stored under: the digest
+    /// itself, or its keyed hash when blobs are encrypted.
+    pub fn blob_id(&self, sha256: &str) -> String {
+        match &self.names {
+            Some(names) => hex(hmac::sign(names, sha256.as_bytes()).as_ref()),
+            None => sha256.to_string(),
+        }
+    }
+
+    /// Seal every blob still stored in the clear under its keyed name and
+    /// remove the plain file, returning the new id of each old one. Does
+    /// nothing without encryption.
+    pub fn seal_plaintext(&self) -> Result<BTreeMap<String, String>, AttachmentError> {
+        let mut renamed = BTreeMap::new();
+        if self.key.is_none() {
+            return Ok(renamed);
+        }
+        let directory = Path::new(&*self.base).join("attachments");
+        let entries = match fs::read_dir(&directory) {
+            Ok(entries) => entries,
+            Err(err) if err.kind() == io::ErrorKind::NotFound => return Ok(renamed),
+            Err(err) => return Err(err.into()),
+        };
+        for entry in entries {
+            let entry = entry?;
+            let name = entry.file_name().to_string_lossy().into_owned();
+            if self.blob_path(&name).is_err() || !entry.file_type()?.is_file() {
+                continue;
+            }
+            if is_sealed(&entry.path())? {
+                continue;
+            }
+            let stored = self.put(&name, File::open(entry.path())?)?;
+            if stored.id != name {
+                fs::remove_file(entry.path())?;
+                renamed.insert(name, stored.id);
+            }
+        }
+        Ok(renamed)
+    }
+
+    /// Copy `reader` into the store in fixed-size chunks, hashing as it goes.
+    pub fn put(&self, name: &str, reader: impl Read) -> Result<StoredAttachment, AttachmentError> {
+        let directory = self.ensure_directory()?;
+        let staging = directory.join(format!(".upload-{}", uuid::Uuid::new_v4()));
+        let mut file = File::create(&staging)?;
+        let (size, sha256) = match &self.key {
+            Some(key) => {
+                let spool = directory.join(format!(".spool-{}", uuid::Uuid::new_v4()));
+                let sealed = self.seal_spooled(key, reader, &spool, &mut file);
+                let _ = fs::remove_file(&spool);
+                sealed?
+            }
+            None => copy_chunks(reader, |chunk| file.write_all(chunk))?,
+        };
+        file.sync_all()?;
+        drop(file);
+
+        let id = self.blob_id(&sha256);
+        let target = directory.join(&id);
+        // A blob left in the clear under the same name is replaced by the
+        // sealed one.
+        if target.exists() && (self.key.is_none() || is_sealed(&target)?) {
+            fs::remove_file(&staging)?;
+        } else {
+            fs::rename(&staging, &target)?;
+        }
+        Ok(StoredAttachment {
+            id,
+            name: name.to_string(),
+            size,
+            sha256,
+        })
+    }
+
+    /// Seal `reader` into `file` with the plaintext size in every chunk's
+    /// associated data. The size is only known at the end, so the upload is
+    /// first sealed into `spool` under a throwaway key and resealed from
+    /// there; the plaintext never reaches the disk.
+    fn seal_spooled(
+        &self,
+        key: &Arc<LessSafeKey>,
+        reader: impl Read,
+        spool: &Path,
+        file: &mut File,
+    ) -> Result<(u64, String), AttachmentError> {
+        let mut secret = [0u8; 32];
+        self.rng
+            .fill(&mut secret)
+            .map_err(|_| AttachmentError::Random)?;
+        let throwaway =
+            UnboundKey::new(&AES_256_GCM, &secret).map_err(|_| AttachmentError::Random)?;
+        let throwaway = Arc::new(LessSafeKey::new(throwaway));
+
+        let mut spooled = File::create(spool)?;
+        let mut sealer = Sealer::start(throwaway.clone(), &self.rng, 0, &mut spooled)?;
+        let (size, sha256) = copy_chunks(reader, |chunk| sealer.push(chunk, &mut spooled))?;
+        let header = sealer.header;
+        sealer.finish(&mut spooled)?;
+        drop(spooled);
+
+        let spooled = Opener::new(throwaway, &header, File::open(spool)?, size, 0, size)?;
+        let mut sealer = Sealer::start(key.clone(), &self.rng, size, file)?;
+        copy_chunks(spooled, |chunk| sealer.push(chunk, file))?;
+        sealer.finish(file)?;
+        Ok((size, sha256))
+    }
+
+    /// Open an attachment, optionally restricted to the given `Range` header;
+    /// a malformed header is an [`AttachmentError::InvalidRange`].
+    pub fn open(&self, id: &str, range: Option<&str>) -> Result<AttachmentStream, AttachmentError> {
+        let path = self.blob_path(id)?;
+        let mut file = File::open(&path).map_err(|err| match err.kind() {
+            io::ErrorKind::NotFound => AttachmentError::NotFound(id.to_string()),
+            _ => AttachmentError::Io(err),
+        })?;
+        let mut header = [0u8; HEADER_LEN];
+        let sealed = file.read_exact(&mut header).is_ok() && header.starts_with(MAGIC);
+        let total = match sealed {
+            true => u64::from_be_bytes(header[HEADER_LEN - 8..].try_into().expect("8 bytes")),
+            false => file.metadata()?.len(),
+        };
+        let range = range
+            .map(|header| ByteRange::parse(header, total))
+            .transpose()?;
+        let (offset, length) = match range {
+            Some(range) => (range.start, range.length()),
+            None => (0, total),
+        };
+        if !sealed {
+            file.seek(SeekFrom::Start(offset))?;
+            return Ok(AttachmentStream {
+                reader: Box::new(file.take(length)),
+                total,
+                range,
+            });
+        }
+        // Sealed blobs cannot be read without the key they were sealed with.
+        let key = self.key.clone().ok_or_else(|| {
+            AttachmentError::Io(io::Error::new(
+                io::ErrorKind::PermissionDenied,
+                "attachment is encrypted and database.sqlcipherKey is not set",
+            ))
+        })?;
+        let opener = Opener::new(key, &header, file, total, offset, length)?;
+        Ok(AttachmentStream {
+            reader: Box::new(opener),
+            total,
+            range,
+        })
+    }
+
+    pub fn delete(&self, id: &str) -> Result<bool, AttachmentError> {
+        let path = self.blob_path(id)?;
+        match fs::remove_file(path) {
+            Ok(()) => Ok(true),
+            Err(err) if err.kind() == io::ErrorKind::NotFound => Ok(false),
+            Err(err) => Err(err.into()),
+        }
+    }
+
+    fn blob_path(&self, id: &str) -> Result<PathBuf, AttachmentError> {
+        if id.is_empty() || !id.chars().all(|c| c.is_ascii_hexdigit()) {
+            return Err(AttachmentError::NotFound(id.to_string()));
+        }
+        Ok(Path::new(&*self.base).join("attachments").join(id))
+    }
+
+    fn ensure_directory(&self) -> Result<PathBuf, AttachmentError> {
+        let directory = Path::new(&*self.base).join("attachments");
+        fs::create_dir_all(&directory)?;
+        Ok(directory)
+    }
+}
+
+/// Whether the blob at `path` starts with the sealed header.
+fn is_sealed(path: &Path) -> io::Result<bool> {
+    let mut magic = [0u8; MAGIC.len()];
+    match File::open(path)?.read_exact(&mut magic) {
+        Ok(()) => Ok(&magic == MAGIC),
+        Err(err) if err.kind() == io::ErrorKind::UnexpectedEof => Ok(false),
+        Err(err) => Err(err),
+    }
+}
+
+fn hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|byte| format!("{byte:02x}")).collect()
+}
+
+/// Copy `reader` to `write` in full chunks, returning the size and SHA-256
+/// of what was copied.
+fn copy_chunks(
+    mut reader: impl Read,
+    mut write: impl FnMut(&[u8]) -> io::Result<()>,
+) -> io::Result<(u64, String)> {
+    let mut hasher = Sha256::new();
+    let mut buffer = vec![0u8; CHUNK_SIZE];
+    let mut size = 0u64;
+    loop {
+        let read = fill(&mut reader, &mut buffer)?;
+        if read == 0 {
+            break;
+        }
+        hasher.update(&buffer[..read]);
+        write(&buffer[..read])?;
+        size += read as u64;
+    }
+    Ok((size, format!("{:x}", hasher.finalize())))
+}
+
+/// Read until `buffer` is full or `reader` is exhausted, so that every
+/// sealed chunk but the last is full.
+fn fill(reader: &mut impl Read, buffer: &mut [u8]) -> io::Result<usize> {
+    let mut filled = 0;
+    while filled < buffer.len() {
+        match reader.read(&mut buffer[filled..]) {
+            Ok(0) => break,
+            Ok(read) => filled += read,
+            Err(err) if err.kind() == io::ErrorKind::Interrupted => {}
+            Err(err) => return Err(err),
+        }
+    }
+    Ok(filled)
+}
+
+fn nonce(prefix: &[u8], index: u32) -> Nonce {
+    let mut nonce = [0u8; NONCE_LEN];
+    nonce[..PREFIX_LEN].copy_from_slice(prefix);
+    nonce[PREFIX_LEN..].copy_from_slice(&index.to_be_bytes());
+    Nonce::assume_unique_for_key(nonce)
+}
+
+/// Associated data of a chunk: the whole header, plaintext size included,
+/// and whether the chunk is the last one.
+fn aad(header: &[u8; HEADER_LEN], last: bool) -> [u8; HEADER_LEN + 1] {
+    let mut aad = [0u8; HEADER_LEN + 1];
+    aad[..HEADER_LEN].copy_from_slice(header);
+    aad[HEADER_LEN] = u8::from(last);
+    aad
+}
+
+/// Seals chunks as they are written, holding one back to mark the last.
+struct Sealer {
+    key: Arc<LessSafeKey>,
+    header: [u8; HEADER_LEN],
+    index: u32,
+    pending: Option<Vec<u8>>,
+}
+
+impl Sealer {
+    fn start(
+        key: Arc<LessSafeKey>,
+        rng: &SystemRandom,
+        size: u64,
+        file: &mut File,
+    ) -> Result<Self, AttachmentError> {
+        let mut header = [0u8; HEADER_LEN];
+        header[..MAGIC.len()].copy_from_slice(MAGIC);
+        rng.fill(&mut header[MAGIC.len()..MAGIC.len() + PREFIX_LEN])
+            .map_err(|_| AttachmentError::Random)?;
+        header[HEADER_LEN - 8..].copy_from_slice(&size.to_be_bytes());
+        file.write_all(&header)?;
+        Ok(Self {
+            key,
+            header,
+            index: 0,
+            pending: None,
+        })
+    }
+
+    fn push(&mut self, chunk: &[u8], file: &mut File) -> io::Result<()> {
+        if let Some(pending) = self.pending.replace(chunk.to_vec()) {
+            self.seal(pending, false, file)?;
+        }
+        Ok(())
+    }
+
+    /// Seal the last chunk, empty for an empty blob.
+    fn finish(mut self, file: &mut File) -> io::Result<()> {
+        let last = self.pending.take().unwrap_or_default();
+        self.seal(last, true, file)
+    }
+
+    fn seal(&mut self, mut chunk: Vec<u8>, last: bool, file: &mut File) -> io::Result<()> {
+        let prefix = &self.header[MAGIC.len()..MAGIC.len() + PREFIX_LEN];
+        self.key
+            .seal_in_place_append_tag(
+                nonce(prefix, self.index),
+                Aad::from(aad(&self.header, last)),
+                &mut chunk,
+            )
+            .map_err(|_| io::Error::other("sealing failed"))?;
+        self.index += 1;
+        file.write_all(&chunk)
+    }
+}
+
+/// Opens the chunks of a sealed blob covering one byte range.
+struct Opener {
+    key: Arc<LessSafeKey>,
+    header: [u8; HEADER_LEN],
+    file: File,
+    chunks: u64,
+    total: u64,
+    index: u64,
+    chunk: Vec<u8>,
+    position: usize,
+    remaining: u64,
+}
+
+impl Opener {
+    fn new(
+        key: Arc<LessSafeKey>,
+        header: &[u8; HEADER_LEN],
+        mut file: File,
+        total: u64,
+        offset: u64,
+        length: u64,
+    ) -> io::Result<Self> {
+        let chunk_size = CHUNK_SIZE as u64;
+        let index = offset / chunk_size;
+        file.seek(SeekFrom::Start(
+            HEADER_LEN as u64 + index * (chunk_size + TAG_LEN as u64),
+        ))?;
+        let mut opener = Self {
+            key,
+            header: *header,
+            file,
+            chunks: total.div_ceil(chunk_size).max(1),
+            total,
+            index,
+            chunk: Vec::new(),
+            position: 0,
+            remaining: length,
+        };
+        if length > 0 {
+            opener.next_chunk()?;
+            opener.position = (offset % chunk_size) as usize;
+        }
+        Ok(opener)
+    }
+
+    fn next_chunk(&mut self) -> io::Result<()> {
+        let unreadable =
+            || io::Error::new(io::ErrorKind::InvalidData, "attachment does not decrypt");
+        if self.index >= self.chunks {
+            return Err(unreadable());
+        }
+        let last = self.index + 1 == self.chunks;
+        let plain = match last {
+            true => self.total - self.index * CHUNK_SIZE as u64,
+            false => CHUNK_SIZE as u64,
+        };
+        let mut sealed = vec![0u8; plain as usize + TAG_LEN];
+        self.file.read_exact(&mut sealed)?;
+        let prefix = &self.header[MAGIC.len()..MAGIC.len() + PREFIX_LEN];
+        let index = u32::try_from(self.index).map_err(|_| unreadable())?;
+        let opened = self
+            .key
+            .open_in_place(
+                nonce(prefix, index),
+                Aad::from(aad(&self.header, last)),
+                &mut sealed,
+            )
+            .map_err(|_| unreadable())?
+            .len();
+        sealed.truncate(opened);
+        self.chunk = sealed;
+        self.position = 0;
+        self.index += 1;
+        Ok(())
+    }
+}
+
+impl Read for Opener {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        if self.remaining == 0 || buf.is_empty() {
+            return Ok(0);
+        }
+        if self.position == self.chunk.len() {
+            self.next_chunk()?;
+        }
+        let available = (self.chunk.len() - self.position).min(buf.len());
+        let count = available.min(usize::try_from(self.remaining).unwrap_or(usize::MAX));
+        buf[..count].copy_from_slice(&self.chunk[self.position..self.position + count]);
+        self.position += count;
+        self.remaining -= count as u64;
+        Ok(count)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_range_forms() {
+        assert_eq!(
+            ByteRange::parse("bytes=0-9", 100).unwrap(),
+            ByteRange { start: 0, end: 9 }
+        );
+        assert_eq!(
+            ByteRange::parse("bytes=90-", 100).unwrap(),
+            ByteRange { start: 90, end: 99 }
+        );
+        assert_eq!(
+            ByteRange::parse("bytes=-5", 100).unwrap(),
+            ByteRange { start: 95, end: 99 }
+        );
+        assert!(matches!(
+            ByteRange::parse("bytes=100-", 100),
+            Err(AttachmentError::RangeNotSatisfiable(100))
+        ));
+        assert!(matches!(
+            ByteRange::parse("items=0-1", 100),
+            Err(AttachmentError::InvalidRange(_))
+        ));
+        assert!(matches!(
+            ByteRange::parse("bytes=5-2", 100),
+            Err(AttachmentError::InvalidRange(_))
+        ));
+    }
+
+    #[test]
+    fn streams_requested_range() {
+        let temp = tempfile::tempdir().unwrap();
+        let store = AttachmentStore::new(temp.path());
+        let payload = (0..200_000u32).map(|i| (i % 251) as u8).collect::<Vec<_>>();
+        let stored = store.put("large.bin", payload.as_slice()).unwrap();
+        assert_eq!(stored.size, payload.len() as u64);
+
+        let mut stream = store.open(&stored.id, Some("bytes=100000-100009")).unwrap();
+        assert_eq!(stream.content_length(), 10);
+        assert_eq!(
+            stream.content_range().as_deref(),
+            Some("bytes 100000-100009/200000")
+        );
+        let mut partial = Vec::new();
+        stream.read_to_end(&mut partial).unwrap();
+        assert_eq!(partial, payload[100_000..100_010]);
+
+        assert!(matches!(
+            store.open(&stored.id, Some("bytes=5-2")),
+            Err(AttachmentError::InvalidRange(_))
+        ));
+        assert!(matches!(
+            store.open(&stored.id, Some("items=0-1")),
+            Err(AttachmentError::InvalidRange(_))
+        ));
+        assert!(matches!(
+            store.open(&stored.id, Some("bytes=200000-")),
+            Err(AttachmentError::RangeNotSatisfiable(200_000))
+        ));
+
+        assert!(matches!(
+            store.open("deadbeef", None),
+            Err(AttachmentError::NotFound(_))
+        ));
+    }
+
+    fn read_all(store: &AttachmentStore, id: &str) -> Result<Vec<u8>, AttachmentError> {
+        let mut content = Vec::new();
+        store.open(id, None)?.read_to_end(&mut content)?;
+        Ok(content)
+    }
+
+    #[test]
+    fn seals_blobs_and_opens_ranges_across_chunks() {
+        let temp = tempfile::tempdir().unwrap();
+        let plain = AttachmentStore::new(temp.path());
+        let legacy = plain
+            .put("legacy.txt", &b"written in the clear"[..])
+            .unwrap();
+        let store = plain.with_encryption("sqlcipher-key");
+        let payload = (0..150_000u32).map(|i| (i % 253) as u8).collect::<Vec<_>>();
+        let stored = store.put("large.bin", payload.as_slice()).unwrap();
+
+        let path = temp.path().join("attachments").join(&stored.id);
+        let on_disk = fs::read(&path).unwrap();
+        assert!(on_disk.starts_with(MAGIC));
+        assert!(!on_disk.windows(64).any(|window| window == &payload[..64]));
+
+        assert_eq!(read_all(&store, &stored.id).unwrap(), payload);
+        let mut stream = store.open(&stored.id, Some("bytes=65530-65545")).unwrap();
+        assert_eq!(
+            stream.content_range().as_deref(),
+            Some("bytes 65530-65545/150000")
+        );
+        let mut partial = Vec::new();
+        stream.read_to_end(&mut partial).unwrap();
+        assert_eq!(partial, payload[65_530..65_546]);
+
+        assert_eq!(
+            read_all(&store, &legacy.id).unwrap(),
+            b"written in the clear"
+        );
+
+        let mut tampered = on_disk.clone();
+        tampered[HEADER_LEN + 10] ^= 1;
+        fs::write(&path, &tampered).unwrap();
+        assert!(read_all(&store, &stored.id).is_err());
+        // A size still within the last chunk changes no chunk boundary, but
+        // the size is part of every chunk's associated data, the first one's
+        // too.
+        let mut resized = on_disk.clone();
+        resized[HEADER_LEN - 8..HEADER_LEN].copy_from_slice(&149_999u64.to_be_bytes());
+        fs::write(&path, &resized).unwrap();
+        assert!(store.open(&stored.id, Some("bytes=0-15")).is_err());
+        let empty = store.put("empty.bin", &b""[..]).unwrap();
+        assert!(read_all(&store, &empty.id).unwrap().is_empty());
+        let small = store.put("small.txt", &b"sealed"[..]).unwrap();
+        let other = AttachmentStore::new(temp.path()).with_encryption("other-key");
+        assert!(read_all(&other, &small.id).is_err());
+        assert!(AttachmentStore::new(temp.path())
+            .open(&small.id, None)
+            .is_err());
+    }
+
+    #[test]
+    fn names_sealed_blobs_by_keyed_hash_and_reseals_plain_ones() {
+        let temp = tempfile::tempdir().unwrap();
+        let plain = AttachmentStore::new(temp.path());
+        let legacy = plain.put("legacy.txt", &b"old content"[..]).unwrap();
+        assert_eq!(legacy.id, legacy.sha256);
+
+        let store = plain.with_encryption("sqlcipher-key");
+        let renamed = store.seal_plaintext().unwrap();
+        let new_id = renamed.get(&legacy.id).unwrap();
+        assert_eq!(*new_id, store.blob_id(&legacy.sha256));
+        assert_ne!(*new_id, legacy.sha256);
+        assert!(!temp.path().join("attachments").join(&legacy.id).exists());
+        let on_disk = fs::read(temp.path().join("attachments").join(new_id)).unwrap();
+        assert!(on_disk.starts_with(MAGIC));
+        assert_eq!(read_all(&store, new_id).unwrap(), b"old content");
+        assert!(store.seal_plaintext().unwrap().is_empty());
+
+        let stored = store.put("again.txt", &b"old content"[..]).unwrap();
+        assert_eq!(stored.id, *new_id);
+        assert_eq!(stored.sha256, legacy.sha256);
+        let other = AttachmentStore::new(temp.path()).with_encryption("other-key");
+        assert_ne!(other.blob_id(&stored.sha256), stored.id);
+    }
+}
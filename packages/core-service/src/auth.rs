@@ -0,0 +1,383 @@
+//! Authentication for the local API.
+//!
+//! Plain TCP clients send their API key or `Authorization` value in the
+//! channel's `auth` frame, and [`crate::ipc`] asks the [`Authenticator`] for a
+//! [`Principal`]; failures map to 401. Each command names the scope it needs
+//! through [`ChannelCommand::scope`], which [`Principal::require`] checks
+//! (403 when missing). [`Role`]s expand to fixed scope sets so keys can be
+//! issued as e.g. "auditor" without listing scopes by hand.
+//!
+//! [`ChannelCommand::scope`]: crate::channel::ChannelCommand::scope
+
+use thiserror::Error;
+
+use crate::config::{ClientCertConfig, SecurityConfig};
+use crate::tls::ClientIdentity;
+
+/// Scope granting every permission.
+pub const ALL_SCOPES: &str = "*";
+
+#[derive(Debug, Error, PartialEq, Eq)]
+pub enum AuthError {
+    #[error("missing or invalid credentials")]
+    Unauthorized,
+    #[error("key {key} lacks scope {scope}")]
+    Forbidden { key: String, scope: String },
+}
+
+impl AuthError {
+    pub fn status(&self) -> u16 {
+        match self {
+            Self::Unauthorized => 401,
+            Self::Forbidden { .. } => 403,
+        }
+    }
+}
+
+/// Coarse roles attached to API keys.
+///
+/// Deleting messages, reloading config and importing legacy archives are not
+/// served over the channel (see [`crate::channel::ChannelCommand::scope`]), so
+/// no role grants a scope for them and no key can reach them.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Role {
+    /// Day-to-day mailbox operation: reading and submitting messages.
+    Operator,
+    /// Read-only monitoring: status, message listings, traces, and the
+    /// audit log.
+    Auditor,
+    /// Follows legacy migrations but cannot touch live mail.
+    MigrationAdmin,
+}
+
+impl Role {
+    pub fn parse(value: &str) -> Option<Self> {
+        match value.trim().to_ascii_lowercase().as_str() {
+            "operator" => Some(Self::Operator),
+            "auditor" => Some(Self::Auditor),
+            "migration-admin" => Some(Self::MigrationAdmin),
+            _ => None,
+        }
+    }
+
+    pub fn scopes(&self) -> &'static [&'static str] {
+        match self {
+            Self::Operator => &[
+                "status:read",
+                "metrics:read",
+                "messages:read",
+                "trace:read",
+                "migration:read",
+                "messages:write",
+            ],
+            Self::Auditor => &[
+                "status:read",
+                "metrics:read",
+                "messages:read",
+                "trace:read",
+                "audit:read",
+            ],
+            Self::MigrationAdmin => &["status:read", "migration:read"],
+        }
+    }
+}
+
+/// Identity attached to an authenticated request.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Principal {
+    pub key_name: String,
+    pub scopes: Vec<String>,
+}
+
+impl Principal {
+    fn anonymous() -> Self {
+        Self {
+            key_name: "anonymous".into(),
+            scopes: vec![ALL_SCOPES.into()],
+        }
+    }
+
+    /// A caller on the owner-only local socket, trusted like the owner.
+    #[cfg(unix)]
+    pub(crate) fn local() -> Self {
+        Self {
+            key_name: "local".into(),
+            scopes: vec![ALL_SCOPES.into()],
+        }
+    }
+
+    pub fn allows(&self, scope: &str) -> bool {
+        self.scopes.iter().any(|granted| {
+            granted == ALL_SCOPES
+                || granted == scope
+                || granted
+                    .strip_suffix(":*")
+                    .map(|prefix| scope.starts_with(&format!("{prefix}:")))
+                    .unwrap_or(false)
+        })
+    }
+
+    pub fn require(&self, scope: &str) -> Result<(), AuthError> {
+        if self.allows(scope) {
+            Ok(())
+        } else {
+            Err(AuthError::Forbidden {
+                key: self.key_name.clone(),
+                scope: scope.to_string(),
+            })
+        }
+    }
+}
+
+#[derive(Clone, Debug)]
+struct Credential {
+    name: String,
+    secret: String,
+    scopes: Vec<String>,
+}
+
+/// Validates API keys and bearer tokens against the configured credentials.
+#[derive(Clone, Debug)]
+pub struct Authenticator {
+    required: bool,
+    credentials: Vec<Credential>,
+    client_certs: Vec<ClientCertConfig>,
+}
+
+impl Authenticator {
+    pub fn from_config(config: &SecurityConfig) -> Self {
+        let mut credentials: Vec<Credential> = config
+            .keys
+            .iter()
+            .filter(|key| !key.secret.is_empty())
+            .map(|key| {
+                let mut scopes = key.scopes.clone();
+                for role in &key.roles {
+                    scopes.extend(role.scopes().iter().map(|scope| scope.to_string()));
+                }
+                Credential {
+                    name: key.name.clone(),
+                    secret: key.secret.clone(),
+                    scopes,
+                }
+            })
+            .collect();
+        if let Some(secret) = &config.api_key {
+            credentials.push(Credential {
+                name: "default".into(),
+                secret: secret.clone(),
+                scopes: vec![ALL_SCOPES.into()],
+            });
+        }
+        Self {
+            required: config.require_auth,
+            credentials,
+            client_certs: config.client_certs.clone(),
+        }
+    }
+
+    pub fn is_required(&self) -> bool {
+        self.required
+    }
+
+    /// Resolve the caller from the `X-Api-Key` and `Authorization` header values.
+    ///
+    /// When authentication is disabled every request is treated as an
+    /// anonymous principal with all scopes, but presented credentials are
+    /// still checked so clients notice misconfigured keys early.
+    pub fn authenticate(
+        &self,
+        api_key: Option<&str>,
+        authorization: Option<&str>,
+    ) -> Result<Principal, AuthError> {
+        let presented = api_key.map(str::trim).or_else(|| {
+            authorization.and_then(|value| {
+                let (scheme, token) = value.trim().split_once(' ')?;
+                scheme
+                    .eq_ignore_ascii_case("bearer")
+                    .then_some(token.trim())
+            })
+        });
+
+        let Some(presented) = presented else {
+            return if self.required {
+                Err(AuthError::Unauthorized)
+            } else {
+                Ok(Principal::anonymous())
+            };
+        };
+
+        self.credentials
+            .iter()
+            .find(|credential| constant_time_eq(credential.secret.as_bytes(), presented.as_bytes()))
+            .map(|credential| Principal {
+                key_name: credential.name.clone(),
+                scopes: credential.scopes.clone(),
+            })
+            .ok_or(AuthError::Unauthorized)
+    }
+}
+
+impl Authenticator {
+    /// Resolve a caller that authenticated with a verified mTLS certificate.
+    ///
+    /// The CA already vouched for the certificate; this only decides what the
+    /// holder may do, so unknown common names are rejected.
+    pub fn authenticate_certificate(
+        &self,
+        identity: &ClientIdentity,
+    ) -> Result<Principal, AuthError> {
+        let common_name = identity
+            .common_name
+            .as_deref()
+            .ok_or(AuthError::Unauthorized)?;
+        let mapping = self
+            .client_certs
+            .iter()
+            .find(|mapping| mapping.common_name == common_name)
+            .ok_or(AuthError::Unauthorized)?;
+        Ok(Principal {
+            key_name: format!("cert:{common_name}"),
+            scopes: mapping
+                .roles
+                .iter()
+                .flat_map(|role| role.scopes().iter().map(|scope| scope.to_string()))
+                .collect(),
+        })
+    }
+}
+
+fn constant_time_eq(left: &[u8], right: &[u8]) -> bool {
+    if left.len() != right.len() {
+        return false;
+    }
+    left.iter()
+        .zip(right)
+        .fold(0u8, |acc, (a, b)| acc | (a ^ b))
+        == 0
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::ApiKeyConfig;
+
+    fn config() -> SecurityConfig {
+        SecurityConfig {
+            require_auth: true,
+            api_key: Some("legacy".into()),
+            keys: vec![ApiKeyConfig {
+                name: "monitoring".into(),
+                secret: "mon-secret".into(),
+                secret_ref: None,
+                scopes: vec!["messages:read".into(), "trace:*".into()],
+                roles: Vec::new(),
+            }],
+            client_certs: vec![ClientCertConfig {
+                common_name: "desktop-shell".into(),
+                roles: vec![Role::Auditor],
+            }],
+            ..SecurityConfig::default()
+        }
+    }
+
+    #[test]
+    fn accepts_api_keys_and_bearer_tokens() {
+        let auth = Authenticator::from_config(&config());
+        let monitoring = auth.authenticate(Some("mon-secret"), None).unwrap();
+        assert_eq!(monitoring.key_name, "monitoring");
+        assert!(monitoring.allows("trace:bundle"));
+        assert_eq!(
+            monitoring.require("messages:delete").unwrap_err().status(),
+            403
+        );
+
+        let legacy = auth.authenticate(None, Some("Bearer legacy")).unwrap();
+        assert!(legacy.allows("messages:delete"));
+
+        assert_eq!(
+            auth.authenticate(None, None).unwrap_err(),
+            AuthError::Unauthorized
+        );
+        assert_eq!(
+            auth.authenticate(Some("wrong"), None).unwrap_err().status(),
+            401
+        );
+    }
+
+    #[test]
+    fn keys_without_scopes_or_roles_are_granted_nothing() {
+        let mut config = config();
+        config.keys[0].scopes.clear();
+        let auth = Authenticator::from_config(&config);
+        let bare = auth.authenticate(Some("mon-secret"), None).unwrap();
+        assert_eq!(
+            bare.require("messages:write").unwrap_err(),
+            AuthError::Forbidden {
+                key: "monitoring".into(),
+                scope: "messages:write".into(),
+            }
+        );
+        assert!(!bare.allows("status:read"));
+    }
+
+    #[test]
+    fn disabled_auth_admits_anonymous_callers() {
+        let mut config = config();
+        config.require_auth = false;
+        let auth = Authenticator::from_config(&config);
+        assert!(auth.authenticate(None, None).unwrap().allows("anything"));
+        assert!(auth.authenticate(Some("wrong"), None).is_err());
+    }
+
+    #[test]
+    fn roles_grant_no_destructive_scopes() {
+        let mut config = config();
+        config.keys = ["operator", "auditor", "migration-admin"]
+            .iter()
+            .map(|role| ApiKeyConfig {
+                name: role.to_string(),
+                secret: format!("{role}-secret"),
+                secret_ref: None,
+                scopes: Vec::new(),
+                roles: vec![Role::parse(role).unwrap()],
+            })
+            .collect();
+        let auth = Authenticator::from_config(&config);
+        let principal = |role: &str| {
+            auth.authenticate(Some(&format!("{role}-secret")), None)
+                .unwrap()
+        };
+
+        let auditor = principal("auditor");
+        for scope in ["status:read", "metrics:read", "messages:read", "audit:read"] {
+            assert!(auditor.require(scope).is_ok(), "{scope}");
+        }
+        assert!(auditor.require("messages:delete").is_err());
+        assert!(auditor.require("messages:write").is_err());
+        assert!(auditor.require("migration:import").is_err());
+
+        let operator = principal("operator");
+        assert!(operator.require("messages:write").is_ok());
+        assert!(operator.require("messages:delete").is_err());
+        assert!(operator.require("config:reload").is_err());
+        assert!(operator.require("migration:import").is_err());
+        assert!(operator.require("audit:read").is_err());
+
+        let migration = principal("migration-admin");
+        assert!(migration.require("migration:read").is_ok());
+        assert!(migration.require("migration:import").is_err());
+        assert!(migration.require("messages:read").is_err());
+        assert_eq!(migration.require("metrics:read").unwrap_err().status(), 403);
+
+        let identity = ClientIdentity {
+            subject: "CN=desktop-shell".into(),
+            common_name: Some("desktop-shell".into()),
+            fingerprint_sha256: String::new(),
+        };
+        let shell = auth.authenticate_certificate(&identity).unwrap();
+        assert_eq!(shell.key_name, "cert:desktop-shell");
+        assert!(shell.require("trace:read").is_ok());
+        assert!(shell.require("messages:write").is_err());
+    }
+}
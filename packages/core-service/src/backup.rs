@@ -0,0 +1,318 @@
+//! `POST /admin/backup` and `POST /admin/restore`: encrypted store snapshots.
+//!
+//! A backup is a [`StoreSnapshot`] sealed with AES-256-GCM under a key
+//! derived from `database.backupKey`, so the file can sit on shared storage.
+//! Snapshots are taken while the service keeps running and restored in one
+//! step; a snapshot that does not decrypt is rejected before anything changes.
+//! After a restore the dispatch queue is rebuilt from the restored `queue`
+//! table, so messages queued in the snapshot are sent and ones dropped by it
+//! are not.
+
+use std::num::NonZeroU32;
+
+use chrono::{DateTime, Utc};
+use ring::aead::{Aad, LessSafeKey, Nonce, UnboundKey, AES_256_GCM, NONCE_LEN};
+use ring::pbkdf2;
+use ring::rand::{SecureRandom, SystemRandom};
+use serde::{Deserialize, Serialize};
+use thiserror::Error;
+use tracing::info;
+
+use crate::audit::AuditLog;
+use crate::config::DatabaseConfig;
+use crate::models::AuditAction;
+use crate::queue::QueueManager;
+use crate::store::{StoreManager, StoreSnapshot};
+
+const MAGIC: &[u8; 5] = b"X4BK1";
+const SALT_LEN: usize = 16;
+const HEADER_LEN: usize = MAGIC.len() + SALT_LEN + NONCE_LEN;
+const PBKDF2_ROUNDS: u32 = 100_000;
+
+#[derive(Debug, Error, PartialEq, Eq)]
+pub enum BackupError {
+    #[error("backups are disabled until database.backupKey is set")]
+    NotConfigured,
+    #[error("not a backup file, or it was sealed with a different key")]
+    Unreadable,
+    #[error("failed to encode snapshot: {0}")]
+    Encode(String),
+    #[error("store unavailable: {0}")]
+    Store(String),
+}
+
+impl BackupError {
+    pub fn status(&self) -> u16 {
+        match self {
+            Self::NotConfigured => 503,
+            Self::Unreadable => 400,
+            Self::Encode(_) | Self::Store(_) => 500,
+        }
+    }
+}
+
+/// Summary returned by `POST /admin/restore`.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct RestoreReport {
+    pub taken_at: DateTime<Utc>,
+    pub messages: usize,
+    pub queued: usize,
+    pub folders: usize,
+}
+
+#[derive(Clone)]
+pub struct BackupManager {
+    store: StoreManager,
+    passphrase: Option<String>,
+    rng: SystemRandom,
+    audit: Option<AuditLog>,
+    queue: Option<QueueManager>,
+}
+
+impl BackupManager {
+    pub fn new(store: StoreManager, passphrase: Option<String>) -> Self {
+        Self {
+            store,
+            passphrase: passphrase.filter(|key| !key.is_empty()),
+            rng: SystemRandom::new(),
+            audit: None,
+            queue: None,
+        }
+    }
+
+    /// Record backups and restores as `store.backup` and `store.restore`.
+    pub fn with_audit(mut self, audit: AuditLog) -> Self {
+        self.audit = Some(audit);
+        self
+    }
+
+    /// Rebuild `queue` from the restored store after every restore.
+    pub fn with_queue(mut self, queue: QueueManager) -> Self {
+        self.queue = Some(queue);
+        self
+    }
+
+    pub fn from_config(store: StoreManager, config: &DatabaseConfig) -> Self {
+        Self::new(store, config.backup_key.clone())
+    }
+
+    /// Snapshot the store and seal it for `actor`.
+    pub fn backup(&self, actor: &str) -> Result<Vec<u8>, BackupError> {
+        let passphrase = self.passphrase.as_ref().ok_or(BackupError::NotConfigured)?;
+        let snapshot = self
+            .store
+            .snapshot()
+            .map_err(|err| BackupError::Store(err.to_string()))?;
+        let mut payload =
+            serde_json::to_vec(&snapshot).map_err(|err| BackupError::Encode(err.to_string()))?;
+
+        let mut header = [0u8; HEADER_LEN];
+        header[..MAGIC.len()].copy_from_slice(MAGIC);
+        self.rng
+            .fill(&mut header[MAGIC.len()..])
+            .map_err(|_| BackupError::Encode("no randomness available".into()))?;
+        let (salt, nonce) = header[MAGIC.len()..].split_at(SALT_LEN);
+        let key = derive_key(passphrase, salt);
+        let nonce = Nonce::try_assume_unique_for_key(nonce).map_err(|_| BackupError::Unreadable)?;
+        key.seal_in_place_append_tag(nonce, Aad::from(&header), &mut payload)
+            .map_err(|_| BackupError::Encode("sealing failed".into()))?;
+
+        info!(
+            target = "backup",
+            messages = snapshot.messages.len(),
+            bytes = HEADER_LEN + payload.len(),
+            "store backup taken"
+        );
+        if let Some(audit) = &self.audit {
+            audit.record(
+                actor,
+                AuditAction::StoreBackup,
+                "store",
+                None,
+                Some(format!("{} messages", snapshot.messages.len())),
+            );
+        }
+        let mut sealed = header.to_vec();
+        sealed.append(&mut payload);
+        Ok(sealed)
+    }
+
+    /// Open a sealed snapshot and replace the store's contents with it, for
+    /// `actor`.
+    pub fn restore(&self, actor: &str, sealed: &[u8]) -> Result<RestoreReport, BackupError> {
+        let passphrase = self.passphrase.as_ref().ok_or(BackupError::NotConfigured)?;
+        if sealed.len() < HEADER_LEN || !sealed.starts_with(MAGIC) {
+            return Err(BackupError::Unreadable);
+        }
+        let (header, ciphertext) = sealed.split_at(HEADER_LEN);
+        let (salt, nonce) = header[MAGIC.len()..].split_at(SALT_LEN);
+        let key = derive_key(passphrase, salt);
+        let nonce = Nonce::try_assume_unique_for_key(nonce).map_err(|_| BackupError::Unreadable)?;
+        let mut buffer = ciphertext.to_vec();
+        let plaintext = key
+            .open_in_place(nonce, Aad::from(header), &mut buffer)
+            .map_err(|_| BackupError::Unreadable)?;
+        let snapshot: StoreSnapshot =
+            serde_json::from_slice(plaintext).map_err(|_| BackupError::Unreadable)?;
+
+        let mut report = RestoreReport {
+            taken_at: snapshot.taken_at,
+            messages: snapshot.messages.len(),
+            queued: snapshot.queue.len(),
+            folders: snapshot.folders.len(),
+        };
+        self.store
+            .restore(snapshot)
+            .map_err(|err| BackupError::Store(err.to_string()))?;
+        if let Some(queue) = &self.queue {
+            report.queued = queue.rebuild();
+        }
+        info!(
+            target = "backup",
+            taken_at = %report.taken_at,
+            messages = report.messages,
+            queued = report.queued,
+            "store restored from backup"
+        );
+        if let Some(audit) = &self.audit {
+            audit.record(
+                actor,
+                AuditAction::StoreRestore,
+                "store",
+                None,
+                Some(format!("snapshot taken {}", report.taken_at)),
+            );
+        }
+        Ok(report)
+    }
+}
+
+fn derive_key(passphrase: &str, salt: &[u8]) -> LessSafeKey {
+    let mut key = [0u8; 32];
+    pbkdf2::derive(
+        pbkdf2::PBKDF2_HMAC_SHA256,
+        NonZeroU32::new(PBKDF2_ROUNDS).expect("non-zero rounds"),
+        salt,
+        passphrase.as_bytes(),
+        &mut key,
+    );
+    LessSafeKey::new(UnboundKey::new(&AES_256_GCM, &key).expect("32-byte AES key"))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn restores_a_sealed_snapshot_and_rejects_the_wrong_key() {
+        let store = StoreManager::new();
+        let ids = store.seed_demo_data();
+        let queue = QueueManager::new().with_store(store.clone());
+        queue.enqueue(ids[0].clone());
+        let backups = BackupManager::new(store.clone(), Some("correct horse".into()))
+            .with_queue(queue.clone());
+        let sealed = backups.backup("ops").unwrap();
+        assert!(!sealed
+            .windows(b"Demo message".len())
+            .any(|window| window == b"Demo message"));
+
+        assert!(queue.dequeue().is_some());
+        store.delete(&ids[0]);
+        store.move_message(&ids[1], "archive");
+        let report = backups.restore("ops", &sealed).unwrap();
+        assert_eq!(report.messages, 3);
+        assert_eq!(report.queued, 1);
+        assert_eq!(
+            queue.pending(),
+            vec![ids[0].clone()],
+            "the restored queue row is dispatchable again"
+        );
+        assert_eq!(store.list("inbox").len(), 3);
+        assert!(store.get(&ids[0]).is_some());
+
+        let other = BackupManager::new(StoreManager::new(), Some("battery staple".into()));
+        assert_eq!(other.restore("ops", &sealed), Err(BackupError::Unreadable));
+        let mut tampered = sealed.clone();
+        *tampered.last_mut().unwrap() ^= 1;
+        store.delete(&ids[2]);
+        assert_eq!(
+            backups.restore("ops", &tampered),
+            Err(BackupError::Unreadable)
+        );
+        assert!(
+            store.get(&ids[2]).is_none(),
+            "a failed restore changes nothing"
+        );
+
+        let dir = tempfile::tempdir().unwrap();
+        let plain = dir.path().join("core.snapshot.json");
+        let written = store.backup_to(&plain).unwrap();
+        let read: StoreSnapshot = serde_json::from_slice(&std::fs::read(&plain).unwrap()).unwrap();
+        assert_eq!(read, written);
+        assert_eq!(read.messages.len(), 2);
+
+        let disabled = BackupManager::new(store, None);
+        assert_eq!(disabled.backup("ops"), Err(BackupError::NotConfigured));
+    }
+
+    #[test]
+    fn restore_covers_held_content_and_migration_state_but_keeps_the_audit_log() {
+        use crate::models::{HeldContentRecord, MigrationCheckpoint};
+
+        let dir = tempfile::tempdir().unwrap();
+        let open = || {
+            StoreManager::new()
+                .with_held_content_table(dir.path().join("held_content.json"))
+                .with_migration_state_table(dir.path().join("migration_state.json"))
+                .with_audit_table(dir.path().join("audit.jsonl"))
+        };
+        let store = open();
+        let ids = store.seed_demo_data();
+        let audit = AuditLog::new(store.clone());
+        let backups = BackupManager::new(store.clone(), Some("correct horse".into()))
+            .with_audit(audit.clone());
+        let job_id = uuid::Uuid::new_v4();
+        store.put_held_content(HeldContentRecord {
+            message_id: ids[0].clone(),
+            attachment_id: "blob-1".into(),
+            size: 42,
+        });
+        store.put_migration_checkpoint(MigrationCheckpoint {
+            job_id,
+            path: "inbox/1.fwz".into(),
+            sha256: "abc".into(),
+            completed_at: Utc::now(),
+        });
+        audit.record("ops", AuditAction::MigrationImport, "job", None, None);
+        let sealed = backups.backup("ops").unwrap();
+
+        store.remove_held_content(&ids[0]);
+        store.remove_migration_checkpoints(job_id);
+        audit.record("ops", AuditAction::StoreBackup, "store", None, None);
+        backups.restore("ops", &sealed).unwrap();
+
+        let reopened = open();
+        assert_eq!(
+            reopened.held_content(&ids[0]).unwrap().attachment_id,
+            "blob-1"
+        );
+        assert_eq!(reopened.migration_checkpoints(job_id).len(), 1);
+        let actions: Vec<_> = reopened
+            .audit_records()
+            .into_iter()
+            .map(|record| record.action)
+            .collect();
+        assert_eq!(
+            actions,
+            vec![
+                AuditAction::MigrationImport,
+                AuditAction::StoreBackup,
+                AuditAction::StoreBackup,
+                AuditAction::StoreRestore,
+            ],
+            "the audit log is never rolled back"
+        );
+        assert!(AuditLog::new(reopened).verify().valid);
+    }
+}
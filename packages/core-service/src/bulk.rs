@@ -0,0 +1,201 @@
+//! `POST /messages/bulk`: one action over many messages in a single request.
+//!
+//! The whole selection is changed in one store transaction and traced as a
+//! single entry, replacing the per-message round trips the UI used to make
+//! for multi-select actions.
+
+use serde::{Deserialize, Serialize};
+use thiserror::Error;
+use tracing::info;
+
+use crate::audit::AuditLog;
+use crate::folders::{FolderError, FolderManager};
+use crate::models::{AuditAction, MessageId};
+use crate::store::StoreManager;
+use crate::trace::TraceManager;
+
+/// Largest selection accepted in one request.
+const MAX_BULK: usize = 1000;
+
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case", tag = "action")]
+pub enum BulkAction {
+    Delete,
+    /// Same body key as `POST /messages/{id}/move`.
+    Move {
+        folder_id: String,
+    },
+    Archive,
+    MarkRead,
+}
+
+impl BulkAction {
+    fn name(&self) -> &'static str {
+        match self {
+            Self::Delete => "delete",
+            Self::Move { .. } => "move",
+            Self::Archive => "archive",
+            Self::MarkRead => "mark-read",
+        }
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct BulkRequest {
+    pub ids: Vec<MessageId>,
+    #[serde(flatten)]
+    pub action: BulkAction,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct BulkReport {
+    pub action: &'static str,
+    pub updated: Vec<MessageId>,
+    /// Requested ids that did not exist; the rest of the selection is still applied.
+    pub missing: Vec<MessageId>,
+}
+
+#[derive(Debug, Error, PartialEq, Eq)]
+pub enum BulkError {
+    #[error("no message ids given")]
+    Empty,
+    #[error("{size} messages selected; at most {limit} can be changed at once")]
+    TooLarge { size: usize, limit: usize },
+    #[error(transparent)]
+    Folder(#[from] FolderError),
+}
+
+impl BulkError {
+    pub fn status(&self) -> u16 {
+        match self {
+            Self::Empty => 400,
+            Self::TooLarge { .. } => 413,
+            Self::Folder(err) => err.status(),
+        }
+    }
+}
+
+#[derive(Clone)]
+pub struct BulkOperations {
+    store: StoreManager,
+    folders: FolderManager,
+    trace: TraceManager,
+    audit: Option<AuditLog>,
+}
+
+impl BulkOperations {
+    pub fn new(store: StoreManager, folders: FolderManager, trace: TraceManager) -> Self {
+        Self {
+            store,
+            folders,
+            trace,
+            audit: None,
+        }
+    }
+
+    /// Record applied bulk operations as `message.bulk`.
+    pub fn with_audit(mut self, audit: AuditLog) -> Self {
+        self.audit = Some(audit);
+        self
+    }
+
+    /// Apply `request` on behalf of `actor`.
+    pub fn apply(&self, actor: &str, request: &BulkRequest) -> Result<BulkReport, BulkError> {
+        if request.ids.is_empty() {
+            return Err(BulkError::Empty);
+        }
+        if request.ids.len() > MAX_BULK {
+            return Err(BulkError::TooLarge {
+                size: request.ids.len(),
+                limit: MAX_BULK,
+            });
+        }
+        if let BulkAction::Move { folder_id } = &request.action {
+            self.folders.ensure_exists(folder_id)?;
+        }
+
+        let action = request.action.name();
+        let (updated, missing) = self.store.apply_bulk(&request.ids, &request.action);
+        self.trace
+            .record_many(format!("bulk.{action}"), updated.clone());
+        info!(
+            target = "bulk",
+            action,
+            updated = updated.len(),
+            missing = missing.len(),
+            "bulk operation applied"
+        );
+        if let Some(audit) = &self.audit {
+            audit.record(
+                actor,
+                AuditAction::MessageBulk,
+                action,
+                None,
+                Some(format!(
+                    "{} updated, {} missing",
+                    updated.len(),
+                    missing.len()
+                )),
+            );
+        }
+        Ok(BulkReport {
+            action,
+            updated,
+            missing,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn applies_one_action_to_the_whole_selection() {
+        let store = StoreManager::new();
+        let ids = store.seed_demo_data();
+        let trace = TraceManager::new();
+        let folders = FolderManager::new(store.clone());
+        let bulk = BulkOperations::new(store.clone(), folders, trace.clone());
+        let missing = MessageId("msg-gone".into());
+
+        let request: BulkRequest = serde_json::from_value(serde_json::json!({
+            "ids": [ids[0], ids[1], missing],
+            "action": "move",
+            "folder_id": "archive",
+        }))
+        .unwrap();
+        let report = bulk.apply("ops", &request).unwrap();
+        assert_eq!(report.updated, ids[..2]);
+        assert_eq!(report.missing, [missing]);
+        assert_eq!(store.list("archive").len(), 2);
+
+        let entries = trace.bundle();
+        assert_eq!(entries.len(), 1);
+        assert_eq!(entries[0].event, "bulk.move");
+        assert_eq!(entries[0].related, [ids[1].clone()]);
+
+        let read = BulkRequest {
+            ids: ids.clone(),
+            action: BulkAction::MarkRead,
+        };
+        bulk.apply("ops", &read).unwrap();
+        assert_eq!(store.flag_counts()["archive"].unread, 0);
+
+        let nowhere = BulkRequest {
+            ids: ids.clone(),
+            action: BulkAction::Move {
+                folder_id: "nowhere".into(),
+            },
+        };
+        assert_eq!(bulk.apply("ops", &nowhere).unwrap_err().status(), 422);
+
+        let delete = BulkRequest {
+            ids: ids.clone(),
+            action: BulkAction::Delete,
+        };
+        assert_eq!(bulk.apply("ops", &delete).unwrap().updated.len(), 3);
+        assert!(store.get(&ids[2]).is_none());
+    }
+}
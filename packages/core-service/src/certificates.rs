@@ -0,0 +1,595 @@
+//! Recipient certificates for S/MIME encryption.
+//!
+//! [`CertificateResolver`] finds each recipient's certificate in the
+//! `userCertificate` attribute of their directory entry, then among the PEM
+//! files in `security.smime.certificatesDir`, which are indexed by the e-mail
+//! addresses in their subject alternative names and subject. Directory
+//! entries are matched by O/R address or by the RFC 822 address the gateway
+//! mapping gives the recipient; files by that RFC 822 address or the one on
+//! the directory entry.
+//!
+//! [`CertificateStore`] manages that directory for operators:
+//! `GET /admin/certificates` lists every certificate with its fingerprint,
+//! validity and key usage, `POST /admin/certificates` imports PEM
+//! certificates (optionally with their key) or a PKCS #12 bundle with its
+//! passphrase, and `DELETE /admin/certificates/{fingerprint}` removes one.
+//! Imported certificates are written as `{fingerprint}.pem`, their private
+//! keys as `{fingerprint}.key`, readable by the service user only.
+
+use std::collections::HashMap;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+
+use base64::engine::general_purpose::STANDARD;
+use base64::Engine as _;
+use chrono::{DateTime, Utc};
+use openssl::error::ErrorStack;
+use openssl::hash::MessageDigest;
+use openssl::nid::Nid;
+use openssl::pkcs12::Pkcs12;
+use openssl::pkey::{PKey, Private};
+use openssl::x509::X509;
+use serde::Serialize;
+use thiserror::Error;
+use tracing::debug;
+
+use crate::audit::AuditLog;
+use crate::clock::SharedClock;
+use crate::directory::{DirectoryEntry, LdapDirectoryClient};
+use crate::gateway::address_map::{parse_or_address, AddressMapper};
+use crate::models::{Address, AuditAction};
+
+/// File extensions read as PEM certificates.
+const CERTIFICATE_EXTENSIONS: [&str; 3] = ["pem", "crt", "cer"];
+
+/// Outcome of [`CertificateResolver::resolve`].
+#[derive(Clone, Debug, Default)]
+pub struct ResolvedCertificates {
+    pub certificates: Vec<X509>,
+    /// Recipients no certificate was found for.
+    pub missing: Vec<Address>,
+}
+
+#[derive(Clone, Debug)]
+pub struct CertificateResolver {
+    certificates_dir: PathBuf,
+    directory: Option<LdapDirectoryClient>,
+    mapper: Option<AddressMapper>,
+}
+
+impl CertificateResolver {
+    pub fn new(certificates_dir: impl Into<PathBuf>) -> Self {
+        Self {
+            certificates_dir: certificates_dir.into(),
+            directory: None,
+            mapper: None,
+        }
+    }
+
+    /// Look up `userCertificate` on directory entries.
+    pub fn with_directory(mut self, directory: LdapDirectoryClient) -> Self {
+        self.directory = Some(directory);
+        self
+    }
+
+    /// Map recipients to RFC 822 addresses for the lookup.
+    pub fn with_mapper(mut self, mapper: AddressMapper) -> Self {
+        self.mapper = Some(mapper);
+        self
+    }
+
+    pub fn resolve(&self, recipients: &[Address]) -> ResolvedCertificates {
+        let files = self.load_dir();
+        let mut resolved = ResolvedCertificates::default();
+        for recipient in recipients {
+            match self.lookup(recipient, &files) {
+                Some(certificate) => resolved.certificates.push(certificate),
+                None => resolved.missing.push(recipient.clone()),
+            }
+        }
+        resolved
+    }
+
+    fn lookup(&self, recipient: &Address, files: &HashMap<String, X509>) -> Option<X509> {
+        let email = self
+            .mapper
+            .as_ref()
+            .and_then(|mapper| mapper.map_or_to_rfc822(recipient).ok())
+            .map(|email| email.to_ascii_lowercase());
+        let entry = self
+            .directory
+            .as_ref()
+            .and_then(|directory| directory_entry(directory, recipient, email.as_deref()));
+        entry
+            .as_ref()
+            .and_then(user_certificate)
+            .or_else(|| email.and_then(|email| files.get(&email).cloned()))
+            .or_else(|| {
+                let email = entry?.rfc822.to_ascii_lowercase();
+                files.get(&email).cloned()
+            })
+    }
+
+    /// Certificates in the PEM files of the certificates directory, keyed by
+    /// lower-cased e-mail address. Unreadable files are skipped.
+    fn load_dir(&self) -> HashMap<String, X509> {
+        let mut index = HashMap::new();
+        for (_, certificates) in certificate_files(&self.certificates_dir) {
+            for certificate in certificates {
+                for email in emails(&certificate) {
+                    index.entry(email).or_insert_with(|| certificate.clone());
+                }
+            }
+        }
+        index
+    }
+}
+
+#[derive(Debug, Error)]
+pub enum CertificateStoreError {
+    #[error("the upload contains no certificate")]
+    Empty,
+    #[error("invalid certificate or key: {0}")]
+    Invalid(String),
+    #[error("the private key does not belong to the certificate")]
+    KeyMismatch,
+    #[error("PKCS#12 bundle could not be opened (wrong passphrase?): {0}")]
+    Pkcs12(String),
+    #[error("certificate store I/O failed: {0}")]
+    Io(#[from] io::Error),
+}
+
+impl From<ErrorStack> for CertificateStoreError {
+    fn from(error: ErrorStack) -> Self {
+        Self::Invalid(error.to_string())
+    }
+}
+
+/// A certificate in the store, as `GET /admin/certificates` lists it.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct CertificateInfo {
+    /// SHA-256 of the DER encoding, lower-case hex.
+    pub fingerprint: String,
+    pub subject: String,
+    pub issuer: String,
+    pub emails: Vec<String>,
+    pub not_before: DateTime<Utc>,
+    pub not_after: DateTime<Utc>,
+    pub expired: bool,
+    /// Key usage and extended key usage names, such as `Digital Signature`
+    /// or `Email Protection`.
+    pub key_usage: Vec<String>,
+    pub has_private_key: bool,
+    /// File the certificate was read from, relative to the store.
+    pub file: String,
+}
+
+/// The certificates directory, managed through the admin API.
+#[derive(Clone)]
+pub struct CertificateStore {
+    dir: PathBuf,
+    clock: SharedClock,
+    audit: Option<AuditLog>,
+}
+
+impl CertificateStore {
+    pub fn new(dir: impl Into<PathBuf>) -> Self {
+        Self {
+            dir: dir.into(),
+            clock: SharedClock::default(),
+            audit: None,
+        }
+    }
+
+    pub fn with_clock(mut self, clock: SharedClock) -> Self {
+        self.clock = clock;
+        self
+    }
+
+    /// Record imports and deletions as `certificate.import` and
+    /// `certificate.delete`.
+    pub fn with_audit(mut self, audit: AuditLog) -> Self {
+        self.audit = Some(audit);
+        self
+    }
+
+    /// Every certificate in the directory, oldest expiry first.
+    pub fn list(&self) -> Vec<CertificateInfo> {
+        let mut listed: Vec<CertificateInfo> = certificate_files(&self.dir)
+            .into_iter()
+            .flat_map(|(path, certificates)| {
+                let file = path
+                    .file_name()
+                    .map(|name| name.to_string_lossy().into_owned())
+                    .unwrap_or_default();
+                certificates
+                    .into_iter()
+                    .map(move |certificate| (file.clone(), certificate))
+            })
+            .filter_map(|(file, certificate)| self.info(&certificate, file).ok())
+            .collect();
+        listed.sort_by(|a, b| {
+            a.not_after
+                .cmp(&b.not_after)
+                .then_with(|| a.fingerprint.cmp(&b.fingerprint))
+        });
+        listed
+    }
+
+    /// Import PEM certificates for `actor`. A PEM `key` must belong to the
+    /// first certificate and is stored with it.
+    pub fn import_pem(
+        &self,
+        actor: &str,
+        certificates: &[u8],
+        key: Option<&[u8]>,
+    ) -> Result<Vec<CertificateInfo>, CertificateStoreError> {
+        let certificates = X509::stack_from_pem(certificates)?;
+        let key = key.map(PKey::private_key_from_pem).transpose()?;
+        self.import(actor, certificates, key)
+    }
+
+    /// Import the certificate, key and CA certificates of a PKCS #12 bundle
+    /// for `actor`.
+    pub fn import_pkcs12(
+        &self,
+        actor: &str,
+        der: &[u8],
+        passphrase: &str,
+    ) -> Result<Vec<CertificateInfo>, CertificateStoreError> {
+        let parsed = Pkcs12::from_der(der)
+            .and_then(|bundle| bundle.parse2(passphrase))
+            .map_err(|error| CertificateStoreError::Pkcs12(error.to_string()))?;
+        let certificates = parsed
+            .cert
+            .into_iter()
+            .chain(parsed.ca.into_iter().flatten())
+            .collect();
+        self.import(actor, certificates, parsed.pkey)
+    }
+
+    /// Remove the certificate with `fingerprint` and its key for `actor`.
+    /// Returns whether anything was removed; files holding other certificates
+    /// as well are left alone.
+    pub fn delete(&self, actor: &str, fingerprint: &str) -> Result<bool, CertificateStoreError> {
+        let wanted = fingerprint.trim().to_ascii_lowercase();
+        let mut removed = false;
+        for (path, certificates) in certificate_files(&self.dir) {
+            if let [certificate] = certificates.as_slice() {
+                if self::fingerprint(certificate)? == wanted {
+                    fs::remove_file(path)?;
+                    removed = true;
+                }
+            }
+        }
+        match fs::remove_file(self.key_path(&wanted)) {
+            Ok(()) => removed = true,
+            Err(error) if error.kind() == io::ErrorKind::NotFound => {}
+            Err(error) => return Err(error.into()),
+        }
+        if let (true, Some(audit)) = (removed, &self.audit) {
+            audit.record(actor, AuditAction::CertificateDelete, &wanted, None, None);
+        }
+        Ok(removed)
+    }
+
+    fn import(
+        &self,
+        actor: &str,
+        certificates: Vec<X509>,
+        key: Option<PKey<Private>>,
+    ) -> Result<Vec<CertificateInfo>, CertificateStoreError> {
+        let first = certificates.first().ok_or(CertificateStoreError::Empty)?;
+        if let Some(key) = &key {
+            if !first.public_key()?.public_eq(key) {
+                return Err(CertificateStoreError::KeyMismatch);
+            }
+        }
+        fs::create_dir_all(&self.dir)?;
+        if let Some(key) = &key {
+            write_private(
+                &self.key_path(&fingerprint(first)?),
+                &key.private_key_to_pem_pkcs8()?,
+            )?;
+        }
+        let mut imported = Vec::new();
+        for certificate in &certificates {
+            let file = format!("{}.pem", fingerprint(certificate)?);
+            fs::write(self.dir.join(&file), certificate.to_pem()?)?;
+            imported.push(self.info(certificate, file)?);
+        }
+        if let Some(audit) = &self.audit {
+            for info in &imported {
+                audit.record(
+                    actor,
+                    AuditAction::CertificateImport,
+                    &info.fingerprint,
+                    None,
+                    Some(info.subject.clone()),
+                );
+            }
+        }
+        Ok(imported)
+    }
+
+    fn info(
+        &self,
+        certificate: &X509,
+        file: String,
+    ) -> Result<CertificateInfo, CertificateStoreError> {
+        let der = certificate.to_der()?;
+        let (_, parsed) = x509_parser::parse_x509_certificate(&der)
+            .map_err(|error| CertificateStoreError::Invalid(error.to_string()))?;
+        let time = |time: x509_parser::time::ASN1Time| {
+            DateTime::from_timestamp(time.timestamp(), 0).unwrap_or_default()
+        };
+        let validity = parsed.validity();
+        let mut key_usage: Vec<String> = match parsed.key_usage() {
+            Ok(Some(usage)) => usage
+                .value
+                .to_string()
+                .split(", ")
+                .filter(|name| !name.is_empty())
+                .map(str::to_string)
+                .collect(),
+            _ => Vec::new(),
+        };
+        if let Ok(Some(extended)) = parsed.extended_key_usage() {
+            let extended = extended.value;
+            for (set, name) in [
+                (extended.server_auth, "TLS Web Server Authentication"),
+                (extended.client_auth, "TLS Web Client Authentication"),
+                (extended.code_signing, "Code Signing"),
+                (extended.email_protection, "Email Protection"),
+                (extended.time_stamping, "Time Stamping"),
+                (extended.ocsp_signing, "OCSP Signing"),
+            ] {
+                if set {
+                    key_usage.push(name.into());
+                }
+            }
+        }
+        let fingerprint = fingerprint(certificate)?;
+        let not_after = time(validity.not_after);
+        Ok(CertificateInfo {
+            has_private_key: self.key_path(&fingerprint).exists(),
+            fingerprint,
+            subject: parsed.subject().to_string(),
+            issuer: parsed.issuer().to_string(),
+            emails: emails(certificate),
+            not_before: time(validity.not_before),
+            expired: not_after <= self.clock.now(),
+            not_after,
+            key_usage,
+            file,
+        })
+    }
+
+    fn key_path(&self, fingerprint: &str) -> PathBuf {
+        self.dir.join(format!("{fingerprint}.key"))
+    }
+}
+
+fn fingerprint(certificate: &X509) -> Result<String, ErrorStack> {
+    Ok(certificate
+        .digest(MessageDigest::sha256())?
+        .iter()
+        .map(|byte| format!("{byte:02x}"))
+        .collect())
+}
+
+/// Write `contents` readable by the owner only.
+fn write_private(path: &Path, contents: &[u8]) -> io::Result<()> {
+    fs::write(path, contents)?;
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::PermissionsExt;
+        fs::set_permissions(path, fs::Permissions::from_mode(0o600))?;
+    }
+    Ok(())
+}
+
+/// The certificate files in `dir` with their certificates, in name order.
+/// Unreadable files are skipped.
+fn certificate_files(dir: &Path) -> Vec<(PathBuf, Vec<X509>)> {
+    let Ok(entries) = fs::read_dir(dir) else {
+        return Vec::new();
+    };
+    let mut paths: Vec<PathBuf> = entries
+        .filter_map(Result::ok)
+        .map(|entry| entry.path())
+        .filter(|path| {
+            path.extension()
+                .and_then(|ext| ext.to_str())
+                .is_some_and(|ext| CERTIFICATE_EXTENSIONS.contains(&ext))
+        })
+        .collect();
+    paths.sort();
+    paths
+        .into_iter()
+        .filter_map(
+            |path| match fs::read(&path).map(|pem| X509::stack_from_pem(&pem)) {
+                Ok(Ok(certificates)) => Some((path, certificates)),
+                _ => {
+                    debug!(target = "smime", path = %path.display(), "skipped certificate file");
+                    None
+                }
+            },
+        )
+        .collect()
+}
+
+fn directory_entry(
+    directory: &LdapDirectoryClient,
+    recipient: &Address,
+    email: Option<&str>,
+) -> Option<DirectoryEntry> {
+    let same = |a: &str, b: &str| a.trim().eq_ignore_ascii_case(b.trim());
+    directory
+        .search(&recipient.surname)
+        .into_iter()
+        .find(|entry| {
+            let or_match = parse_or_address(&entry.or_address).is_some_and(|address| {
+                same(&address.country, &recipient.country)
+                    && same(&address.organization, &recipient.organization)
+                    && same(&address.surname, &recipient.surname)
+            });
+            or_match || email.is_some_and(|email| same(&entry.rfc822, email))
+        })
+}
+
+/// The LDAP `userCertificate` attribute, as PEM or base64 DER.
+fn user_certificate(entry: &DirectoryEntry) -> Option<X509> {
+    let value = entry.attributes.iter().find_map(|(name, value)| {
+        let name = name.to_ascii_lowercase();
+        (name == "usercertificate" || name == "usercertificate;binary").then_some(value)
+    })?;
+    if value.contains("-----BEGIN") {
+        return X509::from_pem(value.as_bytes()).ok();
+    }
+    let compact: String = value.chars().filter(|c| !c.is_whitespace()).collect();
+    X509::from_der(&STANDARD.decode(compact).ok()?).ok()
+}
+
+/// E-mail addresses a certificate was issued for.
+fn emails(certificate: &X509) -> Vec<String> {
+    let mut emails: Vec<String> = certificate
+        .subject_alt_names()
+        .into_iter()
+        .flatten()
+        .filter_map(|name| name.email().map(str::to_ascii_lowercase))
+        .collect();
+    emails.extend(
+        certificate
+            .subject_name()
+            .entries_by_nid(Nid::PKCS9_EMAILADDRESS)
+            .filter_map(|entry| entry.data().to_string().ok())
+            .map(|email| email.to_ascii_lowercase()),
+    );
+    emails
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::LdapConfig;
+    use crate::directory::DirectoryCache;
+    use crate::gateway::address_map::AddressMappingRule;
+    use rcgen::{CertificateParams, KeyPair, SanType};
+
+    fn certificate(email: &str) -> rcgen::Certificate {
+        let mut params = CertificateParams::new(Vec::<String>::new()).unwrap();
+        params.subject_alt_names = vec![SanType::Rfc822Name(email.try_into().unwrap())];
+        params.self_signed(&KeyPair::generate().unwrap()).unwrap()
+    }
+
+    fn address(surname: &str) -> Address {
+        Address {
+            country: "DE".into(),
+            organization: "Org".into(),
+            surname: surname.into(),
+        }
+    }
+
+    #[test]
+    fn resolves_recipients_from_directory_and_certificate_files() {
+        let dir = tempfile::tempdir().unwrap();
+        fs::write(
+            dir.path().join("reader.pem"),
+            certificate("reader@example.com").pem(),
+        )
+        .unwrap();
+        fs::write(dir.path().join("notes.txt"), "not a certificate").unwrap();
+
+        let directory =
+            LdapDirectoryClient::new(LdapConfig::default(), DirectoryCache::new(60, 32));
+        let auditor = certificate("audit@partner.example");
+        directory.upsert_entry(DirectoryEntry {
+            id: "auditor".into(),
+            display_name: "Auditor".into(),
+            rfc822: "audit@partner.example".into(),
+            or_address: "C=DE;O=Org;S=Auditor".into(),
+            attributes: HashMap::from([(
+                "userCertificate;binary".to_string(),
+                STANDARD.encode(auditor.der()),
+            )]),
+        });
+
+        let resolver = CertificateResolver::new(dir.path())
+            .with_directory(directory)
+            .with_mapper(AddressMapper::new(
+                vec![AddressMappingRule::new("{S}@example.com")],
+                HashMap::new(),
+            ));
+        let resolved =
+            resolver.resolve(&[address("Reader"), address("Auditor"), address("Nobody")]);
+
+        assert_eq!(resolved.certificates.len(), 2);
+        assert_eq!(
+            emails(&resolved.certificates[0]),
+            ["reader@example.com".to_string()]
+        );
+        assert_eq!(
+            resolved.certificates[1].to_der().unwrap(),
+            auditor.der().to_vec()
+        );
+        assert_eq!(resolved.missing, [address("Nobody")]);
+    }
+
+    #[test]
+    fn imports_lists_and_deletes_pkcs12_bundles() {
+        let dir = tempfile::tempdir().unwrap();
+        let store = CertificateStore::new(dir.path());
+        let key = KeyPair::generate().unwrap();
+        let mut params = CertificateParams::new(Vec::<String>::new()).unwrap();
+        params.subject_alt_names = vec![SanType::Rfc822Name(
+            "operator@example.com".try_into().unwrap(),
+        )];
+        params.key_usages = vec![rcgen::KeyUsagePurpose::DigitalSignature];
+        params.extended_key_usages = vec![rcgen::ExtendedKeyUsagePurpose::EmailProtection];
+        let operator = params.self_signed(&key).unwrap();
+        let bundle = Pkcs12::builder()
+            .name("operator")
+            .pkey(&PKey::private_key_from_pem(key.serialize_pem().as_bytes()).unwrap())
+            .cert(&X509::from_der(operator.der()).unwrap())
+            .build2("secret")
+            .unwrap()
+            .to_der()
+            .unwrap();
+
+        assert!(matches!(
+            store.import_pkcs12("ops", &bundle, "wrong"),
+            Err(CertificateStoreError::Pkcs12(_))
+        ));
+        let imported = store.import_pkcs12("ops", &bundle, "secret").unwrap();
+        assert_eq!(imported.len(), 1);
+        let info = &imported[0];
+        assert!(info.has_private_key && !info.expired);
+        assert_eq!(info.emails, ["operator@example.com"]);
+        assert_eq!(info.key_usage, ["Digital Signature", "Email Protection"]);
+        assert_eq!(info.file, format!("{}.pem", info.fingerprint));
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::PermissionsExt;
+            let key_file = dir.path().join(format!("{}.key", info.fingerprint));
+            let mode = fs::metadata(key_file).unwrap().permissions().mode();
+            assert_eq!(mode & 0o777, 0o600);
+        }
+        assert_eq!(store.list(), imported);
+
+        let stranger = certificate("someone@example.com").pem();
+        let unrelated_key = KeyPair::generate().unwrap().serialize_pem();
+        assert!(matches!(
+            store.import_pem("ops", stranger.as_bytes(), Some(unrelated_key.as_bytes())),
+            Err(CertificateStoreError::KeyMismatch)
+        ));
+
+        assert!(store
+            .delete("ops", &info.fingerprint.to_uppercase())
+            .unwrap());
+        assert!(store.list().is_empty());
+        assert!(!store.delete("ops", &info.fingerprint).unwrap());
+    }
+}
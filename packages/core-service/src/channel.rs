@@ -0,0 +1,468 @@
+//! Command/notification protocol of the real-time channel.
+//!
+//! The desktop client keeps one connection to the listener of [`crate::ipc`]
+//! open, as plain lines or as a WebSocket on `/ws`, and sends JSON commands
+//! tagged by `type`. Each command is answered with a frame carrying the same
+//! `requestId`; trace events are pushed unsolicited as `event` frames as soon
+//! as they are recorded, to callers holding [`EVENT_SCOPE`]. The connection
+//! owns the socket and only shuttles commands and frames between it and a
+//! [`ChannelSession`].
+//!
+//! Submitted messages are sent from the `orAddress` of the default transport
+//! profile; without one, `submit` is refused with status 503.
+
+use std::sync::mpsc::Receiver;
+use std::sync::Arc;
+
+use serde::{Deserialize, Serialize};
+
+use crate::clock::{SharedClock, SharedIds};
+use crate::drain::DrainController;
+use crate::gateway::address_map::parse_or_address;
+use crate::models::{
+    Address, Message, MessageContent, MessageEnvelope, MessageId, MessageStatus, ReportKind,
+};
+use crate::trace::{TraceEntry, TraceManager};
+use crate::transport::{MessageTransport, TransportError};
+use crate::AppState;
+
+/// Scope a caller needs to receive `event` frames; they name messages and
+/// their progress, which is what `list` and `fetch` reveal.
+pub const EVENT_SCOPE: &str = "messages:read";
+
+#[derive(Clone, Debug, PartialEq, Eq, Deserialize)]
+#[serde(tag = "type", rename_all = "camelCase")]
+pub enum ChannelCommand {
+    #[serde(rename_all = "camelCase")]
+    List {
+        request_id: Option<String>,
+        folder: String,
+    },
+    #[serde(rename_all = "camelCase")]
+    Fetch {
+        request_id: Option<String>,
+        id: String,
+    },
+    #[serde(rename_all = "camelCase")]
+    Submit {
+        request_id: Option<String>,
+        subject: String,
+        body: String,
+        recipients: Vec<ChannelAddress>,
+    },
+    #[serde(rename_all = "camelCase")]
+    Ping { request_id: Option<String> },
+    /// Credentials of a plain TCP client, sent as its first line; the
+    /// connection checks them, see [`crate::ipc`].
+    #[serde(rename_all = "camelCase")]
+    Auth {
+        request_id: Option<String>,
+        api_key: Option<String>,
+        authorization: Option<String>,
+    },
+}
+
+impl ChannelCommand {
+    /// Scope a caller needs to send this command.
+    pub fn scope(&self) -> &'static str {
+        match self {
+            Self::List { .. } | Self::Fetch { .. } => "messages:read",
+            Self::Submit { .. } => "messages:write",
+            Self::Ping { .. } | Self::Auth { .. } => "status:read",
+        }
+    }
+
+    /// The command's `type`, as logged in the access log.
+    pub fn name(&self) -> &'static str {
+        match self {
+            Self::List { .. } => "list",
+            Self::Fetch { .. } => "fetch",
+            Self::Submit { .. } => "submit",
+            Self::Ping { .. } => "ping",
+            Self::Auth { .. } => "auth",
+        }
+    }
+
+    /// The same command answered under `id`, see [`crate::trace::CorrelationScope`].
+    pub fn with_request_id(mut self, id: &str) -> Self {
+        match &mut self {
+            Self::List { request_id, .. }
+            | Self::Fetch { request_id, .. }
+            | Self::Submit { request_id, .. }
+            | Self::Ping { request_id }
+            | Self::Auth { request_id, .. } => *request_id = Some(id.to_string()),
+        }
+        self
+    }
+
+    pub fn request_id(&self) -> Option<String> {
+        match self {
+            Self::List { request_id, .. }
+            | Self::Fetch { request_id, .. }
+            | Self::Submit { request_id, .. }
+            | Self::Ping { request_id }
+            | Self::Auth { request_id, .. } => request_id.clone(),
+        }
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ChannelAddress {
+    pub country: String,
+    pub organization: String,
+    pub surname: String,
+}
+
+impl From<&Address> for ChannelAddress {
+    fn from(address: &Address) -> Self {
+        Self {
+            country: address.country.clone(),
+            organization: address.organization.clone(),
+            surname: address.surname.clone(),
+        }
+    }
+}
+
+impl From<ChannelAddress> for Address {
+    fn from(address: ChannelAddress) -> Self {
+        Self {
+            country: address.country,
+            organization: address.organization,
+            surname: address.surname,
+        }
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct MessageSummary {
+    pub id: String,
+    pub subject: String,
+    pub folder: String,
+    pub status: &'static str,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct MessageView {
+    #[serde(flatten)]
+    pub summary: MessageSummary,
+    pub sender: ChannelAddress,
+    pub recipients: Vec<ChannelAddress>,
+    pub body: String,
+    pub reports: Vec<&'static str>,
+}
+
+/// Frames written back to the socket.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize)]
+#[serde(tag = "type", rename_all = "camelCase")]
+pub enum ChannelFrame {
+    #[serde(rename_all = "camelCase")]
+    Messages {
+        request_id: Option<String>,
+        messages: Vec<MessageSummary>,
+    },
+    #[serde(rename_all = "camelCase")]
+    Message {
+        request_id: Option<String>,
+        message: Option<Box<MessageView>>,
+    },
+    #[serde(rename_all = "camelCase")]
+    Submitted {
+        request_id: Option<String>,
+        message_id: String,
+    },
+    #[serde(rename_all = "camelCase")]
+    Pong { request_id: Option<String> },
+    /// Answer to an accepted `auth` command, naming the key it matched.
+    #[serde(rename_all = "camelCase")]
+    Authenticated {
+        request_id: Option<String>,
+        key: String,
+    },
+    /// A refused or failed command; `status` follows the HTTP status codes
+    /// the same failure maps to elsewhere.
+    #[serde(rename_all = "camelCase")]
+    Error {
+        request_id: Option<String>,
+        status: u16,
+        error: String,
+    },
+    /// The caller's rate limit is spent; retry after `retry_after` seconds.
+    #[serde(rename_all = "camelCase")]
+    RateLimited {
+        request_id: Option<String>,
+        status: u16,
+        retry_after: u64,
+        error: String,
+    },
+    #[serde(rename_all = "camelCase")]
+    Event { event: String, message_id: String },
+}
+
+impl ChannelFrame {
+    /// Status of a reply for the access log: 200 unless it reports a failure.
+    pub fn status(&self) -> u16 {
+        match self {
+            Self::Error { status, .. } | Self::RateLimited { status, .. } => *status,
+            _ => 200,
+        }
+    }
+
+    pub fn to_json(&self) -> String {
+        serde_json::to_string(self).unwrap_or_else(|_| "{\"type\":\"error\"}".into())
+    }
+}
+
+impl From<TraceEntry> for ChannelFrame {
+    fn from(entry: TraceEntry) -> Self {
+        Self::Event {
+            event: entry.event,
+            message_id: entry.message.0,
+        }
+    }
+}
+
+/// State for one connected socket.
+pub struct ChannelSession {
+    drain: DrainController,
+    transport: Arc<dyn MessageTransport>,
+    trace: TraceManager,
+    originator: Option<Address>,
+    clock: SharedClock,
+    ids: SharedIds,
+}
+
+impl ChannelSession {
+    pub fn new(state: &AppState) -> Self {
+        let originator = state
+            .profiles
+            .resolve(&state.config.transport.default_profile)
+            .ok()
+            .flatten()
+            .and_then(|profile| profile.or_address)
+            .and_then(|address| parse_or_address(&address));
+        Self {
+            drain: state.drain.clone(),
+            transport: Arc::new(state.transport.clone()),
+            trace: state.trace.clone(),
+            originator,
+            clock: state.clock.clone(),
+            ids: state.ids.clone(),
+        }
+    }
+
+    /// Handle one inbound text frame and produce its reply.
+    pub fn handle_text(&self, text: &str) -> ChannelFrame {
+        match serde_json::from_str::<ChannelCommand>(text) {
+            Ok(command) => self.handle(command),
+            Err(err) => ChannelFrame::Error {
+                request_id: None,
+                status: 400,
+                error: format!("invalid command: {err}"),
+            },
+        }
+    }
+
+    pub fn handle(&self, command: ChannelCommand) -> ChannelFrame {
+        match command {
+            ChannelCommand::List { request_id, folder } => match self.transport.list(&folder) {
+                Ok(messages) => ChannelFrame::Messages {
+                    request_id,
+                    messages: messages.iter().map(summary).collect(),
+                },
+                Err(err) => transport_error(request_id, err),
+            },
+            ChannelCommand::Fetch { request_id, id } => match self.view(&MessageId(id)) {
+                Ok(message) => ChannelFrame::Message {
+                    request_id,
+                    message: message.map(Box::new),
+                },
+                Err(err) => transport_error(request_id, err),
+            },
+            ChannelCommand::Submit {
+                request_id,
+                subject,
+                body,
+                recipients,
+            } => {
+                if let Err(err) = self.drain.admit_submit() {
+                    return ChannelFrame::Error {
+                        request_id,
+                        status: err.status(),
+                        error: err.to_string(),
+                    };
+                }
+                let Some(originator) = self.originator.clone() else {
+                    return ChannelFrame::Error {
+                        request_id,
+                        status: 503,
+                        error: "no originator is configured; set orAddress in the default profile"
+                            .into(),
+                    };
+                };
+                if recipients.is_empty() {
+                    return ChannelFrame::Error {
+                        request_id,
+                        status: 400,
+                        error: "at least one recipient is required".into(),
+                    };
+                }
+                let envelope = MessageEnvelope::stamped(
+                    &subject,
+                    originator,
+                    recipients.into_iter().map(Into::into).collect(),
+                    &self.ids,
+                    &self.clock,
+                );
+                let message = Message {
+                    envelope,
+                    content: MessageContent {
+                        body,
+                        attachments: Vec::new(),
+                    },
+                };
+                match self.transport.submit(message) {
+                    Ok(id) => ChannelFrame::Submitted {
+                        request_id,
+                        message_id: id.0,
+                    },
+                    Err(err) => transport_error(request_id, err),
+                }
+            }
+            ChannelCommand::Ping { request_id } => ChannelFrame::Pong { request_id },
+            ChannelCommand::Auth { request_id, .. } => ChannelFrame::Error {
+                request_id,
+                status: 400,
+                error: "credentials are checked by the connection, not the session".into(),
+            },
+        }
+    }
+
+    fn view(&self, id: &MessageId) -> Result<Option<MessageView>, TransportError> {
+        let Some(message) = self.transport.fetch(id)? else {
+            return Ok(None);
+        };
+        let reports = self.transport.reports(id)?;
+        Ok(Some(MessageView {
+            summary: summary(&message),
+            sender: (&message.envelope.sender).into(),
+            recipients: message.envelope.recipients.iter().map(Into::into).collect(),
+            body: message.content.body.clone(),
+            reports: reports
+                .iter()
+                .map(|report| report_kind(report.kind))
+                .collect(),
+        }))
+    }
+
+    /// Trace events recorded from now on, to be pushed as `event` frames;
+    /// dropping the receiver unsubscribes.
+    pub fn subscribe(&self) -> Receiver<TraceEntry> {
+        self.trace.subscribe()
+    }
+}
+
+fn transport_error(request_id: Option<String>, err: TransportError) -> ChannelFrame {
+    ChannelFrame::Error {
+        request_id,
+        status: err.status(),
+        error: err.to_string(),
+    }
+}
+
+fn summary(message: &Message) -> MessageSummary {
+    MessageSummary {
+        id: message.envelope.id.0.clone(),
+        subject: message.envelope.subject.clone(),
+        folder: message.envelope.folder.clone(),
+        status: status_name(&message.envelope.status),
+    }
+}
+
+fn status_name(status: &MessageStatus) -> &'static str {
+    match status {
+        MessageStatus::Draft => "draft",
+        MessageStatus::Queued => "queued",
+        MessageStatus::Sent => "sent",
+        MessageStatus::Delivered => "delivered",
+        MessageStatus::Read => "read",
+        MessageStatus::Failed => "failed",
+        MessageStatus::Unknown => "unknown",
+    }
+}
+
+fn report_kind(kind: ReportKind) -> &'static str {
+    match kind {
+        ReportKind::Delivery => "delivery",
+        ReportKind::NonDelivery => "nonDelivery",
+        ReportKind::Read => "read",
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::AppConfig;
+
+    #[test]
+    fn multiplexes_commands_and_pushes_events() {
+        let data = tempfile::tempdir().unwrap();
+        let mut config = AppConfig::default();
+        config.database.path = data.path().join("core.db").to_string_lossy().into_owned();
+        config.transport.profiles_dir = data.path().to_string_lossy().into_owned();
+        let state = AppState::new(config);
+        let unconfigured = ChannelSession::new(&state).handle_text(
+            r#"{"type":"submit","subject":"Hi","body":"","recipients":[{"country":"DE","organization":"Modern","surname":"Peer"}]}"#,
+        );
+        assert_eq!(unconfigured.status(), 503);
+
+        std::fs::write(
+            data.path().join("default.p7p"),
+            "[Profile]\nName=default\nORAddress=C=DE;O=Branch;S=Clerk\n\n[MTA]\nEndpoint=mta.example:102\n",
+        )
+        .unwrap();
+        let session = ChannelSession::new(&state);
+        let events = session.subscribe();
+
+        let reply = session.handle_text(
+            r#"{"type":"submit","requestId":"1","subject":"Hi","body":"Hello","recipients":[{"country":"DE","organization":"Modern","surname":"Peer"}]}"#,
+        );
+        let ChannelFrame::Submitted { message_id, .. } = reply else {
+            panic!("unexpected reply {reply:?}");
+        };
+
+        let events: Vec<ChannelFrame> = events.try_iter().map(ChannelFrame::from).collect();
+        assert!(events.contains(&ChannelFrame::Event {
+            event: "mock.delivered".into(),
+            message_id: message_id.clone(),
+        }));
+
+        let fetched = session.handle_text(&format!(
+            r#"{{"type":"fetch","requestId":"2","id":"{message_id}"}}"#
+        ));
+        let json = fetched.to_json();
+        assert!(json.contains(r#""requestId":"2""#));
+        assert!(json.contains(r#""status":"read""#));
+        assert!(json.contains(r#""reports":["delivery","read"]"#));
+        assert!(
+            json.contains(r#""sender":{"country":"DE","organization":"Branch","surname":"Clerk"}"#)
+        );
+
+        let listed = session.handle_text(r#"{"type":"list","folder":"outbox"}"#);
+        assert!(
+            matches!(listed, ChannelFrame::Messages { ref messages, .. } if messages.len() == 1)
+        );
+        assert!(matches!(
+            session.handle_text("{\"type\":\"launch\"}"),
+            ChannelFrame::Error { .. }
+        ));
+        state.drain.begin();
+        let refused = session.handle_text(
+            r#"{"type":"submit","subject":"Late","body":"","recipients":[{"country":"DE","organization":"Modern","surname":"Peer"}]}"#,
+        );
+        assert!(
+            matches!(refused, ChannelFrame::Error { ref error, .. } if error.contains("draining"))
+        );
+    }
+}
@@ -0,0 +1,163 @@
+//! Time and identifier sources shared through [`AppState`](crate::AppState).
+//!
+//! Production code uses the system clock and random UUIDs; tests swap in
+//! [`ManualClock`] and [`SequentialIds`] so timestamps and identifiers in
+//! golden output are reproducible.
+
+use std::fmt;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+
+use chrono::{DateTime, Duration, Utc};
+use uuid::Uuid;
+
+use crate::models::MessageId;
+
+pub trait Clock: Send + Sync {
+    fn now(&self) -> DateTime<Utc>;
+}
+
+pub trait IdGenerator: Send + Sync {
+    fn uuid(&self) -> Uuid;
+    fn message_id(&self) -> MessageId;
+}
+
+#[derive(Clone, Copy, Debug, Default)]
+pub struct SystemClock;
+
+impl Clock for SystemClock {
+    fn now(&self) -> DateTime<Utc> {
+        Utc::now()
+    }
+}
+
+/// Clock that only moves when told to.
+#[derive(Clone, Debug)]
+pub struct ManualClock {
+    now: Arc<Mutex<DateTime<Utc>>>,
+}
+
+impl ManualClock {
+    pub fn new(start: DateTime<Utc>) -> Self {
+        Self {
+            now: Arc::new(Mutex::new(start)),
+        }
+    }
+
+    pub fn set(&self, now: DateTime<Utc>) {
+        if let Ok(mut current) = self.now.lock() {
+            *current = now;
+        }
+    }
+
+    pub fn advance(&self, by: Duration) {
+        if let Ok(mut current) = self.now.lock() {
+            *current += by;
+        }
+    }
+}
+
+impl Clock for ManualClock {
+    fn now(&self) -> DateTime<Utc> {
+        self.now
+            .lock()
+            .map(|now| *now)
+            .unwrap_or_else(|_| Utc::now())
+    }
+}
+
+/// Random v4 UUIDs and the process-wide `msg-N` counter.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct RandomIds;
+
+impl IdGenerator for RandomIds {
+    fn uuid(&self) -> Uuid {
+        Uuid::new_v4()
+    }
+
+    fn message_id(&self) -> MessageId {
+        MessageId::new()
+    }
+}
+
+/// Deterministic identifiers counting up from 1, independent per instance.
+#[derive(Clone, Debug, Default)]
+pub struct SequentialIds {
+    next: Arc<AtomicU64>,
+}
+
+impl SequentialIds {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    fn bump(&self) -> u64 {
+        self.next.fetch_add(1, Ordering::Relaxed) + 1
+    }
+}
+
+impl IdGenerator for SequentialIds {
+    fn uuid(&self) -> Uuid {
+        Uuid::from_u128(u128::from(self.bump()))
+    }
+
+    fn message_id(&self) -> MessageId {
+        MessageId(format!("msg-{}", self.bump()))
+    }
+}
+
+/// Cloneable handle to the clock in use, defaulting to [`SystemClock`].
+#[derive(Clone)]
+pub struct SharedClock(Arc<dyn Clock>);
+
+impl SharedClock {
+    pub fn new(clock: impl Clock + 'static) -> Self {
+        Self(Arc::new(clock))
+    }
+
+    pub fn now(&self) -> DateTime<Utc> {
+        self.0.now()
+    }
+}
+
+impl Default for SharedClock {
+    fn default() -> Self {
+        Self::new(SystemClock)
+    }
+}
+
+impl fmt::Debug for SharedClock {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_tuple("SharedClock").field(&self.now()).finish()
+    }
+}
+
+/// Cloneable handle to the identifier source, defaulting to [`RandomIds`].
+#[derive(Clone)]
+pub struct SharedIds(Arc<dyn IdGenerator>);
+
+impl SharedIds {
+    pub fn new(ids: impl IdGenerator + 'static) -> Self {
+        Self(Arc::new(ids))
+    }
+
+    pub fn uuid(&self) -> Uuid {
+        self.0.uuid()
+    }
+
+    pub fn message_id(&self) -> MessageId {
+        self.0.message_id()
+    }
+}
+
+impl Default for SharedIds {
+    fn default() -> Self {
+        Self::new(RandomIds)
+    }
+}
+
+impl fmt::Debug for SharedIds {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("SharedIds")
+    }
+}
@@ -1,28 +1,74 @@
 use std::env;
+use std::fmt::Write as _;
 use std::fs;
 use std::path::Path;
 
+use serde::Serialize;
+
+use crate::auth::Role;
+use crate::dlp::DlpAction;
+
+/// Shown in place of secret values in [`AppConfig::masked`].
+const SECRET_MASK: &str = "********";
+
 /// Error type returned when configuration loading fails.
 #[derive(Debug, PartialEq, Eq)]
 pub enum ConfigError {
     MissingFile,
     InvalidFormat,
+    UnknownPreset(String),
+    Invalid(String),
+    WriteFailed,
 }
 
-/// TLS settings placeholder retained for compatibility.
-#[derive(Clone, Debug, PartialEq, Eq, Default)]
+/// TLS settings for the HTTP listener.
+///
+/// Setting `client_ca_path` turns on mutual TLS: clients must present a
+/// certificate issued by that CA, or may omit one when `require_client_cert`
+/// is false.
+///
+/// Revocation of the server chain and the client CA bundle is rechecked every
+/// `revocation_interval_secs`, through `ocsp_responder` when set and
+/// otherwise through the responders and CRLs named in the certificates.
+#[derive(Clone, Debug, PartialEq, Eq)]
 pub struct TlsConfig {
     pub enabled: bool,
     pub certificate_path: String,
     pub private_key_path: String,
+    pub client_ca_path: Option<String>,
+    pub require_client_cert: bool,
+    pub ocsp_responder: Option<String>,
+    pub revocation_interval_secs: u64,
+}
+
+impl Default for TlsConfig {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            certificate_path: String::new(),
+            private_key_path: String::new(),
+            client_ca_path: None,
+            require_client_cert: false,
+            ocsp_responder: None,
+            revocation_interval_secs: 3_600,
+        }
+    }
 }
 
 /// Server configuration describing host/port.
+///
+/// When `socket_path` is set the API listens on that Unix domain socket
+/// instead of `host:port`.
 #[derive(Clone, Debug, PartialEq, Eq)]
 pub struct ServerConfig {
     pub host: String,
     pub port: u16,
     pub tls: TlsConfig,
+    pub socket_path: Option<String>,
+    /// How long shutdown waits for the outbound queue to flush.
+    pub drain_timeout_ms: u64,
+    /// How long an `Idempotency-Key` on `/compose` and `/submit` is remembered.
+    pub idempotency_ttl_secs: u64,
 }
 
 impl Default for ServerConfig {
@@ -31,20 +77,45 @@ impl Default for ServerConfig {
             host: "127.0.0.1".into(),
             port: 3333,
             tls: TlsConfig::default(),
+            socket_path: None,
+            drain_timeout_ms: 30_000,
+            idempotency_ttl_secs: 86_400,
         }
     }
 }
 
 /// Configuration describing where messages are persisted.
+///
+/// `path` names the store; each table is a JSON file beside it with the
+/// table's name as extension, such as `data/messages.queue.json`.
+///
+/// The store opens no SQLite connections, so there is no pool size, busy
+/// timeout or journal mode to tune: writers to one table wait on that table's
+/// lock, and each write replaces the table file through a temporary file.
 #[derive(Clone, Debug, PartialEq, Eq)]
 pub struct DatabaseConfig {
     pub path: String,
+    /// Passphrase that seals `/admin/backup` snapshots; backups are off without it.
+    pub backup_key: Option<String>,
+    /// Secret reference `backup_key` is resolved from.
+    pub backup_key_ref: Option<String>,
+    /// Encrypt the store, and the attachment files beside it, with
+    /// `sqlcipher_key`.
+    pub use_sqlcipher: bool,
+    pub sqlcipher_key: Option<String>,
+    /// Secret reference `sqlcipher_key` is resolved from.
+    pub sqlcipher_key_ref: Option<String>,
 }
 
 impl Default for DatabaseConfig {
     fn default() -> Self {
         Self {
             path: "data/messages.db".into(),
+            backup_key: None,
+            backup_key_ref: None,
+            use_sqlcipher: false,
+            sqlcipher_key: None,
+            sqlcipher_key_ref: None,
         }
     }
 }
@@ -58,6 +129,18 @@ pub struct AppConfig {
     pub gateway: GatewayConfig,
     pub directory: DirectoryConfig,
     pub telemetry: TelemetryConfig,
+    pub dlp: DlpConfig,
+    pub webhooks: WebhookConfig,
+    pub transport: TransportConfig,
+    pub security: SecurityConfig,
+    pub rate_limit: RateLimitConfig,
+    pub access_log: AccessLogConfig,
+    pub tracing: TracingConfig,
+    pub support: SupportConfig,
+    pub queue: QueueConfig,
+    pub supervisor: SupervisorConfig,
+    pub quota: QuotaConfig,
+    pub retention: RetentionConfig,
 }
 
 /// Migration related configuration.
@@ -67,6 +150,10 @@ pub struct MigrationConfig {
     pub quarantine: String,
     pub charset_fallback: String,
     pub parallelism: usize,
+    /// Largest uncompressed size of one FWZ entry; `0` disables the limit.
+    pub max_entry_bytes: u64,
+    /// Largest uncompressed size of a whole FWZ archive; `0` disables the limit.
+    pub max_archive_bytes: u64,
 }
 
 impl Default for MigrationConfig {
@@ -76,6 +163,8 @@ impl Default for MigrationConfig {
             quarantine: "workspace/quarantine".into(),
             charset_fallback: "utf-8".into(),
             parallelism: 4,
+            max_entry_bytes: 512 * 1024 * 1024,
+            max_archive_bytes: 8 * 1024 * 1024 * 1024,
         }
     }
 }
@@ -86,15 +175,749 @@ impl AppConfig {
     /// When the variable is unset or the file cannot be read the default
     /// configuration is returned.
     pub fn load() -> Result<Self, ConfigError> {
+        let base = match env::var("X400_PRESET").ok() {
+            Some(name) => Self::preset(ConfigPreset::parse(&name)?),
+            None => Self::default(),
+        };
         match env::var("CORE_CONFIG").ok() {
-            Some(path) => Self::from_file(Path::new(&path)),
-            None => Ok(Self::default()),
+            Some(path) => Self::from_file(Path::new(&path), base),
+            None => Ok(base),
+        }
+    }
+
+    /// Defaults tuned for one of the supported deployment topologies.
+    pub fn preset(preset: ConfigPreset) -> Self {
+        let mut config = Self::default();
+        match preset {
+            ConfigPreset::StandaloneDesktop => {
+                config.migration.parallelism = 2;
+            }
+            ConfigPreset::GatewayBridge => {
+                config.server.host = "0.0.0.0".into();
+                config.security.require_auth = true;
+                config.gateway.smtp.rate_limit_per_minute = 600;
+                config.telemetry.enabled = true;
+                config.telemetry.sampling = 0.5;
+                config.dlp.enabled = true;
+            }
+            ConfigPreset::CentralServer => {
+                config.server.host = "0.0.0.0".into();
+                config.security.require_auth = true;
+                config.database.path = "/var/lib/x400/messages.db".into();
+                config.migration.workspace = "/var/lib/x400/migration".into();
+                config.migration.quarantine = "/var/lib/x400/quarantine".into();
+                config.migration.parallelism = 8;
+                config.telemetry.enabled = true;
+                config.telemetry.local_path = "/var/log/x400/telemetry".into();
+                config.telemetry.sampling = 0.25;
+                config.telemetry.retention_days = 30;
+                config.directory.cache.capacity = 4096;
+            }
+        }
+        config
+    }
+
+    /// Check invariants the service relies on at startup.
+    pub fn validate(&self) -> Result<(), ConfigError> {
+        if self.server.port == 0 {
+            return Err(ConfigError::Invalid("server.port must be non-zero".into()));
+        }
+        if self.migration.parallelism == 0 {
+            return Err(ConfigError::Invalid(
+                "migration.parallelism must be at least 1".into(),
+            ));
+        }
+        if crate::migration::LegacyCharset::for_label(&self.migration.charset_fallback).is_none() {
+            return Err(ConfigError::Invalid(format!(
+                "migration.charsetFallback: unknown character set {}",
+                self.migration.charset_fallback
+            )));
+        }
+        if self.tracing.buffer_capacity == 0 {
+            return Err(ConfigError::Invalid(
+                "tracing.bufferCapacity must be at least 1".into(),
+            ));
+        }
+        if let Some(url) = &self.support.upload.url {
+            if !url.starts_with("https://") {
+                return Err(ConfigError::Invalid(format!(
+                    "support.upload.url must be an https URL: {url}"
+                )));
+            }
+        }
+        if !(0.0..=1.0).contains(&self.telemetry.sampling) {
+            return Err(ConfigError::Invalid(
+                "telemetry.sampling must be between 0 and 1".into(),
+            ));
+        }
+        if let Some(endpoint) = &self.telemetry.endpoint {
+            if !endpoint.starts_with("https://") && !endpoint.starts_with("http://") {
+                return Err(ConfigError::Invalid(format!(
+                    "telemetry.endpoint must be an http(s) URL: {endpoint}"
+                )));
+            }
+        }
+        if let Some(attribute) = self
+            .telemetry
+            .resource_attributes
+            .iter()
+            .find(|attribute| !attribute.contains('='))
+        {
+            return Err(ConfigError::Invalid(format!(
+                "telemetry.resourceAttributes: {attribute:?} is not key=value"
+            )));
+        }
+        if self.telemetry.batch_size == 0 {
+            return Err(ConfigError::Invalid(
+                "telemetry.batchSize must be at least 1".into(),
+            ));
+        }
+        if self.gateway.mapping.rules.is_empty() && self.gateway.mapping.file.is_none() {
+            return Err(ConfigError::Invalid(
+                "gateway.mapping.rules must contain at least one rule unless gateway.mapping.file is set".into(),
+            ));
+        }
+        let security = &self.gateway.security;
+        if let Some(pattern) = security
+            .domain_allow_list
+            .iter()
+            .chain(&security.domain_deny_list)
+            .chain(&security.tls_domains)
+            .find(|pattern| crate::gateway::policy::DomainPattern::parse(pattern).is_none())
+        {
+            return Err(ConfigError::Invalid(format!(
+                "gateway.security: {pattern:?} is not a domain or *.domain pattern"
+            )));
+        }
+        if self.gateway.security.max_hops == 0 {
+            return Err(ConfigError::Invalid(
+                "gateway.security.maxHops must be at least 1".into(),
+            ));
+        }
+        if let Some(path) = &self.server.socket_path {
+            if path.starts_with(r"\\.\pipe\") {
+                return Err(ConfigError::Invalid(format!(
+                    "server.socketPath {path} is a named pipe; only Unix sockets are supported"
+                )));
+            }
+        }
+        let tls = &self.server.tls;
+        if tls.enabled && (tls.certificate_path.is_empty() || tls.private_key_path.is_empty()) {
+            return Err(ConfigError::Invalid(
+                "server.tls.certificate and server.tls.privateKey are required when TLS is enabled"
+                    .into(),
+            ));
+        }
+        if tls.require_client_cert && tls.client_ca_path.is_none() {
+            return Err(ConfigError::Invalid(
+                "server.tls.requireClientCert needs server.tls.clientCa".into(),
+            ));
+        }
+        let smime = &self.security.smime;
+        if smime.key_source == SmimeKeySource::Pkcs11 && smime.pkcs11.module.trim().is_empty() {
+            return Err(ConfigError::Invalid(
+                "security.smime.keySource=pkcs11 needs security.smime.pkcs11.module".into(),
+            ));
+        }
+        if tls.revocation_interval_secs == 0 {
+            return Err(ConfigError::Invalid(
+                "server.tls.revocationIntervalSecs must be at least 1".into(),
+            ));
+        }
+        for rule in &self.retention.rules {
+            if rule.max_age_days.is_none() && rule.max_count.is_none() {
+                return Err(ConfigError::Invalid(format!(
+                    "retention.folders.{} needs maxAgeDays or maxCount",
+                    rule.folder
+                )));
+            }
+            if rule.folder == "archive" && rule.action == RetentionAction::Archive {
+                return Err(ConfigError::Invalid(
+                    "retention.folders.archive.action must be purge".into(),
+                ));
+            }
+        }
+        if let Some(key) = self
+            .security
+            .keys
+            .iter()
+            .find(|key| key.secret.is_empty() && key.secret_ref.is_none())
+        {
+            return Err(ConfigError::Invalid(format!(
+                "security.keys.{} must not be empty",
+                key.name
+            )));
+        }
+        Ok(())
+    }
+
+    /// Write a validated configuration file for `preset` that [`AppConfig::load`] can read back.
+    ///
+    /// Secrets are never written inline; the file references keychain entries
+    /// that the operator provisions separately.
+    pub fn materialize(preset: ConfigPreset, path: &Path) -> Result<Self, ConfigError> {
+        let mut config = Self::preset(preset);
+        for (key, reference) in config.secret_refs() {
+            config.apply(key, &reference)?;
+        }
+        config.validate()?;
+        config.write_file(
+            path,
+            &format!("Generated from the {} preset", preset.name()),
+        )?;
+        Ok(config)
+    }
+
+    /// Render this configuration as a loadable file, with `header` as a leading comment.
+    pub fn write_file(&self, path: &Path, header: &str) -> Result<(), ConfigError> {
+        let contents = self.render(header);
+        if let Some(parent) = path.parent() {
+            fs::create_dir_all(parent).map_err(|_| ConfigError::WriteFailed)?;
+        }
+        fs::write(path, contents).map_err(|_| ConfigError::WriteFailed)
+    }
+
+    /// The rendered file followed by `key=********` for every secret that is
+    /// set, so a support bundle shows which secrets were resolved but not
+    /// their values.
+    pub fn masked(&self) -> String {
+        let mut contents = self.render("Exported for support; secret values are masked.");
+        let secrets = [
+            (
+                "gateway.smtp.password",
+                self.gateway.smtp.password.is_some(),
+            ),
+            (
+                "directory.ldap.bindPassword",
+                self.directory.ldap.bind_password.is_some(),
+            ),
+            ("security.apiKey", self.security.api_key.is_some()),
+            ("database.backupKey", self.database.backup_key.is_some()),
+            (
+                "database.sqlcipherKey",
+                self.database.sqlcipher_key.is_some(),
+            ),
+        ];
+        for (key, _) in secrets.iter().filter(|(_, set)| *set) {
+            let _ = writeln!(contents, "{key}={SECRET_MASK}");
+        }
+        for key in self
+            .security
+            .keys
+            .iter()
+            .filter(|key| !key.secret.is_empty())
+        {
+            let _ = writeln!(contents, "security.keys.{}={SECRET_MASK}", key.name);
         }
+        contents
     }
 
-    fn from_file(path: &Path) -> Result<Self, ConfigError> {
+    fn render(&self, header: &str) -> String {
+        let mut contents = String::new();
+        for line in header.lines() {
+            let _ = writeln!(contents, "# {line}");
+        }
+        let _ = writeln!(
+            contents,
+            "# Secrets are resolved from their references, never stored here."
+        );
+        for (key, value) in self.entries() {
+            let _ = writeln!(contents, "{key}={value}");
+        }
+        for rule in &self.retention.rules {
+            let prefix = format!("retention.folders.{}", rule.folder);
+            if let Some(days) = rule.max_age_days {
+                let _ = writeln!(contents, "{prefix}.maxAgeDays={days}");
+            }
+            if let Some(count) = rule.max_count {
+                let _ = writeln!(contents, "{prefix}.maxCount={count}");
+            }
+            let _ = writeln!(contents, "{prefix}.action={}", rule.action.name());
+        }
+        for rule in &self.dlp.rules {
+            let prefix = format!("dlp.rules.{}", rule.name);
+            if let Some(action) = rule.action {
+                let _ = writeln!(contents, "{prefix}.action={}", action.name());
+            }
+            if let Some(senders) = &rule.exempt_senders {
+                let _ = writeln!(contents, "{prefix}.exemptSenders={}", senders.join(","));
+            }
+        }
+        for (key, reference) in self.secret_refs() {
+            let _ = writeln!(contents, "{key}={reference}");
+        }
+        if let Some(reference) = &self.database.sqlcipher_key_ref {
+            let _ = writeln!(contents, "database.sqlcipherKeyRef={reference}");
+        }
+        for key in &self.security.keys {
+            if let Some(reference) = &key.secret_ref {
+                let _ = writeln!(contents, "security.keyRefs.{}={reference}", key.name);
+            }
+        }
+        contents
+    }
+
+    /// Secret references as written to a file, defaulting to the entries the
+    /// operator is expected to provision in the keychain.
+    fn secret_refs(&self) -> [(&'static str, String); 4] {
+        let reference = |configured: &Option<String>, account: &str| {
+            configured
+                .clone()
+                .unwrap_or_else(|| format!("keychain:x400-core/{account}"))
+        };
+        [
+            (
+                "gateway.smtp.passwordRef",
+                reference(&self.gateway.smtp.password_ref, "smtp"),
+            ),
+            (
+                "directory.ldap.bindPasswordRef",
+                reference(&self.directory.ldap.bind_password_ref, "ldap"),
+            ),
+            (
+                "security.apiKeyRef",
+                reference(&self.security.api_key_ref, "api-key"),
+            ),
+            (
+                "database.backupKeyRef",
+                reference(&self.database.backup_key_ref, "backup"),
+            ),
+        ]
+    }
+
+    /// Flattened `key=value` pairs understood by the file loader.
+    fn entries(&self) -> Vec<(&'static str, String)> {
+        let join = |items: &[String]| items.join(",");
+        vec![
+            ("server.host", self.server.host.clone()),
+            ("server.port", self.server.port.to_string()),
+            (
+                "server.socketPath",
+                self.server.socket_path.clone().unwrap_or_default(),
+            ),
+            (
+                "server.drainTimeoutMs",
+                self.server.drain_timeout_ms.to_string(),
+            ),
+            (
+                "server.idempotencyTtlSecs",
+                self.server.idempotency_ttl_secs.to_string(),
+            ),
+            ("server.tls.enabled", self.server.tls.enabled.to_string()),
+            (
+                "server.tls.certificate",
+                self.server.tls.certificate_path.clone(),
+            ),
+            (
+                "server.tls.privateKey",
+                self.server.tls.private_key_path.clone(),
+            ),
+            (
+                "server.tls.clientCa",
+                self.server.tls.client_ca_path.clone().unwrap_or_default(),
+            ),
+            (
+                "server.tls.requireClientCert",
+                self.server.tls.require_client_cert.to_string(),
+            ),
+            (
+                "server.tls.ocspResponder",
+                self.server.tls.ocsp_responder.clone().unwrap_or_default(),
+            ),
+            (
+                "server.tls.revocationIntervalSecs",
+                self.server.tls.revocation_interval_secs.to_string(),
+            ),
+            ("database.path", self.database.path.clone()),
+            (
+                "database.useSqlcipher",
+                self.database.use_sqlcipher.to_string(),
+            ),
+            ("migration.workspace", self.migration.workspace.clone()),
+            ("migration.quarantine", self.migration.quarantine.clone()),
+            (
+                "migration.charsetFallback",
+                self.migration.charset_fallback.clone(),
+            ),
+            (
+                "migration.parallelism",
+                self.migration.parallelism.to_string(),
+            ),
+            (
+                "migration.maxEntryBytes",
+                self.migration.max_entry_bytes.to_string(),
+            ),
+            (
+                "migration.maxArchiveBytes",
+                self.migration.max_archive_bytes.to_string(),
+            ),
+            ("telemetry.enabled", self.telemetry.enabled.to_string()),
+            (
+                "telemetry.endpoint",
+                self.telemetry.endpoint.clone().unwrap_or_default(),
+            ),
+            ("telemetry.localPath", self.telemetry.local_path.clone()),
+            ("telemetry.sampling", self.telemetry.sampling.to_string()),
+            (
+                "telemetry.retentionDays",
+                self.telemetry.retention_days.to_string(),
+            ),
+            (
+                "telemetry.caPath",
+                self.telemetry.ca_path.clone().unwrap_or_default(),
+            ),
+            (
+                "telemetry.resourceAttributes",
+                join(&self.telemetry.resource_attributes),
+            ),
+            ("telemetry.batchSize", self.telemetry.batch_size.to_string()),
+            (
+                "telemetry.exportIntervalMs",
+                self.telemetry.export_interval_ms.to_string(),
+            ),
+            ("telemetry.timeoutMs", self.telemetry.timeout_ms.to_string()),
+            ("gateway.smtp.host", self.gateway.smtp.host.clone()),
+            ("gateway.smtp.port", self.gateway.smtp.port.to_string()),
+            ("gateway.smtp.tls", self.gateway.smtp.tls.to_string()),
+            (
+                "gateway.smtp.implicitTls",
+                self.gateway.smtp.implicit_tls.to_string(),
+            ),
+            (
+                "gateway.smtp.username",
+                self.gateway.smtp.username.clone().unwrap_or_default(),
+            ),
+            (
+                "gateway.smtp.rateLimitPerMinute",
+                self.gateway.smtp.rate_limit_per_minute.to_string(),
+            ),
+            (
+                "gateway.smtp.deliver",
+                self.gateway.smtp.deliver.to_string(),
+            ),
+            (
+                "gateway.smtp.poolSize",
+                self.gateway.smtp.pool_size.to_string(),
+            ),
+            (
+                "gateway.smtp.timeoutMs",
+                self.gateway.smtp.timeout_ms.to_string(),
+            ),
+            ("gateway.imap.host", self.gateway.imap.host.clone()),
+            ("gateway.imap.port", self.gateway.imap.port.to_string()),
+            ("gateway.imap.tls", self.gateway.imap.tls.to_string()),
+            ("gateway.imap.mailbox", self.gateway.imap.mailbox.clone()),
+            (
+                "gateway.imap.username",
+                self.gateway.imap.username.clone().unwrap_or_default(),
+            ),
+            (
+                "gateway.imap.receive",
+                self.gateway.imap.receive.to_string(),
+            ),
+            (
+                "gateway.imap.moveTo",
+                self.gateway.imap.move_to.clone().unwrap_or_default(),
+            ),
+            (
+                "gateway.imap.timeoutMs",
+                self.gateway.imap.timeout_ms.to_string(),
+            ),
+            (
+                "gateway.imap.pollIntervalSecs",
+                self.gateway.imap.poll_interval_secs.to_string(),
+            ),
+            (
+                "gateway.imap.batchSize",
+                self.gateway.imap.batch_size.to_string(),
+            ),
+            ("gateway.mapping.rules", join(&self.gateway.mapping.rules)),
+            (
+                "gateway.mapping.file",
+                self.gateway.mapping.file.clone().unwrap_or_default(),
+            ),
+            (
+                "gateway.mapping.reloadIntervalSecs",
+                self.gateway.mapping.reload_interval_secs.to_string(),
+            ),
+            (
+                "gateway.security.enforceTls",
+                self.gateway.security.enforce_tls.to_string(),
+            ),
+            (
+                "gateway.security.allow",
+                join(&self.gateway.security.domain_allow_list),
+            ),
+            (
+                "gateway.security.deny",
+                join(&self.gateway.security.domain_deny_list),
+            ),
+            (
+                "gateway.security.requireTls",
+                join(&self.gateway.security.tls_domains),
+            ),
+            (
+                "gateway.security.maxHops",
+                self.gateway.security.max_hops.to_string(),
+            ),
+            ("directory.ldap.url", self.directory.ldap.url.clone()),
+            ("directory.ldap.baseDN", self.directory.ldap.base_dn.clone()),
+            (
+                "directory.ldap.filterPerson",
+                self.directory.ldap.filter_person.clone(),
+            ),
+            (
+                "directory.cache.ttlSeconds",
+                self.directory.cache.ttl_seconds.to_string(),
+            ),
+            (
+                "directory.cache.capacity",
+                self.directory.cache.capacity.to_string(),
+            ),
+            ("dlp.enabled", self.dlp.enabled.to_string()),
+            ("dlp.keywords", join(&self.dlp.keywords)),
+            ("dlp.patterns", join(&self.dlp.patterns)),
+            ("dlp.markers", join(&self.dlp.markers)),
+            ("dlp.action", self.dlp.action.name().to_string()),
+            ("dlp.exemptSenders", join(&self.dlp.exempt_senders)),
+            (
+                "webhooks.maxAttempts",
+                self.webhooks.max_attempts.to_string(),
+            ),
+            ("webhooks.backoffMs", self.webhooks.backoff_ms.to_string()),
+            ("webhooks.timeoutMs", self.webhooks.timeout_ms.to_string()),
+            ("transport.mode", self.transport.mode.clone()),
+            ("transport.profilesDir", self.transport.profiles_dir.clone()),
+            ("transport.profile", self.transport.default_profile.clone()),
+            ("transport.profiles", join(&self.transport.profiles)),
+            (
+                "transport.breakerThreshold",
+                self.transport.breaker_threshold.to_string(),
+            ),
+            (
+                "transport.breakerCooldownMs",
+                self.transport.breaker_cooldown_ms.to_string(),
+            ),
+            (
+                "transport.rebindInitialMs",
+                self.transport.rebind_initial_ms.to_string(),
+            ),
+            (
+                "transport.rebindMaxMs",
+                self.transport.rebind_max_ms.to_string(),
+            ),
+            (
+                "transport.sdk.connectTimeoutMs",
+                self.transport.sdk_connect_timeout_ms.to_string(),
+            ),
+            (
+                "transport.sdk.operationTimeoutMs",
+                self.transport.sdk_operation_timeout_ms.to_string(),
+            ),
+            (
+                "transport.sdk.maxConcurrentCalls",
+                self.transport.sdk_max_concurrent_calls.to_string(),
+            ),
+            (
+                "transport.sdk.chunkSizeBytes",
+                self.transport.sdk_chunk_size_bytes.to_string(),
+            ),
+            (
+                "transport.tls.caBundle",
+                self.transport.ca_bundle.clone().unwrap_or_default(),
+            ),
+            (
+                "transport.tls.ocspResponder",
+                self.transport.ocsp_responder.clone().unwrap_or_default(),
+            ),
+            (
+                "transport.tls.enforcePinset",
+                self.transport.enforce_pinset.to_string(),
+            ),
+            ("transport.fingerprints", join(&self.transport.fingerprints)),
+            (
+                "security.requireAuth",
+                self.security.require_auth.to_string(),
+            ),
+            (
+                "security.smime.enabled",
+                self.security.smime.enabled.to_string(),
+            ),
+            (
+                "security.smime.certificatesDir",
+                self.security.smime.certificates_dir.clone(),
+            ),
+            (
+                "security.smime.certificate",
+                self.security.smime.certificate_path.clone(),
+            ),
+            (
+                "security.smime.privateKey",
+                self.security.smime.private_key_path.clone(),
+            ),
+            (
+                "security.smime.trustStore",
+                self.security.smime.trust_store_path.clone(),
+            ),
+            (
+                "security.smime.keySource",
+                self.security.smime.key_source.name().to_string(),
+            ),
+            (
+                "security.smime.pkcs11.module",
+                self.security.smime.pkcs11.module.clone(),
+            ),
+            (
+                "security.smime.pkcs11.slot",
+                self.security
+                    .smime
+                    .pkcs11
+                    .slot
+                    .map(|slot| slot.to_string())
+                    .unwrap_or_default(),
+            ),
+            (
+                "security.smime.pkcs11.keyLabel",
+                self.security
+                    .smime
+                    .pkcs11
+                    .key_label
+                    .clone()
+                    .unwrap_or_default(),
+            ),
+            (
+                "security.smime.pkcs11.pinRef",
+                self.security
+                    .smime
+                    .pkcs11
+                    .pin_ref
+                    .clone()
+                    .unwrap_or_default(),
+            ),
+            ("rateLimit.enabled", self.rate_limit.enabled.to_string()),
+            (
+                "rateLimit.perKeyPerMinute",
+                self.rate_limit.per_key_per_minute.to_string(),
+            ),
+            (
+                "rateLimit.perIpPerMinute",
+                self.rate_limit.per_ip_per_minute.to_string(),
+            ),
+            ("rateLimit.burst", self.rate_limit.burst.to_string()),
+            ("accessLog.enabled", self.access_log.enabled.to_string()),
+            ("accessLog.path", self.access_log.path.clone()),
+            (
+                "accessLog.retentionDays",
+                self.access_log.retention_days.to_string(),
+            ),
+            (
+                "accessLog.anonymizeIp",
+                self.access_log.anonymize_ip.to_string(),
+            ),
+            (
+                "tracing.traceBundlePath",
+                self.tracing.trace_bundle_path.clone(),
+            ),
+            (
+                "tracing.bufferCapacity",
+                self.tracing.buffer_capacity.to_string(),
+            ),
+            (
+                "tracing.maxFileBytes",
+                self.tracing.max_file_bytes.to_string(),
+            ),
+            (
+                "tracing.maxFileAgeSecs",
+                self.tracing.max_file_age_secs.to_string(),
+            ),
+            ("tracing.maxFiles", self.tracing.max_files.to_string()),
+            ("tracing.compress", self.tracing.compress.to_string()),
+            (
+                "support.upload.url",
+                self.support.upload.url.clone().unwrap_or_default(),
+            ),
+            (
+                "support.upload.authHeader",
+                self.support.upload.auth_header.clone(),
+            ),
+            (
+                "support.upload.authTokenRef",
+                self.support
+                    .upload
+                    .auth_token_ref
+                    .clone()
+                    .unwrap_or_default(),
+            ),
+            (
+                "support.upload.maxAttempts",
+                self.support.upload.max_attempts.to_string(),
+            ),
+            (
+                "support.upload.backoffMs",
+                self.support.upload.backoff_ms.to_string(),
+            ),
+            (
+                "support.upload.timeoutMs",
+                self.support.upload.timeout_ms.to_string(),
+            ),
+            (
+                "support.upload.intervalSecs",
+                self.support.upload.interval_secs.to_string(),
+            ),
+            (
+                "support.retention.maxTotalBytes",
+                self.support.retention.max_total_bytes.to_string(),
+            ),
+            (
+                "support.retention.maxAgeDays",
+                self.support.retention.max_age_days.to_string(),
+            ),
+            (
+                "support.retention.maxCount",
+                self.support.retention.max_count.to_string(),
+            ),
+            (
+                "support.retention.intervalSecs",
+                self.support.retention.interval_secs.to_string(),
+            ),
+            ("queue.agingMs", self.queue.aging_ms.to_string()),
+            ("queue.batchLimit", self.queue.batch_limit.to_string()),
+            (
+                "supervisor.heartbeatTimeoutMs",
+                self.supervisor.heartbeat_timeout_ms.to_string(),
+            ),
+            (
+                "supervisor.restartBackoffMs",
+                self.supervisor.restart_backoff_ms.to_string(),
+            ),
+            (
+                "supervisor.maxBackoffMs",
+                self.supervisor.max_backoff_ms.to_string(),
+            ),
+            (
+                "supervisor.escalateAfter",
+                self.supervisor.escalate_after.to_string(),
+            ),
+            (
+                "quota.softLimitBytes",
+                self.quota.soft_limit_bytes.to_string(),
+            ),
+            (
+                "quota.maxMessageBytes",
+                self.quota.max_message_bytes.to_string(),
+            ),
+            ("quota.exemptSenders", join(&self.quota.exempt_senders)),
+            ("retention.enabled", self.retention.enabled.to_string()),
+            (
+                "retention.intervalSecs",
+                self.retention.interval_secs.to_string(),
+            ),
+        ]
+    }
+
+    fn from_file(path: &Path, base: Self) -> Result<Self, ConfigError> {
         let contents = fs::read_to_string(path).map_err(|_| ConfigError::MissingFile)?;
-        let mut result = Self::default();
+        let mut result = base;
 
         for line in contents.lines() {
             let line = line.trim();
@@ -109,107 +932,611 @@ impl AppConfig {
                 .trim()
                 .trim_matches('"');
 
-            match key {
-                "server.port" => {
-                    result.server.port = value.parse().map_err(|_| ConfigError::InvalidFormat)?;
-                }
-                "server.host" => {
-                    result.server.host = value.to_string();
-                }
-                "telemetry.enabled" => {
-                    result.telemetry.enabled = matches!(value, "true" | "1" | "yes" | "on");
-                }
-                "telemetry.endpoint" => {
-                    result.telemetry.endpoint = if value.is_empty() {
-                        None
-                    } else {
-                        Some(value.to_string())
-                    };
-                }
-                "telemetry.localPath" => {
-                    result.telemetry.local_path = value.to_string();
-                }
-                "telemetry.sampling" => {
-                    result.telemetry.sampling =
-                        value.parse().map_err(|_| ConfigError::InvalidFormat)?;
-                }
-                "telemetry.retentionDays" => {
-                    result.telemetry.retention_days =
-                        value.parse().map_err(|_| ConfigError::InvalidFormat)?;
-                }
-                "database.path" => {
-                    result.database.path = value.to_string();
-                }
-                "migration.workspace" => {
-                    result.migration.workspace = value.to_string();
-                }
-                "migration.quarantine" => {
-                    result.migration.quarantine = value.to_string();
-                }
-                "migration.charsetFallback" => {
-                    result.migration.charset_fallback = value.to_string();
-                }
-                "migration.parallelism" => {
-                    result.migration.parallelism =
-                        value.parse().map_err(|_| ConfigError::InvalidFormat)?;
-                }
-                "gateway.smtp.host" => {
-                    result.gateway.smtp.host = value.to_string();
-                }
-                "gateway.smtp.port" => {
-                    result.gateway.smtp.port =
-                        value.parse().map_err(|_| ConfigError::InvalidFormat)?;
-                }
-                "gateway.smtp.tls" => {
-                    result.gateway.smtp.tls =
-                        value.parse().map_err(|_| ConfigError::InvalidFormat)?;
-                }
-                "gateway.imap.host" => {
-                    result.gateway.imap.host = value.to_string();
-                }
-                "gateway.imap.port" => {
-                    result.gateway.imap.port =
-                        value.parse().map_err(|_| ConfigError::InvalidFormat)?;
-                }
-                "gateway.imap.mailbox" => {
-                    result.gateway.imap.mailbox = value.to_string();
-                }
-                "gateway.mapping.rules" => {
-                    result.gateway.mapping.rules = value
-                        .split(',')
-                        .map(|item| item.trim().to_string())
-                        .filter(|item| !item.is_empty())
-                        .collect();
-                }
-                "gateway.security.allow" => {
-                    result.gateway.security.domain_allow_list = value
-                        .split(',')
-                        .map(|item| item.trim().to_string())
-                        .filter(|item| !item.is_empty())
-                        .collect();
-                }
-                "directory.ldap.url" => {
-                    result.directory.ldap.url = value.to_string();
-                }
-                "directory.ldap.baseDN" => {
-                    result.directory.ldap.base_dn = value.to_string();
-                }
-                "directory.ldap.filterPerson" => {
-                    result.directory.ldap.filter_person = value.to_string();
-                }
-                "directory.cache.ttlSeconds" => {
-                    result.directory.cache.ttl_seconds =
-                        value.parse().map_err(|_| ConfigError::InvalidFormat)?;
+            result.apply(key, value)?;
+        }
+
+        Ok(result)
+    }
+
+    /// Apply one `key=value` setting as it would appear in a config file.
+    ///
+    /// Unknown keys are ignored so newer files still load on older builds.
+    pub fn apply(&mut self, key: &str, value: &str) -> Result<(), ConfigError> {
+        match key {
+            "server.port" => {
+                self.server.port = value.parse().map_err(|_| ConfigError::InvalidFormat)?;
+            }
+            "server.host" => {
+                self.server.host = value.to_string();
+            }
+            "server.socketPath" => {
+                self.server.socket_path = Some(value.to_string()).filter(|v| !v.is_empty());
+            }
+            "server.drainTimeoutMs" => {
+                self.server.drain_timeout_ms =
+                    value.parse().map_err(|_| ConfigError::InvalidFormat)?;
+            }
+            "server.idempotencyTtlSecs" => {
+                self.server.idempotency_ttl_secs =
+                    value.parse().map_err(|_| ConfigError::InvalidFormat)?;
+            }
+            "server.tls.enabled" => {
+                self.server.tls.enabled = matches!(value, "true" | "1" | "yes" | "on");
+            }
+            "server.tls.certificate" => {
+                self.server.tls.certificate_path = value.to_string();
+            }
+            "server.tls.privateKey" => {
+                self.server.tls.private_key_path = value.to_string();
+            }
+            "server.tls.clientCa" => {
+                self.server.tls.client_ca_path = Some(value.to_string()).filter(|v| !v.is_empty());
+            }
+            "server.tls.requireClientCert" => {
+                self.server.tls.require_client_cert = matches!(value, "true" | "1" | "yes" | "on");
+            }
+            "server.tls.ocspResponder" => {
+                self.server.tls.ocsp_responder = Some(value.to_string()).filter(|v| !v.is_empty());
+            }
+            "server.tls.revocationIntervalSecs" => {
+                self.server.tls.revocation_interval_secs =
+                    value.parse().map_err(|_| ConfigError::InvalidFormat)?;
+            }
+            "telemetry.enabled" => {
+                self.telemetry.enabled = matches!(value, "true" | "1" | "yes" | "on");
+            }
+            "telemetry.endpoint" => {
+                self.telemetry.endpoint = if value.is_empty() {
+                    None
+                } else {
+                    Some(value.to_string())
+                };
+            }
+            "telemetry.localPath" => {
+                self.telemetry.local_path = value.to_string();
+            }
+            "telemetry.sampling" => {
+                self.telemetry.sampling = value.parse().map_err(|_| ConfigError::InvalidFormat)?;
+            }
+            "telemetry.retentionDays" => {
+                self.telemetry.retention_days =
+                    value.parse().map_err(|_| ConfigError::InvalidFormat)?;
+            }
+            "telemetry.caPath" => {
+                self.telemetry.ca_path = Some(value.to_string()).filter(|v| !v.is_empty());
+            }
+            "telemetry.resourceAttributes" => {
+                self.telemetry.resource_attributes = split_list(value);
+            }
+            "telemetry.batchSize" => {
+                self.telemetry.batch_size =
+                    value.parse().map_err(|_| ConfigError::InvalidFormat)?;
+            }
+            "telemetry.exportIntervalMs" => {
+                self.telemetry.export_interval_ms =
+                    value.parse().map_err(|_| ConfigError::InvalidFormat)?;
+            }
+            "telemetry.timeoutMs" => {
+                self.telemetry.timeout_ms =
+                    value.parse().map_err(|_| ConfigError::InvalidFormat)?;
+            }
+            "database.path" => {
+                self.database.path = value.to_string();
+            }
+            "database.backupKey" => {
+                self.database.backup_key = Some(value.to_string()).filter(|v| !v.is_empty());
+            }
+            "database.backupKeyRef" => {
+                self.database.backup_key_ref = Some(value.to_string()).filter(|v| !v.is_empty());
+            }
+            "database.useSqlcipher" => {
+                self.database.use_sqlcipher = matches!(value, "true" | "1" | "yes" | "on");
+            }
+            "database.sqlcipherKeyRef" => {
+                self.database.sqlcipher_key_ref = Some(value.to_string()).filter(|v| !v.is_empty());
+            }
+            "migration.workspace" => {
+                self.migration.workspace = value.to_string();
+            }
+            "migration.quarantine" => {
+                self.migration.quarantine = value.to_string();
+            }
+            "migration.charsetFallback" => {
+                self.migration.charset_fallback = value.to_string();
+            }
+            "migration.parallelism" => {
+                self.migration.parallelism =
+                    value.parse().map_err(|_| ConfigError::InvalidFormat)?;
+            }
+            "migration.maxEntryBytes" => {
+                self.migration.max_entry_bytes =
+                    value.parse().map_err(|_| ConfigError::InvalidFormat)?;
+            }
+            "migration.maxArchiveBytes" => {
+                self.migration.max_archive_bytes =
+                    value.parse().map_err(|_| ConfigError::InvalidFormat)?;
+            }
+            "gateway.smtp.host" => {
+                self.gateway.smtp.host = value.to_string();
+            }
+            "gateway.smtp.port" => {
+                self.gateway.smtp.port = value.parse().map_err(|_| ConfigError::InvalidFormat)?;
+            }
+            "gateway.smtp.tls" => {
+                self.gateway.smtp.tls = value.parse().map_err(|_| ConfigError::InvalidFormat)?;
+            }
+            "gateway.smtp.implicitTls" => {
+                self.gateway.smtp.implicit_tls =
+                    value.parse().map_err(|_| ConfigError::InvalidFormat)?;
+            }
+            "gateway.smtp.username" => {
+                self.gateway.smtp.username = Some(value.to_string()).filter(|v| !v.is_empty());
+            }
+            "gateway.smtp.passwordRef" => {
+                self.gateway.smtp.password_ref = Some(value.to_string()).filter(|v| !v.is_empty());
+            }
+            "gateway.smtp.deliver" => {
+                self.gateway.smtp.deliver =
+                    value.parse().map_err(|_| ConfigError::InvalidFormat)?;
+            }
+            "gateway.smtp.poolSize" => {
+                self.gateway.smtp.pool_size =
+                    value.parse().map_err(|_| ConfigError::InvalidFormat)?;
+            }
+            "gateway.smtp.timeoutMs" => {
+                self.gateway.smtp.timeout_ms =
+                    value.parse().map_err(|_| ConfigError::InvalidFormat)?;
+            }
+            "gateway.smtp.rateLimitPerMinute" => {
+                self.gateway.smtp.rate_limit_per_minute =
+                    value.parse().map_err(|_| ConfigError::InvalidFormat)?;
+            }
+            "gateway.imap.host" => {
+                self.gateway.imap.host = value.to_string();
+            }
+            "gateway.imap.port" => {
+                self.gateway.imap.port = value.parse().map_err(|_| ConfigError::InvalidFormat)?;
+            }
+            "gateway.imap.tls" => {
+                self.gateway.imap.tls = value.parse().map_err(|_| ConfigError::InvalidFormat)?;
+            }
+            "gateway.imap.mailbox" => {
+                self.gateway.imap.mailbox = value.to_string();
+            }
+            "gateway.imap.username" => {
+                self.gateway.imap.username = Some(value.to_string()).filter(|v| !v.is_empty());
+            }
+            "gateway.imap.receive" => {
+                self.gateway.imap.receive =
+                    value.parse().map_err(|_| ConfigError::InvalidFormat)?;
+            }
+            "gateway.imap.moveTo" => {
+                self.gateway.imap.move_to = Some(value.to_string()).filter(|v| !v.is_empty());
+            }
+            "gateway.imap.timeoutMs" => {
+                self.gateway.imap.timeout_ms =
+                    value.parse().map_err(|_| ConfigError::InvalidFormat)?;
+            }
+            "gateway.imap.pollIntervalSecs" => {
+                self.gateway.imap.poll_interval_secs =
+                    value.parse().map_err(|_| ConfigError::InvalidFormat)?;
+            }
+            "gateway.imap.batchSize" => {
+                self.gateway.imap.batch_size =
+                    value.parse().map_err(|_| ConfigError::InvalidFormat)?;
+            }
+            "gateway.mapping.rules" => {
+                self.gateway.mapping.rules = split_list(value);
+            }
+            "gateway.mapping.file" => {
+                self.gateway.mapping.file = Some(value.to_string()).filter(|v| !v.is_empty());
+            }
+            "gateway.mapping.reloadIntervalSecs" => {
+                self.gateway.mapping.reload_interval_secs =
+                    value.parse().map_err(|_| ConfigError::InvalidFormat)?;
+            }
+            "gateway.security.enforceTls" => {
+                self.gateway.security.enforce_tls =
+                    value.parse().map_err(|_| ConfigError::InvalidFormat)?;
+            }
+            "gateway.security.allow" => {
+                self.gateway.security.domain_allow_list = split_list(value);
+            }
+            "gateway.security.deny" => {
+                self.gateway.security.domain_deny_list = split_list(value);
+            }
+            "gateway.security.requireTls" => {
+                self.gateway.security.tls_domains = split_list(value);
+            }
+            "gateway.security.maxHops" => {
+                self.gateway.security.max_hops =
+                    value.parse().map_err(|_| ConfigError::InvalidFormat)?;
+            }
+            "directory.ldap.url" => {
+                self.directory.ldap.url = value.to_string();
+            }
+            "directory.ldap.baseDN" => {
+                self.directory.ldap.base_dn = value.to_string();
+            }
+            "directory.ldap.bindPasswordRef" => {
+                self.directory.ldap.bind_password_ref =
+                    Some(value.to_string()).filter(|v| !v.is_empty());
+            }
+            "directory.ldap.filterPerson" => {
+                self.directory.ldap.filter_person = value.to_string();
+            }
+            "directory.cache.ttlSeconds" => {
+                self.directory.cache.ttl_seconds =
+                    value.parse().map_err(|_| ConfigError::InvalidFormat)?;
+            }
+            "directory.cache.capacity" => {
+                self.directory.cache.capacity =
+                    value.parse().map_err(|_| ConfigError::InvalidFormat)?;
+            }
+            "dlp.enabled" => {
+                self.dlp.enabled = matches!(value, "true" | "1" | "yes" | "on");
+            }
+            "dlp.keywords" => {
+                self.dlp.keywords = split_list(value);
+            }
+            "dlp.patterns" => {
+                self.dlp.patterns = split_list(value);
+            }
+            "dlp.markers" => {
+                self.dlp.markers = split_list(value);
+            }
+            "dlp.action" => {
+                self.dlp.action = DlpAction::parse(value).ok_or(ConfigError::InvalidFormat)?;
+            }
+            "dlp.exemptSenders" => {
+                self.dlp.exempt_senders = split_list(value);
+            }
+            key if key.starts_with("dlp.rules.") => {
+                let (name, field) = key["dlp.rules.".len()..]
+                    .rsplit_once('.')
+                    .ok_or(ConfigError::InvalidFormat)?;
+                match field {
+                    "action" => {
+                        self.dlp.rule(name).action =
+                            Some(DlpAction::parse(value).ok_or(ConfigError::InvalidFormat)?);
+                    }
+                    "exemptSenders" => {
+                        self.dlp.rule(name).exempt_senders = Some(split_list(value));
+                    }
+                    _ => return Err(ConfigError::InvalidFormat),
                 }
-                "directory.cache.capacity" => {
-                    result.directory.cache.capacity =
-                        value.parse().map_err(|_| ConfigError::InvalidFormat)?;
+            }
+            "webhooks.maxAttempts" => {
+                self.webhooks.max_attempts =
+                    value.parse().map_err(|_| ConfigError::InvalidFormat)?;
+            }
+            "webhooks.backoffMs" => {
+                self.webhooks.backoff_ms = value.parse().map_err(|_| ConfigError::InvalidFormat)?;
+            }
+            "webhooks.timeoutMs" => {
+                self.webhooks.timeout_ms = value.parse().map_err(|_| ConfigError::InvalidFormat)?;
+            }
+            "transport.mode" => {
+                self.transport.mode = value.to_string();
+            }
+            "transport.profilesDir" => {
+                self.transport.profiles_dir = value.to_string();
+            }
+            "transport.profile" => {
+                self.transport.default_profile = value.to_string();
+            }
+            "transport.profiles" => {
+                self.transport.profiles = split_list(value);
+            }
+            "transport.breakerThreshold" => {
+                self.transport.breaker_threshold =
+                    value.parse().map_err(|_| ConfigError::InvalidFormat)?;
+            }
+            "transport.breakerCooldownMs" => {
+                self.transport.breaker_cooldown_ms =
+                    value.parse().map_err(|_| ConfigError::InvalidFormat)?;
+            }
+            "transport.rebindInitialMs" => {
+                self.transport.rebind_initial_ms =
+                    value.parse().map_err(|_| ConfigError::InvalidFormat)?;
+            }
+            "transport.rebindMaxMs" => {
+                self.transport.rebind_max_ms =
+                    value.parse().map_err(|_| ConfigError::InvalidFormat)?;
+            }
+            "transport.sdk.connectTimeoutMs" => {
+                self.transport.sdk_connect_timeout_ms =
+                    value.parse().map_err(|_| ConfigError::InvalidFormat)?;
+            }
+            "transport.sdk.operationTimeoutMs" => {
+                self.transport.sdk_operation_timeout_ms =
+                    value.parse().map_err(|_| ConfigError::InvalidFormat)?;
+            }
+            "transport.sdk.maxConcurrentCalls" => {
+                self.transport.sdk_max_concurrent_calls =
+                    value.parse().map_err(|_| ConfigError::InvalidFormat)?;
+            }
+            "transport.sdk.chunkSizeBytes" => {
+                self.transport.sdk_chunk_size_bytes =
+                    value.parse().map_err(|_| ConfigError::InvalidFormat)?;
+            }
+            "transport.tls.caBundle" => {
+                self.transport.ca_bundle = Some(value.to_string()).filter(|v| !v.is_empty());
+            }
+            "transport.tls.ocspResponder" => {
+                self.transport.ocsp_responder = Some(value.to_string()).filter(|v| !v.is_empty());
+            }
+            "transport.tls.enforcePinset" => {
+                self.transport.enforce_pinset = matches!(value, "true" | "1" | "yes" | "on");
+            }
+            "transport.fingerprints" => {
+                self.transport.fingerprints = split_list(value);
+            }
+            "security.requireAuth" => {
+                self.security.require_auth = matches!(value, "true" | "1" | "yes" | "on");
+            }
+            "security.apiKey" => {
+                self.security.api_key = Some(value.to_string()).filter(|v| !v.is_empty());
+            }
+            "security.apiKeyRef" => {
+                self.security.api_key_ref = Some(value.to_string()).filter(|v| !v.is_empty());
+            }
+            "security.smime.enabled" => {
+                self.security.smime.enabled = matches!(value, "true" | "1" | "yes" | "on");
+            }
+            "security.smime.certificatesDir" => {
+                self.security.smime.certificates_dir = value.to_string();
+            }
+            "security.smime.certificate" => {
+                self.security.smime.certificate_path = value.to_string();
+            }
+            "security.smime.privateKey" => {
+                self.security.smime.private_key_path = value.to_string();
+            }
+            "security.smime.trustStore" => {
+                self.security.smime.trust_store_path = value.to_string();
+            }
+            "security.smime.keySource" => {
+                self.security.smime.key_source =
+                    SmimeKeySource::parse(value).ok_or(ConfigError::InvalidFormat)?;
+            }
+            "security.smime.pkcs11.module" => {
+                self.security.smime.pkcs11.module = value.to_string();
+            }
+            "security.smime.pkcs11.slot" => {
+                self.security.smime.pkcs11.slot = match value {
+                    "" => None,
+                    slot => Some(slot.parse().map_err(|_| ConfigError::InvalidFormat)?),
+                };
+            }
+            "security.smime.pkcs11.keyLabel" => {
+                self.security.smime.pkcs11.key_label =
+                    Some(value.to_string()).filter(|v| !v.is_empty());
+            }
+            "security.smime.pkcs11.pinRef" => {
+                self.security.smime.pkcs11.pin_ref =
+                    Some(value.to_string()).filter(|v| !v.is_empty());
+            }
+            "rateLimit.enabled" => {
+                self.rate_limit.enabled = matches!(value, "true" | "1" | "yes" | "on");
+            }
+            "rateLimit.perKeyPerMinute" => {
+                self.rate_limit.per_key_per_minute =
+                    value.parse().map_err(|_| ConfigError::InvalidFormat)?;
+            }
+            "rateLimit.perIpPerMinute" => {
+                self.rate_limit.per_ip_per_minute =
+                    value.parse().map_err(|_| ConfigError::InvalidFormat)?;
+            }
+            "rateLimit.burst" => {
+                self.rate_limit.burst = value.parse().map_err(|_| ConfigError::InvalidFormat)?;
+            }
+            "accessLog.enabled" => {
+                self.access_log.enabled = matches!(value, "true" | "1" | "yes" | "on");
+            }
+            "accessLog.path" => {
+                self.access_log.path = value.to_string();
+            }
+            "accessLog.retentionDays" => {
+                self.access_log.retention_days =
+                    value.parse().map_err(|_| ConfigError::InvalidFormat)?;
+            }
+            "accessLog.anonymizeIp" => {
+                self.access_log.anonymize_ip = matches!(value, "true" | "1" | "yes" | "on");
+            }
+            "tracing.traceBundlePath" => {
+                self.tracing.trace_bundle_path = value.to_string();
+            }
+            "tracing.bufferCapacity" => {
+                self.tracing.buffer_capacity =
+                    value.parse().map_err(|_| ConfigError::InvalidFormat)?;
+            }
+            "tracing.maxFileBytes" => {
+                self.tracing.max_file_bytes =
+                    value.parse().map_err(|_| ConfigError::InvalidFormat)?;
+            }
+            "tracing.maxFileAgeSecs" => {
+                self.tracing.max_file_age_secs =
+                    value.parse().map_err(|_| ConfigError::InvalidFormat)?;
+            }
+            "tracing.maxFiles" => {
+                self.tracing.max_files = value.parse().map_err(|_| ConfigError::InvalidFormat)?;
+            }
+            "tracing.compress" => {
+                self.tracing.compress = matches!(value, "true" | "1" | "yes" | "on");
+            }
+            "support.upload.url" => {
+                self.support.upload.url = Some(value.to_string()).filter(|v| !v.is_empty());
+            }
+            "support.upload.authHeader" => {
+                self.support.upload.auth_header = value.to_string();
+            }
+            "support.upload.authTokenRef" => {
+                self.support.upload.auth_token_ref =
+                    Some(value.to_string()).filter(|v| !v.is_empty());
+            }
+            "support.upload.maxAttempts" => {
+                self.support.upload.max_attempts =
+                    value.parse().map_err(|_| ConfigError::InvalidFormat)?;
+            }
+            "support.upload.backoffMs" => {
+                self.support.upload.backoff_ms =
+                    value.parse().map_err(|_| ConfigError::InvalidFormat)?;
+            }
+            "support.upload.timeoutMs" => {
+                self.support.upload.timeout_ms =
+                    value.parse().map_err(|_| ConfigError::InvalidFormat)?;
+            }
+            "support.upload.intervalSecs" => {
+                self.support.upload.interval_secs =
+                    value.parse().map_err(|_| ConfigError::InvalidFormat)?;
+            }
+            "support.retention.maxTotalBytes" => {
+                self.support.retention.max_total_bytes =
+                    value.parse().map_err(|_| ConfigError::InvalidFormat)?;
+            }
+            "support.retention.maxAgeDays" => {
+                self.support.retention.max_age_days =
+                    value.parse().map_err(|_| ConfigError::InvalidFormat)?;
+            }
+            "support.retention.maxCount" => {
+                self.support.retention.max_count =
+                    value.parse().map_err(|_| ConfigError::InvalidFormat)?;
+            }
+            "support.retention.intervalSecs" => {
+                self.support.retention.interval_secs =
+                    value.parse().map_err(|_| ConfigError::InvalidFormat)?;
+            }
+            "queue.agingMs" => {
+                self.queue.aging_ms = value.parse().map_err(|_| ConfigError::InvalidFormat)?;
+            }
+            "queue.batchLimit" => {
+                self.queue.batch_limit = value.parse().map_err(|_| ConfigError::InvalidFormat)?;
+            }
+            "supervisor.heartbeatTimeoutMs" => {
+                self.supervisor.heartbeat_timeout_ms =
+                    value.parse().map_err(|_| ConfigError::InvalidFormat)?;
+            }
+            "supervisor.restartBackoffMs" => {
+                self.supervisor.restart_backoff_ms =
+                    value.parse().map_err(|_| ConfigError::InvalidFormat)?;
+            }
+            "supervisor.maxBackoffMs" => {
+                self.supervisor.max_backoff_ms =
+                    value.parse().map_err(|_| ConfigError::InvalidFormat)?;
+            }
+            "supervisor.escalateAfter" => {
+                self.supervisor.escalate_after =
+                    value.parse().map_err(|_| ConfigError::InvalidFormat)?;
+            }
+            "quota.softLimitBytes" => {
+                self.quota.soft_limit_bytes =
+                    value.parse().map_err(|_| ConfigError::InvalidFormat)?;
+            }
+            "quota.maxMessageBytes" => {
+                self.quota.max_message_bytes =
+                    value.parse().map_err(|_| ConfigError::InvalidFormat)?;
+            }
+            "quota.exemptSenders" => {
+                self.quota.exempt_senders = split_list(value);
+            }
+            "retention.enabled" => {
+                self.retention.enabled = matches!(value, "true" | "1" | "yes" | "on");
+            }
+            "retention.intervalSecs" => {
+                self.retention.interval_secs =
+                    value.parse().map_err(|_| ConfigError::InvalidFormat)?;
+            }
+            key if key.starts_with("retention.folders.") => {
+                let (folder, field) = key["retention.folders.".len()..]
+                    .rsplit_once('.')
+                    .ok_or(ConfigError::InvalidFormat)?;
+                let rule = self.retention.rule(folder);
+                match field {
+                    "maxAgeDays" => {
+                        rule.max_age_days =
+                            Some(value.parse().map_err(|_| ConfigError::InvalidFormat)?);
+                    }
+                    "maxCount" => {
+                        rule.max_count =
+                            Some(value.parse().map_err(|_| ConfigError::InvalidFormat)?);
+                    }
+                    "action" => {
+                        rule.action =
+                            RetentionAction::parse(value).ok_or(ConfigError::InvalidFormat)?;
+                    }
+                    _ => return Err(ConfigError::InvalidFormat),
                 }
-                _ => {}
             }
+            key if key.starts_with("security.clientCerts.") => {
+                let common_name = &key["security.clientCerts.".len()..];
+                let roles = split_list(value)
+                    .iter()
+                    .map(|role| Role::parse(role).ok_or(ConfigError::InvalidFormat))
+                    .collect::<Result<_, _>>()?;
+                self.security.client_certs.push(ClientCertConfig {
+                    common_name: common_name.to_string(),
+                    roles,
+                });
+            }
+            key if key.starts_with("security.keys.") => {
+                let name = &key["security.keys.".len()..];
+                self.security.named_key(name).secret = value.to_string();
+            }
+            key if key.starts_with("security.keyRefs.") => {
+                let name = &key["security.keyRefs.".len()..];
+                self.security.named_key(name).secret_ref =
+                    Some(value.to_string()).filter(|v| !v.is_empty());
+            }
+            key if key.starts_with("security.scopes.") => {
+                let name = &key["security.scopes.".len()..];
+                self.security.named_key(name).scopes = split_list(value);
+            }
+            key if key.starts_with("security.roles.") => {
+                let name = &key["security.roles.".len()..];
+                self.security.named_key(name).roles = split_list(value)
+                    .iter()
+                    .map(|role| Role::parse(role).ok_or(ConfigError::InvalidFormat))
+                    .collect::<Result<_, _>>()?;
+            }
+            _ => {}
         }
+        Ok(())
+    }
+}
 
-        Ok(result)
+fn split_list(value: &str) -> Vec<String> {
+    value
+        .split(',')
+        .map(|item| item.trim().to_string())
+        .filter(|item| !item.is_empty())
+        .collect()
+}
+
+/// Named defaults for common deployment topologies, selected with `X400_PRESET`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ConfigPreset {
+    StandaloneDesktop,
+    GatewayBridge,
+    CentralServer,
+}
+
+impl ConfigPreset {
+    pub fn parse(value: &str) -> Result<Self, ConfigError> {
+        match value.trim().to_ascii_lowercase().as_str() {
+            "standalone-desktop" => Ok(Self::StandaloneDesktop),
+            "gateway-bridge" => Ok(Self::GatewayBridge),
+            "central-server" => Ok(Self::CentralServer),
+            _ => Err(ConfigError::UnknownPreset(value.to_string())),
+        }
+    }
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            Self::StandaloneDesktop => "standalone-desktop",
+            Self::GatewayBridge => "gateway-bridge",
+            Self::CentralServer => "central-server",
+        }
     }
 }
 
@@ -227,9 +1554,18 @@ pub struct GatewaySmtpConfig {
     pub host: String,
     pub port: u16,
     pub tls: bool,
+    /// TLS from the first byte (port 465) instead of a STARTTLS upgrade.
+    pub implicit_tls: bool,
     pub username: Option<String>,
     pub password: Option<String>,
+    /// Secret reference `password` is resolved from.
+    pub password_ref: Option<String>,
     pub rate_limit_per_minute: u32,
+    /// Relay outbound mail to `host`; when off it is only kept in memory.
+    pub deliver: bool,
+    /// Connections kept open to the relay.
+    pub pool_size: u32,
+    pub timeout_ms: u64,
 }
 
 impl Default for GatewaySmtpConfig {
@@ -238,9 +1574,14 @@ impl Default for GatewaySmtpConfig {
             host: "smtp.example.com".into(),
             port: 587,
             tls: true,
+            implicit_tls: false,
             username: None,
             password: None,
+            password_ref: None,
             rate_limit_per_minute: 120,
+            deliver: false,
+            pool_size: 4,
+            timeout_ms: 30_000,
         }
     }
 }
@@ -251,6 +1592,17 @@ pub struct GatewayImapConfig {
     pub port: u16,
     pub tls: bool,
     pub mailbox: String,
+    pub username: Option<String>,
+    pub password: Option<String>,
+    /// Read `mailbox` on `host`; when off only the in-memory queue is read.
+    pub receive: bool,
+    /// Folder fetched messages are moved to; without one they are flagged `\Seen`.
+    pub move_to: Option<String>,
+    pub timeout_ms: u64,
+    /// Seconds between inbound polls of the supervised gateway worker.
+    pub poll_interval_secs: u64,
+    /// Messages fetched per poll.
+    pub batch_size: usize,
 }
 
 impl Default for GatewayImapConfig {
@@ -260,6 +1612,13 @@ impl Default for GatewayImapConfig {
             port: 993,
             tls: true,
             mailbox: "Inbox".into(),
+            username: None,
+            password: None,
+            receive: false,
+            move_to: None,
+            timeout_ms: 30_000,
+            poll_interval_secs: 60,
+            batch_size: 50,
         }
     }
 }
@@ -268,21 +1627,39 @@ impl Default for GatewayImapConfig {
 pub struct GatewayMappingConfig {
     pub rules: Vec<String>,
     pub allow_list_domains: Vec<String>,
+    /// JSON rule chain with priorities and conditions; replaces `rules` once
+    /// loaded and is re-read when it changes.
+    pub file: Option<String>,
+    pub reload_interval_secs: u64,
 }
 
 impl Default for GatewayMappingConfig {
     fn default() -> Self {
         Self {
-            rules: vec!["{G}.{S}@{O}.{C}.example".into()],
+            rules: vec![
+                "{G}.{S}@{O}.{C}.example".into(),
+                "{S}@{O}.{C}.example".into(),
+            ],
             allow_list_domains: vec!["example.com".into()],
+            file: None,
+            reload_interval_secs: 30,
         }
     }
 }
 
 #[derive(Clone, Debug, PartialEq, Eq)]
 pub struct GatewaySecurityConfig {
+    /// Require TLS for every domain; when off only `tls_domains` need it.
     pub enforce_tls: bool,
+    /// Domains outbound mail may go to; `*.example.com` admits subdomains.
     pub domain_allow_list: Vec<String>,
+    /// Domains refused even when an allow entry matches them.
+    pub domain_deny_list: Vec<String>,
+    /// Domains that must be reached over TLS.
+    pub tls_domains: Vec<String>,
+    /// Gateway crossings (`X-X400-Gateway-Hops`) after which a message is
+    /// taken to be looping and bounced.
+    pub max_hops: u32,
 }
 
 impl Default for GatewaySecurityConfig {
@@ -290,6 +1667,9 @@ impl Default for GatewaySecurityConfig {
         Self {
             enforce_tls: true,
             domain_allow_list: vec!["example.com".into()],
+            domain_deny_list: Vec::new(),
+            tls_domains: Vec::new(),
+            max_hops: 8,
         }
     }
 }
@@ -307,6 +1687,8 @@ pub struct LdapConfig {
     pub base_dn: String,
     pub bind_dn: Option<String>,
     pub bind_password: Option<String>,
+    /// Secret reference `bind_password` is resolved from.
+    pub bind_password_ref: Option<String>,
     pub filter_person: String,
     pub tls_verify: bool,
 }
@@ -318,6 +1700,7 @@ impl Default for LdapConfig {
             base_dn: "dc=example,dc=com".into(),
             bind_dn: None,
             bind_password: None,
+            bind_password_ref: None,
             filter_person: "(&(objectClass=person)(mail=*))".into(),
             tls_verify: true,
         }
@@ -341,10 +1724,20 @@ impl Default for DirectoryCacheConfig {
 #[derive(Clone, Debug, PartialEq)]
 pub struct TelemetryConfig {
     pub enabled: bool,
+    /// Base URL of an OTLP/HTTP collector; traces go to `/v1/traces` and
+    /// metrics to `/v1/metrics` below it.
     pub endpoint: Option<String>,
     pub local_path: String,
     pub sampling: f32,
     pub retention_days: u16,
+    /// PEM bundle trusted for the collector in addition to the public roots.
+    pub ca_path: Option<String>,
+    /// `key=value` pairs added to the exported resource.
+    pub resource_attributes: Vec<String>,
+    /// Spans sent per request; a full batch is sent without waiting.
+    pub batch_size: usize,
+    pub export_interval_ms: u64,
+    pub timeout_ms: u64,
 }
 
 impl Default for TelemetryConfig {
@@ -355,6 +1748,535 @@ impl Default for TelemetryConfig {
             local_path: "telemetry".into(),
             sampling: 1.0,
             retention_days: 7,
+            ca_path: None,
+            resource_attributes: Vec::new(),
+            batch_size: 512,
+            export_interval_ms: 5000,
+            timeout_ms: 10000,
+        }
+    }
+}
+
+/// Data loss prevention rules applied to outbound content.
+///
+/// A rule is named after its kind and text, e.g. `keyword:payroll` or
+/// `marker:[SECRET]`. `dlp.rules.<rule>.action` and
+/// `dlp.rules.<rule>.exemptSenders` override `dlp.action` and
+/// `dlp.exemptSenders` for that rule.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct DlpConfig {
+    pub enabled: bool,
+    pub keywords: Vec<String>,
+    pub patterns: Vec<String>,
+    pub markers: Vec<String>,
+    pub action: DlpAction,
+    pub exempt_senders: Vec<String>,
+    pub rules: Vec<DlpRuleConfig>,
+}
+
+impl DlpConfig {
+    fn rule(&mut self, name: &str) -> &mut DlpRuleConfig {
+        let index = match self.rules.iter().position(|rule| rule.name == name) {
+            Some(index) => index,
+            None => {
+                self.rules.push(DlpRuleConfig {
+                    name: name.to_string(),
+                    action: None,
+                    exempt_senders: None,
+                });
+                self.rules.len() - 1
+            }
+        };
+        &mut self.rules[index]
+    }
+}
+
+/// Settings of one DLP rule; unset fields fall back to the `dlp.*` defaults.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct DlpRuleConfig {
+    pub name: String,
+    pub action: Option<DlpAction>,
+    pub exempt_senders: Option<Vec<String>>,
+}
+
+impl Default for DlpConfig {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            keywords: Vec::new(),
+            patterns: Vec::new(),
+            markers: vec!["[SECRET]".into(), "[CONFIDENTIAL]".into()],
+            action: DlpAction::Block,
+            exempt_senders: Vec::new(),
+            rules: Vec::new(),
+        }
+    }
+}
+
+/// Delivery settings for report webhooks.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct WebhookConfig {
+    pub max_attempts: u32,
+    pub backoff_ms: u64,
+    pub timeout_ms: u64,
+}
+
+impl Default for WebhookConfig {
+    fn default() -> Self {
+        Self {
+            max_attempts: 5,
+            backoff_ms: 1000,
+            timeout_ms: 10_000,
+        }
+    }
+}
+
+/// Transport selection and where vendor SDK profiles are installed.
+///
+/// `ca_bundle` is the pinned CA set stapled OCSP responses from MTAs must
+/// chain to; `ocsp_responder` is asked when an MTA staples none. With
+/// `enforce_pinset`, MTA chains must contain a CA listed in `fingerprints`
+/// (SHA-256, hex); an empty list learns one, see [`crate::transport::pinset`].
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct TransportConfig {
+    pub mode: String,
+    pub profiles_dir: String,
+    pub default_profile: String,
+    /// Further profiles kept bound alongside `default_profile`, one session
+    /// each.
+    pub profiles: Vec<String>,
+    pub breaker_threshold: u32,
+    pub breaker_cooldown_ms: u64,
+    /// First delay before re-binding a lost P7 session; doubled after each
+    /// failed attempt up to `rebind_max_ms`.
+    pub rebind_initial_ms: u64,
+    pub rebind_max_ms: u64,
+    /// Longest wait for an SDK bind, enforced on the Rust side as well.
+    pub sdk_connect_timeout_ms: u64,
+    /// Longest wait for any other SDK call.
+    pub sdk_operation_timeout_ms: u64,
+    /// SDK calls allowed in the vendor library at once.
+    pub sdk_max_concurrent_calls: usize,
+    /// Bodies larger than this are submitted in parts of this size.
+    pub sdk_chunk_size_bytes: usize,
+    pub ca_bundle: Option<String>,
+    pub ocsp_responder: Option<String>,
+    pub enforce_pinset: bool,
+    pub fingerprints: Vec<String>,
+}
+
+impl Default for TransportConfig {
+    fn default() -> Self {
+        Self {
+            mode: "mock".into(),
+            profiles_dir: "profiles".into(),
+            default_profile: "default".into(),
+            profiles: Vec::new(),
+            breaker_threshold: 5,
+            breaker_cooldown_ms: 30_000,
+            rebind_initial_ms: 1_000,
+            rebind_max_ms: 60_000,
+            sdk_connect_timeout_ms: 10_000,
+            sdk_operation_timeout_ms: 30_000,
+            sdk_max_concurrent_calls: 8,
+            sdk_chunk_size_bytes: 1_048_576,
+            ca_bundle: None,
+            ocsp_responder: None,
+            enforce_pinset: false,
+            fingerprints: Vec::new(),
+        }
+    }
+}
+
+/// API authentication settings.
+///
+/// Named keys come from `security.keys.<name>=<secret>` with optional
+/// `security.scopes.<name>=scope,scope` and `security.roles.<name>=role,role`.
+/// A key with neither scopes nor roles is granted nothing; only the legacy
+/// single `security.apiKey` is unrestricted.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct SecurityConfig {
+    pub require_auth: bool,
+    pub api_key: Option<String>,
+    /// Secret reference `api_key` is resolved from.
+    pub api_key_ref: Option<String>,
+    pub keys: Vec<ApiKeyConfig>,
+    /// Roles granted to mTLS clients, keyed by certificate common name
+    /// (`security.clientCerts.<CN>=role,role`).
+    pub client_certs: Vec<ClientCertConfig>,
+    pub smime: SmimeConfig,
+}
+
+/// S/MIME signing and encryption of submitted messages, and verification
+/// and decryption of received ones.
+///
+/// `certificate_path` and `private_key_path` are PEM files holding the
+/// service's own certificate and key; the certificate is also the one
+/// messages are encrypted to. `trust_store_path` is a PEM bundle of the CA
+/// certificates signatures are verified against. With the `pkcs11` key
+/// source the signing key stays on the token described by `pkcs11` and
+/// `private_key_path` is not read.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct SmimeConfig {
+    pub enabled: bool,
+    pub certificates_dir: String,
+    pub certificate_path: String,
+    pub private_key_path: String,
+    pub trust_store_path: String,
+    pub key_source: SmimeKeySource,
+    pub pkcs11: Pkcs11Config,
+}
+
+/// Where the S/MIME signing key lives.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum SmimeKeySource {
+    #[default]
+    File,
+    Pkcs11,
+}
+
+impl SmimeKeySource {
+    pub fn parse(value: &str) -> Option<Self> {
+        match value.trim().to_ascii_lowercase().as_str() {
+            "file" => Some(Self::File),
+            "pkcs11" => Some(Self::Pkcs11),
+            _ => None,
+        }
+    }
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            Self::File => "file",
+            Self::Pkcs11 => "pkcs11",
+        }
+    }
+}
+
+/// A key on a smartcard or HSM, reached through the token's PKCS#11
+/// `module`. `pin_ref` is the secret reference of the user PIN, e.g.
+/// `keychain:<service>/<account>`.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct Pkcs11Config {
+    pub module: String,
+    pub slot: Option<u64>,
+    pub key_label: Option<String>,
+    pub pin_ref: Option<String>,
+}
+
+impl Default for SmimeConfig {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            certificates_dir: "profiles/certs".into(),
+            certificate_path: "profiles/certs/smime.pem".into(),
+            private_key_path: "profiles/certs/smime.key".into(),
+            trust_store_path: "profiles/certs/smime-trust.pem".into(),
+            key_source: SmimeKeySource::File,
+            pkcs11: Pkcs11Config::default(),
+        }
+    }
+}
+
+impl SecurityConfig {
+    fn named_key(&mut self, name: &str) -> &mut ApiKeyConfig {
+        let index = match self.keys.iter().position(|key| key.name == name) {
+            Some(index) => index,
+            None => {
+                self.keys.push(ApiKeyConfig {
+                    name: name.to_string(),
+                    secret: String::new(),
+                    secret_ref: None,
+                    scopes: Vec::new(),
+                    roles: Vec::new(),
+                });
+                self.keys.len() - 1
+            }
+        };
+        &mut self.keys[index]
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct ApiKeyConfig {
+    pub name: String,
+    pub secret: String,
+    /// Secret reference `secret` is resolved from.
+    pub secret_ref: Option<String>,
+    pub scopes: Vec<String>,
+    pub roles: Vec<Role>,
+}
+
+/// Token-bucket limits applied to every HTTP request.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct RateLimitConfig {
+    pub enabled: bool,
+    pub per_key_per_minute: u32,
+    pub per_ip_per_minute: u32,
+    pub burst: u32,
+}
+
+impl Default for RateLimitConfig {
+    fn default() -> Self {
+        Self {
+            enabled: true,
+            per_key_per_minute: 600,
+            per_ip_per_minute: 300,
+            burst: 60,
+        }
+    }
+}
+
+/// HTTP access log kept apart from application logs, with its own retention.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct AccessLogConfig {
+    pub enabled: bool,
+    pub path: String,
+    pub retention_days: u32,
+    /// Truncate client addresses to their /24 (IPv4) or /48 (IPv6) network.
+    pub anonymize_ip: bool,
+}
+
+impl Default for AccessLogConfig {
+    fn default() -> Self {
+        Self {
+            enabled: true,
+            path: "logs/access".into(),
+            retention_days: 14,
+            anonymize_ip: true,
+        }
+    }
+}
+
+/// Trace entries kept for support bundles.
+///
+/// The newest `buffer_capacity` entries stay in memory. Older ones are
+/// appended to `current.jsonl` under `trace_bundle_path`, which is rotated
+/// once it reaches `max_file_bytes` or `max_file_age_secs`; the newest
+/// `max_files` rotated files are kept. An empty path keeps entries in memory
+/// only.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct TracingConfig {
+    pub trace_bundle_path: String,
+    pub buffer_capacity: usize,
+    pub max_file_bytes: u64,
+    pub max_file_age_secs: u64,
+    pub max_files: usize,
+    /// Store rotated files as ZIP archives.
+    pub compress: bool,
+}
+
+impl Default for TracingConfig {
+    fn default() -> Self {
+        Self {
+            trace_bundle_path: "traces".into(),
+            buffer_capacity: 10_000,
+            max_file_bytes: 8 * 1024 * 1024,
+            max_file_age_secs: 86_400,
+            max_files: 14,
+            compress: true,
+        }
+    }
+}
+
+/// Support bundle handling.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct SupportConfig {
+    pub upload: SupportUploadConfig,
+    pub retention: SupportRetentionConfig,
+}
+
+/// Limits on the support directory, enforced after each stored bundle and
+/// every `interval_secs`; the oldest bundles go first. `0` disables a limit.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct SupportRetentionConfig {
+    pub max_total_bytes: u64,
+    pub max_age_days: u32,
+    pub max_count: usize,
+    pub interval_secs: u64,
+}
+
+impl Default for SupportRetentionConfig {
+    fn default() -> Self {
+        Self {
+            max_total_bytes: 1024 * 1024 * 1024,
+            max_age_days: 30,
+            max_count: 200,
+            interval_secs: 3_600,
+        }
+    }
+}
+
+/// Pushing stored support bundles to a remote intake.
+///
+/// Uploads are off until `url` is set. The bundle is sent with an
+/// `auth_header` whose value, `auth_token`, is resolved from
+/// `auth_token_ref`. Transient failures are retried after `backoff_ms`,
+/// doubling with each attempt, until `max_attempts` is reached.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct SupportUploadConfig {
+    pub url: Option<String>,
+    pub auth_header: String,
+    pub auth_token: Option<String>,
+    /// Secret reference `auth_token` is resolved from.
+    pub auth_token_ref: Option<String>,
+    pub max_attempts: u32,
+    pub backoff_ms: u64,
+    pub timeout_ms: u64,
+    /// How often the upload worker looks for bundles that are due.
+    pub interval_secs: u64,
+}
+
+impl Default for SupportUploadConfig {
+    fn default() -> Self {
+        Self {
+            url: None,
+            auth_header: "Authorization".into(),
+            auth_token: None,
+            auth_token_ref: None,
+            max_attempts: 5,
+            backoff_ms: 30_000,
+            timeout_ms: 60_000,
+            interval_secs: 60,
+        }
+    }
+}
+
+/// Outbound queue scheduling.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct QueueConfig {
+    /// Waiting time after which a queued message is treated as one priority
+    /// level higher, so low-priority mail is not starved by a steady stream of
+    /// urgent submissions.
+    pub aging_ms: u64,
+    /// Most messages accepted by one `TransportSwitch::submit_batch` call.
+    pub batch_limit: usize,
+}
+
+impl Default for QueueConfig {
+    fn default() -> Self {
+        Self {
+            aging_ms: 300_000,
+            batch_limit: 500,
+        }
+    }
+}
+
+/// Mailbox storage limits.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct QuotaConfig {
+    /// Store size above which new messages are refused; 0 disables the quota.
+    pub soft_limit_bytes: u64,
+    /// Larger messages are stored headers-only with the content held at the
+    /// gateway; 0 disables the limit.
+    pub max_message_bytes: u64,
+    /// O/R addresses (`C=..;O=..;S=..`) whose messages are never refused or
+    /// truncated.
+    pub exempt_senders: Vec<String>,
+}
+
+/// Per-folder retention applied by the periodic sweep.
+///
+/// Rules come from `retention.folders.<folder>.maxAgeDays`,
+/// `retention.folders.<folder>.maxCount` and
+/// `retention.folders.<folder>.action` (`archive` or `purge`).
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct RetentionConfig {
+    pub enabled: bool,
+    pub interval_secs: u64,
+    pub rules: Vec<RetentionRule>,
+}
+
+impl Default for RetentionConfig {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            interval_secs: 3_600,
+            rules: Vec::new(),
+        }
+    }
+}
+
+impl RetentionConfig {
+    fn rule(&mut self, folder: &str) -> &mut RetentionRule {
+        let index = match self.rules.iter().position(|rule| rule.folder == folder) {
+            Some(index) => index,
+            None => {
+                self.rules.push(RetentionRule {
+                    folder: folder.to_string(),
+                    max_age_days: None,
+                    max_count: None,
+                    action: RetentionAction::Purge,
+                });
+                self.rules.len() - 1
+            }
+        };
+        &mut self.rules[index]
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct RetentionRule {
+    pub folder: String,
+    /// Messages older than this many days expire.
+    pub max_age_days: Option<u32>,
+    /// Only the newest this many messages are kept.
+    pub max_count: Option<usize>,
+    pub action: RetentionAction,
+}
+
+/// What happens to a message once its folder's retention expires it.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub enum RetentionAction {
+    /// Move to the `archive` folder.
+    Archive,
+    /// Delete the message together with any content held for it.
+    Purge,
+}
+
+impl RetentionAction {
+    pub fn parse(value: &str) -> Option<Self> {
+        match value.trim().to_ascii_lowercase().as_str() {
+            "archive" => Some(Self::Archive),
+            "purge" | "delete" => Some(Self::Purge),
+            _ => None,
         }
     }
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            Self::Archive => "archive",
+            Self::Purge => "purge",
+        }
+    }
+}
+
+/// Background worker supervision.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct SupervisorConfig {
+    /// A worker that has not reported a heartbeat for this long is treated as
+    /// hung and replaced.
+    pub heartbeat_timeout_ms: u64,
+    /// Delay before the first restart; doubles on each consecutive failure.
+    pub restart_backoff_ms: u64,
+    pub max_backoff_ms: u64,
+    /// Consecutive failures after which the supervisor raises an alert.
+    pub escalate_after: u32,
+}
+
+impl Default for SupervisorConfig {
+    fn default() -> Self {
+        Self {
+            heartbeat_timeout_ms: 30_000,
+            restart_backoff_ms: 1_000,
+            max_backoff_ms: 60_000,
+            escalate_after: 5,
+        }
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct ClientCertConfig {
+    pub common_name: String,
+    pub roles: Vec<Role>,
 }
@@ -0,0 +1,492 @@
+use std::io::Read;
+
+use regex::{Regex, RegexBuilder};
+use serde::{Deserialize, Serialize};
+
+use crate::attachments::AttachmentStore;
+use crate::config::DlpConfig;
+use crate::models::{Address, Message};
+
+/// Action taken when a DLP rule matches outbound content.
+///
+/// Variants are ordered by severity so the strongest action wins when several
+/// rules match the same message.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum DlpAction {
+    RequireEncryption,
+    Quarantine,
+    Block,
+}
+
+impl DlpAction {
+    pub fn parse(value: &str) -> Option<Self> {
+        match value.trim().to_ascii_lowercase().as_str() {
+            "block" => Some(Self::Block),
+            "quarantine" => Some(Self::Quarantine),
+            "require-encryption" | "encrypt" => Some(Self::RequireEncryption),
+            _ => None,
+        }
+    }
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            Self::Block => "block",
+            Self::Quarantine => "quarantine",
+            Self::RequireEncryption => "require-encryption",
+        }
+    }
+}
+
+/// Content matcher used by a DLP rule.
+#[derive(Clone, Debug)]
+pub enum DlpMatcher {
+    /// Case-insensitive substring match, compiled once by [`DlpMatcher::keyword`].
+    Keyword(Regex),
+    /// Regular expression evaluated against subject and body.
+    Pattern(Regex),
+    /// Classification marker such as `[SECRET]`, matched case-sensitively.
+    Marker(String),
+}
+
+impl DlpMatcher {
+    pub fn keyword(keyword: &str) -> Self {
+        let regex = RegexBuilder::new(&regex::escape(keyword))
+            .case_insensitive(true)
+            .build()
+            .expect("escaped keyword is a valid pattern");
+        Self::Keyword(regex)
+    }
+
+    fn find<'a>(&self, haystack: &'a str) -> Option<&'a str> {
+        match self {
+            Self::Keyword(regex) | Self::Pattern(regex) => {
+                regex.find(haystack).map(|found| found.as_str())
+            }
+            Self::Marker(marker) => haystack
+                .find(marker.as_str())
+                .map(|start| &haystack[start..start + marker.len()]),
+        }
+    }
+}
+
+/// Sender attributes of an exemption such as `O=Legal` or
+/// `C=DE;O=Legal;S=Counsel`. Every attribute given must equal the sender's,
+/// ignoring case; attributes left out match any sender.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct DlpExemption {
+    pub country: Option<String>,
+    pub organization: Option<String>,
+    pub surname: Option<String>,
+}
+
+impl DlpExemption {
+    /// Parse `C=..;O=..;S=..` with any of the attributes left out. Returns
+    /// `None` for other attributes or a fragment naming none.
+    pub fn parse(fragment: &str) -> Option<Self> {
+        let mut exemption = Self::default();
+        for part in fragment.split(';').filter(|part| !part.trim().is_empty()) {
+            let (key, value) = part.split_once('=')?;
+            let value = Some(value.trim().to_string()).filter(|value| !value.is_empty())?;
+            let slot = match key.trim().to_ascii_uppercase().as_str() {
+                "C" => &mut exemption.country,
+                "O" => &mut exemption.organization,
+                "S" => &mut exemption.surname,
+                _ => return None,
+            };
+            *slot = Some(value);
+        }
+        (exemption != Self::default()).then_some(exemption)
+    }
+
+    fn covers(&self, sender: &Address) -> bool {
+        let matches = |wanted: &Option<String>, actual: &str| {
+            wanted
+                .as_ref()
+                .is_none_or(|wanted| wanted.eq_ignore_ascii_case(actual))
+        };
+        matches(&self.country, &sender.country)
+            && matches(&self.organization, &sender.organization)
+            && matches(&self.surname, &sender.surname)
+    }
+}
+
+/// Named rule combining a matcher, an action, and sender exemptions.
+#[derive(Clone, Debug)]
+pub struct DlpRule {
+    pub name: String,
+    pub matcher: DlpMatcher,
+    pub action: DlpAction,
+    /// Senders that bypass the rule.
+    pub exemptions: Vec<DlpExemption>,
+}
+
+impl DlpRule {
+    pub fn new(name: impl Into<String>, matcher: DlpMatcher, action: DlpAction) -> Self {
+        Self {
+            name: name.into(),
+            matcher,
+            action,
+            exemptions: Vec::new(),
+        }
+    }
+
+    /// Exempt the senders `fragment` describes, see [`DlpExemption::parse`].
+    /// Fragments that do not parse are logged and exempt nobody.
+    pub fn with_exemption(mut self, fragment: &str) -> Self {
+        match DlpExemption::parse(fragment) {
+            Some(exemption) => self.exemptions.push(exemption),
+            None => tracing::warn!(
+                target = "dlp",
+                rule = %self.name,
+                "ignoring unreadable sender exemption {fragment}"
+            ),
+        }
+        self
+    }
+
+    fn is_exempt(&self, sender: &Address) -> bool {
+        self.exemptions
+            .iter()
+            .any(|exemption| exemption.covers(sender))
+    }
+}
+
+/// Evidence describing a single rule match.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct DlpMatch {
+    pub rule: String,
+    pub action: DlpAction,
+    pub field: String,
+    pub excerpt: String,
+}
+
+/// Outcome of scanning a message against the configured rules.
+#[derive(Clone, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct DlpVerdict {
+    pub matches: Vec<DlpMatch>,
+}
+
+impl DlpVerdict {
+    pub fn is_clean(&self) -> bool {
+        self.matches.is_empty()
+    }
+
+    /// Most severe action requested by any matching rule.
+    pub fn action(&self) -> Option<DlpAction> {
+        self.matches.iter().map(|item| item.action).max()
+    }
+}
+
+/// Error returned when outbound content is rejected by DLP policy.
+#[derive(Debug, thiserror::Error, PartialEq, Eq)]
+pub enum DlpError {
+    #[error("message blocked by DLP rule {0}")]
+    Blocked(String),
+    #[error("DLP rule {0} requires encryption which this transport cannot apply")]
+    EncryptionRequired(String),
+}
+
+const MAX_EXCERPT: usize = 64;
+/// Text attachments larger than this are scanned up to this many bytes.
+const MAX_SCANNED_ATTACHMENT: u64 = 1024 * 1024;
+
+/// Rule engine scanning outbound messages before they reach a transport.
+#[derive(Clone, Default)]
+pub struct DlpEngine {
+    rules: Vec<DlpRule>,
+    attachments: Option<AttachmentStore>,
+}
+
+impl DlpEngine {
+    pub fn new(rules: Vec<DlpRule>) -> Self {
+        Self {
+            rules,
+            attachments: None,
+        }
+    }
+
+    /// Where attachment contents are read from; without a store only
+    /// attachment names are scanned.
+    pub fn with_attachments(mut self, attachments: AttachmentStore) -> Self {
+        self.attachments = Some(attachments);
+        self
+    }
+
+    /// Build the rule set described by `dlp.*` configuration keys. Rules
+    /// take `dlp.action` and `dlp.exemptSenders` unless
+    /// `dlp.rules.<rule>.action` or `dlp.rules.<rule>.exemptSenders` name
+    /// their own.
+    pub fn from_config(config: &DlpConfig) -> Self {
+        if !config.enabled {
+            return Self::default();
+        }
+        let configure = |name: String, matcher: DlpMatcher| {
+            let own = config.rules.iter().find(|rule| rule.name == name);
+            let action = own.and_then(|own| own.action).unwrap_or(config.action);
+            let senders = own
+                .and_then(|own| own.exempt_senders.as_ref())
+                .unwrap_or(&config.exempt_senders);
+            senders
+                .iter()
+                .fold(DlpRule::new(name, matcher, action), |rule, sender| {
+                    rule.with_exemption(sender)
+                })
+        };
+        let mut rules = Vec::new();
+        for keyword in &config.keywords {
+            rules.push(configure(
+                format!("keyword:{keyword}"),
+                DlpMatcher::keyword(keyword),
+            ));
+        }
+        for pattern in &config.patterns {
+            match Regex::new(pattern) {
+                Ok(regex) => rules.push(configure(
+                    format!("pattern:{pattern}"),
+                    DlpMatcher::Pattern(regex),
+                )),
+                Err(err) => {
+                    tracing::warn!(target = "dlp", "ignoring invalid pattern {pattern}: {err}")
+                }
+            }
+        }
+        for marker in &config.markers {
+            rules.push(configure(
+                format!("marker:{marker}"),
+                DlpMatcher::Marker(marker.clone()),
+            ));
+        }
+        for own in &config.rules {
+            if !rules.iter().any(|rule| rule.name == own.name) {
+                tracing::warn!(
+                    target = "dlp",
+                    "dlp.rules.{} names no configured rule",
+                    own.name
+                );
+            }
+        }
+        Self {
+            rules,
+            attachments: None,
+        }
+    }
+
+    pub fn rules(&self) -> &[DlpRule] {
+        &self.rules
+    }
+
+    /// Match every rule against the subject, the body, attachment file
+    /// names and the text of `text/*` attachments, reporting the first field
+    /// each rule matches.
+    pub fn scan(&self, message: &Message) -> DlpVerdict {
+        let mut fields = vec![
+            ("subject".to_string(), message.envelope.subject.clone()),
+            ("body".to_string(), message.content.body.clone()),
+        ];
+        if !self.rules.is_empty() {
+            for attachment in &message.content.attachments {
+                let field = format!("attachment:{}", attachment.filename);
+                fields.push((format!("{field}:name"), attachment.filename.clone()));
+                if attachment.mime_type.starts_with("text/") {
+                    if let Some(text) = self.attachment_text(&attachment.id) {
+                        fields.push((field, text));
+                    }
+                }
+            }
+        }
+        let mut matches = Vec::new();
+        for rule in &self.rules {
+            if rule.is_exempt(&message.envelope.sender) {
+                continue;
+            }
+            for (field, value) in &fields {
+                if let Some(found) = rule.matcher.find(value) {
+                    matches.push(DlpMatch {
+                        rule: rule.name.clone(),
+                        action: rule.action,
+                        field: field.clone(),
+                        excerpt: found.chars().take(MAX_EXCERPT).collect(),
+                    });
+                    break;
+                }
+            }
+        }
+        DlpVerdict { matches }
+    }
+
+    fn attachment_text(&self, id: &str) -> Option<String> {
+        let stream = match self.attachments.as_ref()?.open(id, None) {
+            Ok(stream) => stream,
+            Err(err) => {
+                tracing::warn!(
+                    target = "dlp",
+                    attachment = id,
+                    "attachment not scanned: {err}"
+                );
+                return None;
+            }
+        };
+        let mut bytes = Vec::new();
+        if let Err(err) = stream.take(MAX_SCANNED_ATTACHMENT).read_to_end(&mut bytes) {
+            tracing::warn!(
+                target = "dlp",
+                attachment = id,
+                "attachment not scanned: {err}"
+            );
+            return None;
+        }
+        Some(String::from_utf8_lossy(&bytes).into_owned())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::models::{MessageAttachment, MessageContent, MessageEnvelope};
+
+    fn message(subject: &str, body: &str) -> Message {
+        Message {
+            envelope: MessageEnvelope::new(subject, Address::sample(), vec![Address::sample()]),
+            content: MessageContent {
+                body: body.into(),
+                attachments: Vec::new(),
+            },
+        }
+    }
+
+    #[test]
+    fn strongest_action_wins() {
+        let engine = DlpEngine::new(vec![
+            DlpRule::new(
+                "iban",
+                DlpMatcher::Pattern(Regex::new(r"DE\d{20}").unwrap()),
+                DlpAction::Quarantine,
+            ),
+            DlpRule::new(
+                "secret",
+                DlpMatcher::Marker("[SECRET]".into()),
+                DlpAction::Block,
+            ),
+        ]);
+        let verdict = engine.scan(&message(
+            "[SECRET] payroll",
+            "Account DE12345678901234567890",
+        ));
+        assert_eq!(verdict.matches.len(), 2);
+        assert_eq!(verdict.action(), Some(DlpAction::Block));
+        assert_eq!(verdict.matches[0].excerpt, "DE12345678901234567890");
+        assert_eq!(verdict.matches[1].field, "subject");
+    }
+
+    #[test]
+    fn exempt_senders_skip_rule() {
+        let engine = DlpEngine::new(vec![DlpRule::new(
+            "confidential",
+            DlpMatcher::keyword("confidential"),
+            DlpAction::Block,
+        )
+        .with_exemption("O=Modern")]);
+        let verdict = engine.scan(&message("Status", "Confidential figures"));
+        assert!(verdict.is_clean());
+
+        for fragment in [
+            "O=Mod",
+            "C=DE;O=Modern;S=Oper",
+            "O=Modern Legal",
+            "OU=Modern",
+        ] {
+            let engine = DlpEngine::new(vec![DlpRule::new(
+                "confidential",
+                DlpMatcher::keyword("confidential"),
+                DlpAction::Block,
+            )
+            .with_exemption(fragment)]);
+            let verdict = engine.scan(&message("Status", "Confidential figures"));
+            assert!(!verdict.is_clean(), "{fragment} exempted the sender");
+        }
+        assert_eq!(
+            DlpExemption::parse("s=operator; c=de"),
+            Some(DlpExemption {
+                country: Some("de".into()),
+                organization: None,
+                surname: Some("operator".into()),
+            })
+        );
+        assert!(DlpExemption::parse("s=operator; c=de")
+            .unwrap()
+            .covers(&Address::sample()));
+    }
+
+    #[test]
+    fn rules_take_their_own_action_and_exemptions() {
+        let mut config = crate::config::AppConfig::default();
+        for (key, value) in [
+            ("dlp.enabled", "true"),
+            ("dlp.keywords", "payroll,salary"),
+            ("dlp.markers", ""),
+            ("dlp.exemptSenders", "O=Modern"),
+            ("dlp.rules.keyword:payroll.action", "quarantine"),
+            ("dlp.rules.keyword:payroll.exemptSenders", "O=Legal"),
+        ] {
+            config.apply(key, value).unwrap();
+        }
+        assert!(config
+            .apply("dlp.rules.keyword:payroll.colour", "red")
+            .is_err());
+        let engine = DlpEngine::from_config(&config.dlp);
+
+        let verdict = engine.scan(&message("Payroll and salary", ""));
+        assert_eq!(verdict.matches.len(), 1);
+        assert_eq!(verdict.matches[0].rule, "keyword:payroll");
+        assert_eq!(verdict.action(), Some(DlpAction::Quarantine));
+    }
+
+    #[test]
+    fn scans_attachment_names_and_text() {
+        let temp = tempfile::tempdir().unwrap();
+        let store = AttachmentStore::new(temp.path());
+        let notes = store
+            .put("notes.txt", &b"Quarterly [SECRET] figures"[..])
+            .unwrap();
+        let image = store.put("chart.png", &b"[SECRET]"[..]).unwrap();
+        let engine = DlpEngine::new(vec![
+            DlpRule::new(
+                "secret",
+                DlpMatcher::Marker("[SECRET]".into()),
+                DlpAction::Block,
+            ),
+            DlpRule::new(
+                "payroll",
+                DlpMatcher::keyword("payroll"),
+                DlpAction::Quarantine,
+            ),
+        ])
+        .with_attachments(store);
+        let attach = |id: &str, filename: &str, mime_type: &str| MessageAttachment {
+            id: id.into(),
+            filename: filename.into(),
+            mime_type: mime_type.into(),
+            size: 0,
+        };
+
+        let mut outbound = message("Status", "See attached");
+        outbound.content.attachments = vec![
+            attach(&image.id, "chart.png", "image/png"),
+            attach(&notes.id, "payroll.txt", "text/plain"),
+        ];
+        let verdict = engine.scan(&outbound);
+        let fields: Vec<_> = verdict
+            .matches
+            .iter()
+            .map(|found| (found.rule.as_str(), found.field.as_str()))
+            .collect();
+        assert_eq!(
+            fields,
+            [
+                ("secret", "attachment:payroll.txt"),
+                ("payroll", "attachment:payroll.txt:name"),
+            ]
+        );
+    }
+}
@@ -0,0 +1,337 @@
+//! Server-side drafts for the composer.
+//!
+//! `POST /drafts` creates a message in the `drafts` folder and every
+//! `PUT /drafts/{id}` autosave records a revision, so work in progress lives
+//! in the core service instead of the UI's local storage. A save may name the
+//! revision it was based on; if another window saved in between the save is
+//! refused rather than silently overwriting it. `POST /drafts/{id}/send`
+//! submits the draft under its existing message id.
+
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use thiserror::Error;
+
+use crate::clock::{SharedClock, SharedIds};
+use crate::mock_provider::MockDeliveryProvider;
+use crate::models::{
+    Address, Message, MessageContent, MessageEnvelope, MessageId, MessagePriority, MessageStatus,
+};
+use crate::store::StoreManager;
+use crate::transport::{MessageTransport, TransportError, TransportSwitch};
+
+/// Autosave revisions kept per draft; older ones are dropped.
+const MAX_REVISIONS: usize = 50;
+
+const DRAFTS_FOLDER: &str = "drafts";
+
+#[derive(Debug, Error, PartialEq, Eq)]
+pub enum DraftError {
+    #[error("draft {0} not found")]
+    NotFound(MessageId),
+    #[error("draft is at revision {current}, but the update was based on revision {base}")]
+    Conflict { current: u32, base: u32 },
+    #[error("draft has no recipients")]
+    NoRecipients,
+    #[error(transparent)]
+    Rejected(#[from] TransportError),
+}
+
+impl DraftError {
+    pub fn status(&self) -> u16 {
+        match self {
+            Self::NotFound(_) => 404,
+            Self::Conflict { .. } => 409,
+            Self::NoRecipients => 422,
+            Self::Rejected(err) => err.status(),
+        }
+    }
+}
+
+/// Editable part of a draft, as sent by `POST /drafts` and `PUT /drafts/{id}`.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct DraftContent {
+    pub subject: String,
+    pub sender: Address,
+    pub recipients: Vec<Address>,
+    pub body: String,
+    pub priority: MessagePriority,
+}
+
+/// A draft with its current revision.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Draft {
+    pub message: Message,
+    pub revision: u32,
+    pub updated_at: DateTime<Utc>,
+}
+
+/// One autosave, as listed by `GET /drafts/{id}/revisions`.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct DraftRevision {
+    pub revision: u32,
+    pub saved_at: DateTime<Utc>,
+    pub subject: String,
+    pub body: String,
+}
+
+#[derive(Clone)]
+pub struct DraftManager {
+    store: StoreManager,
+    revisions: Arc<Mutex<HashMap<MessageId, Vec<DraftRevision>>>>,
+    ids: SharedIds,
+    clock: SharedClock,
+}
+
+impl DraftManager {
+    pub fn new(store: StoreManager) -> Self {
+        Self {
+            store,
+            revisions: Arc::new(Mutex::new(HashMap::new())),
+            ids: SharedIds::default(),
+            clock: SharedClock::default(),
+        }
+    }
+
+    pub fn with_ids(mut self, ids: SharedIds) -> Self {
+        self.ids = ids;
+        self
+    }
+
+    pub fn with_clock(mut self, clock: SharedClock) -> Self {
+        self.clock = clock;
+        self
+    }
+
+    pub fn create(&self, content: DraftContent) -> Draft {
+        let mut envelope = MessageEnvelope::stamped(
+            &content.subject,
+            content.sender.clone(),
+            content.recipients.clone(),
+            &self.ids,
+            &self.clock,
+        );
+        envelope.folder = DRAFTS_FOLDER.into();
+        envelope.status = MessageStatus::Draft;
+        envelope.priority = content.priority;
+        let message = Message {
+            envelope,
+            content: MessageContent {
+                body: content.body,
+                attachments: Vec::new(),
+            },
+        };
+        self.save_revision(message, 1)
+    }
+
+    /// Autosave new content. With `base_revision` set, the save fails if the
+    /// draft has moved on since that revision.
+    pub fn update(
+        &self,
+        id: &MessageId,
+        content: DraftContent,
+        base_revision: Option<u32>,
+    ) -> Result<Draft, DraftError> {
+        let draft = self
+            .get(id)
+            .ok_or_else(|| DraftError::NotFound(id.clone()))?;
+        if let Some(base) = base_revision.filter(|base| *base != draft.revision) {
+            return Err(DraftError::Conflict {
+                current: draft.revision,
+                base,
+            });
+        }
+        let mut message = draft.message;
+        message.envelope.subject = content.subject;
+        message.envelope.sender = content.sender;
+        message.envelope.recipients = content.recipients;
+        message.envelope.priority = content.priority;
+        message.content.body = content.body;
+        Ok(self.save_revision(message, draft.revision + 1))
+    }
+
+    pub fn get(&self, id: &MessageId) -> Option<Draft> {
+        let message = self
+            .store
+            .get(id)
+            .filter(|message| message.envelope.status == MessageStatus::Draft)?;
+        let latest = self
+            .revisions
+            .lock()
+            .ok()?
+            .get(id)
+            .and_then(|history| history.last().cloned());
+        Some(match latest {
+            Some(latest) => Draft {
+                message,
+                revision: latest.revision,
+                updated_at: latest.saved_at,
+            },
+            // Withdrawn scheduled messages land in drafts without history.
+            None => Draft {
+                updated_at: message.envelope.created_at,
+                message,
+                revision: 0,
+            },
+        })
+    }
+
+    /// Autosave history, oldest first.
+    pub fn revisions(&self, id: &MessageId) -> Vec<DraftRevision> {
+        self.revisions
+            .lock()
+            .ok()
+            .and_then(|revisions| revisions.get(id).cloned())
+            .unwrap_or_default()
+    }
+
+    /// Submit the draft through `transport`, keeping its message id. A draft
+    /// the transport rejects stays in `drafts` untouched.
+    ///
+    /// With a send-at time still ahead the draft passes the same screening
+    /// ([`TransportSwitch::admit`]) and is then parked with `scheduler` until
+    /// it is due.
+    pub fn send(
+        &self,
+        id: &MessageId,
+        transport: &TransportSwitch,
+        scheduler: &MockDeliveryProvider,
+        deferred_until: Option<DateTime<Utc>>,
+    ) -> Result<MessageId, DraftError> {
+        let draft = self
+            .get(id)
+            .ok_or_else(|| DraftError::NotFound(id.clone()))?;
+        if draft.message.envelope.recipients.is_empty() {
+            return Err(DraftError::NoRecipients);
+        }
+        let mut message = draft.message;
+        message.envelope.folder = "outbox".into();
+        message.envelope.status = MessageStatus::Queued;
+        let sent = match deferred_until.filter(|until| *until > self.clock.now()) {
+            Some(until) => match transport.admit(&mut message)? {
+                Some(quarantined) => quarantined,
+                None => scheduler.schedule(message, until),
+            },
+            None => transport.submit(message)?,
+        };
+        if let Ok(mut revisions) = self.revisions.lock() {
+            revisions.remove(id);
+        }
+        Ok(sent)
+    }
+
+    fn save_revision(&self, message: Message, revision: u32) -> Draft {
+        let saved_at = self.clock.now();
+        let entry = DraftRevision {
+            revision,
+            saved_at,
+            subject: message.envelope.subject.clone(),
+            body: message.content.body.clone(),
+        };
+        if let Ok(mut revisions) = self.revisions.lock() {
+            let history = revisions.entry(message.envelope.id.clone()).or_default();
+            if history.len() == MAX_REVISIONS {
+                history.remove(0);
+            }
+            history.push(entry);
+        }
+        self.store.save(message.clone());
+        Draft {
+            message,
+            revision,
+            updated_at: saved_at,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::clock::SequentialIds;
+    use crate::queue::QueueManager;
+    use crate::trace::TraceManager;
+    use crate::transport::p7_driver::{P7Driver, UnloadedSdk};
+    use std::sync::Arc;
+
+    fn content(subject: &str, body: &str, recipients: Vec<Address>) -> DraftContent {
+        DraftContent {
+            subject: subject.into(),
+            sender: Address::sample(),
+            recipients,
+            body: body.into(),
+            priority: MessagePriority::Normal,
+        }
+    }
+
+    #[test]
+    fn autosaves_revisions_and_sends_under_the_draft_id() {
+        let store = StoreManager::new();
+        let drafts =
+            DraftManager::new(store.clone()).with_ids(SharedIds::new(SequentialIds::default()));
+        let provider = MockDeliveryProvider::new(
+            QueueManager::new().with_store(store.clone()),
+            store.clone(),
+            TraceManager::new(),
+        );
+        let transport = TransportSwitch::new(
+            "mock",
+            Arc::new(provider.clone()),
+            P7Driver::new(Arc::new(UnloadedSdk), "ops"),
+            Arc::new(provider.clone()),
+        );
+
+        let draft = drafts.create(content("Manifest", "Cargo:", Vec::new()));
+        let id = draft.message.envelope.id.clone();
+        assert_eq!(draft.revision, 1);
+        assert_eq!(store.list("drafts").len(), 1);
+
+        let saved = drafts
+            .update(
+                &id,
+                content("Manifest", "Cargo: steel", Vec::new()),
+                Some(1),
+            )
+            .unwrap();
+        assert_eq!(saved.revision, 2);
+        assert_eq!(
+            drafts.update(&id, content("Manifest", "Cargo: tin", Vec::new()), Some(1)),
+            Err(DraftError::Conflict {
+                current: 2,
+                base: 1
+            })
+        );
+        assert_eq!(
+            drafts.send(&id, &transport, &provider, None),
+            Err(DraftError::NoRecipients)
+        );
+
+        drafts
+            .update(
+                &id,
+                content("Manifest", "Cargo: steel coils", vec![Address::sample()]),
+                None,
+            )
+            .unwrap();
+        let bodies: Vec<String> = drafts
+            .revisions(&id)
+            .into_iter()
+            .map(|revision| revision.body)
+            .collect();
+        assert_eq!(bodies, ["Cargo:", "Cargo: steel", "Cargo: steel coils"]);
+
+        assert_eq!(
+            drafts.send(&id, &transport, &provider, None),
+            Ok(id.clone())
+        );
+        let sent = store.get(&id).unwrap();
+        assert_eq!(sent.envelope.folder, "outbox");
+        assert_eq!(sent.content.body, "Cargo: steel coils");
+        assert!(store.list("drafts").is_empty());
+        assert_eq!(
+            drafts.send(&id, &transport, &provider, None),
+            Err(DraftError::NotFound(id))
+        );
+    }
+}
@@ -0,0 +1,204 @@
+//! Graceful drain before shutdown.
+//!
+//! `main` blocks SIGTERM and SIGINT with [`ShutdownSignals::block`] before any
+//! thread starts and waits for them on a dedicated thread, which then calls
+//! [`AppState::shutdown`](crate::AppState::shutdown). From
+//! [`DrainController::begin`] on, new submissions are refused with
+//! `503 Service Unavailable`. [`drain`] hands queued messages to the
+//! transport until the queue is empty or the drain timeout
+//! (`server.drainTimeoutMs`) expires, and writes whatever is left to the
+//! queue snapshot; those messages also stay in the queue table, so the next
+//! start picks them up.
+//!
+//! [`drain`]: DrainController::drain
+
+use std::io;
+use std::path::Path;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+use serde::Serialize;
+use thiserror::Error;
+use tracing::{info, warn};
+
+use crate::config::ServerConfig;
+use crate::models::MessageId;
+use crate::queue::QueueManager;
+
+#[derive(Debug, Error, PartialEq, Eq)]
+#[error("service is draining for shutdown; submissions are not accepted")]
+pub struct Draining;
+
+impl Draining {
+    pub fn status(&self) -> u16 {
+        503
+    }
+}
+
+/// Outcome of a drain, logged on exit.
+#[derive(Clone, Debug, Default, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct DrainReport {
+    pub flushed: usize,
+    pub failed: usize,
+    pub persisted: usize,
+    pub timed_out: bool,
+}
+
+#[derive(Clone)]
+pub struct DrainController {
+    draining: Arc<AtomicBool>,
+    timeout: Duration,
+}
+
+impl DrainController {
+    pub fn new(timeout: Duration) -> Self {
+        Self {
+            draining: Arc::new(AtomicBool::new(false)),
+            timeout,
+        }
+    }
+
+    pub fn from_config(config: &ServerConfig) -> Self {
+        Self::new(Duration::from_millis(config.drain_timeout_ms))
+    }
+
+    pub fn begin(&self) {
+        if !self.draining.swap(true, Ordering::SeqCst) {
+            info!(target = "drain", "drain started; refusing new submissions");
+        }
+    }
+
+    pub fn is_draining(&self) -> bool {
+        self.draining.load(Ordering::SeqCst)
+    }
+
+    /// Gate for `/submit` and channel submissions.
+    pub fn admit_submit(&self) -> Result<(), Draining> {
+        if self.is_draining() {
+            Err(Draining)
+        } else {
+            Ok(())
+        }
+    }
+
+    /// Flush `queue` through `deliver`, then persist the remainder to `snapshot`.
+    ///
+    /// Messages the transport rejects are put back rather than dropped, so
+    /// they end up in the snapshot alongside anything the timeout cut off.
+    pub fn drain(
+        &self,
+        queue: &QueueManager,
+        snapshot: &Path,
+        mut deliver: impl FnMut(&MessageId) -> bool,
+    ) -> io::Result<DrainReport> {
+        self.begin();
+        let deadline = Instant::now() + self.timeout;
+        let mut report = DrainReport::default();
+        let mut rejected = Vec::new();
+        loop {
+            if Instant::now() >= deadline {
+                report.timed_out = !queue.pending().is_empty();
+                break;
+            }
+            let Some(id) = queue.dequeue() else {
+                break;
+            };
+            if deliver(&id) {
+                queue.complete(&id);
+                report.flushed += 1;
+            } else {
+                report.failed += 1;
+                rejected.push(id);
+            }
+        }
+        queue.seed(rejected);
+        report.persisted = queue.snapshot_to(snapshot)?;
+        if report.timed_out {
+            warn!(
+                target = "drain",
+                persisted = report.persisted,
+                "drain timed out; remaining messages persisted"
+            );
+        } else {
+            info!(
+                target = "drain",
+                flushed = report.flushed,
+                persisted = report.persisted,
+                "drain complete"
+            );
+        }
+        Ok(report)
+    }
+}
+
+/// SIGTERM and SIGINT, held back from every thread so one thread can wait
+/// for them with [`ShutdownSignals::wait`].
+#[cfg(unix)]
+pub struct ShutdownSignals {
+    set: libc::sigset_t,
+}
+
+#[cfg(unix)]
+impl ShutdownSignals {
+    /// Block the signals for the calling thread and every thread it starts
+    /// afterwards. Call this first thing in `main`.
+    pub fn block() -> Self {
+        // SAFETY: `set` is initialised by `sigemptyset` before use, and the
+        // mask only changes how the signals are delivered, not memory.
+        unsafe {
+            let mut set = std::mem::zeroed::<libc::sigset_t>();
+            libc::sigemptyset(&mut set);
+            libc::sigaddset(&mut set, libc::SIGTERM);
+            libc::sigaddset(&mut set, libc::SIGINT);
+            libc::pthread_sigmask(libc::SIG_BLOCK, &set, std::ptr::null_mut());
+            Self { set }
+        }
+    }
+
+    /// Wait until one of the signals arrives and return its number.
+    pub fn wait(&self) -> i32 {
+        let mut signal = 0;
+        // SAFETY: `set` was built in `block` and `signal` outlives the call.
+        unsafe { libc::sigwait(&self.set, &mut signal) };
+        signal
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn refuses_submissions_and_persists_leftovers() {
+        let dir = tempfile::tempdir().unwrap();
+        let snapshot = dir.path().join("queue.json");
+        let queue = QueueManager::new();
+        queue.seed(vec![
+            MessageId("msg-1".into()),
+            MessageId("msg-2".into()),
+            MessageId("msg-3".into()),
+        ]);
+
+        let drain = DrainController::new(Duration::from_secs(5));
+        assert!(drain.admit_submit().is_ok());
+        let report = drain
+            .drain(&queue, &snapshot, |id| id.0 != "msg-2")
+            .unwrap();
+        assert_eq!(drain.admit_submit(), Err(Draining));
+        assert_eq!(report.flushed, 2);
+        assert_eq!(report.failed, 1);
+        assert_eq!(report.persisted, 1);
+        assert!(!report.timed_out);
+
+        let restored = QueueManager::new();
+        assert_eq!(restored.restore_from(&snapshot).unwrap(), 1);
+        assert_eq!(restored.pending(), vec![MessageId("msg-2".into())]);
+
+        let stalled = DrainController::new(Duration::ZERO);
+        let report = stalled.drain(&restored, &snapshot, |_| true).unwrap();
+        assert!(report.timed_out);
+        assert_eq!(report.persisted, 1);
+    }
+}
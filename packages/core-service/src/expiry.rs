@@ -0,0 +1,224 @@
+//! Latest-delivery-time enforcement.
+//!
+//! X.400 lets the originator set a latest delivery time; once it passes, the
+//! MTS gives up and returns a non-delivery report with reason
+//! `unable-to-transfer` and diagnostic `maximum-time-expired`. The supervised
+//! `expiry` worker calls [`ExpirySweeper::sweep`] every [`SWEEP_INTERVAL`] to
+//! do the same for messages still waiting in our queue.
+
+use std::thread;
+use std::time::Duration;
+
+use tracing::warn;
+
+use crate::clock::SharedClock;
+use crate::models::{MessageId, MessageStatus, Report, ReportKind};
+use crate::queue::QueueManager;
+use crate::reports::ReportIngestor;
+use crate::store::StoreManager;
+use crate::supervisor::Supervisor;
+use crate::telemetry::TelemetryManager;
+
+/// Folder expired messages are moved to.
+pub const FAILED_FOLDER: &str = "failed";
+
+/// How often the `expiry` worker sweeps the queue.
+pub const SWEEP_INTERVAL: Duration = Duration::from_secs(30);
+
+#[derive(Clone)]
+pub struct ExpirySweeper {
+    queue: QueueManager,
+    store: StoreManager,
+    reports: ReportIngestor,
+    telemetry: Option<TelemetryManager>,
+    clock: SharedClock,
+}
+
+impl ExpirySweeper {
+    pub fn new(queue: QueueManager, store: StoreManager, reports: ReportIngestor) -> Self {
+        Self {
+            queue,
+            store,
+            reports,
+            telemetry: None,
+            clock: SharedClock::default(),
+        }
+    }
+
+    pub fn with_telemetry(mut self, telemetry: TelemetryManager) -> Self {
+        self.telemetry = Some(telemetry);
+        self
+    }
+
+    pub fn with_clock(mut self, clock: SharedClock) -> Self {
+        self.clock = clock;
+        self
+    }
+
+    /// Run [`sweep`](Self::sweep) every [`SWEEP_INTERVAL`] as the supervised
+    /// `expiry` worker.
+    pub fn spawn(&self, supervisor: &Supervisor) {
+        let sweeper = self.clone();
+        supervisor.spawn("expiry", move |context| {
+            while !context.should_stop() {
+                sweeper.sweep();
+                let mut waited = Duration::ZERO;
+                while waited < SWEEP_INTERVAL && !context.should_stop() {
+                    context.heartbeat();
+                    let step = (SWEEP_INTERVAL - waited).min(Duration::from_secs(1));
+                    thread::sleep(step);
+                    waited += step;
+                }
+            }
+        });
+    }
+
+    /// Fail every queued message whose latest delivery time has passed,
+    /// returning the ids that were expired.
+    pub fn sweep(&self) -> Vec<MessageId> {
+        let now = self.clock.now();
+        let mut expired = Vec::new();
+        for queued in self.queue.order() {
+            let id = queued.message_id;
+            let Some(message) = self.store.get(&id) else {
+                continue;
+            };
+            // Delivered or failed already; the entry is stale, not expired.
+            if message.envelope.status != MessageStatus::Queued {
+                self.queue.cancel(&id);
+                continue;
+            }
+            if message
+                .envelope
+                .latest_delivery
+                .is_none_or(|latest| latest > now)
+            {
+                continue;
+            }
+            // Lost the race with the transport; the message is no longer ours to fail.
+            if !self.queue.cancel(&id) {
+                continue;
+            }
+            self.fail(id.clone(), message.envelope.recipients.len());
+            expired.push(id);
+        }
+        expired
+    }
+
+    fn fail(&self, id: MessageId, recipients: usize) {
+        let report = Report {
+            message_id: id.clone(),
+            kind: ReportKind::NonDelivery,
+            recipient: None,
+            reason: Some("unable-to-transfer".into()),
+            diagnostic: Some("maximum-time-expired".into()),
+            timestamp: self.clock.now(),
+        };
+        if let Err(err) = self.reports.ingest(report) {
+            warn!(target = "expiry", message = %id, "cannot record expiry report: {err}");
+        }
+        if let Some(mut message) = self.store.get(&id) {
+            message.envelope.folder = FAILED_FOLDER.into();
+            self.store.save(message);
+        }
+        warn!(
+            target = "expiry",
+            message = %id,
+            recipients,
+            "latest delivery time passed; message failed"
+        );
+        if let Some(telemetry) = &self.telemetry {
+            telemetry.record_flow(
+                "queue.expired",
+                Duration::from_millis(0),
+                false,
+                self.queue.pending().len(),
+            );
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::clock::ManualClock;
+    use crate::models::{Address, Message, MessageContent, MessageEnvelope, MessageStatus};
+    use crate::trace::TraceManager;
+    use chrono::{TimeZone, Utc};
+
+    #[test]
+    fn expired_messages_get_an_ndr_and_move_to_failed() {
+        let start = Utc.with_ymd_and_hms(2024, 8, 5, 10, 0, 0).unwrap();
+        let clock = ManualClock::new(start);
+        let shared = SharedClock::new(clock.clone());
+        let queue = QueueManager::new().with_clock(shared.clone());
+        let store = StoreManager::new();
+        let reports = ReportIngestor::new(store.clone(), TraceManager::new());
+        let sweeper = ExpirySweeper::new(queue.clone(), store.clone(), reports).with_clock(shared);
+
+        let submit = |subject: &str, latest: Option<chrono::Duration>| {
+            let mut envelope =
+                MessageEnvelope::new(subject, Address::sample(), vec![Address::sample()]);
+            envelope.latest_delivery = latest.map(|after| start + after);
+            let id = envelope.id.clone();
+            store.save(Message {
+                envelope,
+                content: MessageContent {
+                    body: String::new(),
+                    attachments: Vec::new(),
+                },
+            });
+            queue.enqueue(id.clone());
+            id
+        };
+        let urgent = submit("Tender deadline", Some(chrono::Duration::minutes(30)));
+        let relaxed = submit("Newsletter", Some(chrono::Duration::days(1)));
+        let unbounded = submit("Archive", None);
+
+        assert!(sweeper.sweep().is_empty());
+        clock.advance(chrono::Duration::hours(1));
+        assert_eq!(sweeper.sweep(), vec![urgent.clone()]);
+
+        let detail = store.detail(&urgent).unwrap();
+        assert_eq!(detail.message.envelope.folder, FAILED_FOLDER);
+        assert_eq!(detail.message.envelope.status, MessageStatus::Failed);
+        assert_eq!(detail.reports[0].kind, ReportKind::NonDelivery);
+        assert_eq!(
+            detail.reports[0].diagnostic.as_deref(),
+            Some("maximum-time-expired")
+        );
+        assert_eq!(queue.pending(), vec![relaxed, unbounded]);
+        assert!(sweeper.sweep().is_empty(), "expired only once");
+    }
+
+    #[test]
+    fn delivered_messages_with_a_stale_queue_entry_are_not_failed() {
+        let start = Utc.with_ymd_and_hms(2024, 8, 5, 10, 0, 0).unwrap();
+        let clock = ManualClock::new(start);
+        let shared = SharedClock::new(clock.clone());
+        let queue = QueueManager::new().with_clock(shared.clone());
+        let store = StoreManager::new();
+        let reports = ReportIngestor::new(store.clone(), TraceManager::new());
+        let sweeper = ExpirySweeper::new(queue.clone(), store.clone(), reports).with_clock(shared);
+
+        let mut envelope = MessageEnvelope::new("Read", Address::sample(), vec![Address::sample()]);
+        envelope.latest_delivery = Some(start + chrono::Duration::minutes(30));
+        envelope.status = MessageStatus::Read;
+        let id = envelope.id.clone();
+        store.save(Message {
+            envelope,
+            content: MessageContent {
+                body: String::new(),
+                attachments: Vec::new(),
+            },
+        });
+        queue.enqueue(id.clone());
+
+        clock.advance(chrono::Duration::hours(1));
+        assert!(sweeper.sweep().is_empty());
+        assert!(queue.pending().is_empty(), "the stale entry is dropped");
+        let detail = store.detail(&id).unwrap();
+        assert_eq!(detail.message.envelope.status, MessageStatus::Read);
+        assert!(detail.reports.is_empty());
+    }
+}
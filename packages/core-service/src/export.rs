@@ -0,0 +1,459 @@
+//! `POST /export` and `POST /import`: portable mailbox archives.
+//!
+//! An export is a ZIP with one interchange document and one RFC 822 rendering
+//! per message under `messages/<folder>/`, the content held back by the quota
+//! under `attachments/<sha256>`, and a `manifest.json` listing the SHA-256 of
+//! every other entry. Bodies are written in full, so the archive stands on its
+//! own for legal discovery or offline backup. Import checks every checksum
+//! before touching the store and skips messages that are already present.
+
+use std::collections::{BTreeMap, BTreeSet};
+use std::io::{self, Cursor, Read, Seek, Write};
+
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use thiserror::Error;
+use tracing::{info, warn};
+use zip::read::ZipArchive;
+use zip::result::ZipError;
+use zip::write::FileOptions;
+
+use crate::clock::{SharedClock, SharedIds};
+use crate::gateway::inbound::{encode_quoted_printable, encode_word};
+use crate::gateway::AddressMapper;
+use crate::interchange::{self, InterchangeError};
+use crate::models::{Address, Message};
+use crate::quota::QuotaPolicy;
+use crate::store::StoreManager;
+
+const MANIFEST: &str = "manifest.json";
+const MANIFEST_VERSION: &str = "1";
+
+#[derive(Debug, Error)]
+pub enum ExportError {
+    #[error("folder `{0}` does not exist")]
+    UnknownFolder(String),
+    #[error("not a mailbox archive: {0}")]
+    Archive(#[from] ZipError),
+    #[error("archive has no readable manifest")]
+    Manifest,
+    #[error("`{0}` does not match its manifest checksum")]
+    Checksum(String),
+    #[error("`{path}`: {source}")]
+    Interchange {
+        path: String,
+        source: InterchangeError,
+    },
+    #[error("failed to read message content: {0}")]
+    Io(#[from] io::Error),
+}
+
+impl ExportError {
+    pub fn status(&self) -> u16 {
+        match self {
+            Self::UnknownFolder(_) => 404,
+            Self::Archive(_) | Self::Manifest => 400,
+            Self::Checksum(_) => 422,
+            Self::Interchange { source, .. } => source.status(),
+            Self::Io(_) => 500,
+        }
+    }
+}
+
+/// Body of `POST /export`; no folders means every folder.
+#[derive(Clone, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ExportRequest {
+    #[serde(default)]
+    pub folders: Vec<String>,
+}
+
+/// One archive entry as recorded in `manifest.json`.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ManifestEntry {
+    pub path: String,
+    pub sha256: String,
+    pub size: u64,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ExportManifest {
+    pub version: String,
+    pub exported_at: DateTime<Utc>,
+    pub folders: Vec<String>,
+    pub messages: usize,
+    pub entries: Vec<ManifestEntry>,
+}
+
+/// Summary returned by `POST /import`.
+#[derive(Clone, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ImportReport {
+    pub imported: usize,
+    /// Messages whose id is already in the store.
+    pub skipped: usize,
+    pub folders: Vec<String>,
+}
+
+#[derive(Clone)]
+pub struct MailboxExporter {
+    store: StoreManager,
+    quota: QuotaPolicy,
+    mapper: AddressMapper,
+    clock: SharedClock,
+    ids: SharedIds,
+}
+
+impl MailboxExporter {
+    pub fn new(store: StoreManager, quota: QuotaPolicy, mapper: AddressMapper) -> Self {
+        Self {
+            store,
+            quota,
+            mapper,
+            clock: SharedClock::default(),
+            ids: SharedIds::default(),
+        }
+    }
+
+    pub fn with_clock(mut self, clock: SharedClock) -> Self {
+        self.clock = clock;
+        self
+    }
+
+    pub fn with_ids(mut self, ids: SharedIds) -> Self {
+        self.ids = ids;
+        self
+    }
+
+    /// Build the archive in memory.
+    pub fn export(&self, request: &ExportRequest) -> Result<Vec<u8>, ExportError> {
+        let cursor = self.export_to(Cursor::new(Vec::new()), request)?;
+        Ok(cursor.into_inner())
+    }
+
+    /// Write the archive to `writer`, one entry at a time.
+    pub fn export_to<W: Write + Seek>(
+        &self,
+        writer: W,
+        request: &ExportRequest,
+    ) -> Result<W, ExportError> {
+        let folders = self.resolve_folders(&request.folders)?;
+        let mut archive = ArchiveWriter::new(writer);
+        let mut messages = 0;
+        let mut attachments = BTreeMap::new();
+        for folder in &folders {
+            for stored in self.store.list(folder) {
+                let id = stored.envelope.id.clone();
+                let message = match self.quota.held(&id) {
+                    Some(held) => {
+                        let message = self.quota.retrieve(&id).map_err(|err| {
+                            io::Error::new(io::ErrorKind::NotFound, err.to_string())
+                        })?;
+                        attachments.insert(held.attachment_id, message.content.body.clone());
+                        message
+                    }
+                    None => stored,
+                };
+                let base = format!("messages/{}/{}", entry_name(folder), entry_name(&id.0));
+                archive.add(
+                    &format!("{base}.json"),
+                    interchange::export(&message).as_bytes(),
+                )?;
+                archive.add(&format!("{base}.eml"), self.rfc822(&message).as_bytes())?;
+                messages += 1;
+            }
+        }
+        for (attachment, content) in attachments {
+            archive.add(&format!("attachments/{attachment}"), content.as_bytes())?;
+        }
+
+        let manifest = ExportManifest {
+            version: MANIFEST_VERSION.into(),
+            exported_at: self.clock.now(),
+            folders,
+            messages,
+            entries: archive.entries,
+        };
+        let mut writer = archive.writer;
+        writer.start_file(MANIFEST, FileOptions::default())?;
+        writer.write_all(
+            &serde_json::to_vec_pretty(&manifest).expect("manifest always serializes"),
+        )?;
+        info!(
+            target = "export",
+            messages = manifest.messages,
+            folders = manifest.folders.len(),
+            "mailbox exported"
+        );
+        Ok(writer.finish()?)
+    }
+
+    /// Verify an archive from [`export`](Self::export) and load its messages.
+    pub fn import(&self, bytes: &[u8]) -> Result<ImportReport, ExportError> {
+        let mut archive = ZipArchive::new(Cursor::new(bytes))?;
+        let manifest: ExportManifest = {
+            let mut file = archive
+                .by_name(MANIFEST)
+                .map_err(|_| ExportError::Manifest)?;
+            let mut contents = Vec::new();
+            file.read_to_end(&mut contents)?;
+            serde_json::from_slice(&contents).map_err(|_| ExportError::Manifest)?
+        };
+
+        let mut documents = Vec::new();
+        for entry in &manifest.entries {
+            let mut contents = Vec::new();
+            archive
+                .by_name(&entry.path)
+                .map_err(|_| ExportError::Checksum(entry.path.clone()))?
+                .read_to_end(&mut contents)?;
+            if contents.len() as u64 != entry.size || sha256(&contents) != entry.sha256 {
+                return Err(ExportError::Checksum(entry.path.clone()));
+            }
+            if entry.path.starts_with("messages/") && entry.path.ends_with(".json") {
+                let json = String::from_utf8(contents)
+                    .map_err(|_| ExportError::Checksum(entry.path.clone()))?;
+                let message =
+                    interchange::import(&json, &self.ids, &self.clock).map_err(|source| {
+                        ExportError::Interchange {
+                            path: entry.path.clone(),
+                            source,
+                        }
+                    })?;
+                documents.push(message);
+            }
+        }
+
+        let mut report = ImportReport::default();
+        let mut folders = BTreeSet::new();
+        for mut message in documents {
+            if self.store.get(&message.envelope.id).is_some() {
+                report.skipped += 1;
+                continue;
+            }
+            if let Err(err) = self.quota.admit(&mut message) {
+                warn!(target = "export", message = %message.envelope.id, "imported over quota: {err}");
+            }
+            folders.insert(message.envelope.folder.clone());
+            self.store.save(message);
+            report.imported += 1;
+        }
+        report.folders = folders.into_iter().collect();
+        info!(
+            target = "export",
+            imported = report.imported,
+            skipped = report.skipped,
+            "mailbox archive imported"
+        );
+        Ok(report)
+    }
+
+    fn resolve_folders(&self, requested: &[String]) -> Result<Vec<String>, ExportError> {
+        let known: BTreeSet<String> = self
+            .store
+            .folder_stats()
+            .into_iter()
+            .map(|stats| stats.folder)
+            .collect();
+        if requested.is_empty() {
+            return Ok(known.into_iter().collect());
+        }
+        let mut folders = Vec::new();
+        for folder in requested {
+            if !known.contains(folder) {
+                return Err(ExportError::UnknownFolder(folder.clone()));
+            }
+            if !folders.contains(folder) {
+                folders.push(folder.clone());
+            }
+        }
+        Ok(folders)
+    }
+
+    /// Plain-text RFC 822 rendering. Addresses the gateway cannot map keep
+    /// their O/R form so nothing is lost.
+    fn rfc822(&self, message: &Message) -> String {
+        let envelope = &message.envelope;
+        let address = |address: &Address| {
+            self.mapper.map_or_to_rfc822(address).unwrap_or_else(|_| {
+                format!(
+                    "\"C={};O={};S={}\" <>",
+                    address.country, address.organization, address.surname
+                )
+            })
+        };
+        let recipients: Vec<String> = envelope.recipients.iter().map(address).collect();
+        let mut eml = String::new();
+        eml.push_str(&format!("Message-ID: <{}@x400.local>\r\n", envelope.id));
+        eml.push_str(&format!("Date: {}\r\n", envelope.created_at.to_rfc2822()));
+        eml.push_str(&format!("From: {}\r\n", address(&envelope.sender)));
+        eml.push_str(&format!("To: {}\r\n", recipients.join(", ")));
+        eml.push_str(&format!("Subject: {}\r\n", encode_word(&envelope.subject)));
+        if let Some(in_reply_to) = &envelope.in_reply_to {
+            eml.push_str(&format!("In-Reply-To: <{in_reply_to}>\r\n"));
+        }
+        eml.push_str(&format!("X-X400-Folder: {}\r\n", envelope.folder));
+        eml.push_str("MIME-Version: 1.0\r\n");
+        eml.push_str("Content-Type: text/plain; charset=utf-8\r\n");
+        eml.push_str("Content-Transfer-Encoding: quoted-printable\r\n\r\n");
+        eml.push_str(&encode_quoted_printable(message.content.body.as_bytes()));
+        eml.push_str("\r\n");
+        eml
+    }
+}
+
+struct ArchiveWriter<W: Write + Seek> {
+    writer: zip::ZipWriter<W>,
+    entries: Vec<ManifestEntry>,
+}
+
+impl<W: Write + Seek> ArchiveWriter<W> {
+    fn new(writer: W) -> Self {
+        Self {
+            writer: zip::ZipWriter::new(writer),
+            entries: Vec::new(),
+        }
+    }
+
+    fn add(&mut self, path: &str, contents: &[u8]) -> Result<(), ExportError> {
+        self.writer.start_file(path, FileOptions::default())?;
+        self.writer.write_all(contents)?;
+        self.entries.push(ManifestEntry {
+            path: path.to_string(),
+            sha256: sha256(contents),
+            size: contents.len() as u64,
+        });
+        Ok(())
+    }
+}
+
+fn sha256(bytes: &[u8]) -> String {
+    format!("{:x}", Sha256::digest(bytes))
+}
+
+/// Folder names and ids are user-controlled; keep them to one path segment.
+fn entry_name(value: &str) -> String {
+    value
+        .chars()
+        .map(|c| match c {
+            '/' | '\\' | ':' => '_',
+            c if c.is_control() => '_',
+            c => c,
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::attachments::AttachmentStore;
+    use crate::config::QuotaConfig;
+    use crate::models::{MessageContent, MessageEnvelope, MessageStatus};
+
+    fn exporter(store: &StoreManager, dir: &std::path::Path) -> MailboxExporter {
+        let quota = QuotaPolicy::new(
+            QuotaConfig {
+                max_message_bytes: 16,
+                ..QuotaConfig::default()
+            },
+            store.clone(),
+            AttachmentStore::new(dir),
+        );
+        MailboxExporter::new(store.clone(), quota, AddressMapper::default())
+    }
+
+    #[test]
+    fn round_trips_selected_folders_and_rejects_tampering() {
+        let dir = tempfile::tempdir().unwrap();
+        let store = StoreManager::new();
+        let exports = exporter(&store, dir.path());
+        for (folder, body) in [
+            ("inbox", "short"),
+            ("inbox", "a body long enough to be held"),
+            ("sent", "not exported"),
+        ] {
+            let mut envelope =
+                MessageEnvelope::new("Manifest", Address::sample(), vec![Address::sample()]);
+            envelope.folder = folder.into();
+            envelope.status = MessageStatus::Delivered;
+            let mut message = Message {
+                envelope,
+                content: MessageContent {
+                    body: body.into(),
+                    attachments: Vec::new(),
+                },
+            };
+            exports.quota.admit(&mut message).unwrap();
+            store.save(message);
+        }
+
+        let request = ExportRequest {
+            folders: vec!["inbox".into()],
+        };
+        let archive = exports.export(&request).unwrap();
+        let mut zip = ZipArchive::new(Cursor::new(archive.clone())).unwrap();
+        let names: Vec<String> = zip.file_names().map(str::to_string).collect();
+        assert_eq!(names.iter().filter(|n| n.ends_with(".eml")).count(), 2);
+        assert_eq!(
+            names
+                .iter()
+                .filter(|n| n.starts_with("attachments/"))
+                .count(),
+            1
+        );
+        let mut eml = String::new();
+        zip.by_name(names.iter().find(|n| n.ends_with(".eml")).unwrap())
+            .unwrap()
+            .read_to_string(&mut eml)
+            .unwrap();
+        assert!(eml.contains("From: \"C=DE;O=Modern;S=Operator\" <>\r\n"));
+
+        let target = StoreManager::new();
+        let other = tempfile::tempdir().unwrap();
+        let report = exporter(&target, other.path()).import(&archive).unwrap();
+        assert_eq!(report.imported, 2);
+        assert_eq!(report.folders, ["inbox"]);
+        let mut bodies: Vec<String> = target
+            .list("inbox")
+            .into_iter()
+            .map(|message| message.content.body)
+            .collect();
+        bodies.sort();
+        assert!(bodies[0].starts_with("[Message content"), "held again");
+        assert_eq!(bodies[1], "short");
+        assert_eq!(
+            exporter(&target, other.path())
+                .import(&archive)
+                .unwrap()
+                .skipped,
+            2
+        );
+
+        let mut tampered = zip::ZipWriter::new(Cursor::new(Vec::new()));
+        for index in 0..zip.len() {
+            let mut file = zip.by_index(index).unwrap();
+            let mut contents = String::new();
+            file.read_to_string(&mut contents).unwrap();
+            tampered
+                .start_file(file.name(), FileOptions::default())
+                .unwrap();
+            tampered
+                .write_all(contents.replace("short", "shirt").as_bytes())
+                .unwrap();
+        }
+        let tampered = tampered.finish().unwrap().into_inner();
+        assert!(matches!(
+            exporter(&StoreManager::new(), other.path()).import(&tampered),
+            Err(ExportError::Checksum(_))
+        ));
+        assert!(matches!(
+            exports.export(&ExportRequest {
+                folders: vec!["missing".into()]
+            }),
+            Err(ExportError::UnknownFolder(_))
+        ));
+    }
+}
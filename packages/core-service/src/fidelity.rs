@@ -0,0 +1,609 @@
+//! Content fidelity checks against a golden corpus.
+//!
+//! A corpus directory holds `<name>.case` files: `key: value` headers, a blank
+//! line, then the body bytes in the declared charset. Each case is pushed
+//! through two paths and the canonical export of the stored result is compared
+//! with `<name>.submit.golden` and `<name>.gateway.golden`:
+//!
+//! * **submit** – client submission through the mock provider into the store.
+//! * **gateway** – rendered as RFC 822 (quoted-printable in the case charset),
+//!   fetched from the IMAP client and converted by [`gateway::inbound`].
+//!
+//! Attachments are only exercised on the submit path, since gateway ingestion
+//! does not understand multipart MIME yet. Release validation runs this as an
+//! admin job (`POST /admin/fidelity/runs`) or with `--fidelity <corpus>`.
+
+use std::collections::HashMap;
+use std::fmt::Write as _;
+use std::fs;
+use std::io::{self, Read};
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+
+use chrono::{DateTime, Utc};
+use encoding_rs::{Encoding, UTF_8};
+use serde::Serialize;
+use sha2::{Digest, Sha256};
+use thiserror::Error;
+use uuid::Uuid;
+
+use crate::attachments::{AttachmentStore, StoredAttachment};
+use crate::clock::{SharedClock, SharedIds};
+use crate::gateway::{self, AddressMapper, GatewayImapClient, InboundMessage};
+use crate::mock_provider::MockDeliveryProvider;
+use crate::models::{
+    Address, Message, MessageContent, MessageEnvelope, MessagePriority, MessageSensitivity,
+};
+use crate::queue::QueueManager;
+use crate::store::StoreManager;
+use crate::trace::TraceManager;
+
+#[derive(Debug, Error)]
+pub enum FidelityError {
+    #[error("failed to access {path}: {source}")]
+    Io { path: PathBuf, source: io::Error },
+    #[error("corpus case {case} is invalid: {reason}")]
+    InvalidCase { case: String, reason: String },
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub enum FidelityPath {
+    Submit,
+    Gateway,
+}
+
+impl FidelityPath {
+    pub const ALL: [FidelityPath; 2] = [FidelityPath::Submit, FidelityPath::Gateway];
+
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Self::Submit => "submit",
+            Self::Gateway => "gateway",
+        }
+    }
+}
+
+/// One check that did not reproduce its golden output.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct FidelityRegression {
+    pub case: String,
+    pub path: FidelityPath,
+    /// First differing line (1-based), when both outputs exist.
+    pub line: Option<usize>,
+    pub expected: Option<String>,
+    pub actual: Option<String>,
+    pub detail: String,
+}
+
+/// Outcome of one corpus run, as returned by `GET /admin/fidelity/runs/:id`.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct FidelityReport {
+    pub job_id: Uuid,
+    pub corpus: PathBuf,
+    pub started_at: DateTime<Utc>,
+    pub finished_at: DateTime<Utc>,
+    pub cases: usize,
+    pub checks: usize,
+    pub passed: usize,
+    /// Golden files written because the run was started with `bless`.
+    pub blessed: usize,
+    pub regressions: Vec<FidelityRegression>,
+}
+
+impl FidelityReport {
+    pub fn is_clean(&self) -> bool {
+        self.regressions.is_empty()
+    }
+}
+
+/// Reference message parsed from a `.case` file.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct CorpusCase {
+    pub name: String,
+    pub subject: String,
+    pub from: Address,
+    pub to: Vec<Address>,
+    pub priority: MessagePriority,
+    pub sensitivity: MessageSensitivity,
+    pub charset: &'static Encoding,
+    pub attachments: Vec<PathBuf>,
+    pub body: String,
+}
+
+impl CorpusCase {
+    pub fn from_file(path: &Path) -> Result<Self, FidelityError> {
+        let bytes = fs::read(path).map_err(|source| FidelityError::Io {
+            path: path.to_path_buf(),
+            source,
+        })?;
+        let name = path
+            .file_stem()
+            .map(|stem| stem.to_string_lossy().into_owned())
+            .unwrap_or_default();
+        let base = path.parent().unwrap_or_else(|| Path::new("."));
+        Self::parse(&name, base, &bytes)
+    }
+
+    /// Parse a case; attachment paths are resolved against `base`.
+    pub fn parse(name: &str, base: &Path, bytes: &[u8]) -> Result<Self, FidelityError> {
+        let invalid = |reason: String| FidelityError::InvalidCase {
+            case: name.to_string(),
+            reason,
+        };
+        let split = bytes
+            .windows(2)
+            .position(|window| window == b"\n\n")
+            .map(|index| (index, index + 2))
+            .or_else(|| {
+                bytes
+                    .windows(4)
+                    .position(|window| window == b"\r\n\r\n")
+                    .map(|index| (index, index + 4))
+            })
+            .ok_or_else(|| invalid("missing blank line after headers".into()))?;
+        let head = std::str::from_utf8(&bytes[..split.0])
+            .map_err(|_| invalid("headers must be UTF-8".into()))?;
+
+        let mut case = Self {
+            name: name.to_string(),
+            subject: String::new(),
+            from: Address::sample(),
+            to: Vec::new(),
+            priority: MessagePriority::Normal,
+            sensitivity: MessageSensitivity::Normal,
+            charset: UTF_8,
+            attachments: Vec::new(),
+            body: String::new(),
+        };
+        for line in head.lines().filter(|line| !line.trim().is_empty()) {
+            let (key, value) = line
+                .split_once(':')
+                .ok_or_else(|| invalid(format!("malformed header line `{line}`")))?;
+            let value = value.trim();
+            match key.trim().to_ascii_lowercase().as_str() {
+                "subject" => case.subject = value.to_string(),
+                "from" => {
+                    case.from = parse_address(value)
+                        .ok_or_else(|| invalid(format!("bad O/R address `{value}`")))?
+                }
+                "to" => case.to.push(
+                    parse_address(value)
+                        .ok_or_else(|| invalid(format!("bad O/R address `{value}`")))?,
+                ),
+                "priority" => {
+                    case.priority = match value.to_ascii_lowercase().as_str() {
+                        "low" => MessagePriority::Low,
+                        "normal" => MessagePriority::Normal,
+                        "high" => MessagePriority::High,
+                        other => return Err(invalid(format!("unknown priority `{other}`"))),
+                    }
+                }
+                "sensitivity" => {
+                    case.sensitivity = match value.to_ascii_lowercase().as_str() {
+                        "normal" => MessageSensitivity::Normal,
+                        "personal" => MessageSensitivity::Personal,
+                        other => return Err(invalid(format!("unknown sensitivity `{other}`"))),
+                    }
+                }
+                "charset" => {
+                    case.charset = Encoding::for_label(value.as_bytes())
+                        .ok_or_else(|| invalid(format!("unknown charset `{value}`")))?
+                }
+                "attachment" => case.attachments.push(base.join(value)),
+                other => return Err(invalid(format!("unknown header `{other}`"))),
+            }
+        }
+        if case.to.is_empty() {
+            return Err(invalid("at least one `to` header is required".into()));
+        }
+        let (body, malformed) = case.charset.decode_without_bom_handling(&bytes[split.1..]);
+        if malformed {
+            return Err(invalid(format!(
+                "body is not valid {}",
+                case.charset.name()
+            )));
+        }
+        case.body = body.replace("\r\n", "\n");
+        Ok(case)
+    }
+
+    fn envelope(&self, ids: &SharedIds, clock: &SharedClock) -> MessageEnvelope {
+        let mut envelope = MessageEnvelope::stamped(
+            &self.subject,
+            self.from.clone(),
+            self.to.clone(),
+            ids,
+            clock,
+        );
+        envelope.priority = self.priority;
+        envelope.sensitivity = self.sensitivity.clone();
+        envelope
+    }
+}
+
+/// `C=DE;O=Org;S=Surname`, the same form the alias table uses.
+fn parse_address(value: &str) -> Option<Address> {
+    let mut address = Address {
+        country: String::new(),
+        organization: String::new(),
+        surname: String::new(),
+    };
+    for part in value.split(';') {
+        let (key, value) = part.split_once('=')?;
+        let value = value.trim().to_string();
+        match key.trim().to_ascii_uppercase().as_str() {
+            "C" => address.country = value,
+            "O" => address.organization = value,
+            "S" => address.surname = value,
+            _ => return None,
+        }
+    }
+    (!address.country.is_empty() && !address.surname.is_empty()).then_some(address)
+}
+
+fn format_address(address: &Address) -> String {
+    format!(
+        "C={};O={};S={}",
+        address.country, address.organization, address.surname
+    )
+}
+
+/// Canonical text form of a stored message used for golden comparison.
+///
+/// Identifiers and timestamps are left out so goldens survive across runs.
+pub fn export(message: &Message, attachments: &[StoredAttachment]) -> String {
+    let envelope = &message.envelope;
+    let mut out = String::new();
+    let _ = writeln!(out, "Subject: {}", envelope.subject);
+    let _ = writeln!(out, "From: {}", format_address(&envelope.sender));
+    for recipient in &envelope.recipients {
+        let _ = writeln!(out, "To: {}", format_address(recipient));
+    }
+    let _ = writeln!(out, "Priority: {:?}", envelope.priority);
+    let _ = writeln!(out, "Sensitivity: {:?}", envelope.sensitivity);
+    let _ = writeln!(out, "Folder: {}", envelope.folder);
+    let _ = writeln!(out, "Status: {:?}", envelope.status);
+    for attachment in attachments {
+        let _ = writeln!(
+            out,
+            "Attachment: {}; size={}; sha256={}",
+            attachment.name, attachment.size, attachment.sha256
+        );
+    }
+    out.push('\n');
+    out.push_str(&message.content.body);
+    if !out.ends_with('\n') {
+        out.push('\n');
+    }
+    out
+}
+
+/// Runs corpus checks and keeps their reports for the admin API.
+#[derive(Clone)]
+pub struct FidelityRunner {
+    mapper: AddressMapper,
+    attachments: AttachmentStore,
+    clock: SharedClock,
+    ids: SharedIds,
+    reports: Arc<Mutex<HashMap<Uuid, FidelityReport>>>,
+}
+
+impl FidelityRunner {
+    pub fn new(mapper: AddressMapper, attachments: AttachmentStore) -> Self {
+        Self {
+            mapper,
+            attachments,
+            clock: SharedClock::default(),
+            ids: SharedIds::default(),
+            reports: Arc::new(Mutex::new(HashMap::new())),
+        }
+    }
+
+    pub fn with_clock(mut self, clock: SharedClock) -> Self {
+        self.clock = clock;
+        self
+    }
+
+    pub fn with_ids(mut self, ids: SharedIds) -> Self {
+        self.ids = ids;
+        self
+    }
+
+    /// Run every case in `corpus`. With `bless`, missing or differing goldens
+    /// are rewritten from the current output instead of being reported.
+    pub fn run(&self, corpus: &Path, bless: bool) -> Result<FidelityReport, FidelityError> {
+        let io_error = |source| FidelityError::Io {
+            path: corpus.to_path_buf(),
+            source,
+        };
+        let mut cases = fs::read_dir(corpus)
+            .map_err(io_error)?
+            .filter_map(|entry| entry.ok().map(|entry| entry.path()))
+            .filter(|path| path.extension().is_some_and(|ext| ext == "case"))
+            .collect::<Vec<_>>();
+        cases.sort();
+
+        let mut report = FidelityReport {
+            job_id: self.ids.uuid(),
+            corpus: corpus.to_path_buf(),
+            started_at: self.clock.now(),
+            finished_at: self.clock.now(),
+            cases: cases.len(),
+            checks: 0,
+            passed: 0,
+            blessed: 0,
+            regressions: Vec::new(),
+        };
+        for path in &cases {
+            let case = CorpusCase::from_file(path)?;
+            for fidelity_path in FidelityPath::ALL {
+                report.checks += 1;
+                let golden =
+                    corpus.join(format!("{}.{}.golden", case.name, fidelity_path.as_str()));
+                let actual = match self.render(&case, fidelity_path) {
+                    Ok(actual) => actual,
+                    Err(detail) => {
+                        report.regressions.push(FidelityRegression {
+                            case: case.name.clone(),
+                            path: fidelity_path,
+                            line: None,
+                            expected: None,
+                            actual: None,
+                            detail,
+                        });
+                        continue;
+                    }
+                };
+                let expected = fs::read_to_string(&golden).ok();
+                if expected.as_deref() == Some(actual.as_str()) {
+                    report.passed += 1;
+                } else if bless {
+                    fs::write(&golden, &actual).map_err(|source| FidelityError::Io {
+                        path: golden.clone(),
+                        source,
+                    })?;
+                    report.blessed += 1;
+                } else {
+                    report.regressions.push(regression(
+                        &case.name,
+                        fidelity_path,
+                        expected,
+                        &actual,
+                    ));
+                }
+            }
+        }
+        report.finished_at = self.clock.now();
+        if let Ok(mut reports) = self.reports.lock() {
+            reports.insert(report.job_id, report.clone());
+        }
+        Ok(report)
+    }
+
+    pub fn report(&self, job_id: Uuid) -> Option<FidelityReport> {
+        self.reports
+            .lock()
+            .ok()
+            .and_then(|reports| reports.get(&job_id).cloned())
+    }
+
+    fn render(&self, case: &CorpusCase, path: FidelityPath) -> Result<String, String> {
+        match path {
+            FidelityPath::Submit => self.submit(case),
+            FidelityPath::Gateway => self.gateway(case),
+        }
+    }
+
+    fn submit(&self, case: &CorpusCase) -> Result<String, String> {
+        let store = StoreManager::new().with_clock(self.clock.clone());
+        let provider =
+            MockDeliveryProvider::new(QueueManager::new(), store.clone(), TraceManager::new())
+                .with_clock(self.clock.clone());
+        let message = Message {
+            envelope: case.envelope(&self.ids, &self.clock),
+            content: MessageContent {
+                body: case.body.clone(),
+                attachments: Vec::new(),
+            },
+        };
+        let id = provider.dispatch(message);
+        let stored = store
+            .get(&id)
+            .ok_or_else(|| format!("submitted message {id} was not stored"))?;
+
+        let mut attachments = Vec::new();
+        for path in &case.attachments {
+            let name = path
+                .file_name()
+                .map(|name| name.to_string_lossy().into_owned())
+                .unwrap_or_default();
+            let file = fs::File::open(path)
+                .map_err(|err| format!("attachment {}: {err}", path.display()))?;
+            let put = self
+                .attachments
+                .put(&name, file)
+                .map_err(|err| format!("attachment {name}: {err}"))?;
+            attachments.push(self.read_back(put)?);
+        }
+        Ok(export(&stored, &attachments))
+    }
+
+    /// Re-read a stored attachment so the export reflects what comes back out.
+    fn read_back(&self, stored: StoredAttachment) -> Result<StoredAttachment, String> {
+        let mut stream = self
+            .attachments
+            .open(&stored.id, None)
+            .map_err(|err| format!("attachment {}: {err}", stored.name))?;
+        let mut bytes = Vec::new();
+        stream
+            .read_to_end(&mut bytes)
+            .map_err(|err| format!("attachment {}: {err}", stored.name))?;
+        Ok(StoredAttachment {
+            size: bytes.len() as u64,
+            sha256: format!("{:x}", Sha256::digest(&bytes)),
+            ..stored
+        })
+    }
+
+    fn gateway(&self, case: &CorpusCase) -> Result<String, String> {
+        let raw = self.rfc822(case)?;
+        let imap = GatewayImapClient::new(Default::default());
+        imap.enqueue(InboundMessage {
+            uid: case.name.clone(),
+            subject: case.subject.clone(),
+            from: String::new(),
+            raw,
+        });
+        let fetched = imap
+            .fetch(1)
+            .map_err(|err| err.to_string())?
+            .pop()
+            .ok_or_else(|| "gateway fetch returned nothing".to_string())?;
+        let message = gateway::inbound::to_message(&fetched, &self.mapper, &self.ids, &self.clock)
+            .map_err(|err| err.to_string())?;
+        let store = StoreManager::new().with_clock(self.clock.clone());
+        let id = message.envelope.id.clone();
+        store.save(message);
+        let stored = store
+            .get(&id)
+            .ok_or_else(|| format!("ingested message {id} was not stored"))?;
+        Ok(export(&stored, &[]))
+    }
+
+    /// Render the case the way a remote MTA would hand it to the gateway.
+    fn rfc822(&self, case: &CorpusCase) -> Result<String, String> {
+        let map = |address: &Address| {
+            self.mapper
+                .map_or_to_rfc822(address)
+                .map_err(|err| format!("{}: {err}", format_address(address)))
+        };
+        let to = case.to.iter().map(map).collect::<Result<Vec<_>, _>>()?;
+        let (body, _, unmappable) = case.charset.encode(&case.body);
+        if unmappable {
+            return Err(format!("body cannot be encoded as {}", case.charset.name()));
+        }
+        let x_priority = match case.priority {
+            MessagePriority::High => "1 (Highest)",
+            MessagePriority::Normal => "3 (Normal)",
+            MessagePriority::Low => "5 (Lowest)",
+        };
+        let mut raw = String::new();
+        let _ = write!(raw, "From: {}\r\n", map(&case.from)?);
+        let _ = write!(raw, "To: {}\r\n", to.join(", "));
+        let _ = write!(
+            raw,
+            "Subject: {}\r\n",
+            gateway::inbound::encode_word(&case.subject)
+        );
+        let _ = write!(raw, "X-Priority: {x_priority}\r\n");
+        if case.sensitivity == MessageSensitivity::Personal {
+            raw.push_str("Sensitivity: Personal\r\n");
+        }
+        raw.push_str("MIME-Version: 1.0\r\n");
+        let _ = write!(
+            raw,
+            "Content-Type: text/plain; charset=\"{}\"\r\n",
+            case.charset.name()
+        );
+        raw.push_str("Content-Transfer-Encoding: quoted-printable\r\n\r\n");
+        raw.push_str(&gateway::inbound::encode_quoted_printable(&body));
+        Ok(raw)
+    }
+}
+
+fn regression(
+    case: &str,
+    path: FidelityPath,
+    expected: Option<String>,
+    actual: &str,
+) -> FidelityRegression {
+    let Some(expected) = expected else {
+        return FidelityRegression {
+            case: case.to_string(),
+            path,
+            line: None,
+            expected: None,
+            actual: Some(actual.to_string()),
+            detail: "golden output is missing".into(),
+        };
+    };
+    let mut expected_lines = expected.lines();
+    let mut actual_lines = actual.lines();
+    let mut line = 1;
+    loop {
+        match (expected_lines.next(), actual_lines.next()) {
+            (Some(left), Some(right)) if left == right => line += 1,
+            (left, right) => {
+                return FidelityRegression {
+                    case: case.to_string(),
+                    path,
+                    line: Some(line),
+                    expected: left.map(str::to_string),
+                    actual: right.map(str::to_string),
+                    detail: format!("output differs from golden at line {line}"),
+                };
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::gateway::AddressMappingRule;
+
+    fn runner(dir: &Path) -> FidelityRunner {
+        let mapper = AddressMapper::new(
+            vec![AddressMappingRule::new("{S}@{O}.example")],
+            HashMap::new(),
+        );
+        FidelityRunner::new(mapper, AttachmentStore::new(dir.join("attachments")))
+    }
+
+    #[test]
+    fn blesses_then_detects_regressions() {
+        let dir = tempfile::tempdir().unwrap();
+        let corpus = dir.path().join("corpus");
+        fs::create_dir_all(&corpus).unwrap();
+        fs::write(corpus.join("note.txt"), b"attached").unwrap();
+        let mut case = b"subject: Caf\xc3\xa9 order\nfrom: C=DE;O=Org;S=Sender\n\
+to: C=DE;O=Org;S=Receiver\npriority: high\ncharset: windows-1252\nattachment: note.txt\n\n"
+            .to_vec();
+        case.extend_from_slice(b"Zwei Kaffee, bitte \x80 5\n");
+        fs::write(corpus.join("order.case"), case).unwrap();
+
+        let runner = runner(dir.path());
+        let first = runner.run(&corpus, false).unwrap();
+        assert_eq!(first.checks, 2);
+        assert_eq!(first.regressions.len(), 2);
+        assert_eq!(first.regressions[0].detail, "golden output is missing");
+
+        let blessed = runner.run(&corpus, true).unwrap();
+        assert_eq!(blessed.blessed, 2);
+        let submit = fs::read_to_string(corpus.join("order.submit.golden")).unwrap();
+        assert!(submit.contains("Subject: Café order\n"));
+        assert!(submit.contains("Attachment: note.txt; size=8;"));
+        assert!(submit.ends_with("\nZwei Kaffee, bitte € 5\n"));
+        let gateway = fs::read_to_string(corpus.join("order.gateway.golden")).unwrap();
+        assert!(gateway.contains("Priority: High\n"));
+        assert!(gateway.ends_with("\nZwei Kaffee, bitte € 5\n"));
+
+        let clean = runner.run(&corpus, false).unwrap();
+        assert!(clean.is_clean());
+        assert_eq!(runner.report(clean.job_id), Some(clean));
+
+        fs::write(
+            corpus.join("order.gateway.golden"),
+            gateway.replace("€", "EUR"),
+        )
+        .unwrap();
+        let regressed = runner.run(&corpus, false).unwrap();
+        assert_eq!(regressed.regressions.len(), 1);
+        let regression = &regressed.regressions[0];
+        assert_eq!(regression.path, FidelityPath::Gateway);
+        assert_eq!(regression.actual.as_deref(), Some("Zwei Kaffee, bitte € 5"));
+    }
+}
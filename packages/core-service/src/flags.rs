@@ -0,0 +1,140 @@
+//! `PATCH /messages/{id}/flags`: read, flagged and answered state plus
+//! free-form labels.
+
+use serde::{Deserialize, Serialize};
+use thiserror::Error;
+
+use crate::models::{MessageFlags, MessageId};
+use crate::store::StoreManager;
+
+const MAX_LABEL_LEN: usize = 64;
+
+#[derive(Debug, Error, PartialEq, Eq)]
+pub enum FlagsError {
+    #[error("message {0} not found")]
+    NotFound(MessageId),
+    #[error("invalid label {0:?}: labels are 1-{MAX_LABEL_LEN} characters without whitespace")]
+    InvalidLabel(String),
+}
+
+impl FlagsError {
+    pub fn status(&self) -> u16 {
+        match self {
+            Self::NotFound(_) => 404,
+            Self::InvalidLabel(_) => 400,
+        }
+    }
+}
+
+/// Requested change; absent fields are left as they are.
+#[derive(Clone, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct FlagsPatch {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub read: Option<bool>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub flagged: Option<bool>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub answered: Option<bool>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub add_labels: Vec<String>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub remove_labels: Vec<String>,
+}
+
+impl FlagsPatch {
+    /// Apply the patch to a stored message and return its new flags.
+    pub fn apply(&self, store: &StoreManager, id: &MessageId) -> Result<MessageFlags, FlagsError> {
+        if let Some(label) = self.add_labels.iter().find(|label| !valid_label(label)) {
+            return Err(FlagsError::InvalidLabel(label.clone()));
+        }
+        store
+            .update_flags(id, |flags| {
+                if let Some(read) = self.read {
+                    flags.read = read;
+                }
+                if let Some(flagged) = self.flagged {
+                    flags.flagged = flagged;
+                }
+                if let Some(answered) = self.answered {
+                    flags.answered = answered;
+                }
+                for label in &self.remove_labels {
+                    flags.labels.remove(label);
+                }
+                flags.labels.extend(self.add_labels.iter().cloned());
+            })
+            .ok_or_else(|| FlagsError::NotFound(id.clone()))
+    }
+}
+
+fn valid_label(label: &str) -> bool {
+    !label.is_empty()
+        && label.chars().count() <= MAX_LABEL_LEN
+        && !label.chars().any(char::is_whitespace)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn patches_only_the_given_flags() {
+        let store = StoreManager::new();
+        let ids = store.seed_demo_data();
+        let patch: FlagsPatch =
+            serde_json::from_str(r#"{"flagged":true,"addLabels":["customs","urgent"]}"#).unwrap();
+        let flags = patch.apply(&store, &ids[0]).unwrap();
+        assert!(flags.flagged && !flags.read);
+
+        let read = FlagsPatch {
+            read: Some(true),
+            remove_labels: vec!["urgent".into()],
+            ..FlagsPatch::default()
+        };
+        let flags = read.apply(&store, &ids[0]).unwrap();
+        assert!(flags.flagged && flags.read);
+        assert_eq!(flags.labels.into_iter().collect::<Vec<_>>(), ["customs"]);
+
+        let counts = &store.flag_counts()["inbox"];
+        assert_eq!((counts.unread, counts.flagged), (2, 1));
+        assert_eq!(counts.labels["customs"], 1);
+
+        let bad = FlagsPatch {
+            add_labels: vec!["two words".into()],
+            ..FlagsPatch::default()
+        };
+        assert_eq!(
+            bad.apply(&store, &ids[1]),
+            Err(FlagsError::InvalidLabel("two words".into()))
+        );
+        let missing = MessageId("msg-missing".into());
+        assert_eq!(
+            FlagsPatch::default().apply(&store, &missing),
+            Err(FlagsError::NotFound(missing))
+        );
+    }
+
+    #[test]
+    fn flags_survive_a_restart() {
+        let dir = tempfile::tempdir().unwrap();
+        let open = || {
+            StoreManager::new()
+                .with_message_table(dir.path().join("core.messages.json"))
+                .with_flags_table(dir.path().join("core.flags.json"))
+        };
+        let store = open();
+        let ids = store.seed_demo_data();
+        let patch: FlagsPatch =
+            serde_json::from_str(r#"{"flagged":true,"addLabels":["customs"]}"#).unwrap();
+        patch.apply(&store, &ids[0]).unwrap();
+        store.delete(&ids[1]);
+        drop(store);
+
+        let reopened = open();
+        let flags = reopened.flags(&ids[0]).unwrap();
+        assert!(flags.flagged);
+        assert_eq!(flags.labels.into_iter().collect::<Vec<_>>(), ["customs"]);
+        assert_eq!(reopened.flag_counts()["inbox"].flagged, 1);
+    }
+}
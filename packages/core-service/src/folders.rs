@@ -0,0 +1,447 @@
+//! Folder management.
+//!
+//! The built-in folders the service files messages into are always present;
+//! users add their own, optionally nested, through `POST /folders`, rename
+//! them with `PATCH /folders/{id}` and remove them with `DELETE /folders/{id}`.
+//! Custom folders get a generated id, and messages refer to folders by id.
+//! `POST /messages/{id}/move` only accepts a folder that exists.
+
+use std::collections::{BTreeMap, HashSet};
+
+use serde::Serialize;
+use thiserror::Error;
+use tracing::info;
+
+use crate::audit::AuditLog;
+use crate::clock::SharedIds;
+use crate::models::{AuditAction, FolderRecord, MessageId};
+use crate::store::StoreManager;
+
+/// Built-in folders as `(id, display name)`, in listing order.
+pub const SYSTEM_FOLDERS: &[(&str, &str)] = &[
+    ("inbox", "Inbox"),
+    ("outbox", "Outbox"),
+    ("sent", "Sent"),
+    ("drafts", "Drafts"),
+    ("archive", "Archive"),
+    ("quarantine", "Quarantine"),
+];
+
+const MAX_NAME_LEN: usize = 255;
+
+#[derive(Debug, Error, PartialEq, Eq)]
+pub enum FolderError {
+    #[error("folder {0} not found")]
+    NotFound(String),
+    #[error("message {0} not found")]
+    MessageNotFound(MessageId),
+    #[error("thread {0} not found")]
+    ThreadNotFound(MessageId),
+    #[error("cannot move a message to nonexistent folder {0}")]
+    UnknownTarget(String),
+    #[error("folder names must be 1-{MAX_NAME_LEN} characters without '/'")]
+    InvalidName,
+    #[error("a folder named {0:?} already exists here")]
+    DuplicateName(String),
+    #[error("built-in folder {0} cannot be renamed or deleted")]
+    SystemFolder(String),
+    #[error("folder {folder} still holds {messages} messages")]
+    NotEmpty { folder: String, messages: usize },
+}
+
+impl FolderError {
+    pub fn status(&self) -> u16 {
+        match self {
+            Self::NotFound(_) | Self::MessageNotFound(_) | Self::ThreadNotFound(_) => 404,
+            Self::UnknownTarget(_) => 422,
+            Self::InvalidName => 400,
+            Self::DuplicateName(_) | Self::SystemFolder(_) | Self::NotEmpty { .. } => 409,
+        }
+    }
+}
+
+/// Entry of `GET /folders`.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct FolderInfo {
+    pub id: String,
+    pub name: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub parent_id: Option<String>,
+    pub system: bool,
+    /// Messages without the `read` flag.
+    pub unread_count: usize,
+    pub total_count: usize,
+    pub flagged_count: usize,
+    pub answered_count: usize,
+    /// Messages per label, for labels used in this folder.
+    #[serde(skip_serializing_if = "BTreeMap::is_empty")]
+    pub label_counts: BTreeMap<String, usize>,
+}
+
+#[derive(Clone)]
+pub struct FolderManager {
+    store: StoreManager,
+    ids: SharedIds,
+    audit: Option<AuditLog>,
+}
+
+impl FolderManager {
+    /// Manage the folders in `store`, adding any missing built-in folder.
+    pub fn new(store: StoreManager) -> Self {
+        let missing: Vec<FolderRecord> = SYSTEM_FOLDERS
+            .iter()
+            .filter(|(id, _)| store.folder(id).is_none())
+            .map(|(id, name)| FolderRecord {
+                id: id.to_string(),
+                name: name.to_string(),
+                parent_id: None,
+                system: true,
+            })
+            .collect();
+        if !missing.is_empty() {
+            store.put_folders(missing);
+        }
+        Self {
+            store,
+            ids: SharedIds::default(),
+            audit: None,
+        }
+    }
+
+    pub fn with_ids(mut self, ids: SharedIds) -> Self {
+        self.ids = ids;
+        self
+    }
+
+    /// Record folder deletions as `folder.delete`.
+    pub fn with_audit(mut self, audit: AuditLog) -> Self {
+        self.audit = Some(audit);
+        self
+    }
+
+    /// Built-in folders first, then custom folders by name, with message counts.
+    ///
+    /// Folders that only exist because messages were filed there before the
+    /// table existed (e.g. by a migration) are listed under their id.
+    pub fn list(&self) -> Vec<FolderInfo> {
+        let mut totals: BTreeMap<String, usize> = self
+            .store
+            .folder_stats()
+            .into_iter()
+            .map(|stats| (stats.folder, stats.total))
+            .collect();
+        let mut flags = self.store.flag_counts();
+        let mut records = self.store.folders();
+        records.sort_by_key(|record| {
+            let builtin = SYSTEM_FOLDERS.iter().position(|(id, _)| *id == record.id);
+            (builtin.unwrap_or(usize::MAX), record.name.to_lowercase())
+        });
+        let orphans: Vec<String> = totals
+            .keys()
+            .filter(|id| !records.iter().any(|record| &record.id == *id))
+            .cloned()
+            .collect();
+        let mut info = |id: String, name: String, parent_id: Option<String>, system: bool| {
+            let counts = flags.remove(&id).unwrap_or_default();
+            FolderInfo {
+                total_count: totals.remove(&id).unwrap_or_default(),
+                id,
+                name,
+                parent_id,
+                system,
+                unread_count: counts.unread,
+                flagged_count: counts.flagged,
+                answered_count: counts.answered,
+                label_counts: counts.labels,
+            }
+        };
+
+        let mut folders: Vec<FolderInfo> = records
+            .into_iter()
+            .map(|record| info(record.id, record.name, record.parent_id, record.system))
+            .collect();
+        folders.extend(
+            orphans
+                .into_iter()
+                .map(|id| info(id.clone(), id, None, false)),
+        );
+        folders
+    }
+
+    pub fn create(&self, name: &str, parent_id: Option<&str>) -> Result<FolderRecord, FolderError> {
+        let name = valid_name(name)?;
+        if let Some(parent) = parent_id {
+            if self.store.folder(parent).is_none() {
+                return Err(FolderError::NotFound(parent.to_string()));
+            }
+        }
+        self.ensure_unique(&name, parent_id, None)?;
+        let record = FolderRecord {
+            id: self.ids.uuid().to_string(),
+            name,
+            parent_id: parent_id.map(str::to_string),
+            system: false,
+        };
+        self.store.put_folders(vec![record.clone()]);
+        info!(target = "folders", folder = %record.id, name = %record.name, "created");
+        Ok(record)
+    }
+
+    pub fn rename(&self, id: &str, name: &str) -> Result<FolderRecord, FolderError> {
+        let mut record = self.custom(id)?;
+        let name = valid_name(name)?;
+        self.ensure_unique(&name, record.parent_id.as_deref(), Some(id))?;
+        record.name = name;
+        self.store.put_folders(vec![record.clone()]);
+        Ok(record)
+    }
+
+    /// Delete a folder together with its subfolders, which must all be empty.
+    /// `actor` is recorded in the audit log.
+    pub fn delete(&self, actor: &str, id: &str) -> Result<(), FolderError> {
+        self.custom(id)?;
+        let folders = self.store.folders();
+        let mut doomed = vec![id.to_string()];
+        let mut index = 0;
+        while index < doomed.len() {
+            let parent = doomed[index].clone();
+            doomed.extend(
+                folders
+                    .iter()
+                    .filter(|folder| folder.parent_id.as_deref() == Some(parent.as_str()))
+                    .map(|folder| folder.id.clone()),
+            );
+            index += 1;
+        }
+        let messages: usize = self
+            .store
+            .folder_stats()
+            .iter()
+            .filter(|stats| doomed.contains(&stats.folder))
+            .map(|stats| stats.total)
+            .sum();
+        if messages > 0 {
+            return Err(FolderError::NotEmpty {
+                folder: id.to_string(),
+                messages,
+            });
+        }
+        self.store.remove_folders(&doomed);
+        info!(target = "folders", folder = %id, removed = doomed.len(), "deleted");
+        if let Some(audit) = &self.audit {
+            audit.record(
+                actor,
+                AuditAction::FolderDelete,
+                id,
+                None,
+                Some(format!("{} folders", doomed.len())),
+            );
+        }
+        Ok(())
+    }
+
+    /// Move a message, refusing folders that do not exist.
+    pub fn move_message(&self, id: &MessageId, folder: &str) -> Result<(), FolderError> {
+        self.ensure_exists(folder)?;
+        if self.store.move_message(id, folder) {
+            Ok(())
+        } else {
+            Err(FolderError::MessageNotFound(id.clone()))
+        }
+    }
+
+    pub fn archive_message(&self, id: &MessageId) -> Result<(), FolderError> {
+        self.move_message(id, "archive")
+    }
+
+    /// Archive a whole conversation (`POST /threads/{id}/archive`). Messages
+    /// still in `drafts` or `outbox` have not been sent and stay where they
+    /// are. Returns how many messages were moved.
+    pub fn archive_thread(&self, thread_id: &MessageId) -> Result<usize, FolderError> {
+        let thread = self
+            .store
+            .thread(thread_id)
+            .ok_or_else(|| FolderError::ThreadNotFound(thread_id.clone()))?;
+        let moved = thread
+            .messages
+            .iter()
+            .filter(|message| !matches!(message.envelope.folder.as_str(), "drafts" | "outbox"))
+            .filter(|message| self.store.move_message(&message.envelope.id, "archive"))
+            .count();
+        info!(target = "folders", thread = %thread_id, moved, "archived thread");
+        Ok(moved)
+    }
+
+    /// Fail with [`FolderError::UnknownTarget`] unless messages can be filed under `folder`.
+    pub fn ensure_exists(&self, folder: &str) -> Result<(), FolderError> {
+        let exists = self.store.folder(folder).is_some()
+            || self
+                .store
+                .folder_stats()
+                .iter()
+                .any(|stats| stats.folder == folder);
+        if exists {
+            Ok(())
+        } else {
+            Err(FolderError::UnknownTarget(folder.to_string()))
+        }
+    }
+
+    fn custom(&self, id: &str) -> Result<FolderRecord, FolderError> {
+        match self.store.folder(id) {
+            None => Err(FolderError::NotFound(id.to_string())),
+            Some(record) if record.system => Err(FolderError::SystemFolder(id.to_string())),
+            Some(record) => Ok(record),
+        }
+    }
+
+    /// Sibling names are compared case-insensitively.
+    fn ensure_unique(
+        &self,
+        name: &str,
+        parent_id: Option<&str>,
+        except: Option<&str>,
+    ) -> Result<(), FolderError> {
+        let taken: HashSet<String> = self
+            .store
+            .folders()
+            .into_iter()
+            .filter(|folder| folder.parent_id.as_deref() == parent_id)
+            .filter(|folder| Some(folder.id.as_str()) != except)
+            .map(|folder| folder.name.to_lowercase())
+            .collect();
+        if taken.contains(&name.to_lowercase()) {
+            return Err(FolderError::DuplicateName(name.to_string()));
+        }
+        Ok(())
+    }
+}
+
+fn valid_name(name: &str) -> Result<String, FolderError> {
+    let name = name.trim();
+    if name.is_empty() || name.chars().count() > MAX_NAME_LEN || name.contains('/') {
+        return Err(FolderError::InvalidName);
+    }
+    Ok(name.to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::clock::SequentialIds;
+
+    #[test]
+    fn manages_nested_folders_and_validates_moves() {
+        let store = StoreManager::new();
+        let ids = store.seed_demo_data();
+        let folders =
+            FolderManager::new(store.clone()).with_ids(SharedIds::new(SequentialIds::default()));
+
+        let projects = folders.create("Projects", None).unwrap();
+        let harbour = folders.create("Harbour", Some(&projects.id)).unwrap();
+        assert_eq!(
+            folders.create("harbour ", Some(&projects.id)),
+            Err(FolderError::DuplicateName("harbour".into()))
+        );
+        assert!(
+            folders.create("Harbour", None).is_ok(),
+            "names are per parent"
+        );
+        assert_eq!(folders.create("a/b", None), Err(FolderError::InvalidName));
+        assert_eq!(
+            folders.rename("inbox", "Incoming"),
+            Err(FolderError::SystemFolder("inbox".into()))
+        );
+        let renamed = folders.rename(&harbour.id, "Port").unwrap();
+        assert_eq!(renamed.id, harbour.id);
+
+        assert_eq!(
+            folders.move_message(&ids[0], "nowhere"),
+            Err(FolderError::UnknownTarget("nowhere".into()))
+        );
+        folders.move_message(&ids[0], &harbour.id).unwrap();
+        let listed = folders.list();
+        assert_eq!(listed[0].id, "inbox");
+        let port = listed
+            .iter()
+            .find(|folder| folder.id == harbour.id)
+            .unwrap();
+        assert_eq!(
+            (port.name.as_str(), port.parent_id.as_deref()),
+            ("Port", Some(projects.id.as_str()))
+        );
+        assert_eq!((port.total_count, port.unread_count), (1, 1));
+
+        assert_eq!(
+            folders.delete("ops", &projects.id),
+            Err(FolderError::NotEmpty {
+                folder: projects.id.clone(),
+                messages: 1
+            })
+        );
+        folders.archive_message(&ids[0]).unwrap();
+        folders.delete("ops", &projects.id).unwrap();
+        assert!(store.folder(&projects.id).is_none());
+        assert!(store.folder(&harbour.id).is_none());
+    }
+
+    #[test]
+    fn threads_replies_and_archives_whole_conversations() {
+        use crate::models::{Address, Message, MessageContent, MessageEnvelope};
+
+        let store = StoreManager::new();
+        let folders = FolderManager::new(store.clone());
+        let ids = SharedIds::new(SequentialIds::default());
+        let clock = crate::clock::SharedClock::default();
+        let message = |subject: &str, folder: &str, in_reply_to: Option<&str>| {
+            let mut envelope = MessageEnvelope::stamped(
+                subject,
+                Address::sample(),
+                vec![Address::sample()],
+                &ids,
+                &clock,
+            );
+            envelope.folder = folder.into();
+            envelope.in_reply_to = in_reply_to.map(str::to_string);
+            Message {
+                envelope,
+                content: MessageContent {
+                    body: String::new(),
+                    attachments: Vec::new(),
+                },
+            }
+        };
+
+        let mut original = message("Berth request", "inbox", None);
+        original.envelope.origin_id = Some("berth-1@port.example".into());
+        // The reply is stored before the message it answers arrives.
+        let reply = message("Re: Berth request", "sent", Some("berth-1@port.example"));
+        let answer = message("Re: Re: Berth request", "inbox", Some(&reply.envelope.id.0));
+        let pending = message("Re: Berth request", "outbox", Some(&answer.envelope.id.0));
+        let unrelated = message("Crew list", "inbox", None);
+        let root = original.envelope.id.clone();
+        store.save(reply);
+        store.save(answer);
+        store.save(original);
+        store.save_all(vec![pending, unrelated]);
+
+        let inbox = store.threads("inbox");
+        assert_eq!(inbox.len(), 2);
+        let berth = inbox
+            .iter()
+            .find(|thread| thread.thread_id == root)
+            .unwrap();
+        assert_eq!(berth.subject, "Berth request");
+        assert_eq!(berth.messages.len(), 2);
+        assert_eq!(store.thread(&root).unwrap().messages.len(), 4);
+
+        assert_eq!(folders.archive_thread(&root), Ok(3));
+        assert!(store.list("inbox").len() == 1 && store.list("outbox").len() == 1);
+        let missing = MessageId("msg-none".into());
+        assert_eq!(
+            folders.archive_thread(&missing),
+            Err(FolderError::ThreadNotFound(missing))
+        );
+    }
+}
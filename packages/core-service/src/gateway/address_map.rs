@@ -1,9 +1,12 @@
 use std::collections::HashMap;
+use std::sync::{Arc, RwLock};
 
 use regex::Regex;
+use serde::{Deserialize, Serialize};
 use unicode_normalization::UnicodeNormalization;
 
-use crate::models::Address;
+use crate::models::{Address, Dda, X400Address};
+use crate::store::StoreManager;
 
 /// Error returned when address mapping cannot be completed.
 #[derive(Debug, thiserror::Error, PartialEq, Eq)]
@@ -12,29 +15,127 @@ pub enum MappingError {
     NoMatch,
     #[error("alias not found")]
     AliasMissing,
+    #[error("invalid mapping file: {0}")]
+    File(String),
 }
 
 /// Mapping rule converting an O/R address into an RFC822 address.
-#[derive(Clone, Debug, PartialEq, Eq)]
+///
+/// Rules are tried by descending `priority`, in insertion order among equal
+/// priorities. A rule with conditions only applies to addresses in that
+/// country or private management domain.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
 pub struct AddressMappingRule {
     template: String,
+    #[serde(default)]
+    priority: i32,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    country: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    prmd: Option<String>,
 }
 
 impl AddressMappingRule {
     pub fn new(template: impl Into<String>) -> Self {
         Self {
             template: template.into(),
+            priority: 0,
+            country: None,
+            prmd: None,
         }
     }
 
-    fn placeholder_value(&self, placeholder: &str, address: &Address) -> Option<String> {
-        match placeholder {
-            "C" => Some(address.country.clone()),
-            "O" => Some(address.organization.clone()),
-            "S" => Some(address.surname.clone()),
-            "G" => Some(address.surname.clone()),
-            _ => None,
+    pub fn with_priority(mut self, priority: i32) -> Self {
+        self.priority = priority;
+        self
+    }
+
+    /// Only apply the rule to addresses with this country code.
+    pub fn when_country(mut self, country: impl Into<String>) -> Self {
+        self.country = Some(country.into());
+        self
+    }
+
+    /// Only apply the rule to addresses in this PRMD.
+    pub fn when_prmd(mut self, prmd: impl Into<String>) -> Self {
+        self.prmd = Some(prmd.into());
+        self
+    }
+
+    pub fn template(&self) -> &str {
+        &self.template
+    }
+
+    pub fn priority(&self) -> i32 {
+        self.priority
+    }
+
+    /// Why the conditions exclude `address`, or `None` when they hold. An
+    /// unknown PRMD never satisfies a PRMD condition.
+    fn mismatch(&self, address: &X400Address) -> Option<RuleOutcome> {
+        let name = &address.or_name;
+        if let Some(country) = &self.country {
+            if !country.eq_ignore_ascii_case(name.c.trim()) {
+                return Some(RuleOutcome::CountryMismatch {
+                    expected: country.clone(),
+                    actual: name.c.clone(),
+                });
+            }
+        }
+        let prmd = name.prmd.as_deref();
+        if let Some(expected) = &self.prmd {
+            if !prmd.is_some_and(|prmd| expected.eq_ignore_ascii_case(prmd.trim())) {
+                return Some(RuleOutcome::PrmdMismatch {
+                    expected: expected.clone(),
+                    actual: prmd.map(str::to_string),
+                });
+            }
+        }
+        None
+    }
+
+    /// Placeholder names in the template, in order of appearance.
+    fn placeholders(&self) -> Vec<&str> {
+        let mut names = Vec::new();
+        let mut rest = self.template.as_str();
+        while let Some(start) = rest.find('{') {
+            let Some(end) = rest[start..].find('}') else {
+                break;
+            };
+            names.push(&rest[start + 1..start + end]);
+            rest = &rest[start + end + 1..];
         }
+        names
+    }
+
+    /// `{C}`, `{A}`/`{ADMD}`, `{P}`/`{PRMD}`, `{O}`, `{OU1}`–`{OU4}`, `{S}`,
+    /// `{G}`, `{I}`, `{Q}` and `{DDA:type}`.
+    fn placeholder_value(placeholder: &str, address: &X400Address) -> Option<String> {
+        let name = &address.or_name;
+        let value = match placeholder {
+            "C" => Some(name.c.clone()),
+            "A" | "ADMD" => name.admd.clone(),
+            "P" | "PRMD" => name.prmd.clone(),
+            "O" => name.o.clone(),
+            "OU1" | "OU2" | "OU3" | "OU4" => name
+                .ou
+                .get(usize::from(placeholder.as_bytes()[2] - b'1'))
+                .cloned(),
+            "S" => name.surname.clone(),
+            "G" => name.given_name.clone(),
+            "I" => name.initials.clone(),
+            "Q" => name.generation_qualifier.clone(),
+            _ => {
+                let kind = placeholder.strip_prefix("DDA:")?;
+                address
+                    .dda
+                    .iter()
+                    .find(|dda| dda.kind.eq_ignore_ascii_case(kind))
+                    .map(|dda| dda.value.clone())
+            }
+        };
+        value.filter(|value| !value.trim().is_empty())
     }
 
     fn sanitize(value: &str) -> String {
@@ -53,36 +154,49 @@ impl AddressMappingRule {
     }
 
     pub fn apply(&self, address: &Address) -> Option<String> {
+        self.apply_x400(&address.into())
+    }
+
+    /// Render the template for `address`. A placeholder whose attribute the
+    /// address lacks makes the rule inapplicable; `{G}` needs a given name.
+    pub fn apply_x400(&self, address: &X400Address) -> Option<String> {
         let mut rendered = self.template.clone();
-        for placeholder in ["G", "S", "O", "C"] {
-            let token = format!("{{{placeholder}}}");
-            if rendered.contains(&token) {
-                let value = self.placeholder_value(placeholder, address)?;
-                rendered = rendered.replace(&token, &Self::sanitize(&value));
-            }
+        for placeholder in self.placeholders() {
+            let value = Self::placeholder_value(placeholder, address)?;
+            rendered = rendered.replace(&format!("{{{placeholder}}}"), &Self::sanitize(&value));
         }
-        if rendered.contains('{') {
-            return None;
-        }
-        Some(rendered)
+        (!rendered.contains('{')).then_some(rendered)
     }
 
-    fn as_regex(&self) -> Option<Regex> {
-        let mut escaped = regex::escape(&self.template);
-        for placeholder in ["G", "S", "O", "C"] {
-            let token = format!("\\{{{placeholder}\\}}");
-            if escaped.contains(&token) {
-                escaped = escaped.replace(&token, &format!("(?P<{placeholder}>[a-z0-9-]+)"));
-            }
+    /// The template as a regex with one capture group per placeholder.
+    fn as_regex(&self) -> Option<(Regex, Vec<&str>)> {
+        let placeholders = self.placeholders();
+        let mut pattern = String::from("^");
+        let mut rest = self.template.as_str();
+        for placeholder in &placeholders {
+            let token = format!("{{{placeholder}}}");
+            let start = rest.find(&token)?;
+            pattern.push_str(&regex::escape(&rest[..start]));
+            pattern.push_str("([a-z0-9-]+)");
+            rest = &rest[start + token.len()..];
         }
-        Regex::new(&format!("^{escaped}$")).ok()
+        pattern.push_str(&regex::escape(rest));
+        pattern.push('$');
+        Some((Regex::new(&pattern).ok()?, placeholders))
     }
 
     pub fn invert(&self, email: &str) -> Option<Address> {
-        let regex = self.as_regex()?;
+        self.invert_x400(email).map(|address| address.address())
+    }
+
+    /// Recover the O/R attributes the template encodes from `email`. The
+    /// rule's `country` and `prmd` conditions supply those attributes when
+    /// the template does not capture them; a missing country becomes `XX`.
+    pub fn invert_x400(&self, email: &str) -> Option<X400Address> {
+        let (regex, placeholders) = self.as_regex()?;
         let captures = regex.captures(email)?;
         let sanitize_back = |value: &str| {
-            let mut parts = value
+            value
                 .split('-')
                 .filter(|segment| !segment.is_empty())
                 .map(|segment| {
@@ -92,40 +206,154 @@ impl AddressMappingRule {
                         None => String::new(),
                     }
                 })
-                .collect::<Vec<_>>();
-            if parts.is_empty() {
-                String::new()
-            } else if parts.len() == 1 {
-                parts.remove(0)
-            } else {
-                parts.join(" ")
+                .collect::<Vec<_>>()
+                .join(" ")
+        };
+
+        let mut address = X400Address::default();
+        for (index, placeholder) in placeholders.iter().enumerate() {
+            let Some(value) = captures.get(index + 1).map(|m| sanitize_back(m.as_str())) else {
+                continue;
+            };
+            let name = &mut address.or_name;
+            match *placeholder {
+                "C" => name.c = value,
+                "A" | "ADMD" => name.admd = Some(value),
+                "P" | "PRMD" => name.prmd = Some(value),
+                "O" => name.o = Some(value),
+                "OU1" | "OU2" | "OU3" | "OU4" => {
+                    let index = usize::from(placeholder.as_bytes()[2] - b'1');
+                    if name.ou.len() <= index {
+                        name.ou.resize(index + 1, String::new());
+                    }
+                    name.ou[index] = value;
+                }
+                "S" => name.surname = Some(value),
+                "G" => name.given_name = Some(value),
+                // Initials are upper case in X.400.
+                "I" => name.initials = Some(value.to_ascii_uppercase()),
+                "Q" => name.generation_qualifier = Some(value),
+                other => {
+                    if let Some(kind) = other.strip_prefix("DDA:") {
+                        address.dda.push(Dda {
+                            kind: kind.to_string(),
+                            value,
+                        });
+                    }
+                }
+            }
+        }
+        let name = &mut address.or_name;
+        if name.c.is_empty() {
+            name.c = self.country.clone().unwrap_or_else(|| "XX".into());
+        }
+        if name.prmd.is_none() {
+            name.prmd = self.prmd.clone();
+        }
+        Some(address)
+    }
+}
+
+/// What one rule did with an address in a [`MappingExplanation`].
+#[derive(Clone, Debug, PartialEq, Eq, Serialize)]
+#[serde(tag = "outcome", rename_all = "camelCase")]
+pub enum RuleOutcome {
+    /// The rule produced `result`; later rules are not tried.
+    Matched {
+        result: String,
+    },
+    CountryMismatch {
+        expected: String,
+        actual: String,
+    },
+    PrmdMismatch {
+        expected: String,
+        actual: Option<String>,
+    },
+    /// The conditions held but the template did not fit the address.
+    NoMatch,
+    /// An alias or an earlier rule already matched.
+    NotTried,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct RuleTrace {
+    pub template: String,
+    pub priority: i32,
+    #[serde(flatten)]
+    pub outcome: RuleOutcome,
+}
+
+/// Dry run of a mapping: every rule in the order it is tried and what it did.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct MappingExplanation {
+    pub input: String,
+    /// Alias that answered the lookup before any rule was tried.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub alias: Option<String>,
+    pub rules: Vec<RuleTrace>,
+    /// Mapped address; an O/R string (`C=..;O=..;S=..`) for RFC 822 input.
+    pub result: Option<String>,
+}
+
+#[derive(Default)]
+struct AliasCache {
+    version: Option<u64>,
+    forward: HashMap<String, String>,
+    reverse: HashMap<String, String>,
+}
+
+/// The store's `aliases` table, cached until the store reports a change.
+#[derive(Clone)]
+struct AliasTable {
+    store: StoreManager,
+    cache: Arc<RwLock<AliasCache>>,
+}
+
+impl AliasTable {
+    fn lookup<T>(&self, read: impl FnOnce(&AliasCache) -> T) -> T {
+        let version = self.store.aliases_version();
+        if let Ok(cache) = self.cache.read() {
+            if cache.version == Some(version) {
+                return read(&cache);
             }
+        }
+        let mut fresh = AliasCache {
+            version: Some(version),
+            ..AliasCache::default()
         };
+        for row in self.store.aliases() {
+            fresh
+                .reverse
+                .insert(row.email.to_lowercase(), row.or_address.clone());
+            fresh.forward.insert(row.or_address, row.email);
+        }
+        let value = read(&fresh);
+        if let Ok(mut cache) = self.cache.write() {
+            *cache = fresh;
+        }
+        value
+    }
+}
 
-        Some(Address {
-            country: captures
-                .name("C")
-                .map(|m| sanitize_back(m.as_str()))
-                .unwrap_or_else(|| "XX".into()),
-            organization: captures
-                .name("O")
-                .map(|m| sanitize_back(m.as_str()))
-                .unwrap_or_else(|| "UNKNOWN".into()),
-            surname: captures
-                .name("S")
-                .or_else(|| captures.name("G"))
-                .map(|m| sanitize_back(m.as_str()))
-                .unwrap_or_else(|| "User".into()),
-        })
+impl std::fmt::Debug for AliasTable {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("AliasTable").finish_non_exhaustive()
     }
 }
 
 /// Mapper responsible for translating addresses between X.400 and SMTP worlds.
+///
+/// Clones share the rule chain, so [`replace_rules`](Self::replace_rules)
+/// reaches every component holding a copy.
 #[derive(Clone, Debug, Default)]
 pub struct AddressMapper {
-    rules: Vec<AddressMappingRule>,
+    rules: Arc<RwLock<Vec<AddressMappingRule>>>,
     aliases: HashMap<String, String>,
     alias_reverse: HashMap<String, String>,
+    table: Option<AliasTable>,
 }
 
 impl AddressMapper {
@@ -135,68 +363,260 @@ impl AddressMapper {
             alias_reverse.insert(email.to_lowercase(), or.clone());
         }
         Self {
-            rules,
+            rules: Arc::new(RwLock::new(by_priority(rules))),
             aliases,
             alias_reverse,
+            table: None,
         }
     }
 
-    pub fn map_or_to_rfc822(&self, address: &Address) -> Result<String, MappingError> {
-        let or_string = format!(
-            "C={};O={};S={}",
-            address.country, address.organization, address.surname
-        );
-        if let Some(value) = self.aliases.get(&or_string) {
-            return Ok(value.clone());
+    /// Consult the store's persisted alias table before the aliases given
+    /// here; its entries win for the same O/R or RFC 822 address.
+    pub fn with_alias_table(mut self, store: StoreManager) -> Self {
+        self.table = Some(AliasTable {
+            store,
+            cache: Arc::new(RwLock::new(AliasCache::default())),
+        });
+        self
+    }
+
+    /// The rule chain in the order rules are tried.
+    pub fn rules(&self) -> Vec<AddressMappingRule> {
+        self.rules
+            .read()
+            .map(|rules| rules.clone())
+            .unwrap_or_default()
+    }
+
+    /// Swap the rule chain for this mapper and every clone of it.
+    pub fn replace_rules(&self, rules: Vec<AddressMappingRule>) {
+        if let Ok(mut current) = self.rules.write() {
+            *current = by_priority(rules);
         }
+    }
 
-        for rule in &self.rules {
-            if let Some(result) = rule.apply(address) {
-                return Ok(result);
+    /// Add alias mappings (O/R address to RFC 822), replacing any existing
+    /// alias for the same O/R address.
+    pub fn with_aliases(mut self, aliases: HashMap<String, String>) -> Self {
+        for (or, email) in aliases {
+            if let Some(previous) = self.aliases.insert(or.clone(), email.clone()) {
+                self.alias_reverse.remove(&previous.to_lowercase());
             }
+            self.alias_reverse.insert(email.to_lowercase(), or);
         }
-        Err(MappingError::NoMatch)
+        self
+    }
+
+    pub fn map_or_to_rfc822(&self, address: &Address) -> Result<String, MappingError> {
+        self.map_or_to_rfc822_in(address, None)
+    }
+
+    /// Like [`map_or_to_rfc822`](Self::map_or_to_rfc822) for an address in
+    /// `prmd`, so rules with a PRMD condition can apply.
+    pub fn map_or_to_rfc822_in(
+        &self,
+        address: &Address,
+        prmd: Option<&str>,
+    ) -> Result<String, MappingError> {
+        if let Some(value) = self.alias_email(&or_string(address)) {
+            return Ok(value);
+        }
+        let mut full = X400Address::from(address);
+        full.or_name.prmd = prmd.map(str::to_string);
+        self.map_x400_to_rfc822(&full)
+    }
+
+    /// Map a full O/R address. Aliases are looked up by its complete
+    /// attribute string, then by its `C=..;O=..;S=..` form.
+    pub fn map_x400_to_rfc822(&self, address: &X400Address) -> Result<String, MappingError> {
+        if let Some(value) = self.x400_alias(address) {
+            return Ok(value);
+        }
+        let rules = self.rules.read().map_err(|_| MappingError::NoMatch)?;
+        rules
+            .iter()
+            .find_map(|rule| match apply(rule, address) {
+                RuleOutcome::Matched { result } => Some(result),
+                _ => None,
+            })
+            .ok_or(MappingError::NoMatch)
     }
 
     pub fn map_rfc822_to_or(&self, email: &str) -> Result<Address, MappingError> {
-        if let Some(or) = self.alias_reverse.get(&email.to_lowercase()) {
-            return self.parse_alias(or).ok_or(MappingError::AliasMissing);
+        if let Some(or) = self.alias_or(email) {
+            return parse_or_address(&or).ok_or(MappingError::AliasMissing);
         }
+        self.map_rfc822_to_x400(email)
+            .map(|address| address.address())
+    }
 
-        for rule in &self.rules {
-            if let Some(address) = rule.invert(email) {
-                return Ok(address);
-            }
+    /// Map an RFC 822 address to every O/R attribute its alias or the first
+    /// matching rule yields.
+    pub fn map_rfc822_to_x400(&self, email: &str) -> Result<X400Address, MappingError> {
+        if let Some(or) = self.alias_or(email) {
+            return X400Address::parse(&or).ok_or(MappingError::AliasMissing);
         }
-        Err(MappingError::NoMatch)
-    }
-
-    fn parse_alias(&self, value: &str) -> Option<Address> {
-        let mut country = "".to_string();
-        let mut organization = "".to_string();
-        let mut surname = "".to_string();
-        for part in value.split(';') {
-            let mut key_value = part.splitn(2, '=');
-            let key = key_value.next()?.trim();
-            let val = key_value.next()?.trim();
-            match key.to_ascii_uppercase().as_str() {
-                "C" => country = val.to_string(),
-                "O" => organization = val.to_string(),
-                "S" => surname = val.to_string(),
-                _ => {}
-            }
+        let rules = self.rules.read().map_err(|_| MappingError::NoMatch)?;
+        rules
+            .iter()
+            .find_map(|rule| invert(rule, email).1)
+            .ok_or(MappingError::NoMatch)
+    }
+
+    /// Dry-run [`map_x400_to_rfc822`](Self::map_x400_to_rfc822), reporting
+    /// which rule matched and why the ones before it did not.
+    pub fn explain(&self, address: &X400Address) -> MappingExplanation {
+        let alias = self.x400_alias(address);
+        let mut result = alias.clone();
+        let rules = self
+            .rules()
+            .into_iter()
+            .map(|rule| {
+                let outcome = if result.is_some() {
+                    RuleOutcome::NotTried
+                } else {
+                    apply(&rule, address)
+                };
+                if let RuleOutcome::Matched { result: mapped } = &outcome {
+                    result = Some(mapped.clone());
+                }
+                trace(rule, outcome)
+            })
+            .collect();
+        MappingExplanation {
+            input: address.to_string(),
+            alias,
+            rules,
+            result,
         }
-        if country.is_empty() || surname.is_empty() {
-            return None;
+    }
+
+    /// Dry-run [`map_rfc822_to_x400`](Self::map_rfc822_to_x400).
+    pub fn explain_rfc822(&self, email: &str) -> MappingExplanation {
+        let alias = self.alias_or(email);
+        let mut result = alias
+            .as_deref()
+            .and_then(X400Address::parse)
+            .map(|address| address.to_string());
+        let rules = self
+            .rules()
+            .into_iter()
+            .map(|rule| {
+                let outcome = if alias.is_some() || result.is_some() {
+                    RuleOutcome::NotTried
+                } else {
+                    let (outcome, address) = invert(&rule, email);
+                    result = address.map(|address| address.to_string());
+                    outcome
+                };
+                trace(rule, outcome)
+            })
+            .collect();
+        MappingExplanation {
+            input: email.to_string(),
+            alias,
+            rules,
+            result,
         }
-        if organization.is_empty() {
-            organization = "UNKNOWN".into();
+    }
+
+    fn x400_alias(&self, address: &X400Address) -> Option<String> {
+        self.alias_email(&address.to_string())
+            .or_else(|| self.alias_email(&or_string(&address.address())))
+    }
+
+    fn alias_email(&self, or_address: &str) -> Option<String> {
+        self.table
+            .as_ref()
+            .and_then(|table| table.lookup(|cache| cache.forward.get(or_address).cloned()))
+            .or_else(|| self.aliases.get(or_address).cloned())
+    }
+
+    fn alias_or(&self, email: &str) -> Option<String> {
+        let email = email.to_lowercase();
+        self.table
+            .as_ref()
+            .and_then(|table| table.lookup(|cache| cache.reverse.get(&email).cloned()))
+            .or_else(|| self.alias_reverse.get(&email).cloned())
+    }
+}
+
+/// Parse `C=..;O=..;S=..` as used by the alias table. Country and surname
+/// are required; a missing organisation becomes `UNKNOWN`.
+pub fn parse_or_address(value: &str) -> Option<Address> {
+    let mut country = "".to_string();
+    let mut organization = "".to_string();
+    let mut surname = "".to_string();
+    for part in value.split(';') {
+        let mut key_value = part.splitn(2, '=');
+        let key = key_value.next()?.trim();
+        let val = key_value.next()?.trim();
+        match key.to_ascii_uppercase().as_str() {
+            "C" => country = val.to_string(),
+            "O" => organization = val.to_string(),
+            "S" => surname = val.to_string(),
+            _ => {}
         }
-        Some(Address {
-            country,
-            organization,
-            surname,
-        })
+    }
+    if country.is_empty() || surname.is_empty() {
+        return None;
+    }
+    if organization.is_empty() {
+        organization = "UNKNOWN".into();
+    }
+    Some(Address {
+        country,
+        organization,
+        surname,
+    })
+}
+
+/// Canonical `C=..;O=..;S=..` form of `address`, the alias table's key.
+pub fn or_string(address: &Address) -> String {
+    format!(
+        "C={};O={};S={}",
+        address.country, address.organization, address.surname
+    )
+}
+
+fn by_priority(mut rules: Vec<AddressMappingRule>) -> Vec<AddressMappingRule> {
+    // Stable, so equal priorities keep their configured order.
+    rules.sort_by_key(|rule| std::cmp::Reverse(rule.priority));
+    rules
+}
+
+fn apply(rule: &AddressMappingRule, address: &X400Address) -> RuleOutcome {
+    if let Some(mismatch) = rule.mismatch(address) {
+        return mismatch;
+    }
+    match rule.apply_x400(address) {
+        Some(result) => RuleOutcome::Matched { result },
+        None => RuleOutcome::NoMatch,
+    }
+}
+
+/// An inverted address carries the PRMD of the rule's condition, so only a
+/// captured country or PRMD can contradict the conditions.
+fn invert(rule: &AddressMappingRule, email: &str) -> (RuleOutcome, Option<X400Address>) {
+    let Some(address) = rule.invert_x400(email) else {
+        return (RuleOutcome::NoMatch, None);
+    };
+    match rule.mismatch(&address) {
+        Some(mismatch) => (mismatch, None),
+        None => (
+            RuleOutcome::Matched {
+                result: address.to_string(),
+            },
+            Some(address),
+        ),
+    }
+}
+
+fn trace(rule: AddressMappingRule, outcome: RuleOutcome) -> RuleTrace {
+    RuleTrace {
+        template: rule.template,
+        priority: rule.priority,
+        outcome,
     }
 }
 
@@ -215,8 +635,49 @@ mod tests {
     #[test]
     fn rule_renders_email() {
         let rule = AddressMappingRule::new("{G}.{S}@{O}.{C}.example");
-        let email = rule.apply(&sample_address()).unwrap();
-        assert_eq!(email, "muller.muller@bundespost.de.example");
+        assert_eq!(rule.apply(&sample_address()), None, "no given name");
+        let mut address = X400Address::from(&sample_address());
+        address.or_name.given_name = Some("Hans".into());
+        let email = rule.apply_x400(&address).unwrap();
+        assert_eq!(email, "hans.muller@bundespost.de.example");
+    }
+
+    #[test]
+    fn full_or_names_round_trip() {
+        let address = X400Address::parse(
+            "C=DE;ADMD=ViaT;PRMD=Corp;O=Bundespost;OU1=Logistik;OU2=Hafen;S=Müller;G=Hans;I=K;DDA:UID=4711",
+        )
+        .unwrap();
+        assert_eq!(address.or_name.ou, ["Logistik", "Hafen"]);
+        let mapper = AddressMapper::new(
+            vec![
+                AddressMappingRule::new("{G}.{I}.{S}@{OU2}.{OU1}.{P}.{C}.example")
+                    .with_priority(10),
+                AddressMappingRule::new("{S}.{DDA:UID}@{O}.example"),
+            ],
+            HashMap::new(),
+        );
+        let email = mapper.map_x400_to_rfc822(&address).unwrap();
+        assert_eq!(email, "hans.k.muller@hafen.logistik.corp.de.example");
+        let back = mapper.map_rfc822_to_x400(&email).unwrap();
+        assert_eq!(
+            back.to_string(),
+            "C=De;PRMD=Corp;OU1=Logistik;OU2=Hafen;S=Muller;G=Hans;I=K"
+        );
+
+        let mut without_units = address.clone();
+        without_units.or_name.ou.clear();
+        assert_eq!(
+            mapper.map_x400_to_rfc822(&without_units).unwrap(),
+            "muller.4711@bundespost.example"
+        );
+        let explanation = mapper.explain(&without_units);
+        assert_eq!(explanation.rules[0].outcome, RuleOutcome::NoMatch);
+        assert_eq!(
+            X400Address::parse(&address.to_string()).unwrap(),
+            address,
+            "the attribute form round-trips"
+        );
     }
 
     #[test]
@@ -254,4 +715,27 @@ mod tests {
         assert_eq!(address.organization, "Bundespost");
         assert_eq!(address.surname, "Mueller");
     }
+
+    #[test]
+    fn added_aliases_replace_earlier_ones() {
+        let mut aliases = HashMap::new();
+        aliases.insert(
+            "C=DE;O=Bundespost;S=Mueller".into(),
+            "old@example.com".into(),
+        );
+        let mapper = AddressMapper::new(vec![], aliases.clone()).with_aliases(HashMap::from([(
+            "C=DE;O=Bundespost;S=Mueller".to_string(),
+            "hans@example.com".to_string(),
+        )]));
+        assert_eq!(
+            mapper.map_or_to_rfc822(&Address {
+                country: "DE".into(),
+                organization: "Bundespost".into(),
+                surname: "Mueller".into(),
+            }),
+            Ok("hans@example.com".into())
+        );
+        assert!(mapper.map_rfc822_to_or("old@example.com").is_err());
+        assert!(mapper.map_rfc822_to_or("hans@example.com").is_ok());
+    }
 }
@@ -0,0 +1,239 @@
+//! Gateway alias management.
+//!
+//! `GET /gateway/aliases` lists the persisted O/R ↔ RFC 822 alias table,
+//! `POST /gateway/aliases` adds an entry, `PUT /gateway/aliases/{orAddress}`
+//! changes its email and `DELETE /gateway/aliases/{orAddress}` removes it.
+//! O/R addresses are stored in canonical attribute order
+//! (`C=..;ADMD=..;PRMD=..;O=..;OU1=..;S=..;G=..`), and an email
+//! may only stand for one O/R address so the reverse mapping stays
+//! unambiguous. [`AddressMapper`](crate::gateway::AddressMapper)s built with
+//! `with_alias_table` see every change on their next lookup.
+
+use thiserror::Error;
+use tracing::info;
+
+use crate::audit::AuditLog;
+use crate::models::{AliasRecord, AuditAction, X400Address};
+use crate::store::StoreManager;
+
+#[derive(Debug, Error, PartialEq, Eq)]
+pub enum AliasError {
+    #[error("alias for {0} not found")]
+    NotFound(String),
+    #[error("{0:?} is not an O/R address of the form C=..;O=..;S=..")]
+    InvalidOrAddress(String),
+    #[error("{0:?} is not an email address")]
+    InvalidEmail(String),
+    #[error("an alias for {0} already exists")]
+    Exists(String),
+    #[error("{email} is already the alias of {or_address}")]
+    DuplicateEmail { email: String, or_address: String },
+}
+
+impl AliasError {
+    pub fn status(&self) -> u16 {
+        match self {
+            Self::NotFound(_) => 404,
+            Self::InvalidOrAddress(_) | Self::InvalidEmail(_) => 400,
+            Self::Exists(_) | Self::DuplicateEmail { .. } => 409,
+        }
+    }
+}
+
+#[derive(Clone)]
+pub struct AliasManager {
+    store: StoreManager,
+    audit: Option<AuditLog>,
+}
+
+impl AliasManager {
+    pub fn new(store: StoreManager) -> Self {
+        Self { store, audit: None }
+    }
+
+    /// Record alias deletions as `alias.delete`.
+    pub fn with_audit(mut self, audit: AuditLog) -> Self {
+        self.audit = Some(audit);
+        self
+    }
+
+    pub fn list(&self) -> Vec<AliasRecord> {
+        self.store.aliases()
+    }
+
+    pub fn get(&self, or_address: &str) -> Result<AliasRecord, AliasError> {
+        let key = canonical(or_address)?;
+        self.store
+            .aliases()
+            .into_iter()
+            .find(|row| row.or_address == key)
+            .ok_or(AliasError::NotFound(key))
+    }
+
+    pub fn create(&self, or_address: &str, email: &str) -> Result<AliasRecord, AliasError> {
+        let key = canonical(or_address)?;
+        if self.store.aliases().iter().any(|row| row.or_address == key) {
+            return Err(AliasError::Exists(key));
+        }
+        self.save(key, email)
+    }
+
+    pub fn update(&self, or_address: &str, email: &str) -> Result<AliasRecord, AliasError> {
+        let key = self.get(or_address)?.or_address;
+        self.save(key, email)
+    }
+
+    pub fn delete(&self, actor: &str, or_address: &str) -> Result<AliasRecord, AliasError> {
+        let key = canonical(or_address)?;
+        let removed = self
+            .store
+            .remove_alias(&key)
+            .ok_or(AliasError::NotFound(key))?;
+        info!(target = "gateway.aliases", or_address = %removed.or_address, "alias removed");
+        if let Some(audit) = &self.audit {
+            audit.record(
+                actor,
+                AuditAction::AliasDelete,
+                &removed.or_address,
+                None,
+                Some(removed.email.clone()),
+            );
+        }
+        Ok(removed)
+    }
+
+    fn save(&self, or_address: String, email: &str) -> Result<AliasRecord, AliasError> {
+        let email = email.trim();
+        if !is_email(email) {
+            return Err(AliasError::InvalidEmail(email.to_string()));
+        }
+        if let Some(other) = self
+            .store
+            .aliases()
+            .into_iter()
+            .find(|row| row.or_address != or_address && row.email.eq_ignore_ascii_case(email))
+        {
+            return Err(AliasError::DuplicateEmail {
+                email: email.to_string(),
+                or_address: other.or_address,
+            });
+        }
+        let record = AliasRecord {
+            or_address,
+            email: email.to_string(),
+        };
+        self.store.put_alias(record.clone());
+        info!(target = "gateway.aliases", or_address = %record.or_address, email = %record.email, "alias saved");
+        Ok(record)
+    }
+}
+
+fn canonical(or_address: &str) -> Result<String, AliasError> {
+    X400Address::parse(or_address)
+        .map(|address| address.to_string())
+        .ok_or_else(|| AliasError::InvalidOrAddress(or_address.to_string()))
+}
+
+fn is_email(value: &str) -> bool {
+    match value.split_once('@') {
+        Some((local, domain)) => {
+            !local.is_empty()
+                && domain.contains('.')
+                && !domain.starts_with('.')
+                && !domain.ends_with('.')
+                && !domain.contains('@')
+                && !value
+                    .chars()
+                    .any(|c| c.is_whitespace() || c == '<' || c == '>')
+        }
+        None => false,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::gateway::address_map::{AddressMapper, AddressMappingRule};
+    use crate::models::Address;
+
+    #[test]
+    fn persists_aliases_and_feeds_the_mapper() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("aliases.json");
+        let store = StoreManager::new().with_alias_table(&path);
+        let aliases = AliasManager::new(store.clone());
+        let mapper = AddressMapper::new(
+            vec![AddressMappingRule::new("{S}@port.example")],
+            Default::default(),
+        )
+        .with_alias_table(store.clone());
+        let pilot = Address {
+            country: "DE".into(),
+            organization: "Port".into(),
+            surname: "Pilot".into(),
+        };
+        assert_eq!(
+            mapper.map_or_to_rfc822(&pilot).unwrap(),
+            "pilot@port.example"
+        );
+
+        let record = aliases
+            .create(" c=DE; o=Port; s=Pilot ", "harbour.pilot@port.example")
+            .unwrap();
+        assert_eq!(record.or_address, "C=DE;O=Port;S=Pilot");
+        assert_eq!(
+            mapper.map_or_to_rfc822(&pilot).unwrap(),
+            "harbour.pilot@port.example"
+        );
+        assert_eq!(
+            mapper
+                .map_rfc822_to_or("Harbour.Pilot@port.example")
+                .unwrap(),
+            pilot
+        );
+
+        assert_eq!(
+            aliases.create("C=DE;O=Port;S=Pilot", "other@port.example"),
+            Err(AliasError::Exists("C=DE;O=Port;S=Pilot".into()))
+        );
+        let duplicate = aliases
+            .create("C=DE;O=Port;S=Tug", "HARBOUR.PILOT@port.example")
+            .unwrap_err();
+        assert_eq!(duplicate.status(), 409);
+        assert!(matches!(duplicate, AliasError::DuplicateEmail { .. }));
+        assert_eq!(
+            aliases.create("O=Port;S=Tug", "tug@port.example"),
+            Err(AliasError::InvalidOrAddress("O=Port;S=Tug".into()))
+        );
+        assert_eq!(
+            aliases
+                .create("C=DE;O=Port;S=Tug", "tug at port")
+                .unwrap_err()
+                .status(),
+            400
+        );
+
+        aliases
+            .update("C=DE;O=Port;S=Pilot", "pilot.de@port.example")
+            .unwrap();
+        assert_eq!(
+            mapper.map_or_to_rfc822(&pilot).unwrap(),
+            "pilot.de@port.example"
+        );
+        let reloaded = StoreManager::new().with_alias_table(&path);
+        assert_eq!(reloaded.aliases(), aliases.list());
+
+        aliases.delete("ops", "C=DE;O=Port;S=Pilot").unwrap();
+        assert_eq!(
+            mapper.map_or_to_rfc822(&pilot).unwrap(),
+            "pilot@port.example"
+        );
+        assert_eq!(
+            aliases
+                .delete("ops", "C=DE;O=Port;S=Pilot")
+                .unwrap_err()
+                .status(),
+            404
+        );
+    }
+}
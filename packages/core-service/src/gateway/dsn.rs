@@ -0,0 +1,351 @@
+//! RFC 3464 delivery status notifications.
+//!
+//! [`Dsn`] is the typed form of a `multipart/report;
+//! report-type=delivery-status` message. `Original-Envelope-Id` carries the
+//! X.400 message id so reports coming back through SMTP correlate with the
+//! stored message. Each recipient has its own field group with action, status
+//! and diagnostic. X.400 reason and diagnostic names are sent in an
+//! `X-X400` diagnostic code next to the mapped enhanced status code, so a DSN
+//! that round-trips through the gateway keeps the exact pair.
+
+use chrono::{DateTime, Utc};
+use serde::Serialize;
+
+use crate::gateway::inbound::split_headers;
+use crate::gateway::mime;
+use crate::gateway::report_map::is_enhanced_status;
+
+/// Diagnostic type used for X.400 reason/diagnostic pairs.
+pub const X400_DIAGNOSTIC: &str = "X-X400";
+
+#[derive(Debug, thiserror::Error, PartialEq, Eq)]
+pub enum DsnError {
+    #[error("message has no delivery-status part")]
+    MissingStatus,
+    #[error("delivery status lists no recipients")]
+    NoRecipients,
+    #[error("recipient field group is missing {0}")]
+    MissingField(&'static str),
+    #[error("invalid {field}: {value}")]
+    InvalidField { field: &'static str, value: String },
+}
+
+/// `Action` of a recipient field group.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub enum DsnAction {
+    Failed,
+    Delayed,
+    Delivered,
+    Relayed,
+    Expanded,
+}
+
+impl DsnAction {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Self::Failed => "failed",
+            Self::Delayed => "delayed",
+            Self::Delivered => "delivered",
+            Self::Relayed => "relayed",
+            Self::Expanded => "expanded",
+        }
+    }
+
+    fn parse(value: &str) -> Option<Self> {
+        // Action values may carry a comment, e.g. `failed (bad address)`.
+        let value = value.split(['(', ' ']).next().unwrap_or_default();
+        [
+            Self::Failed,
+            Self::Delayed,
+            Self::Delivered,
+            Self::Relayed,
+            Self::Expanded,
+        ]
+        .into_iter()
+        .find(|action| action.as_str().eq_ignore_ascii_case(value))
+    }
+}
+
+/// Address with its RFC 3464 address type, e.g. `rfc822; user@example.com`.
+/// Recipients the gateway cannot map are reported as `x400; C=DE;O=Org;S=User`.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct TypedAddress {
+    pub address_type: String,
+    pub address: String,
+}
+
+impl TypedAddress {
+    pub fn rfc822(address: impl Into<String>) -> Self {
+        Self {
+            address_type: "rfc822".into(),
+            address: address.into(),
+        }
+    }
+
+    pub fn x400(address: impl Into<String>) -> Self {
+        Self {
+            address_type: "x400".into(),
+            address: address.into(),
+        }
+    }
+
+    fn parse(field: &'static str, value: &str) -> Result<Self, DsnError> {
+        let (address_type, address) =
+            value
+                .split_once(';')
+                .ok_or_else(|| DsnError::InvalidField {
+                    field,
+                    value: value.to_string(),
+                })?;
+        Ok(Self {
+            address_type: address_type.trim().to_ascii_lowercase(),
+            address: address.trim().to_string(),
+        })
+    }
+}
+
+impl std::fmt::Display for TypedAddress {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}; {}", self.address_type, self.address)
+    }
+}
+
+/// `Diagnostic-Code`, e.g. `smtp; 550 5.1.1 user unknown`.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct DiagnosticCode {
+    pub diagnostic_type: String,
+    pub text: String,
+}
+
+impl DiagnosticCode {
+    /// The X.400 reason and diagnostic, for codes of type [`X400_DIAGNOSTIC`].
+    pub fn x400(&self) -> Option<(&str, Option<&str>)> {
+        if !self.diagnostic_type.eq_ignore_ascii_case(X400_DIAGNOSTIC) {
+            return None;
+        }
+        let mut parts = self.text.split(';').map(str::trim);
+        let reason = parts.next().filter(|reason| !reason.is_empty())?;
+        Some((reason, parts.next().filter(|value| !value.is_empty())))
+    }
+}
+
+/// Per-recipient field group.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct DsnRecipient {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub original_recipient: Option<TypedAddress>,
+    pub final_recipient: TypedAddress,
+    pub action: DsnAction,
+    /// Enhanced status code (RFC 3463).
+    pub status: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub remote_mta: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub diagnostic_code: Option<DiagnosticCode>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub last_attempt_date: Option<DateTime<Utc>>,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Dsn {
+    /// `Original-Envelope-Id`: the X.400 message id of the reported message.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub envelope_id: Option<String>,
+    pub reporting_mta: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub arrival_date: Option<DateTime<Utc>>,
+    pub recipients: Vec<DsnRecipient>,
+}
+
+impl Dsn {
+    /// Render the report as a MIME entity: `Subject`, `MIME-Version` and the
+    /// `multipart/report` with a readable explanation and the
+    /// `message/delivery-status` part. The sender adds the envelope headers.
+    pub fn render(&self) -> String {
+        let boundary = format!("=_dsn_{}", self.envelope_id.as_deref().unwrap_or("report"));
+        let failed = self
+            .recipients
+            .iter()
+            .any(|recipient| recipient.action == DsnAction::Failed);
+        let mut raw = String::new();
+        raw.push_str(if failed {
+            "Subject: Delivery Status Notification (Failure)\r\n"
+        } else {
+            "Subject: Delivery Status Notification\r\n"
+        });
+        raw.push_str("MIME-Version: 1.0\r\n");
+        raw.push_str(&format!(
+            "Content-Type: multipart/report; report-type=delivery-status; boundary=\"{boundary}\"\r\n\r\n"
+        ));
+
+        raw.push_str(&format!("--{boundary}\r\n"));
+        raw.push_str("Content-Type: text/plain; charset=us-ascii\r\n\r\n");
+        for recipient in &self.recipients {
+            raw.push_str(&format!(
+                "{}: {} ({})\r\n",
+                recipient.final_recipient.address,
+                recipient.action.as_str(),
+                recipient.status
+            ));
+        }
+
+        raw.push_str(&format!("\r\n--{boundary}\r\n"));
+        raw.push_str("Content-Type: message/delivery-status\r\n\r\n");
+        raw.push_str(&self.status_fields());
+        raw.push_str(&format!("\r\n--{boundary}--\r\n"));
+        raw
+    }
+
+    /// Body of the `message/delivery-status` part: the per-message fields,
+    /// then one field group per recipient.
+    pub fn status_fields(&self) -> String {
+        let mut raw = String::new();
+        if let Some(envelope_id) = &self.envelope_id {
+            raw.push_str(&format!("Original-Envelope-Id: {envelope_id}\r\n"));
+        }
+        raw.push_str(&format!("Reporting-MTA: dns; {}\r\n", self.reporting_mta));
+        if let Some(arrival_date) = self.arrival_date {
+            raw.push_str(&format!("Arrival-Date: {}\r\n", arrival_date.to_rfc2822()));
+        }
+        for recipient in &self.recipients {
+            raw.push_str("\r\n");
+            if let Some(original) = &recipient.original_recipient {
+                raw.push_str(&format!("Original-Recipient: {original}\r\n"));
+            }
+            raw.push_str(&format!(
+                "Final-Recipient: {}\r\n",
+                recipient.final_recipient
+            ));
+            raw.push_str(&format!("Action: {}\r\n", recipient.action.as_str()));
+            raw.push_str(&format!("Status: {}\r\n", recipient.status));
+            if let Some(remote_mta) = &recipient.remote_mta {
+                raw.push_str(&format!("Remote-MTA: dns; {remote_mta}\r\n"));
+            }
+            if let Some(code) = &recipient.diagnostic_code {
+                raw.push_str(&format!(
+                    "Diagnostic-Code: {}; {}\r\n",
+                    code.diagnostic_type, code.text
+                ));
+            }
+            if let Some(date) = recipient.last_attempt_date {
+                raw.push_str(&format!("Last-Attempt-Date: {}\r\n", date.to_rfc2822()));
+            }
+        }
+        raw
+    }
+
+    /// Read a DSN from a full message, or from a bare `message/delivery-status`
+    /// body whose first field group holds the per-message fields.
+    pub fn parse(raw: &str) -> Result<Self, DsnError> {
+        let parsed = mime::parse(raw);
+        let status = parsed
+            .attachments
+            .iter()
+            .find(|part| {
+                part.mime_type == "message/delivery-status"
+                    || part.mime_type == "message/global-delivery-status"
+            })
+            .map(|part| String::from_utf8_lossy(&part.data).into_owned());
+        let status = match status {
+            Some(status) => status,
+            None if parsed.header("Content-Type").is_none() && raw.contains("Final-Recipient:") => {
+                raw.to_string()
+            }
+            None => return Err(DsnError::MissingStatus),
+        };
+
+        let field = |group: &[(String, String)], name: &str| {
+            group
+                .iter()
+                .find(|(key, _)| key.eq_ignore_ascii_case(name))
+                .map(|(_, value)| value.clone())
+        };
+        let mut groups = field_groups(&status);
+        let message = if groups
+            .first()
+            .is_some_and(|group| field(group, "Final-Recipient").is_none())
+        {
+            groups.remove(0)
+        } else {
+            Vec::new()
+        };
+        let mut recipients = Vec::new();
+        for group in groups {
+            let final_recipient = field(&group, "Final-Recipient")
+                .ok_or(DsnError::MissingField("Final-Recipient"))?;
+            let action = field(&group, "Action").ok_or(DsnError::MissingField("Action"))?;
+            let status = field(&group, "Status").ok_or(DsnError::MissingField("Status"))?;
+            // Status may be followed by a comment: `5.1.1 (bad mailbox)`.
+            let status = status.split_whitespace().next().unwrap_or_default();
+            if !is_enhanced_status(status) {
+                return Err(DsnError::InvalidField {
+                    field: "Status",
+                    value: status.to_string(),
+                });
+            }
+            recipients.push(DsnRecipient {
+                original_recipient: field(&group, "Original-Recipient")
+                    .map(|value| TypedAddress::parse("Original-Recipient", &value))
+                    .transpose()?,
+                final_recipient: TypedAddress::parse("Final-Recipient", &final_recipient)?,
+                action: DsnAction::parse(&action).ok_or(DsnError::InvalidField {
+                    field: "Action",
+                    value: action.clone(),
+                })?,
+                status: status.to_string(),
+                remote_mta: field(&group, "Remote-MTA").map(|value| without_type(&value)),
+                diagnostic_code: field(&group, "Diagnostic-Code").map(|value| {
+                    let (diagnostic_type, text) = value.split_once(';').unwrap_or(("smtp", &value));
+                    DiagnosticCode {
+                        diagnostic_type: diagnostic_type.trim().to_string(),
+                        text: text.trim().to_string(),
+                    }
+                }),
+                last_attempt_date: field(&group, "Last-Attempt-Date")
+                    .and_then(|value| date(&value)),
+            });
+        }
+        if recipients.is_empty() {
+            return Err(DsnError::NoRecipients);
+        }
+        Ok(Self {
+            envelope_id: field(&message, "Original-Envelope-Id"),
+            reporting_mta: field(&message, "Reporting-MTA")
+                .map(|value| without_type(&value))
+                .unwrap_or_default(),
+            arrival_date: field(&message, "Arrival-Date").and_then(|value| date(&value)),
+            recipients,
+        })
+    }
+}
+
+/// Header-style field groups separated by blank lines.
+fn field_groups(text: &str) -> Vec<Vec<(String, String)>> {
+    let normalized = text.replace("\r\n", "\n");
+    normalized
+        .split("\n\n")
+        .map(|group| split_headers(&format!("{}\n\n", group.trim_matches('\n'))).0)
+        .filter(|group| !group.is_empty())
+        .collect()
+}
+
+/// `dns; mta.example` without the name type.
+fn without_type(value: &str) -> String {
+    value
+        .split_once(';')
+        .map(|(_, name)| name)
+        .unwrap_or(value)
+        .trim()
+        .to_string()
+}
+
+fn date(value: &str) -> Option<DateTime<Utc>> {
+    DateTime::parse_from_rfc2822(value.trim())
+        .ok()
+        .map(|date| date.with_timezone(&Utc))
+}
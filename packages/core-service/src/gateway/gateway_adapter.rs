@@ -1,8 +1,17 @@
 use crate::gateway::address_map::{AddressMapper, MappingError};
-use crate::gateway::imap_client::{GatewayImapClient, InboundMessage};
+use crate::gateway::dsn::{
+    DiagnosticCode, Dsn, DsnAction, DsnError, DsnRecipient, TypedAddress, X400_DIAGNOSTIC,
+};
+use crate::gateway::imap_client::{GatewayImapClient, ImapError, InboundMessage};
+use crate::gateway::inbound::{self, HOPS_HEADER};
+use crate::gateway::mdn::{Mdn, MdnError};
+use crate::gateway::mime::{self, MimeAttachment, GATEWAY_MESSAGE_DOMAIN};
+use crate::gateway::policy::EffectivePolicy;
 use crate::gateway::report_map::{DeliveryReport, ReportMapper};
 use crate::gateway::smtp_client::{GatewaySmtpClient, SmtpError, SmtpMessage, SmtpSendOutcome};
-use crate::models::Address;
+use crate::models::{Address, Message, MessageId, Report};
+use crate::telemetry::TelemetryManager;
+use crate::transport::{MessageTransport, TransportError};
 use tracing::instrument;
 
 /// Error returned by the high level gateway adapter when an operation fails.
@@ -12,6 +21,31 @@ pub enum GatewayError {
     Mapping(#[from] MappingError),
     #[error("SMTP error: {0}")]
     Smtp(#[from] SmtpError),
+    #[error("IMAP error: {0}")]
+    Imap(#[from] ImapError),
+    #[error("attachment storage failed: {0}")]
+    Storage(String),
+    #[error("invalid DSN: {0}")]
+    Dsn(#[from] DsnError),
+    #[error("invalid MDN: {0}")]
+    Mdn(#[from] MdnError),
+    /// The message already crossed the gateway as often as
+    /// `gateway.security.maxHops` allows; it is most likely looping.
+    #[error("message crossed the gateway {hops} times, limit is {limit}")]
+    HopLimit { hops: u32, limit: u32 },
+    /// The mailbox quota refused the message.
+    #[error("{0}")]
+    Quota(String),
+}
+
+impl From<GatewayError> for TransportError {
+    fn from(err: GatewayError) -> Self {
+        match err {
+            GatewayError::Smtp(_) | GatewayError::Imap(_) => Self::Unavailable(err.to_string()),
+            GatewayError::Quota(message) => Self::QuotaExceeded(message),
+            _ => Self::Rejected(err.to_string()),
+        }
+    }
 }
 
 /// Result returned after processing outbound traffic.
@@ -28,6 +62,26 @@ pub struct GatewayResult {
 pub enum GatewayEvent {
     OutboundQueued(GatewayResult),
     InboundReady(Vec<InboundMessage>),
+    /// A fetched message was converted and stored in the inbox.
+    InboundStored {
+        uid: String,
+        message_id: MessageId,
+    },
+    /// A fetched message could not be converted and was quarantined.
+    InboundQuarantined {
+        uid: String,
+        error: String,
+    },
+    /// A fetched DSN or MDN was recorded as a report on a stored message.
+    InboundReport {
+        uid: String,
+        report: Report,
+    },
+    /// A fetched message over the hop limit was bounced to its sender.
+    InboundBounced {
+        uid: String,
+        hops: u32,
+    },
     ReportMapped(DeliveryReport),
 }
 
@@ -38,6 +92,8 @@ pub struct GatewayAdapter {
     smtp: GatewaySmtpClient,
     imap: GatewayImapClient,
     reports: ReportMapper,
+    max_hops: u32,
+    telemetry: Option<TelemetryManager>,
 }
 
 impl GatewayAdapter {
@@ -52,27 +108,142 @@ impl GatewayAdapter {
             smtp,
             imap,
             reports,
+            max_hops: 8,
+            telemetry: None,
         }
     }
 
+    /// Gateway crossings after which a message counts as looping; see
+    /// `gateway.security.maxHops`.
+    pub fn with_max_hops(mut self, max_hops: u32) -> Self {
+        self.max_hops = max_hops.max(1);
+        self
+    }
+
+    /// Count outbound messages into `x400_gateway_messages_total`.
+    pub fn with_telemetry(mut self, telemetry: TelemetryManager) -> Self {
+        self.telemetry = Some(telemetry);
+        self
+    }
+
     /// Map an O/R message to SMTP and send it over the relay.
-    #[instrument(name = "gateway.outbound", skip(self, recipients, subject, body))]
     pub fn outbound(
         &self,
-        _originator: &Address,
+        originator: &Address,
+        recipients: &[Address],
+        subject: &str,
+        body: &str,
+    ) -> Result<GatewayResult, GatewayError> {
+        self.outbound_with_attachments(originator, recipients, subject, body, Vec::new())
+    }
+
+    /// Like [`outbound`](Self::outbound), sending `attachments` as MIME parts
+    /// after the body. The originator's O/R address travels in
+    /// `X-X400-Originator` so a receiving gateway can restore it.
+    #[instrument(
+        name = "gateway.outbound",
+        skip(self, recipients, subject, body, attachments)
+    )]
+    pub fn outbound_with_attachments(
+        &self,
+        originator: &Address,
+        recipients: &[Address],
+        subject: &str,
+        body: &str,
+        attachments: Vec<MimeAttachment>,
+    ) -> Result<GatewayResult, GatewayError> {
+        let id = format!("gw-{}", subject.len());
+        self.counted(self.relay(
+            id,
+            0,
+            originator,
+            recipients,
+            subject,
+            body,
+            Vec::new(),
+            attachments,
+        ))
+    }
+
+    /// Send a stored X.400 message. Its id becomes the local part of the
+    /// `Message-ID`, and a disposition notification is requested from the
+    /// recipients, so MDNs coming back correlate to the stored message.
+    /// A message that arrived through the gateway is refused with
+    /// [`GatewayError::HopLimit`] once sending it would exceed the hop limit.
+    #[instrument(name = "gateway.send_message", skip(self, message, attachments))]
+    pub fn send_message(
+        &self,
+        message: &Message,
+        attachments: Vec<MimeAttachment>,
+    ) -> Result<GatewayResult, GatewayError> {
+        let envelope = &message.envelope;
+        let notify = self.mapper.map_or_to_rfc822(&envelope.sender);
+        self.counted(notify.map_err(GatewayError::from).and_then(|notify| {
+            self.relay(
+                envelope.id.to_string(),
+                envelope.gateway_hops,
+                &envelope.sender,
+                &envelope.recipients,
+                &envelope.subject,
+                &message.content.body,
+                vec![("Disposition-Notification-To".into(), notify)],
+                attachments,
+            )
+        }))
+    }
+
+    fn counted(
+        &self,
+        result: Result<GatewayResult, GatewayError>,
+    ) -> Result<GatewayResult, GatewayError> {
+        if let Some(telemetry) = &self.telemetry {
+            telemetry.record_gateway("outbound", result.is_ok());
+        }
+        result
+    }
+
+    #[allow(clippy::too_many_arguments)]
+    fn relay(
+        &self,
+        id: String,
+        hops: u32,
+        originator: &Address,
         recipients: &[Address],
         subject: &str,
         body: &str,
+        mut headers: Vec<(String, String)>,
+        attachments: Vec<MimeAttachment>,
     ) -> Result<GatewayResult, GatewayError> {
+        let hops = hops.saturating_add(1);
+        if hops > self.max_hops {
+            return Err(GatewayError::HopLimit {
+                hops,
+                limit: self.max_hops,
+            });
+        }
         let mut mapped = Vec::new();
         for recipient in recipients {
             mapped.push(self.mapper.map_or_to_rfc822(recipient)?);
         }
+        headers.insert(
+            0,
+            (
+                "X-X400-Originator".into(),
+                format!(
+                    "C={};O={};S={}",
+                    originator.country, originator.organization, originator.surname
+                ),
+            ),
+        );
+        headers.insert(1, (HOPS_HEADER.into(), hops.to_string()));
         let message = SmtpMessage {
-            id: format!("gw-{}", subject.len()),
+            id,
+            from: self.mapper.map_or_to_rfc822(originator)?,
             to: mapped.clone(),
             subject: subject.into(),
             body: body.into(),
+            headers,
+            attachments,
         };
         let outcome: SmtpSendOutcome = self.smtp.send(message)?;
         Ok(GatewayResult {
@@ -85,9 +256,83 @@ impl GatewayAdapter {
 
     /// Fetch inbound SMTP messages for conversion to X.400.
     #[instrument(name = "gateway.inbound", skip(self))]
-    pub fn inbound(&self, limit: usize) -> GatewayEvent {
-        let messages = self.imap.fetch(limit);
-        GatewayEvent::InboundReady(messages)
+    pub fn inbound(&self, limit: usize) -> Result<GatewayEvent, GatewayError> {
+        let messages = self.imap.fetch(limit)?;
+        Ok(GatewayEvent::InboundReady(messages))
+    }
+
+    /// Bounce `message` to its SMTP sender with a `5.4.6` (routing loop) DSN
+    /// when converting it would exceed the hop limit. `None` when the message
+    /// is within the limit and may be converted.
+    #[instrument(name = "gateway.bounce_loop", skip(self, message))]
+    pub fn bounce_looping(
+        &self,
+        message: &InboundMessage,
+    ) -> Option<Result<GatewayResult, GatewayError>> {
+        let hops = inbound::gateway_hops(&message.raw).saturating_add(1);
+        if hops <= self.max_hops {
+            return None;
+        }
+        let parsed = mime::parse(&message.raw);
+        let sender = inbound::mailbox(parsed.header("From").unwrap_or(&message.from)).to_string();
+        let recipients = parsed
+            .header("To")
+            .unwrap_or_default()
+            .split(',')
+            .map(inbound::mailbox)
+            .filter(|recipient| !recipient.is_empty())
+            .map(|recipient| DsnRecipient {
+                original_recipient: None,
+                final_recipient: TypedAddress::rfc822(recipient),
+                action: DsnAction::Failed,
+                status: "5.4.6".into(),
+                remote_mta: None,
+                diagnostic_code: Some(DiagnosticCode {
+                    diagnostic_type: X400_DIAGNOSTIC.into(),
+                    text: "transfer-failure; loop-detected".into(),
+                }),
+                last_attempt_date: Some(chrono::Utc::now()),
+            })
+            .collect::<Vec<_>>();
+        let dsn = Dsn {
+            envelope_id: parsed
+                .header("Message-ID")
+                .and_then(|value| inbound::msg_ids(value).next()),
+            reporting_mta: GATEWAY_MESSAGE_DOMAIN.into(),
+            arrival_date: None,
+            recipients,
+        };
+        let bounce = SmtpMessage {
+            id: format!("loop-{}", message.uid),
+            from: format!("postmaster@{GATEWAY_MESSAGE_DOMAIN}"),
+            to: vec![sender.clone()],
+            subject: "Delivery Status Notification (Failure)".into(),
+            body: format!(
+                "Your message \"{}\" crossed the SMTP/X.400 gateway {hops} times and was not \
+                 delivered. Check the routing between the two systems for a loop.\n",
+                message.subject
+            ),
+            headers: vec![
+                (HOPS_HEADER.into(), hops.to_string()),
+                ("Auto-Submitted".into(), "auto-replied".into()),
+            ],
+            attachments: vec![MimeAttachment {
+                filename: "delivery-status".into(),
+                mime_type: "message/delivery-status".into(),
+                data: dsn.status_fields().into_bytes(),
+            }],
+        };
+        Some(
+            self.smtp
+                .send(bounce)
+                .map_err(GatewayError::from)
+                .map(|outcome| GatewayResult {
+                    message_id: outcome.message_id,
+                    recipients: vec![sender],
+                    accepted: outcome.accepted,
+                    warnings: outcome.warnings,
+                }),
+        )
     }
 
     /// Convert a DSN payload to the internal delivery report.
@@ -97,6 +342,20 @@ impl GatewayAdapter {
         GatewayEvent::ReportMapped(report)
     }
 
+    /// Parse an RFC 3464 DSN into one X.400 report per final recipient,
+    /// correlated through `Original-Envelope-Id` or else `correlation_id`.
+    #[instrument(name = "gateway.dsn_reports", skip(self, payload))]
+    pub fn dsn_reports(
+        &self,
+        payload: &str,
+        correlation_id: &str,
+    ) -> Result<Vec<Report>, GatewayError> {
+        let dsn = Dsn::parse(payload)?;
+        Ok(self
+            .reports
+            .reports_from_dsn(&dsn, &self.mapper, correlation_id, chrono::Utc::now()))
+    }
+
     /// Convert an MDN payload to the internal representation.
     #[instrument(name = "gateway.mdn", skip(self, payload))]
     pub fn handle_mdn(&self, payload: &str, correlation_id: &str) -> GatewayEvent {
@@ -104,6 +363,26 @@ impl GatewayAdapter {
         GatewayEvent::ReportMapped(report)
     }
 
+    /// Parse an RFC 8098 MDN into the X.400 report it stands for, if any;
+    /// see [`ReportMapper::report_from_mdn`].
+    #[instrument(name = "gateway.mdn_report", skip(self, payload))]
+    pub fn mdn_report(
+        &self,
+        payload: &str,
+        correlation_id: &str,
+    ) -> Result<Option<Report>, GatewayError> {
+        let mdn = Mdn::parse(payload)?;
+        Ok(self
+            .reports
+            .report_from_mdn(&mdn, &self.mapper, correlation_id, chrono::Utc::now()))
+    }
+
+    /// The domain policy outbound mail is checked against, for
+    /// `GET /gateway/policy`; see [`DomainPolicy::effective`](crate::gateway::DomainPolicy::effective).
+    pub fn policy(&self, domain: Option<&str>) -> EffectivePolicy {
+        self.smtp.policy().effective(domain)
+    }
+
     /// Perform reverse address translation when ingesting SMTP messages.
     #[instrument(name = "gateway.map_sender", skip(self))]
     pub fn map_sender(&self, address: &str) -> Result<Address, GatewayError> {
@@ -112,6 +391,42 @@ impl GatewayAdapter {
     }
 }
 
+/// The gateway only sends: stored messages are relayed over SMTP, and there is
+/// no X.400 message store behind it to read from.
+impl MessageTransport for GatewayAdapter {
+    fn name(&self) -> &'static str {
+        "gateway"
+    }
+
+    fn submit(&self, message: Message) -> Result<MessageId, TransportError> {
+        self.send_message(&message, Vec::new())?;
+        Ok(message.envelope.id)
+    }
+
+    fn fetch(&self, _: &MessageId) -> Result<Option<Message>, TransportError> {
+        Err(unsupported("fetch"))
+    }
+
+    fn list(&self, _: &str) -> Result<Vec<Message>, TransportError> {
+        Err(unsupported("list"))
+    }
+
+    fn delete(&self, _: &MessageId) -> Result<bool, TransportError> {
+        Err(unsupported("delete"))
+    }
+
+    fn reports(&self, _: &MessageId) -> Result<Vec<Report>, TransportError> {
+        Err(unsupported("reports"))
+    }
+}
+
+fn unsupported(operation: &'static str) -> TransportError {
+    TransportError::Unsupported {
+        transport: "gateway",
+        operation,
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -135,6 +450,7 @@ mod tests {
                 username: None,
                 password: None,
                 rate_limit_per_minute: 10,
+                ..GatewaySmtpConfig::default()
             },
             vec!["example.com".into()],
         )
@@ -146,12 +462,14 @@ mod tests {
             port: 993,
             tls: true,
             mailbox: "Inbox".into(),
+            ..GatewayImapConfig::default()
         })
     }
 
     #[test]
     fn sends_outbound_messages() {
-        let adapter = GatewayAdapter::new(mapper(), smtp(), imap(), ReportMapper);
+        let smtp = smtp();
+        let adapter = GatewayAdapter::new(mapper(), smtp.clone(), imap(), ReportMapper);
         let originator = Address {
             country: "DE".into(),
             organization: "Org".into(),
@@ -167,6 +485,9 @@ mod tests {
             .expect("should send");
         assert!(result.accepted);
         assert_eq!(result.recipients[0], "receiver@example.com");
+        assert!(smtp.delivered()[0]
+            .headers
+            .contains(&(HOPS_HEADER.to_string(), "1".to_string())));
     }
 
     #[test]
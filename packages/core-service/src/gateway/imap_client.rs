@@ -1,6 +1,15 @@
+use std::fmt;
+use std::io::{self, BufRead, BufReader, Read, Write};
+use std::net::TcpStream;
 use std::sync::{Arc, Mutex};
+use std::time::Duration;
 
-use crate::config::GatewayImapConfig;
+use rustls::crypto::ring;
+use rustls::pki_types::ServerName;
+use rustls::{ClientConfig, ClientConnection, RootCertStore, StreamOwned};
+
+use crate::config::{GatewayImapConfig, GatewaySecurityConfig};
+use crate::gateway::inbound::{decode_words, mailbox, split_headers};
 
 /// Simplified inbound message representation fetched from IMAP.
 #[derive(Clone, Debug, PartialEq, Eq)]
@@ -11,39 +20,338 @@ pub struct InboundMessage {
     pub raw: String,
 }
 
+/// Error returned when the IMAP mailbox cannot be read.
+#[derive(Debug, thiserror::Error, PartialEq, Eq)]
+pub enum ImapError {
+    #[error("TLS is required but disabled in configuration")]
+    TlsRequired,
+    #[error("IMAP connection failed: {0}")]
+    Connection(String),
+    #[error("IMAP server refused {command}: {response}")]
+    Refused { command: String, response: String },
+    #[error("unexpected IMAP response: {0}")]
+    Protocol(String),
+}
+
+impl From<io::Error> for ImapError {
+    fn from(error: io::Error) -> Self {
+        Self::Connection(error.to_string())
+    }
+}
+
+/// Where [`GatewayImapClient`] takes inbound messages from.
+pub trait ImapSource: fmt::Debug + Send + Sync {
+    /// Take up to `limit` unseen messages; they are not returned again.
+    fn fetch(&self, limit: usize) -> Result<Vec<InboundMessage>, ImapError>;
+}
+
+/// Reads a mailbox on a real IMAP4rev1 server. Each fetch opens a session,
+/// selects `gateway.imap.mailbox`, takes the oldest unseen messages and then
+/// either flags them `\Seen` or moves them to `gateway.imap.moveTo`.
+#[derive(Clone)]
+pub struct ImapMailbox {
+    config: GatewayImapConfig,
+    tls: Option<Arc<ClientConfig>>,
+}
+
+impl fmt::Debug for ImapMailbox {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("ImapMailbox")
+            .field("host", &self.config.host)
+            .field("mailbox", &self.config.mailbox)
+            .finish_non_exhaustive()
+    }
+}
+
+impl ImapMailbox {
+    /// Connections use TLS from the first byte when `gateway.imap.tls` is
+    /// set; a plain-text mailbox is refused while
+    /// `gateway.security.enforceTls` is on.
+    pub fn new(
+        config: GatewayImapConfig,
+        security: &GatewaySecurityConfig,
+    ) -> Result<Self, ImapError> {
+        if !config.tls && security.enforce_tls {
+            return Err(ImapError::TlsRequired);
+        }
+        let tls = if config.tls {
+            let roots = RootCertStore {
+                roots: webpki_roots::TLS_SERVER_ROOTS.to_vec(),
+            };
+            let tls = ClientConfig::builder_with_provider(Arc::new(ring::default_provider()))
+                .with_safe_default_protocol_versions()
+                .map_err(|error| ImapError::Connection(error.to_string()))?
+                .with_root_certificates(roots)
+                .with_no_client_auth();
+            Some(Arc::new(tls))
+        } else {
+            None
+        };
+        Ok(Self { config, tls })
+    }
+
+    fn connect(&self) -> Result<ImapSession, ImapError> {
+        let stream = TcpStream::connect((self.config.host.as_str(), self.config.port))?;
+        let timeout = Some(Duration::from_millis(self.config.timeout_ms));
+        stream.set_read_timeout(timeout)?;
+        stream.set_write_timeout(timeout)?;
+        let stream: Box<dyn ReadWrite> = match &self.tls {
+            Some(tls) => {
+                let name = ServerName::try_from(self.config.host.clone())
+                    .map_err(|error| ImapError::Connection(error.to_string()))?;
+                let connection = ClientConnection::new(tls.clone(), name)
+                    .map_err(|error| ImapError::Connection(error.to_string()))?;
+                Box::new(StreamOwned::new(connection, stream))
+            }
+            None => Box::new(stream),
+        };
+        let mut session = ImapSession {
+            stream: BufReader::new(stream),
+            tag: 0,
+        };
+        let greeting = session.read_line()?;
+        if !greeting.starts_with("* OK") && !greeting.starts_with("* PREAUTH") {
+            return Err(ImapError::Protocol(greeting));
+        }
+        Ok(session)
+    }
+}
+
+impl ImapSource for ImapMailbox {
+    fn fetch(&self, limit: usize) -> Result<Vec<InboundMessage>, ImapError> {
+        let mut session = self.connect()?;
+        if let (Some(username), Some(password)) = (&self.config.username, &self.config.password) {
+            session.command(&format!("LOGIN {} {}", quote(username), quote(password)))?;
+        }
+        let capabilities = session.command("CAPABILITY")?;
+        let can_move = capabilities.iter().any(|response| {
+            response.line.starts_with("* CAPABILITY")
+                && response
+                    .line
+                    .split_whitespace()
+                    .any(|capability| capability.eq_ignore_ascii_case("MOVE"))
+        });
+        session.command(&format!("SELECT {}", quote(&self.config.mailbox)))?;
+
+        let mut uids: Vec<u32> = session
+            .command("UID SEARCH UNSEEN")?
+            .iter()
+            .filter_map(|response| response.line.strip_prefix("* SEARCH"))
+            .flat_map(|numbers| {
+                numbers
+                    .split_whitespace()
+                    .filter_map(|uid| uid.parse().ok())
+            })
+            .collect();
+        uids.sort_unstable();
+        uids.truncate(limit);
+
+        let mut messages = Vec::new();
+        for uid in &uids {
+            let responses = session.command(&format!("UID FETCH {uid} (UID BODY.PEEK[])"))?;
+            let Some(raw) = responses
+                .into_iter()
+                .find_map(|response| response.literals.into_iter().next())
+            else {
+                return Err(ImapError::Protocol(format!(
+                    "no body returned for UID {uid}"
+                )));
+            };
+            messages.push(inbound(uid.to_string(), &String::from_utf8_lossy(&raw)));
+        }
+
+        if !uids.is_empty() {
+            let set = uids
+                .iter()
+                .map(u32::to_string)
+                .collect::<Vec<_>>()
+                .join(",");
+            match &self.config.move_to {
+                Some(folder) if can_move => {
+                    session.command(&format!("UID MOVE {set} {}", quote(folder)))?;
+                }
+                Some(folder) => {
+                    session.command(&format!("UID COPY {set} {}", quote(folder)))?;
+                    session
+                        .command(&format!("UID STORE {set} +FLAGS.SILENT (\\Seen \\Deleted)"))?;
+                    session.command("EXPUNGE")?;
+                }
+                None => {
+                    session.command(&format!("UID STORE {set} +FLAGS.SILENT (\\Seen)"))?;
+                }
+            }
+        }
+        // The messages are already taken; a failed goodbye changes nothing.
+        let _ = session.command("LOGOUT");
+        Ok(messages)
+    }
+}
+
+fn inbound(uid: String, raw: &str) -> InboundMessage {
+    let (headers, _) = split_headers(raw);
+    let header = |name: &str| {
+        headers
+            .iter()
+            .find(|(key, _)| key.eq_ignore_ascii_case(name))
+            .map(|(_, value)| value.as_str())
+            .unwrap_or_default()
+    };
+    InboundMessage {
+        uid,
+        subject: decode_words(header("Subject")),
+        from: mailbox(header("From")).to_string(),
+        raw: raw.to_string(),
+    }
+}
+
+/// An IMAP quoted string.
+fn quote(value: &str) -> String {
+    format!("\"{}\"", value.replace('\\', "\\\\").replace('"', "\\\""))
+}
+
+trait ReadWrite: Read + Write + Send {}
+
+impl<T: Read + Write + Send> ReadWrite for T {}
+
+/// An untagged server response with the literals it carried, in order.
+struct ImapResponse {
+    line: String,
+    literals: Vec<Vec<u8>>,
+}
+
+struct ImapSession {
+    stream: BufReader<Box<dyn ReadWrite>>,
+    tag: u32,
+}
+
+impl ImapSession {
+    /// Send `command` and collect the untagged responses up to its tagged
+    /// completion, which must be `OK`.
+    fn command(&mut self, command: &str) -> Result<Vec<ImapResponse>, ImapError> {
+        self.tag += 1;
+        let tag = format!("A{}", self.tag);
+        let stream = self.stream.get_mut();
+        stream.write_all(format!("{tag} {command}\r\n").as_bytes())?;
+        stream.flush()?;
+
+        // Name the command in errors without the credentials of a LOGIN.
+        let verb = if command.starts_with("LOGIN ") {
+            "LOGIN".to_string()
+        } else {
+            command.split(' ').take(2).collect::<Vec<_>>().join(" ")
+        };
+        let mut responses = Vec::new();
+        loop {
+            let mut response = ImapResponse {
+                line: self.read_line()?,
+                literals: Vec::new(),
+            };
+            // A line ending in `{n}` is followed by n bytes of literal data
+            // and then the rest of the response.
+            while let Some(size) = literal_size(&response.line) {
+                let mut literal = vec![0; size];
+                self.stream.read_exact(&mut literal)?;
+                response.literals.push(literal);
+                let rest = self.read_line()?;
+                response.line.push_str(&rest);
+            }
+            if let Some(status) = response.line.strip_prefix(&format!("{tag} ")) {
+                if status.starts_with("OK") {
+                    return Ok(responses);
+                }
+                return Err(ImapError::Refused {
+                    command: verb,
+                    response: status.to_string(),
+                });
+            }
+            responses.push(response);
+        }
+    }
+
+    fn read_line(&mut self) -> Result<String, ImapError> {
+        let mut line = String::new();
+        if self.stream.read_line(&mut line)? == 0 {
+            return Err(ImapError::Connection("server closed the connection".into()));
+        }
+        Ok(line.trim_end_matches(['\r', '\n']).to_string())
+    }
+}
+
+fn literal_size(line: &str) -> Option<usize> {
+    line.strip_suffix('}')?.rsplit_once('{')?.1.parse().ok()
+}
+
+/// Keeps queued messages in memory; used when no IMAP server is configured
+/// and by tests.
+#[derive(Debug, Default)]
+pub struct MemoryMailbox {
+    queue: Mutex<Vec<InboundMessage>>,
+}
+
+impl ImapSource for MemoryMailbox {
+    fn fetch(&self, limit: usize) -> Result<Vec<InboundMessage>, ImapError> {
+        let mut drained = Vec::new();
+        if let Ok(mut queue) = self.queue.lock() {
+            let take = limit.min(queue.len());
+            for _ in 0..take {
+                if let Some(message) = queue.pop() {
+                    drained.push(message);
+                }
+            }
+        }
+        Ok(drained)
+    }
+}
+
 /// Minimal IMAP client abstraction used by tests and the gateway adapter.
 #[derive(Clone, Debug)]
 pub struct GatewayImapClient {
     config: GatewayImapConfig,
-    mailbox: Arc<Mutex<Vec<InboundMessage>>>,
+    mailbox: Arc<MemoryMailbox>,
+    source: Option<Arc<dyn ImapSource>>,
 }
 
 impl GatewayImapClient {
     pub fn new(config: GatewayImapConfig) -> Self {
         Self {
             config,
-            mailbox: Arc::new(Mutex::new(Vec::new())),
+            mailbox: Arc::new(MemoryMailbox::default()),
+            source: None,
         }
     }
 
+    /// A client that reads the configured server when `gateway.imap.receive`
+    /// is set, and the in-memory queue otherwise.
+    pub fn from_config(
+        config: GatewayImapConfig,
+        security: &GatewaySecurityConfig,
+    ) -> Result<Self, ImapError> {
+        let receive = config.receive;
+        let client = Self::new(config);
+        if !receive {
+            return Ok(client);
+        }
+        let mailbox = ImapMailbox::new(client.config.clone(), security)?;
+        Ok(client.with_source(Arc::new(mailbox)))
+    }
+
+    pub fn with_source(mut self, source: Arc<dyn ImapSource>) -> Self {
+        self.source = Some(source);
+        self
+    }
+
     pub fn enqueue(&self, message: InboundMessage) {
-        if let Ok(mut queue) = self.mailbox.lock() {
+        if let Ok(mut queue) = self.mailbox.queue.lock() {
             queue.push(message);
         }
     }
 
     /// Fetch unseen messages up to the requested limit.
-    pub fn fetch(&self, limit: usize) -> Vec<InboundMessage> {
-        let mut drained = Vec::new();
-        if let Ok(mut queue) = self.mailbox.lock() {
-            let take = limit.min(queue.len());
-            for _ in 0..take {
-                if let Some(message) = queue.pop() {
-                    drained.push(message);
-                }
-            }
+    pub fn fetch(&self, limit: usize) -> Result<Vec<InboundMessage>, ImapError> {
+        match &self.source {
+            Some(source) => source.fetch(limit),
+            None => self.mailbox.fetch(limit),
         }
-        drained
     }
 
     pub fn config(&self) -> &GatewayImapConfig {
@@ -62,6 +370,7 @@ mod tests {
             port: 993,
             tls: true,
             mailbox: "Inbox".into(),
+            ..GatewayImapConfig::default()
         });
         client.enqueue(InboundMessage {
             uid: "1".into(),
@@ -76,8 +385,160 @@ mod tests {
             raw: "raw2".into(),
         });
 
-        let fetched = client.fetch(1);
+        let fetched = client.fetch(1).unwrap();
         assert_eq!(fetched.len(), 1);
         assert_eq!(fetched[0].uid, "2");
     }
+
+    const MEMO: &str =
+        "From: Ops <ops@example.com>\r\nSubject: =?utf-8?q?Gr=C3=BC=C3=9Fe?=\r\n\r\nHello\r\n";
+
+    /// An IMAP server holding unseen UIDs 7 and 9 in `INBOX`, advertising
+    /// MOVE only when asked to. Every command it reads is reported back.
+    fn fake_server(with_move: bool) -> (u16, std::sync::mpsc::Receiver<String>) {
+        let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+        let port = listener.local_addr().unwrap().port();
+        let (commands, received) = std::sync::mpsc::channel();
+        std::thread::spawn(move || {
+            let (stream, _) = listener.accept().unwrap();
+            let mut reader = BufReader::new(stream.try_clone().unwrap());
+            let mut writer = stream;
+            writer.write_all(b"* OK fake IMAP4rev1\r\n").unwrap();
+            let mut line = String::new();
+            while reader.read_line(&mut line).unwrap_or(0) > 0 {
+                let (tag, command) = line.trim_end().split_once(' ').unwrap();
+                let (tag, command) = (tag.to_string(), command.to_string());
+                line.clear();
+                let untagged = if command == "CAPABILITY" {
+                    let extra = if with_move { " MOVE" } else { "" };
+                    format!("* CAPABILITY IMAP4rev1{extra}\r\n")
+                } else if command == "UID SEARCH UNSEEN" {
+                    "* SEARCH 9 7\r\n".to_string()
+                } else if let Some(uid) = command
+                    .strip_prefix("UID FETCH ")
+                    .and_then(|rest| rest.split(' ').next())
+                {
+                    format!(
+                        "* 1 FETCH (UID {uid} BODY[] {{{}}}\r\n{MEMO})\r\n",
+                        MEMO.len()
+                    )
+                } else {
+                    String::new()
+                };
+                let reply = if command.starts_with("SELECT \"Missing\"") {
+                    format!("{tag} NO no such mailbox\r\n")
+                } else {
+                    format!("{untagged}{tag} OK done\r\n")
+                };
+                let _ = commands.send(command);
+                writer.write_all(reply.as_bytes()).unwrap();
+            }
+        });
+        (port, received)
+    }
+
+    fn remote(port: u16, move_to: Option<&str>) -> GatewayImapClient {
+        GatewayImapClient::from_config(
+            GatewayImapConfig {
+                host: "127.0.0.1".into(),
+                port,
+                tls: false,
+                mailbox: "INBOX".into(),
+                username: Some("gateway".into()),
+                password: Some("se\"cret".into()),
+                receive: true,
+                move_to: move_to.map(str::to_string),
+                timeout_ms: 5_000,
+                ..GatewayImapConfig::default()
+            },
+            &GatewaySecurityConfig {
+                enforce_tls: false,
+                ..GatewaySecurityConfig::default()
+            },
+        )
+        .unwrap()
+    }
+
+    #[test]
+    fn fetches_unseen_mail_from_an_imap_server_and_marks_it_seen() {
+        let (port, commands) = fake_server(false);
+        let fetched = remote(port, None).fetch(10).unwrap();
+
+        let uids: Vec<&str> = fetched.iter().map(|message| message.uid.as_str()).collect();
+        assert_eq!(uids, ["7", "9"]);
+        assert_eq!(fetched[0].subject, "Grüße");
+        assert_eq!(fetched[0].from, "ops@example.com");
+        assert_eq!(fetched[0].raw, MEMO);
+
+        let commands: Vec<String> = commands.iter().collect();
+        assert_eq!(
+            commands,
+            [
+                "LOGIN \"gateway\" \"se\\\"cret\"",
+                "CAPABILITY",
+                "SELECT \"INBOX\"",
+                "UID SEARCH UNSEEN",
+                "UID FETCH 7 (UID BODY.PEEK[])",
+                "UID FETCH 9 (UID BODY.PEEK[])",
+                "UID STORE 7,9 +FLAGS.SILENT (\\Seen)",
+                "LOGOUT",
+            ]
+        );
+    }
+
+    #[test]
+    fn moves_fetched_mail_when_configured() {
+        let (port, commands) = fake_server(true);
+        assert_eq!(remote(port, Some("Gateway")).fetch(1).unwrap().len(), 1);
+        let commands: Vec<String> = commands.iter().collect();
+        assert!(commands.contains(&"UID MOVE 7 \"Gateway\"".to_string()));
+
+        let (port, commands) = fake_server(false);
+        remote(port, Some("Gateway")).fetch(1).unwrap();
+        let commands: Vec<String> = commands.iter().collect();
+        assert!(commands.ends_with(&[
+            "UID COPY 7 \"Gateway\"".to_string(),
+            "UID STORE 7 +FLAGS.SILENT (\\Seen \\Deleted)".to_string(),
+            "EXPUNGE".to_string(),
+            "LOGOUT".to_string(),
+        ]));
+    }
+
+    #[test]
+    fn refuses_plain_text_mailboxes_while_tls_is_enforced() {
+        let config = GatewayImapConfig {
+            tls: false,
+            receive: true,
+            ..GatewayImapConfig::default()
+        };
+        let err = GatewayImapClient::from_config(config.clone(), &GatewaySecurityConfig::default())
+            .unwrap_err();
+        assert_eq!(err, ImapError::TlsRequired);
+
+        let (port, _commands) = fake_server(false);
+        let client = GatewayImapClient::new(config).with_source(Arc::new(
+            ImapMailbox::new(
+                GatewayImapConfig {
+                    host: "127.0.0.1".into(),
+                    port,
+                    tls: false,
+                    mailbox: "Missing".into(),
+                    ..GatewayImapConfig::default()
+                },
+                &GatewaySecurityConfig {
+                    enforce_tls: false,
+                    ..GatewaySecurityConfig::default()
+                },
+            )
+            .unwrap(),
+        ));
+        let err = client.fetch(1).unwrap_err();
+        assert_eq!(
+            err,
+            ImapError::Refused {
+                command: "SELECT \"Missing\"".into(),
+                response: "NO no such mailbox".into(),
+            }
+        );
+    }
 }
@@ -0,0 +1,317 @@
+//! Conversion of fetched RFC 822 messages into stored X.400 messages.
+//!
+//! The body and attachments come from the [`mime`] parser, which decodes
+//! quoted-printable and base64 parts and converts text from the declared
+//! charset; RFC 2047 `Q` encoded words are decoded in the subject.
+//! `X-X400-Gateway-Hops` counts gateway crossings, so a message bounced back
+//! and forth between misconfigured routes can be told apart from new mail.
+
+use encoding_rs::{Encoding, UTF_8};
+
+use crate::clock::{SharedClock, SharedIds};
+use crate::gateway::address_map::AddressMapper;
+use crate::gateway::gateway_adapter::GatewayError;
+use crate::gateway::imap_client::InboundMessage;
+use crate::gateway::mime::{self, MimeAttachment};
+use crate::models::{
+    Message, MessageContent, MessageEnvelope, MessagePriority, MessageSensitivity, MessageStatus,
+};
+
+/// Header carrying how often a message crossed the SMTP/X.400 gateway.
+pub const HOPS_HEADER: &str = "X-X400-Gateway-Hops";
+
+/// Gateway crossings recorded in the headers of `raw`; 0 for mail that has
+/// not passed a gateway yet.
+pub fn gateway_hops(raw: &str) -> u32 {
+    let (headers, _) = split_headers(raw);
+    parse_hops(
+        headers
+            .iter()
+            .find(|(name, _)| name.eq_ignore_ascii_case(HOPS_HEADER))
+            .map(|(_, value)| value.as_str()),
+    )
+}
+
+/// Build the inbox message for `inbound`, mapping sender and recipients to
+/// O/R addresses. MIME attachments are dropped; see [`to_parts`].
+pub fn to_message(
+    inbound: &InboundMessage,
+    mapper: &AddressMapper,
+    ids: &SharedIds,
+    clock: &SharedClock,
+) -> Result<Message, GatewayError> {
+    to_parts(inbound, mapper, ids, clock).map(|(message, _)| message)
+}
+
+/// Like [`to_message`], also returning the MIME attachments for the caller
+/// to store and link into the message content.
+pub fn to_parts(
+    inbound: &InboundMessage,
+    mapper: &AddressMapper,
+    ids: &SharedIds,
+    clock: &SharedClock,
+) -> Result<(Message, Vec<MimeAttachment>), GatewayError> {
+    let parsed = mime::parse(&inbound.raw);
+    let header = |name: &str| parsed.header(name);
+
+    let sender = mapper.map_rfc822_to_or(mailbox(header("From").unwrap_or(&inbound.from)))?;
+    let mut recipients = Vec::new();
+    for recipient in header("To").unwrap_or_default().split(',') {
+        let recipient = mailbox(recipient);
+        if !recipient.is_empty() {
+            recipients.push(mapper.map_rfc822_to_or(recipient)?);
+        }
+    }
+    let subject = header("Subject")
+        .map(decode_words)
+        .unwrap_or_else(|| inbound.subject.clone());
+
+    let mut envelope = MessageEnvelope::stamped(&subject, sender, recipients, ids, clock);
+    envelope.folder = "inbox".into();
+    envelope.status = MessageStatus::Delivered;
+    envelope.priority = priority(header("X-Priority"), header("Importance"));
+    // Entering X.400 is itself a crossing.
+    envelope.gateway_hops = parse_hops(header(HOPS_HEADER)).saturating_add(1);
+    envelope.origin_id = header("Message-ID").and_then(|value| msg_ids(value).next());
+    // Some agents only send References; its last entry is the direct parent.
+    envelope.in_reply_to = header("In-Reply-To")
+        .and_then(|value| msg_ids(value).next())
+        .or_else(|| header("References").and_then(|value| msg_ids(value).last()));
+    if header("Sensitivity").is_some_and(|value| {
+        value.eq_ignore_ascii_case("personal") || value.eq_ignore_ascii_case("private")
+    }) {
+        envelope.sensitivity = MessageSensitivity::Personal;
+    }
+
+    let message = Message {
+        envelope,
+        content: MessageContent {
+            body: parsed.body,
+            attachments: Vec::new(),
+        },
+    };
+    Ok((message, parsed.attachments))
+}
+
+/// Quoted-printable encoding with hard line breaks kept as CRLF.
+pub fn encode_quoted_printable(bytes: &[u8]) -> String {
+    let mut encoded = String::new();
+    let mut line_length = 0;
+    let mut iter = bytes.iter().peekable();
+    while let Some(&byte) = iter.next() {
+        if byte == b'\n' {
+            encoded.push_str("\r\n");
+            line_length = 0;
+            continue;
+        }
+        if byte == b'\r' && iter.peek() == Some(&&b'\n') {
+            continue;
+        }
+        let trailing_space = (byte == b' ' || byte == b'\t')
+            && matches!(iter.peek(), None | Some(b'\r') | Some(b'\n'));
+        let literal =
+            ((33..=126).contains(&byte) && byte != b'=') || (byte == b' ' && !trailing_space);
+        let token = if literal {
+            (byte as char).to_string()
+        } else {
+            format!("={byte:02X}")
+        };
+        if line_length + token.len() > 75 {
+            encoded.push_str("=\r\n");
+            line_length = 0;
+        }
+        line_length += token.len();
+        encoded.push_str(&token);
+    }
+    encoded
+}
+
+pub fn decode_quoted_printable(text: &str) -> Vec<u8> {
+    let bytes = text.as_bytes();
+    let mut decoded = Vec::with_capacity(bytes.len());
+    let mut index = 0;
+    while index < bytes.len() {
+        match bytes[index] {
+            b'=' if bytes[index + 1..].starts_with(b"\r\n") => index += 3,
+            b'=' if bytes[index + 1..].starts_with(b"\n") => index += 2,
+            b'=' => match bytes
+                .get(index + 1..index + 3)
+                .and_then(|hex| std::str::from_utf8(hex).ok())
+                .and_then(|hex| u8::from_str_radix(hex, 16).ok())
+            {
+                Some(byte) => {
+                    decoded.push(byte);
+                    index += 3;
+                }
+                None => {
+                    decoded.push(b'=');
+                    index += 1;
+                }
+            },
+            byte => {
+                decoded.push(byte);
+                index += 1;
+            }
+        }
+    }
+    decoded
+}
+
+/// Encode a header value as an RFC 2047 `Q` word when it is not plain ASCII.
+pub fn encode_word(value: &str) -> String {
+    if value.is_ascii() {
+        return value.to_string();
+    }
+    let mut encoded = String::from("=?utf-8?Q?");
+    for byte in value.bytes() {
+        match byte {
+            b' ' => encoded.push('_'),
+            b'0'..=b'9' | b'a'..=b'z' | b'A'..=b'Z' => encoded.push(byte as char),
+            _ => encoded.push_str(&format!("={byte:02X}")),
+        }
+    }
+    encoded.push_str("?=");
+    encoded
+}
+
+pub(crate) fn decode_words(value: &str) -> String {
+    let mut decoded = String::new();
+    let mut rest = value;
+    while let Some(start) = rest.find("=?") {
+        let Some((charset, encoding, text, tail)) = split_word(&rest[start + 2..]) else {
+            break;
+        };
+        decoded.push_str(&rest[..start]);
+        if encoding.eq_ignore_ascii_case("q") {
+            let bytes = decode_quoted_printable(&text.replace('_', " "));
+            let encoding = Encoding::for_label(charset.as_bytes()).unwrap_or(UTF_8);
+            decoded.push_str(&encoding.decode_without_bom_handling(&bytes).0);
+        } else {
+            decoded.push_str(&rest[start..rest.len() - tail.len()]);
+        }
+        // Whitespace between adjacent encoded words is not part of the text.
+        rest = if tail.trim_start().starts_with("=?") {
+            tail.trim_start()
+        } else {
+            tail
+        };
+    }
+    decoded.push_str(rest);
+    decoded
+}
+
+fn split_word(word: &str) -> Option<(&str, &str, &str, &str)> {
+    let (charset, rest) = word.split_once('?')?;
+    let (encoding, rest) = rest.split_once('?')?;
+    let (text, tail) = rest.split_once("?=")?;
+    Some((charset, encoding, text, tail))
+}
+
+pub(crate) fn split_headers(raw: &str) -> (Vec<(String, String)>, &str) {
+    let (head, body) = match raw.find("\r\n\r\n") {
+        Some(index) => (&raw[..index], &raw[index + 4..]),
+        None => match raw.find("\n\n") {
+            Some(index) => (&raw[..index], &raw[index + 2..]),
+            None => (raw, ""),
+        },
+    };
+    let mut headers: Vec<(String, String)> = Vec::new();
+    for line in head.lines() {
+        if line.starts_with([' ', '\t']) {
+            if let Some((_, value)) = headers.last_mut() {
+                value.push(' ');
+                value.push_str(line.trim());
+            }
+        } else if let Some((name, value)) = line.split_once(':') {
+            headers.push((name.trim().to_string(), value.trim().to_string()));
+        }
+    }
+    (headers, body)
+}
+
+pub(crate) fn mailbox(value: &str) -> &str {
+    match (value.find('<'), value.rfind('>')) {
+        (Some(start), Some(end)) if start < end => &value[start + 1..end],
+        _ => value.trim(),
+    }
+}
+
+fn parse_hops(value: Option<&str>) -> u32 {
+    value
+        .and_then(|value| value.trim().parse().ok())
+        .unwrap_or(0)
+}
+
+/// `msg-id`s (`<left@right>`) in a header value, without the angle brackets.
+pub(crate) fn msg_ids(value: &str) -> impl Iterator<Item = String> + '_ {
+    value.split('<').skip(1).filter_map(|part| {
+        let id = part.split_once('>')?.0.trim();
+        (!id.is_empty()).then(|| id.to_string())
+    })
+}
+
+fn priority(x_priority: Option<&str>, importance: Option<&str>) -> MessagePriority {
+    match x_priority.and_then(|value| value.trim().chars().next()) {
+        Some('1' | '2') => return MessagePriority::High,
+        Some('4' | '5') => return MessagePriority::Low,
+        Some(_) => return MessagePriority::Normal,
+        None => {}
+    }
+    match importance.map(str::to_ascii_lowercase).as_deref() {
+        Some("high") => MessagePriority::High,
+        Some("low") => MessagePriority::Low,
+        _ => MessagePriority::Normal,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::gateway::address_map::AddressMappingRule;
+    use std::collections::HashMap;
+
+    #[test]
+    fn converts_quoted_printable_latin1_messages() {
+        let mapper = AddressMapper::new(
+            vec![AddressMappingRule::new("{S}@{O}.example")],
+            HashMap::new(),
+        );
+        let body = encoding_rs::WINDOWS_1252.encode("Grüße aus Köln\n").0;
+        let raw = format!(
+            "From: Sender <sender@org.example>\r\nTo: a@org.example, b@org.example\r\n\
+             Subject: {}\r\nX-Priority: 1 (Highest)\r\n\
+             Message-ID: <r2@org.example>\r\nReferences: <q0@x.example> <q1@x.example>\r\n\
+             Content-Type: text/plain; charset=\"iso-8859-1\"\r\n\
+             Content-Transfer-Encoding: quoted-printable\r\n\r\n{}",
+            encode_word("Größe prüfen"),
+            encode_quoted_printable(&body)
+        );
+        let inbound = InboundMessage {
+            uid: "1".into(),
+            subject: String::new(),
+            from: String::new(),
+            raw,
+        };
+        let message = to_message(
+            &inbound,
+            &mapper,
+            &SharedIds::default(),
+            &SharedClock::default(),
+        )
+        .unwrap();
+        assert_eq!(message.envelope.subject, "Größe prüfen");
+        assert_eq!(message.content.body, "Grüße aus Köln\n");
+        assert_eq!(message.envelope.recipients.len(), 2);
+        assert_eq!(message.envelope.sender.surname, "Sender");
+        assert_eq!(message.envelope.priority, MessagePriority::High);
+        assert_eq!(message.envelope.folder, "inbox");
+        assert_eq!(
+            message.envelope.origin_id.as_deref(),
+            Some("r2@org.example")
+        );
+        assert_eq!(
+            message.envelope.in_reply_to.as_deref(),
+            Some("q1@x.example")
+        );
+    }
+}
@@ -0,0 +1,435 @@
+//! Inbound gateway ingestion with a quarantine for failed conversions.
+//!
+//! Messages fetched over IMAP that cannot be converted (unmapped addresses,
+//! parser bugs) are parked with their raw RFC 822 text instead of being
+//! dropped. Once the cause is fixed an operator runs
+//! `POST /admin/gateway/quarantine/reprocess`, which feeds the selected items
+//! through the current conversion code and mapping rules again and keeps a
+//! per-item outcome report for the job.
+//!
+//! S/MIME messages are opened before conversion; the stored message gets the
+//! inner content and records its signature and encryption status.
+//!
+//! Messages the X.400 side hands over already converted, such as those the
+//! vendor SDK announces, go through [`InboundIngestor::ingest_native`]: their
+//! CMS content is opened the same way, and one that crossed the gateway more
+//! often than `gateway.security.maxHops` allows is filed in `quarantine`
+//! instead of the inbox.
+//!
+//! With [`InboundIngestor::with_quota`] every message is admitted by the
+//! mailbox quota before it is filed: oversize content is held, and a
+//! message the quota refuses is quarantined like a failed conversion (or,
+//! for a native message, reported as an error) unless it is exempt.
+
+use std::collections::BTreeMap;
+use std::sync::{Arc, Mutex, PoisonError, RwLock};
+
+use chrono::{DateTime, Utc};
+use serde::Serialize;
+use tracing::{info, warn};
+use uuid::Uuid;
+
+use crate::attachments::AttachmentStore;
+use crate::clock::{SharedClock, SharedIds};
+use crate::gateway::address_map::AddressMapper;
+use crate::gateway::gateway_adapter::GatewayError;
+use crate::gateway::imap_client::InboundMessage;
+use crate::gateway::{inbound, mime};
+use crate::models::{Message, MessageAttachment, MessageId, MessageStatus};
+use crate::quota::QuotaPolicy;
+use crate::smime::SmimeService;
+use crate::store::StoreManager;
+
+/// Reprocess jobs kept for `GET /admin/gateway/quarantine/jobs/{id}`.
+const JOB_HISTORY: usize = 32;
+
+/// An inbound message that failed conversion.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct QuarantinedInbound {
+    pub id: Uuid,
+    pub uid: String,
+    pub from: String,
+    pub subject: String,
+    #[serde(skip_serializing)]
+    pub raw: String,
+    pub error: String,
+    pub quarantined_at: DateTime<Utc>,
+    /// Conversion attempts so far, including the original ingest.
+    pub attempts: u32,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "camelCase", tag = "result")]
+pub enum ReprocessResult {
+    Imported { message_id: MessageId },
+    Failed { error: String },
+    NotFound,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ReprocessOutcome {
+    pub quarantine_id: Uuid,
+    #[serde(flatten)]
+    pub result: ReprocessResult,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ReprocessReport {
+    pub job_id: Uuid,
+    pub started_at: DateTime<Utc>,
+    pub finished_at: DateTime<Utc>,
+    pub imported: usize,
+    pub failed: usize,
+    pub outcomes: Vec<ReprocessOutcome>,
+}
+
+#[derive(Clone)]
+pub struct InboundIngestor {
+    mapper: Arc<RwLock<AddressMapper>>,
+    store: StoreManager,
+    attachments: Option<AttachmentStore>,
+    quota: Option<QuotaPolicy>,
+    smime: SmimeService,
+    quarantine: Arc<Mutex<BTreeMap<Uuid, QuarantinedInbound>>>,
+    jobs: Arc<Mutex<Vec<ReprocessReport>>>,
+    max_hops: u32,
+    ids: SharedIds,
+    clock: SharedClock,
+}
+
+impl InboundIngestor {
+    pub fn new(mapper: AddressMapper, store: StoreManager) -> Self {
+        Self {
+            mapper: Arc::new(RwLock::new(mapper)),
+            store,
+            attachments: None,
+            quota: None,
+            smime: SmimeService::disabled(),
+            quarantine: Arc::new(Mutex::new(BTreeMap::new())),
+            jobs: Arc::new(Mutex::new(Vec::new())),
+            max_hops: 8,
+            ids: SharedIds::default(),
+            clock: SharedClock::default(),
+        }
+    }
+
+    /// Keep MIME attachments of converted messages; without a store only
+    /// the body is imported.
+    pub fn with_attachments(mut self, attachments: AttachmentStore) -> Self {
+        self.attachments = Some(attachments);
+        self
+    }
+
+    /// Admit every message through `quota` before it is filed.
+    pub fn with_quota(mut self, quota: QuotaPolicy) -> Self {
+        self.quota = Some(quota);
+        self
+    }
+
+    /// Verify and decrypt S/MIME messages; without it they are imported as
+    /// fetched, with the CMS parts as attachments.
+    pub fn with_smime(mut self, smime: SmimeService) -> Self {
+        self.smime = smime;
+        self
+    }
+
+    /// Gateway crossings a native message may have made; see
+    /// `gateway.security.maxHops`.
+    pub fn with_max_hops(mut self, max_hops: u32) -> Self {
+        self.max_hops = max_hops.max(1);
+        self
+    }
+
+    pub fn with_ids(mut self, ids: SharedIds) -> Self {
+        self.ids = ids;
+        self
+    }
+
+    pub fn with_clock(mut self, clock: SharedClock) -> Self {
+        self.clock = clock;
+        self
+    }
+
+    /// Swap in reloaded mapping rules; later ingests and reprocess jobs use them.
+    pub fn set_mapper(&self, mapper: AddressMapper) {
+        if let Ok(mut current) = self.mapper.write() {
+            *current = mapper;
+        }
+    }
+
+    /// Convert `message` into the inbox, quarantining it if conversion fails.
+    pub fn ingest(&self, message: &InboundMessage) -> Result<MessageId, GatewayError> {
+        self.convert(message).inspect_err(|err| {
+            let id = self.ids.uuid();
+            warn!(
+                target = "gateway.inbound",
+                uid = %message.uid,
+                quarantine = %id,
+                "quarantined: {err}"
+            );
+            let entry = QuarantinedInbound {
+                id,
+                uid: message.uid.clone(),
+                from: message.from.clone(),
+                subject: message.subject.clone(),
+                raw: message.raw.clone(),
+                error: err.to_string(),
+                quarantined_at: self.clock.now(),
+                attempts: 1,
+            };
+            if let Ok(mut quarantine) = self.quarantine.lock() {
+                quarantine.insert(id, entry);
+            }
+        })
+    }
+
+    /// File a message received over X.400 in the inbox. A message over the
+    /// hop limit is kept in the `quarantine` folder and reported as an error.
+    pub fn ingest_native(&self, mut message: Message) -> Result<MessageId, GatewayError> {
+        self.open_native(&mut message);
+        let id = message.envelope.id.clone();
+        let hops = message.envelope.gateway_hops;
+        if hops > self.max_hops {
+            warn!(target = "gateway.inbound", message = %id, hops, "looping message quarantined");
+            message.envelope.folder = "quarantine".into();
+            self.store.save(message);
+            return Err(GatewayError::HopLimit {
+                hops,
+                limit: self.max_hops,
+            });
+        }
+        message.envelope.folder = "inbox".into();
+        message.envelope.status = MessageStatus::Delivered;
+        self.admit(&mut message)?;
+        self.store.save(message);
+        Ok(id)
+    }
+
+    fn admit(&self, message: &mut Message) -> Result<(), GatewayError> {
+        match &self.quota {
+            Some(quota) => quota
+                .admit(message)
+                .map(drop)
+                .map_err(|err| GatewayError::Quota(err.to_string())),
+            None => Ok(()),
+        }
+    }
+
+    /// Replace the body of a message that arrived as CMS with the opened
+    /// content, recording its signature and encryption status.
+    fn open_native(&self, message: &mut Message) {
+        let Some(cms) = &message.envelope.security.cms else {
+            return;
+        };
+        let raw = format!(
+            "Content-Type: application/pkcs7-mime; name=smime.p7m\r\n\
+             Content-Transfer-Encoding: base64\r\n\r\n{cms}\r\n"
+        );
+        let Some(opened) = self.smime.open(&raw) else {
+            return;
+        };
+        if opened.raw != raw {
+            message.content.body = mime::parse(&opened.raw).body;
+        }
+        message.envelope.security.signature = opened.signature;
+        message.envelope.security.encryption = opened.encryption;
+    }
+
+    pub fn quarantined(&self) -> Vec<QuarantinedInbound> {
+        self.quarantine
+            .lock()
+            .map(|quarantine| quarantine.values().cloned().collect())
+            .unwrap_or_default()
+    }
+
+    /// Re-run conversion over the selected quarantine entries, or all of them
+    /// when `selection` is `None`. Successes land in the inbox and leave the
+    /// quarantine; failures stay with their latest error.
+    pub fn reprocess(&self, selection: Option<&[Uuid]>) -> ReprocessReport {
+        let started_at = self.clock.now();
+        let targets: Vec<Uuid> = match selection {
+            Some(ids) => ids.to_vec(),
+            None => self
+                .quarantine
+                .lock()
+                .map(|quarantine| quarantine.keys().copied().collect())
+                .unwrap_or_default(),
+        };
+
+        let mut outcomes = Vec::with_capacity(targets.len());
+        for quarantine_id in targets {
+            let entry = self
+                .quarantine
+                .lock()
+                .ok()
+                .and_then(|quarantine| quarantine.get(&quarantine_id).cloned());
+            let result = match entry {
+                None => ReprocessResult::NotFound,
+                Some(entry) => self.retry(entry),
+            };
+            outcomes.push(ReprocessOutcome {
+                quarantine_id,
+                result,
+            });
+        }
+
+        let count = |wanted: fn(&ReprocessResult) -> bool| {
+            outcomes
+                .iter()
+                .filter(|outcome| wanted(&outcome.result))
+                .count()
+        };
+        let report = ReprocessReport {
+            job_id: self.ids.uuid(),
+            started_at,
+            finished_at: self.clock.now(),
+            imported: count(|result| matches!(result, ReprocessResult::Imported { .. })),
+            failed: count(|result| matches!(result, ReprocessResult::Failed { .. })),
+            outcomes,
+        };
+        info!(
+            target = "gateway.inbound",
+            job = %report.job_id,
+            imported = report.imported,
+            failed = report.failed,
+            "quarantine reprocessed"
+        );
+        if let Ok(mut jobs) = self.jobs.lock() {
+            if jobs.len() == JOB_HISTORY {
+                jobs.remove(0);
+            }
+            jobs.push(report.clone());
+        }
+        report
+    }
+
+    pub fn job(&self, id: Uuid) -> Option<ReprocessReport> {
+        self.jobs
+            .lock()
+            .ok()?
+            .iter()
+            .find(|job| job.job_id == id)
+            .cloned()
+    }
+
+    fn retry(&self, entry: QuarantinedInbound) -> ReprocessResult {
+        let inbound = InboundMessage {
+            uid: entry.uid.clone(),
+            subject: entry.subject.clone(),
+            from: entry.from.clone(),
+            raw: entry.raw.clone(),
+        };
+        let result = self.convert(&inbound);
+        let Ok(mut quarantine) = self.quarantine.lock() else {
+            return ReprocessResult::NotFound;
+        };
+        match result {
+            Ok(message_id) => {
+                quarantine.remove(&entry.id);
+                ReprocessResult::Imported { message_id }
+            }
+            Err(err) => {
+                if let Some(stored) = quarantine.get_mut(&entry.id) {
+                    stored.attempts += 1;
+                    stored.error = err.to_string();
+                }
+                ReprocessResult::Failed {
+                    error: err.to_string(),
+                }
+            }
+        }
+    }
+
+    fn convert(&self, message: &InboundMessage) -> Result<MessageId, GatewayError> {
+        let opened = self.smime.open(&message.raw);
+        let unwrapped;
+        let source = match &opened {
+            Some(opened) => {
+                unwrapped = InboundMessage {
+                    raw: opened.raw.clone(),
+                    ..message.clone()
+                };
+                &unwrapped
+            }
+            None => message,
+        };
+        let (mut converted, parts) = {
+            let mapper = self.mapper.read().unwrap_or_else(PoisonError::into_inner);
+            inbound::to_parts(source, &mapper, &self.ids, &self.clock)?
+        };
+        if let Some(opened) = opened {
+            converted.envelope.security.signature = opened.signature;
+            converted.envelope.security.encryption = opened.encryption;
+        }
+        if let Some(store) = &self.attachments {
+            for part in parts {
+                let stored = store
+                    .put(&part.filename, part.data.as_slice())
+                    .map_err(|err| GatewayError::Storage(err.to_string()))?;
+                converted.content.attachments.push(MessageAttachment {
+                    id: stored.id,
+                    filename: part.filename,
+                    mime_type: part.mime_type,
+                    size: stored.size,
+                });
+            }
+        }
+        let id = converted.envelope.id.clone();
+        self.admit(&mut converted)?;
+        self.store.save(converted);
+        Ok(id)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::clock::SequentialIds;
+    use crate::gateway::address_map::AddressMappingRule;
+
+    #[test]
+    fn reprocess_imports_messages_fixed_by_new_rules() {
+        let store = StoreManager::new();
+        let ingestor = InboundIngestor::new(AddressMapper::default(), store.clone())
+            .with_ids(SharedIds::new(SequentialIds::default()));
+        let inbound = |uid: &str, from: &str| InboundMessage {
+            uid: uid.into(),
+            subject: "Shipment".into(),
+            from: from.into(),
+            raw: format!(
+                "From: {from}\r\nTo: ops@modern.example\r\nSubject: Shipment\r\n\r\nETA Friday\r\n"
+            ),
+        };
+
+        assert!(ingestor
+            .ingest(&inbound("1", "anna@modern.example"))
+            .is_err());
+        assert!(ingestor
+            .ingest(&inbound("2", "bob.smith@elsewhere.example"))
+            .is_err());
+        let quarantined = ingestor.quarantined();
+        assert_eq!(quarantined.len(), 2);
+
+        ingestor.set_mapper(AddressMapper::new(
+            vec![AddressMappingRule::new("{S}@{O}.example")],
+            Default::default(),
+        ));
+        let missing = Uuid::nil();
+        let report = ingestor.reprocess(Some(&[quarantined[0].id, quarantined[1].id, missing]));
+        assert_eq!((report.imported, report.failed), (1, 1));
+        let ReprocessResult::Imported { message_id } = &report.outcomes[0].result else {
+            panic!("expected import, got {:?}", report.outcomes[0]);
+        };
+        assert_eq!(store.get(message_id).unwrap().envelope.folder, "inbox");
+        assert_eq!(report.outcomes[2].result, ReprocessResult::NotFound);
+        assert_eq!(ingestor.job(report.job_id), Some(report));
+
+        let rest = ingestor.quarantined();
+        assert_eq!(rest.len(), 1);
+        assert_eq!(rest[0].uid, "2");
+        assert_eq!(rest[0].attempts, 2);
+    }
+}
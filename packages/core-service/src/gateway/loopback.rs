@@ -0,0 +1,447 @@
+//! Loopback SMTP/IMAP counterpart for gateway integration tests and demos.
+//!
+//! Enabled with the `loopback` feature. Both sides are real TCP listeners on
+//! `127.0.0.1`: the SMTP side speaks the subset of RFC 5321 the gateway sends,
+//! the IMAP side the subset of RFC 3501 (plus `MOVE`) that
+//! [`GatewayImapClient`] uses to read a mailbox, so messages injected here go
+//! through the same client code as a production server.
+
+use std::io::{self, BufRead, BufReader, Write};
+use std::net::{SocketAddr, TcpListener, TcpStream};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex};
+use std::thread;
+
+use crate::config::{GatewayImapConfig, GatewaySecurityConfig, GatewaySmtpConfig};
+use crate::gateway::imap_client::{GatewayImapClient, ImapError};
+
+/// Message accepted by the loopback SMTP listener.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct LoopbackMail {
+    pub from: String,
+    pub to: Vec<String>,
+    pub data: String,
+}
+
+/// Message held by the loopback IMAP listener.
+#[derive(Clone, Debug, PartialEq, Eq)]
+struct StoredMessage {
+    uid: u32,
+    folder: String,
+    raw: String,
+    seen: bool,
+    deleted: bool,
+}
+
+#[derive(Debug, Default)]
+struct Mailboxes {
+    next_uid: u32,
+    messages: Vec<StoredMessage>,
+}
+
+impl Mailboxes {
+    fn add(&mut self, folder: &str, raw: String) -> u32 {
+        self.next_uid += 1;
+        self.messages.push(StoredMessage {
+            uid: self.next_uid,
+            folder: folder.to_string(),
+            raw,
+            seen: false,
+            deleted: false,
+        });
+        self.next_uid
+    }
+}
+
+/// Embedded SMTP sink plus an IMAP server for injected mail.
+pub struct LoopbackGateway {
+    address: SocketAddr,
+    imap_address: SocketAddr,
+    received: Arc<Mutex<Vec<LoopbackMail>>>,
+    mailboxes: Arc<Mutex<Mailboxes>>,
+    shutdown: Arc<AtomicBool>,
+}
+
+impl LoopbackGateway {
+    /// Mailbox injected messages are delivered to.
+    pub const INBOX: &'static str = "Inbox";
+
+    /// Bind ephemeral SMTP and IMAP ports on localhost and start accepting
+    /// sessions on both.
+    pub fn start() -> io::Result<Self> {
+        let smtp = TcpListener::bind("127.0.0.1:0")?;
+        let imap = TcpListener::bind("127.0.0.1:0")?;
+        let address = smtp.local_addr()?;
+        let imap_address = imap.local_addr()?;
+        let received = Arc::new(Mutex::new(Vec::new()));
+        let mailboxes = Arc::new(Mutex::new(Mailboxes::default()));
+        let shutdown = Arc::new(AtomicBool::new(false));
+
+        let sink = received.clone();
+        accept(smtp, shutdown.clone(), move |stream| {
+            serve_session(stream, sink.clone())
+        });
+        let store = mailboxes.clone();
+        accept(imap, shutdown.clone(), move |stream| {
+            serve_imap(stream, store.clone())
+        });
+
+        Ok(Self {
+            address,
+            imap_address,
+            received,
+            mailboxes,
+            shutdown,
+        })
+    }
+
+    /// Address of the SMTP listener.
+    pub fn address(&self) -> SocketAddr {
+        self.address
+    }
+
+    pub fn imap_address(&self) -> SocketAddr {
+        self.imap_address
+    }
+
+    /// SMTP configuration pointing the gateway at this listener.
+    pub fn smtp_config(&self) -> GatewaySmtpConfig {
+        GatewaySmtpConfig {
+            host: self.address.ip().to_string(),
+            port: self.address.port(),
+            tls: false,
+            ..GatewaySmtpConfig::default()
+        }
+    }
+
+    /// IMAP configuration reading [`Self::INBOX`] from this listener.
+    pub fn imap_config(&self) -> GatewayImapConfig {
+        GatewayImapConfig {
+            host: self.imap_address.ip().to_string(),
+            port: self.imap_address.port(),
+            tls: false,
+            mailbox: Self::INBOX.into(),
+            receive: true,
+            ..GatewayImapConfig::default()
+        }
+    }
+
+    /// IMAP client reading this listener over plain TCP.
+    pub fn imap_client(&self) -> Result<GatewayImapClient, ImapError> {
+        let security = GatewaySecurityConfig {
+            enforce_tls: false,
+            ..GatewaySecurityConfig::default()
+        };
+        GatewayImapClient::from_config(self.imap_config(), &security)
+    }
+
+    /// Messages received over SMTP so far.
+    pub fn received(&self) -> Vec<LoopbackMail> {
+        self.received
+            .lock()
+            .map(|mail| mail.clone())
+            .unwrap_or_default()
+    }
+
+    pub fn clear(&self) {
+        if let Ok(mut mail) = self.received.lock() {
+            mail.clear();
+        }
+    }
+
+    /// Deliver `raw` to [`Self::INBOX`] as an unseen message and return the
+    /// UID it was given.
+    pub fn inject(&self, raw: impl Into<String>) -> u32 {
+        self.mailboxes
+            .lock()
+            .map(|mut mailboxes| mailboxes.add(Self::INBOX, raw.into()))
+            .unwrap_or_default()
+    }
+
+    /// UIDs currently held in `folder`, including messages already seen.
+    pub fn folder(&self, folder: &str) -> Vec<u32> {
+        self.mailboxes
+            .lock()
+            .map(|mailboxes| {
+                mailboxes
+                    .messages
+                    .iter()
+                    .filter(|message| message.folder == folder)
+                    .map(|message| message.uid)
+                    .collect()
+            })
+            .unwrap_or_default()
+    }
+}
+
+impl Drop for LoopbackGateway {
+    fn drop(&mut self) {
+        self.shutdown.store(true, Ordering::SeqCst);
+        // Wake the accept loops so the listener threads can observe the flag.
+        let _ = TcpStream::connect(self.address);
+        let _ = TcpStream::connect(self.imap_address);
+    }
+}
+
+/// Serve every connection on `listener` with `session` until `stop` is set.
+fn accept<F>(listener: TcpListener, stop: Arc<AtomicBool>, session: F)
+where
+    F: Fn(TcpStream) -> io::Result<()> + Clone + Send + 'static,
+{
+    thread::spawn(move || {
+        for stream in listener.incoming() {
+            if stop.load(Ordering::SeqCst) {
+                break;
+            }
+            if let Ok(stream) = stream {
+                let session = session.clone();
+                thread::spawn(move || {
+                    if let Err(err) = session(stream) {
+                        tracing::debug!(target = "gateway.loopback", "session ended: {err}");
+                    }
+                });
+            }
+        }
+    });
+}
+
+fn serve_session(stream: TcpStream, sink: Arc<Mutex<Vec<LoopbackMail>>>) -> io::Result<()> {
+    let mut writer = stream.try_clone()?;
+    let mut reader = BufReader::new(stream);
+    writer.write_all(b"220 loopback ESMTP ready\r\n")?;
+
+    let mut from = String::new();
+    let mut to = Vec::new();
+    let mut line = String::new();
+    loop {
+        line.clear();
+        if reader.read_line(&mut line)? == 0 {
+            return Ok(());
+        }
+        let command = line.trim_end();
+        let verb = command
+            .split_whitespace()
+            .next()
+            .unwrap_or("")
+            .to_ascii_uppercase();
+        match verb.as_str() {
+            "EHLO" => {
+                writer.write_all(b"250-loopback\r\n250-AUTH PLAIN LOGIN\r\n250 8BITMIME\r\n")?
+            }
+            "HELO" => writer.write_all(b"250 loopback\r\n")?,
+            "AUTH" => writer.write_all(b"235 2.7.0 Authentication successful\r\n")?,
+            "MAIL" => {
+                from = angle_address(command);
+                to.clear();
+                writer.write_all(b"250 2.1.0 OK\r\n")?;
+            }
+            "RCPT" => {
+                to.push(angle_address(command));
+                writer.write_all(b"250 2.1.5 OK\r\n")?;
+            }
+            "DATA" => {
+                writer.write_all(b"354 End data with <CR><LF>.<CR><LF>\r\n")?;
+                let data = read_data(&mut reader)?;
+                if let Ok(mut mail) = sink.lock() {
+                    mail.push(LoopbackMail {
+                        from: from.clone(),
+                        to: to.clone(),
+                        data,
+                    });
+                }
+                writer.write_all(b"250 2.0.0 Queued\r\n")?;
+            }
+            "RSET" => {
+                from.clear();
+                to.clear();
+                writer.write_all(b"250 2.0.0 OK\r\n")?;
+            }
+            "NOOP" => writer.write_all(b"250 2.0.0 OK\r\n")?,
+            "STARTTLS" => writer.write_all(b"454 4.7.0 TLS not available on loopback\r\n")?,
+            "QUIT" => {
+                writer.write_all(b"221 2.0.0 Bye\r\n")?;
+                return Ok(());
+            }
+            _ => writer.write_all(b"502 5.5.2 Command not recognized\r\n")?,
+        }
+    }
+}
+
+fn angle_address(command: &str) -> String {
+    command
+        .split_once('<')
+        .and_then(|(_, rest)| rest.split_once('>'))
+        .map(|(address, _)| address.to_string())
+        .unwrap_or_default()
+}
+
+fn read_data(reader: &mut impl BufRead) -> io::Result<String> {
+    let mut data = String::new();
+    let mut line = String::new();
+    loop {
+        line.clear();
+        if reader.read_line(&mut line)? == 0 {
+            return Err(io::Error::new(
+                io::ErrorKind::UnexpectedEof,
+                "connection closed during DATA",
+            ));
+        }
+        let content = line.trim_end_matches(['\r', '\n']);
+        if content == "." {
+            return Ok(data);
+        }
+        // Undo dot-stuffing from RFC 5321 section 4.5.2.
+        let content = content.strip_prefix('.').unwrap_or(content);
+        data.push_str(content);
+        data.push('\n');
+    }
+}
+
+fn serve_imap(stream: TcpStream, mailboxes: Arc<Mutex<Mailboxes>>) -> io::Result<()> {
+    let mut writer = stream.try_clone()?;
+    let mut reader = BufReader::new(stream);
+    writer.write_all(b"* OK loopback IMAP4rev1 ready\r\n")?;
+
+    let mut selected: Option<String> = None;
+    let mut line = String::new();
+    loop {
+        line.clear();
+        if reader.read_line(&mut line)? == 0 {
+            return Ok(());
+        }
+        let Some((tag, command)) = line.trim_end().split_once(' ') else {
+            writer.write_all(b"* BAD missing command\r\n")?;
+            continue;
+        };
+        let (verb, args) = command.split_once(' ').unwrap_or((command, ""));
+        let verb = verb.to_ascii_uppercase();
+        let Ok(mut boxes) = mailboxes.lock() else {
+            return Err(io::Error::other("mailbox lock poisoned"));
+        };
+        let reply = match (verb.as_str(), &selected) {
+            ("CAPABILITY", _) => {
+                writer.write_all(b"* CAPABILITY IMAP4rev1 MOVE\r\n")?;
+                Ok(())
+            }
+            ("LOGIN" | "NOOP", _) => Ok(()),
+            ("SELECT", _) => {
+                let folder = unquote(args);
+                let exists = boxes
+                    .messages
+                    .iter()
+                    .filter(|message| message.folder == folder)
+                    .count();
+                writer.write_all(format!("* {exists} EXISTS\r\n").as_bytes())?;
+                selected = Some(folder);
+                Ok(())
+            }
+            ("LOGOUT", _) => {
+                writer.write_all(b"* BYE loopback closing\r\n")?;
+                writer.write_all(format!("{tag} OK LOGOUT completed\r\n").as_bytes())?;
+                return Ok(());
+            }
+            ("UID", Some(folder)) => uid_command(&mut writer, &mut boxes, folder, args),
+            ("EXPUNGE", Some(folder)) => {
+                boxes
+                    .messages
+                    .retain(|message| !(message.folder == *folder && message.deleted));
+                Ok(())
+            }
+            ("UID" | "EXPUNGE", None) => Err("no mailbox selected"),
+            _ => Err("command not supported"),
+        };
+        drop(boxes);
+        match reply {
+            Ok(()) => writer.write_all(format!("{tag} OK {verb} completed\r\n").as_bytes())?,
+            Err(reason) => writer.write_all(format!("{tag} BAD {reason}\r\n").as_bytes())?,
+        }
+    }
+}
+
+/// The `UID SEARCH UNSEEN`, `UID FETCH`, `UID STORE`, `UID COPY` and
+/// `UID MOVE` forms the gateway client sends.
+fn uid_command(
+    writer: &mut impl Write,
+    boxes: &mut Mailboxes,
+    folder: &str,
+    args: &str,
+) -> Result<(), &'static str> {
+    let (verb, rest) = args.split_once(' ').unwrap_or((args, ""));
+    let (set, rest) = rest.split_once(' ').unwrap_or((rest, ""));
+    let uids: Vec<u32> = set.split(',').filter_map(|uid| uid.parse().ok()).collect();
+    let in_set = |message: &StoredMessage| message.folder == folder && uids.contains(&message.uid);
+    let write =
+        |writer: &mut dyn Write, bytes: &[u8]| writer.write_all(bytes).map_err(|_| "write failed");
+    match verb.to_ascii_uppercase().as_str() {
+        "SEARCH" => {
+            let unseen: String = boxes
+                .messages
+                .iter()
+                .filter(|message| message.folder == folder && !message.seen)
+                .map(|message| format!(" {}", message.uid))
+                .collect();
+            write(writer, format!("* SEARCH{unseen}\r\n").as_bytes())
+        }
+        "FETCH" => {
+            let selected = boxes
+                .messages
+                .iter()
+                .filter(|message| message.folder == folder);
+            for (sequence, message) in selected.enumerate() {
+                if uids.contains(&message.uid) {
+                    let header = format!(
+                        "* {} FETCH (UID {} BODY[] {{{}}}\r\n",
+                        sequence + 1,
+                        message.uid,
+                        message.raw.len()
+                    );
+                    write(writer, header.as_bytes())?;
+                    write(writer, message.raw.as_bytes())?;
+                    write(writer, b")\r\n")?;
+                }
+            }
+            Ok(())
+        }
+        "STORE" => {
+            let flags = rest.to_ascii_uppercase();
+            for message in boxes.messages.iter_mut().filter(|message| in_set(message)) {
+                message.seen |= flags.contains("\\SEEN");
+                message.deleted |= flags.contains("\\DELETED");
+            }
+            Ok(())
+        }
+        "COPY" => {
+            let target = unquote(rest);
+            let copies: Vec<String> = boxes
+                .messages
+                .iter()
+                .filter(|message| in_set(message))
+                .map(|message| message.raw.clone())
+                .collect();
+            for raw in copies {
+                boxes.add(&target, raw);
+            }
+            Ok(())
+        }
+        "MOVE" => {
+            let target = unquote(rest);
+            for message in boxes.messages.iter_mut().filter(|message| in_set(message)) {
+                message.folder = target.clone();
+            }
+            Ok(())
+        }
+        _ => Err("UID command not supported"),
+    }
+}
+
+/// Value of an IMAP quoted string, or the atom as given.
+fn unquote(value: &str) -> String {
+    let value = value.trim();
+    match value
+        .strip_prefix('"')
+        .and_then(|inner| inner.strip_suffix('"'))
+    {
+        Some(inner) => inner.replace("\\\"", "\"").replace("\\\\", "\\"),
+        None => value.to_string(),
+    }
+}
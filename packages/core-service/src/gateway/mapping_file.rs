@@ -0,0 +1,229 @@
+//! Address mapping rules kept in a file.
+//!
+//! `gateway.mapping.file` names a JSON document with the rule chain:
+//!
+//! ```json
+//! { "rules": [
+//!     { "template": "{S}@corp.example", "priority": 10, "country": "DE", "prmd": "Corp" },
+//!     { "template": "{G}.{S}@{O}.{C}.example" }
+//! ] }
+//! ```
+//!
+//! Every `gateway.mapping.reloadIntervalSecs` the supervised `mapping` worker
+//! checks the file's modification time and swaps a changed chain into the
+//! shared [`AddressMapper`]. A file that cannot be read or parsed, or that has
+//! no rules, is logged and the current chain stays in place. Each swap is
+//! written to the audit log as a `config.reload` by `system`.
+
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::{Duration, SystemTime};
+
+use serde::Deserialize;
+use tracing::{info, warn};
+
+use crate::audit::AuditLog;
+use crate::config::GatewayMappingConfig;
+use crate::gateway::address_map::{AddressMapper, AddressMappingRule, MappingError};
+use crate::models::AuditAction;
+use crate::supervisor::Supervisor;
+
+#[derive(Deserialize)]
+struct MappingFile {
+    rules: Vec<AddressMappingRule>,
+}
+
+/// Read the rule chain from `path`.
+pub fn load_rules(path: &Path) -> Result<Vec<AddressMappingRule>, MappingError> {
+    let raw = std::fs::read_to_string(path)
+        .map_err(|err| MappingError::File(format!("{}: {err}", path.display())))?;
+    let file: MappingFile = serde_json::from_str(&raw)
+        .map_err(|err| MappingError::File(format!("{}: {err}", path.display())))?;
+    if file.rules.is_empty() {
+        return Err(MappingError::File(format!("{}: no rules", path.display())));
+    }
+    Ok(file.rules)
+}
+
+#[derive(Clone)]
+pub struct MappingReloader {
+    path: Option<PathBuf>,
+    mapper: AddressMapper,
+    interval: Duration,
+    loaded: Arc<Mutex<Option<SystemTime>>>,
+    audit: Option<AuditLog>,
+}
+
+impl MappingReloader {
+    pub fn new(config: &GatewayMappingConfig, mapper: AddressMapper) -> Self {
+        Self {
+            path: config.file.as_ref().map(PathBuf::from),
+            mapper,
+            interval: Duration::from_secs(config.reload_interval_secs.max(1)),
+            loaded: Arc::new(Mutex::new(None)),
+            audit: None,
+        }
+    }
+
+    pub fn with_audit(mut self, audit: AuditLog) -> Self {
+        self.audit = Some(audit);
+        self
+    }
+
+    /// Load the file if it changed since the last successful load. Returns
+    /// whether the rule chain was replaced; without a file it never is.
+    pub fn reload(&self) -> Result<bool, MappingError> {
+        let Some(path) = &self.path else {
+            return Ok(false);
+        };
+        let modified = std::fs::metadata(path)
+            .and_then(|metadata| metadata.modified())
+            .map_err(|err| MappingError::File(format!("{}: {err}", path.display())))?;
+        let mut loaded = self
+            .loaded
+            .lock()
+            .map_err(|_| MappingError::File("reloader poisoned".into()))?;
+        if *loaded == Some(modified) {
+            return Ok(false);
+        }
+        let rules = load_rules(path).inspect_err(|err| {
+            warn!(target = "gateway.mapping", "keeping current rules: {err}");
+        })?;
+        info!(
+            target = "gateway.mapping",
+            rules = rules.len(),
+            file = %path.display(),
+            "mapping rules loaded"
+        );
+        let count = rules.len();
+        self.mapper.replace_rules(rules);
+        *loaded = Some(modified);
+        if let Some(audit) = &self.audit {
+            audit.record(
+                "system",
+                AuditAction::ConfigReload,
+                &path.display().to_string(),
+                None,
+                Some(format!("{count} mapping rules")),
+            );
+        }
+        Ok(true)
+    }
+
+    /// Run [`reload`](Self::reload) every `gateway.mapping.reloadIntervalSecs`
+    /// as the supervised `mapping` worker. Does nothing without a mapping file.
+    pub fn spawn(&self, supervisor: &Supervisor) {
+        if self.path.is_none() {
+            return;
+        }
+        let reloader = self.clone();
+        let interval = self.interval;
+        supervisor.spawn("mapping", move |context| {
+            while !context.should_stop() {
+                // Failures are logged; the current rules stay in use.
+                let _ = reloader.reload();
+                let mut waited = Duration::ZERO;
+                while waited < interval && !context.should_stop() {
+                    context.heartbeat();
+                    let step = (interval - waited).min(Duration::from_secs(1));
+                    thread::sleep(step);
+                    waited += step;
+                }
+            }
+        });
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::gateway::address_map::RuleOutcome;
+    use crate::models::Address;
+
+    fn address(country: &str) -> Address {
+        Address {
+            country: country.into(),
+            organization: "Port".into(),
+            surname: "Pilot".into(),
+        }
+    }
+
+    #[test]
+    fn reloads_prioritised_rules_from_file() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("mapping.json");
+        std::fs::write(
+            &path,
+            r#"{ "rules": [
+                { "template": "{S}@{O}.example" },
+                { "template": "{S}@bund.example", "priority": 10, "country": "DE" },
+                { "template": "{S}@corp.example", "priority": 20, "prmd": "Corp" }
+            ] }"#,
+        )
+        .unwrap();
+        let mapper = AddressMapper::new(
+            vec![AddressMappingRule::new("{S}@old.example")],
+            Default::default(),
+        );
+        let shared = mapper.clone();
+        let reloader = MappingReloader::new(
+            &GatewayMappingConfig {
+                file: Some(path.to_string_lossy().into_owned()),
+                ..GatewayMappingConfig::default()
+            },
+            mapper,
+        );
+
+        assert_eq!(reloader.reload(), Ok(true));
+        assert_eq!(reloader.reload(), Ok(false));
+        assert_eq!(
+            shared.map_or_to_rfc822(&address("DE")).unwrap(),
+            "pilot@bund.example"
+        );
+        assert_eq!(
+            shared.map_or_to_rfc822(&address("FR")).unwrap(),
+            "pilot@port.example"
+        );
+        assert_eq!(
+            shared
+                .map_or_to_rfc822_in(&address("DE"), Some("Corp"))
+                .unwrap(),
+            "pilot@corp.example"
+        );
+        assert_eq!(
+            shared.map_rfc822_to_or("tug@bund.example").unwrap().country,
+            "DE"
+        );
+
+        let explanation = shared.explain(&(&address("FR")).into());
+        assert_eq!(explanation.result.as_deref(), Some("pilot@port.example"));
+        let outcomes: Vec<_> = explanation.rules.iter().map(|rule| &rule.outcome).collect();
+        assert!(matches!(
+            outcomes[0],
+            RuleOutcome::PrmdMismatch { actual: None, .. }
+        ));
+        assert!(
+            matches!(outcomes[1], RuleOutcome::CountryMismatch { actual, .. } if actual == "FR")
+        );
+        assert!(matches!(outcomes[2], RuleOutcome::Matched { .. }));
+        let explanation = shared.explain_rfc822("tug@bund.example");
+        assert_eq!(explanation.result.as_deref(), Some("C=DE;S=Tug"));
+        assert_eq!(explanation.rules[0].outcome, RuleOutcome::NoMatch);
+
+        std::fs::write(&path, r#"{ "rules": [] }"#).unwrap();
+        let filetime = SystemTime::now() + Duration::from_secs(5);
+        std::fs::File::options()
+            .write(true)
+            .open(&path)
+            .unwrap()
+            .set_modified(filetime)
+            .unwrap();
+        assert!(matches!(reloader.reload(), Err(MappingError::File(_))));
+        assert_eq!(
+            shared.rules().len(),
+            3,
+            "a broken file keeps the current rules"
+        );
+    }
+}
@@ -0,0 +1,303 @@
+//! RFC 8098 message disposition notifications.
+//!
+//! [`Mdn`] is the typed form of the `message/disposition-notification` part of
+//! a `multipart/report; report-type=disposition-notification` message.
+//! `Original-Message-ID` names the message the notification is about; for
+//! mail sent by the gateway it is `<{message id}@x400.gateway>`, which is how
+//! read receipts find their way back to the stored X.400 message.
+
+use serde::Serialize;
+
+use crate::gateway::dsn::TypedAddress;
+use crate::gateway::inbound::{mailbox, split_headers};
+use crate::gateway::mime::{self, GATEWAY_MESSAGE_DOMAIN};
+
+#[derive(Debug, thiserror::Error, PartialEq, Eq)]
+pub enum MdnError {
+    #[error("message has no disposition-notification part")]
+    MissingNotification,
+    #[error("disposition notification is missing {0}")]
+    MissingField(&'static str),
+    #[error("invalid {field}: {value}")]
+    InvalidField { field: &'static str, value: String },
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum ActionMode {
+    ManualAction,
+    AutomaticAction,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize)]
+pub enum SendingMode {
+    #[serde(rename = "MDN-sent-manually")]
+    SentManually,
+    #[serde(rename = "MDN-sent-automatically")]
+    SentAutomatically,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub enum DispositionType {
+    Displayed,
+    Deleted,
+    Dispatched,
+    Processed,
+}
+
+impl DispositionType {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Self::Displayed => "displayed",
+            Self::Deleted => "deleted",
+            Self::Dispatched => "dispatched",
+            Self::Processed => "processed",
+        }
+    }
+}
+
+/// `Disposition: manual-action/MDN-sent-automatically; displayed/error`.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Disposition {
+    pub action_mode: ActionMode,
+    pub sending_mode: SendingMode,
+    pub kind: DispositionType,
+    /// Modifiers such as `error`.
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub modifiers: Vec<String>,
+}
+
+impl Disposition {
+    fn parse(value: &str) -> Result<Self, MdnError> {
+        let invalid = || MdnError::InvalidField {
+            field: "Disposition",
+            value: value.to_string(),
+        };
+        let (modes, kind) = value.split_once(';').ok_or_else(invalid)?;
+        let (action, sending) = modes.trim().split_once('/').ok_or_else(invalid)?;
+        let action_mode = match action.trim().to_ascii_lowercase().as_str() {
+            "manual-action" => ActionMode::ManualAction,
+            "automatic-action" => ActionMode::AutomaticAction,
+            _ => return Err(invalid()),
+        };
+        let sending_mode = match sending.trim().to_ascii_lowercase().as_str() {
+            "mdn-sent-manually" => SendingMode::SentManually,
+            "mdn-sent-automatically" => SendingMode::SentAutomatically,
+            _ => return Err(invalid()),
+        };
+        let mut parts = kind.trim().split('/');
+        let kind = match parts
+            .next()
+            .unwrap_or_default()
+            .trim()
+            .to_ascii_lowercase()
+            .as_str()
+        {
+            "displayed" => DispositionType::Displayed,
+            "deleted" => DispositionType::Deleted,
+            "dispatched" => DispositionType::Dispatched,
+            "processed" => DispositionType::Processed,
+            _ => return Err(invalid()),
+        };
+        Ok(Self {
+            action_mode,
+            sending_mode,
+            kind,
+            modifiers: parts
+                .flat_map(|modifiers| modifiers.split(','))
+                .map(|modifier| modifier.trim().to_ascii_lowercase())
+                .filter(|modifier| !modifier.is_empty())
+                .collect(),
+        })
+    }
+}
+
+impl std::fmt::Display for Disposition {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let action = match self.action_mode {
+            ActionMode::ManualAction => "manual-action",
+            ActionMode::AutomaticAction => "automatic-action",
+        };
+        let sending = match self.sending_mode {
+            SendingMode::SentManually => "MDN-sent-manually",
+            SendingMode::SentAutomatically => "MDN-sent-automatically",
+        };
+        write!(f, "{action}/{sending}; {}", self.kind.as_str())?;
+        if !self.modifiers.is_empty() {
+            write!(f, "/{}", self.modifiers.join(","))?;
+        }
+        Ok(())
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Mdn {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub reporting_ua: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub mdn_gateway: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub original_recipient: Option<TypedAddress>,
+    pub final_recipient: TypedAddress,
+    /// `Original-Message-ID` without the angle brackets.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub original_message_id: Option<String>,
+    pub disposition: Disposition,
+    /// `Failure`, `Error` and `Warning` fields, in that order of severity.
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub failures: Vec<String>,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub errors: Vec<String>,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub warnings: Vec<String>,
+}
+
+impl Mdn {
+    /// Whether the notification reports a problem instead of a disposition.
+    pub fn is_failure(&self) -> bool {
+        !self.failures.is_empty()
+            || !self.errors.is_empty()
+            || self
+                .disposition
+                .modifiers
+                .iter()
+                .any(|modifier| modifier == "error")
+    }
+
+    /// The local message id when `Original-Message-ID` was written by the gateway.
+    pub fn gateway_message_id(&self) -> Option<&str> {
+        let (local, domain) = self.original_message_id.as_deref()?.rsplit_once('@')?;
+        domain
+            .eq_ignore_ascii_case(GATEWAY_MESSAGE_DOMAIN)
+            .then_some(local)
+    }
+
+    /// Render the notification as a MIME entity: `Subject`, `MIME-Version`
+    /// and the `multipart/report` with a readable explanation and the
+    /// `message/disposition-notification` part. The sender adds the envelope headers.
+    pub fn render(&self) -> String {
+        let boundary = format!(
+            "=_mdn_{}",
+            self.gateway_message_id().unwrap_or("notification")
+        );
+        let mut raw = String::new();
+        raw.push_str(&format!(
+            "Subject: Disposition notification ({})\r\n",
+            self.disposition.kind.as_str()
+        ));
+        raw.push_str("MIME-Version: 1.0\r\n");
+        raw.push_str(&format!(
+            "Content-Type: multipart/report; report-type=disposition-notification; boundary=\"{boundary}\"\r\n\r\n"
+        ));
+
+        raw.push_str(&format!("--{boundary}\r\n"));
+        raw.push_str("Content-Type: text/plain; charset=us-ascii\r\n\r\n");
+        raw.push_str(&format!(
+            "The message sent to {} was {}.\r\n",
+            self.final_recipient.address,
+            self.disposition.kind.as_str()
+        ));
+
+        raw.push_str(&format!("\r\n--{boundary}\r\n"));
+        raw.push_str("Content-Type: message/disposition-notification\r\n\r\n");
+        if let Some(reporting_ua) = &self.reporting_ua {
+            raw.push_str(&format!("Reporting-UA: {reporting_ua}\r\n"));
+        }
+        if let Some(gateway) = &self.mdn_gateway {
+            raw.push_str(&format!("MDN-Gateway: dns; {gateway}\r\n"));
+        }
+        if let Some(original) = &self.original_recipient {
+            raw.push_str(&format!("Original-Recipient: {original}\r\n"));
+        }
+        raw.push_str(&format!("Final-Recipient: {}\r\n", self.final_recipient));
+        if let Some(message_id) = &self.original_message_id {
+            raw.push_str(&format!("Original-Message-ID: <{message_id}>\r\n"));
+        }
+        raw.push_str(&format!("Disposition: {}\r\n", self.disposition));
+        for (name, values) in [
+            ("Failure", &self.failures),
+            ("Error", &self.errors),
+            ("Warning", &self.warnings),
+        ] {
+            for value in values {
+                raw.push_str(&format!("{name}: {value}\r\n"));
+            }
+        }
+        raw.push_str(&format!("\r\n--{boundary}--\r\n"));
+        raw
+    }
+
+    /// Read an MDN from a full message or a bare `message/disposition-notification` body.
+    pub fn parse(raw: &str) -> Result<Self, MdnError> {
+        let parsed = mime::parse(raw);
+        let notification = parsed
+            .attachments
+            .iter()
+            .find(|part| {
+                part.mime_type == "message/disposition-notification"
+                    || part.mime_type == "message/global-disposition-notification"
+            })
+            .map(|part| String::from_utf8_lossy(&part.data).into_owned());
+        let notification = match notification {
+            Some(notification) => notification,
+            None if parsed.header("Content-Type").is_none() && raw.contains("Disposition:") => {
+                raw.to_string()
+            }
+            None => return Err(MdnError::MissingNotification),
+        };
+
+        let (fields, _) = split_headers(&notification);
+        let field = |name: &str| {
+            fields
+                .iter()
+                .find(|(key, _)| key.eq_ignore_ascii_case(name))
+                .map(|(_, value)| value.clone())
+        };
+        let all = |name: &str| {
+            fields
+                .iter()
+                .filter(|(key, _)| key.eq_ignore_ascii_case(name))
+                .map(|(_, value)| value.clone())
+                .collect::<Vec<_>>()
+        };
+        let typed = |name: &'static str, value: String| {
+            let (address_type, address) =
+                value
+                    .split_once(';')
+                    .ok_or_else(|| MdnError::InvalidField {
+                        field: name,
+                        value: value.clone(),
+                    })?;
+            Ok::<_, MdnError>(TypedAddress {
+                address_type: address_type.trim().to_ascii_lowercase(),
+                address: address.trim().to_string(),
+            })
+        };
+
+        let final_recipient =
+            field("Final-Recipient").ok_or(MdnError::MissingField("Final-Recipient"))?;
+        let disposition = field("Disposition").ok_or(MdnError::MissingField("Disposition"))?;
+        Ok(Self {
+            reporting_ua: field("Reporting-UA"),
+            mdn_gateway: field("MDN-Gateway").map(|value| {
+                value
+                    .split_once(';')
+                    .map(|(_, name)| name.trim().to_string())
+                    .unwrap_or(value)
+            }),
+            original_recipient: field("Original-Recipient")
+                .map(|value| typed("Original-Recipient", value))
+                .transpose()?,
+            final_recipient: typed("Final-Recipient", final_recipient)?,
+            original_message_id: field("Original-Message-ID")
+                .map(|value| mailbox(&value).trim().to_string()),
+            disposition: Disposition::parse(&disposition)?,
+            failures: all("Failure"),
+            errors: all("Error"),
+            warnings: all("Warning"),
+        })
+    }
+}
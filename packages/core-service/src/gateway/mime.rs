@@ -0,0 +1,416 @@
+//! MIME rendering and parsing for gateway traffic.
+//!
+//! Outbound messages are rendered as a single `text/plain` part, or as
+//! `multipart/mixed` with the text first and one base64 part per attachment.
+//! A `message/delivery-status` or `message/disposition-notification`
+//! attachment makes the message a `multipart/report` of that type, with the
+//! report part written as is.
+//! Text goes out as 7bit when it is short-lined ASCII and as UTF-8
+//! quoted-printable otherwise. Extra headers, such as the `X-X400-*` headers
+//! carrying the originator's O/R address, are written before `MIME-Version`.
+//!
+//! Inbound parsing walks nested multiparts. The first `text/plain` part
+//! becomes the body, falling back to the first `text/html`; every other leaf,
+//! and any part with a file name or an `attachment` disposition, is returned
+//! as an attachment.
+
+use base64::engine::general_purpose::{GeneralPurpose, GeneralPurposeConfig, STANDARD};
+use base64::engine::DecodePaddingMode;
+use base64::{alphabet, Engine};
+use encoding_rs::{Encoding, UTF_8};
+
+use crate::gateway::inbound::{
+    decode_quoted_printable, decode_words, encode_quoted_printable, encode_word, split_headers,
+};
+use crate::gateway::smtp_client::SmtpMessage;
+
+/// Domain of the `Message-ID`s written on outbound gateway mail.
+pub const GATEWAY_MESSAGE_DOMAIN: &str = "x400.gateway";
+
+/// Multiparts nested deeper than this are kept as opaque attachments.
+const MAX_DEPTH: usize = 8;
+
+/// Accepts base64 with or without padding, as sent by real-world agents.
+const LENIENT_BASE64: GeneralPurpose = GeneralPurpose::new(
+    &alphabet::STANDARD,
+    GeneralPurposeConfig::new().with_decode_padding_mode(DecodePaddingMode::Indifferent),
+);
+
+/// A file carried in a MIME part.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct MimeAttachment {
+    pub filename: String,
+    pub mime_type: String,
+    pub data: Vec<u8>,
+}
+
+/// Result of [`parse`].
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct ParsedMime {
+    /// Top-level headers with folded lines joined.
+    pub headers: Vec<(String, String)>,
+    pub body: String,
+    pub attachments: Vec<MimeAttachment>,
+}
+
+impl ParsedMime {
+    pub fn header(&self, name: &str) -> Option<&str> {
+        header(&self.headers, name)
+    }
+}
+
+/// Render `message` as an RFC 822 document with CRLF line endings.
+pub fn render(message: &SmtpMessage) -> String {
+    let mut raw = String::new();
+    raw.push_str(&format!(
+        "Message-ID: <{}@{GATEWAY_MESSAGE_DOMAIN}>\r\n",
+        message.id
+    ));
+    raw.push_str(&format!("From: {}\r\n", message.from));
+    raw.push_str(&format!("To: {}\r\n", message.to.join(", ")));
+    raw.push_str(&format!("Subject: {}\r\n", encode_word(&message.subject)));
+    for (name, value) in &message.headers {
+        raw.push_str(&format!("{name}: {}\r\n", encode_word(value)));
+    }
+    raw.push_str("MIME-Version: 1.0\r\n");
+    if message.attachments.is_empty() {
+        raw.push_str(&text_part(&message.body));
+        if !raw.ends_with("\r\n") {
+            raw.push_str("\r\n");
+        }
+        return raw;
+    }
+
+    let boundary = format!("=_x400_{}", message.id);
+    let report = message
+        .attachments
+        .iter()
+        .find_map(|attachment| report_type(&attachment.mime_type));
+    match report {
+        Some(report_type) => raw.push_str(&format!(
+            "Content-Type: multipart/report; report-type={report_type}; boundary=\"{boundary}\"\r\n\r\n"
+        )),
+        None => raw.push_str(&format!(
+            "Content-Type: multipart/mixed; boundary=\"{boundary}\"\r\n\r\n"
+        )),
+    }
+    raw.push_str("This is a multi-part message in MIME format.\r\n");
+    raw.push_str(&format!("--{boundary}\r\n"));
+    raw.push_str(&text_part(&message.body));
+    for attachment in &message.attachments {
+        if report_type(&attachment.mime_type).is_some() {
+            raw.push_str(&format!("\r\n--{boundary}\r\n"));
+            raw.push_str(&format!("Content-Type: {}\r\n\r\n", attachment.mime_type));
+            raw.push_str(String::from_utf8_lossy(&attachment.data).trim_end());
+            continue;
+        }
+        let filename = encode_word(&attachment.filename).replace('"', "\\\"");
+        raw.push_str(&format!("\r\n--{boundary}\r\n"));
+        raw.push_str(&format!(
+            "Content-Type: {}; name=\"{filename}\"\r\n",
+            attachment.mime_type
+        ));
+        raw.push_str(&format!(
+            "Content-Disposition: attachment; filename=\"{filename}\"\r\n"
+        ));
+        raw.push_str("Content-Transfer-Encoding: base64\r\n\r\n");
+        let encoded = STANDARD.encode(&attachment.data);
+        let lines: Vec<&str> = encoded
+            .as_bytes()
+            .chunks(76)
+            .filter_map(|line| std::str::from_utf8(line).ok())
+            .collect();
+        raw.push_str(&lines.join("\r\n"));
+    }
+    raw.push_str(&format!("\r\n--{boundary}--\r\n"));
+    raw
+}
+
+/// `report-type` of a `multipart/report` whose report part has `mime_type`.
+fn report_type(mime_type: &str) -> Option<&'static str> {
+    match mime_type {
+        "message/delivery-status" => Some("delivery-status"),
+        "message/disposition-notification" => Some("disposition-notification"),
+        _ => None,
+    }
+}
+
+/// Split `raw` into its body text and attachments.
+pub fn parse(raw: &str) -> ParsedMime {
+    let (headers, body) = split_headers(raw);
+    let mut parts = Parts::default();
+    walk(&headers, body, 0, &mut parts);
+    ParsedMime {
+        headers,
+        body: parts.text.or(parts.html).unwrap_or_default(),
+        attachments: parts.attachments,
+    }
+}
+
+/// Value of the `name` parameter in a structured header such as
+/// `Content-Type`, without quotes.
+pub fn parameter(value: &str, name: &str) -> Option<String> {
+    split_parameters(value).skip(1).find_map(|parameter| {
+        let (key, value) = parameter.split_once('=')?;
+        key.trim()
+            .eq_ignore_ascii_case(name)
+            .then(|| value.trim().trim_matches('"').replace("\\\"", "\""))
+    })
+}
+
+/// Headers and content of the text part; the line break before the next
+/// delimiter is left to the caller.
+fn text_part(body: &str) -> String {
+    let plain = body.is_ascii()
+        && !body.contains("=_")
+        && body
+            .lines()
+            .all(|line| line.len() <= 76 && !line.ends_with(' '));
+    if plain {
+        format!(
+            "Content-Type: text/plain; charset=us-ascii\r\nContent-Transfer-Encoding: 7bit\r\n\r\n{}",
+            body.replace("\r\n", "\n").replace('\n', "\r\n")
+        )
+    } else {
+        format!(
+            "Content-Type: text/plain; charset=utf-8\r\nContent-Transfer-Encoding: quoted-printable\r\n\r\n{}",
+            encode_quoted_printable(body.as_bytes())
+        )
+    }
+}
+
+#[derive(Default)]
+struct Parts {
+    text: Option<String>,
+    html: Option<String>,
+    attachments: Vec<MimeAttachment>,
+}
+
+fn walk(headers: &[(String, String)], body: &str, depth: usize, parts: &mut Parts) {
+    let content_type = header(headers, "Content-Type").unwrap_or("text/plain");
+    let mime_type = split_parameters(content_type)
+        .next()
+        .unwrap_or("text/plain")
+        .trim()
+        .to_ascii_lowercase();
+    if mime_type.starts_with("multipart/") && depth < MAX_DEPTH {
+        if let Some(boundary) = parameter(content_type, "boundary") {
+            for part in split_multipart(body, &boundary) {
+                let (headers, body) = split_headers(part);
+                walk(&headers, body, depth + 1, parts);
+            }
+            return;
+        }
+    }
+
+    let disposition = header(headers, "Content-Disposition");
+    let filename = disposition
+        .and_then(|value| parameter(value, "filename"))
+        .or_else(|| parameter(content_type, "name"))
+        .map(|name| decode_words(&name));
+    let is_attachment = filename.is_some()
+        || disposition.is_some_and(|value| {
+            value
+                .trim_start()
+                .get(..10)
+                .is_some_and(|kind| kind.eq_ignore_ascii_case("attachment"))
+        })
+        || !mime_type.starts_with("text/");
+    let encoding = header(headers, "Content-Transfer-Encoding")
+        .unwrap_or("7bit")
+        .trim()
+        .to_ascii_lowercase();
+
+    if !is_attachment && (mime_type == "text/plain" || mime_type == "text/html") {
+        let slot = if mime_type == "text/plain" {
+            &mut parts.text
+        } else {
+            &mut parts.html
+        };
+        if slot.is_none() {
+            *slot = Some(decode_text(body, &encoding, content_type));
+            return;
+        }
+    }
+    let extension = match mime_type.as_str() {
+        "message/rfc822" => "eml",
+        "text/plain" => "txt",
+        "text/html" => "html",
+        _ => "bin",
+    };
+    let filename =
+        filename.unwrap_or_else(|| format!("part-{}.{extension}", parts.attachments.len() + 1));
+    parts.attachments.push(MimeAttachment {
+        filename,
+        mime_type,
+        data: decode_transfer(body, &encoding),
+    });
+}
+
+/// Body text of a text part. Only encoded content is converted from its
+/// declared charset; 7bit and 8bit text already arrived as UTF-8.
+fn decode_text(body: &str, encoding: &str, content_type: &str) -> String {
+    let text = match encoding {
+        "quoted-printable" | "base64" => {
+            let charset = parameter(content_type, "charset")
+                .and_then(|label| Encoding::for_label(label.as_bytes()))
+                .unwrap_or(UTF_8);
+            charset
+                .decode_without_bom_handling(&decode_transfer(body, encoding))
+                .0
+                .into_owned()
+        }
+        _ => body.to_string(),
+    };
+    text.replace("\r\n", "\n")
+}
+
+fn decode_transfer(body: &str, encoding: &str) -> Vec<u8> {
+    match encoding {
+        "quoted-printable" => decode_quoted_printable(body),
+        "base64" => {
+            let compact: String = body.chars().filter(|c| !c.is_whitespace()).collect();
+            LENIENT_BASE64
+                .decode(compact)
+                .unwrap_or_else(|_| body.as_bytes().to_vec())
+        }
+        _ => body.as_bytes().to_vec(),
+    }
+}
+
+/// The body parts between `--boundary` delimiter lines, without the line
+/// break that belongs to the following delimiter.
+pub(crate) fn split_multipart<'a>(body: &'a str, boundary: &str) -> Vec<&'a str> {
+    let delimiter = format!("--{boundary}");
+    let mut parts = Vec::new();
+    let mut start: Option<usize> = None;
+    let mut offset = 0;
+    for line in body.split_inclusive('\n') {
+        let trimmed = line.trim_end();
+        if let Some(rest) = trimmed.strip_prefix(&delimiter) {
+            if rest.is_empty() || rest == "--" {
+                if let Some(start) = start {
+                    let part = &body[start..offset];
+                    let part = part
+                        .strip_suffix("\r\n")
+                        .or_else(|| part.strip_suffix('\n'))
+                        .unwrap_or(part);
+                    parts.push(part);
+                }
+                if rest == "--" {
+                    return parts;
+                }
+                start = Some(offset + line.len());
+            }
+        }
+        offset += line.len();
+    }
+    // A truncated message without a closing delimiter keeps its last part.
+    if let Some(start) = start {
+        parts.push(&body[start..]);
+    }
+    parts
+}
+
+/// Split a structured header value at semicolons outside quoted strings.
+fn split_parameters(value: &str) -> impl Iterator<Item = &str> {
+    let mut quoted = false;
+    let mut escaped = false;
+    value
+        .split(move |c: char| {
+            if escaped {
+                escaped = false;
+            } else if c == '\\' {
+                escaped = true;
+            } else if c == '"' {
+                quoted = !quoted;
+            }
+            c == ';' && !quoted
+        })
+        .filter(|parameter| !parameter.trim().is_empty())
+}
+
+fn header<'a>(headers: &'a [(String, String)], name: &str) -> Option<&'a str> {
+    headers
+        .iter()
+        .find(|(key, _)| key.eq_ignore_ascii_case(name))
+        .map(|(_, value)| value.as_str())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn rendered_multipart_messages_parse_back() {
+        let message = SmtpMessage {
+            id: "gw-7".into(),
+            from: "dispatch@port.example".into(),
+            to: vec!["pilot@port.example".into(), "tug@port.example".into()],
+            subject: "Liegeplatz geändert".into(),
+            body: "Neuer Liegeplatz: Kai 4 — ab 14:00 Uhr.\n".into(),
+            headers: vec![("X-X400-Originator".into(), "C=DE;O=Port;S=Dispatch".into())],
+            attachments: vec![
+                MimeAttachment {
+                    filename: "berth plan.pdf".into(),
+                    mime_type: "application/pdf".into(),
+                    data: (0..=255).collect(),
+                },
+                MimeAttachment {
+                    filename: "Lageplan Süd.txt".into(),
+                    mime_type: "text/plain".into(),
+                    data: b"Pier 4 south".to_vec(),
+                },
+            ],
+        };
+        let raw = render(&message);
+        assert!(raw.contains("X-X400-Originator: C=DE;O=Port;S=Dispatch\r\n"));
+        assert!(raw.contains("Content-Transfer-Encoding: quoted-printable"));
+        assert!(raw.lines().all(|line| line.len() <= 998));
+
+        let parsed = parse(&raw);
+        assert_eq!(parsed.body, message.body);
+        assert_eq!(parsed.attachments, message.attachments);
+        assert_eq!(
+            parsed.header("X-X400-Originator"),
+            Some("C=DE;O=Port;S=Dispatch")
+        );
+        assert_eq!(
+            decode_words(parsed.header("Subject").unwrap()),
+            message.subject
+        );
+    }
+
+    #[test]
+    fn parses_nested_alternatives_and_unnamed_parts() {
+        let raw = "From: a@x.example\r\n\
+            Content-Type: multipart/mixed; boundary=\"outer\"\r\n\r\n\
+            preamble\r\n\
+            --outer\r\n\
+            Content-Type: multipart/alternative; boundary=inner\r\n\r\n\
+            --inner\r\n\
+            Content-Type: text/html; charset=utf-8\r\n\r\n\
+            <p>Hallo</p>\r\n\
+            --inner\r\n\
+            Content-Type: text/plain; charset=\"iso-8859-1\"\r\n\
+            Content-Transfer-Encoding: quoted-printable\r\n\r\n\
+            Gr=FC=DFe\r\n\
+            --inner--\r\n\
+            --outer\r\n\
+            Content-Type: image/png\r\n\
+            Content-Transfer-Encoding: base64\r\n\r\n\
+            iVBORw0K\r\nGgo\r\n\
+            --outer--\r\n\
+            epilogue\r\n";
+        let parsed = parse(raw);
+        assert_eq!(parsed.body, "Grüße");
+        assert_eq!(parsed.attachments.len(), 1);
+        assert_eq!(parsed.attachments[0].filename, "part-1.bin");
+        assert_eq!(parsed.attachments[0].mime_type, "image/png");
+        assert_eq!(parsed.attachments[0].data, b"\x89PNG\r\n\x1a\n");
+
+        let plain = parse("Subject: x\r\n\r\nJust text\r\n");
+        assert_eq!(plain.body, "Just text\n");
+        assert!(plain.attachments.is_empty());
+    }
+}
@@ -1,11 +1,35 @@
 pub mod address_map;
+pub mod aliases;
+pub mod dsn;
 pub mod gateway_adapter;
 pub mod imap_client;
+pub mod inbound;
+pub mod ingest;
+#[cfg(feature = "loopback")]
+pub mod loopback;
+pub mod mapping_file;
+pub mod mdn;
+pub mod mime;
+pub mod policy;
+pub mod poller;
 pub mod report_map;
 pub mod smtp_client;
 
-pub use address_map::{AddressMapper, AddressMappingRule};
+pub use address_map::{AddressMapper, AddressMappingRule, MappingExplanation, RuleOutcome};
+pub use aliases::{AliasError, AliasManager};
+pub use dsn::{Dsn, DsnAction, DsnError, DsnRecipient};
 pub use gateway_adapter::{GatewayAdapter, GatewayEvent, GatewayResult};
-pub use imap_client::{GatewayImapClient, InboundMessage};
+pub use imap_client::{GatewayImapClient, ImapError, ImapMailbox, ImapSource, InboundMessage};
+pub use ingest::{InboundIngestor, QuarantinedInbound, ReprocessReport};
+#[cfg(feature = "loopback")]
+pub use loopback::{LoopbackGateway, LoopbackMail};
+pub use mapping_file::MappingReloader;
+pub use mdn::{Disposition, DispositionType, Mdn, MdnError};
+pub use mime::{MimeAttachment, ParsedMime};
+pub use policy::{DomainPattern, DomainPolicy, DomainVerdict, EffectivePolicy};
+pub use poller::{GatewayPoller, PollReport};
 pub use report_map::{DeliveryReport, ReportMapper};
-pub use smtp_client::{GatewaySmtpClient, SmtpMessage, SmtpSendOutcome};
+pub use smtp_client::{
+    GatewaySmtpClient, LettreRelay, MemoryRelay, SendWindow, SmtpMessage, SmtpRelay,
+    SmtpSendOutcome,
+};
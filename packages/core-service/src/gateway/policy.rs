@@ -0,0 +1,199 @@
+//! Gateway domain policy.
+//!
+//! Outbound mail may only go to domains on `gateway.security.allow`. An entry
+//! is either a domain, matched exactly, or `*.example.com`, which admits every
+//! subdomain of `example.com` but not `example.com` itself; `*` admits any
+//! domain. Entries on `gateway.security.deny` use the same syntax and win over
+//! any allow entry. With `gateway.security.enforceTls` every domain must be
+//! reached over TLS; without it only the domains on
+//! `gateway.security.requireTls` do.
+//!
+//! `GET /gateway/policy` returns the [`EffectivePolicy`]; with `?domain=` it
+//! also reports how that domain is treated and which entry decided it.
+
+use serde::{Deserialize, Serialize};
+
+use crate::config::GatewaySecurityConfig;
+
+/// One allow, deny or TLS entry.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum DomainPattern {
+    Any,
+    Exact(String),
+    /// `*.{0}`: strict subdomains of the domain.
+    Subdomains(String),
+}
+
+impl DomainPattern {
+    /// `None` for empty entries and wildcards anywhere but the first label.
+    pub fn parse(pattern: &str) -> Option<Self> {
+        let pattern = pattern.trim().trim_end_matches('.').to_ascii_lowercase();
+        if pattern == "*" {
+            return Some(Self::Any);
+        }
+        let (parsed, domain) = match pattern.strip_prefix("*.") {
+            Some(parent) => (Self::Subdomains(parent.to_string()), parent),
+            None => (Self::Exact(pattern.clone()), pattern.as_str()),
+        };
+        let valid = !domain.is_empty()
+            && domain
+                .split('.')
+                .all(|label| !label.is_empty() && !label.contains(['*', '@', ' ']));
+        valid.then_some(parsed)
+    }
+
+    /// Whether `domain`, already lower-cased, falls under the pattern.
+    pub fn matches(&self, domain: &str) -> bool {
+        match self {
+            Self::Any => true,
+            Self::Exact(exact) => domain == exact,
+            Self::Subdomains(parent) => domain
+                .strip_suffix(parent.as_str())
+                .is_some_and(|prefix| prefix.len() > 1 && prefix.ends_with('.')),
+        }
+    }
+}
+
+impl std::fmt::Display for DomainPattern {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Any => f.write_str("*"),
+            Self::Exact(domain) => f.write_str(domain),
+            Self::Subdomains(parent) => write!(f, "*.{parent}"),
+        }
+    }
+}
+
+/// How the policy treats one domain.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct DomainVerdict {
+    pub domain: String,
+    pub allowed: bool,
+    pub denied: bool,
+    /// The deny entry that refused the domain, or the allow entry that
+    /// admitted it.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub matched: Option<String>,
+    pub require_tls: bool,
+}
+
+/// Body of `GET /gateway/policy`.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct EffectivePolicy {
+    pub enforce_tls: bool,
+    pub allow: Vec<String>,
+    pub deny: Vec<String>,
+    pub require_tls: Vec<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub domain: Option<DomainVerdict>,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct DomainPolicy {
+    enforce_tls: bool,
+    allow: Vec<DomainPattern>,
+    deny: Vec<DomainPattern>,
+    require_tls: Vec<DomainPattern>,
+}
+
+impl DomainPolicy {
+    /// Admit the `allow` entries and require TLS everywhere. Entries that do
+    /// not parse are left out.
+    pub fn allow(allow: &[String]) -> Self {
+        Self {
+            enforce_tls: true,
+            allow: patterns(allow),
+            deny: Vec::new(),
+            require_tls: Vec::new(),
+        }
+    }
+
+    pub fn from_config(config: &GatewaySecurityConfig) -> Self {
+        Self {
+            enforce_tls: config.enforce_tls,
+            allow: patterns(&config.domain_allow_list),
+            deny: patterns(&config.domain_deny_list),
+            require_tls: patterns(&config.tls_domains),
+        }
+    }
+
+    pub fn evaluate(&self, domain: &str) -> DomainVerdict {
+        let domain = domain.trim().trim_end_matches('.').to_ascii_lowercase();
+        let denied_by = self.deny.iter().find(|pattern| pattern.matches(&domain));
+        let allowed_by = self.allow.iter().find(|pattern| pattern.matches(&domain));
+        DomainVerdict {
+            allowed: denied_by.is_none() && allowed_by.is_some(),
+            denied: denied_by.is_some(),
+            matched: denied_by.or(allowed_by).map(ToString::to_string),
+            require_tls: self.enforce_tls
+                || self
+                    .require_tls
+                    .iter()
+                    .any(|pattern| pattern.matches(&domain)),
+            domain,
+        }
+    }
+
+    /// The configured entries, with the verdict for `domain` when given.
+    pub fn effective(&self, domain: Option<&str>) -> EffectivePolicy {
+        let render =
+            |patterns: &[DomainPattern]| patterns.iter().map(ToString::to_string).collect();
+        EffectivePolicy {
+            enforce_tls: self.enforce_tls,
+            allow: render(&self.allow),
+            deny: render(&self.deny),
+            require_tls: render(&self.require_tls),
+            domain: domain.map(|domain| self.evaluate(domain)),
+        }
+    }
+}
+
+fn patterns(entries: &[String]) -> Vec<DomainPattern> {
+    entries
+        .iter()
+        .filter_map(|entry| DomainPattern::parse(entry))
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn deny_entries_override_wildcard_allows() {
+        let policy = DomainPolicy::from_config(&GatewaySecurityConfig {
+            enforce_tls: false,
+            domain_allow_list: vec!["*.example.com".into(), "partner.org".into()],
+            domain_deny_list: vec!["legacy.example.com".into()],
+            tls_domains: vec!["*.example.com".into()],
+            ..GatewaySecurityConfig::default()
+        });
+
+        let mail = policy.evaluate("Mail.Example.com");
+        assert!(mail.allowed && mail.require_tls);
+        assert_eq!(mail.matched.as_deref(), Some("*.example.com"));
+        assert!(!policy.evaluate("example.com").allowed);
+        assert!(!policy.evaluate("badexample.com").allowed);
+
+        let legacy = policy.evaluate("legacy.example.com");
+        assert!(legacy.denied && !legacy.allowed);
+        assert_eq!(legacy.matched.as_deref(), Some("legacy.example.com"));
+
+        let partner = policy.evaluate("partner.org");
+        assert!(partner.allowed && !partner.require_tls);
+
+        let effective = policy.effective(Some("partner.org"));
+        assert_eq!(effective.deny, ["legacy.example.com"]);
+        assert_eq!(effective.domain, Some(partner));
+    }
+
+    #[test]
+    fn rejects_misplaced_wildcards() {
+        assert_eq!(DomainPattern::parse("*"), Some(DomainPattern::Any));
+        assert!(DomainPattern::parse("mail.*.example.com").is_none());
+        assert!(DomainPattern::parse("*example.com").is_none());
+        assert!(DomainPattern::parse("").is_none());
+    }
+}
@@ -0,0 +1,550 @@
+//! Scheduled inbound gateway polling.
+//!
+//! Every `gateway.imap.pollIntervalSecs` the supervised `gateway` worker
+//! fetches up to `gateway.imap.batchSize` messages through the
+//! [`GatewayAdapter`] and hands each one to the [`InboundIngestor`], which
+//! converts it to an X.400 message with the current mapping rules and stores
+//! it in the inbox, or quarantines it. Delivery status and disposition
+//! notifications about mail the gateway sent are not stored as mail: with a
+//! [`ReportIngestor`] attached they become reports on the original message.
+//! Mail that has crossed the gateway more than `gateway.security.maxHops`
+//! times is bounced to its sender instead of being converted again.
+//! Every outcome is recorded in the trace, counted in telemetry and published
+//! to subscribers as a [`GatewayEvent`].
+
+use std::sync::mpsc::{self, Receiver, Sender};
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::{Duration, Instant};
+
+use serde::Serialize;
+use tracing::{info, warn};
+
+use crate::config::GatewayImapConfig;
+use crate::gateway::gateway_adapter::{GatewayAdapter, GatewayError, GatewayEvent};
+use crate::gateway::imap_client::InboundMessage;
+use crate::gateway::inbound::{self, split_headers};
+use crate::gateway::ingest::InboundIngestor;
+use crate::gateway::mime;
+use crate::models::{MessageId, Report};
+use crate::reports::ReportIngestor;
+use crate::supervisor::Supervisor;
+use crate::telemetry::TelemetryManager;
+use crate::trace::TraceManager;
+
+/// Outcome of one [`GatewayPoller::poll`].
+#[derive(Clone, Debug, Default, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct PollReport {
+    pub fetched: usize,
+    pub imported: Vec<MessageId>,
+    pub quarantined: usize,
+    /// DSNs and MDNs recorded as reports instead of being stored as mail.
+    pub reports: usize,
+    /// Looping messages bounced over the hop limit.
+    pub bounced: usize,
+}
+
+#[derive(Clone)]
+pub struct GatewayPoller {
+    adapter: GatewayAdapter,
+    ingestor: InboundIngestor,
+    reports: Option<ReportIngestor>,
+    trace: TraceManager,
+    telemetry: Option<TelemetryManager>,
+    interval: Duration,
+    batch_size: usize,
+    enabled: bool,
+    subscribers: Arc<Mutex<Vec<Sender<GatewayEvent>>>>,
+}
+
+impl GatewayPoller {
+    pub fn new(
+        config: &GatewayImapConfig,
+        adapter: GatewayAdapter,
+        ingestor: InboundIngestor,
+        trace: TraceManager,
+    ) -> Self {
+        Self {
+            adapter,
+            ingestor,
+            reports: None,
+            trace,
+            telemetry: None,
+            interval: Duration::from_secs(config.poll_interval_secs.max(1)),
+            batch_size: config.batch_size.max(1),
+            enabled: config.receive,
+            subscribers: Arc::new(Mutex::new(Vec::new())),
+        }
+    }
+
+    pub fn with_telemetry(mut self, telemetry: TelemetryManager) -> Self {
+        self.telemetry = Some(telemetry);
+        self
+    }
+
+    /// Record fetched DSNs and MDNs as reports on the messages they refer to.
+    pub fn with_reports(mut self, reports: ReportIngestor) -> Self {
+        self.reports = Some(reports);
+        self
+    }
+
+    /// Receive every gateway event published from now on; dropping the
+    /// receiver unsubscribes.
+    pub fn subscribe(&self) -> Receiver<GatewayEvent> {
+        let (sender, receiver) = mpsc::channel();
+        if let Ok(mut subscribers) = self.subscribers.lock() {
+            subscribers.push(sender);
+        }
+        receiver
+    }
+
+    /// Fetch one batch and move it into the inbox. A failed fetch leaves the
+    /// mailbox untouched, so the next poll picks the same messages up again.
+    pub fn poll(&self) -> Result<PollReport, GatewayError> {
+        let started = Instant::now();
+        let messages = match self.adapter.inbound(self.batch_size) {
+            Ok(GatewayEvent::InboundReady(messages)) => messages,
+            Ok(_) => Vec::new(),
+            Err(err) => {
+                warn!(target = "gateway.inbound", "poll failed: {err}");
+                if let Some(telemetry) = &self.telemetry {
+                    telemetry.record_flow(
+                        "gateway.inbound",
+                        started.elapsed(),
+                        false,
+                        telemetry.queue_depth(),
+                    );
+                }
+                return Err(err);
+            }
+        };
+
+        let mut report = PollReport {
+            fetched: messages.len(),
+            ..PollReport::default()
+        };
+        if messages.is_empty() {
+            return Ok(report);
+        }
+        self.publish(GatewayEvent::InboundReady(messages.clone()));
+        for message in &messages {
+            if let Some(result) = self.ingest_report(message) {
+                match result {
+                    Ok(reports) => {
+                        report.reports += reports.len();
+                        for ingested in reports {
+                            self.publish(GatewayEvent::InboundReport {
+                                uid: message.uid.clone(),
+                                report: ingested,
+                            });
+                        }
+                    }
+                    Err(error) => {
+                        warn!(target = "gateway.inbound", uid = %message.uid, "report dropped: {error}");
+                        report.quarantined += 1;
+                        self.publish(GatewayEvent::InboundQuarantined {
+                            uid: message.uid.clone(),
+                            error,
+                        });
+                    }
+                }
+                continue;
+            }
+            if let Some(result) = self.adapter.bounce_looping(message) {
+                let hops = inbound::gateway_hops(&message.raw);
+                match result {
+                    Ok(_) => {
+                        warn!(target = "gateway.inbound", uid = %message.uid, hops, "loop detected, bounced");
+                        report.bounced += 1;
+                        self.publish(GatewayEvent::InboundBounced {
+                            uid: message.uid.clone(),
+                            hops,
+                        });
+                    }
+                    Err(err) => {
+                        warn!(target = "gateway.inbound", uid = %message.uid, hops, "loop detected, bounce failed: {err}");
+                        report.quarantined += 1;
+                        self.publish(GatewayEvent::InboundQuarantined {
+                            uid: message.uid.clone(),
+                            error: err.to_string(),
+                        });
+                    }
+                }
+                continue;
+            }
+            let started = Instant::now();
+            let result = self.ingestor.ingest(message);
+            if let Some(telemetry) = &self.telemetry {
+                telemetry.record_flow(
+                    "gateway.inbound",
+                    started.elapsed(),
+                    result.is_ok(),
+                    telemetry.queue_depth(),
+                );
+                telemetry.record_gateway("inbound", result.is_ok());
+            }
+            match result {
+                Ok(message_id) => {
+                    self.trace.record("gateway.inbound", message_id.clone());
+                    self.publish(GatewayEvent::InboundStored {
+                        uid: message.uid.clone(),
+                        message_id: message_id.clone(),
+                    });
+                    report.imported.push(message_id);
+                }
+                Err(err) => {
+                    report.quarantined += 1;
+                    self.publish(GatewayEvent::InboundQuarantined {
+                        uid: message.uid.clone(),
+                        error: err.to_string(),
+                    });
+                }
+            }
+        }
+        info!(
+            target = "gateway.inbound",
+            fetched = report.fetched,
+            imported = report.imported.len(),
+            quarantined = report.quarantined,
+            reports = report.reports,
+            bounced = report.bounced,
+            "inbound poll finished"
+        );
+        Ok(report)
+    }
+
+    /// Run [`poll`](Self::poll) every `gateway.imap.pollIntervalSecs` as the
+    /// supervised `gateway` worker. Does nothing unless `gateway.imap.receive` is set.
+    pub fn spawn(&self, supervisor: &Supervisor) {
+        if !self.enabled {
+            return;
+        }
+        let poller = self.clone();
+        let interval = self.interval;
+        supervisor.spawn("gateway", move |context| {
+            while !context.should_stop() {
+                // Errors are logged and counted; the next poll simply retries.
+                let _ = poller.poll();
+                let mut waited = Duration::ZERO;
+                while waited < interval && !context.should_stop() {
+                    context.heartbeat();
+                    let step = (interval - waited).min(Duration::from_secs(1));
+                    thread::sleep(step);
+                    waited += step;
+                }
+            }
+        });
+    }
+
+    /// Hand a `multipart/report` message to the report ingestor. `None` when
+    /// it is ordinary mail or no ingestor is attached; an MDN that maps to no
+    /// report (`deleted`, failed) is consumed without one.
+    fn ingest_report(&self, message: &InboundMessage) -> Option<Result<Vec<Report>, String>> {
+        let reports = self.reports.as_ref()?;
+        let (headers, _) = split_headers(&message.raw);
+        let content_type = headers
+            .iter()
+            .find(|(name, _)| name.eq_ignore_ascii_case("Content-Type"))
+            .map(|(_, value)| value.as_str())?;
+        if !content_type
+            .trim()
+            .to_ascii_lowercase()
+            .starts_with("multipart/report")
+        {
+            return None;
+        }
+        let parsed = match mime::parameter(content_type, "report-type")?
+            .to_ascii_lowercase()
+            .as_str()
+        {
+            "delivery-status" => self.adapter.dsn_reports(&message.raw, ""),
+            "disposition-notification" => self
+                .adapter
+                .mdn_report(&message.raw, "")
+                .map(|report| report.into_iter().collect()),
+            _ => return None,
+        };
+        Some(parsed.map_err(|err| err.to_string()).and_then(|parsed| {
+            for report in &parsed {
+                reports
+                    .ingest(report.clone())
+                    .map_err(|err| err.to_string())?;
+            }
+            Ok(parsed)
+        }))
+    }
+
+    fn publish(&self, event: GatewayEvent) {
+        if let Ok(mut subscribers) = self.subscribers.lock() {
+            subscribers.retain(|subscriber| subscriber.send(event.clone()).is_ok());
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::clock::{SequentialIds, SharedIds};
+    use crate::config::{GatewaySmtpConfig, SupervisorConfig};
+    use crate::gateway::address_map::{AddressMapper, AddressMappingRule};
+    use crate::gateway::imap_client::{GatewayImapClient, ImapSource, InboundMessage};
+    use crate::gateway::report_map::ReportMapper;
+    use crate::gateway::smtp_client::GatewaySmtpClient;
+    use crate::gateway::ImapError;
+    use crate::reports::ReportIngestor;
+    use crate::store::StoreManager;
+
+    /// Hands out its messages once, then reports the mailbox as unreachable.
+    #[derive(Debug)]
+    struct OneShot(Mutex<Option<Vec<InboundMessage>>>);
+
+    impl ImapSource for OneShot {
+        fn fetch(&self, limit: usize) -> Result<Vec<InboundMessage>, ImapError> {
+            let mut messages = self
+                .0
+                .lock()
+                .unwrap()
+                .take()
+                .ok_or_else(|| ImapError::Connection("connection refused".into()))?;
+            messages.truncate(limit);
+            Ok(messages)
+        }
+    }
+
+    fn inbound(uid: &str, from: &str) -> InboundMessage {
+        InboundMessage {
+            uid: uid.into(),
+            subject: "Berth change".into(),
+            from: from.into(),
+            raw: format!(
+                "From: {from}\r\nTo: harbour@port.example\r\nSubject: Berth change\r\n\r\nPier 4\r\n"
+            ),
+        }
+    }
+
+    #[test]
+    fn polls_imap_into_the_inbox_and_publishes_events() {
+        let config = GatewayImapConfig {
+            receive: true,
+            batch_size: 2,
+            ..GatewayImapConfig::default()
+        };
+        let source = OneShot(Mutex::new(Some(vec![
+            inbound("7", "pilot@port.example"),
+            inbound("8", "unknown@elsewhere.invalid"),
+            inbound("9", "tug@port.example"),
+        ])));
+        let mapper = AddressMapper::new(
+            vec![AddressMappingRule::new("{S}@port.example")],
+            Default::default(),
+        );
+        let adapter = GatewayAdapter::new(
+            mapper.clone(),
+            GatewaySmtpClient::new(GatewaySmtpConfig::default(), Vec::new()),
+            GatewayImapClient::new(config.clone()).with_source(Arc::new(source)),
+            ReportMapper,
+        );
+        let store = StoreManager::new();
+        let ingestor = InboundIngestor::new(mapper, store.clone())
+            .with_ids(SharedIds::new(SequentialIds::default()));
+        let trace = TraceManager::new();
+        let poller = GatewayPoller::new(&config, adapter, ingestor.clone(), trace.clone());
+        let events = poller.subscribe();
+
+        let report = poller.poll().unwrap();
+        assert_eq!((report.fetched, report.quarantined), (2, 1));
+        assert_eq!(store.list("inbox").len(), 1);
+        assert_eq!(
+            store
+                .get(&report.imported[0])
+                .unwrap()
+                .envelope
+                .sender
+                .surname,
+            "Pilot"
+        );
+        assert_eq!(ingestor.quarantined()[0].uid, "8");
+        assert_eq!(trace.bundle()[0].event, "gateway.inbound");
+
+        let events: Vec<GatewayEvent> = events.try_iter().collect();
+        assert!(matches!(&events[0], GatewayEvent::InboundReady(batch) if batch.len() == 2));
+        assert_eq!(
+            events[1],
+            GatewayEvent::InboundStored {
+                uid: "7".into(),
+                message_id: report.imported[0].clone(),
+            }
+        );
+        assert!(matches!(&events[2], GatewayEvent::InboundQuarantined { uid, .. } if uid == "8"));
+
+        let err = poller.poll().unwrap_err();
+        assert!(matches!(err, GatewayError::Imap(ImapError::Connection(_))));
+        assert_eq!(store.list("inbox").len(), 1);
+
+        let supervisor = Supervisor::new(SupervisorConfig::default());
+        poller.spawn(&supervisor);
+        assert!(supervisor
+            .status()
+            .iter()
+            .any(|worker| worker.name == "gateway"));
+        supervisor.shutdown();
+    }
+
+    #[test]
+    fn records_read_receipts_on_sent_messages() {
+        let config = GatewayImapConfig {
+            receive: true,
+            ..GatewayImapConfig::default()
+        };
+        let source = Arc::new(OneShot(Mutex::new(None)));
+        let mapper = AddressMapper::new(
+            vec![AddressMappingRule::new("{S}@port.example")],
+            Default::default(),
+        );
+        let smtp =
+            GatewaySmtpClient::new(GatewaySmtpConfig::default(), vec!["port.example".into()]);
+        let adapter = GatewayAdapter::new(
+            mapper.clone(),
+            smtp.clone(),
+            GatewayImapClient::new(config.clone()).with_source(source.clone()),
+            ReportMapper,
+        );
+        let store = StoreManager::new();
+        let trace = TraceManager::new();
+        let poller = GatewayPoller::new(
+            &config,
+            adapter.clone(),
+            InboundIngestor::new(mapper.clone(), store.clone()),
+            trace.clone(),
+        )
+        .with_reports(ReportIngestor::new(store.clone(), trace.clone()));
+        let events = poller.subscribe();
+
+        let address = |surname: &str| crate::models::Address {
+            country: "DE".into(),
+            organization: "Port".into(),
+            surname: surname.into(),
+        };
+        let mut envelope = crate::models::MessageEnvelope::new(
+            "Berth change",
+            address("Harbour"),
+            vec![address("Pilot")],
+        );
+        envelope.folder = "sent".into();
+        let message = crate::models::Message {
+            envelope,
+            content: crate::models::MessageContent {
+                body: "Pier 4".into(),
+                attachments: Vec::new(),
+            },
+        };
+        let id = message.envelope.id.clone();
+        store.save(message.clone());
+        adapter.send_message(&message, Vec::new()).unwrap();
+        let sent = mime::render(&smtp.delivered()[0]);
+        assert!(sent.contains(&format!("Message-ID: <{id}@x400.gateway>")));
+        assert!(sent.contains("Disposition-Notification-To: harbour@port.example"));
+
+        let mdn = ReportMapper.mdn_for_report(
+            &Report {
+                message_id: id.clone(),
+                kind: crate::models::ReportKind::Read,
+                recipient: Some(address("Pilot")),
+                reason: None,
+                diagnostic: None,
+                timestamp: chrono::Utc::now(),
+            },
+            &mapper,
+            "mua.port.example",
+        );
+        *source.0.lock().unwrap() = Some(vec![InboundMessage {
+            uid: "11".into(),
+            subject: "Disposition notification (displayed)".into(),
+            from: "pilot@port.example".into(),
+            raw: format!(
+                "From: pilot@port.example\r\nTo: harbour@port.example\r\n{}",
+                mdn.render()
+            ),
+        }]);
+
+        let report = poller.poll().unwrap();
+        assert_eq!((report.reports, report.quarantined), (1, 0));
+        assert!(report.imported.is_empty());
+        assert_eq!(
+            store.get(&id).unwrap().envelope.status,
+            crate::models::MessageStatus::Read
+        );
+        let stored = store.reports(&id);
+        assert_eq!(stored[0].kind, crate::models::ReportKind::Read);
+        assert_eq!(stored[0].recipient.as_ref().unwrap().surname, "Pilot");
+        assert!(trace
+            .bundle()
+            .iter()
+            .any(|entry| entry.event == "report.read"));
+        assert!(events
+            .try_iter()
+            .any(|event| matches!(event, GatewayEvent::InboundReport { uid, .. } if uid == "11")));
+    }
+
+    #[test]
+    fn bounces_messages_looping_through_the_gateway() {
+        let config = GatewayImapConfig {
+            receive: true,
+            ..GatewayImapConfig::default()
+        };
+        let mut looping = inbound("21", "pilot@port.example");
+        looping.raw = format!(
+            "X-X400-Gateway-Hops: 3\r\nMessage-ID: <m21@port.example>\r\n{}",
+            looping.raw
+        );
+        let mut returning = inbound("22", "tug@port.example");
+        returning.raw = format!("X-X400-Gateway-Hops: 2\r\n{}", returning.raw);
+        let source = OneShot(Mutex::new(Some(vec![looping, returning])));
+        let mapper = AddressMapper::new(
+            vec![AddressMappingRule::new("{S}@port.example")],
+            Default::default(),
+        );
+        let smtp =
+            GatewaySmtpClient::new(GatewaySmtpConfig::default(), vec!["port.example".into()]);
+        let adapter = GatewayAdapter::new(
+            mapper.clone(),
+            smtp.clone(),
+            GatewayImapClient::new(config.clone()).with_source(Arc::new(source)),
+            ReportMapper,
+        )
+        .with_max_hops(3);
+        let store = StoreManager::new();
+        let poller = GatewayPoller::new(
+            &config,
+            adapter.clone(),
+            InboundIngestor::new(mapper, store.clone()),
+            TraceManager::new(),
+        );
+        let events = poller.subscribe();
+
+        let report = poller.poll().unwrap();
+        assert_eq!((report.bounced, report.imported.len()), (1, 1));
+        assert!(events.try_iter().any(|event| event
+            == GatewayEvent::InboundBounced {
+                uid: "21".into(),
+                hops: 3,
+            }));
+
+        let bounce = &smtp.delivered()[0];
+        assert_eq!(bounce.to, ["pilot@port.example"]);
+        let raw = mime::render(bounce);
+        assert!(raw.contains("report-type=delivery-status"));
+        let dsn = crate::gateway::Dsn::parse(&raw).unwrap();
+        assert_eq!(dsn.envelope_id.as_deref(), Some("m21@port.example"));
+        assert_eq!(dsn.recipients[0].status, "5.4.6");
+
+        // The imported copy has now crossed three times and may not leave again.
+        let stored = store.get(&report.imported[0]).unwrap();
+        assert_eq!(stored.envelope.gateway_hops, 3);
+        assert_eq!(
+            adapter.send_message(&stored, Vec::new()).unwrap_err(),
+            GatewayError::HopLimit { hops: 4, limit: 3 }
+        );
+    }
+}
@@ -1,3 +1,126 @@
+use chrono::{DateTime, Utc};
+
+use crate::gateway::address_map::AddressMapper;
+use crate::gateway::dsn::{
+    DiagnosticCode, Dsn, DsnAction, DsnRecipient, TypedAddress, X400_DIAGNOSTIC,
+};
+use crate::gateway::mdn::{ActionMode, Disposition, DispositionType, Mdn, SendingMode};
+use crate::gateway::mime::GATEWAY_MESSAGE_DOMAIN;
+use crate::models::{Address, MessageId, Report, ReportKind};
+
+/// One row of the enhanced status code ↔ X.411 non-delivery mapping.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct StatusMapping {
+    pub smtp: &'static str,
+    pub reason: &'static str,
+    pub diagnostic: &'static str,
+}
+
+const fn row(smtp: &'static str, reason: &'static str, diagnostic: &'static str) -> StatusMapping {
+    StatusMapping {
+        smtp,
+        reason,
+        diagnostic,
+    }
+}
+
+/// RFC 3463 codes paired with X.411 reason and diagnostic names.
+///
+/// Several SMTP codes collapse onto the same X.400 pair; the first row for a
+/// pair is the one used when mapping back to SMTP.
+pub const STATUS_MAPPINGS: &[StatusMapping] = &[
+    row("5.1.1", "unable-to-transfer", "unrecognised-OR-name"),
+    row("5.1.2", "unable-to-transfer", "unrecognised-OR-name"),
+    row("5.1.4", "unable-to-transfer", "ambiguous-OR-name"),
+    row("5.2.1", "unable-to-transfer", "recipient-unavailable"),
+    row("5.2.2", "unable-to-transfer", "recipient-unavailable"),
+    row("5.2.3", "unable-to-transfer", "content-too-long"),
+    row(
+        "5.3.3",
+        "unable-to-transfer",
+        "unsupported-critical-function",
+    ),
+    row("5.3.4", "unable-to-transfer", "size-constraint-violation"),
+    row("5.4.6", "transfer-failure", "loop-detected"),
+    row("5.4.7", "transfer-failure", "maximum-time-expired"),
+    row("4.4.7", "transfer-failure", "maximum-time-expired"),
+    row("4.3.1", "transfer-failure", "mts-congestion"),
+    row("4.4.5", "transfer-failure", "mts-congestion"),
+    row("5.5.0", "unable-to-transfer", "protocol-violation"),
+    row("5.5.3", "unable-to-transfer", "too-many-recipients"),
+    row("5.5.4", "unable-to-transfer", "invalid-arguments"),
+    row(
+        "5.6.1",
+        "conversion-not-performed",
+        "encoded-information-types-unsupported",
+    ),
+    row(
+        "5.6.3",
+        "conversion-not-performed",
+        "conversion-impractical",
+    ),
+    row(
+        "5.6.5",
+        "conversion-not-performed",
+        "conversion-impractical",
+    ),
+    row("5.7.1", "restricted-delivery", "no-bilateral-agreement"),
+    row(
+        "5.7.5",
+        "transfer-failure-for-security-reason",
+        "secure-messaging-error",
+    ),
+    row(
+        "5.7.7",
+        "transfer-failure-for-security-reason",
+        "secure-messaging-error",
+    ),
+];
+
+/// Whether `value` has the `class.subject.detail` shape of an enhanced status code.
+pub fn is_enhanced_status(value: &str) -> bool {
+    let parts: Vec<&str> = value.split('.').collect();
+    parts.len() == 3
+        && matches!(parts[0], "2" | "4" | "5")
+        && parts[1..].iter().all(|part| {
+            !part.is_empty() && part.len() <= 3 && part.bytes().all(|b| b.is_ascii_digit())
+        })
+}
+
+/// X.400 reason and diagnostic for an enhanced SMTP status code.
+///
+/// Unlisted codes fall back on their class: transient failures become
+/// `transfer-failure`, everything else `unable-to-transfer` without a
+/// diagnostic.
+pub fn x400_for_smtp(code: &str) -> (&'static str, Option<&'static str>) {
+    if let Some(mapping) = STATUS_MAPPINGS.iter().find(|mapping| mapping.smtp == code) {
+        return (mapping.reason, Some(mapping.diagnostic));
+    }
+    if code.starts_with("4.") {
+        ("transfer-failure", None)
+    } else {
+        ("unable-to-transfer", None)
+    }
+}
+
+/// Enhanced SMTP status code for an X.400 reason/diagnostic pair.
+pub fn smtp_for_x400(reason: &str, diagnostic: Option<&str>) -> &'static str {
+    let exact = diagnostic.and_then(|diagnostic| {
+        STATUS_MAPPINGS
+            .iter()
+            .find(|mapping| mapping.reason == reason && mapping.diagnostic == diagnostic)
+    });
+    if let Some(mapping) = exact {
+        return mapping.smtp;
+    }
+    match reason {
+        "transfer-failure" => "4.0.0",
+        "conversion-not-performed" => "5.6.0",
+        "restricted-delivery" | "transfer-failure-for-security-reason" => "5.7.0",
+        _ => "5.0.0",
+    }
+}
+
 /// Simplified representation of a delivery report exchanged between SMTP and X.400.
 #[derive(Clone, Debug, PartialEq, Eq)]
 pub struct DeliveryReport {
@@ -6,13 +129,39 @@ pub struct DeliveryReport {
     pub detail: String,
 }
 
+impl DeliveryReport {
+    /// X.400 reason and diagnostic when the status is a failure code.
+    pub fn x400_reason(&self) -> Option<(&'static str, Option<&'static str>)> {
+        (is_enhanced_status(&self.status) && !self.status.starts_with("2."))
+            .then(|| x400_for_smtp(&self.status))
+    }
+}
+
 /// Mapper handling conversion between DSN/MDN and DR/Read reports.
 #[derive(Default, Clone, Debug)]
 pub struct ReportMapper;
 
 impl ReportMapper {
     /// Convert a Delivery Status Notification into the internal representation.
+    /// For a structured DSN the status is that of the first failed recipient,
+    /// or the first recipient, and `Original-Envelope-Id` wins over
+    /// `correlation_id`.
     pub fn from_dsn(&self, payload: &str, correlation_id: &str) -> DeliveryReport {
+        if let Ok(dsn) = Dsn::parse(payload) {
+            let recipient = dsn
+                .recipients
+                .iter()
+                .find(|recipient| recipient.action == DsnAction::Failed)
+                .unwrap_or(&dsn.recipients[0]);
+            return DeliveryReport {
+                correlation_id: dsn
+                    .envelope_id
+                    .clone()
+                    .unwrap_or_else(|| correlation_id.into()),
+                status: recipient.status.clone(),
+                detail: payload.to_string(),
+            };
+        }
         DeliveryReport {
             correlation_id: correlation_id.into(),
             status: payload
@@ -24,8 +173,27 @@ impl ReportMapper {
         }
     }
 
-    /// Convert a Message Disposition Notification into a read report.
+    /// Convert a Message Disposition Notification into a read report. A
+    /// structured MDN about gateway mail correlates through its
+    /// `Original-Message-ID`, and a failed or `deleted` disposition keeps its
+    /// name as status.
     pub fn from_mdn(&self, payload: &str, correlation_id: &str) -> DeliveryReport {
+        if let Ok(mdn) = Mdn::parse(payload) {
+            let status = match mdn.disposition.kind {
+                _ if mdn.is_failure() => "failed",
+                DispositionType::Displayed => "read",
+                DispositionType::Deleted => "deleted",
+                DispositionType::Dispatched | DispositionType::Processed => "processed",
+            };
+            return DeliveryReport {
+                correlation_id: mdn
+                    .gateway_message_id()
+                    .unwrap_or(correlation_id)
+                    .to_string(),
+                status: status.into(),
+                detail: payload.to_string(),
+            };
+        }
         let status = if payload.contains("displayed") {
             "read"
         } else {
@@ -38,18 +206,294 @@ impl ReportMapper {
         }
     }
 
-    /// Serialize an X.400 delivery report back into DSN format.
-    pub fn to_dsn(&self, report: &DeliveryReport) -> String {
-        format!(
-            "Status: {}\nCorrelation-ID: {}",
-            report.status, report.correlation_id
-        )
+    /// Build the MDN a recipient's user agent would send for an X.400 read
+    /// report: `displayed`, sent automatically after the user opened the
+    /// message. `Original-Message-ID` names the gateway copy of the message.
+    pub fn mdn_for_report(
+        &self,
+        report: &Report,
+        mapper: &AddressMapper,
+        reporting_ua: &str,
+    ) -> Mdn {
+        let final_recipient = match &report.recipient {
+            Some(address) => mapper
+                .map_or_to_rfc822(address)
+                .map(TypedAddress::rfc822)
+                .unwrap_or_else(|_| TypedAddress::x400(or_name(address))),
+            None => TypedAddress::x400("unknown"),
+        };
+        Mdn {
+            reporting_ua: Some(reporting_ua.into()),
+            mdn_gateway: None,
+            original_recipient: None,
+            final_recipient,
+            original_message_id: Some(format!("{}@{GATEWAY_MESSAGE_DOMAIN}", report.message_id)),
+            disposition: Disposition {
+                action_mode: ActionMode::ManualAction,
+                sending_mode: SendingMode::SentAutomatically,
+                kind: DispositionType::Displayed,
+                modifiers: Vec::new(),
+            },
+            failures: Vec::new(),
+            errors: Vec::new(),
+            warnings: Vec::new(),
+        }
+    }
+
+    /// Turn an MDN into an X.400 report for the gateway message named by
+    /// `Original-Message-ID`, or `correlation_id` for a message id the gateway
+    /// did not write. `displayed` becomes a read report, `processed` and
+    /// `dispatched` a delivery report; `deleted` and failed dispositions say
+    /// nothing about the message reaching its reader and yield none.
+    pub fn report_from_mdn(
+        &self,
+        mdn: &Mdn,
+        mapper: &AddressMapper,
+        correlation_id: &str,
+        received_at: DateTime<Utc>,
+    ) -> Option<Report> {
+        if mdn.is_failure() {
+            return None;
+        }
+        let (kind, reason) = match mdn.disposition.kind {
+            DispositionType::Displayed => (ReportKind::Read, "receipt"),
+            DispositionType::Processed | DispositionType::Dispatched => {
+                (ReportKind::Delivery, "delivered")
+            }
+            DispositionType::Deleted => return None,
+        };
+        let recipient = mdn
+            .original_recipient
+            .as_ref()
+            .unwrap_or(&mdn.final_recipient);
+        Some(Report {
+            message_id: MessageId(
+                mdn.gateway_message_id()
+                    .unwrap_or(correlation_id)
+                    .to_string(),
+            ),
+            kind,
+            recipient: if recipient.address_type == "x400" {
+                parse_or_name(&recipient.address)
+            } else {
+                mapper.map_rfc822_to_or(&recipient.address).ok()
+            },
+            reason: Some(reason.into()),
+            diagnostic: None,
+            timestamp: received_at,
+        })
+    }
+
+    /// Serialize a gateway delivery report as a single-recipient DSN for
+    /// `recipient`. Failures carry the X.400 reason as diagnostic code.
+    pub fn to_dsn(&self, report: &DeliveryReport, recipient: &str, reporting_mta: &str) -> String {
+        let failed = report.x400_reason();
+        let dsn = Dsn {
+            envelope_id: Some(report.correlation_id.clone()),
+            reporting_mta: reporting_mta.into(),
+            arrival_date: None,
+            recipients: vec![DsnRecipient {
+                original_recipient: None,
+                final_recipient: TypedAddress::rfc822(recipient),
+                action: match (&failed, report.status.starts_with("4.")) {
+                    (Some(_), true) => DsnAction::Delayed,
+                    (Some(_), false) => DsnAction::Failed,
+                    (None, _) => DsnAction::Delivered,
+                },
+                status: if is_enhanced_status(&report.status) {
+                    report.status.clone()
+                } else {
+                    "2.0.0".into()
+                },
+                remote_mta: None,
+                diagnostic_code: failed.map(|(reason, diagnostic)| x400_code(reason, diagnostic)),
+                last_attempt_date: None,
+            }],
+        };
+        dsn.render()
+    }
+
+    /// Turn a gateway report into the form consumed by report ingestion.
+    pub fn to_report(
+        &self,
+        report: &DeliveryReport,
+        message_id: MessageId,
+        timestamp: DateTime<Utc>,
+    ) -> Report {
+        let (kind, reason, diagnostic) = match report.status.as_str() {
+            "read" => (ReportKind::Read, Some("receipt"), None),
+            status if status.starts_with("2.") || status == "processed" => {
+                (ReportKind::Delivery, Some("delivered"), None)
+            }
+            _ => {
+                let (reason, diagnostic) =
+                    report.x400_reason().unwrap_or(("unable-to-transfer", None));
+                (ReportKind::NonDelivery, Some(reason), diagnostic)
+            }
+        };
+        Report {
+            message_id,
+            kind,
+            recipient: None,
+            reason: reason.map(str::to_string),
+            diagnostic: diagnostic.map(str::to_string),
+            timestamp,
+        }
+    }
+
+    /// Build one DSN for the X.400 delivery and non-delivery reports of a
+    /// message, one field group per report. Recipients the mapper cannot
+    /// express in RFC 822 keep their O/R address with the `x400` type; read
+    /// reports have no DSN equivalent and are left out.
+    pub fn dsn_for_reports(
+        &self,
+        reports: &[Report],
+        mapper: &AddressMapper,
+        reporting_mta: &str,
+    ) -> Dsn {
+        let recipients = reports
+            .iter()
+            .filter(|report| report.kind != ReportKind::Read)
+            .map(|report| {
+                let final_recipient = match &report.recipient {
+                    Some(address) => mapper
+                        .map_or_to_rfc822(address)
+                        .map(TypedAddress::rfc822)
+                        .unwrap_or_else(|_| TypedAddress::x400(or_name(address))),
+                    None => TypedAddress::x400("unknown"),
+                };
+                let (action, status, diagnostic_code) = match report.kind {
+                    ReportKind::NonDelivery => {
+                        let reason = report.reason.as_deref().unwrap_or("unable-to-transfer");
+                        let diagnostic = report.diagnostic.as_deref();
+                        (
+                            DsnAction::Failed,
+                            smtp_for_x400(reason, diagnostic),
+                            Some(x400_code(reason, diagnostic)),
+                        )
+                    }
+                    _ => (DsnAction::Delivered, "2.0.0", None),
+                };
+                DsnRecipient {
+                    original_recipient: None,
+                    final_recipient,
+                    action,
+                    status: status.to_string(),
+                    remote_mta: None,
+                    diagnostic_code,
+                    last_attempt_date: Some(report.timestamp),
+                }
+            })
+            .collect();
+        Dsn {
+            envelope_id: reports.first().map(|report| report.message_id.to_string()),
+            reporting_mta: reporting_mta.into(),
+            arrival_date: None,
+            recipients,
+        }
+    }
+
+    /// Turn each final recipient of `dsn` into an X.400 report for the message
+    /// named by `Original-Envelope-Id`, or `correlation_id` when it has none.
+    /// An `X-X400` diagnostic code is taken as is; other failures are mapped
+    /// from their status code. `delayed` recipients are not final and yield
+    /// no report.
+    pub fn reports_from_dsn(
+        &self,
+        dsn: &Dsn,
+        mapper: &AddressMapper,
+        correlation_id: &str,
+        received_at: DateTime<Utc>,
+    ) -> Vec<Report> {
+        let message_id = MessageId(
+            dsn.envelope_id
+                .clone()
+                .unwrap_or_else(|| correlation_id.to_string()),
+        );
+        dsn.recipients
+            .iter()
+            .filter(|recipient| recipient.action != DsnAction::Delayed)
+            .map(|recipient| {
+                let address = &recipient.final_recipient;
+                let recipient_address = if address.address_type == "x400" {
+                    parse_or_name(&address.address)
+                } else {
+                    mapper.map_rfc822_to_or(&address.address).ok()
+                };
+                let (kind, reason, diagnostic) = if recipient.action == DsnAction::Failed {
+                    let (reason, diagnostic) = recipient
+                        .diagnostic_code
+                        .as_ref()
+                        .and_then(DiagnosticCode::x400)
+                        .map(|(reason, diagnostic)| {
+                            (reason.to_string(), diagnostic.map(str::to_string))
+                        })
+                        .unwrap_or_else(|| {
+                            let (reason, diagnostic) = x400_for_smtp(&recipient.status);
+                            (reason.to_string(), diagnostic.map(str::to_string))
+                        });
+                    (ReportKind::NonDelivery, Some(reason), diagnostic)
+                } else {
+                    (ReportKind::Delivery, Some("delivered".to_string()), None)
+                };
+                Report {
+                    message_id: message_id.clone(),
+                    kind,
+                    recipient: recipient_address,
+                    reason,
+                    diagnostic,
+                    timestamp: recipient
+                        .last_attempt_date
+                        .or(dsn.arrival_date)
+                        .unwrap_or(received_at),
+                }
+            })
+            .collect()
+    }
+}
+
+fn x400_code(reason: &str, diagnostic: Option<&str>) -> DiagnosticCode {
+    DiagnosticCode {
+        diagnostic_type: X400_DIAGNOSTIC.into(),
+        text: match diagnostic {
+            Some(diagnostic) => format!("{reason}; {diagnostic}"),
+            None => reason.to_string(),
+        },
+    }
+}
+
+fn or_name(address: &Address) -> String {
+    format!(
+        "C={};O={};S={}",
+        address.country, address.organization, address.surname
+    )
+}
+
+/// `C=DE;O=Org;S=Surname` as written by [`or_name`].
+fn parse_or_name(value: &str) -> Option<Address> {
+    let mut address = Address {
+        country: String::new(),
+        organization: String::new(),
+        surname: String::new(),
+    };
+    for part in value.split(';') {
+        let (key, value) = part.split_once('=')?;
+        let value = value.trim().to_string();
+        match key.trim().to_ascii_uppercase().as_str() {
+            "C" => address.country = value,
+            "O" => address.organization = value,
+            "S" => address.surname = value,
+            _ => {}
+        }
     }
+    (!address.country.is_empty() && !address.surname.is_empty()).then_some(address)
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::gateway::dsn::DsnError;
+    use chrono::Timelike;
 
     #[test]
     fn parses_dsn_status_line() {
@@ -62,12 +506,206 @@ mod tests {
     #[test]
     fn serializes_to_dsn() {
         let mapper = ReportMapper;
-        let payload = mapper.to_dsn(&DeliveryReport {
-            correlation_id: "123".into(),
-            status: "2.0.0".into(),
-            detail: "Delivered".into(),
-        });
+        let payload = mapper.to_dsn(
+            &DeliveryReport {
+                correlation_id: "123".into(),
+                status: "2.0.0".into(),
+                detail: "Delivered".into(),
+            },
+            "user@example.com",
+            "gw.example.com",
+        );
+        assert!(payload.contains("report-type=delivery-status"));
         assert!(payload.contains("Status: 2.0.0"));
-        assert!(payload.contains("Correlation-ID: 123"));
+        assert!(payload.contains("Original-Envelope-Id: 123"));
+        assert_eq!(mapper.from_dsn(&payload, "other").correlation_id, "123");
+    }
+
+    #[test]
+    fn maps_status_codes_both_ways() {
+        assert_eq!(
+            x400_for_smtp("5.1.1"),
+            ("unable-to-transfer", Some("unrecognised-OR-name"))
+        );
+        assert_eq!(x400_for_smtp("4.9.9"), ("transfer-failure", None));
+        assert_eq!(
+            smtp_for_x400("unable-to-transfer", Some("recipient-unavailable")),
+            "5.2.1"
+        );
+        assert_eq!(smtp_for_x400("restricted-delivery", None), "5.7.0");
+        for mapping in STATUS_MAPPINGS {
+            let (reason, diagnostic) = x400_for_smtp(mapping.smtp);
+            let back = smtp_for_x400(reason, diagnostic);
+            assert_eq!(x400_for_smtp(back), (reason, diagnostic));
+        }
+
+        let mapper = ReportMapper;
+        let dsn = mapper.from_dsn("Status: 5.2.2\nAction: failed", "msg-9");
+        let report = mapper.to_report(&dsn, MessageId("msg-9".into()), Utc::now());
+        assert_eq!(report.kind, ReportKind::NonDelivery);
+        assert_eq!(report.diagnostic.as_deref(), Some("recipient-unavailable"));
+        let dsn = mapper.dsn_for_reports(&[report], &AddressMapper::default(), "gw.example.com");
+        assert_eq!(dsn.recipients[0].status, "5.2.1");
+    }
+
+    #[test]
+    fn round_trips_multi_recipient_dsns() {
+        let mapper = ReportMapper;
+        let addresses = AddressMapper::new(
+            vec![crate::gateway::address_map::AddressMappingRule::new(
+                "{S}@{O}.example",
+            )],
+            Default::default(),
+        );
+        let at = Utc::now().with_nanosecond(0).unwrap();
+        let report = |surname: &str, kind, reason: Option<&str>, diagnostic: Option<&str>| Report {
+            message_id: MessageId("msg-42".into()),
+            kind,
+            recipient: Some(Address {
+                country: "DE".into(),
+                organization: "port".into(),
+                surname: surname.into(),
+            }),
+            reason: reason.map(str::to_string),
+            diagnostic: diagnostic.map(str::to_string),
+            timestamp: at,
+        };
+        let reports = [
+            report("Pilot", ReportKind::Delivery, Some("delivered"), None),
+            // Shares 5.1.1 with unrecognised-OR-name; the X-X400 code keeps it apart.
+            report(
+                "Tug",
+                ReportKind::NonDelivery,
+                Some("unable-to-transfer"),
+                Some("ambiguous-OR-name"),
+            ),
+            report(
+                "Harbour Master",
+                ReportKind::NonDelivery,
+                Some("transfer-failure"),
+                Some("loop-detected"),
+            ),
+        ];
+
+        let dsn = mapper.dsn_for_reports(&reports, &addresses, "gw.example.com");
+        let raw = dsn.render();
+        assert!(raw.contains("Final-Recipient: rfc822; tug@port.example"));
+        assert!(raw.contains("Status: 5.1.4"));
+        assert!(raw.contains("Diagnostic-Code: X-X400; transfer-failure; loop-detected"));
+
+        let parsed = Dsn::parse(&format!(
+            "From: postmaster@gw.example.com\r\nTo: ops@port.example\r\n{raw}"
+        ))
+        .unwrap();
+        assert_eq!(parsed, dsn);
+        let back = mapper.reports_from_dsn(&parsed, &addresses, "ignored", Utc::now());
+        let summary = |report: &Report| {
+            (
+                report.message_id.clone(),
+                report.kind,
+                report.reason.clone(),
+                report.diagnostic.clone(),
+                report
+                    .recipient
+                    .as_ref()
+                    .map(|address| address.surname.clone()),
+                report.timestamp,
+            )
+        };
+        assert_eq!(
+            back.iter().map(summary).collect::<Vec<_>>(),
+            reports.iter().map(summary).collect::<Vec<_>>()
+        );
+
+        let foreign =
+            "Content-Type: multipart/report; report-type=delivery-status; boundary=b\r\n\r\n\
+            --b\r\nContent-Type: text/plain\r\n\r\nSorry.\r\n\
+            --b\r\nContent-Type: message/delivery-status\r\n\r\n\
+            Reporting-MTA: dns; mx.elsewhere.example\r\n\r\n\
+            Final-Recipient: rfc822; pilot@port.example\r\n\
+            Action: failed\r\nStatus: 5.2.2 (mailbox full)\r\n\
+            Diagnostic-Code: smtp; 552 5.2.2 over quota\r\n\r\n\
+            Final-Recipient: rfc822; tug@port.example\r\n\
+            Action: delayed\r\nStatus: 4.4.1\r\n\
+            --b--\r\n";
+        let parsed = Dsn::parse(foreign).unwrap();
+        assert_eq!(parsed.reporting_mta, "mx.elsewhere.example");
+        let reports = mapper.reports_from_dsn(&parsed, &addresses, "msg-7", at);
+        assert_eq!(reports.len(), 1, "delayed recipients are not final");
+        assert_eq!(reports[0].message_id, MessageId("msg-7".into()));
+        assert_eq!(
+            reports[0].diagnostic.as_deref(),
+            Some("recipient-unavailable")
+        );
+        assert_eq!(reports[0].timestamp, at);
+        assert_eq!(
+            Dsn::parse("Subject: hi\r\n\r\nbody"),
+            Err(DsnError::MissingStatus)
+        );
+    }
+
+    #[test]
+    fn maps_mdns_to_read_reports() {
+        let mapper = ReportMapper;
+        let addresses = AddressMapper::new(
+            vec![crate::gateway::address_map::AddressMappingRule::new(
+                "{S}@{O}.example",
+            )],
+            Default::default(),
+        );
+        let at = Utc::now().with_nanosecond(0).unwrap();
+        let read = Report {
+            message_id: MessageId("msg-42".into()),
+            kind: ReportKind::Read,
+            recipient: Some(Address {
+                country: "DE".into(),
+                organization: "port".into(),
+                surname: "Pilot".into(),
+            }),
+            reason: Some("receipt".into()),
+            diagnostic: None,
+            timestamp: at,
+        };
+
+        let mdn = mapper.mdn_for_report(&read, &addresses, "gw.example.com; x400-gateway");
+        let raw = mdn.render();
+        assert!(raw.contains("report-type=disposition-notification"));
+        assert!(raw.contains("Original-Message-ID: <msg-42@x400.gateway>"));
+        assert!(raw.contains("Disposition: manual-action/MDN-sent-automatically; displayed"));
+        let parsed = Mdn::parse(&format!("From: pilot@port.example\r\n{raw}")).unwrap();
+        assert_eq!(parsed, mdn);
+        let back = mapper
+            .report_from_mdn(&parsed, &addresses, "ignored", at)
+            .unwrap();
+        assert_eq!(back.message_id, read.message_id);
+        assert_eq!(back.kind, ReportKind::Read);
+        assert_eq!(back.recipient.unwrap().surname, "Pilot");
+        assert_eq!(mapper.from_mdn(&raw, "other").status, "read");
+        assert_eq!(mapper.from_mdn(&raw, "other").correlation_id, "msg-42");
+
+        let foreign = "Content-Type: multipart/report; report-type=disposition-notification; boundary=b\r\n\r\n\
+            --b\r\nContent-Type: text/plain\r\n\r\nNot shown.\r\n\
+            --b\r\nContent-Type: message/disposition-notification\r\n\r\n\
+            Reporting-UA: mua.elsewhere.example\r\n\
+            Final-Recipient: rfc822; tug@port.example\r\n\
+            Original-Message-ID: <abc@elsewhere.example>\r\n\
+            Disposition: automatic-action/MDN-sent-automatically; deleted/error\r\n\
+            Error: mailbox quota exceeded\r\n\
+            --b--\r\n";
+        let parsed = Mdn::parse(foreign).unwrap();
+        assert_eq!(parsed.disposition.kind, DispositionType::Deleted);
+        assert_eq!(parsed.disposition.modifiers, ["error"]);
+        assert_eq!(parsed.errors, ["mailbox quota exceeded"]);
+        assert_eq!(parsed.gateway_message_id(), None);
+        assert!(parsed.is_failure());
+        assert_eq!(
+            mapper.report_from_mdn(&parsed, &addresses, "msg-7", at),
+            None
+        );
+        assert_eq!(mapper.from_mdn(foreign, "msg-7").status, "failed");
+        assert_eq!(
+            Mdn::parse("Subject: hi\r\n\r\nbody"),
+            Err(crate::gateway::mdn::MdnError::MissingNotification)
+        );
     }
 }
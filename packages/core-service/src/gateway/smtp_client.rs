@@ -1,14 +1,32 @@
-use std::sync::{Arc, Mutex};
+use std::collections::VecDeque;
+use std::fmt;
+use std::sync::{Arc, Mutex, PoisonError};
+use std::thread;
+use std::time::{Duration, Instant};
+
+use lettre::address::Envelope;
+use lettre::transport::smtp::authentication::{Credentials, Mechanism};
+use lettre::transport::smtp::PoolConfig;
+use lettre::Address;
+use lettre::{AsyncSmtpTransport, AsyncTransport, Tokio1Executor};
+use tokio::runtime::Runtime;
+use tracing::debug;
 
 use crate::config::GatewaySmtpConfig;
+use crate::gateway::mime::{self, MimeAttachment};
+use crate::gateway::policy::DomainPolicy;
 
 /// Representation of a message scheduled for SMTP delivery.
 #[derive(Clone, Debug, PartialEq, Eq)]
 pub struct SmtpMessage {
     pub id: String,
+    pub from: String,
     pub to: Vec<String>,
     pub subject: String,
     pub body: String,
+    /// Additional headers, e.g. `X-X400-Originator`.
+    pub headers: Vec<(String, String)>,
+    pub attachments: Vec<MimeAttachment>,
 }
 
 /// Outcome returned by the gateway SMTP client.
@@ -24,67 +42,319 @@ pub struct SmtpSendOutcome {
 pub enum SmtpError {
     #[error("recipient domain is not in the allow list: {0}")]
     DomainNotAllowed(String),
+    #[error("recipient domain is on the deny list: {0}")]
+    DomainDenied(String),
     #[error("TLS is required but disabled in configuration")]
     TlsRequired,
-    #[error("rate limit exceeded")]
-    RateLimited,
+    #[error("invalid mail address: {0}")]
+    InvalidAddress(String),
+    /// The relay refused the message with a permanent (5xx) reply.
+    #[error("SMTP relay rejected message: {0}")]
+    Rejected(String),
+    /// The relay could not be reached or answered with a transient reply;
+    /// the message may be retried.
+    #[error("SMTP relay unavailable: {0}")]
+    Unavailable(String),
+}
+
+/// Where [`GatewaySmtpClient`] hands messages once they pass its checks.
+pub trait SmtpRelay: fmt::Debug + Send + Sync {
+    fn relay(&self, message: &SmtpMessage) -> Result<(), SmtpError>;
+
+    /// Messages kept by relays that do not deliver, for inspection in tests.
+    fn delivered(&self) -> Vec<SmtpMessage> {
+        Vec::new()
+    }
+}
+
+/// Keeps messages in memory instead of delivering them; the default relay
+/// when `gateway.smtp.deliver` is off. Only the latest 256 are kept.
+#[derive(Debug)]
+pub struct MemoryRelay {
+    sent: Mutex<Vec<SmtpMessage>>,
+    max_buffer: usize,
+}
+
+impl Default for MemoryRelay {
+    fn default() -> Self {
+        Self {
+            sent: Mutex::new(Vec::new()),
+            max_buffer: 256,
+        }
+    }
+}
+
+impl SmtpRelay for MemoryRelay {
+    fn relay(&self, message: &SmtpMessage) -> Result<(), SmtpError> {
+        let mut sent = self.sent.lock().unwrap_or_else(PoisonError::into_inner);
+        if sent.len() >= self.max_buffer {
+            sent.remove(0);
+        }
+        sent.push(message.clone());
+        Ok(())
+    }
+
+    fn delivered(&self) -> Vec<SmtpMessage> {
+        self.sent
+            .lock()
+            .map(|buffer| buffer.clone())
+            .unwrap_or_default()
+    }
+}
+
+/// Delivers through a real SMTP server with lettre. Connections are pooled
+/// and reused across messages; the transport runs on its own small Tokio
+/// runtime so callers stay synchronous.
+#[derive(Clone)]
+pub struct LettreRelay {
+    inner: Arc<PooledTransport>,
+}
+
+/// The pool spawns its housekeeping on the runtime when it is created and
+/// again when it is dropped, so both happen inside the runtime's context.
+struct PooledTransport {
+    transport: Option<AsyncSmtpTransport<Tokio1Executor>>,
+    runtime: Runtime,
+}
+
+impl Drop for PooledTransport {
+    fn drop(&mut self) {
+        let _context = self.runtime.enter();
+        self.transport.take();
+    }
+}
+
+impl fmt::Debug for LettreRelay {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("LettreRelay").finish_non_exhaustive()
+    }
+}
+
+impl LettreRelay {
+    /// Build the transport described by `config`: implicit TLS when
+    /// `implicitTls` is set, otherwise a STARTTLS upgrade that must succeed.
+    /// Without `tls` the connection stays in plain text, which only a local
+    /// smarthost should accept. Credentials are offered with AUTH PLAIN or
+    /// LOGIN, whichever the server announces.
+    pub fn new(config: &GatewaySmtpConfig) -> Result<Self, SmtpError> {
+        let builder = if !config.tls {
+            AsyncSmtpTransport::<Tokio1Executor>::builder_dangerous(&config.host)
+        } else if config.implicit_tls {
+            AsyncSmtpTransport::<Tokio1Executor>::relay(&config.host)
+                .map_err(|error| SmtpError::Unavailable(error.to_string()))?
+        } else {
+            AsyncSmtpTransport::<Tokio1Executor>::starttls_relay(&config.host)
+                .map_err(|error| SmtpError::Unavailable(error.to_string()))?
+        };
+        let mut builder = builder
+            .port(config.port)
+            .timeout(Some(Duration::from_millis(config.timeout_ms)))
+            .pool_config(PoolConfig::new().max_size(config.pool_size.max(1)));
+        if let (Some(username), Some(password)) = (&config.username, &config.password) {
+            builder = builder
+                .credentials(Credentials::new(username.clone(), password.clone()))
+                .authentication(vec![Mechanism::Plain, Mechanism::Login]);
+        }
+        let runtime = tokio::runtime::Builder::new_multi_thread()
+            .worker_threads(1)
+            .thread_name("smtp-relay")
+            .enable_all()
+            .build()
+            .map_err(|error| SmtpError::Unavailable(error.to_string()))?;
+        let transport = {
+            let _context = runtime.enter();
+            builder.build()
+        };
+        Ok(Self {
+            inner: Arc::new(PooledTransport {
+                transport: Some(transport),
+                runtime,
+            }),
+        })
+    }
+}
+
+impl SmtpRelay for LettreRelay {
+    fn relay(&self, message: &SmtpMessage) -> Result<(), SmtpError> {
+        let address = |address: &str| {
+            address
+                .parse::<Address>()
+                .map_err(|_| SmtpError::InvalidAddress(address.to_string()))
+        };
+        let recipients = message
+            .to
+            .iter()
+            .map(|recipient| address(recipient))
+            .collect::<Result<Vec<_>, _>>()?;
+        let envelope = Envelope::new(Some(address(&message.from)?), recipients)
+            .map_err(|error| SmtpError::InvalidAddress(error.to_string()))?;
+        let email = format!(
+            "Date: {}\r\n{}",
+            chrono::Utc::now().to_rfc2822(),
+            mime::render(message)
+        );
+        let Some(transport) = &self.inner.transport else {
+            return Err(SmtpError::Unavailable("relay is shut down".into()));
+        };
+        self.inner
+            .runtime
+            .block_on(transport.send_raw(&envelope, email.as_bytes()))
+            .map(|_| ())
+            .map_err(|error| {
+                if error.is_permanent() {
+                    SmtpError::Rejected(error.to_string())
+                } else {
+                    SmtpError::Unavailable(error.to_string())
+                }
+            })
+    }
+}
+
+/// Sliding window over the messages handed to the relay. Once `limit`
+/// messages went out within `window`, the next one waits until the oldest
+/// leaves the window; further senders queue behind it in turn.
+#[derive(Debug)]
+pub struct SendWindow {
+    limit: usize,
+    window: Duration,
+    sent: Mutex<VecDeque<Instant>>,
+    turn: Mutex<()>,
+}
+
+impl SendWindow {
+    /// A window admitting `limit` sends per `window`; `0` disables limiting.
+    pub fn new(limit: u32, window: Duration) -> Self {
+        Self {
+            limit: limit as usize,
+            window,
+            sent: Mutex::new(VecDeque::new()),
+            turn: Mutex::new(()),
+        }
+    }
+
+    /// Record a send at `now` when the window has room, or return how long
+    /// until it has.
+    pub fn try_reserve_at(&self, now: Instant) -> Result<(), Duration> {
+        if self.limit == 0 {
+            return Ok(());
+        }
+        let mut sent = self.sent.lock().unwrap_or_else(PoisonError::into_inner);
+        while sent
+            .front()
+            .is_some_and(|oldest| now.saturating_duration_since(*oldest) >= self.window)
+        {
+            sent.pop_front();
+        }
+        if sent.len() < self.limit {
+            sent.push_back(now);
+            return Ok(());
+        }
+        let oldest = sent.front().copied().unwrap_or(now);
+        Err((oldest + self.window).saturating_duration_since(now))
+    }
+
+    /// Block until the window has room and record the send. Returns how
+    /// long the caller waited.
+    pub fn reserve(&self) -> Duration {
+        let started = Instant::now();
+        let _turn = self.turn.lock().unwrap_or_else(PoisonError::into_inner);
+        while let Err(wait) = self.try_reserve_at(Instant::now()) {
+            thread::sleep(wait);
+        }
+        started.elapsed()
+    }
 }
 
-/// Lightweight SMTP client used for integration tests.
+/// SMTP client of the gateway. It enforces the [`DomainPolicy`], including
+/// per-domain TLS, holds messages back to `gateway.smtp.rateLimitPerMinute`, then hands
+/// them to its [`SmtpRelay`].
 #[derive(Clone, Debug)]
 pub struct GatewaySmtpClient {
     config: GatewaySmtpConfig,
-    allow_list: Vec<String>,
-    sent: Arc<Mutex<Vec<SmtpMessage>>>,
-    max_buffer: usize,
+    policy: DomainPolicy,
+    relay: Arc<dyn SmtpRelay>,
+    window: Arc<SendWindow>,
 }
 
 impl GatewaySmtpClient {
+    /// A client that keeps messages in a [`MemoryRelay`] and admits the
+    /// domains on `allow_list`, all over TLS.
     pub fn new(config: GatewaySmtpConfig, allow_list: Vec<String>) -> Self {
+        let window = SendWindow::new(config.rate_limit_per_minute, Duration::from_secs(60));
         Self {
             config,
-            allow_list,
-            sent: Arc::new(Mutex::new(Vec::new())),
-            max_buffer: 256,
+            policy: DomainPolicy::allow(&allow_list),
+            relay: Arc::new(MemoryRelay::default()),
+            window: Arc::new(window),
         }
     }
 
-    fn check_domain(&self, recipient: &str) -> Result<(), SmtpError> {
-        if let Some(domain) = recipient.split('@').nth(1) {
-            if self
-                .allow_list
-                .iter()
-                .any(|allowed| domain.eq_ignore_ascii_case(allowed))
-            {
-                return Ok(());
-            }
-            return Err(SmtpError::DomainNotAllowed(domain.into()));
+    /// A client that relays through `gateway.smtp.host` when
+    /// `gateway.smtp.deliver` is set, and keeps messages in memory otherwise.
+    pub fn from_config(
+        config: GatewaySmtpConfig,
+        allow_list: Vec<String>,
+    ) -> Result<Self, SmtpError> {
+        let deliver = config.deliver;
+        let client = Self::new(config, allow_list);
+        if !deliver {
+            return Ok(client);
         }
-        Ok(())
+        let relay = LettreRelay::new(&client.config)?;
+        Ok(client.with_relay(Arc::new(relay)))
     }
 
-    fn rate_limited(&self) -> bool {
-        if let Ok(sent) = self.sent.lock() {
-            sent.len() >= self.max_buffer
-        } else {
-            false
-        }
+    pub fn with_relay(mut self, relay: Arc<dyn SmtpRelay>) -> Self {
+        self.relay = relay;
+        self
     }
 
-    /// Submit a message to the mocked SMTP relay.
-    pub fn send(&self, message: SmtpMessage) -> Result<SmtpSendOutcome, SmtpError> {
-        if !self.config.tls {
+    /// Apply `gateway.smtp.rateLimitPerMinute` over `window` instead of a
+    /// minute.
+    pub fn with_window(mut self, window: Duration) -> Self {
+        self.window = Arc::new(SendWindow::new(self.config.rate_limit_per_minute, window));
+        self
+    }
+
+    /// Replace the allow list given to [`new`](Self::new) with `policy`.
+    pub fn with_policy(mut self, policy: DomainPolicy) -> Self {
+        self.policy = policy;
+        self
+    }
+
+    pub fn policy(&self) -> &DomainPolicy {
+        &self.policy
+    }
+
+    fn check_domain(&self, recipient: &str) -> Result<(), SmtpError> {
+        let Some(domain) = recipient.split('@').nth(1) else {
+            return Ok(());
+        };
+        let verdict = self.policy.evaluate(domain);
+        if verdict.denied {
+            return Err(SmtpError::DomainDenied(domain.into()));
+        }
+        if !verdict.allowed {
+            return Err(SmtpError::DomainNotAllowed(domain.into()));
+        }
+        if verdict.require_tls && !self.config.tls {
             return Err(SmtpError::TlsRequired);
         }
+        Ok(())
+    }
+
+    /// Submit a message to the relay. Over the rate limit the call blocks
+    /// until the sliding window has room, so callers are slowed down rather
+    /// than refused.
+    pub fn send(&self, message: SmtpMessage) -> Result<SmtpSendOutcome, SmtpError> {
         for recipient in &message.to {
             self.check_domain(recipient)?;
         }
-        if self.rate_limited() {
-            return Err(SmtpError::RateLimited);
-        }
-        if let Ok(mut buffer) = self.sent.lock() {
-            buffer.push(message.clone());
+        let waited = self.window.reserve();
+        if !waited.is_zero() {
+            debug!(target = "gateway.smtp", id = %message.id, ?waited, "held back by rate limit");
         }
+        self.relay.relay(&message)?;
         Ok(SmtpSendOutcome {
             accepted: true,
             message_id: message.id,
@@ -92,18 +362,16 @@ impl GatewaySmtpClient {
         })
     }
 
-    /// Retrieve the messages that have been sent through the client.
+    /// Retrieve the messages kept by the relay; empty for relays that deliver.
     pub fn delivered(&self) -> Vec<SmtpMessage> {
-        self.sent
-            .lock()
-            .map(|buffer| buffer.clone())
-            .unwrap_or_default()
+        self.relay.delivered()
     }
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::config::GatewaySecurityConfig;
 
     fn config() -> GatewaySmtpConfig {
         GatewaySmtpConfig {
@@ -113,6 +381,7 @@ mod tests {
             username: None,
             password: None,
             rate_limit_per_minute: 10,
+            ..GatewaySmtpConfig::default()
         }
     }
 
@@ -122,23 +391,67 @@ mod tests {
         let err = client
             .send(SmtpMessage {
                 id: "1".into(),
+                from: "gateway@example.com".into(),
                 to: vec!["user@forbidden.com".into()],
                 subject: "Test".into(),
                 body: "Hello".into(),
+                headers: Vec::new(),
+                attachments: Vec::new(),
             })
             .unwrap_err();
         assert!(matches!(err, SmtpError::DomainNotAllowed(_)));
     }
 
+    #[test]
+    fn applies_deny_entries_and_per_domain_tls() {
+        let client = GatewaySmtpClient::new(
+            GatewaySmtpConfig {
+                tls: false,
+                ..config()
+            },
+            Vec::new(),
+        )
+        .with_policy(DomainPolicy::from_config(&GatewaySecurityConfig {
+            enforce_tls: false,
+            domain_allow_list: vec!["*.example.com".into()],
+            domain_deny_list: vec!["old.example.com".into()],
+            tls_domains: vec!["bank.example.com".into()],
+            ..GatewaySecurityConfig::default()
+        }));
+        let send = |to: &str| {
+            client.send(SmtpMessage {
+                id: "1".into(),
+                from: "gateway@example.com".into(),
+                to: vec![to.into()],
+                subject: "Test".into(),
+                body: "Hello".into(),
+                headers: Vec::new(),
+                attachments: Vec::new(),
+            })
+        };
+        assert!(send("user@mail.example.com").is_ok());
+        assert_eq!(
+            send("user@old.example.com").unwrap_err(),
+            SmtpError::DomainDenied("old.example.com".into())
+        );
+        assert_eq!(
+            send("user@bank.example.com").unwrap_err(),
+            SmtpError::TlsRequired
+        );
+    }
+
     #[test]
     fn stores_delivered_messages() {
         let client = GatewaySmtpClient::new(config(), vec!["example.com".into()]);
         let result = client
             .send(SmtpMessage {
                 id: "42".into(),
+                from: "gateway@example.com".into(),
                 to: vec!["user@example.com".into()],
                 subject: "Test".into(),
                 body: "Hello".into(),
+                headers: Vec::new(),
+                attachments: Vec::new(),
             })
             .unwrap();
         assert!(result.accepted);
@@ -146,4 +459,185 @@ mod tests {
         assert_eq!(stored.len(), 1);
         assert_eq!(stored[0].id, "42");
     }
+
+    #[test]
+    fn sliding_window_admits_the_limit_per_window() {
+        let window = SendWindow::new(2, Duration::from_secs(60));
+        let start = Instant::now();
+        assert_eq!(window.try_reserve_at(start), Ok(()));
+        assert_eq!(
+            window.try_reserve_at(start + Duration::from_secs(10)),
+            Ok(())
+        );
+        assert_eq!(
+            window.try_reserve_at(start + Duration::from_secs(30)),
+            Err(Duration::from_secs(30))
+        );
+        // The first send leaves the window; the second still counts.
+        assert_eq!(
+            window.try_reserve_at(start + Duration::from_secs(60)),
+            Ok(())
+        );
+        assert_eq!(
+            window.try_reserve_at(start + Duration::from_secs(61)),
+            Err(Duration::from_secs(9))
+        );
+    }
+
+    #[test]
+    fn holds_messages_over_the_rate_limit_back() {
+        let client = GatewaySmtpClient::new(
+            GatewaySmtpConfig {
+                rate_limit_per_minute: 2,
+                ..config()
+            },
+            vec!["example.com".into()],
+        )
+        .with_window(Duration::from_millis(200));
+        let started = Instant::now();
+        for id in 0..3 {
+            let outcome = client
+                .send(SmtpMessage {
+                    id: id.to_string(),
+                    from: "gateway@example.com".into(),
+                    to: vec!["user@example.com".into()],
+                    subject: "Test".into(),
+                    body: "Hello".into(),
+                    headers: Vec::new(),
+                    attachments: Vec::new(),
+                })
+                .unwrap();
+            assert!(outcome.accepted);
+        }
+        assert!(started.elapsed() >= Duration::from_millis(200));
+        assert_eq!(client.delivered().len(), 3);
+    }
+
+    /// An SMTP server that only offers AUTH LOGIN and refuses `nobody@`.
+    /// Every command it reads is reported back.
+    fn fake_server() -> (u16, std::sync::mpsc::Receiver<String>) {
+        let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+        let port = listener.local_addr().unwrap().port();
+        let (commands, received) = std::sync::mpsc::channel();
+        std::thread::spawn(move || {
+            for stream in listener.incoming().map_while(Result::ok) {
+                let commands = commands.clone();
+                std::thread::spawn(move || serve_smtp(stream, commands));
+            }
+        });
+        (port, received)
+    }
+
+    fn serve_smtp(stream: std::net::TcpStream, commands: std::sync::mpsc::Sender<String>) {
+        use std::io::{BufRead, BufReader, Write};
+
+        let mut reader = BufReader::new(stream.try_clone().unwrap());
+        let mut writer = stream;
+        writer.write_all(b"220 relay.test ESMTP\r\n").unwrap();
+        let mut auth_step = 0;
+        let mut line = String::new();
+        while reader.read_line(&mut line).unwrap_or(0) > 0 {
+            let command = line.trim_end().to_string();
+            line.clear();
+            let reply: &[u8] = if auth_step > 0 {
+                auth_step += 1;
+                if auth_step == 2 {
+                    b"334 UGFzc3dvcmQ6\r\n"
+                } else {
+                    auth_step = 0;
+                    b"235 accepted\r\n"
+                }
+            } else if command.starts_with("EHLO") {
+                b"250-relay.test\r\n250 AUTH LOGIN\r\n"
+            } else if command == "AUTH LOGIN" {
+                auth_step = 1;
+                b"334 VXNlcm5hbWU6\r\n"
+            } else if command.starts_with("RCPT TO:<nobody@") {
+                b"550 no such user\r\n"
+            } else if command == "DATA" {
+                writer.write_all(b"354 go ahead\r\n").unwrap();
+                while reader.read_line(&mut line).unwrap_or(0) > 0 && line != ".\r\n" {
+                    line.clear();
+                }
+                line.clear();
+                b"250 queued\r\n"
+            } else if command == "QUIT" {
+                let _ = writer.write_all(b"221 bye\r\n");
+                break;
+            } else {
+                b"250 ok\r\n"
+            };
+            let _ = commands.send(command);
+            if writer.write_all(reply).is_err() {
+                break;
+            }
+        }
+    }
+
+    #[test]
+    fn relays_over_an_authenticated_connection() {
+        let (port, commands) = fake_server();
+        let relay = LettreRelay::new(&GatewaySmtpConfig {
+            host: "127.0.0.1".into(),
+            port,
+            tls: false,
+            username: Some("gateway".into()),
+            password: Some("secret".into()),
+            pool_size: 1,
+            timeout_ms: 5_000,
+            ..GatewaySmtpConfig::default()
+        })
+        .unwrap();
+        let message = |to: &str| SmtpMessage {
+            id: "7".into(),
+            from: "gateway@example.com".into(),
+            to: vec![to.into()],
+            subject: "Status".into(),
+            body: "All quiet".into(),
+            headers: vec![("X-X400-Originator".into(), "C=DE;O=Org;S=Gateway".into())],
+            attachments: vec![MimeAttachment {
+                filename: "status.csv".into(),
+                mime_type: "text/csv".into(),
+                data: b"queue,depth\nurgent,0\n".to_vec(),
+            }],
+        };
+
+        relay.relay(&message("ops@example.com")).unwrap();
+        relay.relay(&message("duty@example.com")).unwrap();
+        let err = relay.relay(&message("nobody@example.com")).unwrap_err();
+        assert!(matches!(err, SmtpError::Rejected(_)), "{err:?}");
+
+        let transcript: Vec<String> = commands
+            .iter()
+            .take_while(|command| !command.starts_with("RCPT TO:<nobody@"))
+            .collect();
+        assert!(transcript.contains(&"AUTH LOGIN".to_string()));
+        assert!(
+            transcript.contains(&"Z2F0ZXdheQ==".to_string()),
+            "base64 user name"
+        );
+        let recipients: Vec<&String> = transcript
+            .iter()
+            .filter(|command| command.starts_with("RCPT TO"))
+            .collect();
+        assert_eq!(
+            recipients,
+            ["RCPT TO:<ops@example.com>", "RCPT TO:<duty@example.com>"]
+        );
+    }
+
+    #[test]
+    fn delivery_is_opt_in() {
+        let client = GatewaySmtpClient::from_config(config(), vec!["example.com".into()]).unwrap();
+        assert!(format!("{client:?}").contains("MemoryRelay"));
+        let relaying = GatewaySmtpClient::from_config(
+            GatewaySmtpConfig {
+                deliver: true,
+                ..config()
+            },
+            vec!["example.com".into()],
+        )
+        .unwrap();
+        assert!(format!("{relaying:?}").contains("LettreRelay"));
+    }
 }
@@ -0,0 +1,226 @@
+//! `Idempotency-Key` handling for `POST /compose` and `POST /submit`.
+//!
+//! A client that loses the response to a submission can resend it with the
+//! same key and get the original message id back instead of a duplicate
+//! message. Key to message-id mappings live in the store's `idempotency`
+//! table for `server.idempotencyTtlSecs`; the request body is fingerprinted
+//! so a key cannot be reused for a different message.
+
+use std::collections::HashSet;
+use std::sync::{Arc, Mutex, PoisonError};
+use std::time::Duration;
+
+use serde::Serialize;
+use sha2::{Digest, Sha256};
+use thiserror::Error;
+use tracing::info;
+
+use crate::clock::SharedClock;
+use crate::config::ServerConfig;
+use crate::models::{IdempotencyRecord, MessageId};
+use crate::store::StoreManager;
+
+/// Longest key accepted, matching common gateway limits.
+const MAX_KEY_LEN: usize = 255;
+
+#[derive(Debug, Error, PartialEq, Eq)]
+pub enum IdempotencyError {
+    #[error("Idempotency-Key must be 1-{MAX_KEY_LEN} visible ASCII characters")]
+    InvalidKey,
+    #[error("Idempotency-Key {0} was already used for a different request")]
+    KeyReused(String),
+    #[error("a request with Idempotency-Key {0} is still being processed")]
+    InProgress(String),
+}
+
+impl IdempotencyError {
+    pub fn status(&self) -> u16 {
+        match self {
+            Self::InvalidKey => 400,
+            Self::KeyReused(_) => 422,
+            Self::InProgress(_) => 409,
+        }
+    }
+}
+
+/// Result of a keyed submission; `replayed` responses carry the
+/// `Idempotent-Replayed: true` header.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct IdempotentSubmit {
+    pub message_id: MessageId,
+    pub replayed: bool,
+}
+
+/// Hex SHA-256 of a request body, as stored alongside the key.
+pub fn fingerprint(body: &[u8]) -> String {
+    format!("{:x}", Sha256::digest(body))
+}
+
+#[derive(Clone)]
+pub struct IdempotencyGuard {
+    store: StoreManager,
+    ttl: Duration,
+    in_flight: Arc<Mutex<HashSet<String>>>,
+    clock: SharedClock,
+}
+
+impl IdempotencyGuard {
+    pub fn new(store: StoreManager, ttl: Duration) -> Self {
+        Self {
+            store,
+            ttl,
+            in_flight: Arc::new(Mutex::new(HashSet::new())),
+            clock: SharedClock::default(),
+        }
+    }
+
+    pub fn from_config(store: StoreManager, config: &ServerConfig) -> Self {
+        Self::new(store, Duration::from_secs(config.idempotency_ttl_secs))
+    }
+
+    pub fn with_clock(mut self, clock: SharedClock) -> Self {
+        self.clock = clock;
+        self
+    }
+
+    /// Run `submit` unless `key` already produced a message.
+    ///
+    /// The outer error rejects the key itself; the inner result is the
+    /// submission's own. A failed submission is not remembered, so the client
+    /// may retry it with the same key.
+    pub fn submit<E>(
+        &self,
+        key: &str,
+        fingerprint: &str,
+        submit: impl FnOnce() -> Result<MessageId, E>,
+    ) -> Result<Result<IdempotentSubmit, E>, IdempotencyError> {
+        if key.is_empty() || key.len() > MAX_KEY_LEN || !key.bytes().all(|b| b.is_ascii_graphic()) {
+            return Err(IdempotencyError::InvalidKey);
+        }
+        if let Some(record) = self.store.idempotency_record(key) {
+            return replay(record, fingerprint);
+        }
+
+        {
+            let mut in_flight = self
+                .in_flight
+                .lock()
+                .unwrap_or_else(PoisonError::into_inner);
+            // Re-check under the lock: a concurrent request may have finished.
+            if let Some(record) = self.store.idempotency_record(key) {
+                return replay(record, fingerprint);
+            }
+            if !in_flight.insert(key.to_string()) {
+                return Err(IdempotencyError::InProgress(key.to_string()));
+            }
+        }
+
+        let result = submit();
+        if let Ok(message_id) = &result {
+            let created_at = self.clock.now();
+            self.store.put_idempotency_record(IdempotencyRecord {
+                key: key.to_string(),
+                fingerprint: fingerprint.to_string(),
+                message_id: message_id.clone(),
+                created_at,
+                expires_at: created_at
+                    + chrono::Duration::from_std(self.ttl).unwrap_or(chrono::Duration::MAX),
+            });
+        }
+        self.in_flight
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner)
+            .remove(key);
+        Ok(result.map(|message_id| IdempotentSubmit {
+            message_id,
+            replayed: false,
+        }))
+    }
+}
+
+fn replay<E>(
+    record: IdempotencyRecord,
+    fingerprint: &str,
+) -> Result<Result<IdempotentSubmit, E>, IdempotencyError> {
+    if record.fingerprint != fingerprint {
+        return Err(IdempotencyError::KeyReused(record.key));
+    }
+    info!(target = "idempotency", key = %record.key, message = %record.message_id, "replayed");
+    Ok(Ok(IdempotentSubmit {
+        message_id: record.message_id,
+        replayed: true,
+    }))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::clock::ManualClock;
+    use chrono::{TimeZone, Utc};
+
+    #[test]
+    fn retries_return_the_original_message_until_the_key_expires() {
+        let dir = tempfile::tempdir().unwrap();
+        let table = dir.path().join("core.idempotency.json");
+        let clock = ManualClock::new(Utc.with_ymd_and_hms(2024, 9, 2, 8, 0, 0).unwrap());
+        let shared = SharedClock::new(clock.clone());
+        let store = StoreManager::new()
+            .with_clock(shared.clone())
+            .with_idempotency_table(&table);
+        let guard =
+            IdempotencyGuard::new(store, Duration::from_secs(60)).with_clock(shared.clone());
+        let body = fingerprint(br#"{"subject":"Manifest"}"#);
+        let mut submissions = 0;
+        let mut submit = || -> Result<MessageId, String> {
+            submissions += 1;
+            Ok(MessageId(format!("msg-{submissions}")))
+        };
+
+        let first = guard
+            .submit("retry-1", &body, &mut submit)
+            .unwrap()
+            .unwrap();
+        assert!(!first.replayed);
+        let again = guard
+            .submit("retry-1", &body, &mut submit)
+            .unwrap()
+            .unwrap();
+        assert_eq!(again.message_id, first.message_id);
+        assert!(again.replayed);
+        assert_eq!(
+            guard.submit("retry-1", &fingerprint(b"other"), &mut submit),
+            Err(IdempotencyError::KeyReused("retry-1".into()))
+        );
+        assert_eq!(
+            guard.submit("", &body, &mut submit),
+            Err(IdempotencyError::InvalidKey)
+        );
+
+        let failed = guard.submit("retry-2", &body, || Err::<MessageId, _>("rejected"));
+        assert_eq!(failed, Ok(Err("rejected")));
+        let retried = guard
+            .submit("retry-2", &body, &mut submit)
+            .unwrap()
+            .unwrap();
+        assert!(!retried.replayed, "failures are not remembered");
+
+        // A restarted service still knows the key.
+        let reloaded = StoreManager::new()
+            .with_clock(shared.clone())
+            .with_idempotency_table(&table);
+        assert_eq!(
+            reloaded.idempotency_record("retry-1").unwrap().message_id,
+            first.message_id
+        );
+
+        clock.advance(chrono::Duration::seconds(61));
+        let fresh = guard
+            .submit("retry-1", &body, &mut submit)
+            .unwrap()
+            .unwrap();
+        assert!(!fresh.replayed);
+        assert_ne!(fresh.message_id, first.message_id);
+        assert_eq!(submissions, 3);
+    }
+}
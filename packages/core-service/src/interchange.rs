@@ -0,0 +1,382 @@
+//! Canonical JSON interchange format for messages.
+//!
+//! Partners exchange messages as [`InterchangeMessage`] documents. The format
+//! carries a `schemaVersion`; unknown fields, unknown enum values and versions
+//! this build does not understand are rejected rather than ignored, so a
+//! document either imports exactly as written or not at all. The JSON Schema
+//! from [`schema`] is served at `GET /interchange/schema` and checked in as
+//! `api/message-interchange.schema.json`; `POST /interchange/import` and
+//! `GET /messages/:id/interchange` use [`import`] and [`export`].
+
+use chrono::{DateTime, Utc};
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+use thiserror::Error;
+
+use crate::clock::{SharedClock, SharedIds};
+use crate::models::{
+    Address, Message, MessageContent, MessageEnvelope, MessageId, MessagePriority,
+    MessageSensitivity, MessageStatus,
+};
+
+/// Version written by [`export`].
+pub const INTERCHANGE_VERSION: &str = "1.0";
+const SUPPORTED_VERSIONS: &[&str] = &["1.0"];
+const MAX_SUBJECT_CHARS: usize = 998;
+
+#[derive(Debug, Error, PartialEq, Eq)]
+pub enum InterchangeError {
+    #[error("interchange document is not valid JSON for the schema: {0}")]
+    Malformed(String),
+    #[error("unsupported interchange schemaVersion `{0}`")]
+    UnsupportedVersion(String),
+    #[error("invalid `{field}`: {reason}")]
+    Invalid { field: String, reason: String },
+}
+
+impl InterchangeError {
+    pub fn status(&self) -> u16 {
+        match self {
+            Self::Malformed(_) => 400,
+            Self::UnsupportedVersion(_) | Self::Invalid { .. } => 422,
+        }
+    }
+
+    fn invalid(field: impl Into<String>, reason: &str) -> Self {
+        Self::Invalid {
+            field: field.into(),
+            reason: reason.into(),
+        }
+    }
+}
+
+/// A message in the canonical interchange format.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize, JsonSchema)]
+#[serde(rename_all = "camelCase", deny_unknown_fields)]
+#[schemars(title = "X.400 message interchange document")]
+pub struct InterchangeMessage {
+    /// Format version, currently `1.0`.
+    pub schema_version: String,
+    /// Identifier assigned by the exporting system; a new one is assigned on import when absent.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub id: Option<String>,
+    #[schemars(length(max = 998))]
+    pub subject: String,
+    pub sender: InterchangeAddress,
+    #[schemars(length(min = 1))]
+    pub recipients: Vec<InterchangeAddress>,
+    pub body: String,
+    #[serde(default)]
+    pub priority: InterchangePriority,
+    #[serde(default)]
+    pub sensitivity: InterchangeSensitivity,
+    /// Mailbox folder; `inbox` when absent.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub folder: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub status: Option<InterchangeStatus>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub created_at: Option<DateTime<Utc>>,
+    /// X.400 latest delivery time; the message fails with an NDR if still queued after it.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub latest_delivery_time: Option<DateTime<Utc>>,
+}
+
+/// O/R address reduced to the attributes the core service stores.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize, JsonSchema)]
+#[serde(rename_all = "camelCase", deny_unknown_fields)]
+pub struct InterchangeAddress {
+    /// ISO 3166 alpha-2 country code.
+    #[schemars(regex(pattern = r"^[A-Z]{2}$"))]
+    pub country: String,
+    #[schemars(length(min = 1))]
+    pub organization: String,
+    #[schemars(length(min = 1))]
+    pub surname: String,
+}
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Serialize, Deserialize, JsonSchema)]
+#[serde(rename_all = "camelCase")]
+pub enum InterchangePriority {
+    Low,
+    #[default]
+    Normal,
+    High,
+}
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Serialize, Deserialize, JsonSchema)]
+#[serde(rename_all = "camelCase")]
+pub enum InterchangeSensitivity {
+    #[default]
+    Normal,
+    Personal,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize, JsonSchema)]
+#[serde(rename_all = "camelCase")]
+pub enum InterchangeStatus {
+    Draft,
+    Queued,
+    Sent,
+    Delivered,
+    Read,
+    Failed,
+}
+
+impl From<&Address> for InterchangeAddress {
+    fn from(address: &Address) -> Self {
+        Self {
+            country: address.country.clone(),
+            organization: address.organization.clone(),
+            surname: address.surname.clone(),
+        }
+    }
+}
+
+impl From<InterchangeAddress> for Address {
+    fn from(address: InterchangeAddress) -> Self {
+        Self {
+            country: address.country,
+            organization: address.organization,
+            surname: address.surname,
+        }
+    }
+}
+
+impl From<MessagePriority> for InterchangePriority {
+    fn from(priority: MessagePriority) -> Self {
+        match priority {
+            MessagePriority::Low => Self::Low,
+            MessagePriority::Normal => Self::Normal,
+            MessagePriority::High => Self::High,
+        }
+    }
+}
+
+impl From<InterchangePriority> for MessagePriority {
+    fn from(priority: InterchangePriority) -> Self {
+        match priority {
+            InterchangePriority::Low => Self::Low,
+            InterchangePriority::Normal => Self::Normal,
+            InterchangePriority::High => Self::High,
+        }
+    }
+}
+
+impl InterchangeStatus {
+    fn from_status(status: &MessageStatus) -> Option<Self> {
+        match status {
+            MessageStatus::Draft => Some(Self::Draft),
+            MessageStatus::Queued => Some(Self::Queued),
+            MessageStatus::Sent => Some(Self::Sent),
+            MessageStatus::Delivered => Some(Self::Delivered),
+            MessageStatus::Read => Some(Self::Read),
+            MessageStatus::Failed => Some(Self::Failed),
+            MessageStatus::Unknown => None,
+        }
+    }
+
+    fn into_status(self) -> MessageStatus {
+        match self {
+            Self::Draft => MessageStatus::Draft,
+            Self::Queued => MessageStatus::Queued,
+            Self::Sent => MessageStatus::Sent,
+            Self::Delivered => MessageStatus::Delivered,
+            Self::Read => MessageStatus::Read,
+            Self::Failed => MessageStatus::Failed,
+        }
+    }
+}
+
+impl From<&Message> for InterchangeMessage {
+    fn from(message: &Message) -> Self {
+        let envelope = &message.envelope;
+        Self {
+            schema_version: INTERCHANGE_VERSION.into(),
+            id: Some(envelope.id.0.clone()),
+            subject: envelope.subject.clone(),
+            sender: (&envelope.sender).into(),
+            recipients: envelope.recipients.iter().map(Into::into).collect(),
+            body: message.content.body.clone(),
+            priority: envelope.priority.into(),
+            sensitivity: match envelope.sensitivity {
+                MessageSensitivity::Normal => InterchangeSensitivity::Normal,
+                MessageSensitivity::Personal => InterchangeSensitivity::Personal,
+            },
+            folder: Some(envelope.folder.clone()),
+            status: InterchangeStatus::from_status(&envelope.status),
+            created_at: Some(envelope.created_at),
+            latest_delivery_time: envelope.latest_delivery,
+        }
+    }
+}
+
+impl InterchangeMessage {
+    /// Check the rules the JSON Schema expresses beyond field types.
+    pub fn validate(&self) -> Result<(), InterchangeError> {
+        if !SUPPORTED_VERSIONS.contains(&self.schema_version.as_str()) {
+            return Err(InterchangeError::UnsupportedVersion(
+                self.schema_version.clone(),
+            ));
+        }
+        if self.subject.chars().count() > MAX_SUBJECT_CHARS {
+            return Err(InterchangeError::invalid(
+                "subject",
+                "longer than 998 characters",
+            ));
+        }
+        if self.recipients.is_empty() {
+            return Err(InterchangeError::invalid(
+                "recipients",
+                "at least one recipient is required",
+            ));
+        }
+        if self.id.as_deref().is_some_and(|id| id.trim().is_empty()) {
+            return Err(InterchangeError::invalid("id", "must not be blank"));
+        }
+        validate_address("sender", &self.sender)?;
+        for (index, recipient) in self.recipients.iter().enumerate() {
+            validate_address(&format!("recipients[{index}]"), recipient)?;
+        }
+        Ok(())
+    }
+
+    /// Convert a validated document, filling in id and timestamp when absent.
+    pub fn into_message(
+        self,
+        ids: &SharedIds,
+        clock: &SharedClock,
+    ) -> Result<Message, InterchangeError> {
+        self.validate()?;
+        let mut envelope = MessageEnvelope::stamped(
+            &self.subject,
+            self.sender.into(),
+            self.recipients.into_iter().map(Into::into).collect(),
+            ids,
+            clock,
+        );
+        if let Some(id) = self.id {
+            envelope.id = MessageId(id);
+            envelope.thread_id = envelope.id.clone();
+        }
+        if let Some(created_at) = self.created_at {
+            envelope.created_at = created_at;
+        }
+        envelope.latest_delivery = self.latest_delivery_time;
+        envelope.folder = self.folder.unwrap_or_else(|| "inbox".into());
+        envelope.status = self
+            .status
+            .map_or(MessageStatus::Delivered, InterchangeStatus::into_status);
+        envelope.priority = self.priority.into();
+        envelope.sensitivity = match self.sensitivity {
+            InterchangeSensitivity::Normal => MessageSensitivity::Normal,
+            InterchangeSensitivity::Personal => MessageSensitivity::Personal,
+        };
+        Ok(Message {
+            envelope,
+            content: MessageContent {
+                body: self.body,
+                attachments: Vec::new(),
+            },
+        })
+    }
+}
+
+fn validate_address(field: &str, address: &InterchangeAddress) -> Result<(), InterchangeError> {
+    if address.country.len() != 2 || !address.country.bytes().all(|b| b.is_ascii_uppercase()) {
+        return Err(InterchangeError::invalid(
+            format!("{field}.country"),
+            "must be an ISO 3166 alpha-2 code",
+        ));
+    }
+    if address.organization.trim().is_empty() {
+        return Err(InterchangeError::invalid(
+            format!("{field}.organization"),
+            "must not be empty",
+        ));
+    }
+    if address.surname.trim().is_empty() {
+        return Err(InterchangeError::invalid(
+            format!("{field}.surname"),
+            "must not be empty",
+        ));
+    }
+    Ok(())
+}
+
+/// Parse and validate one interchange document and convert it to a message.
+pub fn import(
+    json: &str,
+    ids: &SharedIds,
+    clock: &SharedClock,
+) -> Result<Message, InterchangeError> {
+    let document: InterchangeMessage =
+        serde_json::from_str(json).map_err(|err| InterchangeError::Malformed(err.to_string()))?;
+    document.into_message(ids, clock)
+}
+
+/// Serialize a stored message as an interchange document.
+pub fn export(message: &Message) -> String {
+    serde_json::to_string_pretty(&InterchangeMessage::from(message))
+        .expect("interchange documents always serialize")
+}
+
+/// JSON Schema (draft-07) describing [`InterchangeMessage`].
+pub fn schema() -> serde_json::Value {
+    let mut schema =
+        serde_json::to_value(schemars::schema_for!(InterchangeMessage)).expect("schema serializes");
+    if let Some(object) = schema.as_object_mut() {
+        object.insert(
+            "$id".into(),
+            format!("urn:x400:message-interchange:{INTERCHANGE_VERSION}").into(),
+        );
+    }
+    schema
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::clock::{ManualClock, SequentialIds};
+    use chrono::TimeZone;
+
+    #[test]
+    fn round_trips_and_rejects_loose_documents() {
+        let clock = SharedClock::new(ManualClock::new(
+            Utc.with_ymd_and_hms(2024, 7, 1, 12, 0, 0).unwrap(),
+        ));
+        let ids = SharedIds::new(SequentialIds::new());
+
+        let minimal = r#"{
+            "schemaVersion": "1.0",
+            "subject": "Invoice 42",
+            "sender": {"country": "FR", "organization": "Partner", "surname": "Martin"},
+            "recipients": [{"country": "DE", "organization": "Modern", "surname": "Peer"}],
+            "body": "Please find the invoice attached.",
+            "priority": "high"
+        }"#;
+        let message = import(minimal, &ids, &clock).unwrap();
+        assert_eq!(message.envelope.id, MessageId("msg-1".into()));
+        assert_eq!(message.envelope.folder, "inbox");
+        assert_eq!(message.envelope.priority, MessagePriority::High);
+        assert_eq!(message.envelope.created_at, clock.now());
+
+        let exported = export(&message);
+        assert_eq!(import(&exported, &ids, &clock).unwrap(), message);
+
+        let unknown_field = minimal.replace("\"priority\"", "\"urgency\"");
+        assert!(matches!(
+            import(&unknown_field, &ids, &clock),
+            Err(InterchangeError::Malformed(_))
+        ));
+        let future = minimal.replace("\"1.0\"", "\"2.0\"");
+        let err = import(&future, &ids, &clock).unwrap_err();
+        assert_eq!(err, InterchangeError::UnsupportedVersion("2.0".into()));
+        assert_eq!(err.status(), 422);
+        let bad_country = minimal.replace("\"FR\"", "\"France\"");
+        assert_eq!(
+            import(&bad_country, &ids, &clock).unwrap_err(),
+            InterchangeError::invalid("sender.country", "must be an ISO 3166 alpha-2 code")
+        );
+    }
+}
@@ -0,0 +1,868 @@
+//! Listener for the local API.
+//!
+//! By default the API binds `server.host:server.port`. With
+//! `server.socketPath` set it binds a Unix domain socket instead, so desktop
+//! clients on the same machine skip TCP and access is governed by the file
+//! permissions of the socket (owner read/write only).
+//!
+//! [`serve`] answers every connection with the real-time channel protocol of
+//! [`ChannelSession`], one JSON command or frame per line. A client that
+//! opens with a WebSocket upgrade request for `/ws` exchanges the same
+//! commands and frames as text messages instead, see [`websocket`]. Unix
+//! socket clients are trusted through the socket's permissions and TLS
+//! clients are identified by their mutual TLS certificate. Other TCP clients
+//! send `{"type":"auth","apiKey":"…"}` (or `"authorization":"Bearer …"`)
+//! before any command; [`Authenticator::authenticate`] checks it, and a
+//! rejected attempt closes the connection. Without `security.requireAuth`
+//! such clients may skip the frame and act as anonymous. Every command spends
+//! a token of the caller's key and peer address from [`RateLimiter`]; an
+//! exhausted bucket is answered with a `rateLimited` frame carrying `status`
+//! 429 and `retryAfter` in seconds. Each command is handled inside a
+//! [`CorrelationScope`] named by its `requestId`, or a fresh id when it has
+//! none, and the reply carries that id.
+//!
+//! Each connection is [split](Connection::split) so a writer thread owns the
+//! outgoing side: replies and trace events are queued to it and written as
+//! soon as they are ready, so events reach an idle client without waiting
+//! for its next command. Events are only pushed once the caller is
+//! authenticated as a principal holding [`EVENT_SCOPE`], and at most
+//! [`OUTGOING_FRAMES`] wait for a client that stops reading; further events
+//! for it are dropped.
+//!
+//! A line longer than [`MAX_FRAME`] bytes is answered with status 413 and
+//! closes the connection. A TCP client that has not authenticated within
+//! [`AUTH_TIMEOUT`] of connecting is disconnected, and at most
+//! [`MAX_SESSIONS`] connections are served at once; further clients are
+//! closed as soon as they are accepted.
+//!
+//! With `server.tls.enabled` the TCP listener speaks TLS built by
+//! [`tls::server_config`]: [`Listener::accept`] finishes the handshake, so a
+//! client without an acceptable certificate under mutual TLS never gets a
+//! [`Connection`], and the verified certificate is available from
+//! [`Connection::client_identity`]. A Unix socket is not wrapped in TLS.
+
+use std::fmt;
+use std::io::{self, BufRead, BufReader, Read, Write};
+use std::net::{IpAddr, Ipv4Addr, TcpListener, TcpStream};
+#[cfg(unix)]
+use std::os::unix::net::{UnixListener, UnixStream};
+use std::path::PathBuf;
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use std::sync::mpsc::{self, RecvTimeoutError, SyncSender, TrySendError};
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::{Duration, Instant};
+
+use rustls::{ServerConnection, StreamOwned};
+use tracing::{info, warn};
+
+use crate::access_log::{AccessLog, AccessRecord};
+use crate::auth::{AuthError, Authenticator, Principal};
+use crate::channel::{ChannelCommand, ChannelFrame, ChannelSession, EVENT_SCOPE};
+use crate::clock::SharedIds;
+use crate::config::ServerConfig;
+use crate::rate_limit::{RateLimited, RateLimiter};
+use crate::tls::{self, ClientIdentity};
+use crate::trace::CorrelationScope;
+use crate::websocket::{self, MessageReader, SharedWriter};
+use crate::AppState;
+
+/// How long a client may take to finish the TLS handshake.
+const HANDSHAKE_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// How long a TCP client may stay silent before it has authenticated.
+pub const AUTH_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Longest command line a client may send, newline included.
+pub const MAX_FRAME: usize = 1024 * 1024;
+
+/// Connections served at once.
+pub const MAX_SESSIONS: usize = 256;
+
+/// Pause after a failed accept, e.g. while the process is out of descriptors.
+const ACCEPT_BACKOFF: Duration = Duration::from_millis(100);
+
+/// Frames queued for a connection's writer before further events are dropped.
+pub const OUTGOING_FRAMES: usize = 256;
+
+/// How often an idle event pump checks whether its connection closed.
+const EVENT_POLL: Duration = Duration::from_secs(1);
+
+/// Ciphertext read from the socket at a time; small enough that the
+/// plaintext it decrypts to always fits rustls' receive buffer.
+const TLS_READ_CHUNK: usize = 4096;
+
+/// Address the API listener is bound to.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum ListenAddress {
+    Tcp(String),
+    Unix(PathBuf),
+}
+
+impl ListenAddress {
+    pub fn from_config(config: &ServerConfig) -> Self {
+        match &config.socket_path {
+            Some(path) => Self::Unix(PathBuf::from(path)),
+            None => Self::Tcp(format!("{}:{}", config.host, config.port)),
+        }
+    }
+}
+
+impl fmt::Display for ListenAddress {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Tcp(address) => write!(f, "tcp://{address}"),
+            Self::Unix(path) => write!(f, "unix://{}", path.display()),
+        }
+    }
+}
+
+/// Bound listener; a Unix socket file is removed again when this is dropped.
+pub enum Listener {
+    Tcp {
+        listener: TcpListener,
+        tls: Option<Arc<rustls::ServerConfig>>,
+    },
+    #[cfg(unix)]
+    Unix {
+        listener: UnixListener,
+        path: PathBuf,
+    },
+}
+
+/// One accepted client connection.
+pub enum Connection {
+    Tcp(TcpStream),
+    Tls {
+        stream: Box<StreamOwned<ServerConnection, TcpStream>>,
+        identity: Option<ClientIdentity>,
+    },
+    #[cfg(unix)]
+    Unix(UnixStream),
+}
+
+impl Listener {
+    pub fn bind(config: &ServerConfig) -> io::Result<Self> {
+        let address = ListenAddress::from_config(config);
+        let listener = match &address {
+            ListenAddress::Tcp(address) => {
+                let tls = if config.tls.enabled {
+                    let tls = tls::server_config(&config.tls)
+                        .map_err(|err| io::Error::new(io::ErrorKind::InvalidInput, err))?;
+                    Some(tls)
+                } else {
+                    None
+                };
+                Self::Tcp {
+                    listener: TcpListener::bind(address)?,
+                    tls,
+                }
+            }
+            #[cfg(unix)]
+            ListenAddress::Unix(path) => Self::bind_unix(path.clone())?,
+            #[cfg(not(unix))]
+            ListenAddress::Unix(path) => {
+                return Err(io::Error::new(
+                    io::ErrorKind::Unsupported,
+                    format!("Unix sockets are not available here: {}", path.display()),
+                ))
+            }
+        };
+        info!(target = "ipc", %address, "listening");
+        Ok(listener)
+    }
+
+    #[cfg(unix)]
+    fn bind_unix(path: PathBuf) -> io::Result<Self> {
+        use std::fs;
+        use std::os::unix::fs::{DirBuilderExt, FileTypeExt, PermissionsExt};
+
+        // A socket left behind by a crashed service is replaced; a live one is not.
+        if let Ok(metadata) = fs::symlink_metadata(&path) {
+            if !metadata.file_type().is_socket() {
+                return Err(io::Error::new(
+                    io::ErrorKind::AlreadyExists,
+                    format!("{} exists and is not a socket", path.display()),
+                ));
+            }
+            if UnixStream::connect(&path).is_ok() {
+                return Err(io::Error::new(
+                    io::ErrorKind::AddrInUse,
+                    format!("{} is in use by another process", path.display()),
+                ));
+            }
+            fs::remove_file(&path)?;
+        }
+        // Bind inside an owner-only directory and move the socket into place
+        // once it is 0600, so no other user can connect in between.
+        let name = path.file_name().ok_or_else(|| {
+            io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!("{} names no socket file", path.display()),
+            )
+        })?;
+        let private = path.with_file_name(format!(
+            ".{}.{}",
+            name.to_string_lossy(),
+            std::process::id()
+        ));
+        fs::DirBuilder::new().mode(0o700).create(&private)?;
+        let staged = private.join(name);
+        let bound = UnixListener::bind(&staged).and_then(|listener| {
+            fs::set_permissions(&staged, fs::Permissions::from_mode(0o600))?;
+            fs::rename(&staged, &path)?;
+            Ok(listener)
+        });
+        let _ = fs::remove_file(&staged);
+        let removed = fs::remove_dir(&private);
+        let listener = bound?;
+        removed?;
+        Ok(Self::Unix { listener, path })
+    }
+
+    /// Wait for the next client; on a TLS listener this includes the
+    /// handshake, and a client the handshake rejects is returned as an error.
+    pub fn accept(&self) -> io::Result<Connection> {
+        self.accept_pending()?.establish()
+    }
+
+    /// Wait for the next client without running the TLS handshake, so a
+    /// slow client does not hold up the accept loop.
+    pub fn accept_pending(&self) -> io::Result<PendingConnection> {
+        match self {
+            Self::Tcp { listener, tls } => {
+                let (stream, _) = listener.accept()?;
+                Ok(match tls {
+                    Some(tls) => PendingConnection::Tls(tls.clone(), stream),
+                    None => PendingConnection::Ready(Connection::Tcp(stream)),
+                })
+            }
+            #[cfg(unix)]
+            Self::Unix { listener, .. } => listener
+                .accept()
+                .map(|(stream, _)| PendingConnection::Ready(Connection::Unix(stream))),
+        }
+    }
+
+    pub fn local_address(&self) -> io::Result<ListenAddress> {
+        match self {
+            Self::Tcp { listener, .. } => {
+                Ok(ListenAddress::Tcp(listener.local_addr()?.to_string()))
+            }
+            #[cfg(unix)]
+            Self::Unix { path, .. } => Ok(ListenAddress::Unix(path.clone())),
+        }
+    }
+}
+
+impl Drop for Listener {
+    fn drop(&mut self) {
+        #[cfg(unix)]
+        if let Self::Unix { path, .. } = self {
+            let _ = std::fs::remove_file(path);
+        }
+    }
+}
+
+/// Accepted client whose TLS handshake, if any, has not run yet.
+pub enum PendingConnection {
+    Ready(Connection),
+    Tls(Arc<rustls::ServerConfig>, TcpStream),
+}
+
+impl PendingConnection {
+    pub fn establish(self) -> io::Result<Connection> {
+        match self {
+            Self::Ready(connection) => Ok(connection),
+            Self::Tls(config, stream) => Connection::handshake(config, stream),
+        }
+    }
+}
+
+impl Connection {
+    fn handshake(config: Arc<rustls::ServerConfig>, mut stream: TcpStream) -> io::Result<Self> {
+        stream.set_read_timeout(Some(HANDSHAKE_TIMEOUT))?;
+        let mut connection = ServerConnection::new(config).map_err(io::Error::other)?;
+        while connection.is_handshaking() {
+            connection.complete_io(&mut stream)?;
+        }
+        stream.set_read_timeout(None)?;
+        let identity = connection
+            .peer_certificates()
+            .and_then(|certificates| certificates.first())
+            .map(|certificate| ClientIdentity::from_der(certificate))
+            .transpose()
+            .map_err(|err| io::Error::new(io::ErrorKind::InvalidData, err))?;
+        Ok(Self::Tls {
+            stream: Box::new(StreamOwned::new(connection, stream)),
+            identity,
+        })
+    }
+
+    /// Who is calling, when the connection itself tells: Unix socket peers
+    /// hold every scope and TLS clients the roles mapped to their
+    /// certificate. `None` for plain TCP clients, who send an `auth` frame.
+    fn principal(&self, auth: &Authenticator) -> Option<Result<Principal, AuthError>> {
+        #[cfg(unix)]
+        if let Self::Unix(_) = self {
+            return Some(Ok(Principal::local()));
+        }
+        self.client_identity()
+            .map(|identity| auth.authenticate_certificate(identity))
+    }
+
+    /// Address of the peer; Unix socket peers count as loopback.
+    fn peer_ip(&self) -> IpAddr {
+        let address = match self {
+            Self::Tcp(stream) => stream.peer_addr(),
+            Self::Tls { stream, .. } => stream.sock.peer_addr(),
+            #[cfg(unix)]
+            Self::Unix(_) => return IpAddr::V4(Ipv4Addr::LOCALHOST),
+        };
+        address
+            .map(|address| address.ip())
+            .unwrap_or(IpAddr::V4(Ipv4Addr::UNSPECIFIED))
+    }
+
+    /// Handle on the underlying TCP socket, for setting read timeouts after
+    /// the connection has been [split](Self::split).
+    fn tcp_socket(&self) -> io::Result<Option<TcpStream>> {
+        match self {
+            Self::Tcp(stream) => stream.try_clone().map(Some),
+            Self::Tls { stream, .. } => stream.sock.try_clone().map(Some),
+            #[cfg(unix)]
+            Self::Unix(_) => Ok(None),
+        }
+    }
+
+    /// Certificate the client presented during a mutual TLS handshake.
+    pub fn client_identity(&self) -> Option<&ClientIdentity> {
+        match self {
+            Self::Tls { identity, .. } => identity.as_ref(),
+            _ => None,
+        }
+    }
+
+    /// Separate read and write halves that can be used from different
+    /// threads. A TLS session is shared between them and only locked while
+    /// records are decrypted or encrypted, never while waiting for the peer.
+    pub fn split(self) -> io::Result<(Box<dyn Read + Send>, Box<dyn Write + Send>)> {
+        match self {
+            Self::Tcp(stream) => Ok((Box::new(stream.try_clone()?), Box::new(stream))),
+            Self::Tls { stream, .. } => {
+                let StreamOwned { conn, sock } = *stream;
+                let session = Arc::new(Mutex::new(conn));
+                let reader = TlsReader {
+                    session: session.clone(),
+                    socket: sock.try_clone()?,
+                };
+                Ok((
+                    Box::new(reader),
+                    Box::new(TlsWriter {
+                        session,
+                        socket: sock,
+                    }),
+                ))
+            }
+            #[cfg(unix)]
+            Self::Unix(stream) => Ok((Box::new(stream.try_clone()?), Box::new(stream))),
+        }
+    }
+}
+
+fn lock_session(
+    session: &Mutex<ServerConnection>,
+) -> io::Result<std::sync::MutexGuard<'_, ServerConnection>> {
+    session
+        .lock()
+        .map_err(|_| io::Error::other("TLS session lock poisoned"))
+}
+
+/// Read half of a split TLS connection.
+struct TlsReader {
+    session: Arc<Mutex<ServerConnection>>,
+    socket: TcpStream,
+}
+
+impl Read for TlsReader {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let mut records = [0u8; TLS_READ_CHUNK];
+        loop {
+            match lock_session(&self.session)?.reader().read(buf) {
+                Err(err) if err.kind() == io::ErrorKind::WouldBlock => {}
+                result => return result,
+            }
+            let read = self.socket.read(&mut records)?;
+            if read == 0 {
+                return Ok(0);
+            }
+            let mut session = lock_session(&self.session)?;
+            let mut received = &records[..read];
+            while !received.is_empty() {
+                session.read_tls(&mut received)?;
+                session
+                    .process_new_packets()
+                    .map_err(|err| io::Error::new(io::ErrorKind::InvalidData, err))?;
+            }
+            while session.wants_write() {
+                session.write_tls(&mut self.socket)?;
+            }
+        }
+    }
+}
+
+/// Write half of a split TLS connection.
+struct TlsWriter {
+    session: Arc<Mutex<ServerConnection>>,
+    socket: TcpStream,
+}
+
+impl Write for TlsWriter {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let mut session = lock_session(&self.session)?;
+        let written = session.writer().write(buf)?;
+        while session.wants_write() {
+            session.write_tls(&mut self.socket)?;
+        }
+        Ok(written)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        let mut session = lock_session(&self.session)?;
+        session.writer().flush()?;
+        while session.wants_write() {
+            session.write_tls(&mut self.socket)?;
+        }
+        self.socket.flush()
+    }
+}
+
+impl Read for Connection {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        match self {
+            Self::Tcp(stream) => stream.read(buf),
+            Self::Tls { stream, .. } => stream.read(buf),
+            #[cfg(unix)]
+            Self::Unix(stream) => stream.read(buf),
+        }
+    }
+}
+
+impl Write for Connection {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        match self {
+            Self::Tcp(stream) => stream.write(buf),
+            Self::Tls { stream, .. } => stream.write(buf),
+            #[cfg(unix)]
+            Self::Unix(stream) => stream.write(buf),
+        }
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        match self {
+            Self::Tcp(stream) => stream.flush(),
+            Self::Tls { stream, .. } => stream.flush(),
+            #[cfg(unix)]
+            Self::Unix(stream) => stream.flush(),
+        }
+    }
+}
+
+/// Serve clients on `listener`, each on its own thread. Failed accepts are
+/// logged and retried after a pause; a client that fails the TLS handshake
+/// only loses its own connection, and one accepted while [`MAX_SESSIONS`]
+/// are being served is closed straight away.
+pub fn serve(listener: &Listener, state: &AppState) {
+    let sessions = Arc::new(AtomicUsize::new(0));
+    loop {
+        let pending = match listener.accept_pending() {
+            Ok(pending) => pending,
+            Err(err) => {
+                warn!(target = "ipc", "accept failed: {err}");
+                thread::sleep(ACCEPT_BACKOFF);
+                continue;
+            }
+        };
+        let Some(slot) = SessionSlot::take(&sessions) else {
+            warn!(
+                target = "ipc",
+                "{MAX_SESSIONS} connections already open, refusing another"
+            );
+            continue;
+        };
+        let session = ChannelSession::new(state);
+        let auth = state.auth.clone();
+        let limiter = state.rate_limiter.clone();
+        let access_log = state.access_log.clone();
+        let ids = state.ids.clone();
+        thread::spawn(move || {
+            let _slot = slot;
+            if let Err(err) = serve_connection(pending, session, &auth, &limiter, &access_log, &ids)
+            {
+                info!(target = "ipc", "connection closed: {err}");
+            }
+        });
+    }
+}
+
+/// One of the [`MAX_SESSIONS`] places, given back when dropped.
+struct SessionSlot(Arc<AtomicUsize>);
+
+impl SessionSlot {
+    fn take(sessions: &Arc<AtomicUsize>) -> Option<Self> {
+        sessions
+            .fetch_update(Ordering::SeqCst, Ordering::SeqCst, |open| {
+                (open < MAX_SESSIONS).then_some(open + 1)
+            })
+            .ok()
+            .map(|_| Self(sessions.clone()))
+    }
+}
+
+impl Drop for SessionSlot {
+    fn drop(&mut self) {
+        self.0.fetch_sub(1, Ordering::SeqCst);
+    }
+}
+
+fn serve_connection(
+    pending: PendingConnection,
+    session: ChannelSession,
+    auth: &Authenticator,
+    limiter: &RateLimiter,
+    access_log: &AccessLog,
+    ids: &SharedIds,
+) -> io::Result<()> {
+    let connection = pending.establish()?;
+    let principal = connection.principal(auth);
+    let peer = connection.peer_ip();
+    let socket = connection.tcp_socket()?;
+    let (reader, mut writer) = connection.split()?;
+    let mut principal = match principal {
+        Some(Ok(principal)) => Some(principal),
+        Some(Err(err)) => {
+            let frame = ChannelFrame::Error {
+                request_id: None,
+                status: err.status(),
+                error: err.to_string(),
+            };
+            return writeln!(writer, "{}", frame.to_json());
+        }
+        None => auth.authenticate(None, None).ok(),
+    };
+    let lifted = Arc::new(AtomicBool::new(false));
+    let unauthenticated = socket.filter(|_| principal.is_none());
+    let mut reader = BufReader::new(AuthDeadline::new(reader, unauthenticated, lifted.clone()));
+    let upgraded = websocket::is_upgrade(reader.fill_buf()?);
+    if upgraded {
+        websocket::accept(&mut reader, &mut writer)?;
+    }
+    let writer: SharedWriter = Arc::new(Mutex::new(writer));
+    let reader: Box<dyn BufRead + Send> = if upgraded {
+        Box::new(BufReader::new(MessageReader::new(reader, writer.clone())))
+    } else {
+        Box::new(reader)
+    };
+
+    let (frames, outgoing) = mpsc::sync_channel::<String>(OUTGOING_FRAMES);
+    let writer = thread::spawn(move || -> io::Result<()> {
+        for json in outgoing {
+            let mut writer = writer
+                .lock()
+                .map_err(|_| io::Error::other("connection writer lock poisoned"))?;
+            if upgraded {
+                websocket::write_text(&mut **writer, &json)?;
+            } else {
+                writeln!(writer, "{json}")?;
+                writer.flush()?;
+            }
+        }
+        Ok(())
+    });
+    let closed = Arc::new(AtomicBool::new(false));
+    let mut pumping = false;
+    let served = serve_commands(
+        reader,
+        &session,
+        auth,
+        limiter,
+        access_log,
+        ids,
+        peer,
+        &mut principal,
+        |principal: &Option<Principal>| {
+            let Some(principal) = principal else {
+                return;
+            };
+            lifted.store(true, Ordering::SeqCst);
+            if principal.allows(EVENT_SCOPE) && !pumping {
+                pumping = true;
+                pump_events(&session, frames.clone(), closed.clone());
+            }
+        },
+        &frames,
+    );
+    closed.store(true, Ordering::SeqCst);
+    drop(frames);
+    let written = writer
+        .join()
+        .unwrap_or_else(|_| Err(io::Error::other("connection writer panicked")));
+    served.and(written)
+}
+
+/// Read commands until the client disconnects or fails to authenticate,
+/// queueing each reply for the writer. `authenticated` is told about the
+/// principal before every command so events start once there is one.
+#[allow(clippy::too_many_arguments)]
+fn serve_commands(
+    mut reader: impl BufRead,
+    session: &ChannelSession,
+    auth: &Authenticator,
+    limiter: &RateLimiter,
+    access_log: &AccessLog,
+    ids: &SharedIds,
+    peer: IpAddr,
+    principal: &mut Option<Principal>,
+    mut authenticated: impl FnMut(&Option<Principal>),
+    frames: &SyncSender<String>,
+) -> io::Result<()> {
+    let mut line = String::new();
+    loop {
+        authenticated(principal);
+        line.clear();
+        let read = (&mut reader).take(MAX_FRAME as u64).read_line(&mut line)?;
+        if read == 0 {
+            return Ok(());
+        }
+        let received = Instant::now();
+        if read == MAX_FRAME && !line.ends_with('\n') {
+            let frame = ChannelFrame::Error {
+                request_id: None,
+                status: 413,
+                error: format!("command exceeds {MAX_FRAME} bytes"),
+            };
+            let _ = frames.send(frame.to_json());
+            access_log.record(AccessRecord {
+                principal: principal
+                    .as_ref()
+                    .map(|principal| principal.key_name.as_str()),
+                method: "COMMAND",
+                path: "invalid",
+                status: frame.status(),
+                latency: received.elapsed(),
+                bytes: 0,
+                remote: Some(peer),
+            });
+            return Ok(());
+        }
+        let text = line.trim();
+        if text.is_empty() {
+            continue;
+        }
+        let command = serde_json::from_str::<ChannelCommand>(text);
+        let requested = command.as_ref().ok().and_then(ChannelCommand::request_id);
+        let scope = CorrelationScope::enter(requested.as_deref(), ids);
+        let command = command.map(|command| command.with_request_id(scope.id()));
+        let name = command.as_ref().map_or("invalid", ChannelCommand::name);
+        let mut close = false;
+        let reply = match (command, &*principal) {
+            (
+                Ok(ChannelCommand::Auth {
+                    request_id,
+                    api_key,
+                    authorization,
+                }),
+                _,
+            ) => match limiter.check(None, peer) {
+                Err(limited) => rate_limited(request_id, &limited),
+                Ok(()) => match auth.authenticate(api_key.as_deref(), authorization.as_deref()) {
+                    Ok(authenticated) => {
+                        let key = authenticated.key_name.clone();
+                        *principal = Some(authenticated);
+                        ChannelFrame::Authenticated { request_id, key }
+                    }
+                    Err(err) => {
+                        close = true;
+                        ChannelFrame::Error {
+                            request_id,
+                            status: err.status(),
+                            error: err.to_string(),
+                        }
+                    }
+                },
+            },
+            (Ok(command), None) => ChannelFrame::Error {
+                request_id: command.request_id(),
+                status: AuthError::Unauthorized.status(),
+                error: format!("{}; send an auth frame first", AuthError::Unauthorized),
+            },
+            (Ok(command), Some(principal)) => {
+                match limiter.check(Some(&principal.key_name), peer) {
+                    Err(limited) => rate_limited(command.request_id(), &limited),
+                    Ok(()) => match principal.require(command.scope()) {
+                        Ok(()) => session.handle(command),
+                        Err(err) => ChannelFrame::Error {
+                            request_id: command.request_id(),
+                            status: err.status(),
+                            error: err.to_string(),
+                        },
+                    },
+                }
+            }
+            (Err(err), _) => ChannelFrame::Error {
+                request_id: Some(scope.id().to_string()),
+                status: 400,
+                error: format!("invalid command: {err}"),
+            },
+        };
+        let json = reply.to_json();
+        let bytes = json.len() as u64 + 1;
+        frames
+            .send(json)
+            .map_err(|_| io::Error::new(io::ErrorKind::BrokenPipe, "connection writer stopped"))?;
+        access_log.record(AccessRecord {
+            principal: principal
+                .as_ref()
+                .map(|principal| principal.key_name.as_str()),
+            method: "COMMAND",
+            path: name,
+            status: reply.status(),
+            latency: received.elapsed(),
+            bytes,
+            remote: Some(peer),
+        });
+        if close {
+            return Ok(());
+        }
+    }
+}
+
+/// Read half of a connection that fails with `TimedOut` once
+/// [`AUTH_TIMEOUT`] has passed and the client has still not authenticated.
+/// `lifted` is set once it has, and `socket` is the TCP socket of a client
+/// that has to.
+struct AuthDeadline {
+    reader: Box<dyn Read + Send>,
+    pending: Option<(TcpStream, Instant)>,
+    lifted: Arc<AtomicBool>,
+}
+
+impl AuthDeadline {
+    fn new(
+        reader: Box<dyn Read + Send>,
+        socket: Option<TcpStream>,
+        lifted: Arc<AtomicBool>,
+    ) -> Self {
+        Self {
+            reader,
+            pending: socket.map(|socket| (socket, Instant::now() + AUTH_TIMEOUT)),
+            lifted,
+        }
+    }
+}
+
+impl Read for AuthDeadline {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        if self.lifted.load(Ordering::SeqCst) {
+            if let Some((socket, _)) = self.pending.take() {
+                socket.set_read_timeout(None)?;
+            }
+        }
+        if let Some((socket, deadline)) = &self.pending {
+            let left = deadline.saturating_duration_since(Instant::now());
+            if left.is_zero() {
+                return Err(io::Error::new(
+                    io::ErrorKind::TimedOut,
+                    "client did not authenticate in time",
+                ));
+            }
+            socket.set_read_timeout(Some(left))?;
+        }
+        self.reader.read(buf)
+    }
+}
+
+/// Forward trace events to the connection's writer until it closes. An event
+/// that finds the queue full is dropped rather than buffered without bound.
+fn pump_events(session: &ChannelSession, frames: SyncSender<String>, closed: Arc<AtomicBool>) {
+    let events = session.subscribe();
+    thread::spawn(move || loop {
+        match events.recv_timeout(EVENT_POLL) {
+            Ok(entry) => match frames.try_send(ChannelFrame::from(entry).to_json()) {
+                Ok(()) => {}
+                Err(TrySendError::Full(_)) => {
+                    warn!(target = "ipc", "client is not reading, event dropped");
+                }
+                Err(TrySendError::Disconnected(_)) => return,
+            },
+            Err(RecvTimeoutError::Timeout) if !closed.load(Ordering::SeqCst) => {}
+            Err(_) => return,
+        }
+    });
+}
+
+fn rate_limited(request_id: Option<String>, limited: &RateLimited) -> ChannelFrame {
+    ChannelFrame::RateLimited {
+        request_id,
+        status: 429,
+        retry_after: limited.retry_after_header().parse().unwrap_or(1),
+        error: limited.to_string(),
+    }
+}
+
+#[cfg(all(test, unix))]
+mod tests {
+    use super::*;
+    use std::fs;
+    use std::os::unix::fs::PermissionsExt;
+    use std::thread;
+
+    #[test]
+    fn serves_owner_only_unix_socket_and_cleans_up() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("core.sock");
+        let config = ServerConfig {
+            socket_path: Some(path.to_string_lossy().into_owned()),
+            ..ServerConfig::default()
+        };
+
+        // Left behind by a previous process that did not shut down cleanly.
+        drop(UnixListener::bind(&path).unwrap());
+        assert!(path.exists());
+
+        let listener = Listener::bind(&config).unwrap();
+        assert_eq!(
+            listener.local_address().unwrap(),
+            ListenAddress::Unix(path.clone())
+        );
+        let mode = fs::metadata(&path).unwrap().permissions().mode();
+        assert_eq!(mode & 0o777, 0o600);
+        // The owner-only directory the socket was bound in is gone.
+        assert_eq!(fs::read_dir(dir.path()).unwrap().count(), 1);
+
+        let client = {
+            let path = path.clone();
+            thread::spawn(move || {
+                let mut stream = UnixStream::connect(path).unwrap();
+                stream.write_all(b"ping").unwrap();
+                let mut reply = [0u8; 4];
+                stream.read_exact(&mut reply).unwrap();
+                reply
+            })
+        };
+        let mut connection = listener.accept().unwrap();
+        let mut request = [0u8; 4];
+        connection.read_exact(&mut request).unwrap();
+        assert_eq!(&request, b"ping");
+        connection.write_all(b"pong").unwrap();
+        assert_eq!(&client.join().unwrap(), b"pong");
+
+        // A second service must not steal a live socket.
+        let err = Listener::bind(&config).err().expect("socket in use");
+        assert_eq!(err.kind(), io::ErrorKind::AddrInUse);
+
+        drop(connection);
+        drop(listener);
+        assert!(!path.exists());
+    }
+}
@@ -0,0 +1,86 @@
+//! Secrets held in the operating system keychain.
+//!
+//! Configuration refers to entries as `keychain:<service>/<account>`, e.g.
+//! `keychain:x400-core/smime-pin`. Entries are read with the platform tool:
+//! `security` on macOS and `secret-tool` (libsecret) elsewhere, so nothing is
+//! linked against the keychain libraries.
+
+use std::process::Command;
+
+use thiserror::Error;
+
+#[derive(Debug, Error, PartialEq, Eq)]
+pub enum KeychainError {
+    #[error("{0} is not a keychain:<service>/<account> reference")]
+    InvalidReference(String),
+    #[error("keychain is not available: {0}")]
+    Unavailable(String),
+    #[error("no keychain entry for {service}/{account}")]
+    NotFound { service: String, account: String },
+}
+
+/// One keychain entry.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct KeychainRef {
+    pub service: String,
+    pub account: String,
+}
+
+impl KeychainRef {
+    pub fn parse(reference: &str) -> Result<Self, KeychainError> {
+        let invalid = || KeychainError::InvalidReference(reference.to_string());
+        let (service, account) = reference
+            .trim()
+            .strip_prefix("keychain:")
+            .and_then(|entry| entry.split_once('/'))
+            .ok_or_else(invalid)?;
+        if service.is_empty() || account.is_empty() {
+            return Err(invalid());
+        }
+        Ok(Self {
+            service: service.to_string(),
+            account: account.to_string(),
+        })
+    }
+
+    /// The secret stored in the entry, without a trailing newline.
+    pub fn lookup(&self) -> Result<String, KeychainError> {
+        let mut command = if cfg!(target_os = "macos") {
+            let mut command = Command::new("security");
+            command.args(["find-generic-password", "-w", "-s", &self.service]);
+            command.args(["-a", &self.account]);
+            command
+        } else {
+            let mut command = Command::new("secret-tool");
+            command.args(["lookup", "service", &self.service]);
+            command.args(["account", &self.account]);
+            command
+        };
+        let output = command
+            .output()
+            .map_err(|err| KeychainError::Unavailable(err.to_string()))?;
+        let secret = String::from_utf8_lossy(&output.stdout);
+        let secret = secret.trim_end_matches(['\r', '\n']);
+        if !output.status.success() || secret.is_empty() {
+            return Err(KeychainError::NotFound {
+                service: self.service.clone(),
+                account: self.account.clone(),
+            });
+        }
+        Ok(secret.to_string())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_service_and_account() {
+        let entry = KeychainRef::parse("keychain:x400-core/smime-pin").unwrap();
+        assert_eq!(entry.service, "x400-core");
+        assert_eq!(entry.account, "smime-pin");
+        assert!(KeychainRef::parse("keychain:x400-core").is_err());
+        assert!(KeychainRef::parse("env:SMIME_PIN").is_err());
+    }
+}
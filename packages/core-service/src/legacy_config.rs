@@ -0,0 +1,222 @@
+//! Conversion of FileWork settings into the service configuration.
+//!
+//! Accepts either the `FILEWORK.INI` file or a `regedit` export of the
+//! `FileWork` registry key. Settings with a direct equivalent are applied to an
+//! [`AppConfig`]; everything else is listed in the report so operators can
+//! carry it over by hand. Backs `--import-filework` and
+//! `POST /admin/config/import`.
+
+use std::fs;
+use std::path::Path;
+
+use serde::Serialize;
+use thiserror::Error;
+
+use crate::config::{AppConfig, ConfigError};
+
+#[derive(Debug, Error)]
+pub enum LegacyConfigError {
+    #[error("failed to read legacy configuration: {0}")]
+    Io(#[from] std::io::Error),
+    #[error("converted configuration is invalid: {0:?}")]
+    Config(ConfigError),
+}
+
+/// Legacy setting that could not be carried over.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize)]
+pub struct UnmappedSetting {
+    pub section: String,
+    pub key: String,
+    pub value: String,
+    pub reason: String,
+}
+
+/// Result of a conversion: the new configuration and what was left behind.
+#[derive(Clone, Debug)]
+pub struct LegacyConfigImport {
+    pub config: AppConfig,
+    pub mapped: Vec<(String, String)>,
+    pub unmapped: Vec<UnmappedSetting>,
+}
+
+impl LegacyConfigImport {
+    pub fn from_file(path: &Path) -> Result<Self, LegacyConfigError> {
+        let bytes = fs::read(path)?;
+        // regedit exports are UTF-16LE with a BOM; INI files are usually ANSI.
+        let contents = if bytes.starts_with(&[0xFF, 0xFE]) {
+            encoding_rs::UTF_16LE.decode(&bytes).0.into_owned()
+        } else {
+            encoding_rs::WINDOWS_1252.decode(&bytes).0.into_owned()
+        };
+        Ok(Self::parse(&contents))
+    }
+
+    /// Convert legacy settings on top of the default configuration.
+    pub fn parse(contents: &str) -> Self {
+        let mut import = Self {
+            config: AppConfig::default(),
+            mapped: Vec::new(),
+            unmapped: Vec::new(),
+        };
+        let mut section = String::new();
+
+        for line in contents.lines() {
+            let line = line.trim().trim_start_matches('\u{feff}');
+            if line.is_empty()
+                || line.starts_with(';')
+                || line.starts_with('#')
+                || line.starts_with("Windows Registry Editor")
+                || line == "REGEDIT4"
+            {
+                continue;
+            }
+            if let Some(header) = line.strip_prefix('[').and_then(|l| l.strip_suffix(']')) {
+                // Registry exports name sections by full key path; keep the leaf.
+                section = header.rsplit('\\').next().unwrap_or(header).to_string();
+                continue;
+            }
+            let Some((key, value)) = line.split_once('=') else {
+                continue;
+            };
+            let key = key.trim().trim_matches('"').to_string();
+            let value = registry_value(value.trim());
+            import.map_setting(&section, &key, &value);
+        }
+
+        import
+    }
+
+    /// Validate and write the converted configuration.
+    pub fn write(&self, path: &Path) -> Result<(), LegacyConfigError> {
+        self.config.validate().map_err(LegacyConfigError::Config)?;
+        let mut header = String::from("Converted from FileWork settings");
+        for setting in &self.unmapped {
+            header.push_str(&format!(
+                "\nunmapped [{}] {}: {}",
+                setting.section, setting.key, setting.reason
+            ));
+        }
+        self.config
+            .write_file(path, &header)
+            .map_err(LegacyConfigError::Config)
+    }
+
+    fn map_setting(&mut self, section: &str, key: &str, value: &str) {
+        let target = match target_key(section, key) {
+            Ok(target) => target,
+            Err(reason) => {
+                self.unmapped.push(UnmappedSetting {
+                    section: section.to_string(),
+                    key: key.to_string(),
+                    value: redact(key, value),
+                    reason: reason.to_string(),
+                });
+                return;
+            }
+        };
+        let value = match target {
+            "gateway.smtp.tls" => bool_value(value).to_string(),
+            _ => value.to_string(),
+        };
+        match self.config.apply(target, &value) {
+            Ok(()) => self.mapped.push((target.to_string(), value)),
+            Err(_) => self.unmapped.push(UnmappedSetting {
+                section: section.to_string(),
+                key: key.to_string(),
+                value,
+                reason: format!("value is not valid for {target}"),
+            }),
+        }
+    }
+}
+
+fn target_key(section: &str, key: &str) -> Result<&'static str, &'static str> {
+    let section = section.to_ascii_lowercase();
+    let key = key.to_ascii_lowercase();
+    if key.contains("password") || key.contains("secret") {
+        return Err("secrets must be provisioned in the keychain");
+    }
+    let target = match (section.as_str(), key.as_str()) {
+        ("server", "host" | "listenaddress") => "server.host",
+        ("server", "port") => "server.port",
+        ("storage" | "database", "path" | "database") => "database.path",
+        ("gateway", "smtphost" | "smtpserver") => "gateway.smtp.host",
+        ("gateway", "smtpport") => "gateway.smtp.port",
+        ("gateway", "smtptls" | "usetls") => "gateway.smtp.tls",
+        ("gateway", "imaphost" | "imapserver") => "gateway.imap.host",
+        ("gateway", "imapport") => "gateway.imap.port",
+        ("gateway", "mailbox" | "imapmailbox") => "gateway.imap.mailbox",
+        ("gateway", "allowdomains") => "gateway.security.allow",
+        ("gateway", "mappingrule") => "gateway.mapping.rules",
+        ("directory", "ldapurl" | "server") => "directory.ldap.url",
+        ("directory", "basedn" | "searchbase") => "directory.ldap.baseDN",
+        ("migration" | "import", "workdir" | "workspace") => "migration.workspace",
+        ("migration" | "import", "charset" | "codepage") => "migration.charsetFallback",
+        ("addressbook" | "addressbooks", _) => {
+            return Err("address books are imported through the migration workflow")
+        }
+        ("mailboxes", _) => return Err("mailboxes map to folders created on first import"),
+        ("printing" | "ui" | "window", _) => return Err("desktop-only setting"),
+        _ => return Err("no equivalent setting"),
+    };
+    Ok(target)
+}
+
+fn registry_value(raw: &str) -> String {
+    if let Some(hex) = raw.strip_prefix("dword:") {
+        return u32::from_str_radix(hex, 16)
+            .map(|value| value.to_string())
+            .unwrap_or_else(|_| raw.to_string());
+    }
+    raw.trim_matches('"').replace("\\\\", "\\")
+}
+
+fn bool_value(value: &str) -> bool {
+    matches!(
+        value.to_ascii_lowercase().as_str(),
+        "1" | "true" | "yes" | "on"
+    )
+}
+
+fn redact(key: &str, value: &str) -> String {
+    let key = key.to_ascii_lowercase();
+    if key.contains("password") || key.contains("secret") {
+        "<redacted>".into()
+    } else {
+        value.to_string()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn converts_ini_and_registry_exports() {
+        let ini = LegacyConfigImport::parse(
+            "[Gateway]\nSmtpHost=mail.corp.example\nSmtpPort=25\nUseTls=1\nSmtpPassword=hunter2\n\n[AddressBook]\nPath=C:\\FW\\ADDR.FWA\n\n[Server]\nPort=notaport\n",
+        );
+        assert_eq!(ini.config.gateway.smtp.host, "mail.corp.example");
+        assert_eq!(ini.config.gateway.smtp.port, 25);
+        assert!(ini.config.gateway.smtp.tls);
+        assert_eq!(ini.unmapped.len(), 3);
+        assert_eq!(ini.unmapped[0].value, "<redacted>");
+        assert!(ini.unmapped[2].reason.contains("server.port"));
+
+        let reg = LegacyConfigImport::parse(
+            "Windows Registry Editor Version 5.00\n\n[HKEY_LOCAL_MACHINE\\SOFTWARE\\FileWork\\Directory]\n\"LdapUrl\"=\"ldap://dir.corp.example\"\n\"BaseDN\"=\"o=corp,c=de\"\n\n[HKEY_LOCAL_MACHINE\\SOFTWARE\\FileWork\\Gateway]\n\"ImapPort\"=dword:000003e1\n",
+        );
+        assert_eq!(reg.config.directory.ldap.url, "ldap://dir.corp.example");
+        assert_eq!(reg.config.directory.ldap.base_dn, "o=corp,c=de");
+        assert_eq!(reg.config.gateway.imap.port, 993);
+        assert!(reg.unmapped.is_empty());
+
+        let temp = tempfile::tempdir().unwrap();
+        let path = temp.path().join("core.cfg");
+        ini.write(&path).unwrap();
+        let written = fs::read_to_string(&path).unwrap();
+        assert!(written.contains("gateway.smtp.host=mail.corp.example"));
+        assert!(written.contains("# unmapped [AddressBook] Path"));
+        assert!(!written.contains("hunter2"));
+    }
+}
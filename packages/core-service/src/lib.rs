@@ -1,53 +1,636 @@
+pub mod access_log;
+pub mod attachments;
+pub mod audit;
+pub mod auth;
+pub mod backup;
+pub mod bulk;
+pub mod certificates;
+pub mod channel;
+pub mod clock;
 pub mod config;
 pub mod directory;
+pub mod dlp;
+pub mod drafts;
+pub mod drain;
+pub mod expiry;
+pub mod export;
+pub mod fidelity;
+pub mod flags;
+pub mod folders;
 pub mod gateway;
+pub mod idempotency;
+pub mod interchange;
+pub mod ipc;
+pub mod keychain;
+pub mod legacy_config;
+pub mod message_table;
+pub mod metrics;
+pub mod metrics_history;
 pub mod migration;
 pub mod mock_provider;
 pub mod models;
+pub mod otlp;
+pub mod pkcs11;
 pub mod queue;
+pub mod quota;
+pub mod rate_limit;
+pub mod reports;
+pub mod retention;
+pub mod revocation;
+pub mod secrets;
+pub mod smime;
 pub mod store;
+pub mod submit;
+pub mod supervisor;
 pub mod support;
 pub mod telemetry;
+pub mod tls;
 pub mod trace;
+pub mod trace_export;
+pub mod transport;
+pub mod webhooks;
+pub mod websocket;
 
+use std::io;
+use std::path::Path;
+use std::sync::atomic::{AtomicBool, Ordering};
 use std::sync::Arc;
 
+use access_log::AccessLog;
+use attachments::AttachmentStore;
+use audit::AuditLog;
+use auth::Authenticator;
+use backup::BackupManager;
+use bulk::BulkOperations;
+use certificates::{CertificateResolver, CertificateStore};
+use clock::{SharedClock, SharedIds};
+use directory::{DirectoryCache, LdapDirectoryClient};
+use dlp::DlpEngine;
+use drafts::DraftManager;
+use drain::{DrainController, DrainReport};
+use expiry::ExpirySweeper;
+use export::MailboxExporter;
+use fidelity::FidelityRunner;
+use folders::FolderManager;
+use gateway::{
+    AddressMapper, AddressMappingRule, AliasManager, DomainPolicy, GatewayAdapter,
+    GatewayImapClient, GatewayPoller, GatewaySmtpClient, InboundIngestor, MappingReloader,
+    ReportMapper,
+};
+use idempotency::IdempotencyGuard;
+use metrics_history::MetricsHistory;
+use mock_provider::MockDeliveryProvider;
+use models::AuditAction;
 use queue::QueueManager;
+use quota::QuotaPolicy;
+use rate_limit::RateLimiter;
+use reports::ReportIngestor;
+use retention::RetentionSweeper;
+use revocation::RevocationChecker;
+use smime::SmimeService;
 use store::StoreManager;
-use support::SupportStorage;
+use submit::SubmitPolicy;
+use supervisor::{ShutdownHandle, Supervisor};
+use support::{SupportStorage, SupportUploader};
 use telemetry::TelemetryManager;
 use trace::TraceManager;
+use trace_export::TraceExport;
+use transport::sdk_events::EVENT_QUEUE;
+use transport::{
+    CircuitBreakers, P7Driver, ProfileDiscovery, RelayTransport, SdkCallRecorder, SdkEventPump,
+    StaplingVerifier, TlsPinset, TransportMode, TransportSwitch, UnloadedSdk,
+};
+use webhooks::WebhookManager;
 
 /// Shared state for the simplified core service.
 #[derive(Clone)]
 pub struct AppState {
     pub queue: QueueManager,
     pub store: StoreManager,
+    pub idempotency: IdempotencyGuard,
+    pub drafts: DraftManager,
+    pub folders: FolderManager,
+    pub bulk: BulkOperations,
+    pub backups: BackupManager,
+    pub exports: MailboxExporter,
     pub trace: TraceManager,
     pub config: Arc<config::AppConfig>,
     pub migration: migration::MigrationManager,
     pub telemetry: TelemetryManager,
     pub support: SupportStorage,
+    pub support_uploader: SupportUploader,
+    pub dlp: DlpEngine,
+    pub smime: SmimeService,
+    pub certificates: CertificateStore,
+    pub reports: ReportIngestor,
+    pub attachments: AttachmentStore,
+    pub directory: LdapDirectoryClient,
+    pub quota: QuotaPolicy,
+    pub webhooks: WebhookManager,
+    pub profiles: ProfileDiscovery,
+    pub auth: Authenticator,
+    pub breakers: CircuitBreakers,
+    pub sdk_calls: SdkCallRecorder,
+    pub p7: P7Driver,
+    /// Files events the P7 SDK raises; runs once [`AppState::start`] is called.
+    pub sdk_events: SdkEventPump,
+    /// The transport `transport.mode` selects; handlers submit through it
+    /// and `POST /admin/transport` switches it.
+    pub transport: TransportSwitch,
+    /// The mock transport behind `transport`, kept for simulating reports.
+    pub mock: MockDeliveryProvider,
+    /// The SMTP relay behind `transport`, when a relay profile is defined.
+    pub relay: Option<RelayTransport>,
+    pub stapling: StaplingVerifier,
+    /// CA pins MTA handshakes are checked against; `POST /admin/tls/pin`
+    /// confirms a learned one.
+    pub pinset: TlsPinset,
+    pub fidelity: FidelityRunner,
+    pub inbound: InboundIngestor,
+    pub gateway: GatewayAdapter,
+    pub gateway_poller: GatewayPoller,
+    pub mapping: MappingReloader,
+    pub aliases: AliasManager,
+    pub drain: DrainController,
+    pub metrics_history: MetricsHistory,
+    pub access_log: AccessLog,
+    pub trace_export: TraceExport,
+    pub audit: AuditLog,
+    pub expiry: ExpirySweeper,
+    pub supervisor: Supervisor,
+    pub retention: RetentionSweeper,
+    pub rate_limiter: RateLimiter,
+    pub revocation: RevocationChecker,
+    pub clock: SharedClock,
+    pub ids: SharedIds,
+    started: Arc<AtomicBool>,
 }
 
 impl AppState {
     pub fn new(config: config::AppConfig) -> Self {
+        Self::with_sources(config, SharedClock::default(), SharedIds::default())
+    }
+
+    /// Build the state with explicit time and identifier sources, e.g. a
+    /// [`clock::ManualClock`] and [`clock::SequentialIds`] in tests.
+    pub fn with_sources(config: config::AppConfig, clock: SharedClock, ids: SharedIds) -> Self {
         let telemetry = TelemetryManager::from_config(&config.telemetry);
-        let queue = QueueManager::with_telemetry(telemetry.clone());
-        let store = StoreManager::new();
-        let trace = TraceManager::new();
+        let store = StoreManager::new()
+            .with_clock(clock.clone())
+            .with_message_table(Path::new(&config.database.path).with_extension("messages.json"))
+            .with_flags_table(Path::new(&config.database.path).with_extension("flags.json"))
+            .with_queue_table(Path::new(&config.database.path).with_extension("queue.json"))
+            .with_idempotency_table(
+                Path::new(&config.database.path).with_extension("idempotency.json"),
+            )
+            .with_folder_table(Path::new(&config.database.path).with_extension("folders.json"))
+            .with_alias_table(Path::new(&config.database.path).with_extension("aliases.json"))
+            .with_migration_state_table(
+                Path::new(&config.database.path).with_extension("migration_state.json"),
+            )
+            .with_webhook_table(Path::new(&config.database.path).with_extension("webhooks.json"))
+            .with_held_content_table(
+                Path::new(&config.database.path).with_extension("held_content.json"),
+            )
+            .with_audit_table(Path::new(&config.database.path).with_extension("audit.jsonl"));
+        let queue = QueueManager::with_telemetry(telemetry.clone())
+            .with_store(store.clone())
+            .with_clock(clock.clone())
+            .with_aging(std::time::Duration::from_millis(config.queue.aging_ms));
+        let restored = queue.rebuild();
+        if restored > 0 {
+            tracing::info!(target = "queue", restored, "rebuilt queue from store");
+        }
+        let idempotency =
+            IdempotencyGuard::from_config(store.clone(), &config.server).with_clock(clock.clone());
+        let drafts = DraftManager::new(store.clone())
+            .with_ids(ids.clone())
+            .with_clock(clock.clone());
+        let audit = AuditLog::new(store.clone()).with_clock(clock.clone());
+        let folders = FolderManager::new(store.clone())
+            .with_ids(ids.clone())
+            .with_audit(audit.clone());
+        let trace = TraceManager::from_config(&config.tracing).with_clock(clock.clone());
+        let bulk = BulkOperations::new(store.clone(), folders.clone(), trace.clone())
+            .with_audit(audit.clone());
+        let backups = BackupManager::from_config(store.clone(), &config.database)
+            .with_audit(audit.clone())
+            .with_queue(queue.clone());
         let config = Arc::new(config);
-        let migration = migration::MigrationManager::new(store.clone());
-        let support = SupportStorage::new(".");
+        let support = SupportStorage::new(".")
+            .with_retention(config.support.retention.clone())
+            .with_clock(clock.clone());
+        let webhooks = WebhookManager::new(config.webhooks.clone())
+            .with_store(store.clone())
+            .with_clock(clock.clone())
+            .with_ids(ids.clone());
+        let reports =
+            ReportIngestor::new(store.clone(), trace.clone()).with_webhooks(webhooks.clone());
+        let attachments = AttachmentStore::new(
+            Path::new(&config.database.path)
+                .parent()
+                .unwrap_or_else(|| Path::new(".")),
+        );
+        let attachments = match (
+            config.database.use_sqlcipher,
+            config.database.sqlcipher_key.as_deref(),
+        ) {
+            (true, Some(key)) => attachments.with_encryption(key),
+            (true, None) => {
+                tracing::warn!(
+                    target = "store",
+                    "database.useSqlcipher is set without a key; attachments are stored unencrypted"
+                );
+                attachments
+            }
+            (false, _) => attachments,
+        };
+        match attachments.seal_plaintext() {
+            Ok(renamed) if !renamed.is_empty() => {
+                store.rename_attachments(&renamed);
+                audit.record(
+                    "system",
+                    AuditAction::StoreRekey,
+                    "attachments",
+                    None,
+                    Some(format!("{} plaintext blobs sealed", renamed.len())),
+                );
+            }
+            Ok(_) => {}
+            Err(err) => tracing::warn!(
+                target = "store",
+                "failed to seal plaintext attachment blobs: {err}"
+            ),
+        }
+        let dlp = DlpEngine::from_config(&config.dlp).with_attachments(attachments.clone());
+        let directory = LdapDirectoryClient::new(
+            config.directory.ldap.clone(),
+            DirectoryCache::new(
+                config.directory.cache.ttl_seconds,
+                config.directory.cache.capacity,
+            ),
+        );
+        let quota = QuotaPolicy::new(config.quota.clone(), store.clone(), attachments.clone());
+        let profiles = ProfileDiscovery::new(&config.transport.profiles_dir);
+        let relay_profile = match profiles.scan() {
+            Ok(report) => {
+                for skipped in &report.skipped {
+                    tracing::warn!(
+                        target = "transport",
+                        path = %skipped.path.display(),
+                        reason = %skipped.reason,
+                        "profile file skipped"
+                    );
+                }
+                let configured = std::iter::once(&config.transport.default_profile)
+                    .chain(&config.transport.profiles);
+                for name in configured {
+                    let defined = report
+                        .profiles
+                        .iter()
+                        .any(|profile| profile.name.eq_ignore_ascii_case(name));
+                    if !defined && config.transport.mode == "sdk" {
+                        tracing::warn!(
+                            target = "transport",
+                            profile = %name,
+                            dir = %profiles.dir().display(),
+                            "no profile file defines a configured profile"
+                        );
+                    }
+                }
+                tracing::info!(
+                    target = "transport",
+                    loaded = report.profiles.len(),
+                    skipped = report.skipped.len(),
+                    "profiles loaded"
+                );
+                // The default profile when it relays, else the first relay profile.
+                let mut relays: Vec<_> = report
+                    .profiles
+                    .into_iter()
+                    .filter(|profile| profile.mode == TransportMode::Relay)
+                    .collect();
+                relays.sort_by_key(|profile| {
+                    !profile
+                        .name
+                        .eq_ignore_ascii_case(&config.transport.default_profile)
+                });
+                relays.into_iter().next()
+            }
+            Err(error) => {
+                tracing::warn!(
+                    target = "transport",
+                    dir = %profiles.dir().display(),
+                    %error,
+                    "profiles directory unreadable"
+                );
+                None
+            }
+        };
+        let auth = Authenticator::from_config(&config.security);
+        let rate_limiter = RateLimiter::new(config.rate_limit.clone());
+        let breakers =
+            CircuitBreakers::from_config(&config.transport).with_telemetry(telemetry.clone());
+        let sdk_calls = SdkCallRecorder::new().with_telemetry(telemetry.clone());
+        let (sdk_events, sdk_event_queue) = transport::sdk_events::channel(EVENT_QUEUE);
+        let p7 = P7Driver::from_config(Arc::new(UnloadedSdk), &config.transport)
+            .with_sdk_calls(sdk_calls.clone())
+            .with_events(sdk_events)
+            .with_profile_files(profiles.clone())
+            .with_telemetry(telemetry.clone())
+            .with_clock(clock.clone());
+        let stapling = StaplingVerifier::from_config(&config.transport).with_clock(clock.clone());
+        let pinset = TlsPinset::from_config(&config.transport)
+            .with_clock(clock.clone())
+            .with_audit(audit.clone());
+
+        let mapper = AddressMapper::new(
+            config
+                .gateway
+                .mapping
+                .rules
+                .iter()
+                .map(AddressMappingRule::new)
+                .collect(),
+            Default::default(),
+        )
+        .with_alias_table(store.clone());
+        let smime = SmimeService::from_config(&config.security.smime)
+            .unwrap_or_else(|error| {
+                tracing::warn!(
+                    target = "smime",
+                    %error,
+                    "S/MIME credentials unavailable, protected submissions will be refused"
+                );
+                SmimeService::disabled()
+            })
+            .with_resolver(
+                CertificateResolver::new(&config.security.smime.certificates_dir)
+                    .with_directory(directory.clone())
+                    .with_mapper(mapper.clone()),
+            );
+        let certificates = CertificateStore::new(&config.security.smime.certificates_dir)
+            .with_clock(clock.clone())
+            .with_audit(audit.clone());
+        let aliases = AliasManager::new(store.clone()).with_audit(audit.clone());
+        let mapping =
+            MappingReloader::new(&config.gateway.mapping, mapper.clone()).with_audit(audit.clone());
+        if let Err(error) = mapping.reload() {
+            tracing::warn!(
+                target = "gateway",
+                %error,
+                "mapping file unavailable, using gateway.mapping.rules"
+            );
+        }
+        let smtp = GatewaySmtpClient::from_config(
+            config.gateway.smtp.clone(),
+            config.gateway.security.domain_allow_list.clone(),
+        )
+        .unwrap_or_else(|error| {
+            tracing::warn!(
+                target = "gateway",
+                %error,
+                "SMTP relay unavailable, keeping outbound mail in memory"
+            );
+            GatewaySmtpClient::new(
+                config.gateway.smtp.clone(),
+                config.gateway.security.domain_allow_list.clone(),
+            )
+        })
+        .with_policy(DomainPolicy::from_config(&config.gateway.security));
+        let imap =
+            GatewayImapClient::from_config(config.gateway.imap.clone(), &config.gateway.security)
+                .unwrap_or_else(|error| {
+                    tracing::warn!(
+                        target = "gateway",
+                        %error,
+                        "IMAP mailbox unavailable, reading the in-memory queue only"
+                    );
+                    GatewayImapClient::new(config.gateway.imap.clone())
+                });
+        let gateway = GatewayAdapter::new(mapper.clone(), smtp, imap, ReportMapper)
+            .with_max_hops(config.gateway.security.max_hops)
+            .with_telemetry(telemetry.clone());
+        let mock = MockDeliveryProvider::new(queue.clone(), store.clone(), trace.clone())
+            .with_reports(reports.clone())
+            .with_clock(clock.clone());
+        let relay = relay_profile.and_then(|profile| {
+            RelayTransport::from_profile(
+                &profile,
+                store.clone(),
+                reports.clone(),
+                Path::new(&config.database.path).with_extension("relay"),
+            )
+        });
+        let relay = relay.map(|relay| relay.with_clock(clock.clone()));
+        let mut mode = config.transport.mode.as_str();
+        if mode == "relay" && relay.is_none() {
+            tracing::warn!(
+                target = "transport",
+                "transport.mode is relay but no relay profile is defined, using mock"
+            );
+            mode = "mock";
+        }
+        let mut transport = TransportSwitch::new(
+            mode,
+            Arc::new(mock.clone()),
+            p7.clone(),
+            Arc::new(gateway.clone()),
+        )
+        .with_drain_timeout(std::time::Duration::from_millis(
+            config.server.drain_timeout_ms,
+        ))
+        .with_clock(clock.clone())
+        .with_telemetry(telemetry.clone())
+        .with_breakers(breakers.clone())
+        .with_quota(quota.clone())
+        .with_audit(audit.clone())
+        .with_batch_limit(config.queue.batch_limit)
+        .with_outbox(store.clone(), queue.clone())
+        .with_policy(
+            SubmitPolicy::new(trace.clone())
+                .with_dlp(dlp.clone())
+                .with_smime(smime.clone())
+                .with_audit(audit.clone()),
+            store.clone(),
+        );
+        if let Some(relay) = &relay {
+            transport = transport.with_relay(Arc::new(relay.clone()));
+        }
+        let inbound = InboundIngestor::new(mapper.clone(), store.clone())
+            .with_attachments(attachments.clone())
+            .with_quota(quota.clone())
+            .with_smime(smime.clone())
+            .with_max_hops(config.gateway.security.max_hops)
+            .with_ids(ids.clone())
+            .with_clock(clock.clone());
+        let migration = migration::MigrationManager::new(store.clone())
+            .with_mapper(mapper.clone())
+            .with_parallelism(config.migration.parallelism)
+            .with_quarantine_dir(&config.migration.quarantine)
+            .with_archive_limits(migration::FwzLimits::from_config(&config.migration))
+            .with_attachments(attachments.clone())
+            .with_directory(directory.clone())
+            .with_charset_fallback(migration::LegacyCharset::for_label(
+                &config.migration.charset_fallback,
+            ))
+            .with_audit(audit.clone())
+            .with_clock(clock.clone())
+            .with_ids(ids.clone());
+        let exports = MailboxExporter::new(store.clone(), quota.clone(), mapper.clone())
+            .with_clock(clock.clone())
+            .with_ids(ids.clone());
+        let fidelity = FidelityRunner::new(mapper, attachments.clone())
+            .with_clock(clock.clone())
+            .with_ids(ids.clone());
+
+        let drain = DrainController::from_config(&config.server);
+        let metrics_history = MetricsHistory::new().with_clock(clock.clone());
+        let access_log = AccessLog::new(config.access_log.clone()).with_clock(clock.clone());
+        let trace_export = TraceExport::new(
+            trace.clone(),
+            telemetry.clone(),
+            access_log.clone(),
+            &config,
+        )
+        .with_clock(clock.clone());
+        let expiry = ExpirySweeper::new(queue.clone(), store.clone(), reports.clone())
+            .with_telemetry(telemetry.clone())
+            .with_clock(clock.clone());
+        let supervisor = Supervisor::new(config.supervisor.clone())
+            .with_telemetry(telemetry.clone())
+            .with_clock(clock.clone());
+        let retention = RetentionSweeper::new(
+            config.retention.clone(),
+            store.clone(),
+            quota.clone(),
+            trace.clone(),
+        )
+        .with_audit(audit.clone())
+        .with_clock(clock.clone());
+        let support_uploader = SupportUploader::new(config.support.upload.clone(), support.clone())
+            .with_clock(clock.clone());
+        let gateway_poller = GatewayPoller::new(
+            &config.gateway.imap,
+            gateway.clone(),
+            inbound.clone(),
+            trace.clone(),
+        )
+        .with_reports(reports.clone())
+        .with_telemetry(telemetry.clone());
+        let revocation =
+            RevocationChecker::from_config(&config.server.tls).with_clock(clock.clone());
+        let sdk_events = SdkEventPump::new(
+            p7.clone(),
+            store.clone(),
+            inbound.clone(),
+            reports.clone(),
+            sdk_event_queue,
+        )
+        .with_clock(clock.clone());
 
         Self {
             queue,
             store,
+            idempotency,
+            drafts,
+            folders,
+            bulk,
+            backups,
+            exports,
             trace,
             config,
             migration,
             telemetry,
             support,
+            support_uploader,
+            dlp,
+            smime,
+            certificates,
+            reports,
+            attachments,
+            directory,
+            quota,
+            webhooks,
+            profiles,
+            auth,
+            breakers,
+            sdk_calls,
+            p7,
+            sdk_events,
+            transport,
+            mock,
+            relay,
+            stapling,
+            pinset,
+            fidelity,
+            inbound,
+            gateway,
+            gateway_poller,
+            mapping,
+            aliases,
+            drain,
+            metrics_history,
+            access_log,
+            trace_export,
+            audit,
+            expiry,
+            supervisor,
+            retention,
+            rate_limiter,
+            revocation,
+            clock,
+            ids,
+            started: Arc::default(),
         }
     }
+
+    /// Start the background workers under [`AppState::supervisor`].
+    ///
+    /// Building the state starts nothing, so one-shot commands and tests
+    /// leave no threads behind. Workers run until [`AppState::shutdown`] or
+    /// until the returned handle is dropped; a second call starts nothing.
+    pub fn start(&self) -> ShutdownHandle {
+        let supervisor = &self.supervisor;
+        if !self.started.swap(true, Ordering::SeqCst) {
+            self.telemetry.spawn(supervisor);
+            self.metrics_history.spawn(supervisor, &self.telemetry);
+            self.retention.spawn(supervisor);
+            self.expiry.spawn(supervisor);
+            self.webhooks.spawn(supervisor);
+            self.support_uploader.spawn(supervisor);
+            self.support.spawn(supervisor);
+            self.gateway_poller.spawn(supervisor);
+            self.revocation.spawn(supervisor);
+            self.mapping.spawn(supervisor);
+            self.mock.spawn(supervisor);
+            self.p7.spawn(supervisor);
+            if let Some(relay) = &self.relay {
+                relay.spawn(supervisor);
+            }
+            self.sdk_events.spawn(supervisor);
+        }
+        supervisor.shutdown_handle()
+    }
+
+    /// Drain the queue through the active transport on SIGTERM, then stop the
+    /// supervised workers.
+    ///
+    /// Queued messages were admitted when they were submitted, so they are
+    /// handed straight to the transport without DLP, S/MIME or quota checks;
+    /// messages that already left the queue state, e.g. delivered by the
+    /// mock, are completed without a second submission.
+    pub fn shutdown(&self) -> io::Result<DrainReport> {
+        let snapshot = Path::new(&self.config.database.path).with_extension("drain.json");
+        let report = self
+            .drain
+            .drain(&self.queue, &snapshot, |id| match self.store.get(id) {
+                Some(message) if message.envelope.status == models::MessageStatus::Queued => {
+                    self.transport.dispatch_queued(message).is_ok()
+                }
+                _ => true,
+            });
+        self.supervisor.shutdown();
+        report
+    }
 }
@@ -1,13 +1,137 @@
+use std::env;
+use std::path::Path;
+use std::process;
+
+use core_service::audit::AuditLog;
 use core_service::config::AppConfig;
+#[cfg(unix)]
+use core_service::drain::ShutdownSignals;
+use core_service::ipc::{self, ListenAddress, Listener};
+use core_service::legacy_config::LegacyConfigImport;
+use core_service::models::AuditAction;
+use core_service::secrets::SecretResolver;
+use core_service::store::StoreManager;
 use core_service::AppState;
 
 fn main() {
-    let config = AppConfig::load().unwrap_or_default();
+    #[cfg(unix)]
+    let signals = ShutdownSignals::block();
+    let args: Vec<String> = env::args().skip(1).collect();
+    if let [flag, legacy, output] = args.as_slice() {
+        if flag == "--import-filework" {
+            process::exit(import_filework(Path::new(legacy), Path::new(output)));
+        }
+    }
+    if let [flag, corpus] = args.as_slice() {
+        if flag == "--fidelity" {
+            process::exit(run_fidelity(Path::new(corpus)));
+        }
+    }
+
+    let mut config = AppConfig::load().unwrap_or_default();
+    for error in SecretResolver::default().resolve_config(&mut config) {
+        eprintln!("secret not resolved: {error}");
+    }
     let state = AppState::new(config);
+    let _workers = state.start();
+    let listener = match Listener::bind(&state.config.server) {
+        Ok(listener) => listener,
+        Err(err) => {
+            eprintln!(
+                "cannot listen on {}: {err}",
+                ListenAddress::from_config(&state.config.server)
+            );
+            process::exit(1);
+        }
+    };
+    let address = listener
+        .local_address()
+        .unwrap_or_else(|_| ListenAddress::from_config(&state.config.server));
     println!(
-        "Core service initialised on {}:{} with {} queued messages",
-        state.config.server.host,
-        state.config.server.port,
+        "Core service listening on {address} with {} queued messages",
         state.queue.pending().len()
     );
+    #[cfg(unix)]
+    {
+        let state = state.clone();
+        std::thread::spawn(move || {
+            let signal = signals.wait();
+            eprintln!("signal {signal} received, draining the queue");
+            match state.shutdown() {
+                Ok(report) => {
+                    println!(
+                        "Drained {} messages, {} failed, {} left for the next start",
+                        report.flushed, report.failed, report.persisted
+                    );
+                    process::exit(0);
+                }
+                Err(err) => {
+                    eprintln!("cannot write the queue snapshot: {err}");
+                    process::exit(1);
+                }
+            }
+        });
+    }
+    ipc::serve(&listener, &state);
+}
+
+fn import_filework(legacy: &Path, output: &Path) -> i32 {
+    let import = match LegacyConfigImport::from_file(legacy) {
+        Ok(import) => import,
+        Err(err) => {
+            eprintln!("{err}");
+            return 1;
+        }
+    };
+    if let Err(err) = import.write(output) {
+        eprintln!("{err}");
+        return 1;
+    }
+    if let Ok(config) = AppConfig::load() {
+        let store = StoreManager::new()
+            .with_audit_table(Path::new(&config.database.path).with_extension("audit.jsonl"));
+        AuditLog::new(store).record(
+            "cli",
+            AuditAction::ConfigImport,
+            &output.display().to_string(),
+            None,
+            Some(format!("from {}", legacy.display())),
+        );
+    }
+    println!(
+        "Wrote {} with {} mapped settings",
+        output.display(),
+        import.mapped.len()
+    );
+    for setting in &import.unmapped {
+        println!(
+            "unmapped [{}] {}={}: {}",
+            setting.section, setting.key, setting.value, setting.reason
+        );
+    }
+    0
+}
+
+fn run_fidelity(corpus: &Path) -> i32 {
+    let state = AppState::new(AppConfig::load().unwrap_or_default());
+    let report = match state.fidelity.run(corpus, false) {
+        Ok(report) => report,
+        Err(err) => {
+            eprintln!("{err}");
+            return 1;
+        }
+    };
+    println!(
+        "{} of {} fidelity checks passed across {} cases",
+        report.passed, report.checks, report.cases
+    );
+    for regression in &report.regressions {
+        println!(
+            "regression {} [{}]: {}",
+            regression.case,
+            regression.path.as_str(),
+            regression.detail
+        );
+    }
+    i32::from(!report.is_clean())
 }
@@ -0,0 +1,150 @@
+//! Row layout of the persistent `messages` table.
+//!
+//! Every envelope field is its own column, so listing, sorting and searching
+//! read plain values instead of decoding a serialized envelope per row.
+
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+
+use crate::models::{
+    Address, Message, MessageAttachment, MessageContent, MessageEnvelope, MessageId,
+    MessagePriority, MessageSecurity, MessageSensitivity, MessageStatus,
+};
+
+/// One row of the `messages` table.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct MessageRow {
+    pub id: MessageId,
+    pub thread_id: MessageId,
+    pub folder: String,
+    pub status: MessageStatus,
+    pub priority: MessagePriority,
+    pub sensitivity: MessageSensitivity,
+    pub subject: String,
+    pub sender_country: String,
+    pub sender_organization: String,
+    pub sender_surname: String,
+    pub recipients: Vec<Address>,
+    pub created_at: DateTime<Utc>,
+    pub latest_delivery: Option<DateTime<Utc>>,
+    pub origin_id: Option<String>,
+    pub in_reply_to: Option<String>,
+    #[serde(default)]
+    pub gateway_hops: u32,
+    #[serde(default)]
+    pub security: MessageSecurity,
+    pub body: String,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub attachments: Vec<MessageAttachment>,
+    /// [`Message::content_hash`], indexed for duplicate detection. Derived
+    /// from the other columns, so rows written without it still load.
+    #[serde(default)]
+    pub content_hash: String,
+}
+
+impl From<&Message> for MessageRow {
+    fn from(message: &Message) -> Self {
+        let envelope = &message.envelope;
+        Self {
+            id: envelope.id.clone(),
+            thread_id: envelope.thread_id.clone(),
+            folder: envelope.folder.clone(),
+            status: envelope.status.clone(),
+            priority: envelope.priority,
+            sensitivity: envelope.sensitivity.clone(),
+            subject: envelope.subject.clone(),
+            sender_country: envelope.sender.country.clone(),
+            sender_organization: envelope.sender.organization.clone(),
+            sender_surname: envelope.sender.surname.clone(),
+            recipients: envelope.recipients.clone(),
+            created_at: envelope.created_at,
+            latest_delivery: envelope.latest_delivery,
+            origin_id: envelope.origin_id.clone(),
+            in_reply_to: envelope.in_reply_to.clone(),
+            gateway_hops: envelope.gateway_hops,
+            security: envelope.security.clone(),
+            body: message.content.body.clone(),
+            attachments: message.content.attachments.clone(),
+            content_hash: message.content_hash(),
+        }
+    }
+}
+
+impl From<MessageRow> for Message {
+    fn from(row: MessageRow) -> Self {
+        Self {
+            envelope: MessageEnvelope {
+                id: row.id,
+                subject: row.subject,
+                sender: Address {
+                    country: row.sender_country,
+                    organization: row.sender_organization,
+                    surname: row.sender_surname,
+                },
+                recipients: row.recipients,
+                folder: row.folder,
+                status: row.status,
+                priority: row.priority,
+                sensitivity: row.sensitivity,
+                created_at: row.created_at,
+                latest_delivery: row.latest_delivery,
+                origin_id: row.origin_id,
+                in_reply_to: row.in_reply_to,
+                gateway_hops: row.gateway_hops,
+                security: row.security,
+                thread_id: row.thread_id,
+            },
+            content: MessageContent {
+                body: row.body,
+                attachments: row.attachments,
+            },
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::store::StoreManager;
+
+    #[test]
+    fn store_reloads_the_columns_and_lists_by_date() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("core.messages.json");
+        let store = StoreManager::new().with_message_table(&path);
+        let mut ids = Vec::new();
+        for (subject, created_at) in [
+            ("msg-old", "2024-03-01T09:00:00Z"),
+            ("msg-new", "2024-03-02T09:00:00Z"),
+        ] {
+            let mut envelope = MessageEnvelope::new(subject, Address::sample(), Vec::new());
+            envelope.folder = "inbox".into();
+            envelope.created_at = created_at.parse().unwrap();
+            ids.push(envelope.id.clone());
+            store.save(Message {
+                envelope,
+                content: MessageContent {
+                    body: String::new(),
+                    attachments: Vec::new(),
+                },
+            });
+        }
+        let table: serde_json::Value =
+            serde_json::from_slice(&std::fs::read(&path).unwrap()).unwrap();
+        assert_eq!(table[0]["senderSurname"], Address::sample().surname);
+
+        let reopened = StoreManager::new().with_message_table(&path);
+        let subjects: Vec<String> = reopened
+            .list("inbox")
+            .into_iter()
+            .map(|message| message.envelope.subject)
+            .collect();
+        assert_eq!(subjects, ["msg-new", "msg-old"]);
+
+        reopened.move_message(&ids[0], "archive");
+        let reopened = StoreManager::new().with_message_table(&path);
+        assert_eq!(reopened.list("inbox").len(), 1);
+        assert_eq!(reopened.list("archive")[0].envelope.subject, "msg-old");
+    }
+}
@@ -0,0 +1,311 @@
+//! Prometheus metrics served by `GET /metrics`.
+//!
+//! Managers report through [`TelemetryManager`](crate::telemetry::TelemetryManager),
+//! which feeds one [`MetricsRegistry`]; the metrics themselves are the
+//! constants below. [`MetricsRegistry::render`] produces the text exposition
+//! format, served with [`CONTENT_TYPE`].
+
+use std::collections::BTreeMap;
+use std::fmt::Write as _;
+use std::sync::{Arc, Mutex};
+
+/// `Content-Type` of the `/metrics` response.
+pub const CONTENT_TYPE: &str = "text/plain; version=0.0.4; charset=utf-8";
+
+/// Upper bounds, in seconds, of the latency histogram buckets.
+pub const LATENCY_BUCKETS: [f64; 12] = [
+    0.005, 0.01, 0.025, 0.05, 0.1, 0.25, 0.5, 1.0, 2.5, 5.0, 10.0, 30.0,
+];
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum MetricKind {
+    Counter,
+    Gauge,
+    Histogram,
+}
+
+impl MetricKind {
+    fn as_str(self) -> &'static str {
+        match self {
+            Self::Counter => "counter",
+            Self::Gauge => "gauge",
+            Self::Histogram => "histogram",
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Metric {
+    pub name: &'static str,
+    pub help: &'static str,
+    pub kind: MetricKind,
+}
+
+pub const QUEUE_DEPTH: Metric = Metric {
+    name: "x400_queue_depth",
+    help: "Messages waiting in the outbound queue.",
+    kind: MetricKind::Gauge,
+};
+pub const FLOWS: Metric = Metric {
+    name: "x400_flows_total",
+    help: "Queue and gateway flows by outcome.",
+    kind: MetricKind::Counter,
+};
+pub const SUBMISSIONS: Metric = Metric {
+    name: "x400_submissions_total",
+    help: "Submissions handed to the active transport, by outcome.",
+    kind: MetricKind::Counter,
+};
+pub const SUBMIT_DURATION: Metric = Metric {
+    name: "x400_submit_duration_seconds",
+    help: "Time the active transport took to accept a submission.",
+    kind: MetricKind::Histogram,
+};
+pub const ERRORS: Metric = Metric {
+    name: "x400_errors_total",
+    help: "Errors reported to telemetry.",
+    kind: MetricKind::Counter,
+};
+pub const WORKER_RESTARTS: Metric = Metric {
+    name: "x400_worker_restarts_total",
+    help: "Supervised workers restarted after a crash or a missed heartbeat.",
+    kind: MetricKind::Counter,
+};
+pub const SESSION_LOSSES: Metric = Metric {
+    name: "x400_p7_session_losses_total",
+    help: "P7 sessions lost and re-bound.",
+    kind: MetricKind::Counter,
+};
+pub const GATEWAY_MESSAGES: Metric = Metric {
+    name: "x400_gateway_messages_total",
+    help: "Messages relayed by the SMTP gateway, by direction and outcome.",
+    kind: MetricKind::Counter,
+};
+pub const SDK_CALLS: Metric = Metric {
+    name: "x400_sdk_calls_total",
+    help: "Vendor SDK calls by operation and outcome.",
+    kind: MetricKind::Counter,
+};
+pub const SDK_CALL_DURATION: Metric = Metric {
+    name: "x400_sdk_call_duration_seconds",
+    help: "Duration of vendor SDK calls.",
+    kind: MetricKind::Histogram,
+};
+pub const SDK_TIMEOUTS: Metric = Metric {
+    name: "x400_sdk_timeouts_total",
+    help: "Vendor SDK calls given up on after their timeout.",
+    kind: MetricKind::Counter,
+};
+
+type Labels = Vec<(&'static str, String)>;
+
+#[derive(Default)]
+struct Series {
+    /// Counter or gauge value; the sum of observations for a histogram.
+    value: f64,
+    /// Observations per bucket, not cumulative.
+    buckets: [u64; LATENCY_BUCKETS.len()],
+    count: u64,
+}
+
+struct Family {
+    metric: Metric,
+    series: BTreeMap<Labels, Series>,
+}
+
+/// One metric and its series, as read by [`MetricsRegistry::snapshot`].
+#[derive(Clone, Debug, PartialEq)]
+pub struct MetricFamily {
+    pub metric: Metric,
+    pub series: Vec<MetricSeries>,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct MetricSeries {
+    pub labels: Vec<(&'static str, String)>,
+    /// Counter or gauge value; the sum of observations for a histogram.
+    pub value: f64,
+    /// Histogram observations per [`LATENCY_BUCKETS`] bound, not cumulative;
+    /// empty for counters and gauges.
+    pub buckets: Vec<u64>,
+    pub count: u64,
+}
+
+/// Counters, gauges and histograms by label set.
+#[derive(Clone, Default)]
+pub struct MetricsRegistry {
+    families: Arc<Mutex<BTreeMap<&'static str, Family>>>,
+}
+
+impl MetricsRegistry {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn increment(&self, metric: &Metric, labels: &[(&'static str, &str)]) {
+        self.update(metric, labels, |series| series.value += 1.0);
+    }
+
+    pub fn set(&self, metric: &Metric, labels: &[(&'static str, &str)], value: f64) {
+        self.update(metric, labels, |series| series.value = value);
+    }
+
+    /// Add one observation, in seconds, to a histogram.
+    pub fn observe(&self, metric: &Metric, labels: &[(&'static str, &str)], seconds: f64) {
+        self.update(metric, labels, |series| {
+            if let Some(bucket) = LATENCY_BUCKETS.iter().position(|bound| seconds <= *bound) {
+                series.buckets[bucket] += 1;
+            }
+            series.value += seconds;
+            series.count += 1;
+        });
+    }
+
+    fn update(
+        &self,
+        metric: &Metric,
+        labels: &[(&'static str, &str)],
+        apply: impl FnOnce(&mut Series),
+    ) {
+        let Ok(mut families) = self.families.lock() else {
+            return;
+        };
+        let family = families.entry(metric.name).or_insert_with(|| Family {
+            metric: *metric,
+            series: BTreeMap::new(),
+        });
+        let labels = labels
+            .iter()
+            .map(|(name, value)| (*name, value.to_string()))
+            .collect();
+        apply(family.series.entry(labels).or_default());
+    }
+
+    /// Every metric recorded so far, for exporters other than `/metrics`.
+    pub fn snapshot(&self) -> Vec<MetricFamily> {
+        let Ok(families) = self.families.lock() else {
+            return Vec::new();
+        };
+        families
+            .values()
+            .map(|family| MetricFamily {
+                metric: family.metric,
+                series: family
+                    .series
+                    .iter()
+                    .map(|(labels, series)| MetricSeries {
+                        labels: labels.clone(),
+                        value: series.value,
+                        buckets: if family.metric.kind == MetricKind::Histogram {
+                            series.buckets.to_vec()
+                        } else {
+                            Vec::new()
+                        },
+                        count: series.count,
+                    })
+                    .collect(),
+            })
+            .collect()
+    }
+
+    /// Every metric in the Prometheus text exposition format.
+    pub fn render(&self) -> String {
+        let mut out = String::new();
+        let Ok(families) = self.families.lock() else {
+            return out;
+        };
+        for family in families.values() {
+            let Metric { name, help, kind } = family.metric;
+            let _ = writeln!(out, "# HELP {name} {help}");
+            let _ = writeln!(out, "# TYPE {name} {}", kind.as_str());
+            for (labels, series) in &family.series {
+                if kind != MetricKind::Histogram {
+                    let _ = writeln!(out, "{name}{} {}", label_set(labels, None), series.value);
+                    continue;
+                }
+                let mut cumulative = 0;
+                for (bound, count) in LATENCY_BUCKETS.iter().zip(series.buckets) {
+                    cumulative += count;
+                    let le = bound.to_string();
+                    let _ = writeln!(
+                        out,
+                        "{name}_bucket{} {cumulative}",
+                        label_set(labels, Some(&le))
+                    );
+                }
+                let _ = writeln!(
+                    out,
+                    "{name}_bucket{} {}",
+                    label_set(labels, Some("+Inf")),
+                    series.count
+                );
+                let _ = writeln!(
+                    out,
+                    "{name}_sum{} {}",
+                    label_set(labels, None),
+                    series.value
+                );
+                let _ = writeln!(
+                    out,
+                    "{name}_count{} {}",
+                    label_set(labels, None),
+                    series.count
+                );
+            }
+        }
+        out
+    }
+}
+
+/// `{name="value",…}` with an optional `le` bucket label; empty without labels.
+fn label_set(labels: &Labels, le: Option<&str>) -> String {
+    let pairs: Vec<String> = labels
+        .iter()
+        .map(|(name, value)| (*name, value.as_str()))
+        .chain(le.map(|le| ("le", le)))
+        .map(|(name, value)| {
+            let escaped = value
+                .replace('\\', "\\\\")
+                .replace('"', "\\\"")
+                .replace('\n', "\\n");
+            format!("{name}=\"{escaped}\"")
+        })
+        .collect();
+    if pairs.is_empty() {
+        String::new()
+    } else {
+        format!("{{{}}}", pairs.join(","))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn renders_the_text_exposition_format() {
+        let registry = MetricsRegistry::new();
+        registry.set(&QUEUE_DEPTH, &[], 3.0);
+        registry.increment(&SDK_CALLS, &[("operation", "submit"), ("outcome", "ok")]);
+        registry.increment(&SDK_CALLS, &[("operation", "submit"), ("outcome", "ok")]);
+        registry.increment(&FLOWS, &[("flow", "odd\"flow"), ("outcome", "error")]);
+        registry.observe(&SUBMIT_DURATION, &[("transport", "mock")], 0.02);
+        registry.observe(&SUBMIT_DURATION, &[("transport", "mock")], 60.0);
+
+        let text = registry.render();
+        assert!(text.contains("# TYPE x400_queue_depth gauge\nx400_queue_depth 3\n"));
+        assert!(text.contains("x400_sdk_calls_total{operation=\"submit\",outcome=\"ok\"} 2\n"));
+        assert!(text.contains("x400_flows_total{flow=\"odd\\\"flow\",outcome=\"error\"} 1\n"));
+        assert!(text.contains(
+            "x400_submit_duration_seconds_bucket{transport=\"mock\",le=\"0.01\"} 0\n\
+             x400_submit_duration_seconds_bucket{transport=\"mock\",le=\"0.025\"} 1\n"
+        ));
+        assert!(
+            text.contains("x400_submit_duration_seconds_bucket{transport=\"mock\",le=\"30\"} 1\n")
+        );
+        assert!(text
+            .contains("x400_submit_duration_seconds_bucket{transport=\"mock\",le=\"+Inf\"} 2\n"));
+        assert!(text.contains("x400_submit_duration_seconds_sum{transport=\"mock\"} 60.02\n"));
+        assert!(text.contains("x400_submit_duration_seconds_count{transport=\"mock\"} 2\n"));
+    }
+}
@@ -0,0 +1,312 @@
+//! Queue statistics history for dashboard trends (`GET /metrics/history`).
+//!
+//! The `metrics-history` worker calls [`MetricsHistory::sample`] once a minute
+//! with the current telemetry counters. Every sample is folded into three tiers at once
+//! — minute, hour and day buckets — and each tier keeps only its own retention,
+//! so older data survives at coarser resolution without a separate rollup job.
+
+use std::collections::VecDeque;
+use std::sync::{Arc, Mutex, PoisonError};
+use std::thread;
+use std::time::Duration;
+
+use chrono::{DateTime, DurationRound, Utc};
+use serde::{Deserialize, Serialize};
+use thiserror::Error;
+
+use crate::clock::SharedClock;
+use crate::supervisor::Supervisor;
+use crate::telemetry::{TelemetryManager, TelemetryMetrics};
+
+/// How often the worker samples the telemetry counters.
+pub const SAMPLE_INTERVAL: Duration = Duration::from_secs(60);
+
+#[derive(Debug, Error, PartialEq, Eq)]
+pub enum HistoryError {
+    #[error("invalid history window `{0}`; use e.g. 90m, 24h or 30d")]
+    InvalidWindow(String),
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub enum Resolution {
+    Minute,
+    Hour,
+    Day,
+}
+
+impl Resolution {
+    fn step(self) -> chrono::Duration {
+        match self {
+            Self::Minute => chrono::Duration::minutes(1),
+            Self::Hour => chrono::Duration::hours(1),
+            Self::Day => chrono::Duration::days(1),
+        }
+    }
+
+    fn retention(self) -> chrono::Duration {
+        match self {
+            Self::Minute => chrono::Duration::hours(6),
+            Self::Hour => chrono::Duration::days(7),
+            Self::Day => chrono::Duration::days(365),
+        }
+    }
+}
+
+/// Aggregated queue statistics for one bucket.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct MetricsPoint {
+    pub start: DateTime<Utc>,
+    pub samples: u32,
+    pub queue_depth_avg: f64,
+    pub queue_depth_max: usize,
+    /// Messages sent during the bucket.
+    pub sent: u64,
+    pub errors: u64,
+}
+
+impl MetricsPoint {
+    pub fn error_rate(&self) -> f64 {
+        let total = self.sent + self.errors;
+        if total == 0 {
+            0.0
+        } else {
+            self.errors as f64 / total as f64
+        }
+    }
+
+    fn merge(&mut self, depth: usize, sent: u64, errors: u64) {
+        let samples = f64::from(self.samples);
+        self.queue_depth_avg = (self.queue_depth_avg * samples + depth as f64) / (samples + 1.0);
+        self.queue_depth_max = self.queue_depth_max.max(depth);
+        self.samples += 1;
+        self.sent += sent;
+        self.errors += errors;
+    }
+}
+
+/// Response body of `GET /metrics/history?window=`.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct MetricsHistoryView {
+    pub window_seconds: i64,
+    pub resolution: Resolution,
+    pub points: Vec<MetricsPoint>,
+}
+
+struct Tier {
+    resolution: Resolution,
+    points: VecDeque<MetricsPoint>,
+}
+
+impl Tier {
+    fn add(&mut self, at: DateTime<Utc>, depth: usize, sent: u64, errors: u64) {
+        let start = at.duration_trunc(self.resolution.step()).unwrap_or(at);
+        match self.points.back_mut() {
+            Some(point) if point.start == start => point.merge(depth, sent, errors),
+            _ => {
+                let mut point = MetricsPoint {
+                    start,
+                    samples: 0,
+                    queue_depth_avg: 0.0,
+                    queue_depth_max: 0,
+                    sent: 0,
+                    errors: 0,
+                };
+                point.merge(depth, sent, errors);
+                self.points.push_back(point);
+            }
+        }
+        let cutoff = at - self.resolution.retention();
+        while self
+            .points
+            .front()
+            .is_some_and(|point| point.start < cutoff)
+        {
+            self.points.pop_front();
+        }
+    }
+}
+
+struct HistoryState {
+    tiers: [Tier; 3],
+    last_counters: Option<(u64, u64)>,
+}
+
+#[derive(Clone)]
+pub struct MetricsHistory {
+    state: Arc<Mutex<HistoryState>>,
+    clock: SharedClock,
+}
+
+impl Default for MetricsHistory {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl MetricsHistory {
+    pub fn new() -> Self {
+        let tier = |resolution| Tier {
+            resolution,
+            points: VecDeque::new(),
+        };
+        Self {
+            state: Arc::new(Mutex::new(HistoryState {
+                tiers: [
+                    tier(Resolution::Minute),
+                    tier(Resolution::Hour),
+                    tier(Resolution::Day),
+                ],
+                last_counters: None,
+            })),
+            clock: SharedClock::default(),
+        }
+    }
+
+    pub fn with_clock(mut self, clock: SharedClock) -> Self {
+        self.clock = clock;
+        self
+    }
+
+    /// Record one sample from the cumulative telemetry counters.
+    ///
+    /// Throughput and errors are the difference to the previous sample; the
+    /// first sample only establishes the baseline.
+    pub fn sample(&self, metrics: &TelemetryMetrics) {
+        let now = self.clock.now();
+        let Ok(mut state) = self.state.lock() else {
+            return;
+        };
+        let counters = (metrics.messages_sent, metrics.error_count);
+        let (sent, errors) = match state.last_counters {
+            Some((sent, errors)) => (
+                counters.0.saturating_sub(sent),
+                counters.1.saturating_sub(errors),
+            ),
+            None => (0, 0),
+        };
+        state.last_counters = Some(counters);
+        for tier in &mut state.tiers {
+            tier.add(now, metrics.queue_depth, sent, errors);
+        }
+    }
+
+    /// Start the `metrics-history` worker sampling `telemetry` every
+    /// [`SAMPLE_INTERVAL`].
+    pub fn spawn(&self, supervisor: &Supervisor, telemetry: &TelemetryManager) {
+        let history = self.clone();
+        let telemetry = telemetry.clone();
+        supervisor.spawn("metrics-history", move |context| {
+            while !context.should_stop() {
+                history.sample(&telemetry.snapshot().metrics);
+                let mut waited = Duration::ZERO;
+                while waited < SAMPLE_INTERVAL && !context.should_stop() {
+                    context.heartbeat();
+                    let step = (SAMPLE_INTERVAL - waited).min(Duration::from_secs(1));
+                    thread::sleep(step);
+                    waited += step;
+                }
+            }
+        });
+    }
+
+    /// Points covering `window` at the finest resolution that still retains it.
+    pub fn history(&self, window: &str) -> Result<MetricsHistoryView, HistoryError> {
+        let span = parse_window(window)?;
+        let cutoff = self.clock.now() - span;
+        let state = self.state.lock().unwrap_or_else(PoisonError::into_inner);
+        let tier = state
+            .tiers
+            .iter()
+            .find(|tier| tier.resolution.retention() >= span)
+            .unwrap_or(&state.tiers[2]);
+        let bucket_cutoff = cutoff
+            .duration_trunc(tier.resolution.step())
+            .unwrap_or(cutoff);
+        Ok(MetricsHistoryView {
+            window_seconds: span.num_seconds(),
+            resolution: tier.resolution,
+            points: tier
+                .points
+                .iter()
+                .filter(|point| point.start >= bucket_cutoff)
+                .cloned()
+                .collect(),
+        })
+    }
+}
+
+fn parse_window(window: &str) -> Result<chrono::Duration, HistoryError> {
+    let invalid = || HistoryError::InvalidWindow(window.to_string());
+    let window = window.trim();
+    let split = window
+        .char_indices()
+        .last()
+        .map(|(index, _)| index)
+        .ok_or_else(invalid)?;
+    let (amount, unit) = window.split_at(split);
+    let amount: i64 = amount.parse().map_err(|_| invalid())?;
+    if amount <= 0 {
+        return Err(invalid());
+    }
+    match unit {
+        "m" => Ok(chrono::Duration::minutes(amount)),
+        "h" => Ok(chrono::Duration::hours(amount)),
+        "d" => Ok(chrono::Duration::days(amount)),
+        _ => Err(invalid()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::clock::ManualClock;
+    use chrono::TimeZone;
+
+    #[test]
+    fn downsamples_minutes_into_hours_and_days() {
+        let clock = ManualClock::new(Utc.with_ymd_and_hms(2024, 3, 1, 0, 0, 0).unwrap());
+        let history = MetricsHistory::new().with_clock(SharedClock::new(clock.clone()));
+        let mut metrics = TelemetryMetrics::default();
+
+        // Ten hours of one sample per minute, two messages and a failure every ten minutes.
+        for minute in 0..600u64 {
+            metrics.messages_sent += 2;
+            metrics.error_count += u64::from(minute % 10 == 0);
+            metrics.queue_depth = (minute % 5) as usize;
+            history.sample(&metrics);
+            clock.advance(chrono::Duration::minutes(1));
+        }
+
+        let recent = history.history("30m").unwrap();
+        assert_eq!(recent.resolution, Resolution::Minute);
+        assert_eq!(recent.points.len(), 30);
+        assert!(recent.points.iter().all(|point| point.sent == 2));
+
+        // Minute data older than six hours is gone; hours still cover the day.
+        assert!(history.history("6h").unwrap().points.len() <= 361);
+        let day = history.history("24h").unwrap();
+        assert_eq!(day.resolution, Resolution::Hour);
+        assert_eq!(day.points.len(), 10);
+        assert_eq!(day.points[1].samples, 60);
+        assert_eq!(day.points[1].sent, 120);
+        assert_eq!(day.points[1].errors, 6);
+        assert_eq!(day.points[1].queue_depth_max, 4);
+        assert!((day.points[1].queue_depth_avg - 2.0).abs() < f64::EPSILON);
+        assert!((day.points[1].error_rate() - 6.0 / 126.0).abs() < 1e-9);
+
+        let month = history.history("30d").unwrap();
+        assert_eq!(month.resolution, Resolution::Day);
+        assert_eq!(month.points.len(), 1);
+        assert_eq!(month.points[0].sent, 2 * 599);
+
+        assert_eq!(
+            history.history("soon"),
+            Err(HistoryError::InvalidWindow("soon".into()))
+        );
+        assert!(history.history("0h").is_err());
+        assert!(history.history("5µ").is_err());
+    }
+}
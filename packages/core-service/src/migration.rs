@@ -1,11 +1,14 @@
-use std::collections::{BTreeMap, HashMap};
+use std::collections::{BTreeMap, BTreeSet, HashMap};
 use std::fs::{self, File};
-use std::io::{self, Read};
+use std::io::{self, Read, Write};
 use std::path::{Path, PathBuf};
-use std::sync::{Arc, Mutex};
+use std::sync::mpsc::{self, SyncSender};
+use std::sync::{Arc, Condvar, Mutex};
+use std::thread;
 
 use chardetng::EncodingDetector;
 use chrono::{DateTime, Utc};
+use encoding_rs::Encoding;
 use serde::{Deserialize, Serialize};
 use sha2::{Digest, Sha256};
 use thiserror::Error;
@@ -13,12 +16,20 @@ use uuid::Uuid;
 use walkdir::WalkDir;
 use zip::read::ZipArchive;
 
+use crate::attachments::{AttachmentError, AttachmentStore};
+use crate::audit::AuditLog;
+use crate::clock::{SharedClock, SharedIds};
+use crate::config::MigrationConfig;
+use crate::directory::{DirectoryEntry, DistributionList, LdapDirectoryClient};
+use crate::folders::SYSTEM_FOLDERS;
+use crate::gateway::inbound::{self, mailbox, split_headers};
+use crate::gateway::{AddressMapper, InboundMessage};
 use crate::models::{
-    Address, Message, MessageContent, MessageEnvelope, MessagePriority, MessageSensitivity,
-    MessageStatus,
+    Address, AuditAction, Message, MessageAttachment, MessageContent, MessageEnvelope, MessageId,
+    MessagePriority, MessageSensitivity, MessageStatus, MigrationCheckpoint,
 };
 use crate::store::StoreManager;
-use tracing::instrument;
+use tracing::{info, instrument, warn};
 
 /// Errors that can occur during migration.
 #[derive(Debug, Error)]
@@ -27,12 +38,46 @@ pub enum MigrationError {
     Io(#[from] io::Error),
     #[error("unable to open archive: {0}")]
     Archive(#[from] zip::result::ZipError),
+    #[error("failed to store attachment: {0}")]
+    Attachment(#[from] AttachmentError),
     #[error("file does not contain legacy metadata")]
     EmptyDocument,
     #[error("document contained an invalid record: {0}")]
     InvalidRecord(String),
     #[error("the requested job could not be found")]
     UnknownJob,
+    #[error("the job has already finished")]
+    Finished,
+    #[error("the job has not finished yet")]
+    InProgress,
+    #[error("unknown character set: {0}")]
+    UnknownCharset(String),
+    #[error("archive exceeds the extraction limit: {0}")]
+    TooLarge(String),
+    #[error("{0} does not match the archive manifest")]
+    ManifestMismatch(String),
+}
+
+impl MigrationError {
+    /// Whether the artifact itself is unreadable, as opposed to the
+    /// filesystem or the job, so it belongs in quarantine.
+    fn is_corrupt(&self) -> bool {
+        matches!(
+            self,
+            Self::Archive(_) | Self::EmptyDocument | Self::InvalidRecord(_) | Self::TooLarge(_)
+        )
+    }
+
+    pub fn status(&self) -> u16 {
+        match self {
+            Self::UnknownJob => 404,
+            Self::Finished | Self::InProgress => 409,
+            Self::Archive(_) | Self::UnknownCharset(_) => 400,
+            Self::EmptyDocument | Self::InvalidRecord(_) | Self::ManifestMismatch(_) => 422,
+            Self::TooLarge(_) => 413,
+            Self::Io(_) | Self::Attachment(_) => 500,
+        }
+    }
 }
 
 /// Legacy metadata document extracted from FileWork artifacts.
@@ -163,20 +208,104 @@ fn parse_address(input: &str) -> Option<Address> {
     })
 }
 
-/// Decode a legacy FileWork metadata document.
-#[instrument(name = "migration.parse_fwm", skip(bytes))]
+/// Upper half of code page 437; the lower half is ASCII.
+const CP437_HIGH: &str = "ÇüéâäàåçêëèïîìÄÅÉæÆôöòûùÿÖÜ¢£¥₧ƒáíóúñÑªº¿⌐¬½¼¡«»░▒▓│┤╡╢╖╕╣║╗╝╜╛┐\
+                          └┴┬├─┼╞╟╚╔╩╦╠═╬╧╨╤╥╙╘╒╓╫╪┘┌█▄▌▐▀αßΓπΣσµτΦΘΩδ∞φε∩≡±≥≤⌠⌡÷≈°∙·√ⁿ²■\u{a0}";
+
+/// Character set of legacy FileWork text.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum LegacyCharset {
+    /// Any ASCII-compatible encoding `encoding_rs` knows, e.g. `iso-8859-15`.
+    Standard(&'static Encoding),
+    /// The DOS code page many early archives were written in.
+    Cp437,
+}
+
+impl LegacyCharset {
+    /// Look up a charset by label, e.g. `cp437` or `ISO-8859-15`.
+    pub fn for_label(label: &str) -> Option<Self> {
+        let label = label.trim();
+        if ["cp437", "ibm437", "437"]
+            .iter()
+            .any(|alias| label.eq_ignore_ascii_case(alias))
+        {
+            return Some(Self::Cp437);
+        }
+        Encoding::for_label(label.as_bytes())
+            .filter(|encoding| encoding.is_ascii_compatible())
+            .map(Self::Standard)
+    }
+
+    /// The text, or `None` if `bytes` are not valid in this charset.
+    fn decode(self, bytes: &[u8]) -> Option<String> {
+        match self {
+            Self::Standard(encoding) => {
+                let (text, had_errors) = encoding.decode_without_bom_handling(bytes);
+                (!had_errors).then(|| text.into_owned())
+            }
+            Self::Cp437 => {
+                let high: Vec<char> = CP437_HIGH.chars().collect();
+                Some(
+                    bytes
+                        .iter()
+                        .map(|&byte| match byte {
+                            0..=0x7f => char::from(byte),
+                            _ => high[usize::from(byte - 0x80)],
+                        })
+                        .collect(),
+                )
+            }
+        }
+    }
+}
+
+/// How [`parse_fwm_with`] picks the character set of a document.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct FwmDecoding {
+    /// Use this charset without detection; a job's `encoding` override.
+    pub forced: Option<LegacyCharset>,
+    /// Tried when detection is unsure or its guess does not decode cleanly
+    /// (`migration.charsetFallback`).
+    pub fallback: Option<LegacyCharset>,
+}
+
+impl FwmDecoding {
+    fn decode(&self, bytes: &[u8]) -> Result<String, MigrationError> {
+        if let Some(forced) = self.forced {
+            return forced
+                .decode(bytes)
+                .ok_or_else(|| MigrationError::InvalidRecord("encoding error".into()));
+        }
+        let mut detector = EncodingDetector::new();
+        detector.feed(bytes, true);
+        let (guess, confident) = detector.guess_assess(None, true);
+        let guess = LegacyCharset::Standard(guess);
+        let candidates = if confident {
+            [Some(guess), self.fallback]
+        } else {
+            [self.fallback, Some(guess)]
+        };
+        candidates
+            .into_iter()
+            .flatten()
+            .find_map(|charset| charset.decode(bytes))
+            .ok_or_else(|| MigrationError::InvalidRecord("encoding error".into()))
+    }
+}
+
+/// Decode a legacy FileWork metadata document, detecting its charset.
 pub fn parse_fwm(bytes: &[u8]) -> Result<FwmDocument, MigrationError> {
+    parse_fwm_with(bytes, FwmDecoding::default())
+}
+
+/// Decode a legacy FileWork metadata document with an explicit charset policy.
+#[instrument(name = "migration.parse_fwm", skip(bytes))]
+pub fn parse_fwm_with(bytes: &[u8], decoding: FwmDecoding) -> Result<FwmDocument, MigrationError> {
     if bytes.is_empty() {
         return Err(MigrationError::EmptyDocument);
     }
 
-    let mut detector = EncodingDetector::new();
-    detector.feed(bytes, true);
-    let encoding = detector.guess(None, true);
-    let (cow, _, had_errors) = encoding.decode(bytes);
-    if had_errors {
-        return Err(MigrationError::InvalidRecord("encoding error".into()));
-    }
+    let cow = decoding.decode(bytes)?;
 
     let mut values = BTreeMap::new();
     let mut attachments = Vec::new();
@@ -209,65 +338,816 @@ pub fn parse_fwm(bytes: &[u8]) -> Result<FwmDocument, MigrationError> {
     })
 }
 
-/// Attachment summary extracted from archives.
+/// Attachment extracted from archives.
+///
+/// The content is spooled to a temporary file while the archive is read; the
+/// file is removed once the last clone of the summary is dropped.
 #[derive(Clone, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
 pub struct AttachmentSummary {
     pub name: String,
     pub size: u64,
     pub sha256: String,
+    #[serde(skip)]
+    spooled: Option<Arc<SpoolFile>>,
+}
+
+/// Temporary file holding one extracted attachment, deleted on drop.
+#[derive(Debug, PartialEq, Eq)]
+struct SpoolFile(PathBuf);
+
+impl SpoolFile {
+    fn create() -> io::Result<(Self, File)> {
+        let path = std::env::temp_dir().join(format!("x400-fwz-{}.part", Uuid::new_v4()));
+        let file = File::create(&path)?;
+        Ok((Self(path), file))
+    }
+}
+
+impl Drop for SpoolFile {
+    fn drop(&mut self) {
+        let _ = fs::remove_file(&self.0);
+    }
+}
+
+impl AttachmentSummary {
+    /// Read the extracted content back; empty for a summary that was
+    /// deserialized rather than extracted.
+    pub fn open(&self) -> io::Result<Box<dyn Read>> {
+        match &self.spooled {
+            Some(spooled) => Ok(Box::new(File::open(&spooled.0)?)),
+            None => Ok(Box::new(io::empty())),
+        }
+    }
+
+    /// Whether an FWM `ATTACH` value names this entry. Documents refer to
+    /// their files with or without the `ATTACH/` prefix and with either
+    /// slash direction.
+    pub fn is_named(&self, reference: &str) -> bool {
+        let reference = reference.replace('\\', "/");
+        let strip = |name: &str| {
+            let lower = name.to_ascii_lowercase();
+            lower
+                .strip_prefix("attach/")
+                .map(str::to_string)
+                .unwrap_or(lower)
+        };
+        strip(&self.name) == strip(&reference)
+    }
 }
 
 /// Result of parsing an FWZ archive.
 #[derive(Clone, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
 pub struct FwzArchive {
+    /// Documents that agree with the manifest, if any.
     pub documents: Vec<FwmDocument>,
+    /// Documents left out of [`documents`](Self::documents) because they
+    /// differ from the manifest or it does not list them.
+    pub rejected: Vec<String>,
+    #[serde(skip)]
+    pub address_books: Vec<AddressBook>,
+    /// Attachments whose size and hash agree with the manifest, if any.
     pub attachments: Vec<AttachmentSummary>,
+    /// Entries that are missing or disagree with the manifest.
+    pub mismatched: Vec<String>,
+    /// Per-entry outcome of the manifest check; empty when the archive has
+    /// neither a `MANIFEST` nor a `CHECKSUM` entry.
+    pub verification: Vec<EntryVerification>,
     pub checksum_ok: bool,
 }
 
-/// Read a FileWork archive (.FWZ) collecting documents and attachment metadata.
-pub fn read_fwz(path: &Path) -> Result<FwzArchive, MigrationError> {
-    let file = File::open(path)?;
-    let mut archive = ZipArchive::new(file)?;
-    let mut documents = Vec::new();
-    let mut attachments = Vec::new();
-    let mut checksum_ok = true;
-
-    for index in 0..archive.len() {
-        let mut entry = archive.by_index(index)?;
-        let name = entry.name().to_string();
-
-        if name.to_ascii_lowercase().ends_with(".fwm") {
-            let mut buffer = Vec::new();
-            entry.read_to_end(&mut buffer)?;
-            let mut document = parse_fwm(&buffer)?;
-            document.source = Some(PathBuf::from(name));
-            documents.push(document);
-        } else if name.to_ascii_uppercase().starts_with("ATTACH/") {
-            let mut hasher = Sha256::new();
-            let mut buffer = Vec::new();
-            entry.read_to_end(&mut buffer)?;
-            hasher.update(&buffer);
-            attachments.push(AttachmentSummary {
+/// Outcome of checking one archive member against the manifest.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub enum EntryCheck {
+    Verified,
+    /// Size, CRC-32 or SHA-256 differ from the manifest.
+    Mismatch,
+    /// Listed in the manifest but not in the archive.
+    Missing,
+    /// In the archive but not listed in the manifest.
+    Unlisted,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct EntryVerification {
+    pub name: String,
+    pub check: EntryCheck,
+    /// What the manifest lists, e.g. `sha256:<hex>`, `crc32:<hex>` or `size:<bytes>`.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub expected: Option<String>,
+    /// The same property of the archive member.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub actual: Option<String>,
+}
+
+/// What an archive says about one of its members. FileWork 4 wrote a
+/// `CHECKSUM` entry with `<entry name> <crc32>` lines; later versions write a
+/// `MANIFEST` with `<entry name> <size> <sha256>` lines. An archive may carry
+/// both.
+#[derive(Default)]
+struct ManifestLine {
+    size: Option<u64>,
+    crc32: Option<u32>,
+    sha256: Option<String>,
+}
+
+/// Size and digests of an archive member as extracted.
+struct EntryDigest {
+    size: u64,
+    crc32: u32,
+    sha256: String,
+}
+
+fn manifest_lines(text: &str) -> impl Iterator<Item = &str> {
+    text.lines()
+        .map(str::trim)
+        .filter(|line| !line.is_empty() && !line.starts_with('#'))
+}
+
+fn parse_manifest(
+    text: &str,
+    lines: &mut BTreeMap<String, ManifestLine>,
+) -> Result<(), MigrationError> {
+    for line in manifest_lines(text) {
+        let mut fields = line.rsplitn(3, char::is_whitespace);
+        let (Some(sha256), Some(size), Some(name)) = (fields.next(), fields.next(), fields.next())
+        else {
+            return Err(MigrationError::InvalidRecord(format!(
+                "malformed manifest line: {line}"
+            )));
+        };
+        let size = size
+            .parse()
+            .map_err(|_| MigrationError::InvalidRecord(format!("bad size in manifest: {line}")))?;
+        let entry = lines.entry(name.trim().to_string()).or_default();
+        entry.size = Some(size);
+        entry.sha256 = Some(sha256.to_ascii_lowercase());
+    }
+    Ok(())
+}
+
+fn parse_checksums(
+    text: &str,
+    lines: &mut BTreeMap<String, ManifestLine>,
+) -> Result<(), MigrationError> {
+    for line in manifest_lines(text) {
+        let mut fields = line.rsplitn(2, char::is_whitespace);
+        let (Some(crc32), Some(name)) = (fields.next(), fields.next()) else {
+            return Err(MigrationError::InvalidRecord(format!(
+                "malformed checksum line: {line}"
+            )));
+        };
+        let crc32 = u32::from_str_radix(crc32, 16).map_err(|_| {
+            MigrationError::InvalidRecord(format!("bad CRC-32 in checksum: {line}"))
+        })?;
+        lines.entry(name.trim().to_string()).or_default().crc32 = Some(crc32);
+    }
+    Ok(())
+}
+
+/// Compare every member against the manifest, in name order. The first
+/// property that differs is reported, checking the cheapest first.
+fn verify_entries(
+    manifest: &BTreeMap<String, ManifestLine>,
+    digests: &BTreeMap<String, EntryDigest>,
+) -> Vec<EntryVerification> {
+    let names: BTreeSet<&String> = manifest.keys().chain(digests.keys()).collect();
+    names
+        .into_iter()
+        .map(|name| {
+            let (check, expected, actual) = match (manifest.get(name), digests.get(name)) {
+                (Some(line), Some(digest)) => {
+                    let pairs = [
+                        line.size
+                            .map(|size| (format!("size:{size}"), format!("size:{}", digest.size))),
+                        line.crc32.map(|crc| {
+                            (
+                                format!("crc32:{crc:08x}"),
+                                format!("crc32:{:08x}", digest.crc32),
+                            )
+                        }),
+                        line.sha256.as_ref().map(|sha| {
+                            (format!("sha256:{sha}"), format!("sha256:{}", digest.sha256))
+                        }),
+                    ];
+                    match pairs
+                        .iter()
+                        .flatten()
+                        .find(|(expected, actual)| expected != actual)
+                    {
+                        Some((expected, actual)) => (
+                            EntryCheck::Mismatch,
+                            Some(expected.clone()),
+                            Some(actual.clone()),
+                        ),
+                        None => {
+                            let strongest = pairs.into_iter().flatten().last().map(|(e, _)| e);
+                            (EntryCheck::Verified, strongest.clone(), strongest)
+                        }
+                    }
+                }
+                (Some(line), None) => {
+                    let expected = line
+                        .sha256
+                        .as_ref()
+                        .map(|sha| format!("sha256:{sha}"))
+                        .or_else(|| line.crc32.map(|crc| format!("crc32:{crc:08x}")));
+                    (EntryCheck::Missing, expected, None)
+                }
+                (None, _) => (EntryCheck::Unlisted, None, None),
+            };
+            EntryVerification {
                 name: name.clone(),
-                size: buffer.len() as u64,
-                sha256: format!("{:x}", hasher.finalize()),
-            });
+                check,
+                expected,
+                actual,
+            }
+        })
+        .collect()
+}
+
+/// Size limits applied while extracting an FWZ archive, so an archive that
+/// inflates far beyond its size on disk is refused instead of exhausting
+/// memory. `0` disables a limit.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct FwzLimits {
+    /// Largest uncompressed size of a single entry (`migration.maxEntryBytes`).
+    pub max_entry_bytes: u64,
+    /// Largest uncompressed size of all entries together
+    /// (`migration.maxArchiveBytes`).
+    pub max_total_bytes: u64,
+}
+
+impl FwzLimits {
+    pub fn from_config(config: &MigrationConfig) -> Self {
+        Self {
+            max_entry_bytes: config.max_entry_bytes,
+            max_total_bytes: config.max_archive_bytes,
+        }
+    }
+}
+
+impl Default for FwzLimits {
+    fn default() -> Self {
+        Self::from_config(&MigrationConfig::default())
+    }
+}
+
+/// Bytes read from an entry at a time.
+const EXTRACT_CHUNK_BYTES: usize = 64 * 1024;
+
+/// Running total of extracted bytes, checked against [`FwzLimits`] after
+/// every chunk rather than trusting the sizes the archive declares.
+struct ExtractionBudget {
+    limits: FwzLimits,
+    extracted: u64,
+}
+
+impl ExtractionBudget {
+    /// Read an entry, hashing it on the way; the content is returned only
+    /// when `keep` is set, so members that are merely verified stay off the heap.
+    fn read(
+        &mut self,
+        name: &str,
+        declared: u64,
+        entry: &mut impl Read,
+        keep: bool,
+    ) -> Result<(Vec<u8>, EntryDigest), MigrationError> {
+        let mut content = Vec::new();
+        let digest = if keep {
+            self.copy(name, declared, entry, &mut content)?
         } else {
-            // Skip other files such as traces; do not treat them as errors.
+            self.copy(name, declared, entry, &mut io::sink())?
+        };
+        Ok((content, digest))
+    }
+
+    /// Copy an entry into `sink` chunk by chunk, hashing it on the way.
+    fn copy(
+        &mut self,
+        name: &str,
+        declared: u64,
+        entry: &mut impl Read,
+        sink: &mut impl Write,
+    ) -> Result<EntryDigest, MigrationError> {
+        // Refuse early when the archive admits to being too large.
+        self.check(name, declared, self.extracted.saturating_add(declared))?;
+        let mut size = 0u64;
+        let mut crc32 = crc32fast::Hasher::new();
+        let mut sha256 = Sha256::new();
+        let mut chunk = vec![0; EXTRACT_CHUNK_BYTES];
+        loop {
+            let read = match entry.read(&mut chunk) {
+                Ok(0) => break,
+                Ok(read) => read,
+                Err(error) if error.kind() == io::ErrorKind::Interrupted => continue,
+                Err(error) => return Err(error.into()),
+            };
+            size += read as u64;
+            self.extracted += read as u64;
+            self.check(name, size, self.extracted)?;
+            crc32.update(&chunk[..read]);
+            sha256.update(&chunk[..read]);
+            sink.write_all(&chunk[..read])?;
+        }
+        Ok(EntryDigest {
+            size,
+            crc32: crc32.finalize(),
+            sha256: format!("{:x}", sha256.finalize()),
+        })
+    }
+
+    fn check(&self, name: &str, entry_bytes: u64, total_bytes: u64) -> Result<(), MigrationError> {
+        let FwzLimits {
+            max_entry_bytes,
+            max_total_bytes,
+        } = self.limits;
+        if max_entry_bytes > 0 && entry_bytes > max_entry_bytes {
+            return Err(MigrationError::TooLarge(format!(
+                "{name} is larger than {max_entry_bytes} bytes"
+            )));
+        }
+        if max_total_bytes > 0 && total_bytes > max_total_bytes {
+            return Err(MigrationError::TooLarge(format!(
+                "contents are larger than {max_total_bytes} bytes"
+            )));
+        }
+        Ok(())
+    }
+}
+
+/// Read a FileWork archive (.FWZ) collecting documents and attachments.
+///
+/// When the archive carries a `MANIFEST` or `CHECKSUM` entry, every member is
+/// checked against it and the results are listed in
+/// [`FwzArchive::verification`]. A member that is missing or differs clears
+/// `checksum_ok`. Documents and attachments that differ, or that the manifest
+/// does not list, are left out of [`FwzArchive::documents`] and
+/// [`FwzArchive::attachments`]; rejected documents are named in
+/// [`FwzArchive::rejected`].
+pub fn read_fwz(path: &Path) -> Result<FwzArchive, MigrationError> {
+    FwzReader::default().read(path)
+}
+
+/// [`read_fwz`] decoding the documents with an explicit charset policy.
+pub fn read_fwz_with(path: &Path, decoding: FwmDecoding) -> Result<FwzArchive, MigrationError> {
+    FwzReader::default().with_decoding(decoding).read(path)
+}
+
+/// Streaming FWZ extraction. Entries are read one at a time in fixed-size
+/// chunks under [`FwzLimits`], and documents are parsed by a pool of worker
+/// threads fed through a bounded queue, so only a few raw documents are held
+/// at once. Attachments go straight to temporary files and are never held in
+/// memory.
+#[derive(Clone, Copy, Debug)]
+pub struct FwzReader {
+    decoding: FwmDecoding,
+    limits: FwzLimits,
+    parallelism: usize,
+}
+
+impl Default for FwzReader {
+    fn default() -> Self {
+        Self {
+            decoding: FwmDecoding::default(),
+            limits: FwzLimits::default(),
+            parallelism: MigrationConfig::default().parallelism,
+        }
+    }
+}
+
+/// What the extraction loop found besides the documents.
+#[derive(Default)]
+struct FwzEntries {
+    address_books: Vec<AddressBook>,
+    attachments: Vec<AttachmentSummary>,
+    manifest: Option<BTreeMap<String, ManifestLine>>,
+    digests: BTreeMap<String, EntryDigest>,
+}
+
+impl FwzReader {
+    pub fn with_decoding(mut self, decoding: FwmDecoding) -> Self {
+        self.decoding = decoding;
+        self
+    }
+
+    pub fn with_limits(mut self, limits: FwzLimits) -> Self {
+        self.limits = limits;
+        self
+    }
+
+    /// Number of documents parsed at once; at least one.
+    pub fn with_parallelism(mut self, parallelism: usize) -> Self {
+        self.parallelism = parallelism.max(1);
+        self
+    }
+
+    pub fn read(&self, path: &Path) -> Result<FwzArchive, MigrationError> {
+        let file = File::open(path)?;
+        let mut archive = ZipArchive::new(file)?;
+        let (sender, receiver) = mpsc::sync_channel::<(usize, String, Vec<u8>)>(self.parallelism);
+        let receiver = Mutex::new(receiver);
+        let parsed = Mutex::new(Vec::new());
+
+        let entries = thread::scope(|scope| {
+            for _ in 0..self.parallelism {
+                scope.spawn(|| loop {
+                    let Ok((index, name, buffer)) = receiver.lock().unwrap().recv() else {
+                        break;
+                    };
+                    let result = parse_fwm_with(&buffer, self.decoding).map(|mut document| {
+                        document.source = Some(PathBuf::from(name));
+                        document
+                    });
+                    parsed.lock().unwrap().push((index, result));
+                });
+            }
+            // Dropping the sender when extraction ends lets the workers finish.
+            self.extract(&mut archive, sender)
+        })?;
+
+        let mut parsed = parsed.into_inner().unwrap();
+        parsed.sort_by_key(|(index, _)| *index);
+        let mut documents = parsed
+            .into_iter()
+            .map(|(_, result)| result)
+            .collect::<Result<Vec<_>, _>>()?;
+        let FwzEntries {
+            address_books,
+            mut attachments,
+            manifest,
+            digests,
+        } = entries;
+
+        let mut checksum_ok = !documents.is_empty();
+        let mut mismatched = Vec::new();
+        let mut rejected = Vec::new();
+        let mut verification = Vec::new();
+        if let Some(manifest) = manifest {
+            verification = verify_entries(&manifest, &digests);
+            mismatched.extend(
+                verification
+                    .iter()
+                    .filter(|entry| {
+                        matches!(entry.check, EntryCheck::Mismatch | EntryCheck::Missing)
+                    })
+                    .map(|entry| entry.name.clone()),
+            );
+            attachments.retain(|attachment| {
+                let check = verification
+                    .iter()
+                    .find(|entry| entry.name == attachment.name)
+                    .map(|entry| entry.check);
+                if check == Some(EntryCheck::Unlisted) {
+                    mismatched.push(attachment.name.clone());
+                }
+                check == Some(EntryCheck::Verified)
+            });
+            documents.retain(|document| {
+                let name = document
+                    .source
+                    .as_ref()
+                    .map(|source| source.to_string_lossy().into_owned())
+                    .unwrap_or_default();
+                let check = verification
+                    .iter()
+                    .find(|entry| entry.name == name)
+                    .map(|entry| entry.check);
+                if check == Some(EntryCheck::Verified) {
+                    return true;
+                }
+                if check == Some(EntryCheck::Unlisted) {
+                    mismatched.push(name.clone());
+                }
+                rejected.push(name);
+                false
+            });
+            if !mismatched.is_empty() {
+                checksum_ok = false;
+            }
+        }
+
+        Ok(FwzArchive {
+            documents,
+            rejected,
+            address_books,
+            attachments,
+            mismatched,
+            verification,
+            checksum_ok,
+        })
+    }
+
+    /// Walk the entries in order, handing documents to the parsing workers
+    /// and keeping everything else.
+    fn extract(
+        &self,
+        archive: &mut ZipArchive<File>,
+        documents: SyncSender<(usize, String, Vec<u8>)>,
+    ) -> Result<FwzEntries, MigrationError> {
+        let mut budget = ExtractionBudget {
+            limits: self.limits,
+            extracted: 0,
+        };
+        let mut entries = FwzEntries::default();
+
+        for index in 0..archive.len() {
+            let mut entry = archive.by_index(index)?;
+            let name = entry.name().to_string();
+            let declared = entry.size();
+
+            if name.eq_ignore_ascii_case("manifest") || name.eq_ignore_ascii_case("checksum") {
+                let (buffer, _) = budget.read(&name, declared, &mut entry, true)?;
+                let text = String::from_utf8(buffer).map_err(|_| {
+                    MigrationError::InvalidRecord(format!("{name} is not valid UTF-8"))
+                })?;
+                let lines = entries.manifest.get_or_insert_with(BTreeMap::new);
+                if name.eq_ignore_ascii_case("manifest") {
+                    parse_manifest(&text, lines)?;
+                } else {
+                    parse_checksums(&text, lines)?;
+                }
+                continue;
+            }
+            if entry.is_dir() {
+                continue;
+            }
+
+            let lower = name.to_ascii_lowercase();
+            if lower.starts_with("attach/") {
+                let (spool, mut file) = SpoolFile::create()?;
+                let digest = budget.copy(&name, declared, &mut entry, &mut file)?;
+                entries.attachments.push(AttachmentSummary {
+                    name: name.clone(),
+                    size: digest.size,
+                    sha256: digest.sha256.clone(),
+                    spooled: Some(Arc::new(spool)),
+                });
+                entries.digests.insert(name, digest);
+                continue;
+            }
+            let kept = lower.ends_with(".fwm") || lower.ends_with(".fwa");
+            // Other files such as traces are only read to verify them.
+            let (buffer, digest) = budget.read(&name, declared, &mut entry, kept)?;
+            if lower.ends_with(".fwm") {
+                if documents.send((index, name.clone(), buffer)).is_err() {
+                    break;
+                }
+            } else if lower.ends_with(".fwa") {
+                let mut book = parse_fwa(&buffer, self.decoding)?;
+                book.source = Some(PathBuf::from(name.clone()));
+                entries.address_books.push(book);
+            }
+            entries.digests.insert(name, digest);
+        }
+        Ok(entries)
+    }
+}
+
+/// Contacts and distribution lists read from a FileWork address book (.FWA).
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct AddressBook {
+    pub source: Option<PathBuf>,
+    pub entries: Vec<DirectoryEntry>,
+    pub lists: Vec<DistributionList>,
+    /// Sections that were left out, with the reason.
+    pub skipped: Vec<String>,
+}
+
+impl AddressBook {
+    /// O/R address to RFC 822 mappings for [`AddressMapper::with_aliases`],
+    /// from entries that carry both.
+    pub fn aliases(&self) -> HashMap<String, String> {
+        self.entries
+            .iter()
+            .filter(|entry| !entry.rfc822.is_empty())
+            .map(|entry| (entry.or_address.clone(), entry.rfc822.clone()))
+            .collect()
+    }
+}
+
+/// Decode a FileWork address book. `[ENTRY]` sections describe a contact
+/// with `ID`, `NAME`, `ORADDR` and `RFC822` (other keys become attributes);
+/// `[LIST]` sections a distribution list with `ID`, `NAME` and one `MEMBER`
+/// line per member id.
+pub fn parse_fwa(bytes: &[u8], decoding: FwmDecoding) -> Result<AddressBook, MigrationError> {
+    if bytes.is_empty() {
+        return Err(MigrationError::EmptyDocument);
+    }
+    let text = decoding.decode(bytes)?;
+
+    let mut sections: Vec<(String, Vec<(String, String)>)> = Vec::new();
+    for line in text.lines() {
+        let trimmed = line.trim();
+        if trimmed.is_empty() || trimmed.starts_with('#') || trimmed.starts_with("//") {
             continue;
         }
+        if let Some(kind) = trimmed
+            .strip_prefix('[')
+            .and_then(|rest| rest.strip_suffix(']'))
+        {
+            sections.push((kind.trim().to_ascii_uppercase(), Vec::new()));
+        } else if let (Some((key, value)), Some((_, fields))) =
+            (trimmed.split_once('='), sections.last_mut())
+        {
+            fields.push((
+                key.trim().to_ascii_uppercase(),
+                value.trim().trim_matches('"').to_string(),
+            ));
+        } else {
+            return Err(MigrationError::InvalidRecord(format!(
+                "unexpected line in address book: {trimmed}"
+            )));
+        }
+    }
+    if sections.is_empty() {
+        return Err(MigrationError::EmptyDocument);
     }
 
-    if documents.is_empty() {
-        checksum_ok = false;
+    let mut book = AddressBook::default();
+    for (index, (kind, fields)) in sections.into_iter().enumerate() {
+        let field = |name: &str| {
+            fields
+                .iter()
+                .find(|(key, _)| key == name)
+                .map(|(_, value)| value.clone())
+        };
+        match kind.as_str() {
+            "ENTRY" => {
+                let Some(address) = field("ORADDR")
+                    .filter(|value| value.contains('='))
+                    .and_then(|value| parse_address(&value))
+                else {
+                    book.skipped
+                        .push(format!("section {}: entry without ORADDR", index + 1));
+                    continue;
+                };
+                let or_address = format!(
+                    "C={};O={};S={}",
+                    address.country, address.organization, address.surname
+                );
+                book.entries.push(DirectoryEntry {
+                    id: field("ID").unwrap_or_else(|| or_address.clone()),
+                    display_name: field("NAME").unwrap_or_else(|| address.surname.clone()),
+                    rfc822: field("RFC822")
+                        .or_else(|| field("EMAIL"))
+                        .unwrap_or_default(),
+                    or_address,
+                    attributes: fields
+                        .iter()
+                        .filter(|(key, _)| {
+                            !["ID", "NAME", "ORADDR", "RFC822", "EMAIL"].contains(&key.as_str())
+                        })
+                        .cloned()
+                        .collect(),
+                });
+            }
+            "LIST" => {
+                let Some(id) = field("ID") else {
+                    book.skipped
+                        .push(format!("section {}: list without ID", index + 1));
+                    continue;
+                };
+                book.lists.push(DistributionList {
+                    name: field("NAME").unwrap_or_else(|| id.clone()),
+                    id,
+                    members: fields
+                        .iter()
+                        .filter(|(key, _)| key == "MEMBER")
+                        .map(|(_, value)| value.clone())
+                        .collect(),
+                });
+            }
+            other => book
+                .skipped
+                .push(format!("section {}: unknown kind [{other}]", index + 1)),
+        }
     }
+    Ok(book)
+}
 
-    Ok(FwzArchive {
-        documents,
-        attachments,
-        checksum_ok,
-    })
+/// One RFC 822 message read from an `.eml` file or an mbox.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct Rfc822Document {
+    pub source: Option<PathBuf>,
+    pub raw: String,
+}
+
+impl Rfc822Document {
+    fn header(&self, name: &str) -> Option<String> {
+        split_headers(&self.raw)
+            .0
+            .into_iter()
+            .find(|(key, _)| key.eq_ignore_ascii_case(name))
+            .map(|(_, value)| value)
+    }
+
+    pub fn created_at(&self) -> Option<DateTime<Utc>> {
+        DateTime::parse_from_rfc2822(&self.header("Date")?)
+            .map(|dt| dt.with_timezone(&Utc))
+            .ok()
+    }
+}
+
+/// Imports single `.eml` files, mapping RFC 822 addresses back to O/R
+/// addresses with the gateway's [`AddressMapper`].
+#[derive(Clone, Debug, Default)]
+pub struct EmlImporter {
+    mapper: AddressMapper,
+}
+
+impl EmlImporter {
+    pub fn new(mapper: AddressMapper) -> Self {
+        Self { mapper }
+    }
+
+    pub fn read(&self, bytes: &[u8]) -> Result<Rfc822Document, MigrationError> {
+        let raw = String::from_utf8_lossy(bytes).into_owned();
+        if split_headers(&raw).0.is_empty() {
+            return Err(MigrationError::EmptyDocument);
+        }
+        Ok(Rfc822Document { source: None, raw })
+    }
+
+    /// Build the stored message. The `Date` header becomes the creation time
+    /// and an `X-X400-Folder` header, as written by mailbox exports, the folder.
+    pub fn to_message(
+        &self,
+        document: &Rfc822Document,
+        ids: &SharedIds,
+        clock: &SharedClock,
+    ) -> Result<Message, MigrationError> {
+        let inbound = InboundMessage {
+            uid: String::new(),
+            subject: String::new(),
+            from: String::new(),
+            raw: document.raw.clone(),
+        };
+        let mut message = inbound::to_message(&inbound, &self.mapper, ids, clock)
+            .map_err(|err| MigrationError::InvalidRecord(err.to_string()))?;
+        if let Some(created_at) = document.created_at() {
+            message.envelope.created_at = created_at;
+        }
+        if let Some(folder) = document.header("X-X400-Folder") {
+            message.envelope.folder = folder;
+        }
+        Ok(message)
+    }
+}
+
+/// Splits an mbox file into its messages and imports each like an `.eml`.
+#[derive(Clone, Debug, Default)]
+pub struct MboxImporter {
+    eml: EmlImporter,
+}
+
+impl MboxImporter {
+    pub fn new(mapper: AddressMapper) -> Self {
+        Self {
+            eml: EmlImporter::new(mapper),
+        }
+    }
+
+    /// Messages in file order. `From ` separator lines are dropped and
+    /// `>From ` escapes (mboxrd) are undone.
+    pub fn read(&self, bytes: &[u8]) -> Result<Vec<Rfc822Document>, MigrationError> {
+        let text = String::from_utf8_lossy(bytes);
+        let mut documents = Vec::new();
+        let mut current: Option<String> = None;
+        for line in text.split_inclusive('\n') {
+            if line.starts_with("From ") {
+                if let Some(raw) = current.take() {
+                    documents.push(Rfc822Document { source: None, raw });
+                }
+                current = Some(String::new());
+                continue;
+            }
+            let Some(raw) = current.as_mut() else {
+                continue;
+            };
+            let unescaped = line.trim_start_matches('>');
+            if unescaped.starts_with("From ") && unescaped.len() < line.len() {
+                raw.push_str(&line[1..]);
+            } else {
+                raw.push_str(line);
+            }
+        }
+        documents.extend(current.map(|raw| Rfc822Document { source: None, raw }));
+        if documents.is_empty() {
+            return Err(MigrationError::EmptyDocument);
+        }
+        Ok(documents)
+    }
+
+    pub fn to_message(
+        &self,
+        document: &Rfc822Document,
+        ids: &SharedIds,
+        clock: &SharedClock,
+    ) -> Result<Message, MigrationError> {
+        self.eml.to_message(document, ids, clock)
+    }
 }
 
 /// Accepted migration modes.
@@ -278,23 +1158,33 @@ pub enum MigrationMode {
     Auto,
     Fwm,
     Fwz,
+    Eml,
+    Mbox,
 }
 
 /// Request parameters for launching a migration job.
 #[derive(Clone, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
 pub struct MigrationRequest {
     pub path: PathBuf,
     #[serde(default)]
     pub mode: MigrationMode,
+    #[serde(default)]
     pub dry_run: bool,
+    /// Job to continue: documents it already imported, as recorded in the
+    /// `migration_state` table, are skipped, so this also works after a restart.
     pub resume: Option<Uuid>,
     pub limit: Option<usize>,
     pub since: Option<DateTime<Utc>>,
     pub quarantine: Option<PathBuf>,
+    /// Charset of FileWork documents, e.g. `cp437` or `iso-8859-15`, for
+    /// archives whose encoding is known; skips detection.
+    pub encoding: Option<String>,
 }
 
 /// Summary of an import run.
 #[derive(Clone, Debug, Default, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
 pub struct MigrationReport {
     pub job_id: Uuid,
     pub started_at: DateTime<Utc>,
@@ -305,12 +1195,144 @@ pub struct MigrationReport {
     pub duplicates: usize,
     pub dry_run: bool,
     pub checksum_ok: bool,
+    /// Artifacts copied to quarantine because they could not be parsed.
+    #[serde(default)]
+    pub quarantined: usize,
+    #[serde(default)]
+    pub quarantined_bytes: u64,
+    #[serde(default)]
+    pub quarantine_dir: Option<PathBuf>,
     pub notes: Vec<String>,
     pub errors: Vec<MigrationErrorRecord>,
+    /// What a dry run would change; absent for real imports.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub diff: Option<DryRunDiff>,
+    /// [`DryRunDiff::to_text`] of `diff`, for reading in a terminal.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub diff_text: Option<String>,
+    /// Manifest check of each FWZ member; empty when there was nothing to check.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub verification: Vec<EntryVerification>,
+}
+
+/// What a dry run found, for review before the real import.
+#[derive(Clone, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct DryRunDiff {
+    pub new_messages: Vec<DiffMessage>,
+    pub duplicates: Vec<DiffDuplicate>,
+    pub folder_conflicts: Vec<FolderConflict>,
+    pub unmappable_addresses: Vec<UnmappableAddress>,
+}
+
+/// A document the import would add.
+#[derive(Clone, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct DiffMessage {
+    pub path: PathBuf,
+    pub subject: String,
+    pub folder: String,
+}
+
+/// A document the import would skip because the store already has it.
+#[derive(Clone, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct DiffDuplicate {
+    pub path: PathBuf,
+    pub subject: String,
+    pub existing_id: String,
+    pub existing_folder: String,
+}
+
+/// A document whose folder does not exist yet (`existing_folder` absent) or
+/// whose stored copy is filed somewhere else.
+#[derive(Clone, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct FolderConflict {
+    pub path: PathBuf,
+    pub folder: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub existing_folder: Option<String>,
+}
+
+/// An RFC 822 address that no mapping rule or alias turns into an O/R address.
+#[derive(Clone, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct UnmappableAddress {
+    pub path: PathBuf,
+    pub address: String,
+}
+
+impl DryRunDiff {
+    fn sort(&mut self) {
+        self.new_messages.sort_by(|a, b| a.path.cmp(&b.path));
+        self.duplicates.sort_by(|a, b| a.path.cmp(&b.path));
+        self.folder_conflicts.sort_by(|a, b| a.path.cmp(&b.path));
+        self.unmappable_addresses
+            .sort_by(|a, b| a.path.cmp(&b.path));
+    }
+
+    /// One line per finding, grouped by kind under a count summary.
+    pub fn to_text(&self) -> String {
+        let mut text = format!(
+            "{} new, {} duplicates, {} folder conflicts, {} unmappable addresses\n",
+            self.new_messages.len(),
+            self.duplicates.len(),
+            self.folder_conflicts.len(),
+            self.unmappable_addresses.len()
+        );
+        if !self.new_messages.is_empty() {
+            text.push_str("New messages:\n");
+            for entry in &self.new_messages {
+                text.push_str(&format!(
+                    "  + {}  {:?} -> {}\n",
+                    entry.path.display(),
+                    entry.subject,
+                    entry.folder
+                ));
+            }
+        }
+        if !self.duplicates.is_empty() {
+            text.push_str("Duplicates:\n");
+            for entry in &self.duplicates {
+                text.push_str(&format!(
+                    "  = {}  {:?} (stored as {} in {})\n",
+                    entry.path.display(),
+                    entry.subject,
+                    entry.existing_id,
+                    entry.existing_folder
+                ));
+            }
+        }
+        if !self.folder_conflicts.is_empty() {
+            text.push_str("Folder conflicts:\n");
+            for entry in &self.folder_conflicts {
+                let reason = match &entry.existing_folder {
+                    Some(existing) => {
+                        format!("wants {}, stored copy is in {existing}", entry.folder)
+                    }
+                    None => format!("folder {} does not exist", entry.folder),
+                };
+                text.push_str(&format!("  ! {}  {reason}\n", entry.path.display()));
+            }
+        }
+        if !self.unmappable_addresses.is_empty() {
+            text.push_str("Unmappable addresses:\n");
+            for entry in &self.unmappable_addresses {
+                text.push_str(&format!(
+                    "  ? {}  {}\n",
+                    entry.path.display(),
+                    entry.address
+                ));
+            }
+        }
+        text
+    }
 }
 
 /// Progress snapshot for consumers (CLI/UI).
 #[derive(Clone, Debug, Default, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
 pub struct MigrationProgress {
     pub job_id: Uuid,
     pub status: MigrationStatus,
@@ -329,11 +1351,22 @@ pub struct MigrationProgress {
 
 /// Errors recorded while processing individual artifacts.
 #[derive(Clone, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
 pub struct MigrationErrorRecord {
     pub path: PathBuf,
     pub message: String,
 }
 
+/// Sidecar written next to a quarantined artifact as `<name>.error.json`.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct QuarantineRecord {
+    pub job_id: Uuid,
+    pub source: PathBuf,
+    pub error: String,
+    pub quarantined_at: DateTime<Utc>,
+}
+
 /// Job status values exposed externally.
 #[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
 #[serde(rename_all = "lowercase")]
@@ -341,43 +1374,169 @@ pub enum MigrationStatus {
     #[default]
     Pending,
     Running,
+    Paused,
     Completed,
     Failed,
+    Cancelled,
+}
+
+impl MigrationStatus {
+    pub fn is_finished(self) -> bool {
+        matches!(self, Self::Completed | Self::Failed | Self::Cancelled)
+    }
 }
 
 struct MigrationJob {
     request: MigrationRequest,
     progress: MigrationProgress,
     report: Option<MigrationReport>,
+    errors: Vec<MigrationErrorRecord>,
+    quarantined: usize,
+    quarantined_bytes: u64,
+    /// Documents skipped because an earlier run of the job imported them.
+    resumed: usize,
+    diff: DryRunDiff,
+    verification: Vec<EntryVerification>,
+    cancelled: bool,
 }
 
 /// Main coordinator responsible for import jobs.
+///
+/// Each job runs on its own thread and spreads its documents over
+/// `migration.parallelism` workers, so [`import`](Self::import) returns as
+/// soon as the job is registered and [`progress`](Self::progress) follows it
+/// live. Workers check for pause and cancellation between documents.
 #[derive(Clone)]
 pub struct MigrationManager {
     store: StoreManager,
+    mapper: AddressMapper,
     jobs: Arc<Mutex<HashMap<Uuid, MigrationJob>>>,
+    /// Signalled whenever a job is paused, resumed, cancelled or finishes.
+    changed: Arc<Condvar>,
+    /// Held while checking for a duplicate and saving, so parallel workers
+    /// cannot both import the same message.
+    saving: Arc<Mutex<()>>,
+    parallelism: usize,
+    quarantine_dir: Option<PathBuf>,
+    attachments: Option<AttachmentStore>,
+    charset_fallback: Option<LegacyCharset>,
+    archive_limits: FwzLimits,
+    directory: Option<LdapDirectoryClient>,
+    /// O/R to RFC 822 aliases from address books imported so far.
+    aliases: Arc<Mutex<HashMap<String, String>>>,
+    audit: Option<AuditLog>,
+    clock: SharedClock,
+    ids: SharedIds,
 }
 
 impl MigrationManager {
     pub fn new(store: StoreManager) -> Self {
         Self {
             store,
+            mapper: AddressMapper::default(),
             jobs: Arc::new(Mutex::new(HashMap::new())),
+            changed: Arc::new(Condvar::new()),
+            saving: Arc::new(Mutex::new(())),
+            parallelism: MigrationConfig::default().parallelism,
+            quarantine_dir: None,
+            attachments: None,
+            charset_fallback: None,
+            archive_limits: FwzLimits::default(),
+            directory: None,
+            aliases: Arc::new(Mutex::new(HashMap::new())),
+            audit: None,
+            clock: SharedClock::default(),
+            ids: SharedIds::default(),
         }
     }
 
-    /// Launch a migration job. The processing is synchronous for the mock implementation,
-    /// but the job bookkeeping mirrors an asynchronous interface for consumers.
+    pub fn with_clock(mut self, clock: SharedClock) -> Self {
+        self.clock = clock;
+        self
+    }
+
+    pub fn with_ids(mut self, ids: SharedIds) -> Self {
+        self.ids = ids;
+        self
+    }
+
+    /// Record started and cancelled jobs as `migration.import` and
+    /// `migration.cancel`.
+    pub fn with_audit(mut self, audit: AuditLog) -> Self {
+        self.audit = Some(audit);
+        self
+    }
+
+    /// Address mapping used to turn RFC 822 senders and recipients into O/R
+    /// addresses for EML and mbox imports.
+    pub fn with_mapper(mut self, mapper: AddressMapper) -> Self {
+        self.mapper = mapper;
+        self
+    }
+
+    /// Where unreadable artifacts go when a request names no quarantine
+    /// directory of its own (`migration.quarantine`).
+    pub fn with_quarantine_dir(mut self, dir: impl Into<PathBuf>) -> Self {
+        self.quarantine_dir = Some(dir.into());
+        self
+    }
+
+    /// Where files extracted from FWZ archives are kept. Without a store,
+    /// FWZ imports bring over the documents only.
+    pub fn with_attachments(mut self, attachments: AttachmentStore) -> Self {
+        self.attachments = Some(attachments);
+        self
+    }
+
+    /// Charset tried for FileWork documents whose detected encoding is unsure
+    /// or fails to decode (`migration.charsetFallback`).
+    pub fn with_charset_fallback(mut self, charset: Option<LegacyCharset>) -> Self {
+        self.charset_fallback = charset;
+        self
+    }
+
+    /// Extraction limits for FWZ archives; an archive that exceeds them is
+    /// quarantined.
+    pub fn with_archive_limits(mut self, limits: FwzLimits) -> Self {
+        self.archive_limits = limits;
+        self
+    }
+
+    /// Directory that contacts from FileWork address books (.FWA) go into.
+    pub fn with_directory(mut self, directory: LdapDirectoryClient) -> Self {
+        self.directory = Some(directory);
+        self
+    }
+
+    /// Number of documents a job parses and imports at once; at least one.
+    pub fn with_parallelism(mut self, parallelism: usize) -> Self {
+        self.parallelism = parallelism.max(1);
+        self
+    }
+
+    /// Launch a job in the background for `actor` and return its
+    /// identifier. Failures to read the source end the job as
+    /// `failed` with a note.
     #[instrument(name = "migration.import", skip(self, request))]
-    pub fn import(&self, request: MigrationRequest) -> Result<Uuid, MigrationError> {
+    pub fn import(&self, actor: &str, request: MigrationRequest) -> Result<Uuid, MigrationError> {
         if let Some(resume) = request.resume {
-            if self.jobs.lock().unwrap().contains_key(&resume) {
+            let jobs = self.jobs.lock().unwrap();
+            if jobs
+                .get(&resume)
+                .is_some_and(|job| !job.progress.status.is_finished())
+            {
                 return Ok(resume);
             }
         }
 
-        let job_id = Uuid::new_v4();
-        let started_at = Utc::now();
+        if let Some(label) = &request.encoding {
+            if LegacyCharset::for_label(label).is_none() {
+                return Err(MigrationError::UnknownCharset(label.clone()));
+            }
+        }
+
+        let job_id = request.resume.unwrap_or_else(|| self.ids.uuid());
+        let started_at = self.clock.now();
         let job = MigrationJob {
             request: request.clone(),
             progress: MigrationProgress {
@@ -396,23 +1555,115 @@ impl MigrationManager {
                 notes: Vec::new(),
             },
             report: None,
+            errors: Vec::new(),
+            quarantined: 0,
+            quarantined_bytes: 0,
+            resumed: 0,
+            diff: DryRunDiff::default(),
+            verification: Vec::new(),
+            cancelled: false,
         };
 
         self.jobs.lock().unwrap().insert(job_id, job);
+        if let Some(audit) = &self.audit {
+            audit.record(
+                actor,
+                AuditAction::MigrationImport,
+                &request.path.display().to_string(),
+                None,
+                Some(format!(
+                    "job {job_id}{}",
+                    if request.dry_run { ", dry run" } else { "" }
+                )),
+            );
+        }
+
+        let manager = self.clone();
+        let spawned = thread::Builder::new()
+            .name(format!("migration-{job_id}"))
+            .spawn(move || {
+                if let Err(error) = manager.process_job(job_id) {
+                    manager.finish(job_id, Some(format!("Job failed: {error}")));
+                }
+            });
+        if let Err(error) = spawned {
+            self.finish(job_id, Some(format!("Job failed: {error}")));
+            return Err(error.into());
+        }
 
-        let result = self.process_job(job_id);
+        Ok(job_id)
+    }
 
-        if let Err(error) = result {
-            let mut jobs = self.jobs.lock().unwrap();
-            if let Some(job) = jobs.get_mut(&job_id) {
-                job.progress.status = MigrationStatus::Failed;
-                job.progress.finished_at = Some(Utc::now());
-                job.progress.notes.push(format!("Job failed: {error}"));
+    /// Stop a running job after the documents already in flight.
+    pub fn pause(&self, job_id: Uuid) -> Result<MigrationProgress, MigrationError> {
+        self.transition(job_id, |job| match job.progress.status {
+            MigrationStatus::Running | MigrationStatus::Paused => {
+                job.progress.status = MigrationStatus::Paused;
+                Ok(())
             }
-            return Err(error);
+            _ => Err(MigrationError::Finished),
+        })
+    }
+
+    /// Continue a paused job.
+    pub fn resume(&self, job_id: Uuid) -> Result<MigrationProgress, MigrationError> {
+        self.transition(job_id, |job| match job.progress.status {
+            MigrationStatus::Running | MigrationStatus::Paused => {
+                job.progress.status = MigrationStatus::Running;
+                Ok(())
+            }
+            _ => Err(MigrationError::Finished),
+        })
+    }
+
+    /// Cancel a running or paused job. Messages imported so far stay in the
+    /// store; the report counts them and the job ends as `cancelled`. `actor` is recorded in the audit log.
+    pub fn cancel(&self, actor: &str, job_id: Uuid) -> Result<MigrationProgress, MigrationError> {
+        let progress = self.transition(job_id, |job| {
+            if job.progress.status.is_finished() {
+                return Err(MigrationError::Finished);
+            }
+            job.cancelled = true;
+            Ok(())
+        })?;
+        if let Some(audit) = &self.audit {
+            audit.record(
+                actor,
+                AuditAction::MigrationCancel,
+                &job_id.to_string(),
+                None,
+                Some(format!("{} imported", progress.imported)),
+            );
         }
+        Ok(progress)
+    }
 
-        Ok(job_id)
+    /// Block until the job finishes and return its report.
+    pub fn wait(&self, job_id: Uuid) -> Result<MigrationReport, MigrationError> {
+        let jobs = self.jobs.lock().unwrap();
+        let jobs = self
+            .changed
+            .wait_while(jobs, |jobs| {
+                jobs.get(&job_id)
+                    .is_some_and(|job| !job.progress.status.is_finished())
+            })
+            .unwrap();
+        let job = jobs.get(&job_id).ok_or(MigrationError::UnknownJob)?;
+        job.report.clone().ok_or(MigrationError::UnknownJob)
+    }
+
+    fn transition(
+        &self,
+        job_id: Uuid,
+        change: impl FnOnce(&mut MigrationJob) -> Result<(), MigrationError>,
+    ) -> Result<MigrationProgress, MigrationError> {
+        let mut jobs = self.jobs.lock().unwrap();
+        let job = jobs.get_mut(&job_id).ok_or(MigrationError::UnknownJob)?;
+        change(job)?;
+        let progress = job.progress.clone();
+        drop(jobs);
+        self.changed.notify_all();
+        Ok(progress)
     }
 
     #[instrument(name = "migration.process_job", skip(self))]
@@ -426,177 +1677,357 @@ impl MigrationManager {
                 .ok_or(MigrationError::UnknownJob)?;
         }
 
-        let (documents, checksum_ok) = match self.resolve_documents(&request)? {
-            ResolvedDocuments::Fwm { docs } => (docs, true),
-            ResolvedDocuments::Fwz { docs, checksum_ok } => (docs, checksum_ok),
+        let resolved = match self.resolve_documents(job_id, &request) {
+            Ok(resolved) => resolved,
+            Err(error) => {
+                self.quarantine(job_id, &request, &request.path, &error);
+                return Err(error);
+            }
+        };
+        let (documents, checksum_ok) = match resolved {
+            ResolvedDocuments::Fwm { docs } => (
+                docs.into_iter()
+                    .map(|document| LegacyItem::Fwm(document, Vec::new()))
+                    .collect(),
+                true,
+            ),
+            ResolvedDocuments::Fwz { docs, checksum_ok } => (
+                docs.into_iter()
+                    .map(|(document, attachments)| LegacyItem::Fwm(document, attachments))
+                    .collect::<Vec<_>>(),
+                checksum_ok,
+            ),
+            ResolvedDocuments::Rfc822 { docs } => {
+                (docs.into_iter().map(LegacyItem::Rfc822).collect(), true)
+            }
         };
 
         let total = documents.len();
+        let selected: Vec<LegacyItem> = documents
+            .into_iter()
+            .filter(|document| match (request.since, document.created_at()) {
+                (Some(since), Some(created_at)) => created_at >= since,
+                _ => true,
+            })
+            .take(request.limit.unwrap_or(usize::MAX))
+            .collect();
         {
             let mut jobs = self.jobs.lock().unwrap();
             if let Some(job) = jobs.get_mut(&job_id) {
-                job.progress.total = total;
+                // Files rejected while reading the source are already processed.
+                job.progress.total = job.progress.processed + total;
                 job.progress.checksum_ok = checksum_ok;
             }
         }
 
-        let mut imported = 0usize;
-        let mut failed = 0usize;
-        let mut duplicates = 0usize;
-        let mut processed = 0usize;
-        let mut errors = Vec::new();
-        let limit = request.limit.unwrap_or(usize::MAX);
-
-        for document in documents.into_iter() {
-            if let Some(since) = request.since {
-                if let Some(created_at) = document.created_at() {
-                    if created_at < since {
-                        continue;
+        let checkpoints: HashMap<PathBuf, String> = self
+            .store
+            .migration_checkpoints(job_id)
+            .into_iter()
+            .map(|checkpoint| (checkpoint.path, checkpoint.sha256))
+            .collect();
+        let queue = Mutex::new(selected.into_iter());
+        let workers = self.parallelism.min(total).max(1);
+        thread::scope(|scope| {
+            for _ in 0..workers {
+                scope.spawn(|| {
+                    while self.proceed(job_id) {
+                        let Some(document) = queue.lock().unwrap().next() else {
+                            break;
+                        };
+                        let path = document
+                            .source()
+                            .cloned()
+                            .unwrap_or_else(|| request.path.clone());
+                        let sha256 = document.sha256();
+                        if checkpoints.get(&path) == Some(&sha256) {
+                            self.skip(job_id);
+                            continue;
+                        }
+                        self.started(job_id, &path);
+                        if let (true, LegacyItem::Rfc822(document)) = (request.dry_run, &document) {
+                            self.note_unmappable(job_id, &path, document);
+                        }
+                        let result = match &document {
+                            LegacyItem::Fwm(document, attachments) => {
+                                self.import_document(document, attachments, request.dry_run)
+                            }
+                            LegacyItem::Rfc822(document) => {
+                                self.import_rfc822(document, request.dry_run)
+                            }
+                        };
+                        if result.is_ok() && !request.dry_run {
+                            self.store.put_migration_checkpoint(MigrationCheckpoint {
+                                job_id,
+                                path: path.clone(),
+                                sha256,
+                                completed_at: self.clock.now(),
+                            });
+                        }
+                        self.record(job_id, path, result);
                     }
-                }
+                });
             }
+        });
 
-            if processed >= limit {
-                break;
-            }
+        self.finish(job_id, None);
+        Ok(())
+    }
 
-            processed += 1;
+    /// Wait while the job is paused; false once it is cancelled.
+    fn proceed(&self, job_id: Uuid) -> bool {
+        let jobs = self.jobs.lock().unwrap();
+        let jobs = self
+            .changed
+            .wait_while(jobs, |jobs| {
+                jobs.get(&job_id).is_some_and(|job| {
+                    job.progress.status == MigrationStatus::Paused && !job.cancelled
+                })
+            })
+            .unwrap();
+        jobs.get(&job_id).is_some_and(|job| !job.cancelled)
+    }
 
-            let path = document
-                .source
-                .clone()
-                .unwrap_or_else(|| request.path.clone());
+    fn started(&self, job_id: Uuid, path: &Path) {
+        if let Some(job) = self.jobs.lock().unwrap().get_mut(&job_id) {
+            job.progress.current_path = Some(path.to_path_buf());
+        }
+    }
 
-            match self.import_document(&document, request.dry_run) {
-                Ok(result) => {
-                    imported += 1;
-                    if result.is_duplicate {
-                        duplicates += 1;
-                    }
-                    self.update_progress(
-                        job_id,
-                        Some(path),
-                        processed,
-                        imported,
-                        failed,
-                        duplicates,
-                    )?;
+    fn skip(&self, job_id: Uuid) {
+        if let Some(job) = self.jobs.lock().unwrap().get_mut(&job_id) {
+            job.progress.processed += 1;
+            job.resumed += 1;
+        }
+    }
+
+    fn record(&self, job_id: Uuid, path: PathBuf, result: Result<ImportResult, MigrationError>) {
+        let known_folder = result.as_ref().is_ok_and(|result| {
+            SYSTEM_FOLDERS.iter().any(|(id, _)| *id == result.folder)
+                || self.store.folder(&result.folder).is_some()
+        });
+        let mut jobs = self.jobs.lock().unwrap();
+        let Some(job) = jobs.get_mut(&job_id) else {
+            return;
+        };
+        let progress = &mut job.progress;
+        progress.processed += 1;
+        match result {
+            Ok(result) => {
+                progress.imported += 1;
+                if result.duplicate_of.is_some() {
+                    progress.duplicates += 1;
                 }
-                Err(err) => {
-                    failed += 1;
-                    errors.push(MigrationErrorRecord {
-                        path: path.clone(),
-                        message: err.to_string(),
-                    });
-                    self.update_progress(
-                        job_id,
-                        Some(path),
-                        processed,
-                        imported,
-                        failed,
-                        duplicates,
-                    )?;
+                if job.request.dry_run {
+                    job.diff.add(path, result, known_folder);
                 }
             }
-        }
-
-        let finished_at = Utc::now();
-        {
-            let mut jobs = self.jobs.lock().unwrap();
-            if let Some(job) = jobs.get_mut(&job_id) {
-                job.progress.status = if failed > 0 {
-                    MigrationStatus::Failed
-                } else {
-                    MigrationStatus::Completed
-                };
-                job.progress.processed = processed;
-                job.progress.imported = imported;
-                job.progress.failed = failed;
-                job.progress.duplicates = duplicates;
-                job.progress.finished_at = Some(finished_at);
-                job.progress.current_path = None;
-                let report = MigrationReport {
-                    job_id,
-                    started_at: job.progress.started_at,
-                    finished_at,
-                    total: job.progress.total,
-                    imported,
-                    failed,
-                    duplicates,
-                    dry_run: job.progress.dry_run,
-                    checksum_ok: job.progress.checksum_ok,
-                    notes: job.progress.notes.clone(),
-                    errors,
-                };
-                job.report = Some(report);
+            Err(err) => {
+                progress.failed += 1;
+                job.errors.push(MigrationErrorRecord {
+                    path,
+                    message: err.to_string(),
+                });
             }
         }
+    }
 
-        Ok(())
+    /// Add the From and To addresses of `document` that would not map to the diff.
+    fn note_unmappable(&self, job_id: Uuid, path: &Path, document: &Rfc822Document) {
+        let mapper = self.rfc822_mapper();
+        let unmappable: Vec<UnmappableAddress> = document
+            .header("From")
+            .into_iter()
+            .chain(
+                document
+                    .header("To")
+                    .into_iter()
+                    .flat_map(|to| to.split(',').map(str::to_string).collect::<Vec<_>>()),
+            )
+            .map(|value| mailbox(&value).to_string())
+            .filter(|address| !address.is_empty() && mapper.map_rfc822_to_or(address).is_err())
+            .map(|address| UnmappableAddress {
+                path: path.to_path_buf(),
+                address,
+            })
+            .collect();
+        if let Some(job) = self.jobs.lock().unwrap().get_mut(&job_id) {
+            job.diff.unmappable_addresses.extend(unmappable);
+        }
     }
 
-    #[instrument(name = "migration.update_progress", skip(self, path))]
-    fn update_progress(
-        &self,
-        job_id: Uuid,
-        path: Option<PathBuf>,
-        processed: usize,
-        imported: usize,
-        failed: usize,
-        duplicates: usize,
-    ) -> Result<(), MigrationError> {
+    /// Settle the final status and build the report.
+    fn finish(&self, job_id: Uuid, note: Option<String>) {
+        let finished_at = self.clock.now();
         let mut jobs = self.jobs.lock().unwrap();
-        let job = jobs.get_mut(&job_id).ok_or(MigrationError::UnknownJob)?;
-        job.progress.processed = processed;
-        job.progress.imported = imported;
-        job.progress.failed = failed;
-        job.progress.duplicates = duplicates;
-        job.progress.current_path = path;
-        Ok(())
+        let mut done = false;
+        if let Some(job) = jobs.get_mut(&job_id) {
+            let progress = &mut job.progress;
+            if job.resumed > 0 {
+                progress.notes.push(format!(
+                    "Resumed: skipped {} documents imported by an earlier run",
+                    job.resumed
+                ));
+            }
+            progress.notes.extend(note.clone());
+            progress.status = if job.cancelled {
+                MigrationStatus::Cancelled
+            } else if note.is_some() || progress.failed > 0 {
+                MigrationStatus::Failed
+            } else {
+                MigrationStatus::Completed
+            };
+            progress.finished_at = Some(finished_at);
+            progress.current_path = None;
+            // A job cut short by `limit` may still be resumed for the rest.
+            done = progress.status == MigrationStatus::Completed && job.request.limit.is_none();
+            job.report = Some(MigrationReport {
+                job_id,
+                started_at: progress.started_at,
+                finished_at,
+                total: progress.total,
+                imported: progress.imported,
+                failed: progress.failed,
+                duplicates: progress.duplicates,
+                dry_run: progress.dry_run,
+                checksum_ok: progress.checksum_ok,
+                quarantined: job.quarantined,
+                quarantined_bytes: job.quarantined_bytes,
+                quarantine_dir: (job.quarantined > 0)
+                    .then(|| {
+                        job.request
+                            .quarantine
+                            .clone()
+                            .or_else(|| self.quarantine_dir.clone())
+                    })
+                    .flatten(),
+                notes: progress.notes.clone(),
+                errors: std::mem::take(&mut job.errors),
+                diff: None,
+                diff_text: None,
+                verification: job.verification.clone(),
+            });
+            if job.request.dry_run {
+                let mut diff = std::mem::take(&mut job.diff);
+                diff.sort();
+                if let Some(report) = job.report.as_mut() {
+                    report.diff_text = Some(diff.to_text());
+                    report.diff = Some(diff);
+                }
+            }
+        }
+        drop(jobs);
+        if done {
+            self.store.remove_migration_checkpoints(job_id);
+        }
+        self.changed.notify_all();
     }
 
-    #[instrument(name = "migration.import_document", skip(self, document))]
+    #[instrument(name = "migration.import_document", skip(self, document, attachments))]
     fn import_document(
         &self,
         document: &FwmDocument,
+        attachments: &[AttachmentSummary],
         dry_run: bool,
     ) -> Result<ImportResult, MigrationError> {
         let subject = document.subject();
         let sender = document.sender();
         let recipients = document.recipients();
-        let mut envelope = MessageEnvelope::new(&subject, sender, recipients);
+        let mut envelope =
+            MessageEnvelope::stamped(&subject, sender, recipients, &self.ids, &self.clock);
         envelope.folder = document.folder();
         envelope.status = document.status();
         envelope.priority = MessagePriority::Normal;
         envelope.sensitivity = MessageSensitivity::Normal;
+        if let Some(created_at) = document.created_at() {
+            envelope.created_at = created_at;
+        }
 
         let message = Message {
             envelope,
             content: MessageContent {
                 body: document.body(),
+                attachments: attachments
+                    .iter()
+                    .map(|attachment| {
+                        let filename = attachment
+                            .name
+                            .rsplit('/')
+                            .next()
+                            .unwrap_or(&attachment.name)
+                            .to_string();
+                        MessageAttachment {
+                            id: self.attachments.as_ref().map_or_else(
+                                || attachment.sha256.clone(),
+                                |store| store.blob_id(&attachment.sha256),
+                            ),
+                            mime_type: mime_type(&filename).into(),
+                            filename,
+                            size: attachment.size,
+                        }
+                    })
+                    .collect(),
             },
         };
+        self.store_message(message, attachments, dry_run)
+    }
 
-        let folder = message.envelope.folder.clone();
-        let existing = self.store.list(&folder);
-        let is_duplicate = existing.iter().any(|candidate| {
-            candidate.envelope.subject == message.envelope.subject
-                && candidate.content.body == message.content.body
-        });
+    #[instrument(name = "migration.import_rfc822", skip(self, document))]
+    fn import_rfc822(
+        &self,
+        document: &Rfc822Document,
+        dry_run: bool,
+    ) -> Result<ImportResult, MigrationError> {
+        let message =
+            EmlImporter::new(self.rfc822_mapper()).to_message(document, &self.ids, &self.clock)?;
+        self.store_message(message, &[], dry_run)
+    }
+
+    /// Save the message unless it is a duplicate, writing its attachments to
+    /// the attachment store first so the links never point at missing blobs.
+    fn store_message(
+        &self,
+        message: Message,
+        attachments: &[AttachmentSummary],
+        dry_run: bool,
+    ) -> Result<ImportResult, MigrationError> {
+        let _saving = self.saving.lock().unwrap();
+        let duplicate_of = self
+            .store
+            .find_by_content_hash(&message.content_hash())
+            .map(|existing| (existing.envelope.id, existing.envelope.folder));
+        let result = ImportResult {
+            subject: message.envelope.subject.clone(),
+            folder: message.envelope.folder.clone(),
+            duplicate_of,
+        };
 
-        if !dry_run && !is_duplicate {
+        if !dry_run && result.duplicate_of.is_none() {
+            if let Some(store) = &self.attachments {
+                for attachment in attachments {
+                    store.put(&attachment.name, attachment.open()?)?;
+                }
+            }
             self.store.save(message);
         }
 
-        Ok(ImportResult { is_duplicate })
+        Ok(result)
     }
 
     #[instrument(name = "migration.resolve_documents", skip(self, request))]
     fn resolve_documents(
         &self,
+        job_id: Uuid,
         request: &MigrationRequest,
     ) -> Result<ResolvedDocuments, MigrationError> {
         let path = request.path.clone();
+        let decoding = FwmDecoding {
+            forced: request
+                .encoding
+                .as_deref()
+                .and_then(LegacyCharset::for_label),
+            fallback: self.charset_fallback,
+        };
         let mode = match request.mode {
             MigrationMode::Auto => infer_mode(&path),
             other => other,
@@ -606,23 +2037,43 @@ impl MigrationManager {
             MigrationMode::Fwm => {
                 let mut docs = Vec::new();
                 if path.is_dir() {
-                    for entry in WalkDir::new(&path).into_iter().filter_map(Result::ok) {
-                        if entry.file_type().is_file()
-                            && entry
-                                .path()
-                                .extension()
-                                .map(|ext| ext.eq_ignore_ascii_case("fwm"))
-                                .unwrap_or(false)
+                    for entry in WalkDir::new(&path)
+                        .sort_by_file_name()
+                        .into_iter()
+                        .filter_map(Result::ok)
+                    {
+                        if entry.file_type().is_file() && has_extension(entry.path(), "fwa") {
+                            let parsed = fs::read(entry.path())
+                                .map_err(MigrationError::from)
+                                .and_then(|data| parse_fwa(&data, decoding));
+                            match parsed {
+                                Ok(mut book) => {
+                                    book.source = Some(entry.path().to_path_buf());
+                                    self.load_address_book(job_id, request.dry_run, book);
+                                }
+                                Err(error) => self.reject(job_id, request, entry.path(), error),
+                            }
+                        } else if entry.file_type().is_file() && has_extension(entry.path(), "fwm")
                         {
-                            let data = fs::read(entry.path())?;
-                            let mut doc = parse_fwm(&data)?;
-                            doc.source = Some(entry.path().to_path_buf());
-                            docs.push(doc);
+                            let parsed = fs::read(entry.path())
+                                .map_err(MigrationError::from)
+                                .and_then(|data| parse_fwm_with(&data, decoding));
+                            match parsed {
+                                Ok(mut doc) => {
+                                    doc.source = Some(entry.path().to_path_buf());
+                                    docs.push(doc);
+                                }
+                                Err(error) => self.reject(job_id, request, entry.path(), error),
+                            }
                         }
                     }
+                } else if has_extension(&path, "fwa") {
+                    let mut book = parse_fwa(&fs::read(&path)?, decoding)?;
+                    book.source = Some(path.clone());
+                    self.load_address_book(job_id, request.dry_run, book);
                 } else {
                     let data = fs::read(&path)?;
-                    let mut doc = parse_fwm(&data)?;
+                    let mut doc = parse_fwm_with(&data, decoding)?;
                     doc.source = Some(path.clone());
                     docs.push(doc);
                 }
@@ -630,39 +2081,361 @@ impl MigrationManager {
                 Ok(ResolvedDocuments::Fwm { docs })
             }
             MigrationMode::Fwz => {
-                let archive = read_fwz(&path)?;
+                let mut archive = FwzReader::default()
+                    .with_decoding(decoding)
+                    .with_limits(self.archive_limits)
+                    .with_parallelism(self.parallelism)
+                    .read(&path)?;
+                for book in std::mem::take(&mut archive.address_books) {
+                    self.load_address_book(job_id, request.dry_run, book);
+                }
+                // Rejected documents count as failed; the notes cover the rest.
+                for name in &archive.rejected {
+                    self.record(
+                        job_id,
+                        PathBuf::from(name),
+                        Err(MigrationError::ManifestMismatch(name.clone())),
+                    );
+                }
+                let mut notes: Vec<String> = archive
+                    .mismatched
+                    .iter()
+                    .filter(|name| !archive.rejected.contains(name))
+                    .map(|name| {
+                        if name.to_ascii_uppercase().starts_with("ATTACH/") {
+                            format!("Attachment {name} does not match the archive manifest")
+                        } else {
+                            format!("{name} does not match the archive manifest")
+                        }
+                    })
+                    .collect();
+                if self.attachments.is_none() && !archive.attachments.is_empty() {
+                    notes.push("Attachments were not extracted: no attachment store".into());
+                }
+                let single = archive.documents.len() == 1;
+                let docs = if self.attachments.is_none() {
+                    archive
+                        .documents
+                        .into_iter()
+                        .map(|document| (document, Vec::new()))
+                        .collect()
+                } else {
+                    // Attachments no document names belong to the only one, if
+                    // there is just one.
+                    for attachment in &archive.attachments {
+                        let referenced = archive.documents.iter().any(|document| {
+                            document
+                                .attachments
+                                .iter()
+                                .any(|reference| attachment.is_named(reference))
+                        });
+                        if !referenced && !single {
+                            notes.push(format!(
+                                "Attachment {} is not referenced by any document",
+                                attachment.name
+                            ));
+                        }
+                    }
+                    archive
+                        .documents
+                        .into_iter()
+                        .map(|document| {
+                            let linked = archive
+                                .attachments
+                                .iter()
+                                .filter(|attachment| {
+                                    single
+                                        || document
+                                            .attachments
+                                            .iter()
+                                            .any(|reference| attachment.is_named(reference))
+                                })
+                                .cloned()
+                                .collect();
+                            (document, linked)
+                        })
+                        .collect()
+                };
+                if let Some(job) = self.jobs.lock().unwrap().get_mut(&job_id) {
+                    job.progress.notes.extend(notes);
+                    job.verification = archive.verification;
+                }
                 Ok(ResolvedDocuments::Fwz {
-                    docs: archive.documents,
+                    docs,
                     checksum_ok: archive.checksum_ok,
                 })
             }
+            MigrationMode::Eml => {
+                let importer = EmlImporter::new(self.rfc822_mapper());
+                let mut docs = Vec::new();
+                let files: Vec<PathBuf> = if path.is_dir() {
+                    WalkDir::new(&path)
+                        .sort_by_file_name()
+                        .into_iter()
+                        .filter_map(Result::ok)
+                        .filter(|entry| {
+                            entry.file_type().is_file() && has_extension(entry.path(), "eml")
+                        })
+                        .map(|entry| entry.path().to_path_buf())
+                        .collect()
+                } else {
+                    vec![path.clone()]
+                };
+                let single = !path.is_dir();
+                for file in files {
+                    let parsed = fs::read(&file)
+                        .map_err(MigrationError::from)
+                        .and_then(|data| importer.read(&data));
+                    match parsed {
+                        Ok(mut doc) => {
+                            doc.source = Some(file);
+                            docs.push(doc);
+                        }
+                        Err(error) if single => return Err(error),
+                        Err(error) => self.reject(job_id, request, &file, error),
+                    }
+                }
+                Ok(ResolvedDocuments::Rfc822 { docs })
+            }
+            MigrationMode::Mbox => {
+                let mut docs = MboxImporter::new(self.rfc822_mapper()).read(&fs::read(&path)?)?;
+                for (index, doc) in docs.iter_mut().enumerate() {
+                    doc.source = Some(PathBuf::from(format!("{}#{}", path.display(), index + 1)));
+                }
+                Ok(ResolvedDocuments::Rfc822 { docs })
+            }
             MigrationMode::Auto => unreachable!("auto mode is resolved above"),
         }
     }
 
+    /// Put the contacts of an address book into the directory and remember
+    /// their aliases for later RFC 822 imports.
+    fn load_address_book(&self, job_id: Uuid, dry_run: bool, book: AddressBook) {
+        let source = book
+            .source
+            .as_deref()
+            .map(|source| source.display().to_string())
+            .unwrap_or_default();
+        let mut notes = vec![format!(
+            "Address book {source}: {} contacts, {} distribution lists",
+            book.entries.len(),
+            book.lists.len()
+        )];
+        notes.extend(
+            book.skipped
+                .iter()
+                .map(|reason| format!("Address book {source}: skipped {reason}")),
+        );
+        match &self.directory {
+            Some(directory) if !dry_run => {
+                self.aliases.lock().unwrap().extend(book.aliases());
+                for entry in book.entries {
+                    directory.upsert_entry(entry);
+                }
+                for list in book.lists {
+                    directory.upsert_list(list);
+                }
+            }
+            Some(_) => {}
+            None => notes.push(format!(
+                "Address book {source} not imported: no directory configured"
+            )),
+        }
+        if let Some(job) = self.jobs.lock().unwrap().get_mut(&job_id) {
+            job.progress.notes.extend(notes);
+        }
+    }
+
+    /// The configured mapping plus aliases learnt from imported address books.
+    fn rfc822_mapper(&self) -> AddressMapper {
+        let aliases = self.aliases.lock().unwrap().clone();
+        self.mapper.clone().with_aliases(aliases)
+    }
+
+    /// Count a file from a directory source as failed and quarantine it; the
+    /// rest of the directory is still imported.
+    fn reject(&self, job_id: Uuid, request: &MigrationRequest, path: &Path, error: MigrationError) {
+        self.quarantine(job_id, request, path, &error);
+        self.record(job_id, path.to_path_buf(), Err(error));
+    }
+
+    /// Copy an unreadable artifact to `<quarantine>/<job id>/`, keeping its
+    /// path relative to the source, with a `<name>.error.json` sidecar.
+    fn quarantine(
+        &self,
+        job_id: Uuid,
+        request: &MigrationRequest,
+        path: &Path,
+        error: &MigrationError,
+    ) {
+        if !error.is_corrupt() {
+            return;
+        }
+        let Some(dir) = request
+            .quarantine
+            .clone()
+            .or_else(|| self.quarantine_dir.clone())
+        else {
+            return;
+        };
+        let relative = path
+            .strip_prefix(&request.path)
+            .ok()
+            .filter(|relative| !relative.as_os_str().is_empty())
+            .or_else(|| path.file_name().map(Path::new))
+            .unwrap_or_else(|| Path::new("artifact"));
+        let target = dir.join(job_id.to_string()).join(relative);
+        let record = QuarantineRecord {
+            job_id,
+            source: path.to_path_buf(),
+            error: error.to_string(),
+            quarantined_at: self.clock.now(),
+        };
+        let copied = (|| -> io::Result<u64> {
+            if let Some(parent) = target.parent() {
+                fs::create_dir_all(parent)?;
+            }
+            let bytes = fs::copy(path, &target)?;
+            let mut sidecar = target.clone().into_os_string();
+            sidecar.push(".error.json");
+            let json = serde_json::to_vec_pretty(&record).map_err(io::Error::other)?;
+            fs::write(sidecar, json)?;
+            Ok(bytes)
+        })();
+
+        let mut jobs = self.jobs.lock().unwrap();
+        let Some(job) = jobs.get_mut(&job_id) else {
+            return;
+        };
+        match copied {
+            Ok(bytes) => {
+                info!(target = "migration", source = %path.display(), "artifact quarantined");
+                job.quarantined += 1;
+                job.quarantined_bytes += bytes;
+            }
+            Err(err) => {
+                warn!(target = "migration", source = %path.display(), "cannot quarantine: {err}");
+                job.progress
+                    .notes
+                    .push(format!("Could not quarantine {}: {err}", path.display()));
+            }
+        }
+    }
+
+    /// Live progress of one job.
     pub fn progress(&self, job_id: Uuid) -> Result<MigrationProgress, MigrationError> {
         let jobs = self.jobs.lock().unwrap();
         let job = jobs.get(&job_id).ok_or(MigrationError::UnknownJob)?;
         Ok(job.progress.clone())
     }
 
+    /// Final report of a job; [`MigrationError::InProgress`] until the job has
+    /// finished.
     pub fn report(&self, job_id: Uuid) -> Result<MigrationReport, MigrationError> {
         let jobs = self.jobs.lock().unwrap();
         let job = jobs.get(&job_id).ok_or(MigrationError::UnknownJob)?;
-        job.report.clone().ok_or(MigrationError::UnknownJob)
+        job.report.clone().ok_or(MigrationError::InProgress)
     }
 
-    #[allow(dead_code)]
     pub fn list_jobs(&self) -> Vec<Uuid> {
         self.jobs
             .lock()
             .map(|map| map.keys().copied().collect())
             .unwrap_or_default()
     }
+
+    /// Progress of every job, newest first.
+    pub fn jobs(&self) -> Vec<MigrationProgress> {
+        let mut jobs: Vec<MigrationProgress> = self
+            .jobs
+            .lock()
+            .map(|map| map.values().map(|job| job.progress.clone()).collect())
+            .unwrap_or_default();
+        jobs.sort_by_key(|job| std::cmp::Reverse(job.started_at));
+        jobs
+    }
 }
 
 struct ImportResult {
-    is_duplicate: bool,
+    subject: String,
+    folder: String,
+    /// Id and folder of the stored copy, if the message is a duplicate.
+    duplicate_of: Option<(MessageId, String)>,
+}
+
+impl DryRunDiff {
+    fn add(&mut self, path: PathBuf, result: ImportResult, known_folder: bool) {
+        match result.duplicate_of {
+            Some((existing_id, existing_folder)) => {
+                if existing_folder != result.folder {
+                    self.folder_conflicts.push(FolderConflict {
+                        path: path.clone(),
+                        folder: result.folder,
+                        existing_folder: Some(existing_folder.clone()),
+                    });
+                }
+                self.duplicates.push(DiffDuplicate {
+                    path,
+                    subject: result.subject,
+                    existing_id: existing_id.0,
+                    existing_folder,
+                });
+            }
+            None => {
+                if !known_folder {
+                    self.folder_conflicts.push(FolderConflict {
+                        path: path.clone(),
+                        folder: result.folder.clone(),
+                        existing_folder: None,
+                    });
+                }
+                self.new_messages.push(DiffMessage {
+                    path,
+                    subject: result.subject,
+                    folder: result.folder,
+                });
+            }
+        }
+    }
+}
+
+enum LegacyItem {
+    /// A FileWork document with the archive attachments linked to it.
+    Fwm(FwmDocument, Vec<AttachmentSummary>),
+    Rfc822(Rfc822Document),
+}
+
+impl LegacyItem {
+    fn source(&self) -> Option<&PathBuf> {
+        match self {
+            Self::Fwm(document, _) => document.source.as_ref(),
+            Self::Rfc822(document) => document.source.as_ref(),
+        }
+    }
+
+    /// Content hash recorded in the job's checkpoints.
+    fn sha256(&self) -> String {
+        let mut hasher = Sha256::new();
+        match self {
+            Self::Fwm(document, attachments) => {
+                for (key, value) in &document.values {
+                    hasher.update(format!("{key}={value}\n"));
+                }
+                for attachment in attachments {
+                    hasher.update(format!("{}={}\n", attachment.name, attachment.sha256));
+                }
+            }
+            Self::Rfc822(document) => hasher.update(&document.raw),
+        }
+        format!("{:x}", hasher.finalize())
+    }
+
+    fn created_at(&self) -> Option<DateTime<Utc>> {
+        match self {
+            Self::Fwm(document, _) => document.created_at(),
+            Self::Rfc822(document) => document.created_at(),
+        }
+    }
 }
 
 enum ResolvedDocuments {
@@ -670,18 +2443,50 @@ enum ResolvedDocuments {
         docs: Vec<FwmDocument>,
     },
     Fwz {
-        docs: Vec<FwmDocument>,
+        docs: Vec<(FwmDocument, Vec<AttachmentSummary>)>,
         checksum_ok: bool,
     },
+    Rfc822 {
+        docs: Vec<Rfc822Document>,
+    },
 }
 
-fn infer_mode(path: &Path) -> MigrationMode {
-    if path
-        .extension()
-        .map(|ext| ext.eq_ignore_ascii_case("fwz"))
+fn has_extension(path: &Path, extension: &str) -> bool {
+    path.extension()
+        .map(|ext| ext.eq_ignore_ascii_case(extension))
         .unwrap_or(false)
-    {
+}
+
+/// Content type recorded for an extracted attachment, from its extension.
+fn mime_type(filename: &str) -> &'static str {
+    let extension = filename
+        .rsplit_once('.')
+        .map(|(_, extension)| extension.to_ascii_lowercase())
+        .unwrap_or_default();
+    match extension.as_str() {
+        "txt" | "log" => "text/plain",
+        "csv" => "text/csv",
+        "htm" | "html" => "text/html",
+        "xml" => "application/xml",
+        "pdf" => "application/pdf",
+        "doc" => "application/msword",
+        "xls" => "application/vnd.ms-excel",
+        "zip" => "application/zip",
+        "gif" => "image/gif",
+        "jpg" | "jpeg" => "image/jpeg",
+        "png" => "image/png",
+        "tif" | "tiff" => "image/tiff",
+        _ => "application/octet-stream",
+    }
+}
+
+fn infer_mode(path: &Path) -> MigrationMode {
+    if has_extension(path, "fwz") {
         MigrationMode::Fwz
+    } else if has_extension(path, "mbox") {
+        MigrationMode::Mbox
+    } else if has_extension(path, "eml") {
+        MigrationMode::Eml
     } else {
         MigrationMode::Fwm
     }
@@ -699,4 +2504,87 @@ mod tests {
         assert_eq!(document.body(), "Hello");
         assert_eq!(document.recipients().len(), 1);
     }
+
+    #[test]
+    fn jobs_pause_between_documents_and_can_be_cancelled() {
+        let dir = tempfile::tempdir().unwrap();
+        for index in 0..3 {
+            let body = format!("SUBJECT=Item {index}\nBODY=Body {index}\n");
+            fs::write(dir.path().join(format!("{index}.fwm")), body).unwrap();
+        }
+        let store = StoreManager::new();
+        let manager = MigrationManager::new(store.clone())
+            .with_parallelism(1)
+            .with_audit(AuditLog::new(store.clone()));
+        let request = MigrationRequest {
+            path: dir.path().to_path_buf(),
+            ..MigrationRequest::default()
+        };
+
+        // Hold the worker inside its first save so the pause lands mid-job.
+        let saving = manager.saving.lock().unwrap();
+        let job_id = manager.import("ops", request.clone()).unwrap();
+        let deadline = std::time::Instant::now() + std::time::Duration::from_secs(5);
+        while manager.progress(job_id).unwrap().current_path.is_none() {
+            assert!(std::time::Instant::now() < deadline, "worker never started");
+            thread::sleep(std::time::Duration::from_millis(5));
+        }
+        assert_eq!(
+            manager.pause(job_id).unwrap().status,
+            MigrationStatus::Paused
+        );
+        drop(saving);
+        while manager.progress(job_id).unwrap().processed < 1 {
+            assert!(std::time::Instant::now() < deadline, "worker never ran");
+            thread::sleep(std::time::Duration::from_millis(5));
+        }
+        thread::sleep(std::time::Duration::from_millis(50));
+        let progress = manager.progress(job_id).unwrap();
+        assert_eq!(
+            (progress.status, progress.processed),
+            (MigrationStatus::Paused, 1)
+        );
+
+        manager.cancel("ops", job_id).unwrap();
+        let report = manager.wait(job_id).unwrap();
+        assert_eq!((report.total, report.imported), (3, 1));
+        assert_eq!(
+            manager.progress(job_id).unwrap().status,
+            MigrationStatus::Cancelled
+        );
+        assert!(matches!(
+            manager.resume(job_id),
+            Err(MigrationError::Finished)
+        ));
+        assert_eq!(store.list("inbox").len(), 1);
+        let audited: Vec<_> = store
+            .audit_records()
+            .into_iter()
+            .map(|record| (record.actor, record.action, record.target))
+            .collect();
+        assert_eq!(
+            audited,
+            [
+                (
+                    "ops".to_string(),
+                    AuditAction::MigrationImport,
+                    dir.path().display().to_string()
+                ),
+                (
+                    "ops".to_string(),
+                    AuditAction::MigrationCancel,
+                    job_id.to_string()
+                ),
+            ]
+        );
+
+        let saving = manager.saving.lock().unwrap();
+        let job_id = manager.import("ops", request).unwrap();
+        manager.pause(job_id).unwrap();
+        manager.resume(job_id).unwrap();
+        drop(saving);
+        let report = manager.wait(job_id).unwrap();
+        assert_eq!((report.imported, report.duplicates), (3, 1));
+        assert_eq!(store.list("inbox").len(), 3);
+    }
 }
@@ -1,7 +1,32 @@
-use crate::models::{Message, MessageId, MessageStatus};
+use std::sync::{Arc, Mutex, PoisonError};
+use std::thread;
+use std::time::Duration;
+
+use chrono::{DateTime, Utc};
+
+use crate::clock::SharedClock;
+use crate::models::{Address, Message, MessageId, MessageStatus, Report, ReportKind};
 use crate::queue::QueueManager;
+use crate::reports::{ReportError, ReportIngestor};
 use crate::store::StoreManager;
+use crate::supervisor::Supervisor;
 use crate::trace::TraceManager;
+use crate::transport::{MessageTransport, TransportError};
+use tracing::warn;
+
+/// Parameters for an administratively simulated report, see
+/// [`MockDeliveryProvider::simulate_report`].
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct MockReportRequest {
+    pub message_id: MessageId,
+    pub kind: ReportKind,
+    pub recipient: Option<Address>,
+    pub reason: Option<String>,
+    pub diagnostic: Option<String>,
+    /// Hold the report back for this long; the `mock-scheduler` worker
+    /// releases it through [`MockDeliveryProvider::release_due_reports`].
+    pub delay: Duration,
+}
 
 /// In-memory delivery provider used to simulate message transitions.
 #[derive(Clone)]
@@ -9,29 +34,257 @@ pub struct MockDeliveryProvider {
     queue: QueueManager,
     store: StoreManager,
     trace: TraceManager,
+    reports: ReportIngestor,
+    pending_reports: Arc<Mutex<Vec<Report>>>,
+    clock: SharedClock,
+    mode: Arc<Mutex<&'static str>>,
 }
 
 impl MockDeliveryProvider {
     pub fn new(queue: QueueManager, store: StoreManager, trace: TraceManager) -> Self {
+        let reports = ReportIngestor::new(store.clone(), trace.clone());
         Self {
             queue,
             store,
             trace,
+            reports,
+            pending_reports: Arc::new(Mutex::new(Vec::new())),
+            clock: SharedClock::default(),
+            mode: Arc::new(Mutex::new("mock")),
+        }
+    }
+
+    pub fn with_clock(mut self, clock: SharedClock) -> Self {
+        self.clock = clock;
+        self
+    }
+
+    /// Feed simulated reports through the service's shared ingestor, so they
+    /// reach webhooks like reports from a real transport.
+    pub fn with_reports(mut self, reports: ReportIngestor) -> Self {
+        self.reports = reports;
+        self
+    }
+
+    /// Deliver scheduled messages whose send-at time has come and release
+    /// delayed simulated reports, once a second, as the supervised
+    /// `mock-scheduler` worker.
+    pub fn spawn(&self, supervisor: &Supervisor) {
+        let provider = self.clone();
+        supervisor.spawn("mock-scheduler", move |context| {
+            while !context.should_stop() {
+                provider.release_scheduled();
+                provider.release_due_reports();
+                context.heartbeat();
+                thread::sleep(Duration::from_secs(1));
+            }
+        });
+    }
+
+    /// Park the message in the outbox until `until`, when the
+    /// `mock-scheduler` worker delivers it through
+    /// [`release_scheduled`](Self::release_scheduled).
+    pub fn schedule(&self, mut message: Message, until: DateTime<Utc>) -> MessageId {
+        let id = message.envelope.id.clone();
+        let priority = message.envelope.priority;
+        message.envelope.status = MessageStatus::Queued;
+        self.store.save(message);
+        self.queue.enqueue_deferred(id.clone(), priority, until);
+        self.trace.record("mock.scheduled", id.clone());
+        id
+    }
+
+    /// Deliver scheduled messages whose send-at time has arrived.
+    pub fn release_scheduled(&self) -> usize {
+        let released = self.queue.release_deferred();
+        for id in &released {
+            let Some(message) = self.store.get(id) else {
+                self.queue.complete(id);
+                continue;
+            };
+            self.deliver(id, &message.envelope.recipients);
+            self.queue.complete(id);
+        }
+        released.len()
+    }
+
+    /// Withdraw a scheduled message before dispatch and move it to `drafts`.
+    ///
+    /// Returns `false` once the message has left the queue.
+    pub fn cancel_scheduled(&self, id: &MessageId) -> bool {
+        if !self.queue.cancel(id) {
+            return false;
         }
+        if let Some(mut message) = self.store.get(id) {
+            message.envelope.folder = "drafts".into();
+            message.envelope.status = MessageStatus::Draft;
+            self.store.save(message);
+        }
+        self.trace.record("mock.schedule_cancelled", id.clone());
+        true
     }
 
     pub fn dispatch(&self, message: Message) -> MessageId {
         let id = message.envelope.id.clone();
+        let recipients = message.envelope.recipients.clone();
+        let priority = message.envelope.priority;
         self.trace.record("mock.accepted", id.clone());
         self.store.save(message);
-        self.queue.enqueue(id.clone());
+        self.queue.enqueue_with_priority(id.clone(), priority);
+        self.deliver(&id, &recipients);
+        self.queue.complete(&id);
+        id
+    }
 
-        self.store.update_status(&id, MessageStatus::Delivered);
+    fn deliver(&self, id: &MessageId, recipients: &[Address]) {
+        self.emit_reports(id, recipients, ReportKind::Delivery);
         self.trace.record("mock.delivered", id.clone());
 
-        self.store.update_status(&id, MessageStatus::Read);
+        self.emit_reports(id, recipients, ReportKind::Read);
         self.trace.record("mock.read", id.clone());
+    }
 
-        id
+    /// Produce one report per recipient the way an MTA would after a successful transfer.
+    fn emit_reports(&self, id: &MessageId, recipients: &[Address], kind: ReportKind) {
+        let (reason, diagnostic) = match kind {
+            // X.411 type-of-MTS-user "public" and the IPM receipt type for read notifications.
+            ReportKind::Delivery => (
+                Some("delivered".to_string()),
+                Some("type-of-MTS-user-public".to_string()),
+            ),
+            ReportKind::Read => (
+                Some("receipt".to_string()),
+                Some("explicit-receipt".to_string()),
+            ),
+            ReportKind::NonDelivery => (None, None),
+        };
+        for recipient in recipients {
+            let report = Report {
+                message_id: id.clone(),
+                kind,
+                recipient: Some(recipient.clone()),
+                reason: reason.clone(),
+                diagnostic: diagnostic.clone(),
+                timestamp: self.clock.now(),
+            };
+            if let Err(err) = self.reports.ingest(report) {
+                warn!(target = "mock", "failed to record mock report: {err}");
+            }
+        }
+    }
+
+    /// Generate a report for an existing message and feed it through the
+    /// regular report ingestion path. Refused while another transport is
+    /// active, so real traffic never mixes with simulated reports.
+    pub fn simulate_report(&self, request: MockReportRequest) -> Result<Report, ReportError> {
+        let mode = *self.mode.lock().unwrap_or_else(PoisonError::into_inner);
+        if mode != "mock" {
+            return Err(ReportError::MockInactive(mode));
+        }
+        if self.store.get(&request.message_id).is_none() {
+            return Err(ReportError::UnknownMessage(request.message_id.to_string()));
+        }
+        let (reason, diagnostic) = match request.kind {
+            ReportKind::NonDelivery => (
+                request.reason.or_else(|| Some("unable-to-transfer".into())),
+                request
+                    .diagnostic
+                    .or_else(|| Some("unrecognised-OR-name".into())),
+            ),
+            _ => (request.reason, request.diagnostic),
+        };
+        let delay = chrono::Duration::from_std(request.delay).unwrap_or_default();
+        let report = Report {
+            message_id: request.message_id,
+            kind: request.kind,
+            recipient: request.recipient,
+            reason,
+            diagnostic,
+            timestamp: self.clock.now() + delay,
+        };
+        self.trace
+            .record("mock.report_simulated", report.message_id.clone());
+
+        if request.delay.is_zero() {
+            self.reports.ingest(report.clone())?;
+        } else if let Ok(mut pending) = self.pending_reports.lock() {
+            pending.push(report.clone());
+        }
+        Ok(report)
+    }
+
+    /// Ingest simulated reports whose delay has elapsed, returning how many were released.
+    pub fn release_due_reports(&self) -> usize {
+        let now = self.clock.now();
+        let due = match self.pending_reports.lock() {
+            Ok(mut pending) => {
+                let (due, waiting): (Vec<_>, Vec<_>) = pending
+                    .drain(..)
+                    .partition(|report| report.timestamp <= now);
+                *pending = waiting;
+                due
+            }
+            Err(_) => return 0,
+        };
+        let mut released = 0;
+        for report in due {
+            match self.reports.ingest(report) {
+                Ok(()) => released += 1,
+                Err(err) => warn!(target = "mock", "dropping simulated report: {err}"),
+            }
+        }
+        released
+    }
+}
+
+/// Delivery is simulated as soon as a message is submitted; everything else
+/// reads the local store.
+impl MessageTransport for MockDeliveryProvider {
+    fn name(&self) -> &'static str {
+        "mock"
+    }
+
+    fn submit(&self, message: Message) -> Result<MessageId, TransportError> {
+        Ok(self.dispatch(message))
+    }
+
+    fn fetch(&self, id: &MessageId) -> Result<Option<Message>, TransportError> {
+        Ok(self.store.get(id))
+    }
+
+    fn list(&self, folder: &str) -> Result<Vec<Message>, TransportError> {
+        Ok(self.store.list(folder))
+    }
+
+    fn delete(&self, id: &MessageId) -> Result<bool, TransportError> {
+        Ok(self.store.delete(id))
+    }
+
+    fn reports(&self, id: &MessageId) -> Result<Vec<Report>, TransportError> {
+        Ok(self.store.reports(id))
+    }
+
+    fn schedule(
+        &self,
+        message: Message,
+        until: DateTime<Utc>,
+    ) -> Result<MessageId, TransportError> {
+        Ok(MockDeliveryProvider::schedule(self, message, until))
+    }
+
+    fn can_schedule(&self) -> bool {
+        true
+    }
+
+    fn dispatch_queued(&self, message: Message) -> Result<MessageId, TransportError> {
+        let id = message.envelope.id.clone();
+        self.trace.record("mock.accepted", id.clone());
+        self.deliver(&id, &message.envelope.recipients);
+        self.queue.complete(&id);
+        Ok(id)
+    }
+
+    fn activated(&self, mode: &'static str) {
+        *self.mode.lock().unwrap_or_else(PoisonError::into_inner) = mode;
     }
 }
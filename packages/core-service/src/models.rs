@@ -1,10 +1,20 @@
+use std::collections::BTreeSet;
 use std::fmt;
+use std::path::PathBuf;
 use std::sync::atomic::{AtomicUsize, Ordering};
 
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use uuid::Uuid;
+
+use crate::clock::{SharedClock, SharedIds};
+
 static MESSAGE_COUNTER: AtomicUsize = AtomicUsize::new(1);
 
 /// Unique identifier for messages.
-#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+#[derive(Clone, Debug, PartialEq, Eq, PartialOrd, Ord, Hash, Serialize, Deserialize)]
+#[serde(transparent)]
 pub struct MessageId(pub String);
 
 impl MessageId {
@@ -27,7 +37,7 @@ impl fmt::Display for MessageId {
 }
 
 /// Basic representation of an address.
-#[derive(Clone, Debug, PartialEq, Eq)]
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
 pub struct Address {
     pub country: String,
     pub organization: String,
@@ -44,24 +54,183 @@ impl Address {
     }
 }
 
+/// O/R name attributes (X.402), as the API and SDK exchange them.
+#[derive(Clone, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct OrName {
+    pub c: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub admd: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub prmd: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub o: Option<String>,
+    /// Organisational units OU1 to OU4, outermost first.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub ou: Vec<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub surname: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub given_name: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub initials: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub generation_qualifier: Option<String>,
+}
+
+/// Domain-defined attribute.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Dda {
+    #[serde(rename = "type")]
+    pub kind: String,
+    pub value: String,
+}
+
+/// Full X.400 O/R address. Written and parsed in the
+/// `C=DE;ADMD=ViaT;PRMD=Corp;O=Org;OU1=Ops;S=Muller;G=Hans;I=H;Q=Jr;DDA:UID=42`
+/// form, attributes in that order.
+#[derive(Clone, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct X400Address {
+    pub or_name: OrName,
+    #[serde(default)]
+    pub dda: Vec<Dda>,
+    #[serde(default)]
+    pub routing_hints: Vec<String>,
+}
+
+impl X400Address {
+    /// Parse the semicolon-separated attribute form. Keys are
+    /// case-insensitive; `OU` without a number appends the next unit.
+    /// Country and a surname or given name are required.
+    pub fn parse(value: &str) -> Option<Self> {
+        let mut address = Self::default();
+        let name = &mut address.or_name;
+        for part in value.split(';').filter(|part| !part.trim().is_empty()) {
+            let (raw_key, value) = part.split_once('=')?;
+            let key = raw_key.trim().to_ascii_uppercase();
+            let value = value.trim().to_string();
+            if value.is_empty() {
+                continue;
+            }
+            match key.as_str() {
+                "C" => name.c = value,
+                "ADMD" | "A" => name.admd = Some(value),
+                "PRMD" | "P" => name.prmd = Some(value),
+                "O" => name.o = Some(value),
+                "OU" => name.ou.push(value),
+                "OU1" | "OU2" | "OU3" | "OU4" => {
+                    let index = usize::from(key.as_bytes()[2] - b'1');
+                    if name.ou.len() <= index {
+                        name.ou.resize(index + 1, String::new());
+                    }
+                    name.ou[index] = value;
+                }
+                "S" => name.surname = Some(value),
+                "G" => name.given_name = Some(value),
+                "I" => name.initials = Some(value),
+                "Q" => name.generation_qualifier = Some(value),
+                _ if key.starts_with("DDA:") && key.len() > 4 => address.dda.push(Dda {
+                    kind: raw_key.trim()[4..].trim().to_string(),
+                    value,
+                }),
+                _ => {}
+            }
+        }
+        name.ou.retain(|unit| !unit.is_empty());
+        let named = name.surname.is_some() || name.given_name.is_some();
+        (!name.c.is_empty() && named).then_some(address)
+    }
+
+    /// The stored C/O/S form: the organisation defaults to `UNKNOWN` and a
+    /// missing surname to the given name, then `User`.
+    pub fn address(&self) -> Address {
+        let name = &self.or_name;
+        Address {
+            country: name.c.clone(),
+            organization: name.o.clone().unwrap_or_else(|| "UNKNOWN".into()),
+            surname: name
+                .surname
+                .clone()
+                .or_else(|| name.given_name.clone())
+                .unwrap_or_else(|| "User".into()),
+        }
+    }
+}
+
+impl From<&Address> for X400Address {
+    fn from(address: &Address) -> Self {
+        Self {
+            or_name: OrName {
+                c: address.country.clone(),
+                o: Some(address.organization.clone()).filter(|o| !o.is_empty()),
+                surname: Some(address.surname.clone()).filter(|s| !s.is_empty()),
+                ..OrName::default()
+            },
+            dda: Vec::new(),
+            routing_hints: Vec::new(),
+        }
+    }
+}
+
+impl std::fmt::Display for X400Address {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let name = &self.or_name;
+        let units = name
+            .ou
+            .iter()
+            .take(4)
+            .enumerate()
+            .map(|(index, unit)| (format!("OU{}", index + 1), Some(unit)));
+        let parts: Vec<String> = [
+            ("C".to_string(), Some(&name.c)),
+            ("ADMD".into(), name.admd.as_ref()),
+            ("PRMD".into(), name.prmd.as_ref()),
+            ("O".into(), name.o.as_ref()),
+        ]
+        .into_iter()
+        .chain(units)
+        .chain([
+            ("S".into(), name.surname.as_ref()),
+            ("G".into(), name.given_name.as_ref()),
+            ("I".into(), name.initials.as_ref()),
+            ("Q".into(), name.generation_qualifier.as_ref()),
+        ])
+        .chain(
+            self.dda
+                .iter()
+                .map(|dda| (format!("DDA:{}", dda.kind), Some(&dda.value))),
+        )
+        .filter_map(|(key, value)| value.map(|value| format!("{key}={value}")))
+        .collect();
+        f.write_str(&parts.join(";"))
+    }
+}
+
 /// Message priority options.
-#[derive(Clone, Debug, PartialEq, Eq)]
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
 pub enum MessagePriority {
     Low,
+    #[default]
     Normal,
     High,
 }
 
 /// Sensitivity flag for a message.
-#[derive(Clone, Debug, PartialEq, Eq)]
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
 pub enum MessageSensitivity {
     Normal,
     Personal,
 }
 
 /// Tracking states of a message.
-#[derive(Clone, Debug, PartialEq, Eq)]
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
 pub enum MessageStatus {
+    /// Not submitted, e.g. a scheduled message cancelled before dispatch.
+    Draft,
     Queued,
     Sent,
     Delivered,
@@ -81,12 +250,97 @@ pub struct MessageEnvelope {
     pub status: MessageStatus,
     pub priority: MessagePriority,
     pub sensitivity: MessageSensitivity,
+    pub created_at: DateTime<Utc>,
+    /// X.400 latest-delivery-time; undelivered messages past it are failed with an NDR.
+    pub latest_delivery: Option<DateTime<Utc>>,
+    /// Identifier the message arrived with (RFC 822 `Message-ID` or X.420 IPM
+    /// identifier), if it did not originate here.
+    pub origin_id: Option<String>,
+    /// IPM identifier of the message this one replies to: a local message id
+    /// or another message's `origin_id`.
+    pub in_reply_to: Option<String>,
+    /// How often the message crossed the SMTP/X.400 gateway, carried in
+    /// `X-X400-Gateway-Hops` on the SMTP side.
+    pub gateway_hops: u32,
+    /// S/MIME protection asked for on submit, and its result.
+    pub security: MessageSecurity,
+    /// Id of the first message of the conversation; assigned by the store on save.
+    pub thread_id: MessageId,
+}
+
+/// S/MIME options of a message. The body stays readable in the store; the
+/// protected form travels in `cms`. Received mail records how its signature
+/// and encryption were found.
+#[derive(Clone, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct MessageSecurity {
+    /// Sign the content on submit.
+    #[serde(default)]
+    pub sign: bool,
+    /// Encrypt the content on submit.
+    #[serde(default)]
+    pub encrypt: bool,
+    /// Base64 DER CMS `ContentInfo` produced on submit.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub cms: Option<String>,
+    /// Outcome of checking the signature of a received signed message.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub signature: Option<SignatureStatus>,
+    /// Set when a received message arrived encrypted.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub encryption: Option<EncryptionStatus>,
+}
+
+/// Ordered from best to worst.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub enum SignatureStatus {
+    /// The content is intact and the signer chains to the trust store.
+    Valid,
+    /// The content is intact but the signer is not trusted.
+    Untrusted,
+    /// The content does not match the signature.
+    Invalid,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub enum EncryptionStatus {
+    Decrypted,
+    /// Not encrypted to the local key; the encrypted part is kept as an
+    /// attachment.
+    Undecryptable,
+}
+
+impl MessageSecurity {
+    pub fn is_requested(&self) -> bool {
+        self.sign || self.encrypt
+    }
 }
 
 impl MessageEnvelope {
     pub fn new(subject: &str, sender: Address, recipients: Vec<Address>) -> Self {
+        Self::stamped(
+            subject,
+            sender,
+            recipients,
+            &SharedIds::default(),
+            &SharedClock::default(),
+        )
+    }
+
+    /// Build an envelope whose id and creation time come from the given sources.
+    pub fn stamped(
+        subject: &str,
+        sender: Address,
+        recipients: Vec<Address>,
+        ids: &SharedIds,
+        clock: &SharedClock,
+    ) -> Self {
+        let id = ids.message_id();
         Self {
-            id: MessageId::new(),
+            thread_id: id.clone(),
+            id,
             subject: subject.into(),
             sender,
             recipients,
@@ -94,6 +348,12 @@ impl MessageEnvelope {
             status: MessageStatus::Queued,
             priority: MessagePriority::Normal,
             sensitivity: MessageSensitivity::Normal,
+            created_at: clock.now(),
+            latest_delivery: None,
+            origin_id: None,
+            in_reply_to: None,
+            gateway_hops: 0,
+            security: MessageSecurity::default(),
         }
     }
 }
@@ -102,6 +362,19 @@ impl MessageEnvelope {
 #[derive(Clone, Debug, PartialEq, Eq)]
 pub struct MessageContent {
     pub body: String,
+    /// Files kept in the gateway's attachment store.
+    pub attachments: Vec<MessageAttachment>,
+}
+
+/// Reference from a message to a blob in the attachment store.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct MessageAttachment {
+    /// Attachment store id, see [`AttachmentStore::blob_id`](crate::attachments::AttachmentStore::blob_id).
+    pub id: String,
+    pub filename: String,
+    pub mime_type: String,
+    pub size: u64,
 }
 
 /// Complete message representation.
@@ -110,3 +383,287 @@ pub struct Message {
     pub envelope: MessageEnvelope,
     pub content: MessageContent,
 }
+
+impl Message {
+    /// SHA-256 over what makes two copies of a message the same mail: subject,
+    /// sender and recipients (case-insensitive, recipients in any order), body
+    /// (line endings normalized) and attachment hashes. The id, folder, status
+    /// and timestamps are left out, so a re-imported copy hashes alike wherever
+    /// it was filed.
+    pub fn content_hash(&self) -> String {
+        let envelope = &self.envelope;
+        let address = |address: &Address| {
+            format!(
+                "c={};o={};s={}",
+                address.country.trim(),
+                address.organization.trim(),
+                address.surname.trim()
+            )
+            .to_lowercase()
+        };
+        let mut recipients: Vec<String> = envelope.recipients.iter().map(address).collect();
+        recipients.sort();
+        let mut attachments: Vec<&str> = self
+            .content
+            .attachments
+            .iter()
+            .map(|attachment| attachment.id.as_str())
+            .collect();
+        attachments.sort_unstable();
+
+        let mut hasher = Sha256::new();
+        for part in [
+            envelope.subject.trim(),
+            &address(&envelope.sender),
+            &recipients.join("\n"),
+            self.content.body.replace("\r\n", "\n").trim_end(),
+            &attachments.join("\n"),
+        ] {
+            hasher.update(part.as_bytes());
+            hasher.update([0]);
+        }
+        format!("{:x}", hasher.finalize())
+    }
+}
+
+/// Dispatch state of a queue row.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub enum QueueEntryStatus {
+    Pending,
+    /// Handed to the transport; still pending if the service stops before completion.
+    InFlight,
+}
+
+/// Row of the persistent `queue` table kept by the store.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct QueueEntry {
+    pub message_id: MessageId,
+    pub status: QueueEntryStatus,
+    pub attempts: u32,
+    pub next_attempt_at: DateTime<Utc>,
+    #[serde(default)]
+    pub priority: MessagePriority,
+    /// When the message first entered the queue; drives priority aging.
+    #[serde(default)]
+    pub enqueued_at: DateTime<Utc>,
+    /// Send-at time requested by the submitter; the row sits in the
+    /// scheduled outbox until then.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub deferred_until: Option<DateTime<Utc>>,
+    /// Set by an operator; the scheduler skips the row until it is released.
+    #[serde(default)]
+    pub held: bool,
+}
+
+/// Row of the persistent `idempotency` table: which message a client's
+/// `Idempotency-Key` produced, so a retried request can be answered with it.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct IdempotencyRecord {
+    pub key: String,
+    /// Hash of the request body; a replay must match it.
+    pub fingerprint: String,
+    pub message_id: MessageId,
+    pub created_at: DateTime<Utc>,
+    pub expires_at: DateTime<Utc>,
+}
+
+/// User-set flags of a message, kept apart from the message itself so
+/// toggling one does not rewrite it.
+#[derive(Clone, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct MessageFlags {
+    pub read: bool,
+    pub flagged: bool,
+    pub answered: bool,
+    #[serde(default)]
+    pub labels: BTreeSet<String>,
+}
+
+/// Row of the persistent `flags` table: the flags of one message.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct MessageFlagsRecord {
+    pub message_id: MessageId,
+    #[serde(flatten)]
+    pub flags: MessageFlags,
+}
+
+/// Row of the persistent `folders` table. Messages refer to folders by id,
+/// so renaming a folder never touches its messages.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct FolderRecord {
+    pub id: String,
+    pub name: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub parent_id: Option<String>,
+    /// Built-in folders the service itself files messages into.
+    #[serde(default)]
+    pub system: bool,
+}
+
+/// Row of the persistent `aliases` table: an O/R address, in canonical
+/// `C=..;O=..;S=..` form, and the RFC 822 address the gateway uses for it
+/// in both directions.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct AliasRecord {
+    pub or_address: String,
+    pub email: String,
+}
+
+/// Row of the persistent `held_content` table: where the content of a
+/// message stored headers-only is kept, see [`crate::quota`].
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct HeldContentRecord {
+    pub message_id: MessageId,
+    pub attachment_id: String,
+    pub size: u64,
+}
+
+/// Row of the persistent `webhooks` table: a report subscriber and the
+/// secret its deliveries are signed with.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct WebhookRecord {
+    pub id: Uuid,
+    pub url: String,
+    pub secret: String,
+    /// Report events the subscriber wants; empty means all.
+    #[serde(default)]
+    pub events: Vec<String>,
+    pub active: bool,
+    pub created_at: DateTime<Utc>,
+}
+
+/// Row of the persistent `migration_state` table: a document a migration job
+/// has imported, so a resumed job skips it even after a restart. The hash
+/// makes a document that changed since then count as new.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct MigrationCheckpoint {
+    pub job_id: Uuid,
+    pub path: PathBuf,
+    pub sha256: String,
+    pub completed_at: DateTime<Utc>,
+}
+
+/// Row of the append-only `audit` table. `hash` covers every other field,
+/// including `previous_hash`, so each row vouches for the whole chain before
+/// it.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct AuditRecord {
+    pub sequence: u64,
+    pub timestamp: DateTime<Utc>,
+    /// Authenticated principal, or the worker acting on its own.
+    pub actor: String,
+    pub action: AuditAction,
+    pub target: String,
+    /// HTTP status of the request, when the operation was one.
+    pub status: Option<u16>,
+    pub detail: Option<String>,
+    pub previous_hash: String,
+    pub hash: String,
+}
+
+/// Destructive and security-relevant operations the audit log records.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub enum AuditAction {
+    #[serde(rename = "message.delete")]
+    MessageDelete,
+    #[serde(rename = "message.bulk")]
+    MessageBulk,
+    #[serde(rename = "folder.delete")]
+    FolderDelete,
+    #[serde(rename = "alias.delete")]
+    AliasDelete,
+    #[serde(rename = "retention.purge")]
+    RetentionPurge,
+    #[serde(rename = "store.backup")]
+    StoreBackup,
+    #[serde(rename = "store.restore")]
+    StoreRestore,
+    #[serde(rename = "store.rekey")]
+    StoreRekey,
+    #[serde(rename = "certificate.import")]
+    CertificateImport,
+    #[serde(rename = "certificate.delete")]
+    CertificateDelete,
+    #[serde(rename = "config.import")]
+    ConfigImport,
+    #[serde(rename = "config.reload")]
+    ConfigReload,
+    #[serde(rename = "migration.import")]
+    MigrationImport,
+    #[serde(rename = "migration.cancel")]
+    MigrationCancel,
+    #[serde(rename = "transport.switch")]
+    TransportSwitch,
+    #[serde(rename = "tls.pin")]
+    TlsPin,
+    #[serde(rename = "dlp.block")]
+    DlpBlock,
+    #[serde(rename = "dlp.quarantine")]
+    DlpQuarantine,
+    #[serde(rename = "dlp.encryption_required")]
+    DlpEncryptionRequired,
+}
+
+impl AuditAction {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Self::MessageDelete => "message.delete",
+            Self::MessageBulk => "message.bulk",
+            Self::FolderDelete => "folder.delete",
+            Self::AliasDelete => "alias.delete",
+            Self::RetentionPurge => "retention.purge",
+            Self::StoreBackup => "store.backup",
+            Self::StoreRestore => "store.restore",
+            Self::StoreRekey => "store.rekey",
+            Self::CertificateImport => "certificate.import",
+            Self::CertificateDelete => "certificate.delete",
+            Self::ConfigImport => "config.import",
+            Self::ConfigReload => "config.reload",
+            Self::MigrationImport => "migration.import",
+            Self::MigrationCancel => "migration.cancel",
+            Self::TransportSwitch => "transport.switch",
+            Self::TlsPin => "tls.pin",
+            Self::DlpBlock => "dlp.block",
+            Self::DlpQuarantine => "dlp.quarantine",
+            Self::DlpEncryptionRequired => "dlp.encryption_required",
+        }
+    }
+}
+
+/// Kind of report returned by the MTA for a submitted message.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub enum ReportKind {
+    Delivery,
+    NonDelivery,
+    Read,
+}
+
+/// Delivery, non-delivery, or read report correlated to a stored message.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Report {
+    pub message_id: MessageId,
+    pub kind: ReportKind,
+    pub recipient: Option<Address>,
+    pub reason: Option<String>,
+    pub diagnostic: Option<String>,
+    pub timestamp: DateTime<Utc>,
+}
+
+/// Message together with the reports correlated to it, as returned by `GET /messages/:id`.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct MessageDetail {
+    pub message: Message,
+    pub reports: Vec<Report>,
+}
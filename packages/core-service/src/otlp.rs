@@ -0,0 +1,463 @@
+//! OTLP/HTTP export of spans and metrics to `telemetry.endpoint`.
+//!
+//! Finished spans are queued by [`OtlpExporter::queue`] and posted as OTLP
+//! JSON to `{endpoint}/v1/traces`, `telemetry.batchSize` spans per request.
+//! A full batch goes out straight away; the rest wait for the
+//! `telemetry-export` worker, which also posts the metrics registry to
+//! `{endpoint}/v1/metrics` every `telemetry.exportIntervalMs`. Batches the
+//! collector does not take are handed back to the caller, which keeps them
+//! on disk as `remote-*.bin`.
+
+use std::collections::VecDeque;
+use std::sync::{Arc, Mutex, PoisonError};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use opentelemetry::trace::{SpanId, SpanKind, Status};
+use opentelemetry::{KeyValue, Value};
+use opentelemetry_sdk::export::trace::SpanData;
+use opentelemetry_sdk::Resource;
+use rustls::crypto::ring;
+use rustls::{ClientConfig, RootCertStore};
+use serde_json::{json, Value as Json};
+use thiserror::Error;
+
+use crate::config::TelemetryConfig;
+use crate::metrics::{MetricKind, MetricsRegistry, LATENCY_BUCKETS};
+use crate::telemetry::redact;
+use crate::tls::{load_certificates, TlsError};
+
+/// Spans held between exports, in batches, before the oldest are dropped.
+const QUEUED_BATCHES: usize = 16;
+const SCOPE: &str = "core-service";
+
+#[derive(Debug, Error)]
+pub enum OtlpError {
+    #[error("collector CA bundle unusable: {0}")]
+    Tls(#[from] TlsError),
+    #[error("collector TLS configuration rejected: {0}")]
+    Rustls(#[from] rustls::Error),
+    #[error("collector unreachable: {0}")]
+    Unavailable(String),
+    #[error("collector answered {0}")]
+    Rejected(u16),
+}
+
+impl OtlpError {
+    pub fn status(&self) -> u16 {
+        match self {
+            Self::Tls(_) | Self::Rustls(_) => 500,
+            Self::Unavailable(_) | Self::Rejected(_) => 502,
+        }
+    }
+}
+
+pub trait OtlpTransport: Send + Sync {
+    fn post(&self, url: &str, headers: &[(&str, String)], body: &[u8]) -> Result<u16, String>;
+}
+
+/// Posts over HTTPS, trusting the public roots plus `telemetry.caPath`.
+pub struct HttpOtlpTransport {
+    agent: ureq::Agent,
+}
+
+impl HttpOtlpTransport {
+    pub fn new(config: &TelemetryConfig) -> Result<Self, OtlpError> {
+        let mut roots = RootCertStore {
+            roots: webpki_roots::TLS_SERVER_ROOTS.to_vec(),
+        };
+        if let Some(path) = &config.ca_path {
+            for certificate in load_certificates(path)? {
+                roots.add(certificate)?;
+            }
+        }
+        let tls = ClientConfig::builder_with_provider(Arc::new(ring::default_provider()))
+            .with_safe_default_protocol_versions()?
+            .with_root_certificates(roots)
+            .with_no_client_auth();
+        Ok(Self {
+            agent: ureq::AgentBuilder::new()
+                .timeout(Duration::from_millis(config.timeout_ms))
+                .tls_config(Arc::new(tls))
+                .build(),
+        })
+    }
+}
+
+impl OtlpTransport for HttpOtlpTransport {
+    fn post(&self, url: &str, headers: &[(&str, String)], body: &[u8]) -> Result<u16, String> {
+        let mut request = self.agent.post(url);
+        for (name, value) in headers {
+            request = request.set(name, value);
+        }
+        match request.send_bytes(body) {
+            Ok(response) => Ok(response.status()),
+            Err(ureq::Error::Status(status, _)) => Ok(status),
+            Err(err) => Err(err.to_string()),
+        }
+    }
+}
+
+/// Resource attributes: `service.name` and `service.version`, overridden or
+/// extended by `telemetry.resourceAttributes`.
+pub fn resource_attributes(config: &TelemetryConfig) -> Vec<(String, String)> {
+    let mut attributes = vec![
+        ("service.name".to_string(), "x400-core".to_string()),
+        (
+            "service.version".to_string(),
+            env!("CARGO_PKG_VERSION").to_string(),
+        ),
+    ];
+    for pair in &config.resource_attributes {
+        let Some((key, value)) = pair.split_once('=') else {
+            continue;
+        };
+        let key = key.trim().to_string();
+        let value = value.trim().to_string();
+        match attributes.iter_mut().find(|(existing, _)| *existing == key) {
+            Some(existing) => existing.1 = value,
+            None => attributes.push((key, value)),
+        }
+    }
+    attributes
+}
+
+/// The same attributes as an SDK [`Resource`] for the tracer provider.
+pub fn resource(config: &TelemetryConfig) -> Resource {
+    Resource::new(
+        resource_attributes(config)
+            .into_iter()
+            .map(|(key, value)| KeyValue::new(key, value)),
+    )
+}
+
+#[derive(Clone)]
+pub struct OtlpExporter {
+    endpoint: String,
+    resource: Json,
+    batch_size: usize,
+    pending: Arc<Mutex<VecDeque<Json>>>,
+    transport: Arc<dyn OtlpTransport>,
+    /// Start of the cumulative metric series.
+    started: SystemTime,
+}
+
+impl OtlpExporter {
+    /// `None` when no `telemetry.endpoint` is set.
+    pub fn from_config(config: &TelemetryConfig) -> Result<Option<Self>, OtlpError> {
+        if config.endpoint.is_none() {
+            return Ok(None);
+        }
+        let transport = HttpOtlpTransport::new(config)?;
+        Ok(Self::with_transport(config, Arc::new(transport)))
+    }
+
+    pub fn with_transport(
+        config: &TelemetryConfig,
+        transport: Arc<dyn OtlpTransport>,
+    ) -> Option<Self> {
+        let endpoint = config.endpoint.as_deref()?;
+        // Accept the signal URL as well as the base most collectors document.
+        let endpoint = endpoint.trim_end_matches('/');
+        let endpoint = endpoint.strip_suffix("/v1/traces").unwrap_or(endpoint);
+        let attributes: Vec<Json> = resource_attributes(config)
+            .into_iter()
+            .map(|(key, value)| json!({ "key": key, "value": { "stringValue": value } }))
+            .collect();
+        Some(Self {
+            endpoint: endpoint.to_string(),
+            resource: json!({ "attributes": attributes }),
+            batch_size: config.batch_size.max(1),
+            pending: Arc::new(Mutex::new(VecDeque::new())),
+            transport,
+            started: SystemTime::now(),
+        })
+    }
+
+    /// Queue a finished span; true once a full batch is waiting.
+    pub fn queue(&self, span: &SpanData) -> bool {
+        let mut pending = self.pending.lock().unwrap_or_else(PoisonError::into_inner);
+        if pending.len() >= self.batch_size * QUEUED_BATCHES {
+            pending.pop_front();
+        }
+        pending.push_back(encode_span(span));
+        pending.len() >= self.batch_size
+    }
+
+    /// Post every queued span; returns the request bodies that were not
+    /// accepted, oldest first.
+    pub fn export_spans(&self) -> Vec<Vec<u8>> {
+        let mut failed = Vec::new();
+        loop {
+            let batch: Vec<Json> = {
+                let mut pending = self.pending.lock().unwrap_or_else(PoisonError::into_inner);
+                let take = pending.len().min(self.batch_size);
+                pending.drain(..take).collect()
+            };
+            if batch.is_empty() {
+                return failed;
+            }
+            let body = json!({
+                "resourceSpans": [{
+                    "resource": self.resource,
+                    "scopeSpans": [{ "scope": { "name": SCOPE }, "spans": batch }],
+                }],
+            })
+            .to_string()
+            .into_bytes();
+            if self.post("/v1/traces", &body).is_err() {
+                failed.push(body);
+            }
+        }
+    }
+
+    /// Post the current value of every metric as cumulative OTLP series.
+    pub fn export_metrics(&self, registry: &MetricsRegistry) -> Result<(), OtlpError> {
+        let started = unix_nanos(self.started);
+        let now = unix_nanos(SystemTime::now());
+        let metrics: Vec<Json> = registry
+            .snapshot()
+            .into_iter()
+            .map(|family| {
+                let points: Vec<Json> = family
+                    .series
+                    .iter()
+                    .map(|series| {
+                        let attributes: Vec<Json> = series
+                            .labels
+                            .iter()
+                            .map(|(key, value)| {
+                                json!({ "key": key, "value": { "stringValue": value } })
+                            })
+                            .collect();
+                        let mut point = json!({
+                            "attributes": attributes,
+                            "startTimeUnixNano": started,
+                            "timeUnixNano": now,
+                        });
+                        if family.metric.kind == MetricKind::Histogram {
+                            let mut buckets: Vec<String> =
+                                series.buckets.iter().map(u64::to_string).collect();
+                            let bounded: u64 = series.buckets.iter().sum();
+                            buckets.push((series.count - bounded).to_string());
+                            point["count"] = json!(series.count.to_string());
+                            point["sum"] = json!(series.value);
+                            point["bucketCounts"] = json!(buckets);
+                            point["explicitBounds"] = json!(LATENCY_BUCKETS);
+                        } else {
+                            point["asDouble"] = json!(series.value);
+                        }
+                        point
+                    })
+                    .collect();
+                let data = match family.metric.kind {
+                    MetricKind::Counter => json!({ "sum": {
+                        "aggregationTemporality": 2,
+                        "isMonotonic": true,
+                        "dataPoints": points,
+                    }}),
+                    MetricKind::Gauge => json!({ "gauge": { "dataPoints": points } }),
+                    MetricKind::Histogram => json!({ "histogram": {
+                        "aggregationTemporality": 2,
+                        "dataPoints": points,
+                    }}),
+                };
+                let mut metric = json!({
+                    "name": family.metric.name,
+                    "description": family.metric.help,
+                });
+                if let (Some(metric), Json::Object(data)) = (metric.as_object_mut(), data) {
+                    metric.extend(data);
+                }
+                metric
+            })
+            .collect();
+        if metrics.is_empty() {
+            return Ok(());
+        }
+        let body = json!({
+            "resourceMetrics": [{
+                "resource": self.resource,
+                "scopeMetrics": [{ "scope": { "name": SCOPE }, "metrics": metrics }],
+            }],
+        });
+        self.post("/v1/metrics", body.to_string().as_bytes())
+    }
+
+    fn post(&self, path: &str, body: &[u8]) -> Result<(), OtlpError> {
+        let url = format!("{}{path}", self.endpoint);
+        let headers = [("Content-Type", "application/json".to_string())];
+        match self.transport.post(&url, &headers, body) {
+            Ok(status) if (200..300).contains(&status) => Ok(()),
+            Ok(status) => Err(OtlpError::Rejected(status)),
+            Err(err) => Err(OtlpError::Unavailable(err)),
+        }
+    }
+}
+
+fn encode_span(span: &SpanData) -> Json {
+    let mut encoded = json!({
+        "traceId": format!("{:032x}", span.span_context.trace_id()),
+        "spanId": format!("{:016x}", span.span_context.span_id()),
+        "name": redact(span.name.to_string()),
+        "kind": match span.span_kind {
+            SpanKind::Internal => 1,
+            SpanKind::Server => 2,
+            SpanKind::Client => 3,
+            SpanKind::Producer => 4,
+            SpanKind::Consumer => 5,
+        },
+        "startTimeUnixNano": unix_nanos(span.start_time),
+        "endTimeUnixNano": unix_nanos(span.end_time),
+        "attributes": encode_attributes(&span.attributes),
+        "events": span
+            .events
+            .iter()
+            .map(|event| json!({
+                "timeUnixNano": unix_nanos(event.timestamp),
+                "name": redact(event.name.to_string()),
+                "attributes": encode_attributes(&event.attributes),
+            }))
+            .collect::<Vec<_>>(),
+        "status": match &span.status {
+            Status::Unset => json!({ "code": 0 }),
+            Status::Ok => json!({ "code": 1 }),
+            Status::Error { description } => {
+                json!({ "code": 2, "message": redact(description.to_string()) })
+            }
+        },
+    });
+    if span.parent_span_id != SpanId::INVALID {
+        encoded["parentSpanId"] = json!(format!("{:016x}", span.parent_span_id));
+    }
+    encoded
+}
+
+fn encode_attributes(attributes: &[KeyValue]) -> Vec<Json> {
+    attributes
+        .iter()
+        .map(|attribute| {
+            let value = match &attribute.value {
+                Value::Bool(value) => json!({ "boolValue": value }),
+                Value::I64(value) => json!({ "intValue": value.to_string() }),
+                Value::F64(value) => json!({ "doubleValue": value }),
+                Value::String(value) => json!({ "stringValue": redact(value.to_string()) }),
+                Value::Array(value) => json!({ "stringValue": redact(value.to_string()) }),
+            };
+            json!({ "key": attribute.key.as_str(), "value": value })
+        })
+        .collect()
+}
+
+/// OTLP JSON carries 64-bit timestamps as decimal strings.
+fn unix_nanos(time: SystemTime) -> String {
+    time.duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_nanos()
+        .to_string()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::borrow::Cow;
+
+    use opentelemetry::trace::{SpanContext, TraceFlags, TraceId, TraceState};
+    use opentelemetry_sdk::trace::EvictedQueue;
+    use opentelemetry_sdk::InstrumentationLibrary;
+
+    use crate::metrics::{FLOWS, SUBMIT_DURATION};
+
+    /// Answers with `status` and keeps what was posted.
+    struct Recording {
+        status: u16,
+        posted: Mutex<Vec<(String, Json)>>,
+    }
+
+    impl OtlpTransport for Recording {
+        fn post(&self, url: &str, _: &[(&str, String)], body: &[u8]) -> Result<u16, String> {
+            let body = serde_json::from_slice(body).map_err(|err| err.to_string())?;
+            self.posted.lock().unwrap().push((url.to_string(), body));
+            Ok(self.status)
+        }
+    }
+
+    fn span(name: &str) -> SpanData {
+        SpanData {
+            span_context: SpanContext::new(
+                TraceId::from_bytes(1u128.to_be_bytes()),
+                SpanId::from_bytes(2u64.to_be_bytes()),
+                TraceFlags::SAMPLED,
+                false,
+                TraceState::default(),
+            ),
+            parent_span_id: SpanId::INVALID,
+            span_kind: SpanKind::Internal,
+            name: Cow::Owned(name.to_string()),
+            start_time: UNIX_EPOCH + Duration::from_secs(1),
+            end_time: UNIX_EPOCH + Duration::from_secs(2),
+            attributes: vec![KeyValue::new("recipient", "alice@corp.example")],
+            dropped_attributes_count: 0,
+            events: EvictedQueue::new(0),
+            links: EvictedQueue::new(0),
+            status: Status::Unset,
+            resource: Cow::Owned(Resource::empty()),
+            instrumentation_lib: InstrumentationLibrary::default(),
+        }
+    }
+
+    #[test]
+    fn batches_spans_and_posts_metrics_with_resource_attributes() {
+        let config = TelemetryConfig {
+            endpoint: Some("https://collector.example/v1/traces".into()),
+            resource_attributes: vec!["deployment.environment=prod".into()],
+            batch_size: 2,
+            ..TelemetryConfig::default()
+        };
+        let transport = Arc::new(Recording {
+            status: 200,
+            posted: Mutex::new(Vec::new()),
+        });
+        let exporter = OtlpExporter::with_transport(&config, transport.clone()).unwrap();
+
+        assert!(!exporter.queue(&span("queue.submit")));
+        assert!(exporter.queue(&span("queue.submit")));
+        exporter.queue(&span("gateway.outbound"));
+        assert!(exporter.export_spans().is_empty());
+
+        let registry = MetricsRegistry::new();
+        registry.increment(&FLOWS, &[("flow", "queue.submit"), ("outcome", "ok")]);
+        registry.observe(&SUBMIT_DURATION, &[("transport", "mock")], 60.0);
+        exporter.export_metrics(&registry).unwrap();
+
+        let posted = transport.posted.lock().unwrap();
+        let urls: Vec<&str> = posted.iter().map(|(url, _)| url.as_str()).collect();
+        assert_eq!(
+            urls,
+            [
+                "https://collector.example/v1/traces",
+                "https://collector.example/v1/traces",
+                "https://collector.example/v1/metrics",
+            ]
+        );
+        let traces = &posted[0].1["resourceSpans"][0];
+        let attributes = traces["resource"]["attributes"].to_string();
+        assert!(attributes.contains("\"service.name\""));
+        assert!(attributes.contains("\"deployment.environment\""));
+        let spans = traces["scopeSpans"][0]["spans"].as_array().unwrap();
+        assert_eq!(spans.len(), 2);
+        assert_eq!(spans[0]["traceId"], format!("{:032x}", 1));
+        assert_eq!(spans[0]["startTimeUnixNano"], "1000000000");
+        assert_eq!(
+            spans[0]["attributes"][0]["value"]["stringValue"],
+            "[REDACTED]"
+        );
+
+        let metrics = &posted[2].1["resourceMetrics"][0]["scopeMetrics"][0]["metrics"];
+        assert_eq!(metrics[0]["name"], "x400_flows_total");
+        assert_eq!(metrics[0]["sum"]["dataPoints"][0]["asDouble"], 1.0);
+        let histogram = &metrics[1]["histogram"]["dataPoints"][0];
+        assert_eq!(histogram["count"], "1");
+        assert_eq!(histogram["bucketCounts"].as_array().unwrap().len(), 13);
+        assert_eq!(histogram["bucketCounts"][12], "1");
+    }
+}
@@ -0,0 +1,233 @@
+//! Signing keys on smartcards and HSMs.
+//!
+//! With `security.smime.keySource=pkcs11` the S/MIME key never leaves the
+//! token. OpenSSL's `pkcs11` provider is loaded with the token's module from
+//! `security.smime.pkcs11.module`, passed as the provider's
+//! `pkcs11-module-path` parameter through an in-memory OpenSSL configuration
+//! rather than the process environment, and the key is opened through the
+//! provider as an RFC 7512 `pkcs11:` URI built from the slot, the key label
+//! and the PIN. The handle behaves like any other [`PKey`], so CMS signing
+//! and decryption run on the token.
+
+use std::ffi::CString;
+use std::os::raw::{c_char, c_int, c_long, c_ulong, c_void};
+use std::ptr;
+
+use foreign_types::ForeignType;
+use openssl::pkey::{PKey, Private};
+use openssl::provider::Provider;
+
+use crate::config::Pkcs11Config;
+
+/// Provider parameter the `pkcs11` provider reads the token module path from.
+const MODULE_PARAM: &str = "pkcs11-module-path";
+
+/// `OSSL_STORE_INFO_PKEY` from `openssl/store.h`.
+const STORE_INFO_PKEY: c_int = 4;
+
+#[allow(non_camel_case_types)]
+enum OSSL_STORE_CTX {}
+#[allow(non_camel_case_types)]
+enum OSSL_STORE_INFO {}
+
+// The OSSL_STORE API and the configuration loaders are part of libcrypto,
+// which openssl-sys links, but have no bindings there.
+extern "C" {
+    fn NCONF_load_bio(
+        conf: *mut openssl_sys::CONF,
+        bio: *mut openssl_sys::BIO,
+        eline: *mut c_long,
+    ) -> c_int;
+    fn CONF_modules_load(
+        conf: *const openssl_sys::CONF,
+        appname: *const c_char,
+        flags: c_ulong,
+    ) -> c_int;
+    fn OSSL_STORE_open(
+        uri: *const c_char,
+        ui_method: *const c_void,
+        ui_data: *mut c_void,
+        post_process: *const c_void,
+        post_process_data: *mut c_void,
+    ) -> *mut OSSL_STORE_CTX;
+    fn OSSL_STORE_load(ctx: *mut OSSL_STORE_CTX) -> *mut OSSL_STORE_INFO;
+    fn OSSL_STORE_eof(ctx: *mut OSSL_STORE_CTX) -> c_int;
+    fn OSSL_STORE_close(ctx: *mut OSSL_STORE_CTX) -> c_int;
+    fn OSSL_STORE_INFO_get_type(info: *const OSSL_STORE_INFO) -> c_int;
+    fn OSSL_STORE_INFO_get1_PKEY(info: *const OSSL_STORE_INFO) -> *mut openssl_sys::EVP_PKEY;
+    fn OSSL_STORE_INFO_free(info: *mut OSSL_STORE_INFO);
+}
+
+/// A private key on a token, with the provider that serves it. The
+/// provider must outlive the key.
+pub struct TokenKey {
+    pub key: PKey<Private>,
+    pub provider: Provider,
+}
+
+/// Open the private key `config` describes, logging in with `pin`.
+pub fn load_key(config: &Pkcs11Config, pin: Option<&str>) -> Result<TokenKey, String> {
+    configure_provider(&config.module)?;
+    let provider = Provider::try_load(None, "pkcs11", true)
+        .map_err(|err| format!("pkcs11 provider not loaded: {err}"))?;
+    let uri = CString::new(key_uri(config, pin)).map_err(|err| err.to_string())?;
+    // SAFETY: the context is closed on every path and each info is freed
+    // after use; `get1_PKEY` returns an owned reference handed to `PKey`.
+    let key = unsafe {
+        let store = OSSL_STORE_open(
+            uri.as_ptr(),
+            ptr::null(),
+            ptr::null_mut(),
+            ptr::null(),
+            ptr::null_mut(),
+        );
+        if store.is_null() {
+            return Err(format!(
+                "token not opened: {}",
+                openssl::error::ErrorStack::get()
+            ));
+        }
+        let mut key = None;
+        while key.is_none() && OSSL_STORE_eof(store) == 0 {
+            let info = OSSL_STORE_load(store);
+            if info.is_null() {
+                // End of the listing or an error: either way nothing more.
+                break;
+            }
+            if OSSL_STORE_INFO_get_type(info) == STORE_INFO_PKEY {
+                let pkey = OSSL_STORE_INFO_get1_PKEY(info);
+                if !pkey.is_null() {
+                    key = Some(PKey::<Private>::from_ptr(pkey));
+                }
+            }
+            OSSL_STORE_INFO_free(info);
+        }
+        OSSL_STORE_close(store);
+        key
+    };
+    let key = key.ok_or("no private key matches the slot and label")?;
+    Ok(TokenKey { key, provider })
+}
+
+/// Register the `pkcs11` provider with `module` as its token module in the
+/// default library context, the way an `openssl.cnf` provider section would.
+fn configure_provider(module: &str) -> Result<(), String> {
+    let text = provider_config(module)?;
+    let len = c_int::try_from(text.len()).map_err(|err| err.to_string())?;
+    // SAFETY: the BIO reads `text`, which outlives it, and both the BIO and
+    // the parsed configuration are freed on every path.
+    unsafe {
+        let bio = openssl_sys::BIO_new_mem_buf(text.as_ptr().cast(), len);
+        if bio.is_null() {
+            return Err("pkcs11 provider configuration not buffered".into());
+        }
+        let conf = openssl_sys::NCONF_new(ptr::null_mut());
+        if conf.is_null() {
+            openssl_sys::BIO_free_all(bio);
+            return Err("pkcs11 provider configuration not allocated".into());
+        }
+        let mut line: c_long = 0;
+        let loaded = NCONF_load_bio(conf, bio, &mut line) == 1
+            && CONF_modules_load(conf, ptr::null(), 0) == 1;
+        openssl_sys::NCONF_free(conf);
+        openssl_sys::BIO_free_all(bio);
+        if !loaded {
+            return Err(format!(
+                "pkcs11 provider not configured: {}",
+                openssl::error::ErrorStack::get()
+            ));
+        }
+    }
+    Ok(())
+}
+
+/// OpenSSL configuration activating the `pkcs11` provider with `module`,
+/// next to the default provider that explicit activation would otherwise
+/// switch off.
+fn provider_config(module: &str) -> Result<String, String> {
+    if module.is_empty() || module.contains(['\0', '\n', '\r']) {
+        return Err(format!("invalid pkcs11 module path {module:?}"));
+    }
+    let quoted = module.replace('\\', "\\\\").replace('"', "\\\"");
+    Ok(format!(
+        "openssl_conf = openssl_init\n\
+         [openssl_init]\n\
+         providers = provider_sect\n\
+         [provider_sect]\n\
+         default = default_sect\n\
+         pkcs11 = pkcs11_sect\n\
+         [default_sect]\n\
+         activate = 1\n\
+         [pkcs11_sect]\n\
+         {MODULE_PARAM} = \"{quoted}\"\n\
+         activate = 1\n"
+    ))
+}
+
+/// `pkcs11:` URI selecting the private key, with the PIN as `pin-value`.
+pub(crate) fn key_uri(config: &Pkcs11Config, pin: Option<&str>) -> String {
+    let mut path = Vec::new();
+    if let Some(slot) = config.slot {
+        path.push(format!("slot-id={slot}"));
+    }
+    if let Some(label) = &config.key_label {
+        path.push(format!("object={}", encode(label)));
+    }
+    path.push("type=private".into());
+    let mut uri = format!("pkcs11:{}", path.join(";"));
+    if let Some(pin) = pin {
+        uri.push_str(&format!("?pin-value={}", encode(pin)));
+    }
+    uri
+}
+
+/// Percent-encode everything but RFC 3986 unreserved characters.
+fn encode(value: &str) -> String {
+    value
+        .bytes()
+        .map(|byte| match byte {
+            b'A'..=b'Z' | b'a'..=b'z' | b'0'..=b'9' | b'-' | b'.' | b'_' | b'~' => {
+                char::from(byte).to_string()
+            }
+            _ => format!("%{byte:02X}"),
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn builds_key_uris() {
+        let config = Pkcs11Config {
+            module: "/usr/lib/softhsm/libsofthsm2.so".into(),
+            slot: Some(3),
+            key_label: Some("S/MIME signing".into()),
+            pin_ref: None,
+        };
+        assert_eq!(
+            key_uri(&config, Some("12;34")),
+            "pkcs11:slot-id=3;object=S%2FMIME%20signing;type=private?pin-value=12%3B34"
+        );
+        let bare = Pkcs11Config::default();
+        assert_eq!(key_uri(&bare, None), "pkcs11:type=private");
+    }
+
+    #[test]
+    fn quotes_the_module_path_in_the_provider_config() {
+        let config = provider_config(r#"C:\tokens\"odd"$HOME.dll"#).unwrap();
+        assert!(config.contains(r#"pkcs11-module-path = "C:\\tokens\\\"odd\"$HOME.dll""#));
+        assert!(provider_config("/lib/token.so\nactivate = 0").is_err());
+        assert!(provider_config("").is_err());
+    }
+
+    #[test]
+    fn reports_tokens_that_cannot_be_opened() {
+        let config = Pkcs11Config {
+            module: "/nonexistent/libpkcs11.so".into(),
+            ..Pkcs11Config::default()
+        };
+        assert!(load_key(&config, Some("1234")).is_err());
+    }
+}
@@ -1,33 +1,144 @@
-use std::collections::VecDeque;
+use std::collections::{BTreeMap, HashSet, VecDeque};
+use std::fs;
+use std::io;
+use std::path::Path;
+use std::sync::atomic::{AtomicBool, Ordering};
 use std::sync::{Arc, Mutex};
+use std::time::Duration;
 
-use crate::models::MessageId;
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use thiserror::Error;
+use tracing::info;
+
+use crate::clock::SharedClock;
+use crate::config::QueueConfig;
+use crate::models::{MessageId, MessagePriority, QueueEntry, QueueEntryStatus};
+use crate::store::StoreManager;
 use crate::telemetry::TelemetryManager;
 
+#[derive(Clone, Debug)]
+struct Queued {
+    id: MessageId,
+    priority: MessagePriority,
+    enqueued_at: DateTime<Utc>,
+    not_before: DateTime<Utc>,
+    deferred_until: Option<DateTime<Utc>>,
+    held: bool,
+}
+
+impl Queued {
+    /// Scheduled messages start aging once their send-at time has passed.
+    fn waiting_since(&self) -> DateTime<Utc> {
+        self.deferred_until
+            .map_or(self.enqueued_at, |until| until.max(self.enqueued_at))
+    }
+}
+
+/// One row of `GET /queue`, in the order the scheduler will dispatch.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct QueuedMessage {
+    pub message_id: MessageId,
+    pub priority: MessagePriority,
+    /// Priority after aging; never above `high`.
+    pub effective_priority: MessagePriority,
+    pub enqueued_at: DateTime<Utc>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub deferred_until: Option<DateTime<Utc>>,
+    /// False while a retry or the send-at time is still ahead.
+    pub due: bool,
+    #[serde(default)]
+    pub held: bool,
+}
+
+#[derive(Debug, Error, PartialEq, Eq)]
+pub enum QueueError {
+    #[error("message {0} is not queued")]
+    NotQueued(MessageId),
+}
+
+impl QueueError {
+    pub fn status(&self) -> u16 {
+        match self {
+            Self::NotQueued(_) => 404,
+        }
+    }
+}
+
+/// Body of `GET /queue`.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct QueueStats {
+    pub paused: bool,
+    pub depth: usize,
+    pub held: usize,
+    pub in_flight: usize,
+    /// Seconds the longest-waiting queued message has been in the queue.
+    pub oldest_age_secs: Option<i64>,
+    /// Queued messages per store folder; only populated when the queue is
+    /// backed by a store.
+    pub folders: BTreeMap<String, usize>,
+    pub messages: Vec<QueuedMessage>,
+}
+
 #[derive(Clone)]
 pub struct QueueManager {
-    inner: Arc<Mutex<VecDeque<MessageId>>>,
+    inner: Arc<Mutex<VecDeque<Queued>>>,
+    in_flight: Arc<Mutex<HashSet<MessageId>>>,
+    paused: Arc<AtomicBool>,
     telemetry: Option<TelemetryManager>,
+    store: Option<StoreManager>,
+    clock: SharedClock,
+    aging: Duration,
 }
 
 impl QueueManager {
     pub fn new() -> Self {
         Self {
             inner: Arc::new(Mutex::new(VecDeque::new())),
+            in_flight: Arc::new(Mutex::new(HashSet::new())),
+            paused: Arc::new(AtomicBool::new(false)),
             telemetry: None,
+            store: None,
+            clock: SharedClock::default(),
+            aging: Duration::from_millis(QueueConfig::default().aging_ms),
         }
     }
 
     pub fn with_telemetry(telemetry: TelemetryManager) -> Self {
         Self {
-            inner: Arc::new(Mutex::new(VecDeque::new())),
             telemetry: Some(telemetry),
+            ..Self::new()
         }
     }
 
+    /// Mirror every queue change into the store's `queue` table.
+    pub fn with_store(mut self, store: StoreManager) -> Self {
+        self.store = Some(store);
+        self
+    }
+
+    pub fn with_clock(mut self, clock: SharedClock) -> Self {
+        self.clock = clock;
+        self
+    }
+
+    /// How long a message waits before it is promoted by one priority level.
+    /// Zero disables aging.
+    pub fn with_aging(mut self, aging: Duration) -> Self {
+        self.aging = aging;
+        self
+    }
+
     pub fn enqueue(&self, id: MessageId) {
+        self.enqueue_with_priority(id, MessagePriority::Normal);
+    }
+
+    pub fn enqueue_with_priority(&self, id: MessageId, priority: MessagePriority) {
+        let queued = self.persist_pending(&id, Some(priority), self.clock.now(), None);
         if let Ok(mut queue) = self.inner.lock() {
-            queue.push_back(id);
+            queue.push_back(queued);
             if let Some(telemetry) = &self.telemetry {
                 telemetry.record_flow(
                     "queue.enqueue",
@@ -39,9 +150,101 @@ impl QueueManager {
         }
     }
 
+    /// Queue several messages at once: the queue table is written once and the
+    /// scheduler sees either none or all of them. Items with a send-at time
+    /// go to the scheduled outbox.
+    pub fn enqueue_all(&self, items: Vec<(MessageId, MessagePriority, Option<DateTime<Utc>>)>) {
+        let now = self.clock.now();
+        let (queued, entries): (Vec<_>, Vec<_>) = items
+            .iter()
+            .map(|(id, priority, until)| {
+                self.prepare(id, Some(*priority), until.unwrap_or(now), *until)
+            })
+            .unzip();
+        if let Some(store) = &self.store {
+            store.put_queue_entries(entries.into_iter().flatten().collect());
+        }
+        if let Ok(mut queue) = self.inner.lock() {
+            queue.extend(queued);
+            if let Some(telemetry) = &self.telemetry {
+                telemetry.record_flow(
+                    "queue.enqueue_batch",
+                    std::time::Duration::from_millis(0),
+                    true,
+                    queue.len(),
+                );
+            }
+        }
+    }
+
+    /// Hold a message in the scheduled outbox until `until`, then queue it at `priority`.
+    pub fn enqueue_deferred(&self, id: MessageId, priority: MessagePriority, until: DateTime<Utc>) {
+        let queued = self.persist_pending(&id, Some(priority), until, Some(until));
+        if let Ok(mut queue) = self.inner.lock() {
+            queue.push_back(queued);
+        }
+    }
+
+    /// Withdraw a message that has not been handed to the transport yet.
+    ///
+    /// Returns `false` if it is not queued, e.g. already in flight or sent.
+    pub fn cancel(&self, id: &MessageId) -> bool {
+        let removed = self
+            .inner
+            .lock()
+            .map(|mut queue| {
+                let before = queue.len();
+                queue.retain(|queued| &queued.id != id);
+                queue.len() != before
+            })
+            .unwrap_or(false);
+        if removed {
+            self.complete(id);
+        }
+        removed
+    }
+
+    /// Remove scheduled messages whose send-at time has arrived, marking them in flight.
+    pub fn release_deferred(&self) -> Vec<MessageId> {
+        if self.is_paused() {
+            return Vec::new();
+        }
+        let now = self.clock.now();
+        let released: Vec<MessageId> = self
+            .inner
+            .lock()
+            .map(|mut queue| {
+                let (due, rest): (Vec<_>, Vec<_>) = queue.drain(..).partition(|queued| {
+                    queued.deferred_until.is_some() && !queued.held && queued.not_before <= now
+                });
+                queue.extend(rest);
+                due.into_iter().map(|queued| queued.id).collect()
+            })
+            .unwrap_or_default();
+        for id in &released {
+            self.mark_in_flight(id, now);
+        }
+        released
+    }
+
+    /// Take the highest-priority message that is due, marking it in flight.
+    ///
+    /// Aged messages compete at their effective priority; ties go to whichever
+    /// was queued first. Messages whose `next_attempt_at` lies in the future
+    /// stay queued, as do held messages and everything while the queue is paused.
     pub fn dequeue(&self) -> Option<MessageId> {
-        self.inner.lock().ok().and_then(|mut queue| {
-            let item = queue.pop_front();
+        if self.is_paused() {
+            return None;
+        }
+        let now = self.clock.now();
+        let item = self.inner.lock().ok().and_then(|mut queue| {
+            let position = self
+                .schedule(&queue, now)
+                .into_iter()
+                .find(|&index| !queue[index].held && queue[index].not_before <= now);
+            let item = position
+                .and_then(|position| queue.remove(position))
+                .map(|queued| queued.id);
             if let Some(telemetry) = &self.telemetry {
                 telemetry.record_flow(
                     "queue.dequeue",
@@ -51,14 +254,58 @@ impl QueueManager {
                 );
             }
             item
-        })
+        })?;
+        self.mark_in_flight(&item, now);
+        Some(item)
+    }
+
+    fn mark_in_flight(&self, id: &MessageId, now: DateTime<Utc>) {
+        if let Ok(mut in_flight) = self.in_flight.lock() {
+            in_flight.insert(id.clone());
+        }
+        if let Some(store) = &self.store {
+            let mut entry = store
+                .queue_entry(id)
+                .unwrap_or_else(|| pending_entry(id, MessagePriority::Normal, now));
+            entry.status = QueueEntryStatus::InFlight;
+            entry.attempts += 1;
+            store.put_queue_entry(entry);
+        }
+    }
+
+    /// The transport accepted the message; drop it from the queue and drop
+    /// its queue row.
+    pub fn complete(&self, id: &MessageId) {
+        if let Ok(mut queue) = self.inner.lock() {
+            queue.retain(|queued| &queued.id != id);
+        }
+        if let Ok(mut in_flight) = self.in_flight.lock() {
+            in_flight.remove(id);
+        }
+        if let Some(store) = &self.store {
+            store.remove_queue_entry(id);
+        }
+    }
+
+    /// Put a message back for another attempt at `at`.
+    pub fn retry_at(&self, id: MessageId, at: DateTime<Utc>) {
+        if let Ok(mut in_flight) = self.in_flight.lock() {
+            in_flight.remove(&id);
+        }
+        let queued = self.persist_pending(&id, None, at, None);
+        if let Ok(mut queue) = self.inner.lock() {
+            queue.push_back(queued);
+        }
     }
 
     pub fn seed(&self, ids: Vec<MessageId>) {
+        let now = self.clock.now();
+        let seeded: Vec<_> = ids
+            .iter()
+            .map(|id| self.persist_pending(id, None, now, None))
+            .collect();
         if let Ok(mut queue) = self.inner.lock() {
-            for id in ids {
-                queue.push_back(id);
-            }
+            queue.extend(seeded);
             if let Some(telemetry) = &self.telemetry {
                 telemetry.record_flow(
                     "queue.seed",
@@ -70,11 +317,285 @@ impl QueueManager {
         }
     }
 
-    pub fn pending(&self) -> Vec<MessageId> {
-        self.inner
+    /// Reload the in-memory queue from the store's `queue` table after a restart.
+    ///
+    /// Rows still marked in flight were interrupted mid-send and go back to pending.
+    pub fn rebuild(&self) -> usize {
+        let Some(store) = &self.store else {
+            return 0;
+        };
+        let entries = store.queue_entries();
+        let Ok(mut queue) = self.inner.lock() else {
+            return 0;
+        };
+        queue.clear();
+        if let Ok(mut in_flight) = self.in_flight.lock() {
+            in_flight.clear();
+        }
+        for mut entry in entries {
+            if entry.status == QueueEntryStatus::InFlight {
+                entry.status = QueueEntryStatus::Pending;
+                store.put_queue_entry(entry.clone());
+            }
+            queue.push_back(Queued {
+                id: entry.message_id,
+                priority: entry.priority,
+                enqueued_at: entry.enqueued_at,
+                not_before: entry.next_attempt_at,
+                deferred_until: entry.deferred_until,
+                held: entry.held,
+            });
+        }
+        queue.len()
+    }
+
+    /// Keep a queued message out of dispatch until [`QueueManager::release`].
+    pub fn hold(&self, id: &MessageId) -> Result<(), QueueError> {
+        self.set_held(id, true)
+    }
+
+    pub fn release(&self, id: &MessageId) -> Result<(), QueueError> {
+        self.set_held(id, false)
+    }
+
+    fn set_held(&self, id: &MessageId, held: bool) -> Result<(), QueueError> {
+        let found = self
+            .inner
             .lock()
-            .map(|queue| queue.iter().cloned().collect())
-            .unwrap_or_default()
+            .map(|mut queue| {
+                queue
+                    .iter_mut()
+                    .find(|queued| &queued.id == id)
+                    .map(|queued| queued.held = held)
+                    .is_some()
+            })
+            .unwrap_or(false);
+        if !found {
+            return Err(QueueError::NotQueued(id.clone()));
+        }
+        if let Some(store) = &self.store {
+            if let Some(mut entry) = store.queue_entry(id) {
+                entry.held = held;
+                store.put_queue_entry(entry);
+            }
+        }
+        info!(target = "queue", message = %id, held, "queue hold changed");
+        Ok(())
+    }
+
+    /// Stop handing messages to the transport, e.g. for a maintenance window.
+    /// Submissions keep queueing; messages already in flight finish normally.
+    pub fn pause(&self) {
+        if !self.paused.swap(true, Ordering::SeqCst) {
+            info!(target = "queue", "outbound queue paused");
+        }
+    }
+
+    pub fn resume(&self) {
+        if self.paused.swap(false, Ordering::SeqCst) {
+            info!(target = "queue", "outbound queue resumed");
+        }
+    }
+
+    pub fn is_paused(&self) -> bool {
+        self.paused.load(Ordering::SeqCst)
+    }
+
+    pub fn stats(&self) -> QueueStats {
+        let now = self.clock.now();
+        let messages = self.order();
+        let mut folders = BTreeMap::new();
+        if let Some(store) = &self.store {
+            for queued in &messages {
+                if let Some(message) = store.get(&queued.message_id) {
+                    *folders.entry(message.envelope.folder).or_insert(0) += 1;
+                }
+            }
+        }
+        QueueStats {
+            paused: self.is_paused(),
+            depth: messages.len(),
+            held: messages.iter().filter(|queued| queued.held).count(),
+            in_flight: self.in_flight.lock().map(|ids| ids.len()).unwrap_or(0),
+            oldest_age_secs: messages
+                .iter()
+                .map(|queued| queued.enqueued_at)
+                .min()
+                .map(|oldest| (now - oldest).num_seconds()),
+            folders,
+            messages,
+        }
+    }
+
+    /// Queued ids in dispatch order.
+    pub fn pending(&self) -> Vec<MessageId> {
+        self.order()
+            .into_iter()
+            .map(|queued| queued.message_id)
+            .collect()
+    }
+
+    /// Effective dispatch order as served by `GET /queue`.
+    pub fn order(&self) -> Vec<QueuedMessage> {
+        let now = self.clock.now();
+        let Ok(queue) = self.inner.lock() else {
+            return Vec::new();
+        };
+        self.schedule(&queue, now)
+            .into_iter()
+            .map(|index| {
+                let queued = &queue[index];
+                QueuedMessage {
+                    message_id: queued.id.clone(),
+                    priority: queued.priority,
+                    effective_priority: self.effective_priority(queued, now),
+                    enqueued_at: queued.enqueued_at,
+                    deferred_until: queued.deferred_until,
+                    due: queued.not_before <= now,
+                    held: queued.held,
+                }
+            })
+            .collect()
+    }
+
+    /// Messages whose send-at time is still ahead, soonest first, for the
+    /// "outbox (scheduled)" view.
+    pub fn scheduled(&self) -> Vec<QueuedMessage> {
+        let now = self.clock.now();
+        let mut scheduled: Vec<_> = self
+            .order()
+            .into_iter()
+            .filter(|queued| queued.deferred_until.is_some_and(|until| until > now))
+            .collect();
+        scheduled.sort_by_key(|queued| queued.deferred_until);
+        scheduled
+    }
+
+    /// Indices into `queue` sorted by effective priority, oldest first within a level.
+    fn schedule(&self, queue: &VecDeque<Queued>, now: DateTime<Utc>) -> Vec<usize> {
+        let mut indices: Vec<usize> = (0..queue.len()).collect();
+        indices.sort_by_key(|&index| {
+            let queued = &queue[index];
+            (
+                std::cmp::Reverse(rank(self.effective_priority(queued, now))),
+                queued.enqueued_at,
+            )
+        });
+        indices
+    }
+
+    fn effective_priority(&self, queued: &Queued, now: DateTime<Utc>) -> MessagePriority {
+        let waited = (now - queued.waiting_since()).to_std().unwrap_or_default();
+        let aging = self.aging.as_millis();
+        let promotions = waited.as_millis().checked_div(aging).unwrap_or(0);
+        match u128::from(rank(queued.priority)) + promotions {
+            0 => MessagePriority::Low,
+            1 => MessagePriority::Normal,
+            _ => MessagePriority::High,
+        }
+    }
+
+    /// Upsert the pending row; an existing row keeps its priority and send-at
+    /// time unless new ones are given.
+    fn persist_pending(
+        &self,
+        id: &MessageId,
+        priority: Option<MessagePriority>,
+        next_attempt_at: DateTime<Utc>,
+        deferred_until: Option<DateTime<Utc>>,
+    ) -> Queued {
+        let (queued, entry) = self.prepare(id, priority, next_attempt_at, deferred_until);
+        if let (Some(store), Some(entry)) = (&self.store, entry) {
+            store.put_queue_entry(entry);
+        }
+        queued
+    }
+
+    /// Build the in-memory item and, when backed by a store, the row to write.
+    fn prepare(
+        &self,
+        id: &MessageId,
+        priority: Option<MessagePriority>,
+        next_attempt_at: DateTime<Utc>,
+        deferred_until: Option<DateTime<Utc>>,
+    ) -> (Queued, Option<QueueEntry>) {
+        let now = self.clock.now();
+        let Some(store) = &self.store else {
+            let queued = Queued {
+                id: id.clone(),
+                priority: priority.unwrap_or_default(),
+                enqueued_at: now,
+                not_before: next_attempt_at,
+                deferred_until,
+                held: false,
+            };
+            return (queued, None);
+        };
+        let mut entry = store
+            .queue_entry(id)
+            .unwrap_or_else(|| pending_entry(id, priority.unwrap_or_default(), now));
+        entry.status = QueueEntryStatus::Pending;
+        entry.next_attempt_at = next_attempt_at;
+        if let Some(priority) = priority {
+            entry.priority = priority;
+        }
+        if deferred_until.is_some() {
+            entry.deferred_until = deferred_until;
+        }
+        let queued = Queued {
+            id: id.clone(),
+            priority: entry.priority,
+            enqueued_at: entry.enqueued_at,
+            not_before: next_attempt_at,
+            deferred_until: entry.deferred_until,
+            held: entry.held,
+        };
+        (queued, Some(entry))
+    }
+}
+
+fn rank(priority: MessagePriority) -> u8 {
+    match priority {
+        MessagePriority::Low => 0,
+        MessagePriority::Normal => 1,
+        MessagePriority::High => 2,
+    }
+}
+
+fn pending_entry(id: &MessageId, priority: MessagePriority, now: DateTime<Utc>) -> QueueEntry {
+    QueueEntry {
+        message_id: id.clone(),
+        status: QueueEntryStatus::Pending,
+        attempts: 0,
+        next_attempt_at: now,
+        priority,
+        enqueued_at: now,
+        deferred_until: None,
+        held: false,
+    }
+}
+
+impl QueueManager {
+    /// Write the pending ids to `path` as a JSON array, returning how many were saved.
+    pub fn snapshot_to(&self, path: &Path) -> io::Result<usize> {
+        let pending: Vec<String> = self.pending().into_iter().map(|id| id.0).collect();
+        let json = serde_json::to_vec_pretty(&pending).map_err(io::Error::other)?;
+        fs::write(path, json)?;
+        Ok(pending.len())
+    }
+
+    /// Append the ids saved by [`QueueManager::snapshot_to`]; a missing file restores nothing.
+    pub fn restore_from(&self, path: &Path) -> io::Result<usize> {
+        let bytes = match fs::read(path) {
+            Ok(bytes) => bytes,
+            Err(err) if err.kind() == io::ErrorKind::NotFound => return Ok(0),
+            Err(err) => return Err(err),
+        };
+        let ids: Vec<String> = serde_json::from_slice(&bytes)
+            .map_err(|err| io::Error::new(io::ErrorKind::InvalidData, err))?;
+        let count = ids.len();
+        self.seed(ids.into_iter().map(MessageId).collect());
+        Ok(count)
     }
 }
 
@@ -83,3 +604,157 @@ impl Default for QueueManager {
         Self::new()
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::clock::ManualClock;
+    use chrono::TimeZone;
+
+    #[test]
+    fn queue_table_survives_restart() {
+        let dir = tempfile::tempdir().unwrap();
+        let table = dir.path().join("messages.queue.json");
+        let start = Utc.with_ymd_and_hms(2024, 2, 1, 8, 0, 0).unwrap();
+        let clock = SharedClock::new(ManualClock::new(start));
+        let open = || {
+            let store = StoreManager::new().with_queue_table(&table);
+            QueueManager::new()
+                .with_store(store.clone())
+                .with_clock(clock.clone())
+        };
+
+        let queue = open();
+        queue.enqueue(MessageId("msg-1".into()));
+        queue.enqueue(MessageId("msg-2".into()));
+        queue.enqueue(MessageId("msg-3".into()));
+        assert_eq!(queue.dequeue(), Some(MessageId("msg-1".into())));
+        assert_eq!(queue.dequeue(), Some(MessageId("msg-2".into())));
+        queue.complete(&MessageId("msg-2".into()));
+        let later = start + chrono::Duration::minutes(5);
+        assert_eq!(queue.dequeue(), Some(MessageId("msg-3".into())));
+        queue.retry_at(MessageId("msg-3".into()), later);
+        assert_eq!(queue.dequeue(), None);
+        drop(queue);
+
+        // msg-1 was in flight when the process died, msg-3 waits for its retry.
+        let restarted = open();
+        assert_eq!(restarted.rebuild(), 2);
+        let store = StoreManager::new().with_queue_table(&table);
+        let rows = store.queue_entries();
+        assert_eq!(rows[0].message_id, MessageId("msg-1".into()));
+        assert_eq!(rows[0].status, QueueEntryStatus::Pending);
+        assert_eq!(rows[0].attempts, 1);
+        assert_eq!(rows[1].next_attempt_at, later);
+        assert_eq!(restarted.dequeue(), Some(MessageId("msg-1".into())));
+        assert_eq!(restarted.dequeue(), None);
+    }
+
+    #[test]
+    fn high_priority_jumps_ahead_until_low_ages() {
+        let start = Utc.with_ymd_and_hms(2024, 2, 1, 8, 0, 0).unwrap();
+        let clock = ManualClock::new(start);
+        let queue = QueueManager::new()
+            .with_clock(SharedClock::new(clock.clone()))
+            .with_aging(Duration::from_secs(60));
+
+        queue.enqueue_with_priority(MessageId("low".into()), MessagePriority::Low);
+        queue.enqueue(MessageId("normal".into()));
+        queue.enqueue_with_priority(MessageId("high".into()), MessagePriority::High);
+        assert_eq!(
+            queue.pending(),
+            vec![
+                MessageId("high".into()),
+                MessageId("normal".into()),
+                MessageId("low".into()),
+            ]
+        );
+        assert_eq!(queue.dequeue(), Some(MessageId("high".into())));
+
+        // Two aging intervals later the low message competes as high and,
+        // being older, beats fresh urgent mail.
+        clock.advance(chrono::Duration::minutes(2));
+        queue.enqueue_with_priority(MessageId("urgent".into()), MessagePriority::High);
+        let order = queue.order();
+        assert_eq!(order[0].message_id, MessageId("low".into()));
+        assert_eq!(order[0].priority, MessagePriority::Low);
+        assert_eq!(order[0].effective_priority, MessagePriority::High);
+        assert_eq!(queue.dequeue(), Some(MessageId("low".into())));
+        assert_eq!(queue.dequeue(), Some(MessageId("normal".into())));
+        assert_eq!(queue.dequeue(), Some(MessageId("urgent".into())));
+    }
+
+    #[test]
+    fn holds_deferred_messages_until_send_at() {
+        let dir = tempfile::tempdir().unwrap();
+        let start = Utc.with_ymd_and_hms(2024, 2, 1, 8, 0, 0).unwrap();
+        let clock = ManualClock::new(start);
+        let store = StoreManager::new().with_queue_table(dir.path().join("queue.json"));
+        let queue = QueueManager::new()
+            .with_store(store.clone())
+            .with_clock(SharedClock::new(clock.clone()));
+
+        let send_at = start + chrono::Duration::hours(2);
+        queue.enqueue_deferred(MessageId("later".into()), MessagePriority::High, send_at);
+        queue.enqueue_deferred(
+            MessageId("cancelled".into()),
+            MessagePriority::Normal,
+            start + chrono::Duration::hours(1),
+        );
+        queue.enqueue(MessageId("now".into()));
+
+        let scheduled = queue.scheduled();
+        assert_eq!(scheduled.len(), 2);
+        assert_eq!(scheduled[0].message_id, MessageId("cancelled".into()));
+        assert_eq!(scheduled[1].deferred_until, Some(send_at));
+        assert_eq!(queue.dequeue(), Some(MessageId("now".into())));
+        assert_eq!(queue.dequeue(), None);
+
+        assert!(queue.cancel(&MessageId("cancelled".into())));
+        assert!(
+            !queue.cancel(&MessageId("now".into())),
+            "already dispatched"
+        );
+        assert!(store.queue_entry(&MessageId("cancelled".into())).is_none());
+
+        clock.advance(chrono::Duration::hours(2));
+        assert!(queue.scheduled().is_empty());
+        assert_eq!(queue.dequeue(), Some(MessageId("later".into())));
+    }
+
+    #[test]
+    fn operators_can_hold_messages_and_pause_dispatch() {
+        let dir = tempfile::tempdir().unwrap();
+        let start = Utc.with_ymd_and_hms(2024, 2, 1, 8, 0, 0).unwrap();
+        let clock = ManualClock::new(start);
+        let store = StoreManager::new().with_queue_table(dir.path().join("queue.json"));
+        let queue = QueueManager::new()
+            .with_store(store.clone())
+            .with_clock(SharedClock::new(clock.clone()));
+        queue.enqueue(MessageId("first".into()));
+        clock.advance(chrono::Duration::minutes(10));
+        queue.enqueue(MessageId("second".into()));
+        queue.enqueue(MessageId("third".into()));
+
+        queue.hold(&MessageId("first".into())).unwrap();
+        assert!(store.queue_entry(&MessageId("first".into())).unwrap().held);
+        assert_eq!(
+            queue.hold(&MessageId("unknown".into())),
+            Err(QueueError::NotQueued(MessageId("unknown".into())))
+        );
+        assert_eq!(queue.dequeue(), Some(MessageId("second".into())));
+
+        let stats = queue.stats();
+        assert_eq!((stats.depth, stats.held, stats.in_flight), (2, 1, 1));
+        assert_eq!(stats.oldest_age_secs, Some(600));
+        assert!(stats.messages[0].held);
+
+        queue.pause();
+        queue.release(&MessageId("first".into())).unwrap();
+        assert_eq!(queue.dequeue(), None, "paused");
+        assert!(queue.stats().paused);
+        queue.resume();
+        assert_eq!(queue.dequeue(), Some(MessageId("first".into())));
+        assert_eq!(queue.dequeue(), Some(MessageId("third".into())));
+    }
+}
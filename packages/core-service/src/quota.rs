@@ -0,0 +1,323 @@
+//! Storage quotas with exemptions for urgent traffic.
+//!
+//! Before a message is stored, [`QuotaPolicy::admit`] asks the registered
+//! [`QuotaExemption`] hooks whether it must always get through; by default
+//! high-priority mail and the senders listed in `quota.exemptSenders` do.
+//! Everything else is subject to two limits: a message larger than
+//! `quota.maxMessageBytes` keeps only its headers in the store while the
+//! content is held in the gateway's attachment area until someone asks for it,
+//! and once the store exceeds `quota.softLimitBytes` new messages are refused.
+//! Where held content is kept survives restarts in the store's
+//! `held_content` table.
+//!
+//! Inbound mail is admitted by the [`InboundIngestor`] before it is filed.
+//! Submissions pass [`QuotaPolicy::check`] in the transport switch, which
+//! applies the exemptions and the soft limit but leaves the content alone,
+//! since the transport still has to send it.
+//!
+//! [`InboundIngestor`]: crate::gateway::InboundIngestor
+
+use std::io::{Cursor, Read};
+use std::sync::Arc;
+
+use serde::Serialize;
+use thiserror::Error;
+use tracing::info;
+
+use crate::attachments::{AttachmentError, AttachmentStore};
+use crate::config::QuotaConfig;
+use crate::models::{HeldContentRecord, Message, MessageId, MessagePriority};
+use crate::store::StoreManager;
+
+#[derive(Debug, Error)]
+pub enum QuotaError {
+    #[error("mailbox quota exceeded: {usage} of {limit} bytes used")]
+    Exceeded { usage: u64, limit: u64 },
+    #[error("message {0} has no content held at the gateway")]
+    NotHeld(MessageId),
+    #[error(transparent)]
+    Storage(#[from] AttachmentError),
+}
+
+impl QuotaError {
+    pub fn status(&self) -> u16 {
+        match self {
+            Self::Exceeded { .. } => 507,
+            Self::NotHeld(_) => 404,
+            Self::Storage(_) => 500,
+        }
+    }
+}
+
+/// Decides whether a message bypasses quota checks; returns the reason if so.
+pub trait QuotaExemption: Send + Sync {
+    fn exempts(&self, message: &Message) -> Option<String>;
+}
+
+/// Exempts urgent operational mail.
+pub struct PriorityExemption;
+
+impl QuotaExemption for PriorityExemption {
+    fn exempts(&self, message: &Message) -> Option<String> {
+        (message.envelope.priority == MessagePriority::High).then(|| "high priority".to_string())
+    }
+}
+
+/// Exempts mail from a fixed list of O/R addresses, compared case-insensitively.
+pub struct SenderExemption {
+    senders: Vec<String>,
+}
+
+impl SenderExemption {
+    pub fn new(senders: &[String]) -> Self {
+        Self {
+            senders: senders.iter().map(|sender| sender.to_lowercase()).collect(),
+        }
+    }
+}
+
+impl QuotaExemption for SenderExemption {
+    fn exempts(&self, message: &Message) -> Option<String> {
+        let sender = &message.envelope.sender;
+        let address = format!(
+            "C={};O={};S={}",
+            sender.country, sender.organization, sender.surname
+        )
+        .to_lowercase();
+        self.senders
+            .contains(&address)
+            .then(|| format!("exempt sender {address}"))
+    }
+}
+
+/// Outcome of [`QuotaPolicy::admit`].
+#[derive(Clone, Debug, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "camelCase", tag = "decision")]
+pub enum QuotaDecision {
+    Accepted,
+    Exempt {
+        reason: String,
+    },
+    /// The body was replaced by a notice; fetch it with [`QuotaPolicy::retrieve`].
+    HeadersOnly {
+        attachment_id: String,
+        size: u64,
+    },
+}
+
+/// Where the content of a headers-only message is kept.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct HeldContent {
+    pub attachment_id: String,
+    pub size: u64,
+}
+
+#[derive(Clone)]
+pub struct QuotaPolicy {
+    config: QuotaConfig,
+    store: StoreManager,
+    attachments: AttachmentStore,
+    exemptions: Vec<Arc<dyn QuotaExemption>>,
+}
+
+impl QuotaPolicy {
+    /// Build the policy with the built-in priority and sender exemptions.
+    pub fn new(config: QuotaConfig, store: StoreManager, attachments: AttachmentStore) -> Self {
+        let senders = SenderExemption::new(&config.exempt_senders);
+        Self {
+            config,
+            store,
+            attachments,
+            exemptions: vec![Arc::new(PriorityExemption), Arc::new(senders)],
+        }
+    }
+
+    pub fn with_exemption(mut self, exemption: Arc<dyn QuotaExemption>) -> Self {
+        self.exemptions.push(exemption);
+        self
+    }
+
+    /// Check `message` against the quota before it is stored, stripping its
+    /// content if it is oversize.
+    pub fn admit(&self, message: &mut Message) -> Result<QuotaDecision, QuotaError> {
+        if let Some(reason) = self.exemption(message) {
+            return Ok(QuotaDecision::Exempt { reason });
+        }
+
+        let mut decision = QuotaDecision::Accepted;
+        let limit = self.config.max_message_bytes;
+        if limit > 0 && message_bytes(message) > limit {
+            decision = self.hold_content(message)?;
+        }
+        self.check_usage(message)?;
+        Ok(decision)
+    }
+
+    /// Check a submission against the soft limit without touching its
+    /// content; exempt mail always passes.
+    pub fn check(&self, message: &Message) -> Result<QuotaDecision, QuotaError> {
+        if let Some(reason) = self.exemption(message) {
+            return Ok(QuotaDecision::Exempt { reason });
+        }
+        self.check_usage(message)?;
+        Ok(QuotaDecision::Accepted)
+    }
+
+    pub fn held(&self, id: &MessageId) -> Option<HeldContent> {
+        self.store.held_content(id).map(|row| HeldContent {
+            attachment_id: row.attachment_id,
+            size: row.size,
+        })
+    }
+
+    fn exemption(&self, message: &Message) -> Option<String> {
+        self.exemptions
+            .iter()
+            .find_map(|exemption| exemption.exempts(message))
+    }
+
+    fn check_usage(&self, message: &Message) -> Result<(), QuotaError> {
+        let limit = self.config.soft_limit_bytes;
+        if limit > 0 {
+            let usage: u64 = self
+                .store
+                .folder_stats()
+                .iter()
+                .map(|folder| folder.total_bytes)
+                .sum();
+            if usage + message_bytes(message) > limit {
+                return Err(QuotaError::Exceeded { usage, limit });
+            }
+        }
+        Ok(())
+    }
+
+    /// The stored message with its held content put back.
+    pub fn retrieve(&self, id: &MessageId) -> Result<Message, QuotaError> {
+        let held = self
+            .held(id)
+            .ok_or_else(|| QuotaError::NotHeld(id.clone()))?;
+        let mut message = self
+            .store
+            .get(id)
+            .ok_or_else(|| QuotaError::NotHeld(id.clone()))?;
+        let mut body = String::new();
+        self.attachments
+            .open(&held.attachment_id, None)?
+            .read_to_string(&mut body)
+            .map_err(AttachmentError::from)?;
+        message.content.body = body;
+        Ok(message)
+    }
+
+    /// Forget the content held for a removed message, deleting the blob
+    /// unless another held message shares it. Returns whether anything was held.
+    pub fn release(&self, id: &MessageId) -> Result<bool, QuotaError> {
+        let Some((released, shared)) = self.store.remove_held_content(id) else {
+            return Ok(false);
+        };
+        if !shared {
+            self.attachments.delete(&released.attachment_id)?;
+        }
+        Ok(true)
+    }
+
+    fn hold_content(&self, message: &mut Message) -> Result<QuotaDecision, QuotaError> {
+        let id = message.envelope.id.clone();
+        let body = std::mem::take(&mut message.content.body);
+        let stored = self
+            .attachments
+            .put(&format!("{id}.body"), Cursor::new(body.into_bytes()))?;
+        message.content.body = format!(
+            "[Message content of {} bytes is held at the gateway and will be retrieved on request.]",
+            stored.size
+        );
+        info!(target = "quota", message = %id, size = stored.size, "stored headers only");
+        self.store.put_held_content(HeldContentRecord {
+            message_id: id,
+            attachment_id: stored.id.clone(),
+            size: stored.size,
+        });
+        Ok(QuotaDecision::HeadersOnly {
+            attachment_id: stored.id,
+            size: stored.size,
+        })
+    }
+}
+
+/// Size as counted by the store's folder statistics.
+fn message_bytes(message: &Message) -> u64 {
+    (message.envelope.subject.len() + message.content.body.len()) as u64
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::models::{Address, MessageContent, MessageEnvelope};
+
+    fn message(subject: &str, body: &str, priority: MessagePriority) -> Message {
+        let mut envelope =
+            MessageEnvelope::new(subject, Address::sample(), vec![Address::sample()]);
+        envelope.priority = priority;
+        Message {
+            envelope,
+            content: MessageContent {
+                body: body.into(),
+                attachments: Vec::new(),
+            },
+        }
+    }
+
+    #[test]
+    fn urgent_mail_bypasses_quota_and_oversize_mail_is_held() {
+        let dir = tempfile::tempdir().unwrap();
+        let store = StoreManager::new();
+        let policy = QuotaPolicy::new(
+            QuotaConfig {
+                soft_limit_bytes: 150,
+                max_message_bytes: 64,
+                exempt_senders: vec!["C=DE;O=Modern;S=Duty Officer".into()],
+            },
+            store.clone(),
+            AttachmentStore::new(dir.path()),
+        );
+        let report = "x".repeat(200);
+
+        let mut large = message("Quarterly report", &report, MessagePriority::Normal);
+        let decision = policy.admit(&mut large).unwrap();
+        let QuotaDecision::HeadersOnly { size, .. } = decision else {
+            panic!("expected headers-only, got {decision:?}");
+        };
+        assert_eq!(size, 200);
+        assert!(large.content.body.contains("held at the gateway"));
+        store.save(large.clone());
+        let full = policy.retrieve(&large.envelope.id).unwrap();
+        assert_eq!(full.content.body, report);
+
+        let mut filler = message("Filler", &"y".repeat(50), MessagePriority::Low);
+        let err = policy.admit(&mut filler).unwrap_err();
+        assert_eq!(err.status(), 507);
+
+        let mut urgent = message("Outage", &report, MessagePriority::High);
+        assert_eq!(
+            policy.admit(&mut urgent).unwrap(),
+            QuotaDecision::Exempt {
+                reason: "high priority".into()
+            }
+        );
+        assert_eq!(
+            urgent.content.body, report,
+            "exempt mail is never truncated"
+        );
+
+        let mut shift_log = message("Shift log", &report, MessagePriority::Low);
+        shift_log.envelope.sender.surname = "duty officer".into();
+        assert_eq!(
+            policy.admit(&mut shift_log).unwrap(),
+            QuotaDecision::Exempt {
+                reason: "exempt sender c=de;o=modern;s=duty officer".into()
+            }
+        );
+    }
+}
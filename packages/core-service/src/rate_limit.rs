@@ -0,0 +1,146 @@
+//! Token-bucket rate limiting for the local API.
+//!
+//! Every command spends one token from the bucket of its caller's key and one
+//! from the bucket of its remote address. Buckets refill continuously at the
+//! configured per-minute rate up to `burst` tokens. [`crate::ipc`] turns
+//! [`RateLimited`] into a `rateLimited` frame with status 429 and the
+//! `Retry-After` value in seconds.
+
+use std::collections::HashMap;
+use std::net::IpAddr;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+use thiserror::Error;
+
+use crate::config::RateLimitConfig;
+
+/// Buckets idle this long are dropped so one-off clients do not accumulate.
+const IDLE_EVICTION: Duration = Duration::from_secs(600);
+
+#[derive(Debug, Error, PartialEq, Eq)]
+#[error("rate limit exceeded for {subject}; retry after {retry_after:?}")]
+pub struct RateLimited {
+    pub subject: String,
+    pub retry_after: Duration,
+}
+
+impl RateLimited {
+    /// Value for the `Retry-After` header, in whole seconds (at least 1).
+    pub fn retry_after_header(&self) -> String {
+        let secs = self.retry_after.as_secs() + u64::from(self.retry_after.subsec_nanos() > 0);
+        secs.max(1).to_string()
+    }
+}
+
+#[derive(Clone, Copy, Debug)]
+struct Bucket {
+    tokens: f64,
+    updated: Instant,
+}
+
+#[derive(Clone)]
+pub struct RateLimiter {
+    config: RateLimitConfig,
+    buckets: Arc<Mutex<HashMap<String, Bucket>>>,
+}
+
+impl RateLimiter {
+    pub fn new(config: RateLimitConfig) -> Self {
+        Self {
+            config,
+            buckets: Arc::new(Mutex::new(HashMap::new())),
+        }
+    }
+
+    pub fn check(&self, api_key: Option<&str>, remote: IpAddr) -> Result<(), RateLimited> {
+        self.check_at(api_key, remote, Instant::now())
+    }
+
+    /// Spend tokens for a request observed at `now`.
+    ///
+    /// Nothing is spent unless both the key and address buckets have a token,
+    /// so a rejected request does not drain the other bucket.
+    pub fn check_at(
+        &self,
+        api_key: Option<&str>,
+        remote: IpAddr,
+        now: Instant,
+    ) -> Result<(), RateLimited> {
+        if !self.config.enabled {
+            return Ok(());
+        }
+        let Ok(mut buckets) = self.buckets.lock() else {
+            return Ok(());
+        };
+        buckets.retain(|_, bucket| now.saturating_duration_since(bucket.updated) < IDLE_EVICTION);
+
+        let mut subjects = vec![(format!("ip:{remote}"), self.config.per_ip_per_minute)];
+        if let Some(key) = api_key {
+            subjects.push((format!("key:{key}"), self.config.per_key_per_minute));
+        }
+
+        let burst = f64::from(self.config.burst.max(1));
+        for (subject, per_minute) in &subjects {
+            let rate = f64::from(*per_minute) / 60.0;
+            let bucket = buckets.entry(subject.clone()).or_insert(Bucket {
+                tokens: burst,
+                updated: now,
+            });
+            let elapsed = now.saturating_duration_since(bucket.updated).as_secs_f64();
+            bucket.tokens = (bucket.tokens + elapsed * rate).min(burst);
+            bucket.updated = now;
+            if bucket.tokens < 1.0 {
+                let wait = if rate > 0.0 {
+                    Duration::from_secs_f64((1.0 - bucket.tokens) / rate)
+                } else {
+                    Duration::from_secs(60)
+                };
+                return Err(RateLimited {
+                    subject: subject.clone(),
+                    retry_after: wait,
+                });
+            }
+        }
+        for (subject, _) in &subjects {
+            if let Some(bucket) = buckets.get_mut(subject) {
+                bucket.tokens -= 1.0;
+            }
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn limits_per_key_and_per_address() {
+        let limiter = RateLimiter::new(RateLimitConfig {
+            enabled: true,
+            per_key_per_minute: 60,
+            per_ip_per_minute: 120,
+            burst: 2,
+        });
+        let ip: IpAddr = "10.0.0.1".parse().unwrap();
+        let start = Instant::now();
+
+        assert!(limiter.check_at(Some("ops"), ip, start).is_ok());
+        assert!(limiter.check_at(Some("ops"), ip, start).is_ok());
+        let err = limiter.check_at(Some("ops"), ip, start).unwrap_err();
+        assert_eq!(err.subject, "ip:10.0.0.1");
+        assert_eq!(err.retry_after_header(), "1");
+
+        // The address refills at two tokens per second, the key at one.
+        let later = start + Duration::from_millis(600);
+        let err = limiter.check_at(Some("ops"), ip, later).unwrap_err();
+        assert_eq!(err.subject, "key:ops");
+        assert!(limiter
+            .check_at(None, "10.0.0.2".parse().unwrap(), later)
+            .is_ok());
+        assert!(limiter
+            .check_at(Some("ops"), ip, start + Duration::from_secs(1))
+            .is_ok());
+    }
+}
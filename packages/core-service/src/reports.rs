@@ -0,0 +1,84 @@
+use crate::gateway::report_map::{is_enhanced_status, x400_for_smtp};
+use crate::models::{MessageStatus, Report, ReportKind};
+use crate::store::StoreManager;
+use crate::trace::TraceManager;
+use crate::webhooks::WebhookManager;
+
+/// Error returned when a report cannot be correlated to a stored message.
+#[derive(Debug, thiserror::Error, PartialEq, Eq)]
+pub enum ReportError {
+    #[error("no stored message matches report for {0}")]
+    UnknownMessage(String),
+    /// Simulated reports are only accepted while `transport.mode` is `mock`.
+    #[error("the {0} transport is active; simulated reports need the mock transport")]
+    MockInactive(&'static str),
+}
+
+/// Single entry point for delivery, non-delivery, and read reports.
+///
+/// SDK, gateway, and mock transports all hand their reports to the ingestor so
+/// status transitions and trace entries look the same regardless of origin.
+#[derive(Clone)]
+pub struct ReportIngestor {
+    store: StoreManager,
+    trace: TraceManager,
+    webhooks: Option<WebhookManager>,
+}
+
+impl ReportIngestor {
+    pub fn new(store: StoreManager, trace: TraceManager) -> Self {
+        Self {
+            store,
+            trace,
+            webhooks: None,
+        }
+    }
+
+    pub fn with_webhooks(mut self, webhooks: WebhookManager) -> Self {
+        self.webhooks = Some(webhooks);
+        self
+    }
+
+    pub fn ingest(&self, mut report: Report) -> Result<(), ReportError> {
+        normalize_reason(&mut report);
+        let id = report.message_id.clone();
+        let message = self
+            .store
+            .get(&id)
+            .ok_or_else(|| ReportError::UnknownMessage(id.to_string()))?;
+
+        let (status, event) = match report.kind {
+            ReportKind::Delivery => (MessageStatus::Delivered, "report.delivery"),
+            ReportKind::NonDelivery => (MessageStatus::Failed, "report.non_delivery"),
+            ReportKind::Read => (MessageStatus::Read, "report.read"),
+        };
+        // A late delivery report must not downgrade a message already read.
+        let downgrade =
+            message.envelope.status == MessageStatus::Read && report.kind == ReportKind::Delivery;
+        if !downgrade {
+            self.store.update_status(&id, status);
+        }
+        if let Some(webhooks) = &self.webhooks {
+            webhooks.notify_async(&report);
+        }
+        self.store.save_report(report);
+        self.trace.record(event, id);
+        Ok(())
+    }
+}
+
+/// Replace a raw SMTP status code in a non-delivery report with its X.400
+/// reason/diagnostic pair so stored reports do not depend on the transport.
+fn normalize_reason(report: &mut Report) {
+    if report.kind != ReportKind::NonDelivery {
+        return;
+    }
+    let Some(code) = report.reason.as_deref().filter(|r| is_enhanced_status(r)) else {
+        return;
+    };
+    let (reason, diagnostic) = x400_for_smtp(code);
+    report.reason = Some(reason.to_string());
+    if report.diagnostic.is_none() {
+        report.diagnostic = diagnostic.map(str::to_string);
+    }
+}
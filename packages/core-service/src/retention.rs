@@ -0,0 +1,305 @@
+//! Folder retention.
+//!
+//! Each `retention.folders.<folder>` rule expires messages older than
+//! `maxAgeDays` or beyond the newest `maxCount`, then archives or purges them.
+//! The sweep runs every `retention.intervalSecs` as a supervised worker;
+//! `GET /admin/retention/preview` runs the same selection without changing
+//! anything. Queued messages are never expired, so retention cannot pull a
+//! message out from under the transport. Purges are written to the audit log
+//! as `retention.purge` by `retention`.
+
+use std::thread;
+use std::time::Duration;
+
+use chrono::{DateTime, Utc};
+use serde::Serialize;
+use tracing::{info, warn};
+
+use crate::audit::AuditLog;
+use crate::bulk::BulkAction;
+use crate::clock::SharedClock;
+use crate::config::{RetentionAction, RetentionConfig};
+use crate::models::{AuditAction, MessageId, MessageStatus};
+use crate::quota::QuotaPolicy;
+use crate::store::StoreManager;
+use crate::supervisor::Supervisor;
+use crate::trace::TraceManager;
+
+/// Why a message was selected.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub enum RetentionReason {
+    MaxAge,
+    MaxCount,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct RetentionCandidate {
+    pub message_id: MessageId,
+    pub folder: String,
+    pub subject: String,
+    pub created_at: DateTime<Utc>,
+    pub reason: RetentionReason,
+    pub action: RetentionAction,
+}
+
+/// Outcome of a sweep, or with `dry_run` set, what a sweep would do.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct RetentionReport {
+    pub evaluated_at: DateTime<Utc>,
+    pub dry_run: bool,
+    pub candidates: Vec<RetentionCandidate>,
+}
+
+#[derive(Clone)]
+pub struct RetentionSweeper {
+    config: RetentionConfig,
+    store: StoreManager,
+    quota: QuotaPolicy,
+    trace: TraceManager,
+    audit: Option<AuditLog>,
+    clock: SharedClock,
+}
+
+impl RetentionSweeper {
+    pub fn new(
+        config: RetentionConfig,
+        store: StoreManager,
+        quota: QuotaPolicy,
+        trace: TraceManager,
+    ) -> Self {
+        Self {
+            config,
+            store,
+            quota,
+            trace,
+            audit: None,
+            clock: SharedClock::default(),
+        }
+    }
+
+    pub fn with_audit(mut self, audit: AuditLog) -> Self {
+        self.audit = Some(audit);
+        self
+    }
+
+    pub fn with_clock(mut self, clock: SharedClock) -> Self {
+        self.clock = clock;
+        self
+    }
+
+    /// What a sweep would archive or purge right now.
+    pub fn preview(&self) -> RetentionReport {
+        RetentionReport {
+            evaluated_at: self.clock.now(),
+            dry_run: true,
+            candidates: self.candidates(),
+        }
+    }
+
+    /// Archive or purge every expired message.
+    pub fn sweep(&self) -> RetentionReport {
+        let candidates = self.candidates();
+        for action in [RetentionAction::Archive, RetentionAction::Purge] {
+            let ids: Vec<MessageId> = candidates
+                .iter()
+                .filter(|candidate| candidate.action == action)
+                .map(|candidate| candidate.message_id.clone())
+                .collect();
+            if ids.is_empty() {
+                continue;
+            }
+            let bulk = match action {
+                RetentionAction::Archive => BulkAction::Archive,
+                RetentionAction::Purge => BulkAction::Delete,
+            };
+            let (applied, _) = self.store.apply_bulk(&ids, &bulk);
+            if action == RetentionAction::Purge {
+                if let Some(audit) = &self.audit {
+                    for id in &applied {
+                        audit.record("retention", AuditAction::RetentionPurge, &id.0, None, None);
+                    }
+                }
+                for id in &applied {
+                    if let Err(err) = self.quota.release(id) {
+                        warn!(target = "retention", message = %id, "cannot remove held content: {err}");
+                    }
+                }
+            }
+            info!(
+                target = "retention",
+                action = action.name(),
+                count = applied.len(),
+                "retention applied"
+            );
+            self.trace
+                .record_many(format!("retention.{}", action.name()), applied);
+        }
+        RetentionReport {
+            evaluated_at: self.clock.now(),
+            dry_run: false,
+            candidates,
+        }
+    }
+
+    /// Run [`sweep`](Self::sweep) every `retention.intervalSecs` as the
+    /// supervised `retention` worker. Does nothing when retention is disabled.
+    pub fn spawn(&self, supervisor: &Supervisor) {
+        if !self.config.enabled || self.config.rules.is_empty() {
+            return;
+        }
+        let sweeper = self.clone();
+        let interval = Duration::from_secs(self.config.interval_secs.max(1));
+        supervisor.spawn("retention", move |context| {
+            while !context.should_stop() {
+                sweeper.sweep();
+                let mut waited = Duration::ZERO;
+                while waited < interval && !context.should_stop() {
+                    context.heartbeat();
+                    let step = (interval - waited).min(Duration::from_secs(1));
+                    thread::sleep(step);
+                    waited += step;
+                }
+            }
+        });
+    }
+
+    fn candidates(&self) -> Vec<RetentionCandidate> {
+        let now = self.clock.now();
+        let mut candidates = Vec::new();
+        for rule in &self.config.rules {
+            let cutoff = rule
+                .max_age_days
+                .map(|days| now - chrono::Duration::days(i64::from(days)));
+            // Newest first, so everything past `max_count` is the oldest surplus.
+            let messages = self.store.list(&rule.folder);
+            for (position, message) in messages.into_iter().enumerate() {
+                let envelope = message.envelope;
+                if envelope.status == MessageStatus::Queued {
+                    continue;
+                }
+                let reason = if cutoff.is_some_and(|cutoff| envelope.created_at < cutoff) {
+                    RetentionReason::MaxAge
+                } else if rule.max_count.is_some_and(|max| position >= max) {
+                    RetentionReason::MaxCount
+                } else {
+                    continue;
+                };
+                candidates.push(RetentionCandidate {
+                    message_id: envelope.id,
+                    folder: envelope.folder,
+                    subject: envelope.subject,
+                    created_at: envelope.created_at,
+                    reason,
+                    action: rule.action,
+                });
+            }
+        }
+        candidates
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::attachments::AttachmentStore;
+    use crate::clock::{Clock, ManualClock, SequentialIds, SharedIds};
+    use crate::config::{QuotaConfig, RetentionRule};
+    use crate::models::{Address, Message, MessageContent, MessageEnvelope, MessagePriority};
+    use chrono::TimeZone;
+
+    #[test]
+    fn previews_then_archives_and_purges_expired_messages() {
+        let clock = ManualClock::new(Utc.with_ymd_and_hms(2024, 1, 1, 9, 0, 0).unwrap());
+        let shared = SharedClock::new(clock.clone());
+        let ids = SharedIds::new(SequentialIds::default());
+        let store = StoreManager::new().with_clock(shared.clone());
+        let dir = tempfile::tempdir().unwrap();
+        let quota = QuotaPolicy::new(
+            QuotaConfig {
+                max_message_bytes: 16,
+                ..QuotaConfig::default()
+            },
+            store.clone(),
+            AttachmentStore::new(dir.path()),
+        );
+        let mut stored = Vec::new();
+        for (folder, body) in [
+            ("sent", "short"),
+            ("sent", "short"),
+            ("inbox", "a body long enough to be held"),
+            ("inbox", "short"),
+        ] {
+            let mut envelope =
+                MessageEnvelope::stamped("Manifest", Address::sample(), Vec::new(), &ids, &shared);
+            envelope.folder = folder.into();
+            envelope.status = MessageStatus::Delivered;
+            envelope.priority = MessagePriority::Low;
+            let mut message = Message {
+                envelope,
+                content: MessageContent {
+                    body: body.into(),
+                    attachments: Vec::new(),
+                },
+            };
+            quota.admit(&mut message).unwrap();
+            stored.push(message.envelope.id.clone());
+            store.save(message);
+            clock.advance(chrono::Duration::days(10));
+        }
+
+        let config = RetentionConfig {
+            enabled: true,
+            interval_secs: 60,
+            rules: vec![
+                RetentionRule {
+                    folder: "sent".into(),
+                    max_age_days: None,
+                    max_count: Some(1),
+                    action: RetentionAction::Archive,
+                },
+                RetentionRule {
+                    folder: "inbox".into(),
+                    max_age_days: Some(15),
+                    max_count: None,
+                    action: RetentionAction::Purge,
+                },
+            ],
+        };
+        let trace = TraceManager::new();
+        let retention = RetentionSweeper::new(config, store.clone(), quota.clone(), trace.clone())
+            .with_clock(shared.clone());
+
+        let preview = retention.preview();
+        let selected: Vec<_> = preview
+            .candidates
+            .iter()
+            .map(|candidate| (candidate.message_id.clone(), candidate.reason))
+            .collect();
+        assert_eq!(
+            selected,
+            [
+                (stored[0].clone(), RetentionReason::MaxCount),
+                (stored[2].clone(), RetentionReason::MaxAge),
+            ]
+        );
+        assert_eq!(store.list("sent").len(), 2, "a preview changes nothing");
+
+        let held = quota.held(&stored[2]).unwrap();
+        let report = retention.sweep();
+        assert!(!report.dry_run);
+        assert_eq!(store.list("archive")[0].envelope.id, stored[0]);
+        assert!(store.get(&stored[2]).is_none());
+        assert!(quota.held(&stored[2]).is_none());
+        assert!(!dir
+            .path()
+            .join("attachments")
+            .join(held.attachment_id)
+            .exists());
+        assert!(retention.preview().candidates.is_empty());
+        assert_eq!(trace.bundle().len(), 2);
+        assert_eq!(clock.now(), report.evaluated_at);
+    }
+}
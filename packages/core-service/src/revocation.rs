@@ -0,0 +1,578 @@
+//! Revocation checking for the listener's TLS certificates.
+//!
+//! [`RevocationChecker`] checks the server certificate chain and the client
+//! CA bundle on a background worker, and client certificates when asked. A
+//! certificate is checked through `server.tls.ocspResponder`, or the OCSP
+//! responder named in the certificate, falling back to the CRLs at its
+//! distribution points. OCSP responses must verify against the loaded
+//! certificates and CRLs against the issuer's key. Both are cached until
+//! their `nextUpdate`, or for an hour when they carry none.
+//!
+//! The outcome is reported in the `tls` block of `/status`:
+//! `revocationChecked` is set once every certificate with a known issuer
+//! was checked without error; revoked certificates and errors are listed.
+
+use std::collections::HashMap;
+use std::fs;
+use std::io::Read;
+use std::sync::{Arc, Mutex, PoisonError};
+use std::thread;
+use std::time::Duration;
+
+use chrono::{DateTime, NaiveDateTime, Utc};
+use openssl::hash::MessageDigest;
+use openssl::ocsp::{
+    OcspCertId, OcspCertStatus, OcspFlag, OcspRequest, OcspResponse, OcspResponseStatus,
+};
+use openssl::stack::Stack;
+use openssl::x509::store::{X509Store, X509StoreBuilder, X509StoreRef};
+use openssl::x509::{CrlStatus, X509Crl, X509VerifyResult, X509};
+use serde::Serialize;
+use tracing::{info, warn};
+
+use crate::clock::SharedClock;
+use crate::config::TlsConfig;
+use crate::supervisor::Supervisor;
+
+/// How long a response without `nextUpdate` is trusted.
+const DEFAULT_FRESHNESS: chrono::Duration = chrono::Duration::hours(1);
+
+/// Clock skew allowed when checking OCSP response times, in seconds.
+const OCSP_SKEW_SECS: u32 = 300;
+
+/// Fetches CRLs and OCSP responses.
+pub trait RevocationFetcher: Send + Sync {
+    /// GET `url`, e.g. a CRL.
+    fn get(&self, url: &str) -> Result<Vec<u8>, String>;
+    /// POST the DER OCSP `request` to the responder at `url`.
+    fn ocsp(&self, url: &str, request: &[u8]) -> Result<Vec<u8>, String>;
+}
+
+/// Fetcher speaking plain HTTP, as CRL and OCSP endpoints do.
+pub struct HttpFetcher {
+    timeout: Duration,
+}
+
+impl HttpFetcher {
+    pub fn new(timeout: Duration) -> Self {
+        Self { timeout }
+    }
+}
+
+impl RevocationFetcher for HttpFetcher {
+    fn get(&self, url: &str) -> Result<Vec<u8>, String> {
+        let response = ureq::get(url)
+            .timeout(self.timeout)
+            .call()
+            .map_err(|err| err.to_string())?;
+        read_body(response)
+    }
+
+    fn ocsp(&self, url: &str, request: &[u8]) -> Result<Vec<u8>, String> {
+        let response = ureq::post(url)
+            .timeout(self.timeout)
+            .set("Content-Type", "application/ocsp-request")
+            .send_bytes(request)
+            .map_err(|err| err.to_string())?;
+        read_body(response)
+    }
+}
+
+fn read_body(response: ureq::Response) -> Result<Vec<u8>, String> {
+    let mut body = Vec::new();
+    response
+        .into_reader()
+        .read_to_end(&mut body)
+        .map_err(|err| err.to_string())?;
+    Ok(body)
+}
+
+/// Revocation state of one certificate.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Revocation {
+    Good,
+    Revoked { revoked_at: Option<DateTime<Utc>> },
+}
+
+#[derive(Clone, Debug, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct RevokedCertificate {
+    pub subject: String,
+    pub fingerprint: String,
+    pub revoked_at: Option<DateTime<Utc>>,
+}
+
+/// Revocation part of the `tls` block of `/status`.
+#[derive(Clone, Debug, Default, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct RevocationStatus {
+    pub ocsp_responder_configured: bool,
+    pub revocation_checked: bool,
+    pub checked_at: Option<DateTime<Utc>>,
+    pub revoked: Vec<RevokedCertificate>,
+    pub errors: Vec<String>,
+}
+
+struct CachedCrl {
+    crl: Arc<X509Crl>,
+    until: DateTime<Utc>,
+}
+
+struct CachedOcsp {
+    revocation: Revocation,
+    until: DateTime<Utc>,
+}
+
+#[derive(Clone)]
+pub struct RevocationChecker {
+    certificates: Arc<Vec<X509>>,
+    trust: Arc<X509Store>,
+    responder: Option<String>,
+    fetcher: Arc<dyn RevocationFetcher>,
+    interval: Duration,
+    clock: SharedClock,
+    crls: Arc<Mutex<HashMap<String, CachedCrl>>>,
+    ocsp: Arc<Mutex<HashMap<String, CachedOcsp>>>,
+    status: Arc<Mutex<RevocationStatus>>,
+}
+
+impl RevocationChecker {
+    /// Check `certificates`; issuers are looked up among them.
+    pub fn new(certificates: Vec<X509>, fetcher: Arc<dyn RevocationFetcher>) -> Self {
+        let mut trust = X509StoreBuilder::new().expect("allocating an X509 store");
+        for certificate in &certificates {
+            // Duplicates are refused by OpenSSL and do no harm.
+            let _ = trust.add_cert(certificate.clone());
+        }
+        Self {
+            certificates: Arc::new(certificates),
+            trust: Arc::new(trust.build()),
+            responder: None,
+            fetcher,
+            interval: Duration::from_secs(3_600),
+            clock: SharedClock::default(),
+            crls: Arc::new(Mutex::new(HashMap::new())),
+            ocsp: Arc::new(Mutex::new(HashMap::new())),
+            status: Arc::new(Mutex::new(RevocationStatus::default())),
+        }
+    }
+
+    /// The server chain and client CA bundle of `tls`; nothing to check when
+    /// TLS is off. Files that cannot be read are left out, the listener
+    /// reports those itself.
+    pub fn from_config(tls: &TlsConfig) -> Self {
+        let mut certificates = Vec::new();
+        if tls.enabled {
+            let paths =
+                std::iter::once(tls.certificate_path.as_str()).chain(tls.client_ca_path.as_deref());
+            for path in paths {
+                match fs::read(path).map(|pem| X509::stack_from_pem(&pem)) {
+                    Ok(Ok(loaded)) => certificates.extend(loaded),
+                    _ => warn!(
+                        target = "tls",
+                        path, "certificates not loaded for revocation checks"
+                    ),
+                }
+            }
+        }
+        Self::new(
+            certificates,
+            Arc::new(HttpFetcher::new(Duration::from_secs(10))),
+        )
+        .with_responder(tls.ocsp_responder.clone())
+        .with_interval(Duration::from_secs(tls.revocation_interval_secs))
+    }
+
+    /// Query this OCSP responder instead of the ones named in certificates.
+    pub fn with_responder(mut self, responder: Option<String>) -> Self {
+        self.status_mut().ocsp_responder_configured = responder.is_some();
+        self.responder = responder;
+        self
+    }
+
+    pub fn with_interval(mut self, interval: Duration) -> Self {
+        self.interval = interval;
+        self
+    }
+
+    pub fn with_clock(mut self, clock: SharedClock) -> Self {
+        self.clock = clock;
+        self
+    }
+
+    /// Result of the last [`refresh`](Self::refresh).
+    pub fn status(&self) -> RevocationStatus {
+        self.status_mut().clone()
+    }
+
+    /// Check every loaded certificate with a known issuer.
+    pub fn refresh(&self) -> RevocationStatus {
+        let mut checked = 0;
+        let mut revoked = Vec::new();
+        let mut errors = Vec::new();
+        for certificate in self.certificates.iter() {
+            let Some(issuer) = self.issuer(certificate) else {
+                continue;
+            };
+            checked += 1;
+            match self.check_with(certificate, issuer) {
+                Ok(Revocation::Good) => {}
+                Ok(Revocation::Revoked { revoked_at }) => revoked.push(RevokedCertificate {
+                    subject: subject(certificate),
+                    fingerprint: fingerprint(certificate),
+                    revoked_at,
+                }),
+                Err(error) => errors.push(format!("{}: {error}", subject(certificate))),
+            }
+        }
+        for certificate in &revoked {
+            warn!(target = "tls", subject = %certificate.subject, "certificate revoked");
+        }
+        let mut status = self.status_mut();
+        status.revocation_checked = checked > 0 && errors.is_empty();
+        status.checked_at = Some(self.clock.now());
+        status.revoked = revoked;
+        status.errors = errors;
+        status.clone()
+    }
+
+    /// Check a certificate issued by one of the loaded certificates, such as
+    /// a client certificate from the mTLS handshake.
+    pub fn check(&self, certificate: &X509) -> Result<Revocation, String> {
+        let issuer = self
+            .issuer(certificate)
+            .ok_or_else(|| format!("issuer of {} is not loaded", subject(certificate)))?;
+        self.check_with(certificate, issuer)
+    }
+
+    /// Refresh on a worker every `server.tls.revocationIntervalSecs`.
+    pub fn spawn(&self, supervisor: &Supervisor) {
+        if self.certificates.is_empty() {
+            return;
+        }
+        let checker = self.clone();
+        let interval = self.interval;
+        supervisor.spawn("revocation", move |context| {
+            while !context.should_stop() {
+                let status = checker.refresh();
+                info!(
+                    target = "tls",
+                    checked = status.revocation_checked,
+                    revoked = status.revoked.len(),
+                    errors = status.errors.len(),
+                    "revocation check finished"
+                );
+                let mut waited = Duration::ZERO;
+                while waited < interval && !context.should_stop() {
+                    context.heartbeat();
+                    let step = (interval - waited).min(Duration::from_secs(1));
+                    thread::sleep(step);
+                    waited += step;
+                }
+            }
+        });
+    }
+
+    fn issuer(&self, certificate: &X509) -> Option<&X509> {
+        // Self-signed roots are trusted as configured, not checked.
+        if certificate.issued(certificate) == X509VerifyResult::OK {
+            return None;
+        }
+        self.certificates
+            .iter()
+            .find(|candidate| candidate.issued(certificate) == X509VerifyResult::OK)
+    }
+
+    fn check_with(&self, certificate: &X509, issuer: &X509) -> Result<Revocation, String> {
+        let responder = self.responder.clone().or_else(|| {
+            certificate
+                .ocsp_responders()
+                .ok()
+                .and_then(|responders| responders.iter().next().map(|url| url.to_string()))
+        });
+        let crl_urls = crl_urls(certificate);
+        let mut last_error = None;
+        if let Some(url) = responder {
+            match self.ocsp_status(&url, certificate, issuer) {
+                Ok(revocation) => return Ok(revocation),
+                Err(error) => last_error = Some(format!("OCSP {url}: {error}")),
+            }
+        }
+        for url in crl_urls {
+            match self.crl(&url, issuer) {
+                Ok(crl) => {
+                    return Ok(match crl.get_by_cert(certificate) {
+                        CrlStatus::Revoked(entry) => Revocation::Revoked {
+                            revoked_at: asn1_time(&entry.revocation_date().to_string()),
+                        },
+                        CrlStatus::NotRevoked | CrlStatus::RemoveFromCrl(_) => Revocation::Good,
+                    })
+                }
+                Err(error) => last_error = Some(format!("CRL {url}: {error}")),
+            }
+        }
+        Err(last_error.unwrap_or_else(|| "no OCSP responder or CRL distribution point".into()))
+    }
+
+    fn ocsp_status(
+        &self,
+        url: &str,
+        certificate: &X509,
+        issuer: &X509,
+    ) -> Result<Revocation, String> {
+        let key = fingerprint(certificate);
+        let now = self.clock.now();
+        if let Some(cached) = self.ocsp_cache().get(&key) {
+            if cached.until > now {
+                return Ok(cached.revocation);
+            }
+        }
+        let request = ocsp_request(certificate, issuer)?;
+        let response = self.fetcher.ocsp(url, &request)?;
+        let (revocation, next_update) =
+            ocsp_verdict(&response, certificate, issuer, &[], &self.trust)?;
+        let until = next_update.unwrap_or(now + DEFAULT_FRESHNESS);
+        self.ocsp_cache()
+            .insert(key, CachedOcsp { revocation, until });
+        Ok(revocation)
+    }
+
+    fn crl(&self, url: &str, issuer: &X509) -> Result<Arc<X509Crl>, String> {
+        let now = self.clock.now();
+        if let Some(cached) = self.crl_cache().get(url) {
+            if cached.until > now {
+                return Ok(cached.crl.clone());
+            }
+        }
+        let body = self.fetcher.get(url)?;
+        let crl = X509Crl::from_der(&body)
+            .or_else(|_| X509Crl::from_pem(&body))
+            .map_err(|err| err.to_string())?;
+        let key = issuer.public_key().map_err(|err| err.to_string())?;
+        if !crl.verify(&key).unwrap_or(false) {
+            return Err("signature does not verify against the issuer".into());
+        }
+        let until = crl
+            .next_update()
+            .and_then(|time| asn1_time(&time.to_string()))
+            .unwrap_or(now + DEFAULT_FRESHNESS);
+        let crl = Arc::new(crl);
+        self.crl_cache().insert(
+            url.to_string(),
+            CachedCrl {
+                crl: crl.clone(),
+                until,
+            },
+        );
+        Ok(crl)
+    }
+
+    fn status_mut(&self) -> std::sync::MutexGuard<'_, RevocationStatus> {
+        self.status.lock().unwrap_or_else(PoisonError::into_inner)
+    }
+
+    fn crl_cache(&self) -> std::sync::MutexGuard<'_, HashMap<String, CachedCrl>> {
+        self.crls.lock().unwrap_or_else(PoisonError::into_inner)
+    }
+
+    fn ocsp_cache(&self) -> std::sync::MutexGuard<'_, HashMap<String, CachedOcsp>> {
+        self.ocsp.lock().unwrap_or_else(PoisonError::into_inner)
+    }
+}
+
+/// DER OCSP request for `certificate`.
+pub(crate) fn ocsp_request(certificate: &X509, issuer: &X509) -> Result<Vec<u8>, String> {
+    let id = OcspCertId::from_cert(MessageDigest::sha1(), certificate, issuer)
+        .map_err(|err| err.to_string())?;
+    let mut request = OcspRequest::new().map_err(|err| err.to_string())?;
+    request.add_id(id).map_err(|err| err.to_string())?;
+    request.to_der().map_err(|err| err.to_string())
+}
+
+/// Status of `certificate` in the DER OCSP `response`, with the response's
+/// `nextUpdate`. The response must be signed by a certificate chaining to
+/// `trust`; `untrusted` may supply intermediates besides `issuer`.
+pub(crate) fn ocsp_verdict(
+    response: &[u8],
+    certificate: &X509,
+    issuer: &X509,
+    untrusted: &[X509],
+    trust: &X509StoreRef,
+) -> Result<(Revocation, Option<DateTime<Utc>>), String> {
+    let response = OcspResponse::from_der(response).map_err(|err| err.to_string())?;
+    if response.status() != OcspResponseStatus::SUCCESSFUL {
+        return Err(format!("responder answered {}", response.status().as_raw()));
+    }
+    let basic = response.basic().map_err(|err| err.to_string())?;
+    let mut chain = Stack::new().map_err(|err| err.to_string())?;
+    for extra in std::iter::once(issuer).chain(untrusted) {
+        chain.push(extra.clone()).map_err(|err| err.to_string())?;
+    }
+    basic
+        .verify(&chain, trust, OcspFlag::empty())
+        .map_err(|err| format!("response does not verify: {err}"))?;
+    let id = OcspCertId::from_cert(MessageDigest::sha1(), certificate, issuer)
+        .map_err(|err| err.to_string())?;
+    let status = basic
+        .find_status(&id)
+        .ok_or("response does not cover the certificate")?;
+    status
+        .check_validity(OCSP_SKEW_SECS, None)
+        .map_err(|err| format!("response is stale: {err}"))?;
+    let revocation = match status.status {
+        OcspCertStatus::GOOD => Revocation::Good,
+        OcspCertStatus::REVOKED => Revocation::Revoked {
+            revoked_at: status
+                .revocation_time
+                .and_then(|time| asn1_time(&time.to_string())),
+        },
+        _ => return Err("responder does not know the certificate".into()),
+    };
+    let next_update = status
+        .next_update()
+        .and_then(|time| asn1_time(&time.to_string()));
+    Ok((revocation, next_update))
+}
+
+/// URIs of the CRL distribution points of `certificate`.
+fn crl_urls(certificate: &X509) -> Vec<String> {
+    let mut urls = Vec::new();
+    for point in certificate.crl_distribution_points().into_iter().flatten() {
+        let names = point.distpoint().and_then(|name| name.fullname());
+        for name in names.into_iter().flatten() {
+            urls.extend(name.uri().map(str::to_string));
+        }
+    }
+    urls
+}
+
+/// OpenSSL's printed time (`Jan  1 00:00:00 2026 GMT`) as a timestamp.
+fn asn1_time(printed: &str) -> Option<DateTime<Utc>> {
+    NaiveDateTime::parse_from_str(printed, "%b %e %H:%M:%S %Y GMT")
+        .ok()
+        .map(|time| time.and_utc())
+}
+
+pub(crate) fn subject(certificate: &X509) -> String {
+    certificate
+        .subject_name()
+        .entries()
+        .filter_map(|entry| {
+            let key = entry.object().nid().short_name().ok()?;
+            Some(format!("{key}={}", entry.data().to_string().ok()?))
+        })
+        .collect::<Vec<_>>()
+        .join(", ")
+}
+
+pub(crate) fn fingerprint(certificate: &X509) -> String {
+    certificate
+        .digest(MessageDigest::sha256())
+        .map(|digest| digest.iter().map(|byte| format!("{byte:02x}")).collect())
+        .unwrap_or_default()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::clock::ManualClock;
+    use chrono::TimeZone;
+    use rcgen::{
+        date_time_ymd, BasicConstraints, CertificateParams, CertificateRevocationListParams,
+        CrlDistributionPoint, DnType, IsCa, KeyIdMethod, KeyPair, KeyUsagePurpose,
+        RevokedCertParams, SerialNumber,
+    };
+
+    const CRL_URL: &str = "http://crl.example/ca.crl";
+
+    struct StaticFetcher {
+        crl: Vec<u8>,
+        fetches: Mutex<usize>,
+    }
+
+    impl RevocationFetcher for StaticFetcher {
+        fn get(&self, url: &str) -> Result<Vec<u8>, String> {
+            assert_eq!(url, CRL_URL);
+            *self.fetches.lock().unwrap() += 1;
+            Ok(self.crl.clone())
+        }
+
+        fn ocsp(&self, url: &str, _: &[u8]) -> Result<Vec<u8>, String> {
+            Err(format!("{url} unreachable"))
+        }
+    }
+
+    #[test]
+    fn checks_chain_against_cached_crls() {
+        let ca_key = KeyPair::generate().unwrap();
+        let mut params = CertificateParams::new(Vec::<String>::new()).unwrap();
+        params.is_ca = IsCa::Ca(BasicConstraints::Unconstrained);
+        params.key_usages = vec![KeyUsagePurpose::KeyCertSign, KeyUsagePurpose::CrlSign];
+        params
+            .distinguished_name
+            .push(DnType::CommonName, "Test CA");
+        let ca = params.self_signed(&ca_key).unwrap();
+
+        let leaf = |name: &str, serial: u64, crl: bool| {
+            let mut params = CertificateParams::new(vec![format!("{name}.example")]).unwrap();
+            params.distinguished_name.push(DnType::CommonName, name);
+            params.serial_number = Some(SerialNumber::from(serial));
+            if crl {
+                params.crl_distribution_points = vec![CrlDistributionPoint {
+                    uris: vec![CRL_URL.into()],
+                }];
+            }
+            let certificate = params
+                .signed_by(&KeyPair::generate().unwrap(), &ca, &ca_key)
+                .unwrap();
+            X509::from_der(certificate.der()).unwrap()
+        };
+        let crl = CertificateRevocationListParams {
+            this_update: date_time_ymd(2026, 1, 1),
+            next_update: date_time_ymd(2026, 2, 1),
+            crl_number: SerialNumber::from(1),
+            issuing_distribution_point: None,
+            revoked_certs: vec![RevokedCertParams {
+                serial_number: SerialNumber::from(7),
+                revocation_time: date_time_ymd(2026, 1, 5),
+                reason_code: None,
+                invalidity_date: None,
+            }],
+            key_identifier_method: KeyIdMethod::Sha256,
+        }
+        .signed_by(&ca, &ca_key)
+        .unwrap();
+
+        let fetcher = Arc::new(StaticFetcher {
+            crl: crl.der().to_vec(),
+            fetches: Mutex::new(0),
+        });
+        let clock = ManualClock::new(Utc.with_ymd_and_hms(2026, 1, 10, 0, 0, 0).unwrap());
+        let server = leaf("server", 3, true);
+        let checker = RevocationChecker::new(
+            vec![server.clone(), X509::from_der(ca.der()).unwrap()],
+            fetcher.clone(),
+        )
+        .with_clock(SharedClock::new(clock.clone()));
+
+        let status = checker.refresh();
+        assert!(status.revocation_checked && status.revoked.is_empty());
+        assert_eq!(
+            checker.check(&leaf("client", 7, true)),
+            Ok(Revocation::Revoked {
+                revoked_at: Some(Utc.with_ymd_and_hms(2026, 1, 5, 0, 0, 0).unwrap()),
+            })
+        );
+        assert_eq!(*fetcher.fetches.lock().unwrap(), 1);
+
+        clock.advance(chrono::Duration::days(30));
+        checker.refresh();
+        assert_eq!(*fetcher.fetches.lock().unwrap(), 2);
+
+        let unchecked = checker.check(&leaf("legacy", 9, false)).unwrap_err();
+        assert_eq!(unchecked, "no OCSP responder or CRL distribution point");
+        let responder = checker.with_responder(Some("http://ocsp.example".into()));
+        assert!(responder.status().ocsp_responder_configured);
+        assert_eq!(responder.check(&server), Ok(Revocation::Good));
+    }
+}
@@ -0,0 +1,222 @@
+//! Resolution of secret references in the configuration.
+//!
+//! Secrets are not written into the configuration file; settings ending in
+//! `Ref` name where to find them instead:
+//!
+//! - `keychain:<service>/<account>` reads the operating system keychain and,
+//!   when the entry cannot be read, falls back to the environment variable
+//!   `X400_SECRET_<ACCOUNT>` (upper-cased, `-` and `.` as `_`) for CI hosts
+//!   without a keychain;
+//! - `env:<VAR>` reads an environment variable;
+//! - `file:<path>` reads a file, such as a mounted container secret, without
+//!   its trailing newline.
+//!
+//! [`SecretResolver::resolve_config`] fills `gateway.smtp.password`,
+//! `directory.ldap.bindPassword`, `security.apiKey`, the named API keys,
+//! `database.backupKey` and `database.sqlcipherKey` from their references at
+//! startup.
+
+use std::env;
+use std::fs;
+use std::sync::Arc;
+
+use thiserror::Error;
+use tracing::debug;
+
+use crate::config::AppConfig;
+use crate::keychain::{KeychainError, KeychainRef};
+
+/// Prefix of the environment variables keychain entries fall back to.
+const ENV_FALLBACK_PREFIX: &str = "X400_SECRET_";
+
+#[derive(Debug, Error, PartialEq, Eq)]
+pub enum SecretError {
+    #[error("{reference}: {source}")]
+    Keychain {
+        reference: String,
+        source: KeychainError,
+    },
+    #[error("{0}: environment variable is not set")]
+    MissingEnv(String),
+    #[error("{reference}: {reason}")]
+    File { reference: String, reason: String },
+    #[error("{0} is not a keychain:, env: or file: reference")]
+    Unsupported(String),
+}
+
+/// Reads keychain entries; replaced in tests.
+pub trait Keychain: Send + Sync {
+    fn lookup(&self, entry: &KeychainRef) -> Result<String, KeychainError>;
+}
+
+/// The operating system keychain.
+pub struct SystemKeychain;
+
+impl Keychain for SystemKeychain {
+    fn lookup(&self, entry: &KeychainRef) -> Result<String, KeychainError> {
+        entry.lookup()
+    }
+}
+
+#[derive(Clone)]
+pub struct SecretResolver {
+    keychain: Arc<dyn Keychain>,
+}
+
+impl Default for SecretResolver {
+    fn default() -> Self {
+        Self::new(Arc::new(SystemKeychain))
+    }
+}
+
+impl SecretResolver {
+    pub fn new(keychain: Arc<dyn Keychain>) -> Self {
+        Self { keychain }
+    }
+
+    /// The secret `reference` points at.
+    pub fn resolve(&self, reference: &str) -> Result<String, SecretError> {
+        let reference = reference.trim();
+        if let Some(name) = reference.strip_prefix("env:") {
+            return env::var(name).map_err(|_| SecretError::MissingEnv(reference.to_string()));
+        }
+        if let Some(path) = reference.strip_prefix("file:") {
+            return fs::read_to_string(path)
+                .map(|secret| secret.trim_end_matches(['\r', '\n']).to_string())
+                .map_err(|err| SecretError::File {
+                    reference: reference.to_string(),
+                    reason: err.to_string(),
+                });
+        }
+        if reference.starts_with("keychain:") {
+            let keychain_error = |source| SecretError::Keychain {
+                reference: reference.to_string(),
+                source,
+            };
+            let entry = KeychainRef::parse(reference).map_err(keychain_error)?;
+            return match self.keychain.lookup(&entry) {
+                Ok(secret) => Ok(secret),
+                Err(error) => {
+                    let fallback = env_fallback(&entry.account);
+                    debug!(target = "secrets", reference, %error, fallback, "keychain lookup failed");
+                    env::var(&fallback).map_err(|_| keychain_error(error))
+                }
+            };
+        }
+        Err(SecretError::Unsupported(reference.to_string()))
+    }
+
+    /// Fill every secret of `config` that has a reference. Secrets that
+    /// cannot be resolved are left as they were and returned, so one missing
+    /// entry does not keep the others from loading.
+    pub fn resolve_config(&self, config: &mut AppConfig) -> Vec<SecretError> {
+        let mut errors = Vec::new();
+        let mut fill = |reference: &Option<String>, secret: &mut Option<String>| {
+            if let Some(reference) = reference {
+                match self.resolve(reference) {
+                    Ok(value) => *secret = Some(value),
+                    Err(error) => errors.push(error),
+                }
+            }
+        };
+        let smtp = &mut config.gateway.smtp;
+        fill(&smtp.password_ref, &mut smtp.password);
+        let ldap = &mut config.directory.ldap;
+        fill(&ldap.bind_password_ref, &mut ldap.bind_password);
+        let security = &mut config.security;
+        fill(&security.api_key_ref, &mut security.api_key);
+        let database = &mut config.database;
+        fill(&database.backup_key_ref, &mut database.backup_key);
+        fill(&database.sqlcipher_key_ref, &mut database.sqlcipher_key);
+        let upload = &mut config.support.upload;
+        fill(&upload.auth_token_ref, &mut upload.auth_token);
+        for key in &mut config.security.keys {
+            let mut secret = None;
+            fill(&key.secret_ref, &mut secret);
+            if let Some(secret) = secret {
+                key.secret = secret;
+            }
+        }
+        errors
+    }
+}
+
+fn env_fallback(account: &str) -> String {
+    let suffix: String = account
+        .chars()
+        .map(|c| match c {
+            '-' | '.' => '_',
+            c => c.to_ascii_uppercase(),
+        })
+        .collect();
+    format!("{ENV_FALLBACK_PREFIX}{suffix}")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::HashMap;
+
+    struct FakeKeychain(HashMap<String, String>);
+
+    impl Keychain for FakeKeychain {
+        fn lookup(&self, entry: &KeychainRef) -> Result<String, KeychainError> {
+            self.0
+                .get(&entry.account)
+                .cloned()
+                .ok_or_else(|| KeychainError::NotFound {
+                    service: entry.service.clone(),
+                    account: entry.account.clone(),
+                })
+        }
+    }
+
+    #[test]
+    fn resolves_keychain_env_and_file_references() {
+        let dir = tempfile::tempdir().unwrap();
+        let ldap = dir.path().join("ldap-password");
+        fs::write(&ldap, "bind-secret\n").unwrap();
+        env::set_var("X400_SECRET_API_KEY", "from-env");
+        env::set_var("X400_TEST_MONITORING_KEY", "monitoring-secret");
+
+        let keychain = FakeKeychain(HashMap::from([("smtp".into(), "smtp-secret".into())]));
+        let resolver = SecretResolver::new(Arc::new(keychain));
+        let mut config = AppConfig::default();
+        for (key, value) in [
+            (
+                "gateway.smtp.passwordRef",
+                "keychain:x400-core/smtp".to_string(),
+            ),
+            (
+                "directory.ldap.bindPasswordRef",
+                format!("file:{}", ldap.display()),
+            ),
+            ("security.apiKeyRef", "keychain:x400-core/api-key".into()),
+            (
+                "security.keyRefs.monitoring",
+                "env:X400_TEST_MONITORING_KEY".into(),
+            ),
+            ("database.backupKeyRef", "keychain:x400-core/backup".into()),
+        ] {
+            config.apply(key, &value).unwrap();
+        }
+
+        let errors = resolver.resolve_config(&mut config);
+        assert_eq!(config.gateway.smtp.password.as_deref(), Some("smtp-secret"));
+        assert_eq!(
+            config.directory.ldap.bind_password.as_deref(),
+            Some("bind-secret")
+        );
+        assert_eq!(config.security.api_key.as_deref(), Some("from-env"));
+        assert_eq!(config.security.keys[0].secret, "monitoring-secret");
+        assert_eq!(config.database.backup_key, None);
+        assert!(matches!(
+            errors.as_slice(),
+            [SecretError::Keychain { reference, .. }] if reference == "keychain:x400-core/backup"
+        ));
+        assert_eq!(
+            resolver.resolve("hunter2"),
+            Err(SecretError::Unsupported("hunter2".into()))
+        );
+    }
+}
@@ -0,0 +1,639 @@
+//! S/MIME protection of submitted messages.
+//!
+//! With `security.smime.enabled`, a message whose envelope asks for signing
+//! or encryption is protected on submit, before it reaches the transport. The
+//! body is wrapped as a `text/plain` MIME entity and turned into a CMS
+//! `ContentInfo`: `SignedData` with the content attached, `EnvelopedData`
+//! (AES-256-CBC) or, when both are asked for, the signed entity enveloped.
+//! Messages are encrypted to every recipient, with certificates found by the
+//! [`CertificateResolver`], and to the service certificate; a message with a
+//! recipient that has no certificate is refused.
+//! The DER result is stored base64 encoded in [`MessageSecurity::cms`], next
+//! to the readable original.
+//!
+//! Fetched mail goes the other way through [`SmimeService::open`]:
+//! `application/pkcs7-mime` entities are decrypted with the local key or
+//! verified, `multipart/signed` entities are verified against their detached
+//! signature, until a plain entity is left. Signers are checked against the
+//! CA certificates in `security.smime.trustStore`; a signature that is intact
+//! but does not chain to them is reported as untrusted, not accepted.
+
+use std::fmt;
+use std::fs;
+use std::path::Path;
+use std::sync::Arc;
+
+use base64::engine::general_purpose::STANDARD;
+use base64::Engine as _;
+use openssl::cms::{CMSOptions, CmsContentInfo};
+use openssl::error::ErrorStack;
+use openssl::pkey::{PKey, Private};
+use openssl::provider::Provider;
+use openssl::stack::Stack;
+use openssl::symm::Cipher;
+use openssl::x509::store::{X509Store, X509StoreBuilder};
+use openssl::x509::X509;
+use thiserror::Error;
+
+use crate::certificates::CertificateResolver;
+use crate::config::{Pkcs11Config, SmimeConfig, SmimeKeySource};
+use crate::gateway::address_map::or_string;
+use crate::gateway::inbound::split_headers;
+use crate::gateway::mime;
+use crate::models::{Address, EncryptionStatus, Message, MessageSecurity, SignatureStatus};
+use crate::pkcs11;
+use crate::secrets::SecretResolver;
+
+/// DER of the `pkcs-7` content type OID arc; the next byte tells the type.
+const PKCS7_OID: [u8; 10] = [0x06, 0x09, 0x2a, 0x86, 0x48, 0x86, 0xf7, 0x0d, 0x01, 0x07];
+const SIGNED_DATA: u8 = 2;
+const ENVELOPED_DATA: u8 = 3;
+
+/// Signing and encryption layers removed from one received message at most.
+const MAX_LAYERS: usize = 4;
+
+#[derive(Debug, Error, PartialEq, Eq)]
+pub enum SmimeError {
+    #[error("message asks for S/MIME but security.smime.enabled is off")]
+    Disabled,
+    #[error("failed to load {path}: {reason}")]
+    Credentials { path: String, reason: String },
+    #[error("no encryption certificate for {}", .0.join(", "))]
+    MissingCertificates(Vec<String>),
+    #[error("CMS operation failed: {0}")]
+    Cms(String),
+}
+
+impl From<ErrorStack> for SmimeError {
+    fn from(error: ErrorStack) -> Self {
+        Self::Cms(error.to_string())
+    }
+}
+
+struct Credentials {
+    certificate: X509,
+    key: PKey<Private>,
+    /// Serves `key` when it lives on a PKCS#11 token; dropped after it.
+    _provider: Option<Provider>,
+}
+
+/// A received message with its S/MIME layers removed.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct OpenedMessage {
+    /// The original headers over the innermost entity, or the message as
+    /// fetched when it could not be decrypted.
+    pub raw: String,
+    pub signature: Option<SignatureStatus>,
+    pub encryption: Option<EncryptionStatus>,
+}
+
+/// Signs and encrypts with the service's own certificate and key, and
+/// verifies received signatures against the trust store.
+#[derive(Clone, Default)]
+pub struct SmimeService {
+    credentials: Option<Arc<Credentials>>,
+    trust: Option<Arc<X509Store>>,
+    resolver: Option<CertificateResolver>,
+}
+
+impl fmt::Debug for SmimeService {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("SmimeService")
+            .field("enabled", &self.is_enabled())
+            .finish()
+    }
+}
+
+impl SmimeService {
+    /// A service that refuses every message asking for protection.
+    pub fn disabled() -> Self {
+        Self::default()
+    }
+
+    /// Load `security.smime.certificate`, the key from
+    /// `security.smime.privateKey` or the PKCS#11 token, and, when the file
+    /// exists, `security.smime.trustStore`; disabled unless
+    /// `security.smime.enabled` is set.
+    pub fn from_config(config: &SmimeConfig) -> Result<Self, SmimeError> {
+        if !config.enabled {
+            return Ok(Self::disabled());
+        }
+        let read = |path: &str| {
+            fs::read(path).map_err(|err| SmimeError::Credentials {
+                path: path.to_string(),
+                reason: err.to_string(),
+            })
+        };
+        let certificate = read(&config.certificate_path)?;
+        let service = match config.key_source {
+            SmimeKeySource::File => Self::from_pem(&certificate, &read(&config.private_key_path)?)?,
+            SmimeKeySource::Pkcs11 => Self::from_token(&certificate, &config.pkcs11)?,
+        };
+        if !Path::new(&config.trust_store_path).exists() {
+            return Ok(service);
+        }
+        service.with_trust_store(&read(&config.trust_store_path)?)
+    }
+
+    pub fn from_pem(certificate: &[u8], key: &[u8]) -> Result<Self, SmimeError> {
+        let invalid = |what: &str, error: ErrorStack| SmimeError::Credentials {
+            path: what.to_string(),
+            reason: error.to_string(),
+        };
+        let certificate =
+            X509::from_pem(certificate).map_err(|error| invalid("certificate", error))?;
+        let key = PKey::private_key_from_pem(key).map_err(|error| invalid("private key", error))?;
+        Ok(Self {
+            credentials: Some(Arc::new(Credentials {
+                certificate,
+                key,
+                _provider: None,
+            })),
+            trust: None,
+            resolver: None,
+        })
+    }
+
+    /// Sign and decrypt with the key on the token `pkcs11` describes, its
+    /// PIN resolved from the secret reference in `pin_ref`.
+    pub fn from_token(certificate: &[u8], pkcs11: &Pkcs11Config) -> Result<Self, SmimeError> {
+        let failed = |reason: String| SmimeError::Credentials {
+            path: pkcs11.module.clone(),
+            reason,
+        };
+        let certificate = X509::from_pem(certificate).map_err(|error| SmimeError::Credentials {
+            path: "certificate".into(),
+            reason: error.to_string(),
+        })?;
+        let pin = match &pkcs11.pin_ref {
+            Some(reference) => Some(
+                SecretResolver::default()
+                    .resolve(reference)
+                    .map_err(|error| failed(error.to_string()))?,
+            ),
+            None => None,
+        };
+        let token = pkcs11::load_key(pkcs11, pin.as_deref()).map_err(failed)?;
+        if !certificate.public_key()?.public_eq(&token.key) {
+            return Err(failed(
+                "key on the token does not match the certificate".into(),
+            ));
+        }
+        Ok(Self {
+            credentials: Some(Arc::new(Credentials {
+                certificate,
+                key: token.key,
+                _provider: Some(token.provider),
+            })),
+            trust: None,
+            resolver: None,
+        })
+    }
+
+    /// Verify signers against the CA certificates in the PEM bundle `pem`.
+    /// Without a trust store every signer is untrusted.
+    pub fn with_trust_store(mut self, pem: &[u8]) -> Result<Self, SmimeError> {
+        let certificates = X509::stack_from_pem(pem).map_err(|error| SmimeError::Credentials {
+            path: "trust store".into(),
+            reason: error.to_string(),
+        })?;
+        let mut store = X509StoreBuilder::new()?;
+        for certificate in certificates {
+            store.add_cert(certificate)?;
+        }
+        self.trust = Some(Arc::new(store.build()));
+        Ok(self)
+    }
+
+    /// Encrypt to the recipients' certificates; without a resolver messages
+    /// are encrypted to the service certificate only.
+    pub fn with_resolver(mut self, resolver: CertificateResolver) -> Self {
+        self.resolver = Some(resolver);
+        self
+    }
+
+    pub fn is_enabled(&self) -> bool {
+        self.credentials.is_some()
+    }
+
+    /// DER `SignedData` over `content`, which is kept inside.
+    pub fn sign(&self, content: &[u8]) -> Result<Vec<u8>, SmimeError> {
+        let credentials = self.credentials()?;
+        let signed = CmsContentInfo::sign(
+            Some(&credentials.certificate),
+            Some(&credentials.key),
+            None,
+            Some(content),
+            CMSOptions::BINARY,
+        )?;
+        Ok(signed.to_der()?)
+    }
+
+    /// DER `EnvelopedData` of `content` for `recipients` and the service
+    /// certificate.
+    pub fn encrypt(&self, content: &[u8], recipients: &[Address]) -> Result<Vec<u8>, SmimeError> {
+        let credentials = self.credentials()?;
+        let mut certificates = Stack::new()?;
+        certificates.push(credentials.certificate.clone())?;
+        if let Some(resolver) = &self.resolver {
+            let resolved = resolver.resolve(recipients);
+            if !resolved.missing.is_empty() {
+                return Err(SmimeError::MissingCertificates(
+                    resolved.missing.iter().map(or_string).collect(),
+                ));
+            }
+            for certificate in resolved.certificates {
+                certificates.push(certificate)?;
+            }
+        }
+        let enveloped = CmsContentInfo::encrypt(
+            &certificates,
+            content,
+            Cipher::aes_256_cbc(),
+            CMSOptions::BINARY,
+        )?;
+        Ok(enveloped.to_der()?)
+    }
+
+    /// Apply the protection `message` asks for and store the result in its
+    /// envelope. Returns whether anything was applied.
+    pub fn protect(&self, message: &mut Message) -> Result<bool, SmimeError> {
+        let MessageSecurity { sign, encrypt, .. } = message.envelope.security;
+        if !sign && !encrypt {
+            return Ok(false);
+        }
+        let mut entity = text_entity(&message.content.body);
+        if sign {
+            let signed = self.sign(&entity)?;
+            if !encrypt {
+                message.envelope.security.cms = Some(STANDARD.encode(signed));
+                return Ok(true);
+            }
+            entity =
+                b"Content-Type: application/pkcs7-mime; smime-type=signed-data; name=smime.p7m\r\n\
+                Content-Transfer-Encoding: binary\r\n\r\n"
+                    .to_vec();
+            entity.extend_from_slice(&signed);
+        }
+        let enveloped = self.encrypt(&entity, &message.envelope.recipients)?;
+        message.envelope.security.cms = Some(STANDARD.encode(enveloped));
+        Ok(true)
+    }
+
+    /// Remove the signing and encryption layers of the fetched RFC 822
+    /// message `raw`. `None` when S/MIME is off or `raw` is not S/MIME.
+    pub fn open(&self, raw: &str) -> Option<OpenedMessage> {
+        let credentials = self.credentials.as_deref()?;
+        let mut opened = OpenedMessage {
+            raw: raw.to_string(),
+            signature: None,
+            encryption: None,
+        };
+        let mut entity = None;
+        for _ in 0..MAX_LAYERS {
+            let current = entity.as_deref().unwrap_or(raw.as_bytes());
+            match self.unwrap_layer(credentials, current, &mut opened) {
+                Some(inner) => entity = Some(inner),
+                None => break,
+            }
+        }
+        if opened.signature.is_none() && opened.encryption.is_none() {
+            return None;
+        }
+        if let Some(entity) = entity {
+            let (headers, _) = split_headers(raw);
+            let mut rebuilt = String::new();
+            for (name, value) in &headers {
+                let name_lower = name.to_ascii_lowercase();
+                if !name_lower.starts_with("content-") && name_lower != "mime-version" {
+                    rebuilt.push_str(&format!("{name}: {value}\r\n"));
+                }
+            }
+            rebuilt.push_str("MIME-Version: 1.0\r\n");
+            rebuilt.push_str(&String::from_utf8_lossy(&entity));
+            opened.raw = rebuilt;
+        }
+        Some(opened)
+    }
+
+    /// The entity inside `entity` when it is signed or encrypted, recording
+    /// what was found in `opened`. Entities are bytes because decrypted
+    /// content may carry binary CMS.
+    fn unwrap_layer(
+        &self,
+        credentials: &Credentials,
+        entity: &[u8],
+        opened: &mut OpenedMessage,
+    ) -> Option<Vec<u8>> {
+        let (headers, body) = split_entity(entity);
+        let header = |name: &str| {
+            headers
+                .iter()
+                .find(|(key, _)| key.eq_ignore_ascii_case(name))
+                .map(|(_, value)| value.as_str())
+        };
+        let content_type = header("Content-Type").unwrap_or("text/plain");
+        let mime_type = content_type
+            .split(';')
+            .next()
+            .unwrap_or_default()
+            .trim()
+            .to_ascii_lowercase();
+        match mime_type.as_str() {
+            "multipart/signed" => {
+                let boundary = mime::parameter(content_type, "boundary")?;
+                let body = String::from_utf8_lossy(body);
+                let [content, signature] = mime::split_multipart(&body, &boundary)[..] else {
+                    return None;
+                };
+                let (_, signature) = split_headers(signature);
+                let mut cms = CmsContentInfo::from_der(&decode_base64(signature)?).ok()?;
+                let content = canonical(content);
+                let (status, _) = self.verify(&mut cms, Some(content.as_bytes()));
+                opened.signature = opened.signature.max(Some(status));
+                Some(content.into_bytes())
+            }
+            "application/pkcs7-mime" | "application/x-pkcs7-mime" => {
+                let der = match header("Content-Transfer-Encoding") {
+                    Some(encoding) if encoding.trim().eq_ignore_ascii_case("base64") => {
+                        decode_base64(&String::from_utf8_lossy(body))?
+                    }
+                    _ => body.to_vec(),
+                };
+                let mut cms = CmsContentInfo::from_der(&der).ok()?;
+                match pkcs7_type(&der)? {
+                    SIGNED_DATA => {
+                        let (status, content) = self.verify(&mut cms, None);
+                        opened.signature = opened.signature.max(Some(status));
+                        Some(content)
+                    }
+                    ENVELOPED_DATA => {
+                        match cms.decrypt(&credentials.key, &credentials.certificate) {
+                            Ok(content) => {
+                                opened.encryption = Some(EncryptionStatus::Decrypted);
+                                Some(content)
+                            }
+                            Err(_) => {
+                                opened.encryption = Some(EncryptionStatus::Undecryptable);
+                                None
+                            }
+                        }
+                    }
+                    _ => None,
+                }
+            }
+            _ => None,
+        }
+    }
+
+    /// Check a signature against the trust store, telling an untrusted signer
+    /// from content that does not match. Also returns the signed content.
+    fn verify(
+        &self,
+        cms: &mut CmsContentInfo,
+        detached: Option<&[u8]>,
+    ) -> (SignatureStatus, Vec<u8>) {
+        let mut content = Vec::new();
+        if let Some(trust) = &self.trust {
+            if cms
+                .verify(
+                    None,
+                    Some(trust),
+                    detached,
+                    Some(&mut content),
+                    CMSOptions::BINARY,
+                )
+                .is_ok()
+            {
+                return (SignatureStatus::Valid, content);
+            }
+            content.clear();
+        }
+        let unchecked_signer = CMSOptions::BINARY | CMSOptions::NO_SIGNER_CERT_VERIFY;
+        if cms
+            .verify(None, None, detached, Some(&mut content), unchecked_signer)
+            .is_ok()
+        {
+            return (SignatureStatus::Untrusted, content);
+        }
+        content.clear();
+        // Still show what a broken signature covers.
+        let _ = cms.verify(
+            None,
+            None,
+            detached,
+            Some(&mut content),
+            unchecked_signer | CMSOptions::NO_CONTENT_VERIFY | CMSOptions::NO_ATTR_VERIFY,
+        );
+        (SignatureStatus::Invalid, content)
+    }
+
+    fn credentials(&self) -> Result<&Credentials, SmimeError> {
+        self.credentials.as_deref().ok_or(SmimeError::Disabled)
+    }
+}
+
+/// Headers and body of a MIME entity that may carry binary content.
+fn split_entity(entity: &[u8]) -> (Vec<(String, String)>, &[u8]) {
+    let (head, body) = match entity.windows(4).position(|w| w == b"\r\n\r\n") {
+        Some(index) => (&entity[..index], &entity[index + 4..]),
+        None => match entity.windows(2).position(|w| w == b"\n\n") {
+            Some(index) => (&entity[..index], &entity[index + 2..]),
+            None => (entity, &[][..]),
+        },
+    };
+    (split_headers(&String::from_utf8_lossy(head)).0, body)
+}
+
+/// `SIGNED_DATA`, `ENVELOPED_DATA` or another PKCS #7 type from the outer
+/// `ContentInfo` of `der`.
+fn pkcs7_type(der: &[u8]) -> Option<u8> {
+    let head = &der[..der.len().min(16)];
+    let at = head
+        .windows(PKCS7_OID.len())
+        .position(|window| window == PKCS7_OID)?;
+    head.get(at + PKCS7_OID.len()).copied()
+}
+
+fn decode_base64(body: &str) -> Option<Vec<u8>> {
+    let compact: String = body.chars().filter(|c| !c.is_whitespace()).collect();
+    STANDARD.decode(compact).ok()
+}
+
+/// `entity` with CRLF line breaks, the form signatures are computed over.
+fn canonical(entity: &str) -> String {
+    entity.replace("\r\n", "\n").replace('\n', "\r\n")
+}
+
+/// The body as a canonical (CRLF) `text/plain` entity.
+fn text_entity(body: &str) -> Vec<u8> {
+    let mut entity = String::from(
+        "Content-Type: text/plain; charset=utf-8\r\nContent-Transfer-Encoding: 8bit\r\n\r\n",
+    );
+    for line in body.lines() {
+        entity.push_str(line);
+        entity.push_str("\r\n");
+    }
+    entity.into_bytes()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::models::{Address, MessageContent, MessageEnvelope};
+    use rcgen::{BasicConstraints, CertificateParams, DnType, IsCa, KeyPair};
+
+    /// A service whose certificate is issued by a fresh CA, and that CA.
+    fn service() -> (SmimeService, String) {
+        let ca_key = KeyPair::generate().unwrap();
+        let mut ca = CertificateParams::new(Vec::<String>::new()).unwrap();
+        ca.is_ca = IsCa::Ca(BasicConstraints::Unconstrained);
+        ca.distinguished_name.push(DnType::CommonName, "Test CA");
+        let ca = ca.self_signed(&ca_key).unwrap();
+
+        let key = KeyPair::generate().unwrap();
+        let mut params = CertificateParams::new(Vec::<String>::new()).unwrap();
+        params
+            .distinguished_name
+            .push(DnType::CommonName, "gateway@example.com");
+        let certificate = params.signed_by(&key, &ca, &ca_key).unwrap();
+        let service =
+            SmimeService::from_pem(certificate.pem().as_bytes(), key.serialize_pem().as_bytes())
+                .unwrap();
+        (service, ca.pem())
+    }
+
+    fn message(body: &str) -> Message {
+        let address = |surname: &str| Address {
+            country: "DE".into(),
+            organization: "Org".into(),
+            surname: surname.into(),
+        };
+        Message {
+            envelope: MessageEnvelope::new("Report", address("Sender"), vec![address("Reader")]),
+            content: MessageContent {
+                body: body.into(),
+                attachments: Vec::new(),
+            },
+        }
+    }
+
+    #[test]
+    fn signs_then_encrypts_requested_messages() {
+        let (smime, _) = service();
+        let mut message = message("Quarterly figures\nattached");
+        assert!(!smime.protect(&mut message).unwrap());
+
+        message.envelope.security.sign = true;
+        message.envelope.security.encrypt = true;
+        assert!(smime.protect(&mut message).unwrap());
+        assert_eq!(message.content.body, "Quarterly figures\nattached");
+
+        let der = STANDARD
+            .decode(message.envelope.security.cms.as_deref().unwrap())
+            .unwrap();
+        let credentials = smime.credentials().unwrap();
+        let inner = CmsContentInfo::from_der(&der)
+            .unwrap()
+            .decrypt(&credentials.key, &credentials.certificate)
+            .unwrap();
+        let split = inner.windows(4).position(|w| w == b"\r\n\r\n").unwrap();
+        let mut signed = CmsContentInfo::from_der(&inner[split + 4..]).unwrap();
+        let mut content = Vec::new();
+        signed
+            .verify(
+                None,
+                None,
+                None,
+                Some(&mut content),
+                CMSOptions::NO_SIGNER_CERT_VERIFY | CMSOptions::BINARY,
+            )
+            .unwrap();
+        assert!(String::from_utf8(content)
+            .unwrap()
+            .ends_with("\r\n\r\nQuarterly figures\r\nattached\r\n"));
+
+        let err = SmimeService::disabled().protect(&mut message).unwrap_err();
+        assert_eq!(err, SmimeError::Disabled);
+
+        let empty = tempfile::tempdir().unwrap();
+        let err = smime
+            .with_resolver(CertificateResolver::new(empty.path()))
+            .protect(&mut message)
+            .unwrap_err();
+        assert_eq!(
+            err,
+            SmimeError::MissingCertificates(vec!["C=DE;O=Org;S=Reader".into()])
+        );
+    }
+
+    #[test]
+    fn opens_received_mail_and_checks_signers_against_the_trust_store() {
+        let (smime, ca) = service();
+        let trusted = smime.clone().with_trust_store(ca.as_bytes()).unwrap();
+        let headers = "From: a@example.com\r\nTo: b@example.com\r\nSubject: Figures\r\n";
+
+        let mut submitted = message("Quarterly figures");
+        submitted.envelope.security.sign = true;
+        submitted.envelope.security.encrypt = true;
+        smime.protect(&mut submitted).unwrap();
+        let enveloped = |message: &Message| {
+            format!(
+                "{headers}MIME-Version: 1.0\r\nContent-Type: application/pkcs7-mime; \
+                 smime-type=enveloped-data; name=smime.p7m\r\n\
+                 Content-Transfer-Encoding: base64\r\n\r\n{}\r\n",
+                message.envelope.security.cms.as_deref().unwrap()
+            )
+        };
+        let opened = trusted.open(&enveloped(&submitted)).unwrap();
+        assert_eq!(opened.signature, Some(SignatureStatus::Valid));
+        assert_eq!(opened.encryption, Some(EncryptionStatus::Decrypted));
+        let parsed = mime::parse(&opened.raw);
+        assert_eq!(parsed.header("Subject"), Some("Figures"));
+        assert_eq!(parsed.body.trim_end(), "Quarterly figures");
+        assert!(parsed.attachments.is_empty());
+        let untrusted = smime.open(&enveloped(&submitted)).unwrap();
+        assert_eq!(untrusted.signature, Some(SignatureStatus::Untrusted));
+
+        let content = "Content-Type: text/plain\r\n\r\nPay 100 EUR\r\n";
+        let credentials = smime.credentials().unwrap();
+        let signature = CmsContentInfo::sign(
+            Some(&credentials.certificate),
+            Some(&credentials.key),
+            None,
+            Some(content.as_bytes()),
+            CMSOptions::BINARY | CMSOptions::DETACHED,
+        )
+        .and_then(|signed| signed.to_der())
+        .unwrap();
+        let signed = |content: &str| {
+            format!(
+                "{headers}Content-Type: multipart/signed; protocol=\"application/pkcs7-signature\"; \
+                 micalg=sha-256; boundary=\"b1\"\r\n\r\n--b1\r\n{content}\r\n--b1\r\n\
+                 Content-Type: application/pkcs7-signature; name=smime.p7s\r\n\
+                 Content-Transfer-Encoding: base64\r\n\r\n{}\r\n--b1--\r\n",
+                STANDARD.encode(&signature)
+            )
+        };
+        let opened = trusted.open(&signed(content)).unwrap();
+        assert_eq!(opened.signature, Some(SignatureStatus::Valid));
+        assert_eq!(opened.encryption, None);
+        assert!(opened.raw.ends_with("\r\n\r\nPay 100 EUR\r\n"));
+        let tampered = trusted
+            .open(&signed(&content.replace("100", "900")))
+            .unwrap();
+        assert_eq!(tampered.signature, Some(SignatureStatus::Invalid));
+
+        let (other, _) = service();
+        let mut foreign = message("Not for us");
+        foreign.envelope.security.encrypt = true;
+        other.protect(&mut foreign).unwrap();
+        let opened = trusted.open(&enveloped(&foreign)).unwrap();
+        assert_eq!(opened.encryption, Some(EncryptionStatus::Undecryptable));
+        assert_eq!(opened.raw, enveloped(&foreign));
+
+        let plain = format!("{headers}Content-Type: text/plain\r\n\r\nHello\r\n");
+        assert!(trusted.open(&plain).is_none());
+        assert!(SmimeService::disabled().open(&signed(content)).is_none());
+    }
+}
@@ -1,11 +1,290 @@
-use std::collections::HashMap;
+use std::collections::{BTreeMap, BTreeSet, HashMap, HashSet};
+use std::fs::{self, OpenOptions};
+use std::io::{self, BufRead, BufReader, Write};
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicU64, Ordering};
 use std::sync::{Arc, Mutex};
 
-use crate::models::{Message, MessageId, MessageStatus};
+use chrono::{DateTime, Utc};
+use serde::de::DeserializeOwned;
+use serde::{Deserialize, Serialize};
+use tracing::warn;
+use uuid::Uuid;
+
+use crate::bulk::BulkAction;
+use crate::clock::SharedClock;
+use crate::message_table::MessageRow;
+use crate::models::{
+    AliasRecord, AuditRecord, FolderRecord, HeldContentRecord, IdempotencyRecord, Message,
+    MessageDetail, MessageFlags, MessageFlagsRecord, MessageId, MessageStatus, MigrationCheckpoint,
+    QueueEntry, Report, WebhookRecord,
+};
+
+/// Aggregates for one folder as served by `GET /folders/stats`.
+#[derive(Clone, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct FolderStats {
+    pub folder: String,
+    pub total: usize,
+    /// Messages [`is_unread`] counts, the same ones as in [`FlagCounts`].
+    pub unread: usize,
+    pub failed: usize,
+    /// Subject and body sizes plus the size of every attachment.
+    pub total_bytes: u64,
+    /// Age in seconds of the oldest message still `Queued`.
+    pub oldest_queued_age_seconds: Option<i64>,
+}
+
+/// Cached aggregates for one folder. The oldest queued message is kept as a
+/// timestamp and turned into an age on every read, so the cache never serves
+/// a stale age.
+#[derive(Clone)]
+struct CachedFolderStats {
+    stats: FolderStats,
+    oldest_queued_at: Option<DateTime<Utc>>,
+}
+
+impl CachedFolderStats {
+    fn aged(&self, now: DateTime<Utc>) -> FolderStats {
+        FolderStats {
+            oldest_queued_age_seconds: self
+                .oldest_queued_at
+                .map(|at| (now - at).num_seconds().max(0)),
+            ..self.stats.clone()
+        }
+    }
+}
+
+/// Messages of one conversation as served by `GET /threads`.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Thread {
+    pub thread_id: MessageId,
+    /// Subject of the earliest message listed.
+    pub subject: String,
+    pub latest_at: DateTime<Utc>,
+    pub messages: Vec<Message>,
+}
+
+impl Thread {
+    fn new((thread_id, mut messages): (MessageId, Vec<Message>)) -> Self {
+        messages.sort_by(|a, b| {
+            a.envelope
+                .created_at
+                .cmp(&b.envelope.created_at)
+                .then_with(|| a.envelope.id.0.cmp(&b.envelope.id.0))
+        });
+        Self {
+            thread_id,
+            subject: messages[0].envelope.subject.clone(),
+            latest_at: messages[messages.len() - 1].envelope.created_at,
+            messages,
+        }
+    }
+}
+
+/// Flag totals for one folder; `unread` follows [`is_unread`].
+#[derive(Clone, Debug, Default, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct FlagCounts {
+    pub unread: usize,
+    pub flagged: usize,
+    pub answered: usize,
+    pub labels: BTreeMap<String, usize>,
+}
+
+/// Whether a message counts as unread in folder statistics and flag counts.
+///
+/// A message is read once its `read` flag is set or a read report moved it
+/// to `Read`; drafts are never unread.
+pub fn is_unread(message: &Message, flags: Option<&MessageFlags>) -> bool {
+    !matches!(
+        message.envelope.status,
+        MessageStatus::Read | MessageStatus::Draft
+    ) && !flags.is_some_and(|flags| flags.read)
+}
+
+/// Every table of the store as of one instant, as written by
+/// [`StoreManager::backup_to`].
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct StoreSnapshot {
+    pub taken_at: DateTime<Utc>,
+    pub messages: Vec<MessageRow>,
+    pub flags: BTreeMap<MessageId, MessageFlags>,
+    pub reports: Vec<Report>,
+    pub queue: Vec<QueueEntry>,
+    pub idempotency: Vec<IdempotencyRecord>,
+    pub folders: Vec<FolderRecord>,
+    #[serde(default)]
+    pub aliases: Vec<AliasRecord>,
+    #[serde(default)]
+    pub webhooks: Vec<WebhookRecord>,
+    #[serde(default)]
+    pub held: Vec<HeldContentRecord>,
+    #[serde(default)]
+    pub migration_state: Vec<MigrationCheckpoint>,
+    #[serde(default)]
+    pub audit: Vec<AuditRecord>,
+}
+
+/// Stored messages with a `(folder, created_at)` index, so folder listings
+/// come back in date order without visiting every message, and a
+/// `content_hash` index for duplicate checks.
+#[derive(Default)]
+struct Messages {
+    rows: HashMap<MessageId, Message>,
+    by_folder: HashMap<String, BTreeSet<(DateTime<Utc>, MessageId)>>,
+    by_hash: HashMap<String, BTreeSet<MessageId>>,
+}
+
+impl Messages {
+    fn get(&self, id: &MessageId) -> Option<&Message> {
+        self.rows.get(id)
+    }
+
+    fn contains(&self, id: &MessageId) -> bool {
+        self.rows.contains_key(id)
+    }
+
+    fn values(&self) -> impl Iterator<Item = &Message> {
+        self.rows.values()
+    }
+
+    /// Messages filed under `folder`, newest first.
+    fn in_folder<'a>(&'a self, folder: &str) -> impl Iterator<Item = &'a Message> + 'a {
+        self.by_folder
+            .get(folder)
+            .into_iter()
+            .flat_map(|keys| keys.iter().rev())
+            .filter_map(|(_, id)| self.rows.get(id))
+    }
+
+    /// Insert a row exactly as stored, keeping its thread id.
+    fn restore(&mut self, message: Message) {
+        if let Some(old) = self.rows.remove(&message.envelope.id) {
+            self.unindex(&old);
+        }
+        self.index(&message);
+        self.rows.insert(message.envelope.id.clone(), message);
+    }
+
+    /// Insert `message` into its conversation: it joins the thread of the
+    /// message it replies to, and replies stored before it move into its thread.
+    fn insert(&mut self, mut message: Message) {
+        let id = message.envelope.id.clone();
+        let thread_id = message
+            .envelope
+            .in_reply_to
+            .as_deref()
+            .and_then(|parent| self.find_by_ipm(parent))
+            .map_or_else(|| id.clone(), |parent| parent.envelope.thread_id.clone());
+
+        let answers_this = |reply: &Message| {
+            reply.envelope.in_reply_to.as_deref().is_some_and(|parent| {
+                parent == id.0 || Some(parent) == message.envelope.origin_id.as_deref()
+            })
+        };
+        let early: HashSet<MessageId> = self
+            .rows
+            .values()
+            .filter(|reply| answers_this(reply) && reply.envelope.thread_id != thread_id)
+            .map(|reply| reply.envelope.thread_id.clone())
+            .collect();
+        if !early.is_empty() {
+            for stored in self.rows.values_mut() {
+                if early.contains(&stored.envelope.thread_id) {
+                    stored.envelope.thread_id = thread_id.clone();
+                }
+            }
+        }
+        message.envelope.thread_id = thread_id;
+        self.restore(message);
+    }
+
+    fn remove(&mut self, id: &MessageId) -> Option<Message> {
+        let removed = self.rows.remove(id)?;
+        self.unindex(&removed);
+        Some(removed)
+    }
+
+    /// Change a stored message in place, keeping the index in step.
+    fn update(&mut self, id: &MessageId, change: impl FnOnce(&mut Message)) -> bool {
+        let Some(mut message) = self.remove(id) else {
+            return false;
+        };
+        change(&mut message);
+        self.restore(message);
+        true
+    }
+
+    fn find_by_ipm(&self, ipm: &str) -> Option<&Message> {
+        self.rows.get(&MessageId(ipm.to_string())).or_else(|| {
+            self.rows
+                .values()
+                .find(|message| message.envelope.origin_id.as_deref() == Some(ipm))
+        })
+    }
+
+    fn index(&mut self, message: &Message) {
+        let envelope = &message.envelope;
+        self.by_folder
+            .entry(envelope.folder.clone())
+            .or_default()
+            .insert((envelope.created_at, envelope.id.clone()));
+        self.by_hash
+            .entry(message.content_hash())
+            .or_default()
+            .insert(envelope.id.clone());
+    }
+
+    fn unindex(&mut self, message: &Message) {
+        let envelope = &message.envelope;
+        if let Some(keys) = self.by_folder.get_mut(&envelope.folder) {
+            keys.remove(&(envelope.created_at, envelope.id.clone()));
+            if keys.is_empty() {
+                self.by_folder.remove(&envelope.folder);
+            }
+        }
+        let hash = message.content_hash();
+        if let Some(ids) = self.by_hash.get_mut(&hash) {
+            ids.remove(&envelope.id);
+            if ids.is_empty() {
+                self.by_hash.remove(&hash);
+            }
+        }
+    }
+}
 
 #[derive(Clone, Default)]
 pub struct StoreManager {
-    inner: Arc<Mutex<HashMap<MessageId, Message>>>,
+    inner: Arc<Mutex<Messages>>,
+    messages_path: Option<Arc<PathBuf>>,
+    reports: Arc<Mutex<HashMap<MessageId, Vec<Report>>>>,
+    flags: Arc<Mutex<HashMap<MessageId, MessageFlags>>>,
+    flags_path: Option<Arc<PathBuf>>,
+    stats: Arc<Mutex<Option<Vec<CachedFolderStats>>>>,
+    /// Bumped by every invalidation so a `folder_stats` computed from
+    /// messages that changed meanwhile is not cached.
+    stats_generation: Arc<AtomicU64>,
+    queue: Arc<Mutex<HashMap<MessageId, QueueEntry>>>,
+    queue_path: Option<Arc<PathBuf>>,
+    idempotency: Arc<Mutex<HashMap<String, IdempotencyRecord>>>,
+    idempotency_path: Option<Arc<PathBuf>>,
+    folders: Arc<Mutex<BTreeMap<String, FolderRecord>>>,
+    folders_path: Option<Arc<PathBuf>>,
+    aliases: Arc<Mutex<BTreeMap<String, AliasRecord>>>,
+    aliases_path: Option<Arc<PathBuf>>,
+    /// Bumped on every alias change so readers can cache the table.
+    aliases_version: Arc<AtomicU64>,
+    migration_state: Arc<Mutex<BTreeMap<(Uuid, PathBuf), MigrationCheckpoint>>>,
+    migration_state_path: Option<Arc<PathBuf>>,
+    webhooks: Arc<Mutex<BTreeMap<Uuid, WebhookRecord>>>,
+    webhooks_path: Option<Arc<PathBuf>>,
+    held: Arc<Mutex<BTreeMap<MessageId, HeldContentRecord>>>,
+    held_path: Option<Arc<PathBuf>>,
+    audit: Arc<Mutex<Vec<AuditRecord>>>,
+    audit_path: Option<Arc<PathBuf>>,
+    clock: SharedClock,
 }
 
 impl StoreManager {
@@ -13,43 +292,1107 @@ impl StoreManager {
         Self::default()
     }
 
+    pub fn with_clock(mut self, clock: SharedClock) -> Self {
+        self.clock = clock;
+        self
+    }
+
+    /// Persist the `messages` table to `path`, loading rows left by a previous
+    /// run.
+    pub fn with_message_table(mut self, path: impl Into<PathBuf>) -> Self {
+        let path = path.into();
+        match load_table::<MessageRow>(&path) {
+            Ok(rows) => {
+                if let Ok(mut messages) = self.inner.lock() {
+                    for row in rows {
+                        messages.restore(row.into());
+                    }
+                }
+            }
+            Err(err) => warn!(
+                target = "store",
+                path = %path.display(),
+                "ignoring unreadable messages table: {err}"
+            ),
+        }
+        self.messages_path = Some(Arc::new(path));
+        self
+    }
+
+    /// Persist the `queue` table to `path`, loading any rows left by a previous run.
+    pub fn with_queue_table(mut self, path: impl Into<PathBuf>) -> Self {
+        let path = path.into();
+        match load_table::<QueueEntry>(&path) {
+            Ok(rows) => {
+                if let Ok(mut queue) = self.queue.lock() {
+                    queue.extend(rows.into_iter().map(|row| (row.message_id.clone(), row)));
+                }
+            }
+            Err(err) => warn!(
+                target = "store",
+                path = %path.display(),
+                "ignoring unreadable queue table: {err}"
+            ),
+        }
+        self.queue_path = Some(Arc::new(path));
+        self
+    }
+
+    /// Persist the `idempotency` table to `path`, loading rows left by a previous run.
+    pub fn with_idempotency_table(mut self, path: impl Into<PathBuf>) -> Self {
+        let path = path.into();
+        match load_table::<IdempotencyRecord>(&path) {
+            Ok(rows) => {
+                if let Ok(mut table) = self.idempotency.lock() {
+                    table.extend(rows.into_iter().map(|row| (row.key.clone(), row)));
+                }
+            }
+            Err(err) => warn!(
+                target = "store",
+                path = %path.display(),
+                "ignoring unreadable idempotency table: {err}"
+            ),
+        }
+        self.idempotency_path = Some(Arc::new(path));
+        self
+    }
+
+    /// Persist the `folders` table to `path`, loading rows left by a previous run.
+    pub fn with_folder_table(mut self, path: impl Into<PathBuf>) -> Self {
+        let path = path.into();
+        match load_table::<FolderRecord>(&path) {
+            Ok(rows) => {
+                if let Ok(mut folders) = self.folders.lock() {
+                    folders.extend(rows.into_iter().map(|row| (row.id.clone(), row)));
+                }
+            }
+            Err(err) => warn!(
+                target = "store",
+                path = %path.display(),
+                "ignoring unreadable folders table: {err}"
+            ),
+        }
+        self.folders_path = Some(Arc::new(path));
+        self
+    }
+
+    /// Persist the `aliases` table to `path`, loading rows left by a previous run.
+    pub fn with_alias_table(mut self, path: impl Into<PathBuf>) -> Self {
+        let path = path.into();
+        match load_table::<AliasRecord>(&path) {
+            Ok(rows) => {
+                if let Ok(mut aliases) = self.aliases.lock() {
+                    aliases.extend(rows.into_iter().map(|row| (row.or_address.clone(), row)));
+                }
+                self.aliases_version.fetch_add(1, Ordering::SeqCst);
+            }
+            Err(err) => warn!(
+                target = "store",
+                path = %path.display(),
+                "ignoring unreadable aliases table: {err}"
+            ),
+        }
+        self.aliases_path = Some(Arc::new(path));
+        self
+    }
+
+    /// Persist the `flags` table to `path`, loading rows left by a previous run.
+    pub fn with_flags_table(mut self, path: impl Into<PathBuf>) -> Self {
+        let path = path.into();
+        match load_table::<MessageFlagsRecord>(&path) {
+            Ok(rows) => {
+                if let Ok(mut flags) = self.flags.lock() {
+                    flags.extend(rows.into_iter().map(|row| (row.message_id, row.flags)));
+                }
+            }
+            Err(err) => warn!(
+                target = "store",
+                path = %path.display(),
+                "ignoring unreadable flags table: {err}"
+            ),
+        }
+        self.flags_path = Some(Arc::new(path));
+        self
+    }
+
+    /// Persist the `webhooks` table to `path`, loading rows left by a previous run.
+    pub fn with_webhook_table(mut self, path: impl Into<PathBuf>) -> Self {
+        let path = path.into();
+        match load_table::<WebhookRecord>(&path) {
+            Ok(rows) => {
+                if let Ok(mut webhooks) = self.webhooks.lock() {
+                    webhooks.extend(rows.into_iter().map(|row| (row.id, row)));
+                }
+            }
+            Err(err) => warn!(
+                target = "store",
+                path = %path.display(),
+                "ignoring unreadable webhooks table: {err}"
+            ),
+        }
+        self.webhooks_path = Some(Arc::new(path));
+        self
+    }
+
+    /// Persist the `held_content` table to `path`, loading rows left by a
+    /// previous run.
+    pub fn with_held_content_table(mut self, path: impl Into<PathBuf>) -> Self {
+        let path = path.into();
+        match load_table::<HeldContentRecord>(&path) {
+            Ok(rows) => {
+                if let Ok(mut held) = self.held.lock() {
+                    held.extend(rows.into_iter().map(|row| (row.message_id.clone(), row)));
+                }
+            }
+            Err(err) => warn!(
+                target = "store",
+                path = %path.display(),
+                "ignoring unreadable held_content table: {err}"
+            ),
+        }
+        self.held_path = Some(Arc::new(path));
+        self
+    }
+
+    /// Persist the `migration_state` table to `path`, loading rows left by a
+    /// previous run.
+    pub fn with_migration_state_table(mut self, path: impl Into<PathBuf>) -> Self {
+        let path = path.into();
+        match load_table::<MigrationCheckpoint>(&path) {
+            Ok(rows) => {
+                if let Ok(mut state) = self.migration_state.lock() {
+                    state.extend(
+                        rows.into_iter()
+                            .map(|row| ((row.job_id, row.path.clone()), row)),
+                    );
+                }
+            }
+            Err(err) => warn!(
+                target = "store",
+                path = %path.display(),
+                "ignoring unreadable migration_state table: {err}"
+            ),
+        }
+        self.migration_state_path = Some(Arc::new(path));
+        self
+    }
+
+    /// Persist the `audit` table to `path`, one JSON row per line, loading
+    /// rows left by a previous run. Rows are only ever appended.
+    pub fn with_audit_table(mut self, path: impl Into<PathBuf>) -> Self {
+        let path = path.into();
+        match load_lines::<AuditRecord>(&path) {
+            Ok(rows) => {
+                if let Ok(mut audit) = self.audit.lock() {
+                    audit.extend(rows);
+                }
+            }
+            Err(err) => warn!(
+                target = "store",
+                path = %path.display(),
+                "ignoring unreadable audit table: {err}"
+            ),
+        }
+        self.audit_path = Some(Arc::new(path));
+        self
+    }
+
     pub fn save(&self, message: Message) {
         if let Ok(mut map) = self.inner.lock() {
-            map.insert(message.envelope.id.clone(), message);
+            map.insert(message);
+            self.persist_messages(&map);
+        }
+        self.invalidate_stats();
+    }
+
+    /// Insert several messages under a single lock so readers never see a
+    /// partially written batch.
+    pub fn save_all(&self, messages: Vec<Message>) {
+        if let Ok(mut map) = self.inner.lock() {
+            for message in messages {
+                map.insert(message);
+            }
+            self.persist_messages(&map);
         }
+        self.invalidate_stats();
     }
 
     pub fn update_status(&self, id: &MessageId, status: MessageStatus) {
         if let Ok(mut map) = self.inner.lock() {
-            if let Some(message) = map.get_mut(id) {
-                message.envelope.status = status;
+            if map.update(id, |message| message.envelope.status = status) {
+                self.persist_messages(&map);
             }
         }
+        self.invalidate_stats();
     }
 
     pub fn get(&self, id: &MessageId) -> Option<Message> {
         self.inner.lock().ok().and_then(|map| map.get(id).cloned())
     }
 
+    /// A stored message, in any folder, whose [`Message::content_hash`] is `hash`.
+    pub fn find_by_content_hash(&self, hash: &str) -> Option<Message> {
+        let map = self.inner.lock().ok()?;
+        let id = map.by_hash.get(hash)?.first()?;
+        map.get(id).cloned()
+    }
+
+    pub fn detail(&self, id: &MessageId) -> Option<MessageDetail> {
+        let message = self.get(id)?;
+        Some(MessageDetail {
+            message,
+            reports: self.reports(id),
+        })
+    }
+
     pub fn delete(&self, id: &MessageId) -> bool {
-        self.inner
+        if let Ok(mut map) = self.reports.lock() {
+            map.remove(id);
+        }
+        if let Ok(mut flags) = self.flags.lock() {
+            if flags.remove(id).is_some() {
+                self.persist_flags(&flags);
+            }
+        }
+        let removed = self
+            .inner
             .lock()
-            .map(|mut map| map.remove(id).is_some())
-            .unwrap_or(false)
+            .map(|mut map| {
+                let removed = map.remove(id).is_some();
+                if removed {
+                    self.persist_messages(&map);
+                }
+                removed
+            })
+            .unwrap_or(false);
+        if removed {
+            self.invalidate_stats();
+        }
+        removed
+    }
+
+    /// File a message under another folder; `false` if it does not exist.
+    pub fn move_message(&self, id: &MessageId, folder: &str) -> bool {
+        let moved = self
+            .inner
+            .lock()
+            .map(|mut map| {
+                let moved = map.update(id, |message| message.envelope.folder = folder.to_string());
+                if moved {
+                    self.persist_messages(&map);
+                }
+                moved
+            })
+            .unwrap_or(false);
+        if moved {
+            self.invalidate_stats();
+        }
+        moved
     }
 
+    /// Apply one bulk action to `ids` while holding the message lock, so
+    /// readers see either none or all of the change. Returns the ids that were
+    /// changed and those that do not exist.
+    pub fn apply_bulk(
+        &self,
+        ids: &[MessageId],
+        action: &BulkAction,
+    ) -> (Vec<MessageId>, Vec<MessageId>) {
+        let Ok(mut map) = self.inner.lock() else {
+            return (Vec::new(), ids.to_vec());
+        };
+        let (found, missing): (Vec<MessageId>, Vec<MessageId>) =
+            ids.iter().cloned().partition(|id| map.contains(id));
+        let mut file_under = |folder: &str| {
+            for id in &found {
+                map.update(id, |message| message.envelope.folder = folder.to_string());
+            }
+        };
+        match action {
+            BulkAction::Delete => {
+                let (Ok(mut flags), Ok(mut reports)) = (self.flags.lock(), self.reports.lock())
+                else {
+                    return (Vec::new(), ids.to_vec());
+                };
+                for id in &found {
+                    map.remove(id);
+                    flags.remove(id);
+                    reports.remove(id);
+                }
+                self.persist_flags(&flags);
+            }
+            BulkAction::Move { folder_id } => file_under(folder_id),
+            BulkAction::Archive => file_under("archive"),
+            BulkAction::MarkRead => {
+                let Ok(mut flags) = self.flags.lock() else {
+                    return (Vec::new(), ids.to_vec());
+                };
+                for id in &found {
+                    flags.entry(id.clone()).or_default().read = true;
+                }
+                self.persist_flags(&flags);
+            }
+        }
+        if !matches!(action, BulkAction::MarkRead) {
+            self.persist_messages(&map);
+        }
+        drop(map);
+        self.invalidate_stats();
+        (found, missing)
+    }
+
+    /// Flags of a stored message; `None` if the message does not exist.
+    pub fn flags(&self, id: &MessageId) -> Option<MessageFlags> {
+        self.inner.lock().ok()?.get(id)?;
+        Some(self.flags.lock().ok()?.get(id).cloned().unwrap_or_default())
+    }
+
+    /// Change a message's flags in place; `None` if the message does not exist.
+    pub fn update_flags(
+        &self,
+        id: &MessageId,
+        update: impl FnOnce(&mut MessageFlags),
+    ) -> Option<MessageFlags> {
+        self.inner.lock().ok()?.get(id)?;
+        let updated = {
+            let mut flags = self.flags.lock().ok()?;
+            let entry = flags.entry(id.clone()).or_default();
+            update(entry);
+            let updated = entry.clone();
+            self.persist_flags(&flags);
+            updated
+        };
+        self.invalidate_stats();
+        Some(updated)
+    }
+
+    /// Flag totals per folder.
+    pub fn flag_counts(&self) -> BTreeMap<String, FlagCounts> {
+        let mut counts: BTreeMap<String, FlagCounts> = BTreeMap::new();
+        let (Ok(messages), Ok(flags)) = (self.inner.lock(), self.flags.lock()) else {
+            return counts;
+        };
+        for message in messages.values() {
+            let entry = counts.entry(message.envelope.folder.clone()).or_default();
+            let flags = flags.get(&message.envelope.id);
+            entry.unread += usize::from(is_unread(message, flags));
+            let Some(flags) = flags else {
+                continue;
+            };
+            entry.flagged += usize::from(flags.flagged);
+            entry.answered += usize::from(flags.answered);
+            for label in &flags.labels {
+                *entry.labels.entry(label.clone()).or_default() += 1;
+            }
+        }
+        counts
+    }
+
+    pub fn save_report(&self, report: Report) {
+        if let Ok(mut map) = self.reports.lock() {
+            map.entry(report.message_id.clone())
+                .or_default()
+                .push(report);
+        }
+    }
+
+    pub fn reports(&self, id: &MessageId) -> Vec<Report> {
+        self.reports
+            .lock()
+            .ok()
+            .and_then(|map| map.get(id).cloned())
+            .unwrap_or_default()
+    }
+
+    /// Messages in `folder`, newest first.
     pub fn list(&self, folder: &str) -> Vec<Message> {
         self.inner
             .lock()
-            .map(|map| {
-                map.values()
-                    .filter(|msg| msg.envelope.folder == folder)
+            .map(|map| map.in_folder(folder).cloned().collect())
+            .unwrap_or_default()
+    }
+
+    /// Conversations with messages in `folder`, most recently active first.
+    /// Each thread lists only its messages in that folder, oldest first.
+    pub fn threads(&self, folder: &str) -> Vec<Thread> {
+        let mut threads: HashMap<MessageId, Vec<Message>> = HashMap::new();
+        for message in self.list(folder) {
+            threads
+                .entry(message.envelope.thread_id.clone())
+                .or_default()
+                .push(message);
+        }
+        let mut threads: Vec<Thread> = threads.into_iter().map(Thread::new).collect();
+        threads.sort_by(|a, b| {
+            b.latest_at
+                .cmp(&a.latest_at)
+                .then_with(|| a.thread_id.0.cmp(&b.thread_id.0))
+        });
+        threads
+    }
+
+    /// Every message of a conversation, whatever its folder.
+    pub fn thread(&self, thread_id: &MessageId) -> Option<Thread> {
+        let messages: Vec<Message> = self
+            .inner
+            .lock()
+            .ok()?
+            .values()
+            .filter(|message| &message.envelope.thread_id == thread_id)
+            .cloned()
+            .collect();
+        (!messages.is_empty()).then(|| Thread::new((thread_id.clone(), messages)))
+    }
+
+    /// Per-folder aggregates, recomputed only after a write invalidated the cache.
+    ///
+    /// The oldest-queued age is measured against the clock on every call.
+    pub fn folder_stats(&self) -> Vec<FolderStats> {
+        let now = self.clock.now();
+        if let Some(cached) = self.stats.lock().ok().and_then(|stats| stats.clone()) {
+            return cached.iter().map(|folder| folder.aged(now)).collect();
+        }
+
+        let generation = self.stats_generation.load(Ordering::SeqCst);
+        let mut folders: BTreeMap<String, CachedFolderStats> = BTreeMap::new();
+        if let (Ok(map), Ok(flags)) = (self.inner.lock(), self.flags.lock()) {
+            for message in map.values() {
+                let envelope = &message.envelope;
+                let folder =
+                    folders
+                        .entry(envelope.folder.clone())
+                        .or_insert_with(|| CachedFolderStats {
+                            stats: FolderStats {
+                                folder: envelope.folder.clone(),
+                                ..FolderStats::default()
+                            },
+                            oldest_queued_at: None,
+                        });
+                let stats = &mut folder.stats;
+                stats.total += 1;
+                stats.total_bytes += (envelope.subject.len() + message.content.body.len()) as u64
+                    + message
+                        .content
+                        .attachments
+                        .iter()
+                        .map(|attachment| attachment.size)
+                        .sum::<u64>();
+                stats.unread += usize::from(is_unread(message, flags.get(&envelope.id)));
+                match envelope.status {
+                    MessageStatus::Failed => stats.failed += 1,
+                    MessageStatus::Queued => {
+                        folder.oldest_queued_at = Some(
+                            folder
+                                .oldest_queued_at
+                                .map_or(envelope.created_at, |old| old.min(envelope.created_at)),
+                        );
+                    }
+                    _ => {}
+                }
+            }
+        }
+
+        let computed: Vec<CachedFolderStats> = folders.into_values().collect();
+        if let Ok(mut stats) = self.stats.lock() {
+            if self.stats_generation.load(Ordering::SeqCst) == generation {
+                *stats = Some(computed.clone());
+            }
+        }
+        computed.iter().map(|folder| folder.aged(now)).collect()
+    }
+
+    pub fn put_queue_entry(&self, entry: QueueEntry) {
+        if let Ok(mut queue) = self.queue.lock() {
+            queue.insert(entry.message_id.clone(), entry);
+            self.persist_queue(&queue);
+        }
+    }
+
+    /// Upsert several rows with one write of the table.
+    pub fn put_queue_entries(&self, entries: Vec<QueueEntry>) {
+        if let Ok(mut queue) = self.queue.lock() {
+            queue.extend(
+                entries
+                    .into_iter()
+                    .map(|entry| (entry.message_id.clone(), entry)),
+            );
+            self.persist_queue(&queue);
+        }
+    }
+
+    pub fn remove_queue_entry(&self, id: &MessageId) -> bool {
+        self.queue
+            .lock()
+            .map(|mut queue| {
+                let removed = queue.remove(id).is_some();
+                if removed {
+                    self.persist_queue(&queue);
+                }
+                removed
+            })
+            .unwrap_or(false)
+    }
+
+    pub fn queue_entry(&self, id: &MessageId) -> Option<QueueEntry> {
+        self.queue
+            .lock()
+            .ok()
+            .and_then(|queue| queue.get(id).cloned())
+    }
+
+    /// Queue rows ordered by next attempt, then id.
+    pub fn queue_entries(&self) -> Vec<QueueEntry> {
+        let mut entries: Vec<QueueEntry> = self
+            .queue
+            .lock()
+            .map(|queue| queue.values().cloned().collect())
+            .unwrap_or_default();
+        entries.sort_by(|a, b| {
+            a.next_attempt_at
+                .cmp(&b.next_attempt_at)
+                .then_with(|| a.message_id.0.cmp(&b.message_id.0))
+        });
+        entries
+    }
+
+    /// The unexpired record stored under `key`.
+    pub fn idempotency_record(&self, key: &str) -> Option<IdempotencyRecord> {
+        let now = self.clock.now();
+        self.idempotency
+            .lock()
+            .ok()?
+            .get(key)
+            .filter(|record| record.expires_at > now)
+            .cloned()
+    }
+
+    /// Store `record`, dropping expired rows in the same write.
+    pub fn put_idempotency_record(&self, record: IdempotencyRecord) {
+        let now = self.clock.now();
+        if let Ok(mut table) = self.idempotency.lock() {
+            table.retain(|_, row| row.expires_at > now);
+            table.insert(record.key.clone(), record);
+            self.persist_idempotency(&table);
+        }
+    }
+
+    /// Remove expired records; returns how many were dropped.
+    pub fn purge_idempotency_records(&self) -> usize {
+        let now = self.clock.now();
+        let Ok(mut table) = self.idempotency.lock() else {
+            return 0;
+        };
+        let before = table.len();
+        table.retain(|_, row| row.expires_at > now);
+        let purged = before - table.len();
+        if purged > 0 {
+            self.persist_idempotency(&table);
+        }
+        purged
+    }
+
+    pub fn folders(&self) -> Vec<FolderRecord> {
+        self.folders
+            .lock()
+            .map(|folders| folders.values().cloned().collect())
+            .unwrap_or_default()
+    }
+
+    pub fn folder(&self, id: &str) -> Option<FolderRecord> {
+        self.folders.lock().ok()?.get(id).cloned()
+    }
+
+    /// Upsert several rows with one write of the table.
+    pub fn put_folders(&self, records: Vec<FolderRecord>) {
+        if let Ok(mut folders) = self.folders.lock() {
+            folders.extend(records.into_iter().map(|row| (row.id.clone(), row)));
+            self.persist_folders(&folders);
+        }
+    }
+
+    pub fn remove_folders(&self, ids: &[String]) {
+        if let Ok(mut folders) = self.folders.lock() {
+            folders.retain(|id, _| !ids.contains(id));
+            self.persist_folders(&folders);
+        }
+    }
+
+    /// Alias rows ordered by O/R address.
+    pub fn aliases(&self) -> Vec<AliasRecord> {
+        self.aliases
+            .lock()
+            .map(|aliases| aliases.values().cloned().collect())
+            .unwrap_or_default()
+    }
+
+    /// Changes whenever the alias table does.
+    pub fn aliases_version(&self) -> u64 {
+        self.aliases_version.load(Ordering::SeqCst)
+    }
+
+    /// Insert or replace the alias for `record.or_address`.
+    pub fn put_alias(&self, record: AliasRecord) {
+        if let Ok(mut aliases) = self.aliases.lock() {
+            aliases.insert(record.or_address.clone(), record);
+            self.aliases_version.fetch_add(1, Ordering::SeqCst);
+            self.persist_aliases(&aliases);
+        }
+    }
+
+    pub fn remove_alias(&self, or_address: &str) -> Option<AliasRecord> {
+        let mut aliases = self.aliases.lock().ok()?;
+        let removed = aliases.remove(or_address)?;
+        self.aliases_version.fetch_add(1, Ordering::SeqCst);
+        self.persist_aliases(&aliases);
+        Some(removed)
+    }
+
+    pub fn webhooks(&self) -> Vec<WebhookRecord> {
+        self.webhooks
+            .lock()
+            .map(|webhooks| webhooks.values().cloned().collect())
+            .unwrap_or_default()
+    }
+
+    pub fn webhook(&self, id: Uuid) -> Option<WebhookRecord> {
+        self.webhooks.lock().ok()?.get(&id).cloned()
+    }
+
+    pub fn put_webhook(&self, record: WebhookRecord) {
+        if let Ok(mut webhooks) = self.webhooks.lock() {
+            webhooks.insert(record.id, record);
+            self.persist_webhooks(&webhooks);
+        }
+    }
+
+    /// Change a subscription in place; `None` when it does not exist.
+    pub fn update_webhook(
+        &self,
+        id: Uuid,
+        update: impl FnOnce(&mut WebhookRecord),
+    ) -> Option<WebhookRecord> {
+        let mut webhooks = self.webhooks.lock().ok()?;
+        let record = webhooks.get_mut(&id)?;
+        update(record);
+        let updated = record.clone();
+        self.persist_webhooks(&webhooks);
+        Some(updated)
+    }
+
+    pub fn remove_webhook(&self, id: Uuid) -> Option<WebhookRecord> {
+        let mut webhooks = self.webhooks.lock().ok()?;
+        let removed = webhooks.remove(&id)?;
+        self.persist_webhooks(&webhooks);
+        Some(removed)
+    }
+
+    pub fn held_content(&self, id: &MessageId) -> Option<HeldContentRecord> {
+        self.held.lock().ok()?.get(id).cloned()
+    }
+
+    pub fn put_held_content(&self, record: HeldContentRecord) {
+        if let Ok(mut held) = self.held.lock() {
+            held.insert(record.message_id.clone(), record);
+            self.persist_held(&held);
+        }
+    }
+
+    /// Forget where a message's content is held, returning the row and
+    /// whether another held message still shares its attachment.
+    pub fn remove_held_content(&self, id: &MessageId) -> Option<(HeldContentRecord, bool)> {
+        let mut held = self.held.lock().ok()?;
+        let removed = held.remove(id)?;
+        let shared = held
+            .values()
+            .any(|row| row.attachment_id == removed.attachment_id);
+        self.persist_held(&held);
+        Some((removed, shared))
+    }
+
+    /// Point message attachments and held content at new blob ids, e.g.
+    /// after [`AttachmentStore::seal_plaintext`](crate::attachments::AttachmentStore::seal_plaintext)
+    /// renamed the blobs.
+    pub fn rename_attachments(&self, renamed: &BTreeMap<String, String>) {
+        if renamed.is_empty() {
+            return;
+        }
+        if let Ok(mut map) = self.inner.lock() {
+            let ids: Vec<MessageId> = map
+                .values()
+                .filter(|message| {
+                    message
+                        .content
+                        .attachments
+                        .iter()
+                        .any(|attachment| renamed.contains_key(&attachment.id))
+                })
+                .map(|message| message.envelope.id.clone())
+                .collect();
+            for id in &ids {
+                map.update(id, |message| {
+                    for attachment in &mut message.content.attachments {
+                        if let Some(new) = renamed.get(&attachment.id) {
+                            attachment.id = new.clone();
+                        }
+                    }
+                });
+            }
+            if !ids.is_empty() {
+                self.persist_messages(&map);
+            }
+        }
+        if let Ok(mut held) = self.held.lock() {
+            let mut changed = false;
+            for row in held.values_mut() {
+                if let Some(new) = renamed.get(&row.attachment_id) {
+                    row.attachment_id = new.clone();
+                    changed = true;
+                }
+            }
+            if changed {
+                self.persist_held(&held);
+            }
+        }
+        self.invalidate_stats();
+    }
+
+    /// Documents the migration job has already imported.
+    pub fn migration_checkpoints(&self, job_id: Uuid) -> Vec<MigrationCheckpoint> {
+        self.migration_state
+            .lock()
+            .map(|state| {
+                state
+                    .values()
+                    .filter(|row| row.job_id == job_id)
                     .cloned()
                     .collect()
             })
             .unwrap_or_default()
     }
 
+    pub fn put_migration_checkpoint(&self, checkpoint: MigrationCheckpoint) {
+        if let Ok(mut state) = self.migration_state.lock() {
+            state.insert((checkpoint.job_id, checkpoint.path.clone()), checkpoint);
+            self.persist_migration_state(&state);
+        }
+    }
+
+    /// Forget a job's checkpoints once it has nothing left to resume.
+    pub fn remove_migration_checkpoints(&self, job_id: Uuid) {
+        if let Ok(mut state) = self.migration_state.lock() {
+            let before = state.len();
+            state.retain(|(job, _), _| *job != job_id);
+            if state.len() != before {
+                self.persist_migration_state(&state);
+            }
+        }
+    }
+
+    /// Append the row `build` makes from the last one, under the table lock so
+    /// rows are built in order.
+    pub fn append_audit(
+        &self,
+        build: impl FnOnce(Option<&AuditRecord>) -> AuditRecord,
+    ) -> Option<AuditRecord> {
+        let mut audit = self.audit.lock().ok()?;
+        let record = build(audit.last());
+        if let Some(path) = &self.audit_path {
+            if let Err(err) = append_line(path, &record) {
+                warn!(target = "store", "failed to persist audit row: {err}");
+            }
+        }
+        audit.push(record.clone());
+        Some(record)
+    }
+
+    pub fn audit_records(&self) -> Vec<AuditRecord> {
+        self.audit
+            .lock()
+            .map(|audit| audit.clone())
+            .unwrap_or_default()
+    }
+
+    /// Copy every table while holding all of their locks, so the copy never
+    /// mixes states from before and after a concurrent write. Fails rather
+    /// than returning a partial copy when a writer panicked mid-update.
+    pub fn snapshot(&self) -> io::Result<StoreSnapshot> {
+        let taken_at = self.clock.now();
+        let (
+            Ok(messages),
+            Ok(reports),
+            Ok(flags),
+            Ok(queue),
+            Ok(idempotency),
+            Ok(folders),
+            Ok(aliases),
+            Ok(webhooks),
+            Ok(held),
+            Ok(migration_state),
+            Ok(audit),
+        ) = (
+            self.inner.lock(),
+            self.reports.lock(),
+            self.flags.lock(),
+            self.queue.lock(),
+            self.idempotency.lock(),
+            self.folders.lock(),
+            self.aliases.lock(),
+            self.webhooks.lock(),
+            self.held.lock(),
+            self.migration_state.lock(),
+            self.audit.lock(),
+        )
+        else {
+            return Err(io::Error::other("store lock poisoned"));
+        };
+        let mut rows: Vec<MessageRow> = messages.values().map(MessageRow::from).collect();
+        rows.sort_by(|a, b| a.id.cmp(&b.id));
+        let mut queue: Vec<QueueEntry> = queue.values().cloned().collect();
+        queue.sort_by(|a, b| a.message_id.cmp(&b.message_id));
+        let mut idempotency: Vec<IdempotencyRecord> = idempotency.values().cloned().collect();
+        idempotency.sort_by(|a, b| a.key.cmp(&b.key));
+        Ok(StoreSnapshot {
+            taken_at,
+            messages: rows,
+            flags: flags
+                .iter()
+                .map(|(id, f)| (id.clone(), f.clone()))
+                .collect(),
+            reports: reports.values().flatten().cloned().collect(),
+            queue,
+            idempotency,
+            folders: folders.values().cloned().collect(),
+            aliases: aliases.values().cloned().collect(),
+            webhooks: webhooks.values().cloned().collect(),
+            held: held.values().cloned().collect(),
+            migration_state: migration_state.values().cloned().collect(),
+            audit: audit.clone(),
+        })
+    }
+
+    /// Write a consistent [`snapshot`](Self::snapshot) to `path` while the
+    /// store keeps serving requests.
+    pub fn backup_to(&self, path: &Path) -> io::Result<StoreSnapshot> {
+        let snapshot = self.snapshot()?;
+        let json = serde_json::to_vec(&snapshot).map_err(io::Error::other)?;
+        replace_file(path, &json)?;
+        Ok(snapshot)
+    }
+
+    /// Replace every table with `snapshot` in one step and persist the result.
+    /// The audit log is the exception: it only ever grows, so the snapshot's
+    /// copy is not applied. Nothing changes when a table lock is poisoned.
+    pub fn restore(&self, snapshot: StoreSnapshot) -> io::Result<()> {
+        let (
+            Ok(mut messages),
+            Ok(mut reports),
+            Ok(mut flags),
+            Ok(mut queue),
+            Ok(mut idempotency),
+            Ok(mut folders),
+            Ok(mut aliases),
+            Ok(mut webhooks),
+            Ok(mut held),
+            Ok(mut migration_state),
+        ) = (
+            self.inner.lock(),
+            self.reports.lock(),
+            self.flags.lock(),
+            self.queue.lock(),
+            self.idempotency.lock(),
+            self.folders.lock(),
+            self.aliases.lock(),
+            self.webhooks.lock(),
+            self.held.lock(),
+            self.migration_state.lock(),
+        )
+        else {
+            return Err(io::Error::other("store lock poisoned"));
+        };
+        *messages = Messages::default();
+        for row in snapshot.messages {
+            messages.restore(row.into());
+        }
+        *flags = snapshot.flags.into_iter().collect();
+        reports.clear();
+        for report in snapshot.reports {
+            reports
+                .entry(report.message_id.clone())
+                .or_default()
+                .push(report);
+        }
+        *queue = snapshot
+            .queue
+            .into_iter()
+            .map(|row| (row.message_id.clone(), row))
+            .collect();
+        *idempotency = snapshot
+            .idempotency
+            .into_iter()
+            .map(|row| (row.key.clone(), row))
+            .collect();
+        *folders = snapshot
+            .folders
+            .into_iter()
+            .map(|row| (row.id.clone(), row))
+            .collect();
+        *aliases = snapshot
+            .aliases
+            .into_iter()
+            .map(|row| (row.or_address.clone(), row))
+            .collect();
+        *webhooks = snapshot
+            .webhooks
+            .into_iter()
+            .map(|row| (row.id, row))
+            .collect();
+        *held = snapshot
+            .held
+            .into_iter()
+            .map(|row| (row.message_id.clone(), row))
+            .collect();
+        *migration_state = snapshot
+            .migration_state
+            .into_iter()
+            .map(|row| ((row.job_id, row.path.clone()), row))
+            .collect();
+        self.aliases_version.fetch_add(1, Ordering::SeqCst);
+        self.persist_messages(&messages);
+        self.persist_flags(&flags);
+        self.persist_queue(&queue);
+        self.persist_idempotency(&idempotency);
+        self.persist_folders(&folders);
+        self.persist_aliases(&aliases);
+        self.persist_webhooks(&webhooks);
+        self.persist_held(&held);
+        self.persist_migration_state(&migration_state);
+        drop((
+            messages,
+            reports,
+            flags,
+            queue,
+            idempotency,
+            folders,
+            aliases,
+            webhooks,
+            held,
+            migration_state,
+        ));
+        self.invalidate_stats();
+        Ok(())
+    }
+
+    fn persist_messages(&self, messages: &Messages) {
+        if let Some(path) = &self.messages_path {
+            write_messages(path, messages);
+        }
+    }
+
+    fn persist_queue(&self, queue: &HashMap<MessageId, QueueEntry>) {
+        let Some(path) = &self.queue_path else {
+            return;
+        };
+        let mut rows: Vec<&QueueEntry> = queue.values().collect();
+        rows.sort_by(|a, b| a.message_id.0.cmp(&b.message_id.0));
+        if let Err(err) = write_table(path, &rows) {
+            warn!(target = "store", "failed to persist queue table: {err}");
+        }
+    }
+
+    fn persist_idempotency(&self, table: &HashMap<String, IdempotencyRecord>) {
+        let Some(path) = &self.idempotency_path else {
+            return;
+        };
+        let mut rows: Vec<&IdempotencyRecord> = table.values().collect();
+        rows.sort_by(|a, b| a.key.cmp(&b.key));
+        if let Err(err) = write_table(path, &rows) {
+            warn!(
+                target = "store",
+                "failed to persist idempotency table: {err}"
+            );
+        }
+    }
+
+    fn persist_folders(&self, folders: &BTreeMap<String, FolderRecord>) {
+        let Some(path) = &self.folders_path else {
+            return;
+        };
+        let rows: Vec<&FolderRecord> = folders.values().collect();
+        if let Err(err) = write_table(path, &rows) {
+            warn!(target = "store", "failed to persist folders table: {err}");
+        }
+    }
+
+    fn persist_flags(&self, flags: &HashMap<MessageId, MessageFlags>) {
+        let Some(path) = &self.flags_path else {
+            return;
+        };
+        let mut rows: Vec<MessageFlagsRecord> = flags
+            .iter()
+            .map(|(id, flags)| MessageFlagsRecord {
+                message_id: id.clone(),
+                flags: flags.clone(),
+            })
+            .collect();
+        rows.sort_by(|a, b| a.message_id.cmp(&b.message_id));
+        if let Err(err) = write_table(path, &rows) {
+            warn!(target = "store", "failed to persist flags table: {err}");
+        }
+    }
+
+    fn persist_aliases(&self, aliases: &BTreeMap<String, AliasRecord>) {
+        let Some(path) = &self.aliases_path else {
+            return;
+        };
+        let rows: Vec<&AliasRecord> = aliases.values().collect();
+        if let Err(err) = write_table(path, &rows) {
+            warn!(target = "store", "failed to persist aliases table: {err}");
+        }
+    }
+
+    fn persist_webhooks(&self, webhooks: &BTreeMap<Uuid, WebhookRecord>) {
+        let Some(path) = &self.webhooks_path else {
+            return;
+        };
+        let rows: Vec<&WebhookRecord> = webhooks.values().collect();
+        if let Err(err) = write_table(path, &rows) {
+            warn!(target = "store", "failed to persist webhooks table: {err}");
+        }
+    }
+
+    fn persist_held(&self, held: &BTreeMap<MessageId, HeldContentRecord>) {
+        let Some(path) = &self.held_path else {
+            return;
+        };
+        let rows: Vec<&HeldContentRecord> = held.values().collect();
+        if let Err(err) = write_table(path, &rows) {
+            warn!(
+                target = "store",
+                "failed to persist held_content table: {err}"
+            );
+        }
+    }
+
+    fn persist_migration_state(&self, state: &BTreeMap<(Uuid, PathBuf), MigrationCheckpoint>) {
+        let Some(path) = &self.migration_state_path else {
+            return;
+        };
+        let rows: Vec<&MigrationCheckpoint> = state.values().collect();
+        if let Err(err) = write_table(path, &rows) {
+            warn!(
+                target = "store",
+                "failed to persist migration_state table: {err}"
+            );
+        }
+    }
+
+    fn invalidate_stats(&self) {
+        if let Ok(mut stats) = self.stats.lock() {
+            self.stats_generation.fetch_add(1, Ordering::SeqCst);
+            *stats = None;
+        }
+    }
+
     pub fn seed_demo_data(&self) -> Vec<MessageId> {
         let mut ids = Vec::new();
         for index in 0..3 {
@@ -63,6 +1406,7 @@ impl StoreManager {
                 envelope,
                 content: crate::models::MessageContent {
                     body: "This is a demo message.".into(),
+                    attachments: Vec::new(),
                 },
             };
             ids.push(message.envelope.id.clone());
@@ -71,3 +1415,87 @@ impl StoreManager {
         ids
     }
 }
+
+fn write_messages(path: &Path, messages: &Messages) {
+    let mut rows: Vec<MessageRow> = messages.values().map(MessageRow::from).collect();
+    rows.sort_by(|a, b| a.id.cmp(&b.id));
+    if let Err(err) = write_table(path, &rows) {
+        warn!(target = "store", "failed to persist messages table: {err}");
+    }
+}
+
+fn load_table<T: DeserializeOwned>(path: &Path) -> io::Result<Vec<T>> {
+    match fs::read(path) {
+        Ok(bytes) => serde_json::from_slice(&bytes)
+            .map_err(|err| io::Error::new(io::ErrorKind::InvalidData, err)),
+        Err(err) if err.kind() == io::ErrorKind::NotFound => Ok(Vec::new()),
+        Err(err) => Err(err),
+    }
+}
+
+fn load_lines<T: DeserializeOwned>(path: &Path) -> io::Result<Vec<T>> {
+    let file = match fs::File::open(path) {
+        Ok(file) => file,
+        Err(err) if err.kind() == io::ErrorKind::NotFound => return Ok(Vec::new()),
+        Err(err) => return Err(err),
+    };
+    let mut rows = Vec::new();
+    for line in BufReader::new(file).lines() {
+        let line = line?;
+        if line.trim().is_empty() {
+            continue;
+        }
+        rows.push(
+            serde_json::from_str(&line)
+                .map_err(|err| io::Error::new(io::ErrorKind::InvalidData, err))?,
+        );
+    }
+    Ok(rows)
+}
+
+fn append_line<T: Serialize>(path: &Path, row: &T) -> io::Result<()> {
+    let mut line = serde_json::to_vec(row).map_err(io::Error::other)?;
+    line.push(b'\n');
+    if let Some(parent) = path.parent() {
+        fs::create_dir_all(parent)?;
+    }
+    let mut file = OpenOptions::new().create(true).append(true).open(path)?;
+    file.write_all(&line)?;
+    file.sync_data()
+}
+
+/// Rewrite a table through a temporary file so a crash never leaves it half-written.
+fn write_table<T: Serialize>(path: &Path, rows: &[T]) -> io::Result<()> {
+    let json = serde_json::to_vec_pretty(rows).map_err(io::Error::other)?;
+    replace_file(path, &json)
+}
+
+/// Replace `path` with `bytes`: the staging file is synced before the rename
+/// and the directory after it, so after a crash `path` holds either the old
+/// or the new contents, never a truncated mix.
+fn replace_file(path: &Path, bytes: &[u8]) -> io::Result<()> {
+    let parent = path
+        .parent()
+        .filter(|parent| !parent.as_os_str().is_empty())
+        .unwrap_or(Path::new("."));
+    fs::create_dir_all(parent)?;
+    let staging = path.with_extension("tmp");
+    let mut file = fs::File::create(&staging)?;
+    file.write_all(bytes)?;
+    file.sync_all()?;
+    drop(file);
+    fs::rename(&staging, path)?;
+    sync_dir(parent)
+}
+
+#[cfg(unix)]
+fn sync_dir(dir: &Path) -> io::Result<()> {
+    fs::File::open(dir)?.sync_all()
+}
+
+/// Directories cannot be opened for syncing here; the rename is as durable
+/// as the filesystem makes it.
+#[cfg(not(unix))]
+fn sync_dir(_dir: &Path) -> io::Result<()> {
+    Ok(())
+}
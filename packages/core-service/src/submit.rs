@@ -0,0 +1,236 @@
+//! What every submission goes through before it reaches a transport.
+//!
+//! [`SubmitPolicy`] screens a message against the DLP rules and applies the
+//! S/MIME protection it asks for; [`TransportSwitch`] runs it on single
+//! submissions and on each message of a batch
+//! ([`TransportSwitch::submit_batch`]), whatever `transport.mode` is active.
+//!
+//! [`TransportSwitch`]: crate::transport::TransportSwitch
+//! [`TransportSwitch::submit_batch`]: crate::transport::TransportSwitch::submit_batch
+
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use thiserror::Error;
+use tracing::warn;
+
+use crate::audit::AuditLog;
+use crate::dlp::{DlpAction, DlpEngine, DlpError, DlpMatch};
+use crate::models::{AuditAction, Message, MessageId, MessageStatus};
+use crate::smime::{SmimeError, SmimeService};
+use crate::store::StoreManager;
+use crate::trace::{TraceManager, TraceSeverity};
+
+/// One message of a
+/// [`TransportSwitch::submit_batch`](crate::transport::TransportSwitch::submit_batch) call.
+#[derive(Clone, Debug, PartialEq)]
+pub struct BatchSubmission {
+    pub message: Message,
+    pub deferred_until: Option<DateTime<Utc>>,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum BatchOutcome {
+    Accepted,
+    Scheduled,
+    Quarantined,
+    Rejected,
+}
+
+/// Per-message result of
+/// [`TransportSwitch::submit_batch`](crate::transport::TransportSwitch::submit_batch), in
+/// batch order.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize)]
+pub struct BatchItemResult {
+    pub index: usize,
+    pub message_id: MessageId,
+    pub outcome: BatchOutcome,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+}
+
+#[derive(Debug, Error, PartialEq, Eq)]
+pub enum BatchError {
+    #[error("batch contains no messages")]
+    Empty,
+    #[error("batch of {size} messages exceeds the limit of {limit}")]
+    TooLarge { size: usize, limit: usize },
+    #[error("batch submission has no outbox to store the batch in")]
+    NoOutbox,
+}
+
+impl BatchError {
+    pub fn status(&self) -> u16 {
+        match self {
+            Self::Empty => 400,
+            Self::TooLarge { .. } => 413,
+            Self::NoOutbox => 503,
+        }
+    }
+}
+
+/// Why a submission was refused.
+#[derive(Debug, Error, PartialEq, Eq)]
+pub enum SubmitError {
+    #[error(transparent)]
+    Dlp(#[from] DlpError),
+    #[error("S/MIME protection failed: {0}")]
+    Smime(#[from] SmimeError),
+}
+
+/// Verdict of [`SubmitPolicy::screen`].
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Screened {
+    /// Protected as requested and ready for the transport.
+    Accept,
+    /// Held back by DLP; see [`SubmitPolicy::quarantine`].
+    Quarantine,
+}
+
+/// DLP screening and S/MIME protection applied to every submission before
+/// it reaches a transport.
+#[derive(Clone)]
+pub struct SubmitPolicy {
+    dlp: Option<DlpEngine>,
+    smime: SmimeService,
+    trace: TraceManager,
+    audit: Option<AuditLog>,
+}
+
+impl SubmitPolicy {
+    pub fn new(trace: TraceManager) -> Self {
+        Self {
+            dlp: None,
+            smime: SmimeService::disabled(),
+            trace,
+            audit: None,
+        }
+    }
+
+    pub fn with_dlp(mut self, dlp: DlpEngine) -> Self {
+        self.dlp = Some(dlp);
+        self
+    }
+
+    pub fn with_smime(mut self, smime: SmimeService) -> Self {
+        self.smime = smime;
+        self
+    }
+
+    /// Record DLP blocks, quarantines and refusals for want of encryption as
+    /// `dlp.block`, `dlp.quarantine` and `dlp.encryption_required`, naming
+    /// the rule and the matched field. The matched text itself stays out of
+    /// the log; only a SHA-256 prefix of it is kept, enough to tell whether
+    /// two records matched the same text.
+    pub fn with_audit(mut self, audit: AuditLog) -> Self {
+        self.audit = Some(audit);
+        self
+    }
+
+    /// Scan the message against DLP rules and, when it passes, give it the
+    /// S/MIME protection it asks for.
+    pub fn screen(&self, message: &mut Message) -> Result<Screened, SubmitError> {
+        let screened = self.scan(message)?;
+        if screened == Screened::Accept && self.smime.protect(message)? {
+            self.trace
+                .record("smime.protected", message.envelope.id.clone());
+        }
+        Ok(screened)
+    }
+
+    /// Move a quarantined message to the `quarantine` folder of `store`,
+    /// where it never reaches the transport.
+    pub fn quarantine(&self, store: &StoreManager, mut message: Message) -> MessageId {
+        let id = message.envelope.id.clone();
+        self.mark_quarantined(&mut message);
+        store.save(message);
+        id
+    }
+
+    /// Move a quarantined message to the `quarantine` folder without storing
+    /// it, for callers that store it with others.
+    pub fn mark_quarantined(&self, message: &mut Message) {
+        message.envelope.folder = "quarantine".into();
+        message.envelope.status = MessageStatus::Queued;
+        self.trace
+            .record("dlp.quarantined", message.envelope.id.clone());
+    }
+
+    /// Run the DLP scan, tracing every match; blocking verdicts become errors.
+    fn scan(&self, message: &Message) -> Result<Screened, DlpError> {
+        let Some(dlp) = &self.dlp else {
+            return Ok(Screened::Accept);
+        };
+        let verdict = dlp.scan(message);
+        let id = message.envelope.id.clone();
+        for evidence in &verdict.matches {
+            warn!(
+                target = "dlp",
+                message = %id,
+                rule = %evidence.rule,
+                field = %evidence.field,
+                "outbound content matched DLP rule"
+            );
+            self.trace.record_with(
+                format!("dlp.match:{}", evidence.rule),
+                id.clone(),
+                TraceSeverity::Warn,
+                [("field", &evidence.field)],
+            );
+        }
+
+        let Some(decisive) = verdict
+            .matches
+            .iter()
+            .max_by_key(|evidence| evidence.action)
+        else {
+            return Ok(Screened::Accept);
+        };
+        let rule = decisive.rule.clone();
+        match decisive.action {
+            DlpAction::Block => {
+                self.audit(AuditAction::DlpBlock, &id, decisive);
+                self.trace
+                    .record_with("dlp.blocked", id, TraceSeverity::Warn, [("rule", &rule)]);
+                Err(DlpError::Blocked(rule))
+            }
+            DlpAction::Quarantine => {
+                self.audit(AuditAction::DlpQuarantine, &id, decisive);
+                Ok(Screened::Quarantine)
+            }
+            DlpAction::RequireEncryption
+                if message.envelope.security.encrypt && self.smime.is_enabled() =>
+            {
+                Ok(Screened::Accept)
+            }
+            DlpAction::RequireEncryption => {
+                self.audit(AuditAction::DlpEncryptionRequired, &id, decisive);
+                self.trace.record_with(
+                    "dlp.encryption_required",
+                    id,
+                    TraceSeverity::Warn,
+                    [("rule", &rule)],
+                );
+                Err(DlpError::EncryptionRequired(rule))
+            }
+        }
+    }
+
+    fn audit(&self, action: AuditAction, id: &MessageId, evidence: &DlpMatch) {
+        if let Some(audit) = &self.audit {
+            audit.record(
+                "dlp",
+                action,
+                &id.0,
+                None,
+                Some(format!(
+                    "rule {} matched {}, excerpt sha256:{}",
+                    evidence.rule,
+                    evidence.field,
+                    &format!("{:x}", Sha256::digest(evidence.excerpt.as_bytes()))[..16]
+                )),
+            );
+        }
+    }
+}
@@ -0,0 +1,510 @@
+//! Supervision of long-running background workers.
+//!
+//! The sync, queue, scheduler and gateway loops each run on their own thread
+//! and report liveness through [`WorkerContext::heartbeat`]. A worker that
+//! panics, returns, or goes quiet for longer than the heartbeat timeout is
+//! replaced by a fresh instance after an exponential back-off. Once a worker
+//! has failed `escalate_after` times in a row the supervisor raises an alert;
+//! the streak resets after a replacement stays healthy for a full heartbeat
+//! timeout.
+
+use std::any::Any;
+use std::collections::BTreeMap;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex};
+use std::thread::{self, JoinHandle};
+use std::time::Duration;
+
+use chrono::{DateTime, Utc};
+use serde::Serialize;
+use tracing::{error, info, warn};
+
+use crate::clock::SharedClock;
+use crate::config::SupervisorConfig;
+use crate::telemetry::TelemetryManager;
+
+pub type WorkerFn = Arc<dyn Fn(WorkerContext) + Send + Sync>;
+
+/// Receives escalations for workers that keep failing.
+pub trait AlertSink: Send + Sync {
+    fn raise(&self, alert: &WorkerAlert);
+}
+
+#[derive(Clone, Debug, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct WorkerAlert {
+    pub worker: String,
+    pub consecutive_failures: u32,
+    pub last_failure: String,
+    pub raised_at: DateTime<Utc>,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub enum WorkerState {
+    Running,
+    Restarting,
+    Stopped,
+}
+
+/// Health of one supervised worker as reported by `/status`.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct WorkerStatus {
+    pub name: String,
+    pub state: WorkerState,
+    pub restarts: u64,
+    pub consecutive_failures: u32,
+    pub last_heartbeat: Option<DateTime<Utc>>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub last_failure: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub next_restart_at: Option<DateTime<Utc>>,
+    pub escalated: bool,
+}
+
+/// Handle passed to a running worker instance.
+#[derive(Clone)]
+pub struct WorkerContext {
+    beat: Arc<Beat>,
+    clock: SharedClock,
+}
+
+impl WorkerContext {
+    pub fn heartbeat(&self) {
+        if let Ok(mut last) = self.beat.last.lock() {
+            *last = self.clock.now();
+        }
+    }
+
+    /// Whether this instance has been shut down or superseded; worker loops
+    /// should return promptly once this is set.
+    pub fn should_stop(&self) -> bool {
+        self.beat.stop.load(Ordering::SeqCst)
+    }
+}
+
+struct Beat {
+    last: Mutex<DateTime<Utc>>,
+    stop: AtomicBool,
+}
+
+struct Worker {
+    run: WorkerFn,
+    state: WorkerState,
+    instance: Option<(Arc<Beat>, JoinHandle<()>)>,
+    started_at: DateTime<Utc>,
+    restarts: u64,
+    consecutive_failures: u32,
+    last_failure: Option<String>,
+    next_restart_at: Option<DateTime<Utc>>,
+    escalated: bool,
+}
+
+impl Worker {
+    fn last_heartbeat(&self) -> Option<DateTime<Utc>> {
+        let (beat, _) = self.instance.as_ref()?;
+        beat.last.lock().ok().map(|last| *last)
+    }
+}
+
+#[derive(Clone)]
+pub struct Supervisor {
+    config: SupervisorConfig,
+    workers: Arc<Mutex<BTreeMap<String, Worker>>>,
+    stopped: Arc<AtomicBool>,
+    alerts: Option<Arc<dyn AlertSink>>,
+    telemetry: Option<TelemetryManager>,
+    clock: SharedClock,
+}
+
+impl Supervisor {
+    pub fn new(config: SupervisorConfig) -> Self {
+        Self {
+            config,
+            workers: Arc::new(Mutex::new(BTreeMap::new())),
+            stopped: Arc::new(AtomicBool::new(false)),
+            alerts: None,
+            telemetry: None,
+            clock: SharedClock::default(),
+        }
+    }
+
+    pub fn with_alerts(mut self, alerts: Arc<dyn AlertSink>) -> Self {
+        self.alerts = Some(alerts);
+        self
+    }
+
+    pub fn with_telemetry(mut self, telemetry: TelemetryManager) -> Self {
+        self.telemetry = Some(telemetry);
+        self
+    }
+
+    pub fn with_clock(mut self, clock: SharedClock) -> Self {
+        self.clock = clock;
+        self
+    }
+
+    /// Start `run` on its own thread under supervision. Registering a name
+    /// twice replaces the previous worker.
+    pub fn spawn<F>(&self, name: &str, run: F)
+    where
+        F: Fn(WorkerContext) + Send + Sync + 'static,
+    {
+        let now = self.clock.now();
+        let mut worker = Worker {
+            run: Arc::new(run),
+            state: WorkerState::Running,
+            instance: None,
+            started_at: now,
+            restarts: 0,
+            consecutive_failures: 0,
+            last_failure: None,
+            next_restart_at: None,
+            escalated: false,
+        };
+        self.start(name, &mut worker);
+        if let Ok(mut workers) = self.workers.lock() {
+            if let Some(previous) = workers.insert(name.to_string(), worker) {
+                stop_instance(&previous);
+            }
+        }
+    }
+
+    /// Inspect every worker once: detect crashes and missed heartbeats, and
+    /// start replacements whose back-off has elapsed.
+    pub fn check(&self) {
+        if self.stopped.load(Ordering::SeqCst) {
+            return;
+        }
+        let now = self.clock.now();
+        let timeout = chrono::Duration::milliseconds(self.config.heartbeat_timeout_ms as i64);
+        let Ok(mut workers) = self.workers.lock() else {
+            return;
+        };
+        for (name, worker) in workers.iter_mut() {
+            match worker.state {
+                WorkerState::Running => {
+                    let failure = match worker.instance.take() {
+                        Some((_, handle)) if handle.is_finished() => Some(match handle.join() {
+                            Err(payload) => format!("panicked: {}", panic_message(&payload)),
+                            Ok(()) => "exited unexpectedly".to_string(),
+                        }),
+                        Some((beat, handle)) => {
+                            let last = beat.last.lock().map(|last| *last).unwrap_or(now);
+                            if now - last > timeout {
+                                // The thread cannot be killed; tell it to stop and
+                                // let it unwind on its own while a replacement runs.
+                                beat.stop.store(true, Ordering::SeqCst);
+                                Some(format!("no heartbeat since {}", last.to_rfc3339()))
+                            } else {
+                                worker.instance = Some((beat, handle));
+                                None
+                            }
+                        }
+                        None => Some("not running".to_string()),
+                    };
+                    match failure {
+                        Some(reason) => self.fail(name, worker, reason, now),
+                        None if now - worker.started_at >= timeout => {
+                            worker.consecutive_failures = 0;
+                            worker.escalated = false;
+                        }
+                        None => {}
+                    }
+                }
+                WorkerState::Restarting => {
+                    if worker.next_restart_at.is_none_or(|at| at <= now) {
+                        worker.restarts += 1;
+                        info!(
+                            target = "supervisor",
+                            worker = %name,
+                            restarts = worker.restarts,
+                            "restarting worker"
+                        );
+                        if let Some(telemetry) = &self.telemetry {
+                            let reason = worker.last_failure.as_deref().unwrap_or("unknown");
+                            telemetry.record_restart(name, reason);
+                        }
+                        self.start(name, worker);
+                    }
+                }
+                WorkerState::Stopped => {}
+            }
+        }
+    }
+
+    /// Run [`Supervisor::check`] every `interval` on a background thread
+    /// until [`Supervisor::shutdown`] is called.
+    pub fn spawn_monitor(&self, interval: Duration) -> JoinHandle<()> {
+        let supervisor = self.clone();
+        thread::spawn(move || {
+            while !supervisor.stopped.load(Ordering::SeqCst) {
+                supervisor.check();
+                thread::sleep(interval);
+            }
+        })
+    }
+
+    /// Ask every worker to stop and stop restarting them.
+    pub fn shutdown(&self) {
+        self.stopped.store(true, Ordering::SeqCst);
+        if let Ok(mut workers) = self.workers.lock() {
+            for worker in workers.values_mut() {
+                stop_instance(worker);
+                worker.state = WorkerState::Stopped;
+                worker.next_restart_at = None;
+            }
+        }
+    }
+
+    /// Handle that calls [`Supervisor::shutdown`] when dropped.
+    pub fn shutdown_handle(&self) -> ShutdownHandle {
+        ShutdownHandle {
+            supervisor: self.clone(),
+        }
+    }
+
+    pub fn status(&self) -> Vec<WorkerStatus> {
+        self.workers
+            .lock()
+            .map(|workers| {
+                workers
+                    .iter()
+                    .map(|(name, worker)| WorkerStatus {
+                        name: name.clone(),
+                        state: worker.state,
+                        restarts: worker.restarts,
+                        consecutive_failures: worker.consecutive_failures,
+                        last_heartbeat: worker.last_heartbeat(),
+                        last_failure: worker.last_failure.clone(),
+                        next_restart_at: worker.next_restart_at,
+                        escalated: worker.escalated,
+                    })
+                    .collect()
+            })
+            .unwrap_or_default()
+    }
+
+    fn start(&self, name: &str, worker: &mut Worker) {
+        let now = self.clock.now();
+        let beat = Arc::new(Beat {
+            last: Mutex::new(now),
+            stop: AtomicBool::new(false),
+        });
+        let context = WorkerContext {
+            beat: beat.clone(),
+            clock: self.clock.clone(),
+        };
+        let run = worker.run.clone();
+        worker.started_at = now;
+        worker.next_restart_at = None;
+        match thread::Builder::new()
+            .name(format!("worker-{name}"))
+            .spawn(move || run(context))
+        {
+            Ok(handle) => {
+                worker.instance = Some((beat, handle));
+                worker.state = WorkerState::Running;
+            }
+            Err(err) => self.fail(name, worker, format!("cannot spawn thread: {err}"), now),
+        }
+    }
+
+    fn fail(&self, name: &str, worker: &mut Worker, reason: String, now: DateTime<Utc>) {
+        worker.consecutive_failures += 1;
+        let backoff = self.backoff(worker.consecutive_failures);
+        worker.state = WorkerState::Restarting;
+        worker.next_restart_at = Some(now + backoff);
+        warn!(
+            target = "supervisor",
+            worker = %name,
+            failures = worker.consecutive_failures,
+            backoff_ms = backoff.num_milliseconds(),
+            "worker failed: {reason}"
+        );
+        worker.last_failure = Some(reason);
+        if !worker.escalated && worker.consecutive_failures >= self.config.escalate_after.max(1) {
+            worker.escalated = true;
+            self.escalate(name, worker, now);
+        }
+    }
+
+    fn escalate(&self, name: &str, worker: &Worker, now: DateTime<Utc>) {
+        let alert = WorkerAlert {
+            worker: name.to_string(),
+            consecutive_failures: worker.consecutive_failures,
+            last_failure: worker.last_failure.clone().unwrap_or_default(),
+            raised_at: now,
+        };
+        error!(
+            target = "supervisor",
+            worker = %name,
+            failures = alert.consecutive_failures,
+            "worker keeps failing: {}",
+            alert.last_failure
+        );
+        if let Some(telemetry) = &self.telemetry {
+            telemetry.record_error(format!(
+                "worker {name} escalated after {} consecutive failures",
+                alert.consecutive_failures
+            ));
+        }
+        if let Some(alerts) = &self.alerts {
+            alerts.raise(&alert);
+        }
+    }
+
+    fn backoff(&self, failures: u32) -> chrono::Duration {
+        let base = self.config.restart_backoff_ms;
+        let delay = base
+            .saturating_mul(1u64 << failures.saturating_sub(1).min(32))
+            .min(self.config.max_backoff_ms.max(base));
+        chrono::Duration::milliseconds(delay as i64)
+    }
+}
+
+fn stop_instance(worker: &Worker) {
+    if let Some((beat, _)) = &worker.instance {
+        beat.stop.store(true, Ordering::SeqCst);
+    }
+}
+
+fn panic_message(payload: &Box<dyn Any + Send>) -> &str {
+    payload
+        .downcast_ref::<&str>()
+        .copied()
+        .or_else(|| payload.downcast_ref::<String>().map(String::as_str))
+        .unwrap_or("unknown panic")
+}
+
+/// Stops every supervised worker when dropped, or earlier through
+/// [`ShutdownHandle::shutdown`].
+#[must_use = "dropping the handle stops the workers"]
+pub struct ShutdownHandle {
+    supervisor: Supervisor,
+}
+
+impl ShutdownHandle {
+    pub fn shutdown(self) {}
+}
+
+impl Drop for ShutdownHandle {
+    fn drop(&mut self) {
+        self.supervisor.shutdown();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::clock::{Clock, ManualClock};
+    use chrono::TimeZone;
+
+    #[derive(Default)]
+    struct RecordingAlerts(Mutex<Vec<WorkerAlert>>);
+
+    impl AlertSink for RecordingAlerts {
+        fn raise(&self, alert: &WorkerAlert) {
+            self.0.lock().unwrap().push(alert.clone());
+        }
+    }
+
+    fn wait_until(mut done: impl FnMut() -> bool) {
+        for _ in 0..200 {
+            if done() {
+                return;
+            }
+            thread::sleep(Duration::from_millis(5));
+        }
+        panic!("condition not reached");
+    }
+
+    fn supervisor(clock: &ManualClock) -> Supervisor {
+        Supervisor::new(SupervisorConfig {
+            heartbeat_timeout_ms: 10_000,
+            restart_backoff_ms: 1_000,
+            max_backoff_ms: 4_000,
+            escalate_after: 3,
+        })
+        .with_clock(SharedClock::new(clock.clone()))
+    }
+
+    #[test]
+    fn crashed_worker_is_restarted_with_backoff_and_escalated() {
+        let clock = ManualClock::new(Utc.with_ymd_and_hms(2024, 9, 2, 8, 0, 0).unwrap());
+        let alerts = Arc::new(RecordingAlerts::default());
+        let supervisor = supervisor(&clock).with_alerts(alerts.clone());
+        supervisor.spawn("sync", |_| panic!("mailbox locked"));
+
+        let mut delays = Vec::new();
+        for _ in 0..3 {
+            wait_until(|| {
+                supervisor.check();
+                supervisor.status()[0].state == WorkerState::Restarting
+            });
+            let status = supervisor.status().remove(0);
+            delays.push((status.next_restart_at.unwrap() - clock.now()).num_milliseconds());
+            clock.advance(chrono::Duration::milliseconds(delays[delays.len() - 1]));
+            supervisor.check();
+        }
+
+        let status = supervisor.status().remove(0);
+        assert_eq!(delays, vec![1_000, 2_000, 4_000]);
+        assert_eq!(status.restarts, 3);
+        assert_eq!(
+            status.last_failure.as_deref(),
+            Some("panicked: mailbox locked")
+        );
+        assert!(status.escalated);
+        let raised = alerts.0.lock().unwrap();
+        assert_eq!(raised.len(), 1, "escalated once per failure streak");
+        assert_eq!(raised[0].worker, "sync");
+        assert_eq!(raised[0].consecutive_failures, 3);
+        drop(raised);
+        supervisor.shutdown();
+    }
+
+    #[test]
+    fn silent_worker_is_replaced_and_healthy_worker_left_alone() {
+        let clock = ManualClock::new(Utc.with_ymd_and_hms(2024, 9, 2, 8, 0, 0).unwrap());
+        let supervisor = supervisor(&clock);
+        let idle = |context: WorkerContext| {
+            while !context.should_stop() {
+                thread::sleep(Duration::from_millis(1));
+            }
+        };
+        supervisor.spawn("gateway", idle);
+        supervisor.spawn("queue", move |context: WorkerContext| {
+            while !context.should_stop() {
+                context.heartbeat();
+                thread::sleep(Duration::from_millis(1));
+            }
+        });
+
+        clock.advance(chrono::Duration::seconds(11));
+        thread::sleep(Duration::from_millis(20));
+        supervisor.check();
+        let status = supervisor.status();
+        assert_eq!(status[0].name, "gateway");
+        assert_eq!(status[0].state, WorkerState::Restarting);
+        assert!(status[0]
+            .last_failure
+            .as_deref()
+            .unwrap()
+            .starts_with("no heartbeat since"));
+        assert_eq!(status[1].name, "queue");
+        assert_eq!(status[1].state, WorkerState::Running);
+        assert_eq!(status[1].restarts, 0);
+
+        clock.advance(chrono::Duration::seconds(1));
+        supervisor.check();
+        assert_eq!(supervisor.status()[0].state, WorkerState::Running);
+        assert_eq!(supervisor.status()[0].restarts, 1);
+        supervisor.shutdown();
+        assert!(supervisor
+            .status()
+            .iter()
+            .all(|worker| worker.state == WorkerState::Stopped));
+    }
+}
@@ -1,11 +1,32 @@
+//! Support bundles: local storage and optional upload to a remote intake.
+//!
+//! `POST /support/upload` stores a bundle under `support/` beside a `.json`
+//! sidecar holding its [`SupportMetadata`]. When `support.upload.url` is set,
+//! the supervised `support-upload` worker pushes stored bundles there and
+//! records the outcome, including the ticket reference the intake answers
+//! with, in the sidecar's `upload` field. `POST /support/{name}/upload`
+//! retries a bundle whose upload failed.
+//!
+//! `GET /support` serves [`SupportStorage::bundles`]. The directory is kept
+//! within `support.retention.*` by [`SupportStorage::prune`], which runs after
+//! each stored bundle and in the supervised `support-retention` worker.
+
+use std::cmp::Reverse;
 use std::fs::{self, File};
 use std::io::Write;
 use std::path::{Path, PathBuf};
 use std::sync::Arc;
+use std::thread;
+use std::time::Duration;
 
 use chrono::{DateTime, Utc};
 use serde::{Deserialize, Serialize};
 use thiserror::Error;
+use tracing::{info, warn};
+
+use crate::clock::SharedClock;
+use crate::config::{SupportRetentionConfig, SupportUploadConfig};
+use crate::supervisor::Supervisor;
 
 #[derive(Debug, Error)]
 pub enum SupportError {
@@ -15,6 +36,21 @@ pub enum SupportError {
     Serialize(#[from] serde_json::Error),
     #[error("bundle missing data")]
     EmptyBundle,
+    #[error("support bundle {0} not found")]
+    NotFound(String),
+    #[error("support bundle upload is not configured")]
+    UploadDisabled,
+}
+
+impl SupportError {
+    pub fn status(&self) -> u16 {
+        match self {
+            Self::EmptyBundle => 400,
+            Self::NotFound(_) => 404,
+            Self::UploadDisabled => 409,
+            Self::Io(_) | Self::Serialize(_) => 500,
+        }
+    }
 }
 
 #[derive(Clone, Debug, Serialize, Deserialize, PartialEq, Eq)]
@@ -23,6 +59,9 @@ pub struct SupportMetadata {
     pub channel: String,
     pub created_at: DateTime<Utc>,
     pub notes: Option<String>,
+    /// Progress of the upload to the remote intake; absent until attempted.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub upload: Option<SupportUpload>,
 }
 
 impl Default for SupportMetadata {
@@ -32,22 +71,90 @@ impl Default for SupportMetadata {
             channel: "ui".into(),
             created_at: Utc::now(),
             notes: None,
+            upload: None,
         }
     }
 }
 
+#[derive(Clone, Copy, Debug, Serialize, Deserialize, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum UploadStatus {
+    /// Not uploaded yet; retried once `next_attempt_at` has passed.
+    Pending,
+    Uploaded,
+    /// Rejected by the intake or out of attempts.
+    Failed,
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize, PartialEq, Eq)]
+pub struct SupportUpload {
+    pub status: UploadStatus,
+    pub attempts: u32,
+    pub last_attempt_at: Option<DateTime<Utc>>,
+    pub next_attempt_at: Option<DateTime<Utc>>,
+    pub uploaded_at: Option<DateTime<Utc>>,
+    /// Ticket reference the intake answered with.
+    pub ticket: Option<String>,
+    pub error: Option<String>,
+}
+
+impl SupportUpload {
+    fn pending() -> Self {
+        Self {
+            status: UploadStatus::Pending,
+            attempts: 0,
+            last_attempt_at: None,
+            next_attempt_at: None,
+            uploaded_at: None,
+            ticket: None,
+            error: None,
+        }
+    }
+}
+
+/// A stored bundle as listed by `GET /support`, newest first.
+#[derive(Clone, Debug, Serialize, Deserialize, PartialEq, Eq)]
+#[serde(rename_all = "camelCase")]
+pub struct SupportBundle {
+    pub name: String,
+    pub size_bytes: u64,
+    pub metadata: SupportMetadata,
+}
+
+/// Bundles removed by one [`SupportStorage::prune`].
+#[derive(Clone, Debug, Default, Serialize, PartialEq, Eq)]
+#[serde(rename_all = "camelCase")]
+pub struct SupportPruneReport {
+    pub removed: Vec<String>,
+    pub freed_bytes: u64,
+}
+
 #[derive(Clone)]
 pub struct SupportStorage {
     base: Arc<PathBuf>,
+    retention: SupportRetentionConfig,
+    clock: SharedClock,
 }
 
 impl SupportStorage {
     pub fn new(path: impl Into<PathBuf>) -> Self {
         Self {
             base: Arc::new(path.into()),
+            retention: SupportRetentionConfig::default(),
+            clock: SharedClock::default(),
         }
     }
 
+    pub fn with_retention(mut self, retention: SupportRetentionConfig) -> Self {
+        self.retention = retention;
+        self
+    }
+
+    pub fn with_clock(mut self, clock: SharedClock) -> Self {
+        self.clock = clock;
+        self
+    }
+
     pub fn store(
         &self,
         bundle: &[u8],
@@ -61,20 +168,22 @@ impl SupportStorage {
         let name = format!("trace-{}-{}.zip", timestamp, metadata.channel);
         let bundle_path = directory.join(&name);
         fs::write(&bundle_path, bundle)?;
-
-        let mut meta_path = bundle_path.clone();
-        meta_path.set_extension("json");
-        let mut file = File::create(meta_path)?;
-        let serialized = serde_json::to_vec_pretty(metadata)?;
-        file.write_all(&serialized)?;
+        self.write_metadata(&bundle_path, metadata)?;
+        if let Err(err) = self.prune() {
+            warn!(target = "support", "failed to prune support bundles: {err}");
+        }
 
         Ok(bundle_path)
     }
 
     pub fn list(&self) -> Result<Vec<PathBuf>, SupportError> {
-        let directory = self.ensure_directory()?;
+        let entries = match fs::read_dir(self.directory()) {
+            Ok(entries) => entries,
+            Err(err) if err.kind() == std::io::ErrorKind::NotFound => return Ok(Vec::new()),
+            Err(err) => return Err(err.into()),
+        };
         let mut items = Vec::new();
-        for entry in fs::read_dir(directory)? {
+        for entry in entries {
             let entry = entry?;
             if entry.path().extension().and_then(|value| value.to_str()) == Some("zip") {
                 items.push(entry.path());
@@ -84,9 +193,479 @@ impl SupportStorage {
         Ok(items)
     }
 
+    /// Path of the stored bundle called `name`.
+    pub fn bundle(&self, name: &str) -> Result<PathBuf, SupportError> {
+        let path = self.directory().join(name);
+        if name.contains(['/', '\\']) || !name.ends_with(".zip") || !path.is_file() {
+            return Err(SupportError::NotFound(name.to_string()));
+        }
+        Ok(path)
+    }
+
+    /// The sidecar metadata of `bundle`. Without one, defaults dated by the
+    /// bundle's modification time.
+    pub fn metadata(&self, bundle: &Path) -> Result<SupportMetadata, SupportError> {
+        match fs::read(bundle.with_extension("json")) {
+            Ok(contents) => Ok(serde_json::from_slice(&contents)?),
+            Err(err) if err.kind() == std::io::ErrorKind::NotFound => Ok(SupportMetadata {
+                created_at: fs::metadata(bundle)?.modified()?.into(),
+                ..SupportMetadata::default()
+            }),
+            Err(err) => Err(err.into()),
+        }
+    }
+
+    /// Every stored bundle with its size and metadata, newest first.
+    pub fn bundles(&self) -> Result<Vec<SupportBundle>, SupportError> {
+        let mut bundles = Vec::new();
+        for path in self.list()? {
+            bundles.push(SupportBundle {
+                name: path
+                    .file_name()
+                    .map(|name| name.to_string_lossy().into_owned())
+                    .unwrap_or_default(),
+                size_bytes: fs::metadata(&path)?.len(),
+                metadata: self.metadata(&path)?,
+            });
+        }
+        bundles.sort_by_key(|bundle| Reverse(bundle.metadata.created_at));
+        Ok(bundles)
+    }
+
+    /// Delete bundles, oldest first, until the directory is within
+    /// `support.retention.*`.
+    pub fn prune(&self) -> Result<SupportPruneReport, SupportError> {
+        let retention = &self.retention;
+        let cutoff = (retention.max_age_days > 0)
+            .then(|| self.clock.now() - chrono::Duration::days(i64::from(retention.max_age_days)));
+        let mut total: u64 = 0;
+        let mut over_size = false;
+        let mut expired = Vec::new();
+        for (position, bundle) in self.bundles()?.into_iter().enumerate() {
+            let too_old = cutoff.is_some_and(|cutoff| bundle.metadata.created_at < cutoff);
+            let surplus = retention.max_count > 0 && position >= retention.max_count;
+            // Once the newest bundles fill the quota, every older one goes.
+            over_size = over_size
+                || (retention.max_total_bytes > 0
+                    && total + bundle.size_bytes > retention.max_total_bytes);
+            if too_old || surplus || over_size {
+                expired.push(bundle);
+            } else {
+                total += bundle.size_bytes;
+            }
+        }
+
+        let mut report = SupportPruneReport::default();
+        let directory = self.directory();
+        for bundle in expired {
+            let path = directory.join(&bundle.name);
+            fs::remove_file(&path)?;
+            match fs::remove_file(path.with_extension("json")) {
+                Err(err) if err.kind() != std::io::ErrorKind::NotFound => return Err(err.into()),
+                _ => {}
+            }
+            report.freed_bytes += bundle.size_bytes;
+            report.removed.push(bundle.name);
+        }
+        if !report.removed.is_empty() {
+            info!(
+                target = "support",
+                removed = report.removed.len(),
+                freed_bytes = report.freed_bytes,
+                "pruned support bundles"
+            );
+        }
+        Ok(report)
+    }
+
+    /// Run [`prune`](Self::prune) every `support.retention.intervalSecs`.
+    pub fn spawn(&self, supervisor: &Supervisor) {
+        let storage = self.clone();
+        let interval = Duration::from_secs(self.retention.interval_secs.max(1));
+        supervisor.spawn("support-retention", move |context| {
+            while !context.should_stop() {
+                if let Err(err) = storage.prune() {
+                    warn!(target = "support", "failed to prune support bundles: {err}");
+                }
+                let mut waited = Duration::ZERO;
+                while waited < interval && !context.should_stop() {
+                    context.heartbeat();
+                    let step = (interval - waited).min(Duration::from_secs(1));
+                    thread::sleep(step);
+                    waited += step;
+                }
+            }
+        });
+    }
+
+    pub fn write_metadata(
+        &self,
+        bundle: &Path,
+        metadata: &SupportMetadata,
+    ) -> Result<(), SupportError> {
+        let mut file = File::create(bundle.with_extension("json"))?;
+        let serialized = serde_json::to_vec_pretty(metadata)?;
+        file.write_all(&serialized)?;
+        Ok(())
+    }
+
+    fn directory(&self) -> PathBuf {
+        Path::new(&*self.base).join("support")
+    }
+
     fn ensure_directory(&self) -> Result<PathBuf, SupportError> {
-        let directory = Path::new(&*self.base).join("support");
+        let directory = self.directory();
         fs::create_dir_all(&directory)?;
         Ok(directory)
     }
 }
+
+/// HTTP client abstraction so uploads can be exercised without a network.
+pub trait SupportTransport: Send + Sync {
+    /// POST `body` and return the response status and body, or a transport error.
+    fn post(
+        &self,
+        url: &str,
+        headers: &[(&str, String)],
+        body: &[u8],
+    ) -> Result<(u16, String), String>;
+}
+
+/// Blocking transport backed by `ureq`.
+pub struct HttpSupportTransport {
+    agent: ureq::Agent,
+}
+
+impl HttpSupportTransport {
+    pub fn new(timeout: Duration) -> Self {
+        Self {
+            agent: ureq::AgentBuilder::new().timeout(timeout).build(),
+        }
+    }
+}
+
+impl SupportTransport for HttpSupportTransport {
+    fn post(
+        &self,
+        url: &str,
+        headers: &[(&str, String)],
+        body: &[u8],
+    ) -> Result<(u16, String), String> {
+        let mut request = self.agent.post(url);
+        for (name, value) in headers {
+            request = request.set(name, value);
+        }
+        match request.send_bytes(body) {
+            Ok(response) => {
+                let status = response.status();
+                Ok((status, response.into_string().unwrap_or_default()))
+            }
+            Err(ureq::Error::Status(status, response)) => {
+                Ok((status, response.into_string().unwrap_or_default()))
+            }
+            Err(ureq::Error::Transport(err)) => Err(err.to_string()),
+        }
+    }
+}
+
+/// Pushes stored bundles to `support.upload.url`, retrying transient failures.
+#[derive(Clone)]
+pub struct SupportUploader {
+    config: SupportUploadConfig,
+    storage: SupportStorage,
+    transport: Arc<dyn SupportTransport>,
+    clock: SharedClock,
+}
+
+impl SupportUploader {
+    pub fn new(config: SupportUploadConfig, storage: SupportStorage) -> Self {
+        let transport = HttpSupportTransport::new(Duration::from_millis(config.timeout_ms));
+        Self::with_transport(config, storage, Arc::new(transport))
+    }
+
+    pub fn with_transport(
+        config: SupportUploadConfig,
+        storage: SupportStorage,
+        transport: Arc<dyn SupportTransport>,
+    ) -> Self {
+        Self {
+            config,
+            storage,
+            transport,
+            clock: SharedClock::default(),
+        }
+    }
+
+    pub fn with_clock(mut self, clock: SharedClock) -> Self {
+        self.clock = clock;
+        self
+    }
+
+    pub fn enabled(&self) -> bool {
+        self.config.url.is_some()
+    }
+
+    /// Upload the bundle called `name` now, starting over if it had failed.
+    pub fn upload(&self, name: &str) -> Result<SupportUpload, SupportError> {
+        if !self.enabled() {
+            return Err(SupportError::UploadDisabled);
+        }
+        let bundle = self.storage.bundle(name)?;
+        let mut metadata = self.storage.metadata(&bundle)?;
+        match &metadata.upload {
+            Some(upload) if upload.status == UploadStatus::Uploaded => return Ok(upload.clone()),
+            Some(upload) if upload.status == UploadStatus::Failed => metadata.upload = None,
+            _ => {}
+        }
+        self.attempt(&bundle, metadata)
+    }
+
+    /// Attempt every bundle not yet uploaded whose retry time has come.
+    pub fn upload_due(&self) -> Result<Vec<SupportUpload>, SupportError> {
+        if !self.enabled() {
+            return Ok(Vec::new());
+        }
+        let now = self.clock.now();
+        let mut outcomes = Vec::new();
+        for bundle in self.storage.list()? {
+            let metadata = self.storage.metadata(&bundle)?;
+            let due = metadata.upload.as_ref().is_none_or(|upload| {
+                upload.status == UploadStatus::Pending
+                    && upload.next_attempt_at.is_none_or(|next| next <= now)
+            });
+            if due {
+                outcomes.push(self.attempt(&bundle, metadata)?);
+            }
+        }
+        Ok(outcomes)
+    }
+
+    /// Run [`upload_due`](Self::upload_due) every `support.upload.intervalSecs`.
+    pub fn spawn(&self, supervisor: &Supervisor) {
+        if !self.enabled() {
+            return;
+        }
+        let uploader = self.clone();
+        let interval = Duration::from_secs(self.config.interval_secs.max(1));
+        supervisor.spawn("support-upload", move |context| {
+            while !context.should_stop() {
+                if let Err(err) = uploader.upload_due() {
+                    warn!(
+                        target = "support",
+                        "support bundle upload sweep failed: {err}"
+                    );
+                }
+                let mut waited = Duration::ZERO;
+                while waited < interval && !context.should_stop() {
+                    context.heartbeat();
+                    let step = (interval - waited).min(Duration::from_secs(1));
+                    thread::sleep(step);
+                    waited += step;
+                }
+            }
+        });
+    }
+
+    fn attempt(
+        &self,
+        bundle: &Path,
+        mut metadata: SupportMetadata,
+    ) -> Result<SupportUpload, SupportError> {
+        let url = self
+            .config
+            .url
+            .as_deref()
+            .ok_or(SupportError::UploadDisabled)?;
+        let body = fs::read(bundle)?;
+        let name = bundle
+            .file_name()
+            .map(|name| name.to_string_lossy().into_owned())
+            .unwrap_or_default();
+        let mut headers = vec![
+            ("Content-Type", "application/zip".to_string()),
+            ("X-Support-Bundle", name.clone()),
+            ("X-Support-Channel", metadata.channel.clone()),
+            ("X-Support-Reporter", metadata.reporter.clone()),
+        ];
+        if let Some(token) = &self.config.auth_token {
+            headers.push((self.config.auth_header.as_str(), token.clone()));
+        }
+
+        let now = self.clock.now();
+        let mut upload = metadata
+            .upload
+            .take()
+            .unwrap_or_else(SupportUpload::pending);
+        upload.attempts += 1;
+        upload.last_attempt_at = Some(now);
+        upload.next_attempt_at = None;
+        let retryable = match self.transport.post(url, &headers, &body) {
+            Ok((status, response)) if (200..300).contains(&status) => {
+                upload.status = UploadStatus::Uploaded;
+                upload.uploaded_at = Some(now);
+                upload.ticket = ticket(&response);
+                upload.error = None;
+                false
+            }
+            Ok((status, _)) => {
+                upload.error = Some(format!("intake responded with HTTP {status}"));
+                // Client errors other than throttling will not succeed on retry.
+                !(400..500).contains(&status) || status == 408 || status == 429
+            }
+            Err(err) => {
+                upload.error = Some(err);
+                true
+            }
+        };
+        if upload.status != UploadStatus::Uploaded {
+            if retryable && upload.attempts < self.config.max_attempts.max(1) {
+                let backoff = self
+                    .config
+                    .backoff_ms
+                    .saturating_mul(1 << (upload.attempts - 1).min(16));
+                upload.status = UploadStatus::Pending;
+                upload.next_attempt_at = Some(
+                    now + chrono::Duration::milliseconds(
+                        i64::try_from(backoff).unwrap_or(i64::MAX),
+                    ),
+                );
+            } else {
+                upload.status = UploadStatus::Failed;
+                warn!(
+                    target = "support",
+                    bundle = %name,
+                    attempts = upload.attempts,
+                    "support bundle upload failed"
+                );
+            }
+        }
+        metadata.upload = Some(upload.clone());
+        self.storage.write_metadata(bundle, &metadata)?;
+        Ok(upload)
+    }
+}
+
+/// The `ticket` field of the intake's JSON response, if it sent one.
+fn ticket(response: &str) -> Option<String> {
+    serde_json::from_str::<serde_json::Value>(response)
+        .ok()?
+        .get("ticket")?
+        .as_str()
+        .map(str::to_string)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::VecDeque;
+    use std::sync::Mutex;
+
+    use crate::clock::ManualClock;
+
+    #[derive(Default)]
+    struct ScriptedIntake {
+        responses: Mutex<VecDeque<Result<(u16, String), String>>>,
+        headers: Mutex<Vec<Vec<(String, String)>>>,
+    }
+
+    impl SupportTransport for ScriptedIntake {
+        fn post(
+            &self,
+            _url: &str,
+            headers: &[(&str, String)],
+            _body: &[u8],
+        ) -> Result<(u16, String), String> {
+            self.headers.lock().unwrap().push(
+                headers
+                    .iter()
+                    .map(|(name, value)| (name.to_string(), value.clone()))
+                    .collect(),
+            );
+            self.responses
+                .lock()
+                .unwrap()
+                .pop_front()
+                .unwrap_or(Ok((200, String::new())))
+        }
+    }
+
+    #[test]
+    fn retries_uploads_and_records_the_ticket() {
+        let dir = tempfile::tempdir().unwrap();
+        let storage = SupportStorage::new(dir.path());
+        let bundle = storage
+            .store(&[1, 2, 3], &SupportMetadata::default())
+            .unwrap();
+        let intake = Arc::new(ScriptedIntake::default());
+        intake.responses.lock().unwrap().extend([
+            Err("connection reset".to_string()),
+            Ok((201, r#"{"ticket":"SUP-4711"}"#.to_string())),
+        ]);
+        let clock = ManualClock::new(Utc::now());
+        let uploader = SupportUploader::with_transport(
+            SupportUploadConfig {
+                url: Some("https://support.example.com/intake".into()),
+                auth_token: Some("Bearer t0ken".into()),
+                backoff_ms: 1_000,
+                ..SupportUploadConfig::default()
+            },
+            storage.clone(),
+            intake.clone(),
+        )
+        .with_clock(SharedClock::new(clock.clone()));
+
+        let first = uploader.upload_due().unwrap();
+        assert_eq!(first[0].status, UploadStatus::Pending);
+        assert_eq!(first[0].error.as_deref(), Some("connection reset"));
+        assert!(uploader.upload_due().unwrap().is_empty());
+
+        clock.advance(chrono::Duration::seconds(1));
+        let second = uploader.upload_due().unwrap();
+        assert_eq!(second[0].status, UploadStatus::Uploaded);
+        assert_eq!(second[0].attempts, 2);
+
+        let upload = storage.metadata(&bundle).unwrap().upload.unwrap();
+        assert_eq!(upload.ticket.as_deref(), Some("SUP-4711"));
+        let headers = intake.headers.lock().unwrap();
+        assert!(headers[1].contains(&("Authorization".into(), "Bearer t0ken".into())));
+        assert!(uploader.upload_due().unwrap().is_empty());
+    }
+
+    #[test]
+    fn prunes_by_age_count_and_total_size() {
+        let dir = tempfile::tempdir().unwrap();
+        let now = Utc::now();
+        let storage = SupportStorage::new(dir.path())
+            .with_retention(SupportRetentionConfig {
+                max_total_bytes: 25,
+                max_age_days: 30,
+                max_count: 3,
+                interval_secs: 3_600,
+            })
+            .with_clock(SharedClock::new(ManualClock::new(now)));
+        let store = |days_ago: i64, channel: &str, size: usize| {
+            let metadata = SupportMetadata {
+                channel: channel.into(),
+                created_at: now - chrono::Duration::days(days_ago),
+                ..SupportMetadata::default()
+            };
+            storage.store(&vec![0; size], &metadata).unwrap()
+        };
+
+        store(40, "stale", 1);
+        store(4, "cli", 10);
+        store(3, "ui", 10);
+        store(2, "ui", 10);
+        store(1, "api", 1);
+
+        let bundles = storage.bundles().unwrap();
+        let channels: Vec<&str> = bundles
+            .iter()
+            .map(|bundle| bundle.metadata.channel.as_str())
+            .collect();
+        assert_eq!(channels, ["api", "ui", "ui"]);
+        assert_eq!(bundles[1].size_bytes, 10);
+        assert_eq!(fs::read_dir(dir.path().join("support")).unwrap().count(), 6);
+        assert!(storage.prune().unwrap().removed.is_empty());
+    }
+}
@@ -1,23 +1,23 @@
 use std::collections::VecDeque;
 use std::fmt;
-use std::fs::{self, OpenOptions};
-use std::io::{self, Write};
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, Read, Seek, SeekFrom, Write};
 use std::path::{Path, PathBuf};
 use std::pin::Pin;
 use std::sync::{Arc, Mutex};
-use std::time::{Duration, SystemTime};
+use std::thread;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
 
 use once_cell::sync::OnceCell;
 use opentelemetry::global;
 use opentelemetry::trace::TracerProvider;
-use opentelemetry::KeyValue;
 use opentelemetry_sdk::export::trace::{ExportResult, SpanData, SpanExporter};
-use opentelemetry_sdk::trace;
-use opentelemetry_sdk::Resource;
+use opentelemetry_sdk::trace::{self, Sampler};
 use regex::Regex;
+use ring::rand::{SecureRandom, SystemRandom};
 use serde::{Deserialize, Serialize};
 use thiserror::Error;
-use tracing::warn;
+use tracing::{info, warn};
 use tracing_appender::non_blocking::WorkerGuard;
 use tracing_opentelemetry::OpenTelemetryLayer;
 use tracing_subscriber::util::{SubscriberInitExt, TryInitError};
@@ -26,6 +26,12 @@ use zip::result::ZipError;
 use zip::write::FileOptions;
 
 use crate::config::TelemetryConfig;
+use crate::metrics::{self, MetricsRegistry};
+use crate::otlp::{self, OtlpExporter};
+use crate::supervisor::{Supervisor, WorkerContext};
+
+/// How often the `telemetry-retention` worker prunes local files.
+const RETENTION_INTERVAL: Duration = Duration::from_secs(60 * 60);
 
 #[derive(Debug, Error)]
 pub enum TelemetryError {
@@ -52,6 +58,12 @@ pub struct TelemetryMetrics {
     pub latency_samples: u64,
     pub queue_depth: usize,
     pub error_count: u64,
+    #[serde(default)]
+    pub worker_restarts: u64,
+    #[serde(default)]
+    pub p7_session_losses: u64,
+    #[serde(default)]
+    pub sdk_timeouts: u64,
 }
 
 impl TelemetryMetrics {
@@ -63,6 +75,15 @@ impl TelemetryMetrics {
     }
 }
 
+/// Files removed or cut by one [`TelemetryManager::prune`].
+#[derive(Clone, Debug, Default, Serialize, PartialEq, Eq)]
+#[serde(rename_all = "camelCase")]
+pub struct TelemetryPruneReport {
+    pub removed: Vec<String>,
+    /// Includes the expired entries cut from the front of `trace.jsonl`.
+    pub freed_bytes: u64,
+}
+
 #[derive(Clone, Debug, Serialize, Deserialize, PartialEq)]
 pub struct TelemetrySnapshot {
     pub metrics: TelemetryMetrics,
@@ -77,8 +98,91 @@ struct TelemetryInner {
     metrics: Mutex<TelemetryMetrics>,
     events: Mutex<VecDeque<TelemetryEvent>>,
     errors: Mutex<VecDeque<String>>,
-    log_path: PathBuf,
+    log: TraceLog,
     guard: OnceCell<WorkerGuard>,
+    /// Fed whether or not telemetry is enabled; served by `GET /metrics`.
+    registry: MetricsRegistry,
+    /// Set when telemetry is enabled with a `telemetry.endpoint`.
+    otlp: Option<OtlpExporter>,
+}
+
+/// `trace.jsonl`, shared by the log layer, recorded events and retention.
+///
+/// Appends and the retention trim take the same lock, so no line is written
+/// while the file is being cut and none is lost to the rewrite.
+#[derive(Clone, Default)]
+struct TraceLog {
+    path: PathBuf,
+    file: Arc<Mutex<Option<File>>>,
+}
+
+impl TraceLog {
+    fn new(path: PathBuf) -> Self {
+        Self {
+            path,
+            file: Arc::default(),
+        }
+    }
+
+    fn append(&self, bytes: &[u8]) -> io::Result<()> {
+        let mut file = self
+            .file
+            .lock()
+            .map_err(|_| io::Error::other("trace log lock poisoned"))?;
+        if file.is_none() {
+            *file = Some(
+                OpenOptions::new()
+                    .create(true)
+                    .append(true)
+                    .open(&self.path)?,
+            );
+        }
+        file.as_mut().expect("opened above").write_all(bytes)
+    }
+
+    /// Cut the leading entries stamped before `cutoff`, returning the bytes
+    /// removed.
+    fn trim(&self, cutoff: SystemTime) -> io::Result<u64> {
+        let mut file = self
+            .file
+            .lock()
+            .map_err(|_| io::Error::other("trace log lock poisoned"))?;
+        let contents = match fs::read(&self.path) {
+            Ok(contents) => contents,
+            Err(err) if err.kind() == io::ErrorKind::NotFound => return Ok(0),
+            Err(err) => return Err(err),
+        };
+        let mut expired = 0;
+        for line in contents.split_inclusive(|byte| *byte == b'\n') {
+            if logged_at(line).is_none_or(|logged| logged >= cutoff) {
+                break;
+            }
+            expired += line.len();
+        }
+        if expired == 0 {
+            return Ok(0);
+        }
+        // The held handle appends, so after truncating, the kept tail is
+        // written from the start and later appends follow it.
+        let handle = match file.as_mut() {
+            Some(handle) => handle,
+            None => file.insert(OpenOptions::new().append(true).open(&self.path)?),
+        };
+        handle.set_len(0)?;
+        handle.write_all(&contents[expired..])?;
+        Ok(expired as u64)
+    }
+}
+
+impl Write for TraceLog {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.append(buf)?;
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
 }
 
 /// Manager responsible for telemetry and diagnostics.
@@ -90,14 +194,24 @@ pub struct TelemetryManager {
 impl TelemetryManager {
     pub fn from_config(config: &TelemetryConfig) -> Self {
         let base = PathBuf::from(&config.local_path);
-        let log_path = base.join("trace.jsonl");
+        let log = TraceLog::new(base.join("trace.jsonl"));
         let inner = TelemetryInner {
             config: config.clone(),
             metrics: Mutex::new(TelemetryMetrics::default()),
             events: Mutex::new(VecDeque::with_capacity(256)),
             errors: Mutex::new(VecDeque::with_capacity(64)),
-            log_path,
+            log,
             guard: OnceCell::new(),
+            registry: MetricsRegistry::new(),
+            otlp: config
+                .enabled
+                .then(|| OtlpExporter::from_config(config))
+                .transpose()
+                .unwrap_or_else(|err| {
+                    warn!(target = "telemetry", "OTLP export disabled: {err}");
+                    None
+                })
+                .flatten(),
         };
 
         let manager = Self {
@@ -119,22 +233,27 @@ impl TelemetryManager {
     fn initialise_runtime(&self) -> Result<(), TelemetryError> {
         let base = PathBuf::from(&self.inner.config.local_path);
         fs::create_dir_all(&base)?;
-        let file = OpenOptions::new()
-            .create(true)
-            .append(true)
-            .open(&self.inner.log_path)?;
-        let (writer, guard) = tracing_appender::non_blocking(file);
+        let (writer, guard) = tracing_appender::non_blocking(self.inner.log.clone());
         self.inner.guard.get_or_init(|| guard);
 
         let exporter = FileSpanExporter {
             manager: self.clone(),
         };
-        let provider = trace::TracerProvider::builder()
+        let mut provider = trace::TracerProvider::builder()
             .with_simple_exporter(exporter)
-            .with_config(trace::Config::default().with_resource(Resource::new(vec![
-                KeyValue::new("service.name", "x400-core"),
-            ])))
-            .build();
+            .with_config(
+                trace::Config::default()
+                    .with_sampler(Sampler::ParentBased(Box::new(Sampler::TraceIdRatioBased(
+                        f64::from(self.inner.config.sampling),
+                    ))))
+                    .with_resource(otlp::resource(&self.inner.config)),
+            );
+        if self.inner.otlp.is_some() {
+            provider = provider.with_simple_exporter(OtlpSpanExporter {
+                manager: self.clone(),
+            });
+        }
+        let provider = provider.build();
         let tracer = provider.tracer("core-service");
         let layer = OpenTelemetryLayer::new(tracer);
 
@@ -158,6 +277,15 @@ impl TelemetryManager {
     }
 
     pub fn record_flow(&self, flow: &str, latency: Duration, success: bool, queue_depth: usize) {
+        let registry = &self.inner.registry;
+        registry.set(&metrics::QUEUE_DEPTH, &[], queue_depth as f64);
+        registry.increment(
+            &metrics::FLOWS,
+            &[("flow", flow), ("outcome", outcome(success))],
+        );
+        if !success {
+            registry.increment(&metrics::ERRORS, &[]);
+        }
         if !self.inner.config.enabled {
             return;
         }
@@ -172,34 +300,145 @@ impl TelemetryManager {
         metrics.latency_samples += 1;
         drop(metrics);
 
-        let event = TelemetryEvent {
-            flow: flow.to_string(),
-            latency_ms: latency.as_millis(),
-            success,
-            timestamp: now_millis(),
-        };
-        self.push_event(event.clone());
-        if let Err(err) = self.append_event(&event) {
-            warn!(
-                target = "telemetry",
-                "failed to persist telemetry event: {err}"
-            );
+        if self.sampled(success) {
+            let event = TelemetryEvent {
+                flow: flow.to_string(),
+                latency_ms: latency.as_millis(),
+                success,
+                timestamp: now_millis(),
+            };
+            self.push_event(event.clone());
+            if let Err(err) = self.append_event(&event) {
+                warn!(
+                    target = "telemetry",
+                    "failed to persist telemetry event: {err}"
+                );
+            }
         }
         if let Err(err) = self.persist_snapshot() {
             warn!(target = "telemetry", "failed to persist snapshot: {err}");
         }
         if !success {
-            self.record_error(format!("flow {flow} reported failure"));
+            self.keep_error(format!("flow {flow} reported failure"));
+        }
+    }
+
+    /// Whether a flow event is kept, at the `telemetry.sampling` rate. The
+    /// counters always see every flow, and failures are always kept.
+    fn sampled(&self, success: bool) -> bool {
+        let rate = f64::from(self.inner.config.sampling);
+        if !success || rate >= 1.0 {
+            return true;
+        }
+        if rate <= 0.0 {
+            return false;
+        }
+        let mut bytes = [0; 8];
+        if SystemRandom::new().fill(&mut bytes).is_err() {
+            return true;
+        }
+        (u64::from_le_bytes(bytes) as f64) < rate * u64::MAX as f64
+    }
+
+    /// Count a submission the active transport accepted or refused.
+    pub fn record_submit(&self, transport: &str, latency: Duration, success: bool) {
+        let registry = &self.inner.registry;
+        registry.observe(
+            &metrics::SUBMIT_DURATION,
+            &[("transport", transport)],
+            latency.as_secs_f64(),
+        );
+        registry.increment(
+            &metrics::SUBMISSIONS,
+            &[("transport", transport), ("outcome", outcome(success))],
+        );
+    }
+
+    /// Count a message relayed by the gateway; `direction` is `inbound` or `outbound`.
+    pub fn record_gateway(&self, direction: &str, success: bool) {
+        self.inner.registry.increment(
+            &metrics::GATEWAY_MESSAGES,
+            &[("direction", direction), ("outcome", outcome(success))],
+        );
+    }
+
+    /// Count a vendor SDK call and its duration.
+    pub fn record_sdk_call(&self, operation: &str, latency: Duration, success: bool) {
+        let registry = &self.inner.registry;
+        registry.observe(
+            &metrics::SDK_CALL_DURATION,
+            &[("operation", operation)],
+            latency.as_secs_f64(),
+        );
+        registry.increment(
+            &metrics::SDK_CALLS,
+            &[("operation", operation), ("outcome", outcome(success))],
+        );
+    }
+
+    /// The registry behind `GET /metrics`.
+    pub fn registry(&self) -> &MetricsRegistry {
+        &self.inner.registry
+    }
+
+    /// Count a supervised background worker being restarted.
+    pub fn record_restart(&self, worker: &str, reason: &str) {
+        self.inner
+            .registry
+            .increment(&metrics::WORKER_RESTARTS, &[("worker", worker)]);
+        if !self.inner.config.enabled {
+            return;
+        }
+        if let Ok(mut metrics) = self.inner.metrics.lock() {
+            metrics.worker_restarts += 1;
+        }
+        self.record_error(format!("worker {worker} restarted: {reason}"));
+    }
+
+    /// Count a P7 session lost on `profile` and about to be re-bound.
+    pub fn record_session_lost(&self, profile: &str, reason: &str) {
+        self.inner
+            .registry
+            .increment(&metrics::SESSION_LOSSES, &[("profile", profile)]);
+        if !self.inner.config.enabled {
+            return;
+        }
+        if let Ok(mut metrics) = self.inner.metrics.lock() {
+            metrics.p7_session_losses += 1;
+        }
+        self.record_error(format!("P7 session on {profile} lost: {reason}"));
+    }
+
+    /// Count an SDK call given up on after `timeout`.
+    pub fn record_sdk_timeout(&self, operation: &str, profile: &str, timeout: Duration) {
+        self.inner
+            .registry
+            .increment(&metrics::SDK_TIMEOUTS, &[("operation", operation)]);
+        if !self.inner.config.enabled {
+            return;
+        }
+        if let Ok(mut metrics) = self.inner.metrics.lock() {
+            metrics.sdk_timeouts += 1;
         }
+        self.record_error(format!(
+            "sdk {operation} on {profile} timed out after {}ms",
+            timeout.as_millis()
+        ));
     }
 
     pub fn record_error(&self, message: impl Into<String>) {
+        self.inner.registry.increment(&metrics::ERRORS, &[]);
+        self.keep_error(message.into());
+    }
+
+    /// Keep a redacted error for the snapshot without counting it again.
+    fn keep_error(&self, message: String) {
         if !self.inner.config.enabled {
             return;
         }
         {
             let mut errors = self.inner.errors.lock().expect("errors lock");
-            let mut buffer = redact(message.into());
+            let mut buffer = redact(message);
             if buffer.len() > 512 {
                 buffer.truncate(512);
             }
@@ -249,6 +488,103 @@ impl TelemetryManager {
             .unwrap_or_default()
     }
 
+    /// Send queued spans and the metrics registry to the OTLP collector.
+    /// Span batches it does not take are kept with [`append_remote`](Self::append_remote).
+    pub fn export(&self) {
+        let Some(otlp) = &self.inner.otlp else {
+            return;
+        };
+        for body in otlp.export_spans() {
+            match self.append_remote(&body) {
+                Ok(path) => warn!(
+                    target = "telemetry",
+                    path = %path.display(),
+                    "OTLP collector did not take span batch, kept on disk"
+                ),
+                Err(err) => warn!(target = "telemetry", "failed to keep span batch: {err}"),
+            }
+        }
+        if let Err(err) = otlp.export_metrics(&self.inner.registry) {
+            warn!(target = "telemetry", "OTLP metrics export failed: {err}");
+        }
+    }
+
+    /// Delete `snapshot.json` and `remote-*.bin` files last written before
+    /// the `telemetry.retentionDays` window, and cut the entries older than
+    /// the window from the front of `trace.jsonl`. A window of 0 keeps
+    /// everything.
+    pub fn prune(&self) -> Result<TelemetryPruneReport, TelemetryError> {
+        let mut report = TelemetryPruneReport::default();
+        let days = u64::from(self.inner.config.retention_days);
+        if days == 0 {
+            return Ok(report);
+        }
+        let cutoff = SystemTime::now() - Duration::from_secs(days * 24 * 60 * 60);
+        let entries = match fs::read_dir(&self.inner.config.local_path) {
+            Ok(entries) => entries,
+            Err(err) if err.kind() == io::ErrorKind::NotFound => return Ok(report),
+            Err(err) => return Err(err.into()),
+        };
+        for entry in entries {
+            let entry = entry?;
+            let name = entry.file_name().to_string_lossy().into_owned();
+            let disposable =
+                name == "snapshot.json" || (name.starts_with("remote-") && name.ends_with(".bin"));
+            let metadata = entry.metadata()?;
+            if disposable && metadata.is_file() && metadata.modified()? < cutoff {
+                fs::remove_file(entry.path())?;
+                report.freed_bytes += metadata.len();
+                report.removed.push(name);
+            }
+        }
+        report.freed_bytes += self.trim_log(cutoff)?;
+        if report.freed_bytes > 0 {
+            info!(
+                target = "telemetry",
+                removed = report.removed.len(),
+                freed_bytes = report.freed_bytes,
+                "pruned telemetry files"
+            );
+        }
+        Ok(report)
+    }
+
+    /// Cut the leading `trace.jsonl` entries stamped before `cutoff` while
+    /// holding the log lock, so concurrent appends wait for the rewrite.
+    fn trim_log(&self, cutoff: SystemTime) -> Result<u64, TelemetryError> {
+        Ok(self.inner.log.trim(cutoff)?)
+    }
+
+    /// Start the `telemetry-retention` worker, and the `telemetry-export`
+    /// worker when a collector is configured.
+    pub fn spawn(&self, supervisor: &Supervisor) {
+        if self.inner.config.retention_days > 0 {
+            let manager = self.clone();
+            supervisor.spawn("telemetry-retention", move |context| {
+                while !context.should_stop() {
+                    if let Err(err) = manager.prune() {
+                        warn!(
+                            target = "telemetry",
+                            "failed to prune telemetry files: {err}"
+                        );
+                    }
+                    idle(&context, RETENTION_INTERVAL);
+                }
+            });
+        }
+        if self.inner.otlp.is_some() {
+            let manager = self.clone();
+            let interval = Duration::from_millis(self.inner.config.export_interval_ms.max(100));
+            supervisor.spawn("telemetry-export", move |context| {
+                while !context.should_stop() {
+                    manager.export();
+                    idle(&context, interval);
+                }
+                manager.export();
+            });
+        }
+    }
+
     pub fn append_remote(&self, bundle: &[u8]) -> Result<PathBuf, TelemetryError> {
         let base = PathBuf::from(&self.inner.config.local_path);
         fs::create_dir_all(&base)?;
@@ -270,8 +606,8 @@ impl TelemetryManager {
         writer.start_file("snapshot.json", FileOptions::default())?;
         writer.write_all(&serialized)?;
 
-        if Path::new(&self.inner.log_path).exists() {
-            let contents = fs::read(&self.inner.log_path)?;
+        if Path::new(&self.inner.log.path).exists() {
+            let contents = fs::read(&self.inner.log.path)?;
             writer.start_file("trace.jsonl", FileOptions::default())?;
             writer.write_all(&contents)?;
         }
@@ -280,6 +616,27 @@ impl TelemetryManager {
         Ok(cursor.into_inner())
     }
 
+    /// The last `max_bytes` of the service log, starting at a whole line.
+    pub fn recent_log(&self, max_bytes: u64) -> Result<Vec<u8>, TelemetryError> {
+        let mut file = match fs::File::open(&self.inner.log.path) {
+            Ok(file) => file,
+            Err(err) if err.kind() == io::ErrorKind::NotFound => return Ok(Vec::new()),
+            Err(err) => return Err(err.into()),
+        };
+        let start = file.metadata()?.len().saturating_sub(max_bytes);
+        file.seek(SeekFrom::Start(start))?;
+        let mut contents = Vec::new();
+        file.read_to_end(&mut contents)?;
+        if start > 0 {
+            let first_line = contents
+                .iter()
+                .position(|byte| *byte == b'\n')
+                .map_or(contents.len(), |index| index + 1);
+            contents.drain(..first_line);
+        }
+        Ok(contents)
+    }
+
     fn push_event(&self, event: TelemetryEvent) {
         if let Ok(mut queue) = self.inner.events.lock() {
             if queue.len() >= 256 {
@@ -290,14 +647,9 @@ impl TelemetryManager {
     }
 
     fn append_event(&self, event: &TelemetryEvent) -> Result<(), io::Error> {
-        let mut file = OpenOptions::new()
-            .create(true)
-            .append(true)
-            .open(&self.inner.log_path)?;
-        let line = serde_json::to_vec(event).unwrap_or_default();
-        file.write_all(&line)?;
-        file.write_all(b"\n")?;
-        Ok(())
+        let mut line = serde_json::to_vec(event).unwrap_or_default();
+        line.push(b'\n');
+        self.inner.log.append(&line)
     }
 
     fn persist_snapshot(&self) -> Result<(), io::Error> {
@@ -312,13 +664,55 @@ impl TelemetryManager {
     }
 
     fn snapshot_path(&self) -> PathBuf {
-        Path::new(&self.inner.log_path)
+        Path::new(&self.inner.log.path)
             .parent()
             .map(|parent| parent.join("snapshot.json"))
             .unwrap_or_else(|| PathBuf::from("snapshot.json"))
     }
 }
 
+impl fmt::Debug for TelemetryManager {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("TelemetryManager")
+            .field("enabled", &self.inner.config.enabled)
+            .finish_non_exhaustive()
+    }
+}
+
+/// Sleep for `interval` in heartbeat-sized steps, or until asked to stop.
+fn idle(context: &WorkerContext, interval: Duration) {
+    let mut waited = Duration::ZERO;
+    while waited < interval && !context.should_stop() {
+        context.heartbeat();
+        let step = (interval - waited).min(Duration::from_secs(1));
+        thread::sleep(step);
+        waited += step;
+    }
+}
+
+/// When a `trace.jsonl` line was written: the log layer stamps RFC 3339
+/// text, flow events epoch milliseconds.
+fn logged_at(line: &[u8]) -> Option<SystemTime> {
+    let entry: serde_json::Value = serde_json::from_slice(line).ok()?;
+    match entry.get("timestamp")? {
+        serde_json::Value::Number(millis) => {
+            Some(UNIX_EPOCH + Duration::from_millis(millis.as_u64()?))
+        }
+        serde_json::Value::String(text) => chrono::DateTime::parse_from_rfc3339(text)
+            .ok()
+            .map(SystemTime::from),
+        _ => None,
+    }
+}
+
+fn outcome(success: bool) -> &'static str {
+    if success {
+        "ok"
+    } else {
+        "error"
+    }
+}
+
 fn now_millis() -> u64 {
     SystemTime::now()
         .duration_since(SystemTime::UNIX_EPOCH)
@@ -326,7 +720,9 @@ fn now_millis() -> u64 {
         .unwrap_or_default()
 }
 
-fn redact(input: String) -> String {
+/// Mask e-mail addresses; applied to recorded errors, exported spans and
+/// every file of a trace export.
+pub(crate) fn redact(input: String) -> String {
     static RE_EMAIL: once_cell::sync::Lazy<Regex> = once_cell::sync::Lazy::new(|| {
         Regex::new(r"[A-Za-z0-9._%+-]+@[A-Za-z0-9.-]+\.[A-Za-z]{2,}").expect("regex")
     });
@@ -381,6 +777,43 @@ impl SpanExporter for FileSpanExporter {
     fn shutdown(&mut self) {}
 }
 
+/// Queues spans for the OTLP collector; a full batch is sent on the
+/// exporter thread instead of waiting for the worker.
+struct OtlpSpanExporter {
+    manager: TelemetryManager,
+}
+
+impl fmt::Debug for OtlpSpanExporter {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("OtlpSpanExporter").finish()
+    }
+}
+
+impl SpanExporter for OtlpSpanExporter {
+    fn export(
+        &mut self,
+        batch: Vec<SpanData>,
+    ) -> Pin<Box<dyn std::future::Future<Output = ExportResult> + Send + 'static>> {
+        let manager = self.manager.clone();
+
+        Box::pin(async move {
+            let Some(otlp) = &manager.inner.otlp else {
+                return Ok(());
+            };
+            let mut full = false;
+            for span in &batch {
+                full |= otlp.queue(span);
+            }
+            if full {
+                manager.export();
+            }
+            Ok(())
+        })
+    }
+
+    fn shutdown(&mut self) {}
+}
+
 pub fn tracer(flow: &str) -> opentelemetry::global::BoxedTracer {
     let provider = global::tracer_provider();
     provider.tracer(flow.to_string())
@@ -0,0 +1,301 @@
+//! TLS setup for the HTTP listener, including optional mutual TLS.
+//!
+//! [`server_config`] builds the rustls configuration from [`TlsConfig`] for
+//! the TCP [`crate::ipc::Listener`]. When a client CA is configured, the peer
+//! certificate from the finished handshake is turned into a [`ClientIdentity`]
+//! which the auth layer maps to roles.
+
+use std::fs::File;
+use std::io::{self, BufReader};
+use std::sync::Arc;
+
+use rustls::crypto::ring;
+use rustls::pki_types::{CertificateDer, PrivateKeyDer};
+use rustls::server::WebPkiClientVerifier;
+use rustls::{RootCertStore, ServerConfig};
+use sha2::{Digest, Sha256};
+use thiserror::Error;
+
+use crate::config::TlsConfig;
+
+#[derive(Debug, Error)]
+pub enum TlsError {
+    #[error("failed to read {path}: {source}")]
+    Io { path: String, source: io::Error },
+    #[error("{0} contains no usable PEM entries")]
+    EmptyPem(String),
+    #[error("invalid client certificate: {0}")]
+    InvalidCertificate(String),
+    #[error("TLS configuration rejected: {0}")]
+    Rustls(#[from] rustls::Error),
+    #[error("client verifier could not be built: {0}")]
+    Verifier(String),
+}
+
+/// Identity extracted from a verified client certificate.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct ClientIdentity {
+    pub subject: String,
+    pub common_name: Option<String>,
+    pub fingerprint_sha256: String,
+}
+
+impl ClientIdentity {
+    pub fn from_der(der: &[u8]) -> Result<Self, TlsError> {
+        let (_, certificate) = x509_parser::parse_x509_certificate(der)
+            .map_err(|err| TlsError::InvalidCertificate(err.to_string()))?;
+        let subject = certificate.subject();
+        let common_name = subject
+            .iter_common_name()
+            .next()
+            .and_then(|attribute| attribute.as_str().ok())
+            .map(str::to_string);
+        let fingerprint_sha256 = Sha256::digest(der)
+            .iter()
+            .map(|byte| format!("{byte:02x}"))
+            .collect();
+        Ok(Self {
+            subject: subject.to_string(),
+            common_name,
+            fingerprint_sha256,
+        })
+    }
+}
+
+/// Build the listener configuration described by `tls`.
+pub fn server_config(tls: &TlsConfig) -> Result<Arc<ServerConfig>, TlsError> {
+    let provider = Arc::new(ring::default_provider());
+    let certificates = load_certificates(&tls.certificate_path)?;
+    let key = load_private_key(&tls.private_key_path)?;
+
+    let builder = ServerConfig::builder_with_provider(provider.clone())
+        .with_safe_default_protocol_versions()?;
+    let builder = match &tls.client_ca_path {
+        Some(ca_path) => {
+            let mut roots = RootCertStore::empty();
+            for certificate in load_certificates(ca_path)? {
+                roots.add(certificate)?;
+            }
+            let verifier = WebPkiClientVerifier::builder_with_provider(Arc::new(roots), provider);
+            let verifier = if tls.require_client_cert {
+                verifier.build()
+            } else {
+                verifier.allow_unauthenticated().build()
+            }
+            .map_err(|err| TlsError::Verifier(err.to_string()))?;
+            builder.with_client_cert_verifier(verifier)
+        }
+        None => builder.with_no_client_auth(),
+    };
+    Ok(Arc::new(builder.with_single_cert(certificates, key)?))
+}
+
+fn open(path: &str) -> Result<BufReader<File>, TlsError> {
+    File::open(path)
+        .map(BufReader::new)
+        .map_err(|source| TlsError::Io {
+            path: path.to_string(),
+            source,
+        })
+}
+
+pub(crate) fn load_certificates(path: &str) -> Result<Vec<CertificateDer<'static>>, TlsError> {
+    let certificates = rustls_pemfile::certs(&mut open(path)?)
+        .collect::<Result<Vec<_>, _>>()
+        .map_err(|source| TlsError::Io {
+            path: path.to_string(),
+            source,
+        })?;
+    if certificates.is_empty() {
+        return Err(TlsError::EmptyPem(path.to_string()));
+    }
+    Ok(certificates)
+}
+
+fn load_private_key(path: &str) -> Result<PrivateKeyDer<'static>, TlsError> {
+    rustls_pemfile::private_key(&mut open(path)?)
+        .map_err(|source| TlsError::Io {
+            path: path.to_string(),
+            source,
+        })?
+        .ok_or_else(|| TlsError::EmptyPem(path.to_string()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rcgen::{
+        BasicConstraints, CertificateParams, DnType, ExtendedKeyUsagePurpose, IsCa, KeyPair,
+    };
+    use rustls::pki_types::ServerName;
+    use rustls::{ClientConfig, ClientConnection, ServerConnection};
+    use std::fs;
+
+    struct Pki {
+        dir: tempfile::TempDir,
+        config: TlsConfig,
+        ca: rcgen::Certificate,
+        ca_key: KeyPair,
+    }
+
+    fn pki() -> Pki {
+        let dir = tempfile::tempdir().unwrap();
+        let ca_key = KeyPair::generate().unwrap();
+        let mut ca_params = CertificateParams::new(Vec::<String>::new()).unwrap();
+        ca_params.is_ca = IsCa::Ca(BasicConstraints::Unconstrained);
+        ca_params
+            .distinguished_name
+            .push(DnType::CommonName, "X400 Test CA");
+        let ca = ca_params.self_signed(&ca_key).unwrap();
+
+        let server_key = KeyPair::generate().unwrap();
+        let server = CertificateParams::new(vec!["localhost".into()])
+            .unwrap()
+            .signed_by(&server_key, &ca, &ca_key)
+            .unwrap();
+
+        let write = |name: &str, contents: String| {
+            let path = dir.path().join(name);
+            fs::write(&path, contents).unwrap();
+            path.to_string_lossy().into_owned()
+        };
+        let config = TlsConfig {
+            enabled: true,
+            certificate_path: write("server.pem", server.pem()),
+            private_key_path: write("server.key", server_key.serialize_pem()),
+            client_ca_path: Some(write("ca.pem", ca.pem())),
+            require_client_cert: true,
+            ..TlsConfig::default()
+        };
+        Pki {
+            dir,
+            config,
+            ca,
+            ca_key,
+        }
+    }
+
+    fn client_config(pki: &Pki, with_cert: bool) -> Arc<ClientConfig> {
+        let mut roots = RootCertStore::empty();
+        roots.add(pki.ca.der().clone()).unwrap();
+        let builder = ClientConfig::builder_with_provider(Arc::new(ring::default_provider()))
+            .with_safe_default_protocol_versions()
+            .unwrap()
+            .with_root_certificates(roots);
+        let config = if with_cert {
+            let key = KeyPair::generate().unwrap();
+            let mut params = CertificateParams::new(Vec::<String>::new()).unwrap();
+            params
+                .distinguished_name
+                .push(DnType::CommonName, "desktop-shell");
+            params.extended_key_usages = vec![ExtendedKeyUsagePurpose::ClientAuth];
+            let cert = params.signed_by(&key, &pki.ca, &pki.ca_key).unwrap();
+            builder
+                .with_client_auth_cert(
+                    vec![cert.der().clone()],
+                    PrivateKeyDer::try_from(key.serialize_der()).unwrap(),
+                )
+                .unwrap()
+        } else {
+            builder.with_no_client_auth()
+        };
+        Arc::new(config)
+    }
+
+    /// Drive both ends of an in-memory handshake until it settles.
+    fn handshake(client: &mut ClientConnection, server: &mut ServerConnection) -> bool {
+        for _ in 0..20 {
+            let mut buffer = Vec::new();
+            client.write_tls(&mut buffer).unwrap();
+            if !buffer.is_empty() {
+                server.read_tls(&mut buffer.as_slice()).unwrap();
+                if server.process_new_packets().is_err() {
+                    return false;
+                }
+            }
+            let mut buffer = Vec::new();
+            server.write_tls(&mut buffer).unwrap();
+            if !buffer.is_empty() {
+                client.read_tls(&mut buffer.as_slice()).unwrap();
+                if client.process_new_packets().is_err() {
+                    return false;
+                }
+            }
+            if !client.is_handshaking() && !server.is_handshaking() {
+                return true;
+            }
+        }
+        false
+    }
+
+    #[test]
+    fn requires_and_identifies_client_certificates() {
+        let pki = pki();
+        let server_config = server_config(&pki.config).expect("server config");
+        let name = ServerName::try_from("localhost").unwrap();
+
+        let mut client = ClientConnection::new(client_config(&pki, true), name.clone()).unwrap();
+        let mut server = ServerConnection::new(server_config.clone()).unwrap();
+        assert!(handshake(&mut client, &mut server));
+        let peer = &server.peer_certificates().expect("client certificate")[0];
+        let identity = ClientIdentity::from_der(peer).unwrap();
+        assert_eq!(identity.common_name.as_deref(), Some("desktop-shell"));
+        assert_eq!(identity.fingerprint_sha256.len(), 64);
+
+        let mut anonymous = ClientConnection::new(client_config(&pki, false), name).unwrap();
+        let mut server = ServerConnection::new(server_config).unwrap();
+        assert!(!handshake(&mut anonymous, &mut server));
+        drop(pki.dir);
+    }
+
+    #[test]
+    fn listener_enforces_client_certificates() {
+        use crate::config::ServerConfig as ListenerConfig;
+        use crate::ipc::Listener;
+        use rustls::StreamOwned;
+        use std::io::{Read, Write};
+        use std::net::TcpStream;
+        use std::thread;
+
+        let pki = pki();
+        let listener = Listener::bind(&ListenerConfig {
+            port: 0,
+            tls: pki.config.clone(),
+            ..ListenerConfig::default()
+        })
+        .expect("TLS listener");
+        let address = listener.local_address().unwrap().to_string();
+        let address = address.trim_start_matches("tcp://").to_string();
+
+        let connect = |config: Arc<ClientConfig>| {
+            let address = address.clone();
+            thread::spawn(move || {
+                let name = ServerName::try_from("localhost").unwrap();
+                let connection = ClientConnection::new(config, name).unwrap();
+                let mut stream = StreamOwned::new(connection, TcpStream::connect(address).unwrap());
+                stream.write_all(b"ping")?;
+                let mut reply = [0u8; 4];
+                stream.read_exact(&mut reply).map(|_| reply)
+            })
+        };
+
+        let client = connect(client_config(&pki, true));
+        let mut connection = listener.accept().expect("verified client");
+        assert_eq!(
+            connection
+                .client_identity()
+                .and_then(|identity| identity.common_name.as_deref()),
+            Some("desktop-shell")
+        );
+        let mut request = [0u8; 4];
+        connection.read_exact(&mut request).unwrap();
+        assert_eq!(&request, b"ping");
+        connection.write_all(b"pong").unwrap();
+        assert_eq!(&client.join().unwrap().unwrap(), b"pong");
+
+        let anonymous = connect(client_config(&pki, false));
+        assert!(listener.accept().is_err());
+        assert!(anonymous.join().unwrap().is_err());
+        drop(pki.dir);
+    }
+}
@@ -1,17 +1,310 @@
+//! Trace entries collected for support bundles.
+//!
+//! Every entry carries when it happened, a severity, the event name, the
+//! message it concerns and free-form key-value fields. Entries recorded while
+//! a command is handled also carry its correlation id: [`crate::ipc`] enters
+//! a [`CorrelationScope`] for each command with the command's `requestId`, or
+//! a fresh id when the client sent none, and answers with that id as the
+//! reply's `requestId`. `GET /trace/bundle` serves
+//! [`TraceManager::query`] with the filters of [`TraceQuery`].
+//!
+//! Only the newest `tracing.bufferCapacity` entries are held in memory. When
+//! `tracing.traceBundlePath` is set, older entries spill to
+//! `current.jsonl` in that directory, which is rotated to a numbered
+//! `trace-NNNNNN.jsonl.zip` (or `.jsonl` when `tracing.compress` is off) by
+//! size or age. [`TraceManager::bundle`] reads the kept files back and appends
+//! the entries still in memory.
+
+use std::cell::RefCell;
+use std::collections::{BTreeMap, VecDeque};
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, BufRead, BufReader, Read, Write};
+use std::path::{Path, PathBuf};
+use std::sync::mpsc::{self, Receiver, Sender};
 use std::sync::{Arc, Mutex};
 
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use tracing::{info, warn};
+use zip::read::ZipArchive;
+use zip::write::FileOptions;
+
+use crate::clock::{SharedClock, SharedIds};
+use crate::config::TracingConfig;
 use crate::models::MessageId;
-use tracing::info;
 
-#[derive(Clone, Debug, PartialEq, Eq)]
+/// Longest client-supplied correlation id kept; longer ones are replaced.
+const MAX_CORRELATION_LEN: usize = 128;
+const DEFAULT_QUERY_LIMIT: usize = 1000;
+const ACTIVE_FILE: &str = "current.jsonl";
+/// Name of the JSONL file inside a compressed rotated file.
+const ARCHIVED_ENTRY: &str = "trace.jsonl";
+
+thread_local! {
+    static CORRELATION: RefCell<Option<String>> = const { RefCell::new(None) };
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum TraceSeverity {
+    Debug,
+    Info,
+    Warn,
+    Error,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
 pub struct TraceEntry {
+    pub timestamp: DateTime<Utc>,
+    pub severity: TraceSeverity,
     pub event: String,
     pub message: MessageId,
+    /// Further messages covered by the same event, e.g. a bulk operation.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub related: Vec<MessageId>,
+    /// Id of the command the entry was recorded for.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub correlation_id: Option<String>,
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub fields: BTreeMap<String, String>,
+}
+
+/// Filters accepted by `GET /trace/bundle`; unset fields match everything.
+#[derive(Clone, Debug, Default, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct TraceQuery {
+    /// Entries concerning this message, directly or as a related one.
+    pub message: Option<MessageId>,
+    pub event_prefix: Option<String>,
+    pub correlation_id: Option<String>,
+    /// Entries at least this severe.
+    pub severity: Option<TraceSeverity>,
+    pub since: Option<DateTime<Utc>>,
+    pub until: Option<DateTime<Utc>>,
+    pub limit: Option<usize>,
+}
+
+impl TraceQuery {
+    fn matches(&self, entry: &TraceEntry) -> bool {
+        self.message
+            .as_ref()
+            .is_none_or(|id| &entry.message == id || entry.related.contains(id))
+            && self
+                .event_prefix
+                .as_ref()
+                .is_none_or(|prefix| entry.event.starts_with(prefix.as_str()))
+            && self
+                .correlation_id
+                .as_ref()
+                .is_none_or(|id| entry.correlation_id.as_ref() == Some(id))
+            && self
+                .severity
+                .is_none_or(|severity| entry.severity >= severity)
+            && self.since.is_none_or(|since| entry.timestamp >= since)
+            && self.until.is_none_or(|until| entry.timestamp < until)
+    }
+}
+
+/// The correlation id of the command handled on this thread, until dropped.
+pub struct CorrelationScope {
+    id: String,
+    previous: Option<String>,
+}
+
+impl CorrelationScope {
+    /// Enter the scope of a command whose `requestId` is `requested`.
+    /// Missing, overlong or non-printable ids are replaced by a fresh one
+    /// from `ids`.
+    pub fn enter(requested: Option<&str>, ids: &SharedIds) -> Self {
+        let id = requested
+            .map(str::trim)
+            .filter(|id| {
+                !id.is_empty()
+                    && id.len() <= MAX_CORRELATION_LEN
+                    && id.bytes().all(|byte| byte.is_ascii_graphic())
+            })
+            .map_or_else(|| ids.uuid().to_string(), str::to_string);
+        let previous = CORRELATION.with(|current| current.replace(Some(id.clone())));
+        Self { id, previous }
+    }
+
+    /// Value for the reply's `requestId`.
+    pub fn id(&self) -> &str {
+        &self.id
+    }
+
+    /// Correlation id of the command handled on this thread, if any.
+    pub fn current() -> Option<String> {
+        CORRELATION.with(|current| current.borrow().clone())
+    }
+}
+
+impl Drop for CorrelationScope {
+    fn drop(&mut self) {
+        let previous = self.previous.take();
+        CORRELATION.with(|current| *current.borrow_mut() = previous);
+    }
+}
+
+/// Entries in memory, oldest first, and where evicted ones go.
+struct TraceBuffer {
+    entries: VecDeque<TraceEntry>,
+    capacity: usize,
+    spill: Option<TraceSpill>,
+}
+
+impl Default for TraceBuffer {
+    fn default() -> Self {
+        Self {
+            entries: VecDeque::new(),
+            capacity: TracingConfig::default().buffer_capacity,
+            spill: None,
+        }
+    }
+}
+
+/// The spill directory: the active JSONL file and the rotated ones.
+struct TraceSpill {
+    directory: PathBuf,
+    max_file_bytes: u64,
+    max_file_age: chrono::Duration,
+    max_files: usize,
+    compress: bool,
+    /// When the active file was started; after a restart, the timestamp of
+    /// its first entry.
+    active_since: Option<DateTime<Utc>>,
+}
+
+impl TraceSpill {
+    fn new(config: &TracingConfig) -> Self {
+        let directory = PathBuf::from(&config.trace_bundle_path);
+        let active_since = File::open(directory.join(ACTIVE_FILE))
+            .ok()
+            .and_then(|file| BufReader::new(file).lines().next()?.ok())
+            .and_then(|line| serde_json::from_str::<TraceEntry>(&line).ok())
+            .map(|entry| entry.timestamp);
+        Self {
+            directory,
+            max_file_bytes: config.max_file_bytes,
+            max_file_age: chrono::Duration::seconds(
+                i64::try_from(config.max_file_age_secs).unwrap_or(i64::MAX),
+            ),
+            max_files: config.max_files,
+            compress: config.compress,
+            active_since,
+        }
+    }
+
+    fn append(&mut self, entries: &[TraceEntry], now: DateTime<Utc>) -> io::Result<()> {
+        fs::create_dir_all(&self.directory)?;
+        let active = self.directory.join(ACTIVE_FILE);
+        let size = fs::metadata(&active).map_or(0, |metadata| metadata.len());
+        let expired = self
+            .active_since
+            .is_some_and(|since| now - since >= self.max_file_age);
+        if size > 0 && (size >= self.max_file_bytes || expired) {
+            self.rotate(&active)?;
+        }
+        let mut lines = Vec::new();
+        for entry in entries {
+            serde_json::to_writer(&mut lines, entry).map_err(io::Error::other)?;
+            lines.push(b'\n');
+        }
+        OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(&active)?
+            .write_all(&lines)?;
+        self.active_since.get_or_insert(now);
+        Ok(())
+    }
+
+    fn rotate(&mut self, active: &Path) -> io::Result<()> {
+        let sequence = self
+            .rotated()?
+            .last()
+            .map_or(1, |(sequence, _)| sequence + 1);
+        if self.compress {
+            let target = self
+                .directory
+                .join(format!("trace-{sequence:06}.jsonl.zip"));
+            let mut writer = zip::ZipWriter::new(File::create(target)?);
+            writer.start_file(
+                ARCHIVED_ENTRY,
+                FileOptions::default().compression_method(zip::CompressionMethod::Deflated),
+            )?;
+            io::copy(&mut File::open(active)?, &mut writer)?;
+            writer.finish()?;
+            fs::remove_file(active)?;
+        } else {
+            fs::rename(
+                active,
+                self.directory.join(format!("trace-{sequence:06}.jsonl")),
+            )?;
+        }
+        self.active_since = None;
+        let rotated = self.rotated()?;
+        for (_, path) in &rotated[..rotated.len().saturating_sub(self.max_files)] {
+            fs::remove_file(path)?;
+        }
+        Ok(())
+    }
+
+    /// Rotated files by sequence number, oldest first.
+    fn rotated(&self) -> io::Result<Vec<(u64, PathBuf)>> {
+        let entries = match fs::read_dir(&self.directory) {
+            Ok(entries) => entries,
+            Err(err) if err.kind() == io::ErrorKind::NotFound => return Ok(Vec::new()),
+            Err(err) => return Err(err),
+        };
+        let mut files: Vec<(u64, PathBuf)> = entries
+            .filter_map(|entry| entry.ok().map(|entry| entry.path()))
+            .filter_map(|path| {
+                let name = path.file_name()?.to_str()?;
+                let stem = name.strip_prefix("trace-")?;
+                let sequence = stem
+                    .strip_suffix(".jsonl.zip")
+                    .or_else(|| stem.strip_suffix(".jsonl"))?;
+                Some((sequence.parse().ok()?, path))
+            })
+            .collect();
+        files.sort();
+        Ok(files)
+    }
+
+    /// Every spilled entry, oldest first; unreadable lines are skipped.
+    fn read(&self) -> io::Result<Vec<TraceEntry>> {
+        let mut contents = Vec::new();
+        for (_, path) in self.rotated()? {
+            let mut text = String::new();
+            if path.extension().is_some_and(|extension| extension == "zip") {
+                ZipArchive::new(File::open(&path)?)?
+                    .by_name(ARCHIVED_ENTRY)?
+                    .read_to_string(&mut text)?;
+            } else {
+                File::open(&path)?.read_to_string(&mut text)?;
+            }
+            contents.push(text);
+        }
+        match fs::read_to_string(self.directory.join(ACTIVE_FILE)) {
+            Ok(text) => contents.push(text),
+            Err(err) if err.kind() == io::ErrorKind::NotFound => {}
+            Err(err) => return Err(err),
+        }
+        Ok(contents
+            .iter()
+            .flat_map(|text| text.lines())
+            .filter_map(|line| serde_json::from_str(line).ok())
+            .collect())
+    }
 }
 
 #[derive(Clone, Default)]
 pub struct TraceManager {
-    inner: Arc<Mutex<Vec<TraceEntry>>>,
+    inner: Arc<Mutex<TraceBuffer>>,
+    subscribers: Arc<Mutex<Vec<Sender<TraceEntry>>>>,
+    clock: SharedClock,
 }
 
 impl TraceManager {
@@ -19,19 +312,256 @@ impl TraceManager {
         Self::default()
     }
 
+    /// Bound the in-memory buffer and spill older entries as `config` says.
+    pub fn from_config(config: &TracingConfig) -> Self {
+        let buffer = TraceBuffer {
+            entries: VecDeque::new(),
+            capacity: config.buffer_capacity.max(1),
+            spill: (!config.trace_bundle_path.is_empty()).then(|| TraceSpill::new(config)),
+        };
+        Self {
+            inner: Arc::new(Mutex::new(buffer)),
+            ..Self::default()
+        }
+    }
+
+    pub fn with_clock(mut self, clock: SharedClock) -> Self {
+        self.clock = clock;
+        self
+    }
+
     pub fn record(&self, event: impl Into<String>, message: MessageId) {
-        let event = event.into();
-        let log_message = message.clone();
-        if let Ok(mut entries) = self.inner.lock() {
-            entries.push(TraceEntry { event, message });
+        self.push(self.entry(event.into(), message, Vec::new(), TraceSeverity::Info));
+    }
+
+    /// Record an entry with a severity and key-value fields.
+    pub fn record_with<K: Into<String>, V: ToString>(
+        &self,
+        event: impl Into<String>,
+        message: MessageId,
+        severity: TraceSeverity,
+        fields: impl IntoIterator<Item = (K, V)>,
+    ) {
+        let entry = self.entry(event.into(), message, Vec::new(), severity);
+        self.push(TraceEntry {
+            fields: fields
+                .into_iter()
+                .map(|(key, value)| (key.into(), value.to_string()))
+                .collect(),
+            ..entry
+        });
+    }
+
+    /// Record one entry for an event that touched several messages.
+    pub fn record_many(&self, event: impl Into<String>, mut messages: Vec<MessageId>) {
+        if messages.is_empty() {
+            return;
+        }
+        let message = messages.remove(0);
+        self.push(self.entry(event.into(), message, messages, TraceSeverity::Info));
+    }
+
+    fn entry(
+        &self,
+        event: String,
+        message: MessageId,
+        related: Vec<MessageId>,
+        severity: TraceSeverity,
+    ) -> TraceEntry {
+        TraceEntry {
+            timestamp: self.clock.now(),
+            severity,
+            event,
+            message,
+            related,
+            correlation_id: CorrelationScope::current(),
+            fields: BTreeMap::new(),
+        }
+    }
+
+    fn push(&self, entry: TraceEntry) {
+        let log_message = entry.message.clone();
+        let correlation = entry.correlation_id.clone().unwrap_or_default();
+        if let Ok(mut subscribers) = self.subscribers.lock() {
+            subscribers.retain(|subscriber| subscriber.send(entry.clone()).is_ok());
+        }
+        if let Ok(mut buffer) = self.inner.lock() {
+            buffer.entries.push_back(entry);
+            let overflow = buffer.entries.len().saturating_sub(buffer.capacity);
+            if overflow > 0 {
+                let evicted: Vec<TraceEntry> = buffer.entries.drain(..overflow).collect();
+                self.spill(&mut buffer, &evicted);
+            }
+        }
+        info!(target = "trace", message = %log_message, correlation, "trace event recorded");
+    }
+
+    /// Receive every entry recorded from now on; dropping the receiver unsubscribes.
+    pub fn subscribe(&self) -> Receiver<TraceEntry> {
+        let (sender, receiver) = mpsc::channel();
+        if let Ok(mut subscribers) = self.subscribers.lock() {
+            subscribers.push(sender);
+        }
+        receiver
+    }
+
+    /// Write evicted entries to the spill directory, or drop them without one.
+    fn spill(&self, buffer: &mut TraceBuffer, entries: &[TraceEntry]) {
+        let now = self.clock.now();
+        if let Some(spill) = buffer.spill.as_mut() {
+            if let Err(err) = spill.append(entries, now) {
+                warn!(target = "trace", "failed to spill trace entries: {err}");
+            }
+        }
+    }
+
+    /// Spill every entry held in memory, e.g. from the shutdown hook, so the
+    /// next start still has them.
+    pub fn flush(&self) {
+        if let Ok(mut buffer) = self.inner.lock() {
+            if buffer.spill.is_some() {
+                let entries: Vec<TraceEntry> = buffer.entries.drain(..).collect();
+                self.spill(&mut buffer, &entries);
+            }
         }
-        info!(target = "trace", message = %log_message, "trace event recorded");
     }
 
+    /// Spilled entries followed by those in memory, oldest first.
     pub fn bundle(&self) -> Vec<TraceEntry> {
-        self.inner
-            .lock()
-            .map(|entries| entries.clone())
-            .unwrap_or_default()
+        let Ok(buffer) = self.inner.lock() else {
+            return Vec::new();
+        };
+        let mut entries = match buffer.spill.as_ref().map(TraceSpill::read) {
+            Some(Ok(entries)) => entries,
+            Some(Err(err)) => {
+                warn!(
+                    target = "trace",
+                    "failed to read spilled trace entries: {err}"
+                );
+                Vec::new()
+            }
+            None => Vec::new(),
+        };
+        entries.extend(buffer.entries.iter().cloned());
+        entries
+    }
+
+    /// Matching entries, oldest first, keeping the most recent `limit`.
+    pub fn query(&self, query: &TraceQuery) -> Vec<TraceEntry> {
+        let mut matching: Vec<TraceEntry> = self
+            .bundle()
+            .into_iter()
+            .filter(|entry| query.matches(entry))
+            .collect();
+        let limit = query.limit.unwrap_or(DEFAULT_QUERY_LIMIT);
+        matching.drain(..matching.len().saturating_sub(limit));
+        matching
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::clock::{ManualClock, SequentialIds};
+
+    #[test]
+    fn correlates_and_filters_entries() {
+        let clock = ManualClock::new(DateTime::UNIX_EPOCH);
+        let trace = TraceManager::new().with_clock(SharedClock::new(clock.clone()));
+        let ids = SharedIds::new(SequentialIds::new());
+
+        trace.record("mock.accepted", MessageId("m-1".into()));
+        {
+            let scope = CorrelationScope::enter(Some(" req-7 "), &ids);
+            assert_eq!(scope.id(), "req-7");
+            clock.advance(chrono::Duration::seconds(5));
+            trace.record_with(
+                "dlp.blocked",
+                MessageId("m-2".into()),
+                TraceSeverity::Warn,
+                [("rule", "iban"), ("action", "block")],
+            );
+            let nested = CorrelationScope::enter(Some("bad id\n"), &ids);
+            assert_eq!(nested.id(), uuid::Uuid::from_u128(1).to_string());
+        }
+        assert_eq!(CorrelationScope::current(), None);
+        trace.record_many(
+            "bulk.delete",
+            vec![MessageId("m-3".into()), MessageId("m-2".into())],
+        );
+
+        let entries = trace.bundle();
+        assert_eq!(entries[0].correlation_id, None);
+        assert_eq!(entries[1].correlation_id.as_deref(), Some("req-7"));
+        assert_eq!(entries[1].fields["rule"], "iban");
+
+        let correlated = TraceQuery {
+            correlation_id: Some("req-7".into()),
+            ..TraceQuery::default()
+        };
+        assert_eq!(trace.query(&correlated)[0].event, "dlp.blocked");
+        let warnings = TraceQuery {
+            severity: Some(TraceSeverity::Warn),
+            ..TraceQuery::default()
+        };
+        assert_eq!(trace.query(&warnings).len(), 1);
+        let touching = TraceQuery {
+            message: Some(MessageId("m-2".into())),
+            since: Some(DateTime::UNIX_EPOCH + chrono::Duration::seconds(1)),
+            limit: Some(1),
+            ..TraceQuery::default()
+        };
+        assert_eq!(trace.query(&touching)[0].event, "bulk.delete");
+
+        let json = serde_json::to_value(&entries[1]).unwrap();
+        assert_eq!(json["correlationId"], "req-7");
+        assert_eq!(json["severity"], "warn");
+    }
+
+    #[test]
+    fn spills_rotates_and_merges_evicted_entries() {
+        let dir = tempfile::tempdir().unwrap();
+        let config = TracingConfig {
+            trace_bundle_path: dir.path().to_string_lossy().into_owned(),
+            buffer_capacity: 2,
+            max_file_bytes: 1024 * 1024,
+            max_file_age_secs: 60,
+            max_files: 2,
+            compress: true,
+        };
+        let clock = ManualClock::new(DateTime::UNIX_EPOCH);
+        let trace = TraceManager::from_config(&config).with_clock(SharedClock::new(clock.clone()));
+        let messages = |entries: Vec<TraceEntry>| -> Vec<String> {
+            entries.into_iter().map(|entry| entry.message.0).collect()
+        };
+
+        for n in 1..=4 {
+            trace.record("mock.accepted", MessageId(format!("m-{n}")));
+        }
+        assert!(dir.path().join(ACTIVE_FILE).exists());
+        clock.advance(chrono::Duration::seconds(120));
+        trace.record("mock.accepted", MessageId("m-5".into()));
+        assert!(dir.path().join("trace-000001.jsonl.zip").exists());
+        assert_eq!(
+            messages(trace.bundle()),
+            ["m-1", "m-2", "m-3", "m-4", "m-5"]
+        );
+
+        for n in 6..=7 {
+            clock.advance(chrono::Duration::seconds(120));
+            trace.record("mock.accepted", MessageId(format!("m-{n}")));
+        }
+        assert!(!dir.path().join("trace-000001.jsonl.zip").exists());
+        assert_eq!(
+            messages(trace.bundle()),
+            ["m-3", "m-4", "m-5", "m-6", "m-7"]
+        );
+
+        trace.flush();
+        let restarted = TraceManager::from_config(&config);
+        assert_eq!(
+            messages(restarted.bundle()),
+            ["m-3", "m-4", "m-5", "m-6", "m-7"]
+        );
     }
 }
@@ -0,0 +1,185 @@
+//! Single-file diagnostics export for support tickets.
+//!
+//! `GET /trace/export` serves [`TraceExport::export`]: one ZIP with the trace
+//! entries, the telemetry snapshot, the configuration with secret values
+//! masked, the tail of the service log and the last day of the access log.
+//! Every file passes through the telemetry redaction before it is added, so
+//! e-mail addresses never leave the machine.
+
+use std::io::{self, Cursor, Write};
+
+use chrono::Duration;
+use thiserror::Error;
+use zip::result::ZipError;
+use zip::write::FileOptions;
+
+use crate::access_log::{AccessLog, AccessLogQuery};
+use crate::clock::SharedClock;
+use crate::config::AppConfig;
+use crate::telemetry::{redact, TelemetryError, TelemetryManager};
+use crate::trace::TraceManager;
+
+/// How much of the end of the service log is included.
+const RECENT_LOG_BYTES: u64 = 2 * 1024 * 1024;
+/// Access-log entries included, counted back from the export.
+const ACCESS_LOG_WINDOW_HOURS: i64 = 24;
+const ACCESS_LOG_LIMIT: usize = 5000;
+
+#[derive(Debug, Error)]
+pub enum TraceExportError {
+    #[error("failed to read diagnostics: {0}")]
+    Io(#[from] io::Error),
+    #[error("failed to encode diagnostics: {0}")]
+    Serialize(#[from] serde_json::Error),
+    #[error("failed to build export archive: {0}")]
+    Archive(#[from] ZipError),
+    #[error(transparent)]
+    Telemetry(#[from] TelemetryError),
+}
+
+impl TraceExportError {
+    pub fn status(&self) -> u16 {
+        500
+    }
+}
+
+#[derive(Clone)]
+pub struct TraceExport {
+    trace: TraceManager,
+    telemetry: TelemetryManager,
+    access_log: AccessLog,
+    /// Rendered once at startup; secrets are masked before they get here.
+    config: String,
+    clock: SharedClock,
+}
+
+impl TraceExport {
+    pub fn new(
+        trace: TraceManager,
+        telemetry: TelemetryManager,
+        access_log: AccessLog,
+        config: &AppConfig,
+    ) -> Self {
+        Self {
+            trace,
+            telemetry,
+            access_log,
+            config: config.masked(),
+            clock: SharedClock::default(),
+        }
+    }
+
+    pub fn with_clock(mut self, clock: SharedClock) -> Self {
+        self.clock = clock;
+        self
+    }
+
+    /// Build the ZIP: `trace.jsonl`, `telemetry.json`, `config.txt`,
+    /// `logs/service.jsonl` and `logs/access.jsonl`.
+    pub fn export(&self) -> Result<Vec<u8>, TraceExportError> {
+        let mut trace = Vec::new();
+        for entry in self.trace.bundle() {
+            serde_json::to_writer(&mut trace, &entry)?;
+            trace.push(b'\n');
+        }
+
+        let mut access = Vec::new();
+        let since = self.clock.now() - Duration::hours(ACCESS_LOG_WINDOW_HOURS);
+        let entries = self.access_log.query(&AccessLogQuery {
+            since: Some(since),
+            limit: Some(ACCESS_LOG_LIMIT),
+            ..AccessLogQuery::default()
+        })?;
+        for entry in entries.iter().rev() {
+            serde_json::to_writer(&mut access, entry)?;
+            access.push(b'\n');
+        }
+
+        let files = [
+            ("trace.jsonl", trace),
+            (
+                "telemetry.json",
+                serde_json::to_vec_pretty(&self.telemetry.snapshot())?,
+            ),
+            ("config.txt", self.config.clone().into_bytes()),
+            (
+                "logs/service.jsonl",
+                self.telemetry.recent_log(RECENT_LOG_BYTES)?,
+            ),
+            ("logs/access.jsonl", access),
+        ];
+        let mut writer = zip::ZipWriter::new(Cursor::new(Vec::new()));
+        for (name, contents) in files {
+            let redacted = redact(String::from_utf8_lossy(&contents).into_owned());
+            writer.start_file(name, FileOptions::default())?;
+            writer.write_all(redacted.as_bytes())?;
+        }
+        Ok(writer.finish()?.into_inner())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs;
+    use std::io::Read;
+
+    use crate::config::{AccessLogConfig, TelemetryConfig};
+    use crate::models::MessageId;
+    use crate::trace::TraceSeverity;
+
+    #[test]
+    fn exports_redacted_diagnostics_with_masked_secrets() {
+        let dir = tempfile::tempdir().unwrap();
+        let mut config = AppConfig::default();
+        config.gateway.smtp.password = Some("hunter2".into());
+        config.telemetry = TelemetryConfig {
+            local_path: dir.path().join("telemetry").to_string_lossy().into_owned(),
+            ..TelemetryConfig::default()
+        };
+        config.access_log = AccessLogConfig {
+            path: dir.path().join("access").to_string_lossy().into_owned(),
+            ..AccessLogConfig::default()
+        };
+        fs::create_dir_all(dir.path().join("telemetry")).unwrap();
+        fs::write(
+            dir.path().join("telemetry/trace.jsonl"),
+            "{\"msg\":\"bind failed for ops@corp.example\"}\n",
+        )
+        .unwrap();
+
+        let trace = TraceManager::new();
+        trace.record_with(
+            "dlp.blocked",
+            MessageId("m-1".into()),
+            TraceSeverity::Warn,
+            [("recipient", "alice@corp.example")],
+        );
+        let export = TraceExport::new(
+            trace,
+            TelemetryManager::from_config(&config.telemetry),
+            AccessLog::new(config.access_log.clone()),
+            &config,
+        );
+
+        let mut archive = zip::ZipArchive::new(Cursor::new(export.export().unwrap())).unwrap();
+        let mut read = |name: &str| {
+            let mut contents = String::new();
+            archive
+                .by_name(name)
+                .unwrap()
+                .read_to_string(&mut contents)
+                .unwrap();
+            contents
+        };
+        let entries = read("trace.jsonl");
+        assert!(entries.contains("dlp.blocked"));
+        assert!(entries.contains("[REDACTED]") && !entries.contains("alice@"));
+        let config = read("config.txt");
+        assert!(config.contains("gateway.smtp.password=********"));
+        assert!(!config.contains("hunter2"));
+        assert!(read("logs/service.jsonl").contains("bind failed for [REDACTED]"));
+        assert!(read("telemetry.json").contains("\"metrics\""));
+        assert!(read("logs/access.jsonl").is_empty());
+    }
+}
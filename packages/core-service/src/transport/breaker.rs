@@ -0,0 +1,221 @@
+//! Per-target circuit breakers guarding outbound submissions.
+//!
+//! After `failure_threshold` consecutive failures a target is opened and the
+//! transport switch refuses submissions to it for the cool-down. The next
+//! call after that is let through as a half-open probe: success closes the
+//! breaker, failure re-opens it for another cool-down.
+
+use std::collections::BTreeMap;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+use serde::Serialize;
+use thiserror::Error;
+use tracing::{info, warn};
+
+use crate::config::TransportConfig;
+use crate::telemetry::TelemetryManager;
+
+#[derive(Debug, Error, PartialEq, Eq)]
+#[error("circuit open for {target}; retry in {retry_after:?}")]
+pub struct BreakerOpen {
+    pub target: String,
+    pub retry_after: Duration,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub enum BreakerState {
+    Closed,
+    Open,
+    HalfOpen,
+}
+
+/// Breaker state for one target as reported by `/status`.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct BreakerStatus {
+    pub target: String,
+    pub state: BreakerState,
+    pub consecutive_failures: u32,
+    pub opened_count: u64,
+}
+
+#[derive(Debug)]
+struct Breaker {
+    state: BreakerState,
+    consecutive_failures: u32,
+    opened_until: Option<Instant>,
+    probe_in_flight: bool,
+    opened_count: u64,
+}
+
+impl Default for Breaker {
+    fn default() -> Self {
+        Self {
+            state: BreakerState::Closed,
+            consecutive_failures: 0,
+            opened_until: None,
+            probe_in_flight: false,
+            opened_count: 0,
+        }
+    }
+}
+
+#[derive(Clone)]
+pub struct CircuitBreakers {
+    failure_threshold: u32,
+    cooldown: Duration,
+    breakers: Arc<Mutex<BTreeMap<String, Breaker>>>,
+    telemetry: Option<TelemetryManager>,
+}
+
+impl CircuitBreakers {
+    pub fn new(failure_threshold: u32, cooldown: Duration) -> Self {
+        Self {
+            failure_threshold: failure_threshold.max(1),
+            cooldown,
+            breakers: Arc::new(Mutex::new(BTreeMap::new())),
+            telemetry: None,
+        }
+    }
+
+    pub fn from_config(config: &TransportConfig) -> Self {
+        Self::new(
+            config.breaker_threshold,
+            Duration::from_millis(config.breaker_cooldown_ms),
+        )
+    }
+
+    pub fn with_telemetry(mut self, telemetry: TelemetryManager) -> Self {
+        self.telemetry = Some(telemetry);
+        self
+    }
+
+    /// Ask whether a submission to `target` may proceed.
+    pub fn allow(&self, target: &str) -> Result<(), BreakerOpen> {
+        let Ok(mut breakers) = self.breakers.lock() else {
+            return Ok(());
+        };
+        let breaker = breakers.entry(target.to_string()).or_default();
+        match breaker.state {
+            BreakerState::Closed => Ok(()),
+            BreakerState::Open => {
+                let now = Instant::now();
+                let until = breaker.opened_until.unwrap_or(now);
+                if now >= until {
+                    breaker.state = BreakerState::HalfOpen;
+                    breaker.probe_in_flight = true;
+                    info!(target = "transport.breaker", %target, "half-open probe");
+                    Ok(())
+                } else {
+                    Err(BreakerOpen {
+                        target: target.to_string(),
+                        retry_after: until - now,
+                    })
+                }
+            }
+            BreakerState::HalfOpen if breaker.probe_in_flight => Err(BreakerOpen {
+                target: target.to_string(),
+                retry_after: Duration::ZERO,
+            }),
+            BreakerState::HalfOpen => {
+                breaker.probe_in_flight = true;
+                Ok(())
+            }
+        }
+    }
+
+    pub fn record_success(&self, target: &str) {
+        if let Ok(mut breakers) = self.breakers.lock() {
+            let breaker = breakers.entry(target.to_string()).or_default();
+            if breaker.state != BreakerState::Closed {
+                info!(target = "transport.breaker", %target, "circuit closed");
+            }
+            breaker.state = BreakerState::Closed;
+            breaker.consecutive_failures = 0;
+            breaker.opened_until = None;
+            breaker.probe_in_flight = false;
+        }
+    }
+
+    pub fn record_failure(&self, target: &str) {
+        let opened = match self.breakers.lock() {
+            Ok(mut breakers) => {
+                let breaker = breakers.entry(target.to_string()).or_default();
+                breaker.consecutive_failures += 1;
+                breaker.probe_in_flight = false;
+                let trip = breaker.state == BreakerState::HalfOpen
+                    || (breaker.state == BreakerState::Closed
+                        && breaker.consecutive_failures >= self.failure_threshold);
+                if trip {
+                    breaker.state = BreakerState::Open;
+                    breaker.opened_until = Some(Instant::now() + self.cooldown);
+                    breaker.opened_count += 1;
+                }
+                trip.then_some(breaker.consecutive_failures)
+            }
+            Err(_) => None,
+        };
+        if let Some(failures) = opened {
+            warn!(
+                target = "transport.breaker",
+                %target,
+                failures,
+                "circuit opened"
+            );
+            if let Some(telemetry) = &self.telemetry {
+                telemetry.record_error(format!(
+                    "circuit opened for {target} after {failures} failures"
+                ));
+            }
+        }
+    }
+
+    pub fn status(&self) -> Vec<BreakerStatus> {
+        self.breakers
+            .lock()
+            .map(|breakers| {
+                breakers
+                    .iter()
+                    .map(|(target, breaker)| BreakerStatus {
+                        target: target.clone(),
+                        state: breaker.state,
+                        consecutive_failures: breaker.consecutive_failures,
+                        opened_count: breaker.opened_count,
+                    })
+                    .collect()
+            })
+            .unwrap_or_default()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::thread;
+
+    #[test]
+    fn opens_after_threshold_and_probes_after_cooldown() {
+        let breakers = CircuitBreakers::new(2, Duration::from_millis(20));
+        breakers.record_failure("mta");
+        assert!(breakers.allow("mta").is_ok());
+        breakers.record_failure("mta");
+        assert!(breakers.allow("mta").is_err());
+        assert!(breakers.allow("other").is_ok());
+
+        thread::sleep(Duration::from_millis(25));
+        assert!(breakers.allow("mta").is_ok());
+        // Only one probe at a time while half-open.
+        assert!(breakers.allow("mta").is_err());
+        breakers.record_failure("mta");
+        assert_eq!(breakers.status()[0].state, BreakerState::Open);
+        assert_eq!(breakers.status()[0].opened_count, 2);
+
+        thread::sleep(Duration::from_millis(25));
+        assert!(breakers.allow("mta").is_ok());
+        breakers.record_success("mta");
+        assert_eq!(breakers.status()[0].state, BreakerState::Closed);
+        assert!(breakers.allow("mta").is_ok());
+    }
+}
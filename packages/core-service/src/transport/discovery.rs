@@ -0,0 +1,527 @@
+//! Enumerates the P7 profiles installed by the vendor SDK.
+//!
+//! The SDK installer drops one `.p7p` file per profile into its profiles
+//! directory. The files are INI-style:
+//!
+//! ```text
+//! [Profile]
+//! Name=production
+//! ORAddress=C=DE;ADMD=ViaT;PRMD=Corp;O=Operations
+//!
+//! [MTA]
+//! Endpoint=mta1.corp.example:102
+//! Endpoint=mta2.corp.example:102
+//!
+//! [Security]
+//! AuthMode=strong
+//!
+//! [Credentials]
+//! SecretRef=keychain:x400/production
+//!
+//! [TLS]
+//! CaBundle=certs/production-ca.pem
+//! ServerName=mta.corp.example
+//! ```
+//!
+//! `SecretRef` names where the SDK's bind credentials are kept, as a
+//! `keychain:`, `env:` or `file:` reference (see [`crate::secrets`]); the
+//! secret itself never appears in a profile file. `[TLS]` overrides
+//! `transport.tls.*` for the profile's MTA connection; relative paths are
+//! taken from the profiles directory.
+//!
+//! A branch office profile can instead relay through another core-service
+//! instance; it needs no `[MTA]` section:
+//!
+//! ```text
+//! [Transport]
+//! Mode=relay
+//!
+//! [Relay]
+//! Url=https://hq.corp.example:3333
+//! ```
+//!
+//! Files are validated when they are read: an unknown setting value, a
+//! malformed OR address or endpoint, a missing TLS file, or a name another
+//! file already uses lands the file in [`DiscoveryReport::skipped`] with the
+//! reason. The service scans once at startup and logs what it found.
+//! Discovery backs `GET /profiles` and `GET /transport/profiles/discovered`,
+//! and [`P7Driver`](super::P7Driver) binds only names resolved here.
+
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+
+use serde::{Deserialize, Serialize};
+
+const PROFILE_EXTENSION: &str = "p7p";
+/// Schemes accepted in `SecretRef`.
+const SECRET_SCHEMES: [&str; 3] = ["keychain:", "env:", "file:"];
+
+/// How the SDK authenticates against the MTA for a profile.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum AuthMode {
+    None,
+    Simple,
+    Strong,
+}
+
+impl AuthMode {
+    pub fn parse(value: &str) -> Option<Self> {
+        match value.trim().to_ascii_lowercase().as_str() {
+            "none" | "anonymous" => Some(Self::None),
+            "simple" | "password" => Some(Self::Simple),
+            "strong" | "certificate" => Some(Self::Strong),
+            _ => None,
+        }
+    }
+}
+
+/// How messages bound to a profile leave this instance.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum TransportMode {
+    Mock,
+    /// Vendor P7 SDK talking to the MTA endpoints.
+    #[default]
+    Sdk,
+    /// Store-and-forward through another core-service's API.
+    Relay,
+}
+
+impl TransportMode {
+    pub fn parse(value: &str) -> Option<Self> {
+        match value.trim().to_ascii_lowercase().as_str() {
+            "mock" => Some(Self::Mock),
+            "sdk" | "p7" => Some(Self::Sdk),
+            "relay" => Some(Self::Relay),
+            _ => None,
+        }
+    }
+}
+
+/// Profile found on disk together with its MTA endpoints.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct DiscoveredProfile {
+    pub name: String,
+    pub path: PathBuf,
+    pub or_address: Option<String>,
+    pub endpoints: Vec<String>,
+    pub auth_mode: AuthMode,
+    #[serde(default)]
+    pub mode: TransportMode,
+    /// Base URL of the upstream core-service for relay profiles.
+    #[serde(default)]
+    pub relay_url: Option<String>,
+    /// Where the bind credentials are kept; never the secret itself.
+    #[serde(default)]
+    pub credentials_ref: Option<String>,
+    #[serde(default)]
+    pub tls: ProfileTls,
+}
+
+/// Per-profile overrides of `transport.tls.*`.
+#[derive(Clone, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ProfileTls {
+    pub ca_bundle: Option<PathBuf>,
+    pub client_certificate: Option<PathBuf>,
+    /// Name expected in the MTA's certificate, when it differs from the host.
+    pub server_name: Option<String>,
+}
+
+/// File that looked like a profile but could not be parsed.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct SkippedProfile {
+    pub path: PathBuf,
+    pub reason: String,
+}
+
+#[derive(Clone, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct DiscoveryReport {
+    pub profiles: Vec<DiscoveredProfile>,
+    pub skipped: Vec<SkippedProfile>,
+}
+
+/// Scans the configured profiles directory.
+#[derive(Clone, Debug)]
+pub struct ProfileDiscovery {
+    dir: PathBuf,
+}
+
+impl ProfileDiscovery {
+    pub fn new(dir: impl Into<PathBuf>) -> Self {
+        Self { dir: dir.into() }
+    }
+
+    pub fn dir(&self) -> &Path {
+        &self.dir
+    }
+
+    /// List every readable profile, sorted by name.
+    ///
+    /// A missing directory yields an empty report rather than an error because
+    /// machines without the SDK installed are expected in mock mode.
+    pub fn scan(&self) -> io::Result<DiscoveryReport> {
+        let mut report = DiscoveryReport::default();
+        let entries = match fs::read_dir(&self.dir) {
+            Ok(entries) => entries,
+            Err(err) if err.kind() == io::ErrorKind::NotFound => return Ok(report),
+            Err(err) => return Err(err),
+        };
+
+        for entry in entries {
+            let path = entry?.path();
+            let is_profile = path
+                .extension()
+                .map(|ext| ext.eq_ignore_ascii_case(PROFILE_EXTENSION))
+                .unwrap_or(false);
+            if !is_profile || !path.is_file() {
+                continue;
+            }
+            match fs::read_to_string(&path)
+                .map_err(|err| err.to_string())
+                .and_then(|contents| parse_profile(&path, &contents))
+            {
+                Ok(profile) => report.profiles.push(profile),
+                Err(reason) => report.skipped.push(SkippedProfile { path, reason }),
+            }
+        }
+
+        // The first file by path keeps a name claimed twice.
+        report.profiles.sort_by(|a, b| a.path.cmp(&b.path));
+        let mut profiles: Vec<DiscoveredProfile> = Vec::new();
+        for profile in report.profiles {
+            match profiles
+                .iter()
+                .find(|kept| kept.name.eq_ignore_ascii_case(&profile.name))
+            {
+                Some(kept) => report.skipped.push(SkippedProfile {
+                    reason: format!(
+                        "profile name {} is already used by {}",
+                        profile.name,
+                        kept.path.display()
+                    ),
+                    path: profile.path,
+                }),
+                None => profiles.push(profile),
+            }
+        }
+        report.profiles = profiles;
+        report.profiles.sort_by(|a, b| a.name.cmp(&b.name));
+        report.skipped.sort_by(|a, b| a.path.cmp(&b.path));
+        Ok(report)
+    }
+
+    /// Find a discovered profile by name (case-insensitive) for binding.
+    pub fn resolve(&self, name: &str) -> io::Result<Option<DiscoveredProfile>> {
+        Ok(self
+            .scan()?
+            .profiles
+            .into_iter()
+            .find(|profile| profile.name.eq_ignore_ascii_case(name)))
+    }
+}
+
+fn parse_profile(path: &Path, contents: &str) -> Result<DiscoveredProfile, String> {
+    let mut section = String::new();
+    let mut name = None;
+    let mut or_address = None;
+    let mut endpoints = Vec::new();
+    let mut auth_mode = AuthMode::None;
+    let mut mode = TransportMode::default();
+    let mut relay_url = None;
+    let mut credentials_ref = None;
+    let mut tls = ProfileTls::default();
+    let dir = path.parent().unwrap_or(Path::new(""));
+
+    for (index, line) in contents.lines().enumerate() {
+        let line = line.trim();
+        if line.is_empty() || line.starts_with(';') || line.starts_with('#') {
+            continue;
+        }
+        if let Some(header) = line.strip_prefix('[').and_then(|l| l.strip_suffix(']')) {
+            section = header.trim().to_ascii_lowercase();
+            continue;
+        }
+        let (key, value) = line
+            .split_once('=')
+            .ok_or_else(|| format!("line {}: expected key=value", index + 1))?;
+        let (key, value) = (key.trim().to_ascii_lowercase(), value.trim());
+        match (section.as_str(), key.as_str()) {
+            ("profile", "name") => name = Some(value.to_string()),
+            ("profile", "oraddress") => {
+                if !valid_or_address(value) {
+                    return Err(format!("line {}: invalid OR address {value}", index + 1));
+                }
+                or_address = Some(value.to_string());
+            }
+            ("mta", "endpoint") => {
+                let (host, port) = value
+                    .rsplit_once(':')
+                    .ok_or_else(|| format!("line {}: endpoint needs host:port", index + 1))?;
+                if host.is_empty() || port.parse::<u16>().is_err() {
+                    return Err(format!("line {}: invalid endpoint {value}", index + 1));
+                }
+                endpoints.push(value.to_string());
+            }
+            ("security", "authmode") => {
+                auth_mode = AuthMode::parse(value)
+                    .ok_or_else(|| format!("line {}: unknown auth mode {value}", index + 1))?;
+            }
+            ("transport", "mode") => {
+                mode = TransportMode::parse(value)
+                    .ok_or_else(|| format!("line {}: unknown transport mode {value}", index + 1))?;
+            }
+            ("relay", "url") => {
+                if !value.starts_with("http://") && !value.starts_with("https://") {
+                    return Err(format!("line {}: relay URL must be http(s)", index + 1));
+                }
+                relay_url = Some(value.trim_end_matches('/').to_string());
+            }
+            ("credentials", "secretref") => {
+                if !SECRET_SCHEMES
+                    .iter()
+                    .any(|scheme| value.len() > scheme.len() && value.starts_with(scheme))
+                {
+                    return Err(format!(
+                        "line {}: secret reference must be keychain:, env: or file:",
+                        index + 1
+                    ));
+                }
+                credentials_ref = Some(value.to_string());
+            }
+            ("tls", "cabundle") => {
+                tls.ca_bundle = Some(
+                    existing_file(dir, value)
+                        .map_err(|reason| format!("line {}: CA bundle {reason}", index + 1))?,
+                );
+            }
+            ("tls", "clientcertificate") => {
+                tls.client_certificate = Some(existing_file(dir, value).map_err(|reason| {
+                    format!("line {}: client certificate {reason}", index + 1)
+                })?);
+            }
+            ("tls", "servername") => tls.server_name = Some(value.to_string()),
+            _ => {}
+        }
+    }
+
+    let name = name
+        .or_else(|| {
+            path.file_stem()
+                .map(|stem| stem.to_string_lossy().into_owned())
+        })
+        .ok_or_else(|| "profile has no name".to_string())?;
+    match mode {
+        TransportMode::Relay if relay_url.is_none() => {
+            return Err("relay profile declares no relay URL".into());
+        }
+        TransportMode::Sdk if endpoints.is_empty() => {
+            return Err("profile declares no MTA endpoints".into());
+        }
+        _ => {}
+    }
+    Ok(DiscoveredProfile {
+        name,
+        path: path.to_path_buf(),
+        or_address,
+        endpoints,
+        auth_mode,
+        mode,
+        relay_url,
+        credentials_ref,
+        tls,
+    })
+}
+
+/// `key=value` attributes separated by `;`, country included.
+fn valid_or_address(value: &str) -> bool {
+    let mut country = false;
+    for part in value.split(';').filter(|part| !part.trim().is_empty()) {
+        let Some((key, value)) = part.split_once('=') else {
+            return false;
+        };
+        if key.trim().is_empty() || value.trim().is_empty() {
+            return false;
+        }
+        country |= key.trim().eq_ignore_ascii_case("C");
+    }
+    country
+}
+
+/// `value` taken from `dir` unless absolute, if it is a file.
+fn existing_file(dir: &Path, value: &str) -> Result<PathBuf, String> {
+    let path = dir.join(value);
+    if path.is_file() {
+        Ok(path)
+    } else {
+        Err(format!("{} does not exist", path.display()))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::transport::p7_driver::{ConnectionState, P7Driver, P7Error, P7Sdk, P7Session};
+    use std::sync::{Arc, Mutex};
+
+    /// Binds whatever it is given, remembering the profile files.
+    #[derive(Default)]
+    struct FileSdk {
+        bound: Mutex<Vec<DiscoveredProfile>>,
+    }
+
+    impl P7Sdk for FileSdk {
+        fn bind(&self, _: &str) -> Result<P7Session, P7Error> {
+            panic!("profile files are configured");
+        }
+
+        fn bind_profile(&self, profile: &DiscoveredProfile) -> Result<P7Session, P7Error> {
+            self.bound.lock().unwrap().push(profile.clone());
+            Ok(P7Session {
+                id: "s-1".into(),
+                profile: profile.name.clone(),
+                bound_at: chrono::DateTime::UNIX_EPOCH,
+            })
+        }
+
+        fn unbind(&self, _: &P7Session) -> Result<(), P7Error> {
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn discovers_profiles_and_reports_broken_files() {
+        let temp = tempfile::tempdir().unwrap();
+        fs::write(
+            temp.path().join("prod.p7p"),
+            "[Profile]\nName=production\nORAddress=C=DE;O=Ops\n\n[MTA]\nEndpoint=mta1.example:102\nEndpoint=mta2.example:102\n\n[Security]\nAuthMode=strong\n",
+        )
+        .unwrap();
+        fs::write(
+            temp.path().join("lab.P7P"),
+            "[MTA]\nEndpoint=lab.example:102\n",
+        )
+        .unwrap();
+        fs::write(temp.path().join("broken.p7p"), "[MTA]\nEndpoint=nohost\n").unwrap();
+        fs::write(
+            temp.path().join("branch.p7p"),
+            "[Transport]\nMode=relay\n\n[Relay]\nUrl=https://hq.example:3333/\n",
+        )
+        .unwrap();
+        fs::write(temp.path().join("orphan.p7p"), "[Transport]\nMode=relay\n").unwrap();
+        fs::write(temp.path().join("ca.pem"), "").unwrap();
+        fs::write(
+            temp.path().join("secure.p7p"),
+            "[MTA]\nEndpoint=mta.example:102\n\n[Credentials]\nSecretRef=env:P7_SECURE\n\n[TLS]\nCaBundle=ca.pem\nServerName=mta.internal\n",
+        )
+        .unwrap();
+        fs::write(
+            temp.path().join("unpinned.p7p"),
+            "[MTA]\nEndpoint=mta.example:102\n\n[TLS]\nCaBundle=missing.pem\n",
+        )
+        .unwrap();
+        fs::write(
+            temp.path().join("plaintext.p7p"),
+            "[MTA]\nEndpoint=mta.example:102\n\n[Credentials]\nSecretRef=hunter2\n",
+        )
+        .unwrap();
+        fs::write(
+            temp.path().join("twin.p7p"),
+            "[Profile]\nName=Production\nORAddress=C=DE;O=Twin\n\n[MTA]\nEndpoint=mta.example:102\n",
+        )
+        .unwrap();
+        fs::write(
+            temp.path().join("nameless.p7p"),
+            "[Profile]\nORAddress=O=Ops\n\n[MTA]\nEndpoint=mta.example:102\n",
+        )
+        .unwrap();
+        fs::write(temp.path().join("notes.txt"), "ignored").unwrap();
+
+        let discovery = ProfileDiscovery::new(temp.path());
+        let report = discovery.scan().unwrap();
+        let names: Vec<_> = report.profiles.iter().map(|p| p.name.as_str()).collect();
+        assert_eq!(names, vec!["branch", "lab", "production", "secure"]);
+        assert_eq!(report.profiles[0].mode, TransportMode::Relay);
+        assert_eq!(
+            report.profiles[0].relay_url.as_deref(),
+            Some("https://hq.example:3333")
+        );
+        assert_eq!(report.profiles[2].mode, TransportMode::Sdk);
+        assert_eq!(report.profiles[2].auth_mode, AuthMode::Strong);
+        assert_eq!(report.profiles[2].endpoints.len(), 2);
+        assert_eq!(
+            report.profiles[3].credentials_ref.as_deref(),
+            Some("env:P7_SECURE")
+        );
+        assert_eq!(
+            report.profiles[3].tls.ca_bundle,
+            Some(temp.path().join("ca.pem"))
+        );
+        assert_eq!(
+            report.profiles[3].tls.server_name.as_deref(),
+            Some("mta.internal")
+        );
+        let skipped: Vec<_> = report
+            .skipped
+            .iter()
+            .map(|skipped| skipped.path.file_stem().unwrap().to_str().unwrap())
+            .collect();
+        assert_eq!(
+            skipped,
+            vec![
+                "broken",
+                "nameless",
+                "orphan",
+                "plaintext",
+                "twin",
+                "unpinned"
+            ]
+        );
+        assert!(report.skipped[4].reason.contains("prod.p7p"));
+
+        let resolved = discovery.resolve("PRODUCTION").unwrap().unwrap();
+        assert_eq!(resolved.or_address.as_deref(), Some("C=DE;O=Ops"));
+        assert!(ProfileDiscovery::new(temp.path().join("missing"))
+            .scan()
+            .unwrap()
+            .profiles
+            .is_empty());
+    }
+
+    #[test]
+    fn binds_only_profiles_defined_by_files() {
+        let temp = tempfile::tempdir().unwrap();
+        fs::write(
+            temp.path().join("ops.p7p"),
+            "[Profile]\nName=Ops\n\n[MTA]\nEndpoint=mta.example:102\n",
+        )
+        .unwrap();
+        let sdk = Arc::new(FileSdk::default());
+        let driver = P7Driver::new(sdk.clone(), "ops")
+            .with_profiles(["legal"])
+            .with_profile_files(ProfileDiscovery::new(temp.path()));
+
+        assert_eq!(driver.tick(), ConnectionState::Bound);
+        let status = driver.status();
+        assert_eq!(
+            status
+                .profile("ops")
+                .unwrap()
+                .session
+                .as_ref()
+                .unwrap()
+                .profile,
+            "ops"
+        );
+        let legal = status.profile("legal").unwrap();
+        assert_eq!(legal.state, ConnectionState::Rebinding);
+        assert_eq!(
+            legal.last_error.as_deref(),
+            Some("no valid profile file defines legal")
+        );
+        assert_eq!(sdk.bound.lock().unwrap()[0].endpoints, ["mta.example:102"]);
+    }
+}
@@ -0,0 +1,86 @@
+//! The transport handlers and workers submit through.
+//!
+//! `transport.mode` picks the implementation stored in
+//! [`AppState::transport`](crate::AppState): `mock` simulates delivery with
+//! [`MockDeliveryProvider`](crate::mock_provider::MockDeliveryProvider),
+//! `sdk` goes through the vendor SDK with [`P7Driver`](super::P7Driver), and
+//! `gateway` relays over SMTP with
+//! [`GatewayAdapter`](crate::gateway::GatewayAdapter). Submissions are
+//! screened with [`SubmitPolicy`](crate::submit::SubmitPolicy) before
+//! they reach any of them.
+
+use std::time::Duration;
+
+use chrono::{DateTime, Utc};
+use thiserror::Error;
+
+use crate::models::{Message, MessageId, Report};
+
+#[derive(Debug, Error, PartialEq, Eq)]
+pub enum TransportError {
+    #[error("the {transport} transport does not support {operation}")]
+    Unsupported {
+        transport: &'static str,
+        operation: &'static str,
+    },
+    /// The peer cannot be reached right now; the operation may be retried.
+    #[error("transport unavailable: {0}")]
+    Unavailable(String),
+    /// The peer did not answer in time; the outcome is unknown.
+    #[error("transport did not answer within {0:?}")]
+    TimedOut(Duration),
+    #[error("transport rejected the request: {0}")]
+    Rejected(String),
+    /// The mailbox quota leaves no room for the message.
+    #[error("{0}")]
+    QuotaExceeded(String),
+}
+
+impl TransportError {
+    pub fn status(&self) -> u16 {
+        match self {
+            Self::Unsupported { .. } => 501,
+            Self::Unavailable(_) => 503,
+            Self::TimedOut(_) => 504,
+            Self::Rejected(_) => 422,
+            Self::QuotaExceeded(_) => 507,
+        }
+    }
+}
+
+pub trait MessageTransport: Send + Sync {
+    /// `transport.mode` value selecting this transport.
+    fn name(&self) -> &'static str;
+    /// Hand a screened message to the transport, returning its id.
+    fn submit(&self, message: Message) -> Result<MessageId, TransportError>;
+    fn fetch(&self, id: &MessageId) -> Result<Option<Message>, TransportError>;
+    /// Messages in `folder`, newest first.
+    fn list(&self, folder: &str) -> Result<Vec<Message>, TransportError>;
+    /// Whether the message existed.
+    fn delete(&self, id: &MessageId) -> Result<bool, TransportError>;
+    fn reports(&self, id: &MessageId) -> Result<Vec<Report>, TransportError>;
+    /// Hold a screened message back and send it at `until`, returning its id.
+    fn schedule(
+        &self,
+        _message: Message,
+        _until: DateTime<Utc>,
+    ) -> Result<MessageId, TransportError> {
+        Err(TransportError::Unsupported {
+            transport: self.name(),
+            operation: "scheduled delivery",
+        })
+    }
+    /// Whether [`schedule`](Self::schedule) is supported, and with it the
+    /// scheduled outbox the transport switch fills for batches.
+    fn can_schedule(&self) -> bool {
+        false
+    }
+    /// Send a message that is already stored and queued, e.g. by a batch or
+    /// the shutdown drain, without storing or queueing it again. Transports
+    /// that keep no queue of their own just [`submit`](Self::submit) it.
+    fn dispatch_queued(&self, message: Message) -> Result<MessageId, TransportError> {
+        self.submit(message)
+    }
+    /// Called by the transport switch with the mode it now routes to.
+    fn activated(&self, _mode: &'static str) {}
+}
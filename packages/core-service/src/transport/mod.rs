@@ -0,0 +1,30 @@
+pub mod breaker;
+pub mod discovery;
+pub mod message_transport;
+pub mod p7_driver;
+pub mod pinset;
+pub mod relay;
+pub mod sdk_events;
+pub mod sdk_executor;
+pub mod sdk_info;
+pub mod sdk_metrics;
+pub mod stapling;
+pub mod switch;
+
+pub use breaker::{BreakerOpen, BreakerState, BreakerStatus, CircuitBreakers};
+pub use discovery::{
+    AuthMode, DiscoveredProfile, DiscoveryReport, ProfileDiscovery, ProfileTls, TransportMode,
+};
+pub use message_transport::{MessageTransport, TransportError};
+pub use p7_driver::{
+    ConnectionState, ConnectionTransition, P7Driver, P7DriverStatus, P7Error, P7ProfileStatus,
+    P7Sdk, P7Session, UnloadedSdk,
+};
+pub use pinset::{ObservedCa, PinConfirmation, PinError, PinsetMode, PinsetStatus, TlsPinset};
+pub use relay::{HttpRelay, RelayApi, RelayError, RelayTransport};
+pub use sdk_events::{EventSink, FfiSubscription, SdkEvent, SdkEventPump};
+pub use sdk_executor::SdkExecutor;
+pub use sdk_info::{SdkCapabilities, SdkOperation};
+pub use sdk_metrics::{ResultCode, SdkCallRecorder, SdkLatencySummary};
+pub use stapling::{StapleVerdict, StaplingStatus, StaplingVerifier};
+pub use switch::{TransportSwitch, TransportSwitchReport, TRANSPORT_MODES};
@@ -0,0 +1,1395 @@
+//! P7 session management for the vendor SDK.
+//!
+//! [`P7Driver`] keeps one session per pooled profile: `transport.profile`
+//! and any listed in `transport.profiles`, so departmental mailboxes can be
+//! served side by side. Operations go to `transport.profile` unless they are
+//! made through [`P7Driver::routed`].
+//!
+//! Every SDK operation runs through [`P7Driver::call`]. An operation failing
+//! with a connection error (vendor codes 2 and 4) or timing out marks that
+//! profile's session lost: the supervised `p7` worker unbinds it and binds
+//! again, immediately at first and then with an
+//! exponential back-off from `transport.rebindInitialMs` up to
+//! `transport.rebindMaxMs`. Calls made while no session is bound fail fast
+//! with [`P7Error::NotBound`] instead of reaching the SDK.
+//!
+//! SDK calls run on the blocking pool of [`SdkExecutor`], never on the
+//! caller's thread; async callers use [`P7Driver::call_async`] and the
+//! `*_async` operations. Binds are given `transport.sdk.connectTimeoutMs` and
+//! other calls `transport.sdk.operationTimeoutMs`, enforced on the Rust side,
+//! so a hung vendor library cannot hold handler threads.
+//!
+//! With [`P7Driver::with_profile_files`] a profile is bound only once it
+//! resolves to a valid file in `transport.profilesDir`, which is handed to
+//! the SDK with [`P7Sdk::bind_profile`]; until then its bind fails with
+//! [`P7Error::NoProfileFile`] and is retried with the usual back-off.
+//!
+//! Before the first bind the driver asks the SDK what it offers through
+//! `x400_sdk_info` (see [`sdk_info`](super::sdk_info)). Operations the SDK
+//! does not list fail with [`P7Error::Unsupported`] and submissions over its
+//! maximum message size with [`P7Error::TooLarge`], without reaching it.
+//!
+//! Bodies over `transport.sdk.chunkSizeBytes` are submitted in parts through
+//! `x400_sdk_submit_begin`, `_part` and `_commit`, each part its own call
+//! with its own timeout. When a part or the commit fails transiently, the
+//! driver keeps a checkpoint of the acknowledged offset, and the next submit
+//! of the same message, normally the queue's retry, resumes from there. SDKs
+//! without the chunked calls get the single-shot submit.
+//!
+//! A driver that is not the active transport is deactivated: its sessions are
+//! unbound and the worker leaves it alone until it is activated again.
+//!
+//! Each state change is logged, kept in the last transitions of
+//! [`P7DriverStatus`] for `/status`, and counted in telemetry when a session
+//! is lost.
+
+use std::collections::{BTreeMap, VecDeque};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex, MutexGuard, PoisonError};
+use std::thread;
+use std::time::Duration;
+
+use chrono::{DateTime, Utc};
+use serde::Serialize;
+use thiserror::Error;
+use tracing::{debug, info, warn};
+
+use crate::clock::SharedClock;
+use crate::config::TransportConfig;
+use crate::models::{Message, MessageId, Report};
+use crate::supervisor::Supervisor;
+use crate::telemetry::TelemetryManager;
+use crate::transport::discovery::{DiscoveredProfile, ProfileDiscovery};
+use crate::transport::message_transport::{MessageTransport, TransportError};
+use crate::transport::sdk_events::EventSink;
+use crate::transport::sdk_executor::SdkExecutor;
+use crate::transport::sdk_info::SdkCapabilities;
+use crate::transport::sdk_metrics::{ResultCode, SdkCallRecorder};
+
+/// Vendor result codes meaning the association with the MTA is gone.
+pub const CONNECTION_ERROR_CODES: [i32; 2] = [2, 4];
+
+/// Result code recorded for calls that timed out.
+pub const TIMED_OUT_CODE: i32 = -1;
+/// Result code recorded for calls refused because no session is bound.
+pub const NOT_BOUND_CODE: i32 = -2;
+/// Result code recorded for operations the SDK does not offer.
+pub const UNSUPPORTED_CODE: i32 = -3;
+/// Result code recorded for calls routed to a profile outside the pool.
+pub const UNKNOWN_PROFILE_CODE: i32 = -4;
+/// Result code recorded for calls that panicked inside the SDK binding.
+pub const PANICKED_CODE: i32 = -5;
+/// Result code recorded for submissions over the SDK's maximum size.
+pub const TOO_LARGE_CODE: i32 = -6;
+/// Result code recorded for chunk parts the SDK acknowledged nothing of.
+pub const STALLED_CODE: i32 = -7;
+
+/// Transitions kept for `/status`.
+const TRANSITIONS: usize = 32;
+/// How often the `p7` worker looks at the session.
+const POLL: Duration = Duration::from_millis(250);
+/// Interrupted chunked submissions kept for resuming.
+const CHECKPOINTS: usize = 64;
+
+#[derive(Clone, Debug, Error, PartialEq, Eq)]
+pub enum P7Error {
+    #[error("SDK returned {code}: {message}")]
+    Sdk { code: i32, message: String },
+    #[error("SDK call timed out after {0:?}")]
+    TimedOut(Duration),
+    #[error("no P7 session is bound")]
+    NotBound,
+    #[error("SDK does not support {0}")]
+    Unsupported(&'static str),
+    #[error("profile {0} is not in the session pool")]
+    UnknownProfile(String),
+    #[error("no valid profile file defines {0}")]
+    NoProfileFile(String),
+    #[error("message of {size} bytes exceeds the SDK maximum of {max} bytes")]
+    TooLarge { size: usize, max: u64 },
+}
+
+impl P7Error {
+    /// Whether the session must be re-bound before the next call.
+    pub fn is_connection_loss(&self) -> bool {
+        match self {
+            Self::Sdk { code, .. } => CONNECTION_ERROR_CODES.contains(code),
+            Self::TimedOut(_) => true,
+            Self::NotBound
+            | Self::Unsupported(_)
+            | Self::UnknownProfile(_)
+            | Self::NoProfileFile(_)
+            | Self::TooLarge { .. } => false,
+        }
+    }
+}
+
+impl ResultCode for P7Error {
+    fn result_code(&self) -> i32 {
+        match self {
+            Self::Sdk { code, .. } => *code,
+            Self::TimedOut(_) => TIMED_OUT_CODE,
+            Self::NotBound => NOT_BOUND_CODE,
+            Self::Unsupported(_) => UNSUPPORTED_CODE,
+            Self::UnknownProfile(_) | Self::NoProfileFile(_) => UNKNOWN_PROFILE_CODE,
+            Self::TooLarge { .. } => TOO_LARGE_CODE,
+        }
+    }
+}
+
+impl From<P7Error> for TransportError {
+    fn from(err: P7Error) -> Self {
+        match err {
+            P7Error::Unsupported(operation) => Self::Unsupported {
+                transport: "sdk",
+                operation,
+            },
+            P7Error::TimedOut(timeout) => Self::TimedOut(timeout),
+            P7Error::NotBound => Self::Unavailable(err.to_string()),
+            _ if err.is_connection_loss() => Self::Unavailable(err.to_string()),
+            _ => Self::Rejected(err.to_string()),
+        }
+    }
+}
+
+/// A session handed out by the SDK's bind.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct P7Session {
+    pub id: String,
+    pub profile: String,
+    pub bound_at: DateTime<Utc>,
+}
+
+/// The vendor SDK surface, so the driver can be exercised without the
+/// library. Message operations default to [`P7Error::Unsupported`].
+pub trait P7Sdk: Send + Sync {
+    /// Version, operations and maximum message size, from `x400_sdk_info`.
+    fn info(&self) -> Result<SdkCapabilities, P7Error> {
+        Err(P7Error::Unsupported("info"))
+    }
+
+    fn bind(&self, profile: &str) -> Result<P7Session, P7Error>;
+
+    /// Bind with the settings of the profile's file; libraries that find
+    /// profiles on their own just get its name.
+    fn bind_profile(&self, profile: &DiscoveredProfile) -> Result<P7Session, P7Error> {
+        self.bind(&profile.name)
+    }
+
+    fn unbind(&self, session: &P7Session) -> Result<(), P7Error>;
+
+    fn submit(&self, _session: &P7Session, _message: &Message) -> Result<MessageId, P7Error> {
+        Err(P7Error::Unsupported("submit"))
+    }
+
+    fn fetch(&self, _session: &P7Session, _id: &MessageId) -> Result<Option<Message>, P7Error> {
+        Err(P7Error::Unsupported("fetch"))
+    }
+
+    fn list(&self, _session: &P7Session, _folder: &str) -> Result<Vec<Message>, P7Error> {
+        Err(P7Error::Unsupported("list"))
+    }
+
+    fn delete(&self, _session: &P7Session, _id: &MessageId) -> Result<bool, P7Error> {
+        Err(P7Error::Unsupported("delete"))
+    }
+
+    fn reports(&self, _session: &P7Session, _id: &MessageId) -> Result<Vec<Report>, P7Error> {
+        Err(P7Error::Unsupported("reports"))
+    }
+
+    /// Start a chunked submission of `message`, whose body of `size` bytes
+    /// follows in parts; returns the upload handle.
+    fn submit_begin(
+        &self,
+        _session: &P7Session,
+        _message: &Message,
+        _size: u64,
+    ) -> Result<String, P7Error> {
+        Err(P7Error::Unsupported("submit_begin"))
+    }
+
+    /// Send the part of the body starting at `offset`; returns how many
+    /// bytes of the body the SDK holds now. Parts may be resent.
+    fn submit_part(
+        &self,
+        _session: &P7Session,
+        _upload: &str,
+        _offset: u64,
+        _chunk: &[u8],
+    ) -> Result<u64, P7Error> {
+        Err(P7Error::Unsupported("submit_part"))
+    }
+
+    /// Submit the fully sent body.
+    fn submit_commit(&self, _session: &P7Session, _upload: &str) -> Result<MessageId, P7Error> {
+        Err(P7Error::Unsupported("submit_commit"))
+    }
+
+    /// Deliver new-message and report notifications for `session` to `sink`
+    /// until it is unbound; see [`sdk_events`](super::sdk_events).
+    fn subscribe(&self, _session: &P7Session, _sink: EventSink) -> Result<(), P7Error> {
+        Err(P7Error::Unsupported("subscribe"))
+    }
+}
+
+/// Stand-in until a vendor library is loaded; every bind fails.
+pub struct UnloadedSdk;
+
+impl P7Sdk for UnloadedSdk {
+    fn bind(&self, _: &str) -> Result<P7Session, P7Error> {
+        Err(P7Error::Sdk {
+            code: CONNECTION_ERROR_CODES[0],
+            message: "no vendor SDK is loaded".into(),
+        })
+    }
+
+    fn unbind(&self, _: &P7Session) -> Result<(), P7Error> {
+        Ok(())
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub enum ConnectionState {
+    /// Never bound yet, or deactivated.
+    Unbound,
+    Binding,
+    Bound,
+    /// The session was lost or a bind failed; waiting for the next attempt.
+    Rebinding,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ConnectionTransition {
+    pub from: ConnectionState,
+    pub to: ConnectionState,
+    pub at: DateTime<Utc>,
+    pub reason: Option<String>,
+}
+
+/// State of one pooled profile's session.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct P7ProfileStatus {
+    pub profile: String,
+    pub state: ConnectionState,
+    pub session: Option<P7Session>,
+    /// Whether the SDK pushes notifications for the session.
+    pub subscribed: bool,
+    pub rebinds: u64,
+    pub consecutive_failures: u32,
+    pub last_error: Option<String>,
+    pub next_attempt_at: Option<DateTime<Utc>>,
+    /// Most recent last.
+    pub transitions: Vec<ConnectionTransition>,
+}
+
+/// Driver state as reported in the `transport` block of `/status`.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct P7DriverStatus {
+    pub active: bool,
+    pub default_profile: String,
+    /// SDK calls in the library right now, hung ones included.
+    pub in_flight_calls: usize,
+    /// What the SDK reported offering; `None` until it has been asked.
+    pub capabilities: Option<SdkCapabilities>,
+    /// Interrupted chunked submissions waiting to be resumed.
+    pub pending_uploads: usize,
+    /// The default profile first, then the others by name.
+    pub profiles: Vec<P7ProfileStatus>,
+}
+
+impl P7DriverStatus {
+    pub fn profile(&self, name: &str) -> Option<&P7ProfileStatus> {
+        self.profiles.iter().find(|status| status.profile == name)
+    }
+}
+
+/// Where an interrupted chunked submission stopped.
+struct Checkpoint {
+    message: MessageId,
+    profile: String,
+    upload: String,
+    size: usize,
+    /// Bytes the SDK acknowledged.
+    offset: usize,
+}
+
+struct Connection {
+    state: ConnectionState,
+    session: Option<P7Session>,
+    subscribed: bool,
+    /// Lost session still to be unbound before the next bind.
+    stale: Option<P7Session>,
+    rebinds: u64,
+    consecutive_failures: u32,
+    last_error: Option<String>,
+    next_attempt_at: Option<DateTime<Utc>>,
+    transitions: VecDeque<ConnectionTransition>,
+}
+
+impl Connection {
+    fn new() -> Self {
+        Self {
+            state: ConnectionState::Unbound,
+            session: None,
+            subscribed: false,
+            stale: None,
+            rebinds: 0,
+            consecutive_failures: 0,
+            last_error: None,
+            next_attempt_at: None,
+            transitions: VecDeque::new(),
+        }
+    }
+
+    fn status(&self, profile: &str) -> P7ProfileStatus {
+        P7ProfileStatus {
+            profile: profile.to_string(),
+            state: self.state,
+            session: self.session.clone(),
+            subscribed: self.subscribed,
+            rebinds: self.rebinds,
+            consecutive_failures: self.consecutive_failures,
+            last_error: self.last_error.clone(),
+            next_attempt_at: self.next_attempt_at,
+            transitions: self.transitions.iter().cloned().collect(),
+        }
+    }
+}
+
+#[derive(Clone)]
+pub struct P7Driver {
+    sdk: Arc<dyn P7Sdk>,
+    default_profile: String,
+    /// Profile operations of this handle go to; see [`P7Driver::routed`].
+    profile: String,
+    initial_backoff: Duration,
+    max_backoff: Duration,
+    connect_timeout: Duration,
+    operation_timeout: Duration,
+    executor: SdkExecutor,
+    /// Answer of `x400_sdk_info`, once asked.
+    capabilities: Arc<Mutex<Option<SdkCapabilities>>>,
+    chunk_size: usize,
+    /// Cleared once the SDK turns out to lack the chunked calls.
+    chunking: Arc<AtomicBool>,
+    /// Oldest first.
+    checkpoints: Arc<Mutex<VecDeque<Checkpoint>>>,
+    active: Arc<AtomicBool>,
+    /// One connection per pooled profile, shared by all routed handles.
+    sessions: Arc<Mutex<BTreeMap<String, Connection>>>,
+    sdk_calls: SdkCallRecorder,
+    /// Profile files binds are resolved against, when configured.
+    profile_files: Option<ProfileDiscovery>,
+    events: Option<EventSink>,
+    telemetry: Option<TelemetryManager>,
+    clock: SharedClock,
+}
+
+impl P7Driver {
+    pub fn new(sdk: Arc<dyn P7Sdk>, profile: impl Into<String>) -> Self {
+        let profile = profile.into();
+        Self {
+            sdk,
+            default_profile: profile.clone(),
+            profile: profile.clone(),
+            initial_backoff: Duration::from_secs(1),
+            max_backoff: Duration::from_secs(60),
+            connect_timeout: Duration::from_secs(10),
+            operation_timeout: Duration::from_secs(30),
+            executor: SdkExecutor::default(),
+            capabilities: Arc::new(Mutex::new(None)),
+            chunk_size: 1024 * 1024,
+            chunking: Arc::new(AtomicBool::new(true)),
+            checkpoints: Arc::new(Mutex::new(VecDeque::new())),
+            active: Arc::new(AtomicBool::new(true)),
+            sessions: Arc::new(Mutex::new(BTreeMap::from([(profile, Connection::new())]))),
+            sdk_calls: SdkCallRecorder::new(),
+            profile_files: None,
+            events: None,
+            telemetry: None,
+            clock: SharedClock::default(),
+        }
+    }
+
+    /// Bind `transport.profile` and `transport.profiles` with the configured
+    /// back-off and timeouts.
+    pub fn from_config(sdk: Arc<dyn P7Sdk>, config: &TransportConfig) -> Self {
+        Self::new(sdk, &config.default_profile)
+            .with_profiles(&config.profiles)
+            .with_backoff(
+                Duration::from_millis(config.rebind_initial_ms),
+                Duration::from_millis(config.rebind_max_ms),
+            )
+            .with_timeouts(
+                Duration::from_millis(config.sdk_connect_timeout_ms),
+                Duration::from_millis(config.sdk_operation_timeout_ms),
+            )
+            .with_executor(SdkExecutor::new(config.sdk_max_concurrent_calls))
+            .with_chunk_size(config.sdk_chunk_size_bytes)
+    }
+
+    /// Keep a session bound for each of `profiles` as well.
+    pub fn with_profiles<P: Into<String>>(self, profiles: impl IntoIterator<Item = P>) -> Self {
+        {
+            let mut sessions = self.sessions();
+            for profile in profiles {
+                sessions
+                    .entry(profile.into())
+                    .or_insert_with(Connection::new);
+            }
+        }
+        self
+    }
+
+    pub fn with_backoff(mut self, initial: Duration, max: Duration) -> Self {
+        self.initial_backoff = initial;
+        self.max_backoff = max.max(initial);
+        self
+    }
+
+    /// Longest waits for a bind and for any other SDK call.
+    pub fn with_timeouts(mut self, connect: Duration, operation: Duration) -> Self {
+        self.connect_timeout = connect;
+        self.operation_timeout = operation;
+        self
+    }
+
+    /// Run SDK calls on this executor, bounding how many run at once.
+    pub fn with_executor(mut self, executor: SdkExecutor) -> Self {
+        self.executor = executor;
+        self
+    }
+
+    /// Bind only profiles defined by a valid file in `profiles`.
+    pub fn with_profile_files(mut self, profiles: ProfileDiscovery) -> Self {
+        self.profile_files = Some(profiles);
+        self
+    }
+
+    /// Submit bodies larger than `bytes` in parts of `bytes`.
+    pub fn with_chunk_size(mut self, bytes: usize) -> Self {
+        self.chunk_size = bytes.max(1);
+        self
+    }
+
+    /// Trace and time calls through this recorder, usually the shared one.
+    pub fn with_sdk_calls(mut self, sdk_calls: SdkCallRecorder) -> Self {
+        self.sdk_calls = sdk_calls;
+        self
+    }
+
+    /// Subscribe each bound session to SDK notifications delivered to
+    /// `events`, where the SDK offers them.
+    pub fn with_events(mut self, events: EventSink) -> Self {
+        self.events = Some(events);
+        self
+    }
+
+    pub fn with_telemetry(mut self, telemetry: TelemetryManager) -> Self {
+        self.telemetry = Some(telemetry);
+        self
+    }
+
+    pub fn with_clock(mut self, clock: SharedClock) -> Self {
+        self.clock = clock;
+        self
+    }
+
+    /// A handle sharing this driver's pool whose operations go to
+    /// `profile`'s session; handlers use it for a request naming a profile.
+    pub fn routed(&self, profile: &str) -> Result<Self, P7Error> {
+        if !self.sessions().contains_key(profile) {
+            return Err(P7Error::UnknownProfile(profile.to_string()));
+        }
+        Ok(Self {
+            profile: profile.to_string(),
+            ..self.clone()
+        })
+    }
+
+    /// Profile operations of this handle go to.
+    pub fn profile(&self) -> &str {
+        &self.profile
+    }
+
+    /// Run one SDK operation on the profile's bound session, giving up after
+    /// the operation timeout. A connection error or timeout hands the session
+    /// to the `p7` worker for re-binding.
+    pub fn call<T: Send + 'static>(
+        &self,
+        operation: &str,
+        payload_bytes: usize,
+        call: impl FnOnce(&dyn P7Sdk, &P7Session) -> Result<T, P7Error> + Send + 'static,
+    ) -> Result<T, P7Error> {
+        self.check(operation, payload_bytes)?;
+        let session = self.session()?;
+        let result = self
+            .sdk_calls
+            .call(operation, &self.profile, payload_bytes, || {
+                let sdk = self.sdk.clone();
+                let session = session.clone();
+                self.executor
+                    .run(self.operation_timeout, move || call(&*sdk, &session), drop)
+            });
+        self.settle(operation, &session, result)
+    }
+
+    /// [`call`](Self::call) for async callers: the runtime is not blocked
+    /// while the SDK works, and dropping the future cancels the call.
+    pub async fn call_async<T: Send + 'static>(
+        &self,
+        operation: &str,
+        payload_bytes: usize,
+        call: impl FnOnce(&dyn P7Sdk, &P7Session) -> Result<T, P7Error> + Send + 'static,
+    ) -> Result<T, P7Error> {
+        self.check(operation, payload_bytes)?;
+        let session = self.session()?;
+        let run = {
+            let sdk = self.sdk.clone();
+            let session = session.clone();
+            self.executor
+                .run_async(self.operation_timeout, move || call(&*sdk, &session), drop)
+        };
+        let result = self
+            .sdk_calls
+            .call_async(operation, &self.profile, payload_bytes, run)
+            .await;
+        self.settle(operation, &session, result)
+    }
+
+    pub async fn submit_async(&self, message: Message) -> Result<MessageId, TransportError> {
+        let size = message.content.body.len();
+        if size > self.chunk_size && self.chunking.load(Ordering::SeqCst) {
+            match self.submit_chunked(&message).await {
+                Err(P7Error::Unsupported("submit_begin")) => {
+                    self.chunking.store(false, Ordering::SeqCst);
+                    info!(target = "transport.p7", "SDK offers no chunked submission");
+                }
+                submitted => return Ok(submitted?),
+            }
+        }
+        Ok(self
+            .call_async("submit", size, move |sdk, session| {
+                sdk.submit(session, &message)
+            })
+            .await?)
+    }
+
+    /// Submit the body in parts, resuming from the message's checkpoint if a
+    /// previous attempt on this profile was interrupted.
+    async fn submit_chunked(&self, message: &Message) -> Result<MessageId, P7Error> {
+        let size = message.content.body.len();
+        self.check("submit", size)?;
+        let resumed = {
+            let mut checkpoints = self.checkpoints();
+            checkpoints
+                .iter()
+                .position(|checkpoint| checkpoint.message == message.envelope.id)
+                .and_then(|at| checkpoints.remove(at))
+                .filter(|checkpoint| checkpoint.profile == self.profile && checkpoint.size == size)
+        };
+        let (upload, mut offset) = match resumed {
+            Some(checkpoint) => {
+                info!(
+                    target = "transport.p7",
+                    profile = %self.profile,
+                    message = %message.envelope.id.0,
+                    offset = checkpoint.offset,
+                    size,
+                    "resuming chunked submission"
+                );
+                (checkpoint.upload, checkpoint.offset)
+            }
+            None => {
+                let begun = message.clone();
+                let upload = self
+                    .call_async("submit_begin", 0, move |sdk, session| {
+                        sdk.submit_begin(session, &begun, size as u64)
+                    })
+                    .await?;
+                (upload, 0)
+            }
+        };
+        let body = message.content.body.as_bytes();
+        while offset < size {
+            let end = size.min(offset + self.chunk_size);
+            let chunk = body[offset..end].to_vec();
+            let handle = upload.clone();
+            let acknowledged = self
+                .call_async("submit_part", chunk.len(), move |sdk, session| {
+                    sdk.submit_part(session, &handle, offset as u64, &chunk)
+                })
+                .await
+                .and_then(|held| match usize::try_from(held) {
+                    Ok(held) if held > offset => Ok(held.min(size)),
+                    _ => Err(P7Error::Sdk {
+                        code: STALLED_CODE,
+                        message: format!("SDK acknowledged no data past byte {offset}"),
+                    }),
+                });
+            match acknowledged {
+                Ok(held) => offset = held,
+                Err(error) => return Err(self.interrupted(message, upload, offset, error)),
+            }
+        }
+        let handle = upload.clone();
+        self.call_async("submit_commit", 0, move |sdk, session| {
+            sdk.submit_commit(session, &handle)
+        })
+        .await
+        .map_err(|error| self.interrupted(message, upload, offset, error))
+    }
+
+    /// Keep a checkpoint when a chunked submission failed transiently.
+    fn interrupted(
+        &self,
+        message: &Message,
+        upload: String,
+        offset: usize,
+        error: P7Error,
+    ) -> P7Error {
+        if error.is_connection_loss() || error == P7Error::NotBound {
+            warn!(
+                target = "transport.p7",
+                profile = %self.profile,
+                message = %message.envelope.id.0,
+                offset,
+                %error,
+                "chunked submission interrupted"
+            );
+            let mut checkpoints = self.checkpoints();
+            if checkpoints.len() == CHECKPOINTS {
+                checkpoints.pop_front();
+            }
+            checkpoints.push_back(Checkpoint {
+                message: message.envelope.id.clone(),
+                profile: self.profile.clone(),
+                upload,
+                size: message.content.body.len(),
+                offset,
+            });
+        }
+        error
+    }
+
+    pub async fn fetch_async(&self, id: &MessageId) -> Result<Option<Message>, TransportError> {
+        let id = id.clone();
+        Ok(self
+            .call_async("fetch", 0, move |sdk, session| sdk.fetch(session, &id))
+            .await?)
+    }
+
+    /// What the SDK reported offering; every operation, with no size limit,
+    /// until it has been asked or when it cannot say.
+    pub fn capabilities(&self) -> SdkCapabilities {
+        self.capabilities
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner)
+            .clone()
+            .unwrap_or_default()
+    }
+
+    /// Refuse what the SDK said it cannot do before it is asked to.
+    fn check(&self, operation: &str, payload_bytes: usize) -> Result<(), P7Error> {
+        let capabilities = self.capabilities();
+        if let Some(operation) = SdkCapabilities::operation(operation) {
+            if !capabilities.supports(operation) {
+                return Err(P7Error::Unsupported(operation));
+            }
+        }
+        match capabilities.max_message_size {
+            Some(max) if payload_bytes as u64 > max => Err(P7Error::TooLarge {
+                size: payload_bytes,
+                max,
+            }),
+            _ => Ok(()),
+        }
+    }
+
+    /// Ask the SDK what it offers, once it has answered or said it cannot.
+    /// Failures are retried on the next tick.
+    fn discover(&self) {
+        if self
+            .capabilities
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner)
+            .is_some()
+        {
+            return;
+        }
+        let sdk = self.sdk.clone();
+        let capabilities = match self
+            .executor
+            .run(self.connect_timeout, move || sdk.info(), drop)
+        {
+            Ok(capabilities) => {
+                let offered: Vec<&str> = capabilities
+                    .operations
+                    .iter()
+                    .filter(|row| row.supported)
+                    .map(|row| row.operation)
+                    .collect();
+                info!(
+                    target = "transport.p7",
+                    version = capabilities.version.as_deref().unwrap_or_default(),
+                    operations = %offered.join(","),
+                    max_message_size = ?capabilities.max_message_size,
+                    "SDK capabilities"
+                );
+                capabilities
+            }
+            Err(P7Error::Unsupported(_)) => {
+                debug!(
+                    target = "transport.p7",
+                    "SDK does not describe its capabilities"
+                );
+                SdkCapabilities::default()
+            }
+            Err(error) => {
+                warn!(target = "transport.p7", %error, "SDK capability discovery failed");
+                return;
+            }
+        };
+        *self
+            .capabilities
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner) = Some(capabilities);
+    }
+
+    fn session(&self) -> Result<P7Session, P7Error> {
+        self.sessions()
+            .get(&self.profile)
+            .and_then(|connection| connection.session.clone())
+            .ok_or(P7Error::NotBound)
+    }
+
+    /// Account for a finished call: count timeouts and hand a lost session
+    /// to the worker.
+    fn settle<T>(
+        &self,
+        operation: &str,
+        session: &P7Session,
+        result: Result<T, P7Error>,
+    ) -> Result<T, P7Error> {
+        if let Err(error) = &result {
+            if let P7Error::TimedOut(timeout) = error {
+                if let Some(telemetry) = &self.telemetry {
+                    telemetry.record_sdk_timeout(operation, &self.profile, *timeout);
+                }
+            }
+            if error.is_connection_loss() {
+                self.lost(session, error.to_string());
+            }
+        }
+        result
+    }
+
+    /// Bind every pooled profile that has no session and whose back-off has
+    /// passed; returns the state of this handle's profile afterwards. The
+    /// `p7` worker calls this on every poll.
+    pub fn tick(&self) -> ConnectionState {
+        if self.is_active() {
+            self.discover();
+        }
+        let profiles: Vec<String> = self.sessions().keys().cloned().collect();
+        let mut state = ConnectionState::Unbound;
+        for profile in profiles {
+            let after = self.tick_profile(&profile);
+            if profile == self.profile {
+                state = after;
+            }
+        }
+        state
+    }
+
+    fn tick_profile(&self, profile: &str) -> ConnectionState {
+        let now = self.clock.now();
+        let stale = {
+            let mut sessions = self.sessions();
+            let Some(connection) = sessions.get_mut(profile) else {
+                return ConnectionState::Unbound;
+            };
+            if !self.is_active() {
+                return connection.state;
+            }
+            let due = connection.next_attempt_at.is_none_or(|at| now >= at);
+            if matches!(
+                connection.state,
+                ConnectionState::Bound | ConnectionState::Binding
+            ) || !due
+            {
+                return connection.state;
+            }
+            self.transition(connection, ConnectionState::Binding, None);
+            connection.stale.take()
+        };
+        if let Some(stale) = stale {
+            self.unbind(stale);
+        }
+        let bound = self.resolve(profile).and_then(|file| {
+            let sdk = self.sdk.clone();
+            let late = self.sdk.clone();
+            let name = profile.to_string();
+            self.executor.run(
+                self.connect_timeout,
+                move || match file {
+                    Some(file) => sdk.bind_profile(&file),
+                    None => sdk.bind(&name),
+                },
+                // A bind that completes after we gave up is not kept.
+                move |session| {
+                    let _ = late.unbind(&session);
+                },
+            )
+        });
+        if let Err(P7Error::TimedOut(timeout)) = &bound {
+            if let Some(telemetry) = &self.telemetry {
+                telemetry.record_sdk_timeout("bind", profile, *timeout);
+            }
+        }
+        let mut sessions = self.sessions();
+        let connection = match sessions.get_mut(profile) {
+            Some(connection) if connection.state == ConnectionState::Binding => connection,
+            _ => {
+                // Deactivated while binding.
+                drop(sessions);
+                if let Ok(session) = bound {
+                    self.unbind(session);
+                }
+                return ConnectionState::Unbound;
+            }
+        };
+        let mut subscribe = None;
+        match bound {
+            Ok(mut session) => {
+                // Pool entries are keyed by the configured spelling.
+                session.profile = profile.to_string();
+                info!(target = "transport.p7", profile, session = %session.id, "session bound");
+                subscribe = Some(session.clone());
+                if connection
+                    .transitions
+                    .iter()
+                    .any(|transition| transition.to == ConnectionState::Bound)
+                {
+                    connection.rebinds += 1;
+                }
+                connection.session = Some(session);
+                connection.consecutive_failures = 0;
+                connection.next_attempt_at = None;
+                self.transition(connection, ConnectionState::Bound, None);
+            }
+            Err(error) => {
+                connection.consecutive_failures += 1;
+                let delay = self.backoff(connection.consecutive_failures);
+                connection.next_attempt_at = Some(now + delay);
+                connection.last_error = Some(error.to_string());
+                warn!(
+                    target = "transport.p7",
+                    profile,
+                    %error,
+                    retry_in = ?delay,
+                    "bind failed"
+                );
+                self.transition(
+                    connection,
+                    ConnectionState::Rebinding,
+                    Some(error.to_string()),
+                );
+            }
+        }
+        let state = connection.state;
+        drop(sessions);
+        if let Some(session) = subscribe {
+            self.subscribe(session);
+        }
+        state
+    }
+
+    /// The file defining `profile`, when binds are resolved against files.
+    fn resolve(&self, profile: &str) -> Result<Option<DiscoveredProfile>, P7Error> {
+        let Some(files) = &self.profile_files else {
+            return Ok(None);
+        };
+        match files.resolve(profile) {
+            Ok(Some(file)) => Ok(Some(file)),
+            Ok(None) => Err(P7Error::NoProfileFile(profile.to_string())),
+            Err(error) => {
+                warn!(target = "transport.p7", profile, %error, dir = %files.dir().display(), "profiles directory unreadable");
+                Err(P7Error::NoProfileFile(profile.to_string()))
+            }
+        }
+    }
+
+    /// Ask the SDK to push notifications for a freshly bound session.
+    fn subscribe(&self, session: P7Session) {
+        let Some(events) = self.events.clone() else {
+            return;
+        };
+        if !self.capabilities().supports("subscribe") {
+            debug!(target = "transport.p7", profile = %session.profile, "SDK offers no event subscription");
+            return;
+        }
+        let sdk = self.sdk.clone();
+        let bound = session.clone();
+        let subscribed = self.executor.run(
+            self.operation_timeout,
+            move || sdk.subscribe(&bound, events),
+            drop,
+        );
+        match subscribed {
+            Ok(()) => {
+                if let Some(connection) = self.sessions().get_mut(&session.profile) {
+                    connection.subscribed = connection.session.as_ref() == Some(&session);
+                }
+                info!(target = "transport.p7", profile = %session.profile, session = %session.id, "subscribed to SDK events");
+            }
+            Err(P7Error::Unsupported(_)) => {
+                debug!(target = "transport.p7", profile = %session.profile, "SDK offers no event subscription");
+            }
+            Err(error) => {
+                warn!(target = "transport.p7", profile = %session.profile, %error, "subscription to SDK events failed");
+            }
+        }
+    }
+
+    /// Let the worker bind again; the next [`tick`](Self::tick) does.
+    pub fn activate(&self) {
+        if !self.active.swap(true, Ordering::SeqCst) {
+            info!(target = "transport.p7", profile = %self.default_profile, "driver activated");
+        }
+    }
+
+    /// Unbind every session and keep the worker from binding until
+    /// [`activate`](Self::activate).
+    pub fn deactivate(&self) {
+        self.active.store(false, Ordering::SeqCst);
+        let mut bound = Vec::new();
+        for connection in self.sessions().values_mut() {
+            if connection.state == ConnectionState::Unbound {
+                continue;
+            }
+            connection.next_attempt_at = None;
+            connection.consecutive_failures = 0;
+            self.transition(
+                connection,
+                ConnectionState::Unbound,
+                Some("deactivated".into()),
+            );
+            connection.subscribed = false;
+            bound.extend(connection.stale.take().or(connection.session.take()));
+        }
+        for session in bound {
+            self.unbind(session);
+        }
+        info!(target = "transport.p7", profile = %self.default_profile, "driver deactivated");
+    }
+
+    pub fn is_active(&self) -> bool {
+        self.active.load(Ordering::SeqCst)
+    }
+
+    pub fn status(&self) -> P7DriverStatus {
+        let sessions = self.sessions();
+        let default = sessions
+            .get(&self.default_profile)
+            .map(|connection| connection.status(&self.default_profile));
+        let others = sessions
+            .iter()
+            .filter(|(profile, _)| **profile != self.default_profile)
+            .map(|(profile, connection)| connection.status(profile));
+        P7DriverStatus {
+            active: self.is_active(),
+            default_profile: self.default_profile.clone(),
+            in_flight_calls: self.executor.in_flight(),
+            capabilities: self
+                .capabilities
+                .lock()
+                .unwrap_or_else(PoisonError::into_inner)
+                .clone(),
+            pending_uploads: self.checkpoints().len(),
+            profiles: default.into_iter().chain(others).collect(),
+        }
+    }
+
+    /// Keep the session bound as the supervised `p7` worker.
+    pub fn spawn(&self, supervisor: &Supervisor) {
+        let driver = self.clone();
+        supervisor.spawn("p7", move |context| {
+            while !context.should_stop() {
+                driver.tick();
+                context.heartbeat();
+                thread::sleep(POLL);
+            }
+        });
+    }
+
+    fn unbind(&self, session: P7Session) {
+        let sdk = self.sdk.clone();
+        let id = session.id.clone();
+        let unbound = self
+            .executor
+            .run(self.operation_timeout, move || sdk.unbind(&session), drop);
+        if let Err(error) = unbound {
+            warn!(target = "transport.p7", session = %id, %error, "unbind failed");
+        }
+    }
+
+    fn lost(&self, session: &P7Session, reason: String) {
+        let mut sessions = self.sessions();
+        let Some(connection) = sessions.get_mut(&self.profile) else {
+            return;
+        };
+        // Another call may already have reported this session.
+        if connection.session.as_ref() != Some(session) {
+            return;
+        }
+        connection.stale = connection.session.take();
+        connection.subscribed = false;
+        connection.next_attempt_at = None;
+        connection.last_error = Some(reason.clone());
+        warn!(target = "transport.p7", profile = %self.profile, session = %session.id, reason, "session lost");
+        if let Some(telemetry) = &self.telemetry {
+            telemetry.record_session_lost(&self.profile, &reason);
+        }
+        self.transition(connection, ConnectionState::Rebinding, Some(reason));
+    }
+
+    fn transition(&self, connection: &mut Connection, to: ConnectionState, reason: Option<String>) {
+        if connection.transitions.len() == TRANSITIONS {
+            connection.transitions.pop_front();
+        }
+        connection.transitions.push_back(ConnectionTransition {
+            from: connection.state,
+            to,
+            at: self.clock.now(),
+            reason,
+        });
+        connection.state = to;
+    }
+
+    fn backoff(&self, failures: u32) -> Duration {
+        let factor = 2u32.saturating_pow(failures.saturating_sub(1));
+        self.initial_backoff
+            .saturating_mul(factor)
+            .min(self.max_backoff)
+    }
+
+    fn sessions(&self) -> MutexGuard<'_, BTreeMap<String, Connection>> {
+        self.sessions.lock().unwrap_or_else(PoisonError::into_inner)
+    }
+
+    fn checkpoints(&self) -> MutexGuard<'_, VecDeque<Checkpoint>> {
+        self.checkpoints
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner)
+    }
+}
+
+/// Every operation runs on the routed profile's session through
+/// [`P7Driver::call`].
+impl MessageTransport for P7Driver {
+    fn name(&self) -> &'static str {
+        "sdk"
+    }
+
+    fn submit(&self, message: Message) -> Result<MessageId, TransportError> {
+        self.executor.block_on(self.submit_async(message))
+    }
+
+    fn fetch(&self, id: &MessageId) -> Result<Option<Message>, TransportError> {
+        let id = id.clone();
+        Ok(self.call("fetch", 0, move |sdk, session| sdk.fetch(session, &id))?)
+    }
+
+    fn list(&self, folder: &str) -> Result<Vec<Message>, TransportError> {
+        let folder = folder.to_string();
+        Ok(self.call("list", 0, move |sdk, session| sdk.list(session, &folder))?)
+    }
+
+    fn delete(&self, id: &MessageId) -> Result<bool, TransportError> {
+        let id = id.clone();
+        Ok(self.call("delete", 0, move |sdk, session| sdk.delete(session, &id))?)
+    }
+
+    fn reports(&self, id: &MessageId) -> Result<Vec<Report>, TransportError> {
+        let id = id.clone();
+        Ok(self.call("reports", 0, move |sdk, session| sdk.reports(session, &id))?)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::clock::{Clock, ManualClock};
+    use crate::models::{Address, MessageContent, MessageEnvelope};
+    use std::sync::atomic::{AtomicU32, Ordering};
+
+    /// Fails the binds listed in `failing` (1-based) and counts unbinds.
+    #[derive(Default)]
+    struct FlakySdk {
+        binds: AtomicU32,
+        unbinds: AtomicU32,
+        failing: Vec<u32>,
+    }
+
+    impl P7Sdk for FlakySdk {
+        fn bind(&self, profile: &str) -> Result<P7Session, P7Error> {
+            let attempt = self.binds.fetch_add(1, Ordering::SeqCst) + 1;
+            if self.failing.contains(&attempt) {
+                return Err(P7Error::Sdk {
+                    code: 2,
+                    message: "MTA unreachable".into(),
+                });
+            }
+            Ok(P7Session {
+                id: format!("s-{attempt}"),
+                profile: profile.to_string(),
+                bound_at: DateTime::UNIX_EPOCH,
+            })
+        }
+
+        fn unbind(&self, _: &P7Session) -> Result<(), P7Error> {
+            self.unbinds.fetch_add(1, Ordering::SeqCst);
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn rebinds_lost_sessions_with_backoff() {
+        let clock = ManualClock::new(DateTime::UNIX_EPOCH);
+        let sdk = Arc::new(FlakySdk {
+            failing: vec![2, 3],
+            ..FlakySdk::default()
+        });
+        let driver = P7Driver::new(sdk.clone(), "ops")
+            .with_backoff(Duration::from_secs(1), Duration::from_secs(60))
+            .with_timeouts(Duration::from_secs(5), Duration::from_millis(50))
+            .with_clock(SharedClock::new(clock.clone()));
+
+        assert_eq!(
+            driver.call("list", 0, |_, _| Ok(())),
+            Err(P7Error::NotBound)
+        );
+        assert_eq!(
+            MessageTransport::list(&driver, "inbox")
+                .unwrap_err()
+                .status(),
+            503
+        );
+        assert_eq!(driver.tick(), ConnectionState::Bound);
+        assert_eq!(
+            MessageTransport::list(&driver, "inbox"),
+            Err(TransportError::Unsupported {
+                transport: "sdk",
+                operation: "list"
+            })
+        );
+
+        let refused = driver.call::<()>("submit", 10, |_, _| {
+            Err(P7Error::Sdk {
+                code: 7,
+                message: "bad recipient".into(),
+            })
+        });
+        assert!(refused.is_err());
+        assert_eq!(driver.status().profiles[0].state, ConnectionState::Bound);
+
+        // A hung call is given up on after the operation timeout.
+        let timed_out = driver.call::<()>("fetch", 0, |_, _| {
+            thread::sleep(Duration::from_millis(500));
+            Ok(())
+        });
+        assert_eq!(timed_out, Err(P7Error::TimedOut(Duration::from_millis(50))));
+        assert_eq!(
+            driver.status().profiles[0].state,
+            ConnectionState::Rebinding
+        );
+
+        // Two failed binds: retried after 1s, then after 2s.
+        assert_eq!(driver.tick(), ConnectionState::Rebinding);
+        assert_eq!(sdk.unbinds.load(Ordering::SeqCst), 1);
+        clock.advance(chrono::Duration::milliseconds(500));
+        assert_eq!(driver.tick(), ConnectionState::Rebinding);
+        assert_eq!(sdk.binds.load(Ordering::SeqCst), 2);
+        clock.advance(chrono::Duration::milliseconds(500));
+        assert_eq!(driver.tick(), ConnectionState::Rebinding);
+        assert_eq!(
+            driver.status().profiles[0].next_attempt_at,
+            Some(clock.now() + Duration::from_secs(2))
+        );
+        clock.advance(chrono::Duration::seconds(2));
+        assert_eq!(driver.tick(), ConnectionState::Bound);
+
+        let status = driver.status().profiles.remove(0);
+        assert_eq!(status.session.unwrap().id, "s-4");
+        assert_eq!(status.rebinds, 1);
+        assert_eq!(status.consecutive_failures, 0);
+        assert_eq!(
+            status.transitions.last().unwrap().from,
+            ConnectionState::Binding
+        );
+        assert!(driver
+            .call("list", 0, |_, session| Ok(session.id.clone()))
+            .is_ok());
+
+        // Handlers await the same operations without blocking their runtime.
+        let id = MessageId("m-1".into());
+        let fetch = driver.fetch_async(&id);
+        fn assert_send<T: Send>(_: &T) {}
+        assert_send(&fetch);
+        let runtime = tokio::runtime::Builder::new_current_thread()
+            .build()
+            .unwrap();
+        assert_eq!(
+            runtime.block_on(fetch),
+            Err(TransportError::Unsupported {
+                transport: "sdk",
+                operation: "fetch"
+            })
+        );
+    }
+
+    /// Takes chunked submissions, losing the association on `failing_part`.
+    #[derive(Default)]
+    struct ChunkedSdk {
+        begins: AtomicU32,
+        parts: AtomicU32,
+        failing_part: u32,
+        received: Mutex<Vec<u8>>,
+    }
+
+    impl P7Sdk for ChunkedSdk {
+        fn bind(&self, profile: &str) -> Result<P7Session, P7Error> {
+            FlakySdk::default().bind(profile)
+        }
+
+        fn unbind(&self, _: &P7Session) -> Result<(), P7Error> {
+            Ok(())
+        }
+
+        fn submit_begin(&self, _: &P7Session, _: &Message, _: u64) -> Result<String, P7Error> {
+            self.begins.fetch_add(1, Ordering::SeqCst);
+            Ok("u-1".into())
+        }
+
+        fn submit_part(
+            &self,
+            _: &P7Session,
+            _: &str,
+            offset: u64,
+            chunk: &[u8],
+        ) -> Result<u64, P7Error> {
+            if self.parts.fetch_add(1, Ordering::SeqCst) + 1 == self.failing_part {
+                return Err(P7Error::Sdk {
+                    code: 2,
+                    message: "MTA unreachable".into(),
+                });
+            }
+            let mut received = self.received.lock().unwrap();
+            received.truncate(offset as usize);
+            received.extend_from_slice(chunk);
+            Ok(received.len() as u64)
+        }
+
+        fn submit_commit(&self, _: &P7Session, upload: &str) -> Result<MessageId, P7Error> {
+            Ok(MessageId(upload.to_string()))
+        }
+    }
+
+    #[test]
+    fn resumes_interrupted_chunked_submissions() {
+        let sdk = Arc::new(ChunkedSdk {
+            failing_part: 2,
+            ..ChunkedSdk::default()
+        });
+        let driver = P7Driver::new(sdk.clone(), "ops").with_chunk_size(4);
+        driver.tick();
+        let message = Message {
+            envelope: MessageEnvelope::new("Survey", Address::sample(), vec![Address::sample()]),
+            content: MessageContent {
+                body: "abcdefghij".into(),
+                attachments: Vec::new(),
+            },
+        };
+
+        let interrupted = MessageTransport::submit(&driver, message.clone()).unwrap_err();
+        assert_eq!(interrupted.status(), 503);
+        assert_eq!(driver.status().pending_uploads, 1);
+        driver.tick();
+        assert_eq!(
+            MessageTransport::submit(&driver, message.clone()),
+            Ok(MessageId("u-1".into()))
+        );
+        assert_eq!(sdk.begins.load(Ordering::SeqCst), 1);
+        assert_eq!(sdk.parts.load(Ordering::SeqCst), 4);
+        assert_eq!(*sdk.received.lock().unwrap(), b"abcdefghij");
+        assert_eq!(driver.status().pending_uploads, 0);
+
+        // Without the chunked calls the single-shot submit is used.
+        let plain = P7Driver::new(Arc::new(FlakySdk::default()), "ops").with_chunk_size(4);
+        plain.tick();
+        assert_eq!(
+            MessageTransport::submit(&plain, message),
+            Err(TransportError::Unsupported {
+                transport: "sdk",
+                operation: "submit"
+            })
+        );
+        assert!(!plain.chunking.load(Ordering::SeqCst));
+    }
+
+    #[test]
+    fn pools_one_session_per_profile() {
+        let sdk = Arc::new(FlakySdk {
+            failing: vec![2],
+            ..FlakySdk::default()
+        });
+        let driver = P7Driver::new(sdk.clone(), "ops").with_profiles(["finance", "legal"]);
+        assert!(matches!(
+            driver.routed("hr"),
+            Err(P7Error::UnknownProfile(profile)) if profile == "hr"
+        ));
+        let finance = driver.routed("finance").unwrap();
+
+        // Profiles bind in name order; the second bind, legal's, fails.
+        assert_eq!(driver.tick(), ConnectionState::Bound);
+        assert_eq!(finance.tick(), ConnectionState::Bound);
+        let legal = driver.routed("legal").unwrap();
+        assert_eq!(legal.status().profiles[2].state, ConnectionState::Rebinding);
+        assert_eq!(legal.call("list", 0, |_, _| Ok(())), Err(P7Error::NotBound));
+        assert_eq!(
+            finance.call("list", 0, |_, session| Ok(session.profile.clone())),
+            Ok("finance".to_string())
+        );
+
+        let lost = finance.call::<()>("fetch", 0, |_, _| {
+            Err(P7Error::Sdk {
+                code: 4,
+                message: "association aborted".into(),
+            })
+        });
+        assert!(lost.is_err());
+        let status = driver.status();
+        let names: Vec<&str> = status.profiles.iter().map(|p| p.profile.as_str()).collect();
+        assert_eq!(names, ["ops", "finance", "legal"]);
+        assert_eq!(
+            status.profile("finance").unwrap().state,
+            ConnectionState::Rebinding
+        );
+        assert_eq!(status.profile("ops").unwrap().state, ConnectionState::Bound);
+
+        driver.deactivate();
+        assert!(driver
+            .status()
+            .profiles
+            .iter()
+            .all(|profile| profile.state == ConnectionState::Unbound));
+        assert_eq!(sdk.unbinds.load(Ordering::SeqCst), 2);
+    }
+}
@@ -0,0 +1,406 @@
+//! CA pinning for MTA connections, with trust on first use.
+//!
+//! With `transport.tls.enforcePinset` set, every MTA handshake is handed to
+//! [`TlsPinset::check`] next to the stapling check, and the peer chain must
+//! contain a CA whose SHA-256 fingerprint is in the pinset: the fingerprints
+//! in `transport.fingerprints` plus those confirmed earlier, kept one per
+//! line in `tls-pinset` in the profiles directory.
+//!
+//! When enforcement is on but the pinset is empty, the pinset is in learning
+//! mode: handshakes are let through and the CA at the top of each presented
+//! chain is recorded. `POST /admin/tls/pin` with `{"fingerprint": "..."}`
+//! calls [`TlsPinset::confirm`], which writes the observed fingerprint to
+//! `tls-pinset` and enforces it from then on. The fingerprint may be left
+//! out when only one CA was observed. The mode, the pins and the observed
+//! CAs are reported in the `transport` block of `/status` as `pinset`.
+
+use std::collections::BTreeMap;
+use std::fs;
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex, MutexGuard, PoisonError};
+
+use chrono::{DateTime, Utc};
+use openssl::x509::X509;
+use serde::{Deserialize, Serialize};
+use thiserror::Error;
+use tracing::{info, warn};
+
+use crate::audit::AuditLog;
+use crate::clock::SharedClock;
+use crate::config::TransportConfig;
+use crate::models::AuditAction;
+use crate::revocation::{fingerprint, subject};
+
+/// File in the profiles directory confirmed pins are kept in.
+pub const PINSET_FILE: &str = "tls-pinset";
+
+#[derive(Debug, Error, PartialEq, Eq)]
+pub enum PinError {
+    #[error("{endpoint}: no certificate in the chain matches the pinset")]
+    Mismatch { endpoint: String },
+    #[error("{0}: peer presented no certificate")]
+    EmptyChain(String),
+    #[error("the pinset is not learning; enable transport.tls.enforcePinset with no fingerprints")]
+    NotLearning,
+    #[error("no CA has been observed yet")]
+    NothingObserved,
+    #[error("{0} CAs were observed; name the fingerprint to pin")]
+    Ambiguous(usize),
+    #[error("no observed CA has fingerprint {0}")]
+    NotObserved(String),
+    #[error("writing {path}: {reason}")]
+    Persist { path: String, reason: String },
+}
+
+impl PinError {
+    pub fn status(&self) -> u16 {
+        match self {
+            Self::Mismatch { .. } | Self::EmptyChain(_) => 502,
+            Self::NotLearning | Self::NothingObserved => 409,
+            Self::Ambiguous(_) => 422,
+            Self::NotObserved(_) => 404,
+            Self::Persist { .. } => 500,
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub enum PinsetMode {
+    /// `transport.tls.enforcePinset` is off.
+    Off,
+    /// Enforcement is on but nothing is pinned yet.
+    Learning,
+    Enforcing,
+}
+
+/// A CA seen at the top of a peer chain while learning.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ObservedCa {
+    pub fingerprint: String,
+    pub subject: String,
+    /// Endpoint it was first seen on.
+    pub endpoint: String,
+    pub first_seen: DateTime<Utc>,
+    pub last_seen: DateTime<Utc>,
+    pub handshakes: u64,
+}
+
+/// The `pinset` entry of the `transport` block of `/status`.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct PinsetStatus {
+    pub mode: PinsetMode,
+    pub pins: Vec<String>,
+    pub observed: Vec<ObservedCa>,
+    pub file: PathBuf,
+}
+
+/// Response of `POST /admin/tls/pin`.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct PinConfirmation {
+    pub fingerprint: String,
+    pub subject: String,
+    pub file: PathBuf,
+    pub pinned_at: DateTime<Utc>,
+}
+
+#[derive(Default)]
+struct State {
+    /// Confirmed through `POST /admin/tls/pin`, as read from the file.
+    learned: Vec<String>,
+    observed: BTreeMap<String, ObservedCa>,
+}
+
+#[derive(Clone)]
+pub struct TlsPinset {
+    enforce: bool,
+    configured: Arc<Vec<String>>,
+    file: PathBuf,
+    state: Arc<Mutex<State>>,
+    clock: SharedClock,
+    audit: Option<AuditLog>,
+}
+
+impl TlsPinset {
+    /// Pin `configured` and whatever `file` holds when `enforce` is set.
+    pub fn new(enforce: bool, configured: &[String], file: impl Into<PathBuf>) -> Self {
+        let file = file.into();
+        let learned = match fs::read_to_string(&file) {
+            Ok(contents) => contents
+                .lines()
+                .map(str::trim)
+                .filter(|line| !line.is_empty() && !line.starts_with('#'))
+                .map(normalize)
+                .collect(),
+            Err(_) => Vec::new(),
+        };
+        Self {
+            enforce,
+            configured: Arc::new(configured.iter().map(|pin| normalize(pin)).collect()),
+            file,
+            state: Arc::new(Mutex::new(State {
+                learned,
+                observed: BTreeMap::new(),
+            })),
+            clock: SharedClock::default(),
+            audit: None,
+        }
+    }
+
+    pub fn from_config(transport: &TransportConfig) -> Self {
+        Self::new(
+            transport.enforce_pinset,
+            &transport.fingerprints,
+            Path::new(&transport.profiles_dir).join(PINSET_FILE),
+        )
+    }
+
+    pub fn with_clock(mut self, clock: SharedClock) -> Self {
+        self.clock = clock;
+        self
+    }
+
+    /// Record confirmed pins as `tls.pin`.
+    pub fn with_audit(mut self, audit: AuditLog) -> Self {
+        self.audit = Some(audit);
+        self
+    }
+
+    pub fn mode(&self) -> PinsetMode {
+        if !self.enforce {
+            PinsetMode::Off
+        } else if self.pins().is_empty() {
+            PinsetMode::Learning
+        } else {
+            PinsetMode::Enforcing
+        }
+    }
+
+    /// Configured pins first, then confirmed ones.
+    pub fn pins(&self) -> Vec<String> {
+        let mut pins = self.configured.to_vec();
+        for pin in &self.state().learned {
+            if !pins.contains(pin) {
+                pins.push(pin.clone());
+            }
+        }
+        pins
+    }
+
+    /// Check the peer `chain`, leaf first, of a handshake with `endpoint`.
+    pub fn check(&self, endpoint: &str, chain: &[X509]) -> Result<(), PinError> {
+        let mode = self.mode();
+        if mode == PinsetMode::Off {
+            return Ok(());
+        }
+        let Some(top) = chain.last() else {
+            return Err(PinError::EmptyChain(endpoint.to_string()));
+        };
+        if mode == PinsetMode::Enforcing {
+            let pins = self.pins();
+            if chain
+                .iter()
+                .any(|certificate| pins.contains(&fingerprint(certificate)))
+            {
+                return Ok(());
+            }
+            warn!(
+                target = "transport.tls",
+                endpoint, "peer chain matches no pinned CA"
+            );
+            return Err(PinError::Mismatch {
+                endpoint: endpoint.to_string(),
+            });
+        }
+        let now = self.clock.now();
+        let key = fingerprint(top);
+        let mut state = self.state();
+        let observed = state.observed.entry(key.clone()).or_insert_with(|| {
+            warn!(
+                target = "transport.tls",
+                endpoint,
+                fingerprint = %key,
+                "pinset learning: CA observed, confirm with POST /admin/tls/pin"
+            );
+            ObservedCa {
+                fingerprint: key.clone(),
+                subject: subject(top),
+                endpoint: endpoint.to_string(),
+                first_seen: now,
+                last_seen: now,
+                handshakes: 0,
+            }
+        });
+        observed.last_seen = now;
+        observed.handshakes += 1;
+        Ok(())
+    }
+
+    /// Pin an observed CA for `actor`: `fingerprint`, or the only one observed.
+    pub fn confirm(
+        &self,
+        actor: &str,
+        fingerprint: Option<&str>,
+    ) -> Result<PinConfirmation, PinError> {
+        if self.mode() != PinsetMode::Learning {
+            return Err(PinError::NotLearning);
+        }
+        let mut state = self.state();
+        let chosen = match fingerprint.map(normalize) {
+            Some(wanted) => state
+                .observed
+                .get(&wanted)
+                .cloned()
+                .ok_or(PinError::NotObserved(wanted))?,
+            None => {
+                let count = state.observed.len();
+                match state.observed.values().next() {
+                    None => return Err(PinError::NothingObserved),
+                    Some(only) if count == 1 => only.clone(),
+                    Some(_) => return Err(PinError::Ambiguous(count)),
+                }
+            }
+        };
+        self.persist(&chosen)?;
+        state.learned.push(chosen.fingerprint.clone());
+        state.observed.clear();
+        info!(
+            target = "transport.tls",
+            fingerprint = %chosen.fingerprint,
+            subject = %chosen.subject,
+            file = %self.file.display(),
+            "CA pinned"
+        );
+        if let Some(audit) = &self.audit {
+            audit.record(
+                actor,
+                AuditAction::TlsPin,
+                &chosen.fingerprint,
+                None,
+                Some(chosen.subject.clone()),
+            );
+        }
+        Ok(PinConfirmation {
+            fingerprint: chosen.fingerprint,
+            subject: chosen.subject,
+            file: self.file.clone(),
+            pinned_at: self.clock.now(),
+        })
+    }
+
+    pub fn status(&self) -> PinsetStatus {
+        PinsetStatus {
+            mode: self.mode(),
+            pins: self.pins(),
+            observed: self.state().observed.values().cloned().collect(),
+            file: self.file.clone(),
+        }
+    }
+
+    fn persist(&self, pin: &ObservedCa) -> Result<(), PinError> {
+        let failed = |err: std::io::Error| PinError::Persist {
+            path: self.file.display().to_string(),
+            reason: err.to_string(),
+        };
+        if let Some(parent) = self
+            .file
+            .parent()
+            .filter(|parent| !parent.as_os_str().is_empty())
+        {
+            fs::create_dir_all(parent).map_err(failed)?;
+        }
+        let mut file = fs::OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(&self.file)
+            .map_err(failed)?;
+        writeln!(
+            file,
+            "# {} (first seen on {} at {})\n{}",
+            pin.subject,
+            pin.endpoint,
+            pin.first_seen.to_rfc3339(),
+            pin.fingerprint
+        )
+        .map_err(failed)
+    }
+
+    fn state(&self) -> MutexGuard<'_, State> {
+        self.state.lock().unwrap_or_else(PoisonError::into_inner)
+    }
+}
+
+/// Lower-case hex without the `:` separators some tools print.
+fn normalize(fingerprint: &str) -> String {
+    fingerprint
+        .trim()
+        .chars()
+        .filter(|c| *c != ':')
+        .collect::<String>()
+        .to_ascii_lowercase()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rcgen::{BasicConstraints, CertificateParams, DnType, IsCa, KeyPair};
+
+    fn chain(name: &str) -> Vec<X509> {
+        let ca_key = KeyPair::generate().unwrap();
+        let mut params = CertificateParams::new(Vec::<String>::new()).unwrap();
+        params.is_ca = IsCa::Ca(BasicConstraints::Unconstrained);
+        params.distinguished_name.push(DnType::CommonName, name);
+        let ca = params.self_signed(&ca_key).unwrap();
+        let mut params = CertificateParams::new(vec!["mta.example".into()]).unwrap();
+        params.distinguished_name.push(DnType::CommonName, "mta");
+        let leaf = params
+            .signed_by(&KeyPair::generate().unwrap(), &ca, &ca_key)
+            .unwrap();
+        [leaf.der(), ca.der()]
+            .into_iter()
+            .map(|der| X509::from_der(der).unwrap())
+            .collect()
+    }
+
+    #[test]
+    fn learns_confirms_and_then_enforces_a_ca() {
+        let dir = tempfile::tempdir().unwrap();
+        let file = dir.path().join("profiles").join(PINSET_FILE);
+        let (trusted, rogue) = (chain("MTA CA"), chain("Rogue CA"));
+        assert_eq!(
+            TlsPinset::new(false, &[], &file).check("mta:102", &rogue),
+            Ok(())
+        );
+
+        let pinset = TlsPinset::new(true, &[], &file);
+        assert_eq!(pinset.mode(), PinsetMode::Learning);
+        assert_eq!(pinset.confirm("ops", None), Err(PinError::NothingObserved));
+        pinset.check("mta-a:102", &trusted).unwrap();
+        pinset.check("mta-b:102", &trusted).unwrap();
+        pinset.check("mta-c:102", &rogue).unwrap();
+        let observed = pinset.status().observed;
+        assert_eq!(observed.len(), 2);
+        assert_eq!(pinset.confirm("ops", None), Err(PinError::Ambiguous(2)));
+
+        let wanted = fingerprint(&trusted[1]);
+        let spelled = wanted.to_ascii_uppercase();
+        let confirmed = pinset.confirm("ops", Some(&spelled)).unwrap();
+        assert_eq!(confirmed.fingerprint, wanted);
+        assert_eq!(pinset.mode(), PinsetMode::Enforcing);
+        assert_eq!(pinset.confirm("ops", None), Err(PinError::NotLearning));
+        assert!(pinset.check("mta-c:102", &rogue).is_err());
+
+        // The pin survives a restart.
+        let restarted = TlsPinset::new(true, &[], &file);
+        assert_eq!(restarted.pins(), vec![wanted]);
+        assert_eq!(restarted.check("mta-a:102", &trusted), Ok(()));
+        assert_eq!(
+            restarted.check("mta-c:102", &rogue).unwrap_err().status(),
+            502
+        );
+    }
+}
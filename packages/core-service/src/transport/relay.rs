@@ -0,0 +1,668 @@
+//! Store-and-forward relay to another core-service instance.
+//!
+//! Branch offices without their own MTA bind a profile with `Mode=relay`;
+//! [`RelayTransport::from_profile`] builds the transport from its `[Relay]
+//! URL` and `transport.mode = "relay"` (or a switch to `relay`) routes
+//! submissions through it. Submissions are written to a spool directory first
+//! and then posted to the upstream `POST /submit`; if headquarters is
+//! unreachable the spool entry is retried with exponential backoff, so
+//! nothing is lost across restarts. [`RelayTransport::reconcile`] polls the
+//! upstream reports for forwarded messages and feeds them through the local
+//! [`ReportIngestor`] under the local message id. The supervised `relay`
+//! worker runs both every [`POLL_INTERVAL`].
+
+use std::collections::HashMap;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::Duration;
+
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use tracing::warn;
+
+use crate::channel::ChannelAddress;
+use crate::clock::SharedClock;
+use crate::models::{Message, MessageId, MessagePriority, MessageStatus, Report, ReportKind};
+use crate::reports::ReportIngestor;
+use crate::secrets::SecretResolver;
+use crate::store::StoreManager;
+use crate::supervisor::Supervisor;
+use crate::transport::discovery::DiscoveredProfile;
+use crate::transport::message_transport::{MessageTransport, TransportError};
+
+const FORWARDED_FILE: &str = "forwarded.json";
+const MAX_BACKOFF: Duration = Duration::from_secs(3600);
+/// Longest wait for one upstream request.
+const REQUEST_TIMEOUT: Duration = Duration::from_secs(30);
+/// How often the worker retries the spool and polls upstream reports.
+pub const POLL_INTERVAL: Duration = Duration::from_secs(30);
+
+#[derive(Debug, thiserror::Error, PartialEq, Eq)]
+pub enum RelayError {
+    /// Network failure or 5xx; the submission stays spooled.
+    #[error("relay upstream unavailable: {0}")]
+    Unavailable(String),
+    /// The upstream refused the submission; retrying will not help.
+    #[error("relay upstream rejected the message ({status}): {message}")]
+    Rejected { status: u16, message: String },
+    #[error("relay spool error: {0}")]
+    Spool(String),
+}
+
+impl From<io::Error> for RelayError {
+    fn from(err: io::Error) -> Self {
+        Self::Spool(err.to_string())
+    }
+}
+
+/// Body posted to the upstream `/submit`.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct RelaySubmission {
+    /// Id on the branch instance, echoed upstream for tracing.
+    pub origin_id: MessageId,
+    pub subject: String,
+    pub body: String,
+    pub sender: ChannelAddress,
+    pub recipients: Vec<ChannelAddress>,
+    pub priority: MessagePriority,
+}
+
+impl From<&Message> for RelaySubmission {
+    fn from(message: &Message) -> Self {
+        Self {
+            origin_id: message.envelope.id.clone(),
+            subject: message.envelope.subject.clone(),
+            body: message.content.body.clone(),
+            sender: (&message.envelope.sender).into(),
+            recipients: message.envelope.recipients.iter().map(Into::into).collect(),
+            priority: message.envelope.priority,
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub enum RelayReportKind {
+    Delivery,
+    NonDelivery,
+    Read,
+}
+
+impl From<RelayReportKind> for ReportKind {
+    fn from(kind: RelayReportKind) -> Self {
+        match kind {
+            RelayReportKind::Delivery => Self::Delivery,
+            RelayReportKind::NonDelivery => Self::NonDelivery,
+            RelayReportKind::Read => Self::Read,
+        }
+    }
+}
+
+/// Report as listed by the upstream `GET /messages/:id/reports`.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct RelayReport {
+    pub kind: RelayReportKind,
+    pub recipient: Option<ChannelAddress>,
+    pub reason: Option<String>,
+    pub diagnostic: Option<String>,
+    pub timestamp: DateTime<Utc>,
+}
+
+/// Upstream API abstraction so the relay can be exercised without a network.
+pub trait RelayApi: Send + Sync {
+    /// Submit upstream and return the upstream message id.
+    fn submit(&self, submission: &RelaySubmission) -> Result<MessageId, RelayError>;
+    /// All reports the upstream holds for one of its messages, oldest first.
+    fn reports(&self, upstream: &MessageId) -> Result<Vec<RelayReport>, RelayError>;
+}
+
+/// Blocking client for the upstream REST API, backed by `ureq`.
+pub struct HttpRelay {
+    agent: ureq::Agent,
+    base_url: String,
+    api_key: Option<String>,
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct SubmitResponse {
+    message_id: MessageId,
+}
+
+impl HttpRelay {
+    pub fn new(base_url: &str, timeout: Duration) -> Self {
+        Self {
+            agent: ureq::AgentBuilder::new().timeout(timeout).build(),
+            base_url: base_url.trim_end_matches('/').to_string(),
+            api_key: None,
+        }
+    }
+
+    pub fn with_api_key(mut self, api_key: impl Into<String>) -> Self {
+        self.api_key = Some(api_key.into());
+        self
+    }
+
+    fn request(&self, method: &str, path: &str) -> ureq::Request {
+        let request = self
+            .agent
+            .request(method, &format!("{}{path}", self.base_url));
+        match &self.api_key {
+            Some(key) => request.set("X-API-Key", key),
+            None => request,
+        }
+    }
+}
+
+fn classify(err: ureq::Error) -> RelayError {
+    match err {
+        ureq::Error::Status(status, response) if status < 500 => RelayError::Rejected {
+            status,
+            message: response.into_string().unwrap_or_default(),
+        },
+        ureq::Error::Status(status, _) => RelayError::Unavailable(format!("HTTP {status}")),
+        ureq::Error::Transport(err) => RelayError::Unavailable(err.to_string()),
+    }
+}
+
+impl RelayApi for HttpRelay {
+    fn submit(&self, submission: &RelaySubmission) -> Result<MessageId, RelayError> {
+        let response = self
+            .request("POST", "/submit")
+            .send_json(submission)
+            .map_err(classify)?;
+        let body: SubmitResponse = response
+            .into_json()
+            .map_err(|err| RelayError::Unavailable(err.to_string()))?;
+        Ok(body.message_id)
+    }
+
+    fn reports(&self, upstream: &MessageId) -> Result<Vec<RelayReport>, RelayError> {
+        self.request("GET", &format!("/messages/{upstream}/reports"))
+            .call()
+            .map_err(classify)?
+            .into_json()
+            .map_err(|err| RelayError::Unavailable(err.to_string()))
+    }
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct SpoolEntry {
+    submission: RelaySubmission,
+    attempts: u32,
+    next_attempt_at: DateTime<Utc>,
+}
+
+/// Forwarded message whose reports are still being collected.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ForwardedMessage {
+    pub upstream_id: MessageId,
+    pub forwarded_at: DateTime<Utc>,
+    /// Number of upstream reports already ingested locally.
+    pub reports_seen: usize,
+}
+
+/// Result of one spool pass.
+#[derive(Clone, Debug, Default, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct RelayFlush {
+    pub forwarded: usize,
+    pub deferred: usize,
+    pub rejected: usize,
+}
+
+#[derive(Clone)]
+pub struct RelayTransport {
+    api: Arc<dyn RelayApi>,
+    store: StoreManager,
+    ingestor: ReportIngestor,
+    spool: PathBuf,
+    forwarded: Arc<Mutex<HashMap<MessageId, ForwardedMessage>>>,
+    backoff: Duration,
+    clock: SharedClock,
+}
+
+impl RelayTransport {
+    pub fn new(
+        api: Arc<dyn RelayApi>,
+        store: StoreManager,
+        ingestor: ReportIngestor,
+        spool: impl Into<PathBuf>,
+    ) -> Self {
+        let spool = spool.into();
+        let forwarded = load_forwarded(&spool.join(FORWARDED_FILE));
+        Self {
+            api,
+            store,
+            ingestor,
+            spool,
+            forwarded: Arc::new(Mutex::new(forwarded)),
+            backoff: Duration::from_secs(30),
+            clock: SharedClock::default(),
+        }
+    }
+
+    /// Relay to the upstream at `profile`'s relay URL, sending the secret its
+    /// `SecretRef` names as API key; `None` when the profile has no URL.
+    pub fn from_profile(
+        profile: &DiscoveredProfile,
+        store: StoreManager,
+        ingestor: ReportIngestor,
+        spool: impl Into<PathBuf>,
+    ) -> Option<Self> {
+        let mut api = HttpRelay::new(profile.relay_url.as_deref()?, REQUEST_TIMEOUT);
+        if let Some(reference) = &profile.credentials_ref {
+            match SecretResolver::default().resolve(reference) {
+                Ok(key) => api = api.with_api_key(key),
+                Err(err) => warn!(
+                    target = "relay",
+                    profile = %profile.name,
+                    "relay API key unavailable: {err}"
+                ),
+            }
+        }
+        Some(Self::new(Arc::new(api), store, ingestor, spool))
+    }
+
+    /// Delay before the first retry; doubled per failed attempt up to an hour.
+    pub fn with_backoff(mut self, backoff: Duration) -> Self {
+        self.backoff = backoff;
+        self
+    }
+
+    pub fn with_clock(mut self, clock: SharedClock) -> Self {
+        self.clock = clock;
+        self
+    }
+
+    /// Spool `message` and try to hand it upstream right away.
+    ///
+    /// The message is kept locally as queued until the upstream accepts it.
+    pub fn forward(&self, mut message: Message) -> Result<MessageId, RelayError> {
+        let id = message.envelope.id.clone();
+        let entry = SpoolEntry {
+            submission: RelaySubmission::from(&message),
+            attempts: 0,
+            next_attempt_at: self.clock.now(),
+        };
+        fs::create_dir_all(&self.spool)?;
+        write_json(&self.spool_path(&id), &entry)?;
+        message.envelope.status = MessageStatus::Queued;
+        self.store.save(message);
+        self.flush();
+        Ok(id)
+    }
+
+    /// Retry every spooled submission that is due.
+    pub fn flush(&self) -> RelayFlush {
+        let mut outcome = RelayFlush::default();
+        let now = self.clock.now();
+        for (path, mut entry) in self.spooled() {
+            if entry.next_attempt_at > now {
+                outcome.deferred += 1;
+                continue;
+            }
+            let id = entry.submission.origin_id.clone();
+            match self.api.submit(&entry.submission) {
+                Ok(upstream_id) => {
+                    self.remember(
+                        id.clone(),
+                        ForwardedMessage {
+                            upstream_id,
+                            forwarded_at: now,
+                            reports_seen: 0,
+                        },
+                    );
+                    self.store.update_status(&id, MessageStatus::Sent);
+                    let _ = fs::remove_file(&path);
+                    outcome.forwarded += 1;
+                }
+                Err(RelayError::Rejected { status, message }) => {
+                    let _ = fs::remove_file(&path);
+                    let report = Report {
+                        message_id: id,
+                        kind: ReportKind::NonDelivery,
+                        recipient: None,
+                        reason: Some("transfer-failure".into()),
+                        diagnostic: Some(format!("relay rejected ({status}): {message}")),
+                        timestamp: now,
+                    };
+                    if let Err(err) = self.ingestor.ingest(report) {
+                        warn!(target = "relay", "cannot record relay rejection: {err}");
+                    }
+                    outcome.rejected += 1;
+                }
+                Err(err) => {
+                    entry.attempts += 1;
+                    let delay = self
+                        .backoff
+                        .saturating_mul(2u32.saturating_pow(entry.attempts - 1))
+                        .min(MAX_BACKOFF);
+                    entry.next_attempt_at =
+                        now + chrono::Duration::from_std(delay).unwrap_or_default();
+                    warn!(
+                        target = "relay",
+                        message = %id,
+                        attempts = entry.attempts,
+                        "relay submit failed: {err}"
+                    );
+                    if let Err(err) = write_json(&path, &entry) {
+                        warn!(target = "relay", "cannot update spool entry: {err}");
+                    }
+                    outcome.deferred += 1;
+                }
+            }
+        }
+        outcome
+    }
+
+    /// Pull new upstream reports for forwarded messages and ingest them locally.
+    ///
+    /// Messages stop being polled after a non-delivery or read report.
+    pub fn reconcile(&self) -> usize {
+        let forwarded: Vec<_> = self
+            .forwarded
+            .lock()
+            .map(|map| map.iter().map(|(k, v)| (k.clone(), v.clone())).collect())
+            .unwrap_or_default();
+        let mut ingested = 0;
+        for (local, mut state) in forwarded {
+            let reports = match self.api.reports(&state.upstream_id) {
+                Ok(reports) => reports,
+                Err(err) => {
+                    warn!(target = "relay", message = %local, "report poll failed: {err}");
+                    continue;
+                }
+            };
+            let mut finished = false;
+            for report in reports.into_iter().skip(state.reports_seen) {
+                state.reports_seen += 1;
+                finished |= report.kind != RelayReportKind::Delivery;
+                let report = Report {
+                    message_id: local.clone(),
+                    kind: report.kind.into(),
+                    recipient: report.recipient.map(Into::into),
+                    reason: report.reason,
+                    diagnostic: report.diagnostic,
+                    timestamp: report.timestamp,
+                };
+                match self.ingestor.ingest(report) {
+                    Ok(()) => ingested += 1,
+                    Err(err) => warn!(target = "relay", "cannot ingest relay report: {err}"),
+                }
+            }
+            if finished {
+                self.forget(&local);
+            } else {
+                self.remember(local, state);
+            }
+        }
+        ingested
+    }
+
+    /// Start the `relay` worker flushing the spool and reconciling reports.
+    pub fn spawn(&self, supervisor: &Supervisor) {
+        let relay = self.clone();
+        supervisor.spawn("relay", move |context| {
+            while !context.should_stop() {
+                relay.flush();
+                relay.reconcile();
+                let mut waited = Duration::ZERO;
+                while waited < POLL_INTERVAL && !context.should_stop() {
+                    context.heartbeat();
+                    let step = (POLL_INTERVAL - waited).min(Duration::from_secs(1));
+                    thread::sleep(step);
+                    waited += step;
+                }
+            }
+        });
+    }
+
+    /// Messages still waiting in the spool, as `(id, attempts)`.
+    pub fn spooled_ids(&self) -> Vec<(MessageId, u32)> {
+        self.spooled()
+            .into_iter()
+            .map(|(_, entry)| (entry.submission.origin_id, entry.attempts))
+            .collect()
+    }
+
+    pub fn forwarded(&self, id: &MessageId) -> Option<ForwardedMessage> {
+        self.forwarded
+            .lock()
+            .ok()
+            .and_then(|map| map.get(id).cloned())
+    }
+
+    fn spool_path(&self, id: &MessageId) -> PathBuf {
+        let name: String =
+            id.0.chars()
+                .map(|c| {
+                    if c.is_ascii_alphanumeric() || c == '-' {
+                        c
+                    } else {
+                        '_'
+                    }
+                })
+                .collect();
+        self.spool.join(format!("{name}.spool.json"))
+    }
+
+    fn spooled(&self) -> Vec<(PathBuf, SpoolEntry)> {
+        let Ok(entries) = fs::read_dir(&self.spool) else {
+            return Vec::new();
+        };
+        let mut spooled: Vec<_> = entries
+            .filter_map(|entry| entry.ok().map(|entry| entry.path()))
+            .filter(|path| path.to_string_lossy().ends_with(".spool.json"))
+            .filter_map(|path| {
+                let entry = fs::read(&path)
+                    .ok()
+                    .and_then(|bytes| serde_json::from_slice::<SpoolEntry>(&bytes).ok())?;
+                Some((path, entry))
+            })
+            .collect();
+        spooled.sort_by_key(|(_, entry)| entry.next_attempt_at);
+        spooled
+    }
+
+    fn remember(&self, id: MessageId, state: ForwardedMessage) {
+        if let Ok(mut map) = self.forwarded.lock() {
+            map.insert(id, state);
+            self.persist_forwarded(&map);
+        }
+    }
+
+    fn forget(&self, id: &MessageId) {
+        if let Ok(mut map) = self.forwarded.lock() {
+            map.remove(id);
+            self.persist_forwarded(&map);
+        }
+    }
+
+    fn persist_forwarded(&self, map: &HashMap<MessageId, ForwardedMessage>) {
+        let result = fs::create_dir_all(&self.spool)
+            .map_err(RelayError::from)
+            .and_then(|_| write_json(&self.spool.join(FORWARDED_FILE), map));
+        if let Err(err) = result {
+            warn!(target = "relay", "cannot persist forwarded messages: {err}");
+        }
+    }
+}
+
+impl MessageTransport for RelayTransport {
+    fn name(&self) -> &'static str {
+        "relay"
+    }
+
+    /// Spool and forward; an unreachable upstream still accepts the message,
+    /// which stays queued until a later flush hands it over.
+    fn submit(&self, message: Message) -> Result<MessageId, TransportError> {
+        self.forward(message).map_err(|err| match err {
+            RelayError::Rejected { .. } => TransportError::Rejected(err.to_string()),
+            RelayError::Unavailable(_) | RelayError::Spool(_) => {
+                TransportError::Unavailable(err.to_string())
+            }
+        })
+    }
+
+    fn fetch(&self, id: &MessageId) -> Result<Option<Message>, TransportError> {
+        Ok(self.store.get(id))
+    }
+
+    fn list(&self, folder: &str) -> Result<Vec<Message>, TransportError> {
+        Ok(self.store.list(folder))
+    }
+
+    fn delete(&self, id: &MessageId) -> Result<bool, TransportError> {
+        Ok(self.store.delete(id))
+    }
+
+    fn reports(&self, id: &MessageId) -> Result<Vec<Report>, TransportError> {
+        Ok(self.store.reports(id))
+    }
+}
+
+fn write_json(path: &Path, value: &impl Serialize) -> Result<(), RelayError> {
+    let json =
+        serde_json::to_vec_pretty(value).map_err(|err| RelayError::Spool(err.to_string()))?;
+    let tmp = path.with_extension("tmp");
+    fs::write(&tmp, json)?;
+    fs::rename(tmp, path)?;
+    Ok(())
+}
+
+fn load_forwarded(path: &Path) -> HashMap<MessageId, ForwardedMessage> {
+    fs::read(path)
+        .ok()
+        .and_then(|bytes| serde_json::from_slice(&bytes).ok())
+        .unwrap_or_default()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::clock::ManualClock;
+    use crate::models::{Address, MessageContent, MessageEnvelope};
+    use crate::trace::TraceManager;
+    use chrono::TimeZone;
+    use std::sync::atomic::{AtomicBool, Ordering};
+
+    #[derive(Default)]
+    struct FakeHq {
+        online: AtomicBool,
+        submitted: Mutex<Vec<RelaySubmission>>,
+        reports: Mutex<HashMap<MessageId, Vec<RelayReport>>>,
+    }
+
+    impl RelayApi for FakeHq {
+        fn submit(&self, submission: &RelaySubmission) -> Result<MessageId, RelayError> {
+            if !self.online.load(Ordering::SeqCst) {
+                return Err(RelayError::Unavailable("connection refused".into()));
+            }
+            if submission.recipients.is_empty() {
+                return Err(RelayError::Rejected {
+                    status: 422,
+                    message: "no recipients".into(),
+                });
+            }
+            let mut submitted = self.submitted.lock().unwrap();
+            submitted.push(submission.clone());
+            Ok(MessageId(format!("hq-{}", submitted.len())))
+        }
+
+        fn reports(&self, upstream: &MessageId) -> Result<Vec<RelayReport>, RelayError> {
+            Ok(self
+                .reports
+                .lock()
+                .unwrap()
+                .get(upstream)
+                .cloned()
+                .unwrap_or_default())
+        }
+    }
+
+    fn message(id: &str, recipients: Vec<Address>) -> Message {
+        let mut envelope = MessageEnvelope::new("Quarterly", Address::sample(), recipients);
+        envelope.id = MessageId(id.into());
+        Message {
+            envelope,
+            content: MessageContent {
+                body: "Numbers attached".into(),
+                attachments: Vec::new(),
+            },
+        }
+    }
+
+    #[test]
+    fn spools_until_upstream_accepts_and_reconciles_reports() {
+        let spool = tempfile::tempdir().unwrap();
+        let start = Utc.with_ymd_and_hms(2024, 4, 2, 9, 0, 0).unwrap();
+        let clock = ManualClock::new(start);
+        let hq = Arc::new(FakeHq::default());
+        let store = StoreManager::new();
+        let ingestor = ReportIngestor::new(store.clone(), TraceManager::new());
+        let relay = RelayTransport::new(hq.clone(), store.clone(), ingestor, spool.path())
+            .with_backoff(Duration::from_secs(60))
+            .with_clock(SharedClock::new(clock.clone()));
+
+        // Headquarters is down: the message stays spooled and queued locally.
+        let id = relay
+            .forward(message("branch-1", vec![Address::sample()]))
+            .unwrap();
+        assert_eq!(relay.spooled_ids(), vec![(id.clone(), 1)]);
+        assert_eq!(
+            store.get(&id).unwrap().envelope.status,
+            MessageStatus::Queued
+        );
+
+        hq.online.store(true, Ordering::SeqCst);
+        assert_eq!(relay.flush().deferred, 1, "backoff not yet elapsed");
+        clock.advance(chrono::Duration::seconds(61));
+        assert_eq!(relay.flush().forwarded, 1);
+        assert!(relay.spooled_ids().is_empty());
+        assert_eq!(store.get(&id).unwrap().envelope.status, MessageStatus::Sent);
+        assert_eq!(hq.submitted.lock().unwrap()[0].origin_id, id);
+
+        let upstream = relay.forwarded(&id).unwrap().upstream_id;
+        hq.reports.lock().unwrap().insert(
+            upstream,
+            vec![RelayReport {
+                kind: RelayReportKind::Delivery,
+                recipient: Some((&Address::sample()).into()),
+                reason: Some("delivered".into()),
+                diagnostic: None,
+                timestamp: start,
+            }],
+        );
+        assert_eq!(relay.reconcile(), 1);
+        assert_eq!(relay.reconcile(), 0, "reports are ingested once");
+        assert_eq!(
+            store.get(&id).unwrap().envelope.status,
+            MessageStatus::Delivered
+        );
+
+        // The forwarded map survives a restart.
+        let reopened = RelayTransport::new(
+            hq.clone(),
+            store.clone(),
+            ReportIngestor::new(store.clone(), TraceManager::new()),
+            spool.path(),
+        );
+        assert_eq!(reopened.forwarded(&id).unwrap().reports_seen, 1);
+
+        // A permanent rejection becomes a local non-delivery report.
+        let rejected = relay.forward(message("branch-2", Vec::new())).unwrap();
+        assert_eq!(
+            store.get(&rejected).unwrap().envelope.status,
+            MessageStatus::Failed
+        );
+        assert!(relay.spooled_ids().is_empty());
+    }
+}
@@ -0,0 +1,490 @@
+//! New-message and report notifications pushed by the vendor SDK.
+//!
+//! SDKs that export `x400_sdk_subscribe` call back into the service when a
+//! message arrives in a session's message store or a report comes in. The
+//! callback runs on a vendor thread, so [`trampoline`] only copies the event
+//! out of the C struct and hands it to an [`EventSink`] without blocking;
+//! a full queue drops the event, which the next fetch still finds. The
+//! supervised `p7-events` worker ([`SdkEventPump`]) fetches announced
+//! messages through the session of the profile that announced them and hands
+//! them to the [`InboundIngestor`]; reports go to the shared
+//! [`ReportIngestor`], so both take the same path as gateway traffic.
+//!
+//! The C surface, as declared by the vendor header:
+//!
+//! ```text
+//! typedef struct {
+//!     int kind;                 /* X400_EVENT_* */
+//!     const char *message_id;
+//!     const char *diagnostic;   /* reports only, may be NULL */
+//! } x400_sdk_event;
+//!
+//! typedef void (*x400_sdk_callback)(void *context, const x400_sdk_event *event);
+//!
+//! int x400_sdk_subscribe(const char *session, x400_sdk_callback cb, void *context);
+//! int x400_sdk_unsubscribe(const char *session);
+//! ```
+//!
+//! No callback may run once `x400_sdk_unsubscribe` has returned; the context
+//! is freed right after.
+
+use std::ffi::{c_void, CStr, CString};
+use std::os::raw::{c_char, c_int};
+use std::panic::{self, AssertUnwindSafe};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::mpsc::{self, Receiver, RecvTimeoutError, SyncSender, TrySendError};
+use std::sync::{Arc, Mutex, PoisonError};
+use std::time::Duration;
+
+use tracing::{debug, info, warn};
+
+use crate::clock::SharedClock;
+use crate::gateway::InboundIngestor;
+use crate::models::{MessageId, Report, ReportKind};
+use crate::reports::ReportIngestor;
+use crate::store::StoreManager;
+use crate::supervisor::Supervisor;
+use crate::transport::p7_driver::{P7Driver, P7Error, P7Session};
+
+pub const X400_EVENT_MESSAGE: c_int = 1;
+pub const X400_EVENT_DELIVERY_REPORT: c_int = 2;
+pub const X400_EVENT_NON_DELIVERY_REPORT: c_int = 3;
+pub const X400_EVENT_READ_REPORT: c_int = 4;
+
+/// Events waiting for the pump before further ones are dropped.
+pub const EVENT_QUEUE: usize = 1024;
+
+/// How long the pump waits for an event before checking for a stop.
+const POLL: Duration = Duration::from_millis(250);
+
+#[repr(C)]
+pub struct X400SdkEvent {
+    pub kind: c_int,
+    pub message_id: *const c_char,
+    pub diagnostic: *const c_char,
+}
+
+pub type X400SdkCallback = unsafe extern "C" fn(context: *mut c_void, event: *const X400SdkEvent);
+pub type X400SdkSubscribe = unsafe extern "C" fn(
+    session: *const c_char,
+    callback: X400SdkCallback,
+    context: *mut c_void,
+) -> c_int;
+pub type X400SdkUnsubscribe = unsafe extern "C" fn(session: *const c_char) -> c_int;
+
+/// A notification, copied out of the vendor's memory.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum SdkEvent {
+    NewMessage {
+        profile: String,
+        id: MessageId,
+    },
+    Report {
+        profile: String,
+        message_id: MessageId,
+        kind: ReportKind,
+        diagnostic: Option<String>,
+    },
+}
+
+impl SdkEvent {
+    /// Copy `raw` out; `None` for unknown kinds or a missing message id.
+    ///
+    /// # Safety
+    /// The strings of `raw` must be null or valid NUL-terminated strings.
+    unsafe fn from_raw(profile: &str, raw: &X400SdkEvent) -> Option<Self> {
+        let text = |ptr: *const c_char| {
+            (!ptr.is_null()).then(|| CStr::from_ptr(ptr).to_string_lossy().into_owned())
+        };
+        let id = MessageId(text(raw.message_id)?);
+        let kind = match raw.kind {
+            X400_EVENT_MESSAGE => {
+                return Some(Self::NewMessage {
+                    profile: profile.to_string(),
+                    id,
+                })
+            }
+            X400_EVENT_DELIVERY_REPORT => ReportKind::Delivery,
+            X400_EVENT_NON_DELIVERY_REPORT => ReportKind::NonDelivery,
+            X400_EVENT_READ_REPORT => ReportKind::Read,
+            _ => return None,
+        };
+        Some(Self::Report {
+            profile: profile.to_string(),
+            message_id: id,
+            kind,
+            diagnostic: text(raw.diagnostic),
+        })
+    }
+}
+
+/// Where subscriptions deliver events; cheap to clone.
+#[derive(Clone)]
+pub struct EventSink {
+    events: SyncSender<SdkEvent>,
+    dropped: Arc<AtomicU64>,
+}
+
+/// A sink and the receiving end for [`SdkEventPump`].
+pub fn channel(capacity: usize) -> (EventSink, Receiver<SdkEvent>) {
+    let (events, receiver) = mpsc::sync_channel(capacity);
+    let sink = EventSink {
+        events,
+        dropped: Arc::new(AtomicU64::new(0)),
+    };
+    (sink, receiver)
+}
+
+impl EventSink {
+    /// Queue `event` without blocking; false if it was dropped.
+    pub fn push(&self, event: SdkEvent) -> bool {
+        match self.events.try_send(event) {
+            Ok(()) => true,
+            Err(TrySendError::Full(_) | TrySendError::Disconnected(_)) => {
+                self.dropped.fetch_add(1, Ordering::Relaxed);
+                false
+            }
+        }
+    }
+
+    /// Events dropped because the queue was full or the pump gone.
+    pub fn dropped(&self) -> u64 {
+        self.dropped.load(Ordering::Relaxed)
+    }
+}
+
+/// What the callback context points at.
+struct Target {
+    profile: String,
+    sink: EventSink,
+}
+
+/// The `x400_sdk_callback` handed to the SDK.
+///
+/// # Safety
+/// `context` must be the pointer [`FfiSubscription::subscribe`] registered,
+/// and `event` null or valid for the duration of the call.
+pub unsafe extern "C" fn trampoline(context: *mut c_void, event: *const X400SdkEvent) {
+    // Unwinding into the vendor's stack is undefined behaviour.
+    let _ = panic::catch_unwind(AssertUnwindSafe(|| {
+        if context.is_null() || event.is_null() {
+            return;
+        }
+        let target = &*(context as *const Target);
+        match SdkEvent::from_raw(&target.profile, &*event) {
+            Some(event) => {
+                if !target.sink.push(event) {
+                    warn!(target = "transport.p7", profile = %target.profile, "SDK event dropped");
+                }
+            }
+            None => warn!(
+                target = "transport.p7",
+                profile = %target.profile,
+                kind = (*event).kind,
+                "unrecognised SDK event"
+            ),
+        }
+    }));
+}
+
+/// An active `x400_sdk_subscribe` registration; unsubscribes when dropped.
+pub struct FfiSubscription {
+    session: CString,
+    context: *mut Target,
+    unsubscribe: X400SdkUnsubscribe,
+}
+
+// SAFETY: the context is only read by the trampoline, and `EventSink` is
+// `Send + Sync`; the raw pointer is what keeps the auto traits off.
+unsafe impl Send for FfiSubscription {}
+unsafe impl Sync for FfiSubscription {}
+
+impl FfiSubscription {
+    /// Register [`trampoline`] for `session`, delivering into `sink`.
+    ///
+    /// # Safety
+    /// `subscribe` and `unsubscribe` must be the vendor's
+    /// `x400_sdk_subscribe` and `x400_sdk_unsubscribe`.
+    pub unsafe fn subscribe(
+        subscribe: X400SdkSubscribe,
+        unsubscribe: X400SdkUnsubscribe,
+        session: &P7Session,
+        sink: EventSink,
+    ) -> Result<Self, P7Error> {
+        let id = CString::new(session.id.as_str())
+            .map_err(|_| P7Error::Unsupported("session ids containing NUL"))?;
+        let context = Box::into_raw(Box::new(Target {
+            profile: session.profile.clone(),
+            sink,
+        }));
+        let code = subscribe(id.as_ptr(), trampoline, context.cast());
+        if code != 0 {
+            drop(Box::from_raw(context));
+            return Err(P7Error::Sdk {
+                code,
+                message: "x400_sdk_subscribe failed".into(),
+            });
+        }
+        Ok(Self {
+            session: id,
+            context,
+            unsubscribe,
+        })
+    }
+}
+
+impl Drop for FfiSubscription {
+    fn drop(&mut self) {
+        // SAFETY: no callback runs once unsubscribe has returned, so the
+        // context can be freed after it.
+        unsafe {
+            let code = (self.unsubscribe)(self.session.as_ptr());
+            if code != 0 {
+                warn!(target = "transport.p7", code, "x400_sdk_unsubscribe failed");
+            }
+            drop(Box::from_raw(self.context));
+        }
+    }
+}
+
+/// Hands announced messages and reports to the inbound and report ingestors.
+#[derive(Clone)]
+pub struct SdkEventPump {
+    driver: P7Driver,
+    store: StoreManager,
+    inbound: InboundIngestor,
+    reports: ReportIngestor,
+    events: Arc<Mutex<Receiver<SdkEvent>>>,
+    clock: SharedClock,
+}
+
+impl SdkEventPump {
+    pub fn new(
+        driver: P7Driver,
+        store: StoreManager,
+        inbound: InboundIngestor,
+        reports: ReportIngestor,
+        events: Receiver<SdkEvent>,
+    ) -> Self {
+        Self {
+            driver,
+            store,
+            inbound,
+            reports,
+            events: Arc::new(Mutex::new(events)),
+            clock: SharedClock::default(),
+        }
+    }
+
+    pub fn with_clock(mut self, clock: SharedClock) -> Self {
+        self.clock = clock;
+        self
+    }
+
+    /// File one event. Announced messages are fetched through the session
+    /// of the profile that announced them; ones already stored are skipped.
+    /// A message the ingestor quarantines or a report that matches no stored
+    /// message is logged, not returned as an error.
+    pub fn handle(&self, event: SdkEvent) -> Result<(), P7Error> {
+        match event {
+            SdkEvent::NewMessage { profile, id } => {
+                if self.store.get(&id).is_some() {
+                    debug!(target = "transport.p7", id = %id.0, "announced message already stored");
+                    return Ok(());
+                }
+                let driver = self.driver.routed(&profile)?;
+                let fetched =
+                    driver.call("fetch", 0, move |sdk, session| sdk.fetch(session, &id))?;
+                if let Some(message) = fetched {
+                    match self.inbound.ingest_native(message) {
+                        Ok(id) => {
+                            info!(target = "transport.p7", profile, id = %id.0, "announced message stored")
+                        }
+                        Err(error) => {
+                            warn!(target = "transport.p7", profile, %error, "announced message quarantined")
+                        }
+                    }
+                }
+            }
+            SdkEvent::Report {
+                message_id,
+                kind,
+                diagnostic,
+                ..
+            } => {
+                let report = Report {
+                    message_id,
+                    kind,
+                    recipient: None,
+                    reason: None,
+                    diagnostic,
+                    timestamp: self.clock.now(),
+                };
+                if let Err(error) = self.reports.ingest(report) {
+                    warn!(target = "transport.p7", %error, "SDK report dropped");
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// Drain events as the supervised `p7-events` worker.
+    pub fn spawn(&self, supervisor: &Supervisor) {
+        let pump = self.clone();
+        supervisor.spawn("p7-events", move |context| {
+            while !context.should_stop() {
+                let next = pump
+                    .events
+                    .lock()
+                    .unwrap_or_else(PoisonError::into_inner)
+                    .recv_timeout(POLL);
+                match next {
+                    Ok(event) => {
+                        if let Err(error) = pump.handle(event) {
+                            warn!(target = "transport.p7", %error, "SDK event not filed");
+                        }
+                    }
+                    Err(RecvTimeoutError::Timeout) => {}
+                    Err(RecvTimeoutError::Disconnected) => return,
+                }
+                context.heartbeat();
+            }
+        });
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::gateway::AddressMapper;
+    use crate::models::{Message, MessageStatus};
+    use crate::trace::TraceManager;
+    use crate::transport::p7_driver::P7Sdk;
+    use chrono::DateTime;
+    use std::sync::atomic::AtomicU32;
+
+    static UNSUBSCRIBED: AtomicU32 = AtomicU32::new(0);
+
+    /// Announces a message, a report and an unknown event right away.
+    unsafe extern "C" fn announce(
+        _session: *const c_char,
+        callback: X400SdkCallback,
+        context: *mut c_void,
+    ) -> c_int {
+        let id = CString::new("p7-1").unwrap();
+        let diagnostic = CString::new("recipient unknown").unwrap();
+        for (kind, diagnostic) in [
+            (X400_EVENT_MESSAGE, std::ptr::null()),
+            (X400_EVENT_NON_DELIVERY_REPORT, diagnostic.as_ptr()),
+            (99, std::ptr::null()),
+        ] {
+            let event = X400SdkEvent {
+                kind,
+                message_id: id.as_ptr(),
+                diagnostic,
+            };
+            callback(context, &event);
+        }
+        callback(context, std::ptr::null());
+        0
+    }
+
+    unsafe extern "C" fn unsubscribe(_session: *const c_char) -> c_int {
+        UNSUBSCRIBED.fetch_add(1, Ordering::SeqCst);
+        0
+    }
+
+    /// Serves `message` under whatever id is fetched.
+    struct InboxSdk {
+        message: Message,
+        fetches: AtomicU32,
+    }
+
+    impl P7Sdk for InboxSdk {
+        fn bind(&self, profile: &str) -> Result<P7Session, P7Error> {
+            Ok(P7Session {
+                id: format!("{profile}-1"),
+                profile: profile.to_string(),
+                bound_at: DateTime::UNIX_EPOCH,
+            })
+        }
+
+        fn unbind(&self, _: &P7Session) -> Result<(), P7Error> {
+            Ok(())
+        }
+
+        fn fetch(&self, _: &P7Session, id: &MessageId) -> Result<Option<Message>, P7Error> {
+            self.fetches.fetch_add(1, Ordering::SeqCst);
+            let mut message = self.message.clone();
+            message.envelope.id = id.clone();
+            Ok(Some(message))
+        }
+    }
+
+    #[test]
+    fn files_events_delivered_through_the_trampoline() {
+        let (sink, events) = channel(8);
+        let session = P7Session {
+            id: "finance-1".into(),
+            profile: "finance".into(),
+            bound_at: DateTime::UNIX_EPOCH,
+        };
+        let subscription =
+            unsafe { FfiSubscription::subscribe(announce, unsubscribe, &session, sink.clone()) }
+                .unwrap();
+        drop(subscription);
+        assert_eq!(UNSUBSCRIBED.load(Ordering::SeqCst), 1);
+        let received: Vec<SdkEvent> = events.try_iter().collect();
+        assert_eq!(
+            received[0],
+            SdkEvent::NewMessage {
+                profile: "finance".into(),
+                id: MessageId("p7-1".into()),
+            }
+        );
+        assert_eq!(received.len(), 2);
+
+        let demo = StoreManager::new();
+        let message = demo.get(&demo.seed_demo_data()[0]).unwrap();
+        let sdk = Arc::new(InboxSdk {
+            message,
+            fetches: AtomicU32::new(0),
+        });
+        let driver = P7Driver::new(sdk.clone(), "ops").with_profiles(["finance"]);
+        driver.tick();
+        let store = StoreManager::new();
+        let trace = TraceManager::new();
+        let pump = SdkEventPump::new(
+            driver,
+            store.clone(),
+            InboundIngestor::new(AddressMapper::default(), store.clone()).with_max_hops(2),
+            ReportIngestor::new(store.clone(), trace.clone()),
+            events,
+        );
+        for event in received.iter().cloned().chain([received[0].clone()]) {
+            pump.handle(event).unwrap();
+        }
+        let id = MessageId("p7-1".into());
+        let stored = store.get(&id).unwrap();
+        assert_eq!(stored.envelope.folder, "inbox");
+        assert_eq!(stored.envelope.status, MessageStatus::Failed);
+        assert_eq!(sdk.fetches.load(Ordering::SeqCst), 1);
+        let reports = store.reports(&id);
+        assert_eq!(reports[0].kind, ReportKind::NonDelivery);
+        assert_eq!(reports[0].diagnostic.as_deref(), Some("recipient unknown"));
+        assert_eq!(trace.bundle()[0].event, "report.non_delivery");
+
+        // A message that already crossed the gateway too often stays out of the inbox.
+        let mut looping = stored;
+        looping.envelope.id = MessageId("p7-2".into());
+        looping.envelope.gateway_hops = 3;
+        assert!(pump.inbound.ingest_native(looping).is_err());
+        let quarantined = store.get(&MessageId("p7-2".into())).unwrap();
+        assert_eq!(quarantined.envelope.folder, "quarantine");
+
+        let (full, _events) = channel(1);
+        assert!(full.push(received[0].clone()));
+        assert!(!full.push(received[0].clone()));
+        assert_eq!(full.dropped(), 1);
+    }
+}
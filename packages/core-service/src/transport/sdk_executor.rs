@@ -0,0 +1,223 @@
+//! Off-thread execution of vendor SDK calls.
+//!
+//! SDK calls block inside the vendor library, so [`SdkExecutor`] never runs
+//! them on the caller's thread: each call goes to a dedicated blocking pool
+//! and the caller awaits it, asynchronously with [`SdkExecutor::run_async`]
+//! or from plain threads with [`SdkExecutor::run`]. At most
+//! `transport.sdk.maxConcurrentCalls` calls are in the library at once;
+//! further ones wait for a slot.
+//!
+//! The connect and operation timeouts are handed to the vendor library too,
+//! but a hung library would still hold its thread. The caller therefore
+//! waits only until the deadline, slot included, and then gets
+//! [`P7Error::TimedOut`]. Dropping the future cancels the call: a call still
+//! waiting for a slot never starts, and one already in the library finishes
+//! on the pool. A result that arrives after its caller gave up, on timeout or
+//! cancellation, is handed to the `abandoned` callback, so a late session
+//! can still be unbound. A call keeps its slot until the library returns, so
+//! hung calls cannot pile up threads past the limit.
+
+use std::future::Future;
+use std::sync::Arc;
+use std::time::Duration;
+
+use tokio::runtime::{Builder, Runtime};
+use tokio::sync::{oneshot, Semaphore};
+
+use crate::transport::p7_driver::{P7Error, PANICKED_CODE};
+
+/// Calls allowed in the library at once unless configured otherwise.
+pub const DEFAULT_MAX_CONCURRENT_CALLS: usize = 8;
+
+struct Pool {
+    runtime: Option<Runtime>,
+}
+
+impl Drop for Pool {
+    fn drop(&mut self) {
+        // Hung calls must not keep the service from shutting down.
+        if let Some(runtime) = self.runtime.take() {
+            runtime.shutdown_background();
+        }
+    }
+}
+
+#[derive(Clone)]
+pub struct SdkExecutor {
+    pool: Arc<Pool>,
+    slots: Arc<Semaphore>,
+    max_concurrent: usize,
+}
+
+impl Default for SdkExecutor {
+    fn default() -> Self {
+        Self::new(DEFAULT_MAX_CONCURRENT_CALLS)
+    }
+}
+
+impl SdkExecutor {
+    /// An executor letting `max_concurrent` calls into the library at once.
+    pub fn new(max_concurrent: usize) -> Self {
+        let max_concurrent = max_concurrent.max(1);
+        let runtime = Builder::new_multi_thread()
+            .worker_threads(1)
+            .max_blocking_threads(max_concurrent)
+            .thread_name("p7-sdk")
+            .enable_time()
+            .build()
+            .expect("P7 SDK thread pool");
+        Self {
+            pool: Arc::new(Pool {
+                runtime: Some(runtime),
+            }),
+            slots: Arc::new(Semaphore::new(max_concurrent)),
+            max_concurrent,
+        }
+    }
+
+    /// Calls in the library right now, hung ones included.
+    pub fn in_flight(&self) -> usize {
+        self.max_concurrent - self.slots.available_permits()
+    }
+
+    /// Run `call` on the blocking pool and wait at most `timeout` for it,
+    /// blocking the current thread. Not for async code; use
+    /// [`run_async`](Self::run_async) there.
+    pub fn run<T: Send + 'static>(
+        &self,
+        timeout: Duration,
+        call: impl FnOnce() -> Result<T, P7Error> + Send + 'static,
+        abandoned: impl FnOnce(T) + Send + 'static,
+    ) -> Result<T, P7Error> {
+        self.block_on(self.run_async(timeout, call, abandoned))
+    }
+
+    /// Drive a sequence of [`run_async`](Self::run_async) calls from plain
+    /// threads, as [`run`](Self::run) does for one.
+    pub fn block_on<F: Future>(&self, future: F) -> F::Output {
+        self.pool
+            .runtime
+            .as_ref()
+            .expect("the pool is only shut down on drop")
+            .block_on(future)
+    }
+
+    /// Run `call` on the blocking pool and wait at most `timeout` for it
+    /// without blocking the runtime. Dropping the future cancels the call.
+    pub fn run_async<T: Send + 'static>(
+        &self,
+        timeout: Duration,
+        call: impl FnOnce() -> Result<T, P7Error> + Send + 'static,
+        abandoned: impl FnOnce(T) + Send + 'static,
+    ) -> impl Future<Output = Result<T, P7Error>> + Send + 'static {
+        let handle = self
+            .pool
+            .runtime
+            .as_ref()
+            .map(|runtime| runtime.handle().clone());
+        let slots = self.slots.clone();
+        async move {
+            let Some(handle) = handle else {
+                return call();
+            };
+            let context = handle.clone();
+            let work = async move {
+                let slot = slots
+                    .acquire_owned()
+                    .await
+                    .expect("SDK call slots are never closed");
+                let (done, result) = oneshot::channel();
+                handle.spawn_blocking(move || {
+                    let result = call();
+                    drop(slot);
+                    if let Err(Ok(value)) = done.send(result) {
+                        abandoned(value);
+                    }
+                });
+                result.await.unwrap_or_else(|_| {
+                    Err(P7Error::Sdk {
+                        code: PANICKED_CODE,
+                        message: "SDK call panicked".into(),
+                    })
+                })
+            };
+            // The deadline runs on this executor's timer, whichever runtime
+            // awaits the call.
+            let deadline = {
+                let _entered = context.enter();
+                tokio::time::timeout(timeout, work)
+            };
+            deadline.await.unwrap_or(Err(P7Error::TimedOut(timeout)))
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::pin::pin;
+    use std::sync::mpsc;
+    use std::task::Poll;
+    use std::time::Instant;
+
+    #[test]
+    fn bounds_times_out_and_cancels_calls() {
+        let executor = SdkExecutor::new(1);
+        assert_eq!(executor.run(Duration::from_secs(5), || Ok(7), drop), Ok(7));
+
+        // A hung call is given up on; its late result is handed over.
+        let (release, hung) = mpsc::channel::<()>();
+        let (late_tx, late) = mpsc::channel();
+        let started = Instant::now();
+        let result = executor.run(
+            Duration::from_millis(50),
+            move || {
+                let _ = hung.recv();
+                Ok("s-1")
+            },
+            move |session| late_tx.send(session).unwrap(),
+        );
+        assert_eq!(result, Err(P7Error::TimedOut(Duration::from_millis(50))));
+        assert!(started.elapsed() < Duration::from_secs(2));
+
+        // The hung call still holds the only slot, so the next never starts.
+        assert_eq!(executor.in_flight(), 1);
+        let (ran_tx, ran) = mpsc::channel();
+        let queued = executor.run(
+            Duration::from_millis(50),
+            move || {
+                ran_tx.send(()).unwrap();
+                Ok(())
+            },
+            drop,
+        );
+        assert_eq!(queued, Err(P7Error::TimedOut(Duration::from_millis(50))));
+        release.send(()).unwrap();
+        assert_eq!(late.recv_timeout(Duration::from_secs(5)), Ok("s-1"));
+        assert!(ran.try_recv().is_err());
+
+        // Awaited from another runtime, and cancelled by dropping it.
+        let caller = Builder::new_current_thread().build().unwrap();
+        let value = caller.block_on(executor.run_async(Duration::from_secs(5), || Ok(3), drop));
+        assert_eq!(value, Ok(3));
+        let (cancelled_tx, cancelled) = mpsc::channel();
+        let call = executor.run_async(
+            Duration::from_secs(5),
+            || {
+                std::thread::sleep(Duration::from_millis(50));
+                Ok(4)
+            },
+            move |value| cancelled_tx.send(value).unwrap(),
+        );
+        caller.block_on(async {
+            let mut call = pin!(call);
+            // Poll once so the call starts, then drop it.
+            std::future::poll_fn(|cx| {
+                assert!(call.as_mut().poll(cx).is_pending());
+                Poll::Ready(())
+            })
+            .await;
+        });
+        assert_eq!(cancelled.recv_timeout(Duration::from_secs(5)), Ok(4));
+    }
+}
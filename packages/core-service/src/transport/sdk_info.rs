@@ -0,0 +1,217 @@
+//! What the loaded vendor SDK can do.
+//!
+//! SDK versions differ in the operations they offer and the largest message
+//! they accept. [`P7Driver`](super::P7Driver) asks once, through
+//! `x400_sdk_info`, and then refuses unsupported operations and oversized
+//! submissions itself instead of passing them to a library that would fail
+//! them in its own way. The answer is the capability matrix in the
+//! `transport` block of `/status`, next to the SDK version, so a mismatched
+//! installation shows up there. Libraries without the symbol are assumed to
+//! offer every operation with no size limit.
+//!
+//! The C surface, as declared by the vendor header:
+//!
+//! ```text
+//! typedef struct {
+//!     const char *version;
+//!     unsigned int operations;      /* X400_OP_* bits */
+//!     unsigned long long max_message_size;  /* bytes, 0 = no limit */
+//! } x400_sdk_info_t;
+//!
+//! int x400_sdk_info(x400_sdk_info_t *info);
+//! ```
+
+use std::ffi::CStr;
+use std::os::raw::{c_char, c_int, c_uint, c_ulonglong};
+
+use serde::Serialize;
+
+use crate::transport::p7_driver::P7Error;
+
+/// `X400_OP_*` bits and the operation each one stands for.
+pub const OPERATIONS: [(c_uint, &str); 6] = [
+    (0x01, "submit"),
+    (0x02, "fetch"),
+    (0x04, "list"),
+    (0x08, "delete"),
+    (0x10, "reports"),
+    (0x20, "subscribe"),
+];
+
+#[repr(C)]
+pub struct X400SdkInfo {
+    pub version: *const c_char,
+    pub operations: c_uint,
+    pub max_message_size: c_ulonglong,
+}
+
+pub type X400SdkInfoFn = unsafe extern "C" fn(info: *mut X400SdkInfo) -> c_int;
+
+/// One row of the capability matrix.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct SdkOperation {
+    pub operation: &'static str,
+    pub supported: bool,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct SdkCapabilities {
+    /// As reported by the library; `None` when it has no `x400_sdk_info`.
+    pub version: Option<String>,
+    pub operations: Vec<SdkOperation>,
+    /// Largest submission in bytes the library accepts.
+    pub max_message_size: Option<u64>,
+}
+
+impl Default for SdkCapabilities {
+    /// Everything, for libraries that do not describe themselves.
+    fn default() -> Self {
+        Self {
+            version: None,
+            operations: OPERATIONS
+                .iter()
+                .map(|(_, operation)| SdkOperation {
+                    operation,
+                    supported: true,
+                })
+                .collect(),
+            max_message_size: None,
+        }
+    }
+}
+
+impl SdkCapabilities {
+    pub fn new(version: impl Into<String>, operations: c_uint, max_message_size: u64) -> Self {
+        Self {
+            version: Some(version.into()),
+            operations: OPERATIONS
+                .iter()
+                .map(|(bit, operation)| SdkOperation {
+                    operation,
+                    supported: operations & bit != 0,
+                })
+                .collect(),
+            max_message_size: (max_message_size > 0).then_some(max_message_size),
+        }
+    }
+
+    /// Operations outside the matrix, such as bind, are always allowed.
+    pub fn supports(&self, operation: &str) -> bool {
+        self.operations
+            .iter()
+            .find(|row| row.operation == operation)
+            .is_none_or(|row| row.supported)
+    }
+
+    /// The `&'static` name of a listed operation, for [`P7Error::Unsupported`].
+    pub fn operation(operation: &str) -> Option<&'static str> {
+        OPERATIONS
+            .iter()
+            .map(|(_, name)| *name)
+            .find(|name| *name == operation)
+    }
+}
+
+/// Call the vendor's `x400_sdk_info`.
+///
+/// # Safety
+/// `info` must be the vendor's `x400_sdk_info`, which fills the struct and
+/// leaves `version` null or pointing at a NUL-terminated string that lives
+/// as long as the library.
+pub unsafe fn query(info: X400SdkInfoFn) -> Result<SdkCapabilities, P7Error> {
+    let mut raw = X400SdkInfo {
+        version: std::ptr::null(),
+        operations: 0,
+        max_message_size: 0,
+    };
+    let code = info(&mut raw);
+    if code != 0 {
+        return Err(P7Error::Sdk {
+            code,
+            message: "x400_sdk_info failed".into(),
+        });
+    }
+    let version = if raw.version.is_null() {
+        "unknown".to_string()
+    } else {
+        CStr::from_ptr(raw.version).to_string_lossy().into_owned()
+    };
+    Ok(SdkCapabilities::new(
+        version,
+        raw.operations,
+        raw.max_message_size,
+    ))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::transport::p7_driver::{ConnectionState, P7Driver, P7Sdk, P7Session};
+    use crate::transport::MessageTransport;
+    use chrono::DateTime;
+    use std::sync::Arc;
+
+    struct DescribedSdk;
+
+    impl P7Sdk for DescribedSdk {
+        fn info(&self) -> Result<SdkCapabilities, P7Error> {
+            unsafe { query(info) }
+        }
+
+        fn bind(&self, profile: &str) -> Result<P7Session, P7Error> {
+            Ok(P7Session {
+                id: "s-1".into(),
+                profile: profile.to_string(),
+                bound_at: DateTime::UNIX_EPOCH,
+            })
+        }
+
+        fn unbind(&self, _: &P7Session) -> Result<(), P7Error> {
+            Ok(())
+        }
+    }
+
+    unsafe extern "C" fn info(info: *mut X400SdkInfo) -> c_int {
+        (*info).version = c"4.2.1".as_ptr();
+        (*info).operations = 0x01 | 0x02 | 0x10;
+        (*info).max_message_size = 1024;
+        0
+    }
+
+    #[test]
+    fn reads_the_capability_matrix() {
+        let capabilities = unsafe { query(info) }.unwrap();
+        assert_eq!(capabilities.version.as_deref(), Some("4.2.1"));
+        assert!(capabilities.supports("submit") && capabilities.supports("reports"));
+        assert!(!capabilities.supports("list") && !capabilities.supports("subscribe"));
+        assert!(capabilities.supports("bind"));
+        assert_eq!(capabilities.max_message_size, Some(1024));
+        assert!(SdkCapabilities::default().supports("list"));
+        assert_eq!(SdkCapabilities::operation("list"), Some("list"));
+
+        let driver = P7Driver::new(Arc::new(DescribedSdk), "ops");
+        assert_eq!(driver.status().capabilities, None);
+        assert_eq!(driver.tick(), ConnectionState::Bound);
+        assert_eq!(driver.status().capabilities, Some(capabilities));
+        assert_eq!(
+            driver.call("list", 0, |_, _| Ok(())),
+            Err(P7Error::Unsupported("list"))
+        );
+        assert_eq!(
+            driver.call("submit", 2048, |_, _| Ok(())),
+            Err(P7Error::TooLarge {
+                size: 2048,
+                max: 1024
+            })
+        );
+        assert_eq!(
+            MessageTransport::list(&driver, "inbox")
+                .unwrap_err()
+                .status(),
+            501
+        );
+        assert_eq!(driver.call("submit", 512, |_, _| Ok(7)), Ok(7));
+    }
+}
@@ -0,0 +1,226 @@
+//! Tracing and latency accounting for vendor SDK calls.
+//!
+//! Every call across the SDK boundary goes through [`SdkCallRecorder::call`],
+//! which opens an `sdk.call` span carrying the operation, profile, payload
+//! size and vendor result code. The span is exported with the rest of the
+//! OpenTelemetry pipeline, and the call latency is kept in a per-operation
+//! window that `/metrics` reports as percentiles for vendor SLA reviews.
+
+use std::collections::{BTreeMap, VecDeque};
+use std::future::Future;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+use serde::Serialize;
+use tracing::field::Empty;
+use tracing::{info_span, warn, Instrument, Span};
+
+use crate::telemetry::TelemetryManager;
+
+/// Latency samples kept per operation.
+const WINDOW: usize = 1024;
+
+/// Vendor status code of a failed SDK call; `0` is success.
+pub trait ResultCode {
+    fn result_code(&self) -> i32;
+}
+
+impl ResultCode for i32 {
+    fn result_code(&self) -> i32 {
+        *self
+    }
+}
+
+/// Per-operation latency summary as reported by `/metrics`.
+#[derive(Clone, Debug, PartialEq, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct SdkLatencySummary {
+    pub operation: String,
+    pub calls: u64,
+    pub failures: u64,
+    pub p50_ms: f64,
+    pub p95_ms: f64,
+    pub p99_ms: f64,
+    pub max_ms: f64,
+}
+
+#[derive(Default)]
+struct Operation {
+    calls: u64,
+    failures: u64,
+    max: Duration,
+    window: VecDeque<Duration>,
+}
+
+#[derive(Clone, Default)]
+pub struct SdkCallRecorder {
+    operations: Arc<Mutex<BTreeMap<String, Operation>>>,
+    telemetry: Option<TelemetryManager>,
+}
+
+impl SdkCallRecorder {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn with_telemetry(mut self, telemetry: TelemetryManager) -> Self {
+        self.telemetry = Some(telemetry);
+        self
+    }
+
+    /// Run one SDK call inside an `sdk.call` span and record its latency.
+    pub fn call<T, E: ResultCode>(
+        &self,
+        operation: &str,
+        profile: &str,
+        payload_bytes: usize,
+        call: impl FnOnce() -> Result<T, E>,
+    ) -> Result<T, E> {
+        let span = Self::span(operation, profile, payload_bytes);
+        let _entered = span.enter();
+        let started = Instant::now();
+        let result = call();
+        self.finish(&span, operation, profile, started.elapsed(), result)
+    }
+
+    /// [`call`](Self::call) for a call awaited on a runtime.
+    pub async fn call_async<T, E: ResultCode>(
+        &self,
+        operation: &str,
+        profile: &str,
+        payload_bytes: usize,
+        call: impl Future<Output = Result<T, E>>,
+    ) -> Result<T, E> {
+        let span = Self::span(operation, profile, payload_bytes);
+        let started = Instant::now();
+        let result = call.instrument(span.clone()).await;
+        span.in_scope(|| self.finish(&span, operation, profile, started.elapsed(), result))
+    }
+
+    fn span(operation: &str, profile: &str, payload_bytes: usize) -> Span {
+        info_span!(
+            "sdk.call",
+            sdk.operation = operation,
+            sdk.profile = profile,
+            sdk.payload_bytes = payload_bytes as u64,
+            sdk.result_code = Empty,
+            sdk.latency_ms = Empty,
+        )
+    }
+
+    fn finish<T, E: ResultCode>(
+        &self,
+        span: &Span,
+        operation: &str,
+        profile: &str,
+        elapsed: Duration,
+        result: Result<T, E>,
+    ) -> Result<T, E> {
+        let code = result.as_ref().err().map_or(0, ResultCode::result_code);
+        span.record("sdk.result_code", code);
+        span.record("sdk.latency_ms", elapsed.as_secs_f64() * 1000.0);
+        self.record(operation, elapsed, code != 0);
+        if let Some(telemetry) = &self.telemetry {
+            telemetry.record_sdk_call(operation, elapsed, code == 0);
+        }
+        if code != 0 {
+            warn!(
+                target = "transport.sdk",
+                operation, profile, code, "SDK call failed"
+            );
+            if let Some(telemetry) = &self.telemetry {
+                telemetry.record_error(format!("sdk {operation} on {profile} returned {code}"));
+            }
+        }
+        result
+    }
+
+    pub fn summary(&self) -> Vec<SdkLatencySummary> {
+        let Ok(operations) = self.operations.lock() else {
+            return Vec::new();
+        };
+        operations
+            .iter()
+            .map(|(name, operation)| {
+                let mut sorted: Vec<Duration> = operation.window.iter().copied().collect();
+                sorted.sort_unstable();
+                SdkLatencySummary {
+                    operation: name.clone(),
+                    calls: operation.calls,
+                    failures: operation.failures,
+                    p50_ms: percentile(&sorted, 50),
+                    p95_ms: percentile(&sorted, 95),
+                    p99_ms: percentile(&sorted, 99),
+                    max_ms: millis(operation.max),
+                }
+            })
+            .collect()
+    }
+
+    fn record(&self, operation: &str, elapsed: Duration, failed: bool) {
+        let Ok(mut operations) = self.operations.lock() else {
+            return;
+        };
+        let entry = operations.entry(operation.to_string()).or_default();
+        entry.calls += 1;
+        entry.failures += u64::from(failed);
+        entry.max = entry.max.max(elapsed);
+        if entry.window.len() == WINDOW {
+            entry.window.pop_front();
+        }
+        entry.window.push_back(elapsed);
+    }
+}
+
+/// Nearest-rank percentile of an ascending sample.
+fn percentile(sorted: &[Duration], pct: usize) -> f64 {
+    if sorted.is_empty() {
+        return 0.0;
+    }
+    let rank = (pct * sorted.len()).div_ceil(100).max(1);
+    millis(sorted[rank - 1])
+}
+
+fn millis(duration: Duration) -> f64 {
+    duration.as_secs_f64() * 1000.0
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn summarises_latency_and_failures_per_operation() {
+        let recorder = SdkCallRecorder::new();
+        for delay in 1..=20u64 {
+            let result: Result<(), i32> = recorder.call("submit", "hq", 2048, || {
+                std::thread::sleep(Duration::from_millis(delay));
+                Ok(())
+            });
+            assert!(result.is_ok());
+        }
+        let failed: Result<(), i32> = recorder.call("fetch", "hq", 0, || Err(4));
+        assert_eq!(failed, Err(4));
+
+        let summary = recorder.summary();
+        assert_eq!(summary.len(), 2);
+        let fetch = &summary[0];
+        assert_eq!(
+            (fetch.operation.as_str(), fetch.calls, fetch.failures),
+            ("fetch", 1, 1)
+        );
+        let submit = &summary[1];
+        assert_eq!((submit.calls, submit.failures), (20, 0));
+        assert!(submit.p50_ms >= 10.0 && submit.p50_ms < submit.p95_ms);
+        assert!(submit.p95_ms >= 19.0);
+        assert!(submit.max_ms >= submit.p99_ms);
+    }
+
+    #[test]
+    fn percentile_uses_nearest_rank() {
+        let sample: Vec<Duration> = (1..=10).map(Duration::from_millis).collect();
+        assert_eq!(percentile(&sample, 50), 5.0);
+        assert_eq!(percentile(&sample, 95), 10.0);
+        assert_eq!(percentile(&[], 99), 0.0);
+    }
+}
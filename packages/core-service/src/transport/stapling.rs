@@ -0,0 +1,258 @@
+//! Stapled OCSP checks for MTA connections over TLS.
+//!
+//! When the P7 driver reaches an MTA over TLS, the OCSP response the peer
+//! stapled to its handshake is handed to [`StaplingVerifier::verify`]. The
+//! response must be signed by a certificate chaining to the pinned CA set in
+//! `transport.tls.caBundle` and must cover the peer's leaf certificate. When
+//! the peer staples nothing, or the staple does not verify, the leaf is
+//! checked with `transport.tls.ocspResponder` if one is configured.
+//!
+//! The last result per endpoint is reported in the `transport` block of
+//! `/status` as `stapling`.
+
+use std::collections::BTreeMap;
+use std::fs;
+use std::sync::{Arc, Mutex, PoisonError};
+use std::time::Duration;
+
+use chrono::{DateTime, Utc};
+use openssl::x509::store::{X509Store, X509StoreBuilder};
+use openssl::x509::{X509VerifyResult, X509};
+use serde::Serialize;
+use tracing::warn;
+
+use crate::clock::SharedClock;
+use crate::config::TransportConfig;
+use crate::revocation::{ocsp_request, ocsp_verdict, HttpFetcher, Revocation, RevocationFetcher};
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub enum StapleVerdict {
+    Good,
+    Revoked,
+    /// Neither the staple nor the responder gave a usable answer.
+    Unknown,
+}
+
+/// Outcome of one handshake's stapling check.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct StaplingStatus {
+    pub endpoint: String,
+    /// Whether the peer stapled a response at all.
+    pub stapled: bool,
+    /// Whether the verdict came from the staple rather than the responder.
+    pub staple_valid: bool,
+    pub verdict: StapleVerdict,
+    pub revoked_at: Option<DateTime<Utc>>,
+    pub error: Option<String>,
+    pub checked_at: DateTime<Utc>,
+}
+
+#[derive(Clone)]
+pub struct StaplingVerifier {
+    pinned: Arc<Vec<X509>>,
+    trust: Arc<X509Store>,
+    responder: Option<String>,
+    fetcher: Arc<dyn RevocationFetcher>,
+    clock: SharedClock,
+    last: Arc<Mutex<BTreeMap<String, StaplingStatus>>>,
+}
+
+impl StaplingVerifier {
+    /// Trust OCSP signers chaining to `pinned`.
+    pub fn new(pinned: Vec<X509>, fetcher: Arc<dyn RevocationFetcher>) -> Self {
+        let mut trust = X509StoreBuilder::new().expect("allocating an X509 store");
+        for certificate in &pinned {
+            let _ = trust.add_cert(certificate.clone());
+        }
+        Self {
+            pinned: Arc::new(pinned),
+            trust: Arc::new(trust.build()),
+            responder: None,
+            fetcher,
+            clock: SharedClock::default(),
+            last: Arc::new(Mutex::new(BTreeMap::new())),
+        }
+    }
+
+    /// The pinned CA set and responder of `transport`. An unreadable bundle
+    /// leaves the set empty, so every staple fails to verify.
+    pub fn from_config(transport: &TransportConfig) -> Self {
+        let mut pinned = Vec::new();
+        if let Some(path) = transport.ca_bundle.as_deref() {
+            match fs::read(path).map(|pem| X509::stack_from_pem(&pem)) {
+                Ok(Ok(loaded)) => pinned = loaded,
+                _ => warn!(target = "transport", path, "pinned CA bundle not loaded"),
+            }
+        }
+        Self::new(pinned, Arc::new(HttpFetcher::new(Duration::from_secs(10))))
+            .with_responder(transport.ocsp_responder.clone())
+    }
+
+    /// Ask this responder when the peer staples nothing usable.
+    pub fn with_responder(mut self, responder: Option<String>) -> Self {
+        self.responder = responder;
+        self
+    }
+
+    pub fn with_clock(mut self, clock: SharedClock) -> Self {
+        self.clock = clock;
+        self
+    }
+
+    /// Check the peer `chain`, leaf first, of a handshake with `endpoint`
+    /// against the `staple` it sent, and remember the result.
+    pub fn verify(&self, endpoint: &str, chain: &[X509], staple: Option<&[u8]>) -> StaplingStatus {
+        let mut status = StaplingStatus {
+            endpoint: endpoint.to_string(),
+            stapled: staple.is_some(),
+            staple_valid: false,
+            verdict: StapleVerdict::Unknown,
+            revoked_at: None,
+            error: None,
+            checked_at: self.clock.now(),
+        };
+        let outcome = match (chain.first(), self.issuer(chain)) {
+            (Some(leaf), Some(issuer)) => self.check(&mut status, leaf, issuer, chain, staple),
+            (None, _) => Err("peer presented no certificate".to_string()),
+            (Some(_), None) => Err("issuer is not in the chain or the pinned CA set".to_string()),
+        };
+        match outcome {
+            Ok(Revocation::Good) => status.verdict = StapleVerdict::Good,
+            Ok(Revocation::Revoked { revoked_at }) => {
+                status.verdict = StapleVerdict::Revoked;
+                status.revoked_at = revoked_at;
+            }
+            Err(error) => {
+                warn!(
+                    target = "transport",
+                    endpoint, error, "stapled OCSP not verified"
+                );
+                status.error = Some(error);
+            }
+        }
+        self.last_mut().insert(endpoint.to_string(), status.clone());
+        status
+    }
+
+    /// Last result per endpoint, for the `transport` block of `/status`.
+    pub fn statuses(&self) -> Vec<StaplingStatus> {
+        self.last_mut().values().cloned().collect()
+    }
+
+    fn check(
+        &self,
+        status: &mut StaplingStatus,
+        leaf: &X509,
+        issuer: &X509,
+        chain: &[X509],
+        staple: Option<&[u8]>,
+    ) -> Result<Revocation, String> {
+        let untrusted = chain.get(1..).unwrap_or_default();
+        let mut staple_error = None;
+        if let Some(staple) = staple {
+            match ocsp_verdict(staple, leaf, issuer, untrusted, &self.trust) {
+                Ok((revocation, _)) => {
+                    status.staple_valid = true;
+                    return Ok(revocation);
+                }
+                Err(error) => staple_error = Some(format!("staple: {error}")),
+            }
+        }
+        let Some(url) = self.responder.as_deref() else {
+            return Err(staple_error
+                .unwrap_or_else(|| "no staple and no transport.tls.ocspResponder".to_string()));
+        };
+        let queried = ocsp_request(leaf, issuer)
+            .and_then(|request| self.fetcher.ocsp(url, &request))
+            .and_then(|response| ocsp_verdict(&response, leaf, issuer, untrusted, &self.trust));
+        queried
+            .map(|(revocation, _)| revocation)
+            .map_err(|error| match staple_error {
+                Some(staple_error) => format!("{staple_error}; OCSP {url}: {error}"),
+                None => format!("OCSP {url}: {error}"),
+            })
+    }
+
+    fn issuer<'a>(&'a self, chain: &'a [X509]) -> Option<&'a X509> {
+        let leaf = chain.first()?;
+        chain
+            .iter()
+            .skip(1)
+            .chain(self.pinned.iter())
+            .find(|candidate| candidate.issued(leaf) == X509VerifyResult::OK)
+    }
+
+    fn last_mut(&self) -> std::sync::MutexGuard<'_, BTreeMap<String, StaplingStatus>> {
+        self.last.lock().unwrap_or_else(PoisonError::into_inner)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use openssl::ocsp::{OcspResponse, OcspResponseStatus};
+    use rcgen::{BasicConstraints, CertificateParams, DnType, IsCa, KeyPair};
+
+    struct Unauthorized;
+
+    impl RevocationFetcher for Unauthorized {
+        fn get(&self, url: &str) -> Result<Vec<u8>, String> {
+            Err(format!("{url} unreachable"))
+        }
+
+        fn ocsp(&self, url: &str, _: &[u8]) -> Result<Vec<u8>, String> {
+            assert_eq!(url, "http://ocsp.example");
+            OcspResponse::create(OcspResponseStatus::UNAUTHORIZED, None)
+                .and_then(|response| response.to_der())
+                .map_err(|err| err.to_string())
+        }
+    }
+
+    fn chain() -> Vec<X509> {
+        let ca_key = KeyPair::generate().unwrap();
+        let mut params = CertificateParams::new(Vec::<String>::new()).unwrap();
+        params.is_ca = IsCa::Ca(BasicConstraints::Unconstrained);
+        params.distinguished_name.push(DnType::CommonName, "MTA CA");
+        let ca = params.self_signed(&ca_key).unwrap();
+        let mut params = CertificateParams::new(vec!["mta.example".into()]).unwrap();
+        params.distinguished_name.push(DnType::CommonName, "mta");
+        let leaf = params
+            .signed_by(&KeyPair::generate().unwrap(), &ca, &ca_key)
+            .unwrap();
+        [leaf.der(), ca.der()]
+            .into_iter()
+            .map(|der| X509::from_der(der).unwrap())
+            .collect()
+    }
+
+    #[test]
+    fn falls_back_to_the_responder_and_records_failures() {
+        let chain = chain();
+        let pinned = vec![chain[1].clone()];
+        let verifier = StaplingVerifier::new(pinned, Arc::new(Unauthorized));
+
+        let missing = verifier.verify("mta-a:102", &chain[..1], None);
+        assert!(!missing.stapled);
+        assert_eq!(missing.verdict, StapleVerdict::Unknown);
+        assert!(missing.error.unwrap().contains("no staple"));
+
+        let garbage = verifier.verify("mta-b:102", &chain, Some(b"not ocsp"));
+        assert!(garbage.stapled && !garbage.staple_valid);
+        assert!(garbage.error.unwrap().starts_with("staple: "));
+
+        let verifier = verifier.with_responder(Some("http://ocsp.example".into()));
+        let queried = verifier.verify("mta-a:102", &chain, Some(b"not ocsp"));
+        assert_eq!(queried.verdict, StapleVerdict::Unknown);
+        assert!(queried.error.unwrap().contains("responder answered 6"));
+
+        let statuses = verifier.statuses();
+        assert_eq!(statuses.len(), 2);
+        assert!(statuses[0].stapled);
+
+        let unknown = StaplingVerifier::new(Vec::new(), Arc::new(Unauthorized));
+        let unpinned = unknown.verify("mta-c:102", &chain[..1], None);
+        assert!(unpinned.error.unwrap().contains("pinned CA set"));
+    }
+}
@@ -0,0 +1,914 @@
+//! Switching the transport without a restart.
+//!
+//! `POST /admin/transport` with `{"mode": "sdk"}` (or `mock`, `gateway`,
+//! `relay`) calls [`TransportSwitch::switch`]. The new transport is brought
+//! up first; for `sdk` that means binding a session, and a failed bind leaves
+//! the current transport in place. `relay` needs the relay transport given
+//! with [`TransportSwitch::with_relay`]. The swap then waits up to
+//! `server.drainTimeoutMs` for a moment with no operation running on the old
+//! transport. Once the swap is done, the SDK session of a replaced `sdk`
+//! transport is unbound.
+//!
+//! Every submission passes the [`SubmitPolicy`] given with
+//! [`TransportSwitch::with_policy`] before it reaches the active transport,
+//! so DLP and S/MIME apply in every mode, and then the mailbox quota given
+//! with [`TransportSwitch::with_quota`], which refuses it with `507`. With
+//! [`TransportSwitch::with_breakers`], each mode is a breaker target: an
+//! open circuit refuses submissions with `503`, and every outcome is
+//! recorded, with only `Unavailable` and `TimedOut` counting as failures.
+//!
+//! [`TransportSwitch::submit_batch`] screens every message of a batch with
+//! the same policy and quota, then stores and queues the batch in one step
+//! in the outbox given with [`TransportSwitch::with_outbox`]; only the
+//! dispatch of each message goes through the breakers and the active
+//! transport.
+
+use std::sync::{Arc, Mutex, PoisonError, RwLock, RwLockReadGuard, RwLockWriteGuard, TryLockError};
+use std::thread;
+use std::time::{Duration, Instant};
+
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use tracing::{info, warn};
+
+use crate::audit::AuditLog;
+use crate::clock::SharedClock;
+use crate::config::QueueConfig;
+use crate::models::{AuditAction, Message, MessageId, MessageStatus, Report};
+use crate::queue::QueueManager;
+use crate::quota::{QuotaError, QuotaPolicy};
+use crate::store::StoreManager;
+use crate::submit::{
+    BatchError, BatchItemResult, BatchOutcome, BatchSubmission, Screened, SubmitPolicy,
+};
+use crate::telemetry::TelemetryManager;
+use crate::transport::breaker::CircuitBreakers;
+use crate::transport::message_transport::{MessageTransport, TransportError};
+use crate::transport::p7_driver::{ConnectionState, P7Driver};
+
+/// Modes `POST /admin/transport` accepts.
+pub const TRANSPORT_MODES: [&str; 4] = ["mock", "sdk", "gateway", "relay"];
+
+/// Response of `POST /admin/transport`.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct TransportSwitchReport {
+    pub from: String,
+    pub to: String,
+    /// False when the requested mode was already active.
+    pub switched: bool,
+    pub drained_ms: u64,
+    pub switched_at: DateTime<Utc>,
+}
+
+struct Active {
+    mode: &'static str,
+    transport: Arc<dyn MessageTransport>,
+}
+
+/// The active transport; itself a [`MessageTransport`] that forwards to it.
+#[derive(Clone)]
+pub struct TransportSwitch {
+    active: Arc<RwLock<Active>>,
+    mock: Arc<dyn MessageTransport>,
+    gateway: Arc<dyn MessageTransport>,
+    relay: Option<Arc<dyn MessageTransport>>,
+    p7: P7Driver,
+    switching: Arc<Mutex<()>>,
+    drain_timeout: Duration,
+    clock: SharedClock,
+    telemetry: Option<TelemetryManager>,
+    policy: Option<(SubmitPolicy, StoreManager)>,
+    quota: Option<QuotaPolicy>,
+    breakers: Option<CircuitBreakers>,
+    audit: Option<AuditLog>,
+    outbox: Option<(StoreManager, QueueManager)>,
+    batch_limit: usize,
+}
+
+impl TransportSwitch {
+    /// Start on `mode`; an unknown mode falls back to `mock`. A `relay`
+    /// start routes to the mock until [`TransportSwitch::with_relay`].
+    pub fn new(
+        mode: &str,
+        mock: Arc<dyn MessageTransport>,
+        p7: P7Driver,
+        gateway: Arc<dyn MessageTransport>,
+    ) -> Self {
+        let mode = TRANSPORT_MODES
+            .into_iter()
+            .find(|known| *known == mode)
+            .unwrap_or("mock");
+        let switch = Self {
+            active: Arc::new(RwLock::new(Active {
+                mode,
+                transport: mock.clone(),
+            })),
+            mock,
+            gateway,
+            relay: None,
+            p7,
+            switching: Arc::new(Mutex::new(())),
+            drain_timeout: Duration::from_secs(30),
+            clock: SharedClock::default(),
+            telemetry: None,
+            policy: None,
+            quota: None,
+            breakers: None,
+            audit: None,
+            outbox: None,
+            batch_limit: QueueConfig::default().batch_limit,
+        };
+        if mode != "sdk" {
+            switch.p7.deactivate();
+        }
+        switch.write().transport = switch.transport_for(mode);
+        switch.mock.activated(mode);
+        switch
+    }
+
+    /// The transport of the `relay` mode, built from a relay profile.
+    pub fn with_relay(mut self, relay: Arc<dyn MessageTransport>) -> Self {
+        self.relay = Some(relay);
+        if self.mode() == "relay" {
+            self.write().transport = self.transport_for("relay");
+        }
+        self
+    }
+
+    /// Longest wait for in-flight operations; see `server.drainTimeoutMs`.
+    pub fn with_drain_timeout(mut self, timeout: Duration) -> Self {
+        self.drain_timeout = timeout;
+        self
+    }
+
+    pub fn with_clock(mut self, clock: SharedClock) -> Self {
+        self.clock = clock;
+        self
+    }
+
+    /// Time every submission into `x400_submit_duration_seconds`.
+    pub fn with_telemetry(mut self, telemetry: TelemetryManager) -> Self {
+        self.telemetry = Some(telemetry);
+        self
+    }
+
+    /// Screen every submission with `policy`; quarantined messages are kept
+    /// in `store` and never reach the transport.
+    pub fn with_policy(mut self, policy: SubmitPolicy, store: StoreManager) -> Self {
+        self.policy = Some((policy, store));
+        self
+    }
+
+    /// Refuse submissions the mailbox quota has no room for; see
+    /// [`QuotaPolicy::check`].
+    pub fn with_quota(mut self, quota: QuotaPolicy) -> Self {
+        self.quota = Some(quota);
+        self
+    }
+
+    /// Guard submissions with one circuit breaker per mode.
+    pub fn with_breakers(mut self, breakers: CircuitBreakers) -> Self {
+        self.breakers = Some(breakers);
+        self
+    }
+
+    /// Where [`submit_batch`](Self::submit_batch) stores and queues a batch
+    /// before dispatching it.
+    pub fn with_outbox(mut self, store: StoreManager, queue: QueueManager) -> Self {
+        self.outbox = Some((store, queue));
+        self
+    }
+
+    /// Most messages one [`submit_batch`](Self::submit_batch) takes; see
+    /// `queue.batchLimit`.
+    pub fn with_batch_limit(mut self, limit: usize) -> Self {
+        self.batch_limit = limit;
+        self
+    }
+
+    /// Record switches as `transport.switch` and deletions as
+    /// `message.delete`.
+    pub fn with_audit(mut self, audit: AuditLog) -> Self {
+        self.audit = Some(audit);
+        self
+    }
+
+    /// Run the submit policy and the quota on `message` without sending it,
+    /// as [`submit`](MessageTransport::submit) does first; for messages held
+    /// back until a send-at time. `Some` names a message DLP quarantined
+    /// instead.
+    pub fn admit(&self, message: &mut Message) -> Result<Option<MessageId>, TransportError> {
+        if let Some((policy, store)) = &self.policy {
+            match policy.screen(message) {
+                Ok(Screened::Accept) => {}
+                Ok(Screened::Quarantine) => {
+                    return Ok(Some(policy.quarantine(store, message.clone())))
+                }
+                Err(err) => return Err(TransportError::Rejected(err.to_string())),
+            }
+        }
+        if let Some(quota) = &self.quota {
+            quota.check(message).map_err(|err| match err {
+                QuotaError::Exceeded { .. } => TransportError::QuotaExceeded(err.to_string()),
+                _ => TransportError::Unavailable(err.to_string()),
+            })?;
+        }
+        Ok(None)
+    }
+
+    /// `transport.mode` currently in effect.
+    pub fn mode(&self) -> &'static str {
+        self.read().mode
+    }
+
+    /// Submit several messages in one go.
+    ///
+    /// Every message is screened and checked against the quota first. The
+    /// ones that pass, and the quarantined ones, are then saved in one store
+    /// write and queued in one queue write, so no reader ever sees half a
+    /// batch. Only then is each message without a later send-at time
+    /// dispatched through the active transport; one whose dispatch fails
+    /// stays queued and reports the error. A refused message does not fail
+    /// the others.
+    pub fn submit_batch(
+        &self,
+        batch: Vec<BatchSubmission>,
+    ) -> Result<Vec<BatchItemResult>, BatchError> {
+        if batch.is_empty() {
+            return Err(BatchError::Empty);
+        }
+        if batch.len() > self.batch_limit {
+            return Err(BatchError::TooLarge {
+                size: batch.len(),
+                limit: self.batch_limit,
+            });
+        }
+        let Some((store, queue)) = &self.outbox else {
+            return Err(BatchError::NoOutbox);
+        };
+        let now = self.clock.now();
+        let can_schedule = self.read().transport.can_schedule();
+        let mut results = Vec::with_capacity(batch.len());
+        let mut accepted = Vec::new();
+        let mut queued = Vec::new();
+        let mut dispatched = Vec::new();
+        for (
+            index,
+            BatchSubmission {
+                mut message,
+                deferred_until,
+            },
+        ) in batch.into_iter().enumerate()
+        {
+            let id = message.envelope.id.clone();
+            let until = deferred_until.filter(|until| *until > now);
+            let screened = match until {
+                Some(_) if !can_schedule => Err(TransportError::Unsupported {
+                    transport: self.mode(),
+                    operation: "scheduled delivery",
+                }),
+                _ => self.screen(&mut message),
+            };
+            let outcome = match screened {
+                Err(err) => {
+                    results.push(BatchItemResult {
+                        index,
+                        message_id: id,
+                        outcome: BatchOutcome::Rejected,
+                        error: Some(err.to_string()),
+                    });
+                    continue;
+                }
+                Ok(Screened::Quarantine) => BatchOutcome::Quarantined,
+                Ok(Screened::Accept) => {
+                    message.envelope.status = MessageStatus::Queued;
+                    queued.push((id.clone(), message.envelope.priority, until));
+                    match until {
+                        Some(_) => BatchOutcome::Scheduled,
+                        None => {
+                            dispatched.push((results.len(), message.clone()));
+                            BatchOutcome::Accepted
+                        }
+                    }
+                }
+            };
+            accepted.push(message);
+            results.push(BatchItemResult {
+                index,
+                message_id: id,
+                outcome,
+                error: None,
+            });
+        }
+        store.save_all(accepted);
+        queue.enqueue_all(queued);
+        for (at, message) in dispatched {
+            let id = message.envelope.id.clone();
+            match self.send(|transport| transport.dispatch_queued(message)) {
+                Ok(_) => queue.complete(&id),
+                Err(err) => results[at].error = Some(err.to_string()),
+            }
+        }
+        Ok(results)
+    }
+
+    /// Send a message that is already stored and queued through the active
+    /// transport, without screening it again.
+    pub fn dispatch_queued(&self, message: Message) -> Result<MessageId, TransportError> {
+        self.send(|transport| transport.dispatch_queued(message))
+    }
+
+    /// [`admit`](Self::admit) without storing what DLP quarantines: the
+    /// message is only moved to the `quarantine` folder, for a batch to
+    /// store with the rest.
+    fn screen(&self, message: &mut Message) -> Result<Screened, TransportError> {
+        let mut screened = Screened::Accept;
+        if let Some((policy, _)) = &self.policy {
+            screened = policy
+                .screen(message)
+                .map_err(|err| TransportError::Rejected(err.to_string()))?;
+            if screened == Screened::Quarantine {
+                policy.mark_quarantined(message);
+                return Ok(screened);
+            }
+        }
+        if let Some(quota) = &self.quota {
+            quota.check(message).map_err(|err| match err {
+                QuotaError::Exceeded { .. } => TransportError::QuotaExceeded(err.to_string()),
+                _ => TransportError::Unavailable(err.to_string()),
+            })?;
+        }
+        Ok(screened)
+    }
+
+    /// Delete a message on behalf of `actor`, recording it in the audit log.
+    pub fn delete_as(&self, actor: &str, id: &MessageId) -> Result<bool, TransportError> {
+        let deleted = self.read().transport.delete(id)?;
+        if let (true, Some(audit)) = (deleted, &self.audit) {
+            audit.record(actor, AuditAction::MessageDelete, &id.0, None, None);
+        }
+        Ok(deleted)
+    }
+
+    /// Drain in-flight operations and switch to `mode`; `actor` is who asked
+    /// for it, as recorded in the audit log.
+    pub fn switch(&self, actor: &str, mode: &str) -> Result<TransportSwitchReport, TransportError> {
+        let _switching = self
+            .switching
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner);
+        let Some(to) = TRANSPORT_MODES.into_iter().find(|known| *known == mode) else {
+            return Err(TransportError::Rejected(format!(
+                "unknown transport mode {mode}; expected one of {}",
+                TRANSPORT_MODES.join(", ")
+            )));
+        };
+        let from = self.mode();
+        if from == to {
+            return Ok(self.report(from, to, false, Duration::ZERO));
+        }
+        if to == "relay" && self.relay.is_none() {
+            return Err(TransportError::Unavailable(
+                "no relay profile is configured".into(),
+            ));
+        }
+        if to == "sdk" {
+            self.p7.activate();
+            if self.p7.tick() != ConnectionState::Bound {
+                let status = self.p7.status();
+                let error = status
+                    .profile(&status.default_profile)
+                    .and_then(|profile| profile.last_error.clone())
+                    .unwrap_or_default();
+                self.p7.deactivate();
+                warn!(
+                    target = "transport",
+                    from, to, error, "transport switch abandoned"
+                );
+                return Err(TransportError::Unavailable(format!(
+                    "SDK session not bound: {error}"
+                )));
+            }
+        }
+
+        let started = Instant::now();
+        let deadline = started + self.drain_timeout;
+        let mut active = loop {
+            match self.active.try_write() {
+                Ok(active) => break active,
+                Err(TryLockError::Poisoned(poisoned)) => break poisoned.into_inner(),
+                Err(TryLockError::WouldBlock) if Instant::now() < deadline => {
+                    thread::sleep(Duration::from_millis(10));
+                }
+                Err(TryLockError::WouldBlock) => {
+                    if to == "sdk" {
+                        self.p7.deactivate();
+                    }
+                    warn!(
+                        target = "transport",
+                        from, to, "transport switch timed out draining"
+                    );
+                    return Err(TransportError::Unavailable(format!(
+                        "in-flight operations did not finish within {:?}",
+                        self.drain_timeout
+                    )));
+                }
+            }
+        };
+        let drained = started.elapsed();
+        active.mode = to;
+        active.transport = self.transport_for(to);
+        drop(active);
+        self.mock.activated(to);
+        if from == "sdk" {
+            self.p7.deactivate();
+        }
+        info!(
+            target = "transport",
+            from,
+            to,
+            drained_ms = drained.as_millis() as u64,
+            "transport switched"
+        );
+        if let Some(audit) = &self.audit {
+            audit.record(
+                actor,
+                AuditAction::TransportSwitch,
+                to,
+                None,
+                Some(format!("from {from}")),
+            );
+        }
+        Ok(self.report(from, to, true, drained))
+    }
+
+    fn transport_for(&self, mode: &str) -> Arc<dyn MessageTransport> {
+        match mode {
+            "sdk" => Arc::new(self.p7.clone()),
+            "gateway" => self.gateway.clone(),
+            "relay" => self.relay.clone().unwrap_or_else(|| self.mock.clone()),
+            _ => self.mock.clone(),
+        }
+    }
+
+    fn report(
+        &self,
+        from: &str,
+        to: &str,
+        switched: bool,
+        drained: Duration,
+    ) -> TransportSwitchReport {
+        TransportSwitchReport {
+            from: from.to_string(),
+            to: to.to_string(),
+            switched,
+            drained_ms: u64::try_from(drained.as_millis()).unwrap_or(u64::MAX),
+            switched_at: self.clock.now(),
+        }
+    }
+
+    /// Hand an admitted message to the active transport behind its breaker,
+    /// timing the call.
+    fn send(
+        &self,
+        hand_over: impl FnOnce(&dyn MessageTransport) -> Result<MessageId, TransportError>,
+    ) -> Result<MessageId, TransportError> {
+        let active = self.read();
+        if let Some(breakers) = &self.breakers {
+            breakers
+                .allow(active.mode)
+                .map_err(|open| TransportError::Unavailable(open.to_string()))?;
+        }
+        let started = Instant::now();
+        let result = hand_over(active.transport.as_ref());
+        if let Some(telemetry) = &self.telemetry {
+            telemetry.record_submit(active.mode, started.elapsed(), result.is_ok());
+        }
+        if let Some(breakers) = &self.breakers {
+            match &result {
+                Err(TransportError::Unavailable(_) | TransportError::TimedOut(_)) => {
+                    breakers.record_failure(active.mode)
+                }
+                _ => breakers.record_success(active.mode),
+            }
+        }
+        result
+    }
+
+    /// Held for the length of an operation, so a switch waits for it.
+    fn read(&self) -> RwLockReadGuard<'_, Active> {
+        self.active.read().unwrap_or_else(PoisonError::into_inner)
+    }
+
+    fn write(&self) -> RwLockWriteGuard<'_, Active> {
+        self.active.write().unwrap_or_else(PoisonError::into_inner)
+    }
+}
+
+impl MessageTransport for TransportSwitch {
+    fn name(&self) -> &'static str {
+        self.mode()
+    }
+
+    fn submit(&self, mut message: Message) -> Result<MessageId, TransportError> {
+        if let Some(quarantined) = self.admit(&mut message)? {
+            return Ok(quarantined);
+        }
+        self.send(|transport| transport.submit(message))
+    }
+
+    fn schedule(
+        &self,
+        mut message: Message,
+        until: DateTime<Utc>,
+    ) -> Result<MessageId, TransportError> {
+        if let Some(quarantined) = self.admit(&mut message)? {
+            return Ok(quarantined);
+        }
+        self.send(|transport| transport.schedule(message, until))
+    }
+
+    fn fetch(&self, id: &MessageId) -> Result<Option<Message>, TransportError> {
+        self.read().transport.fetch(id)
+    }
+
+    fn list(&self, folder: &str) -> Result<Vec<Message>, TransportError> {
+        self.read().transport.list(folder)
+    }
+
+    /// Deletes through the trait are made by the service itself.
+    fn delete(&self, id: &MessageId) -> Result<bool, TransportError> {
+        self.delete_as("system", id)
+    }
+
+    fn reports(&self, id: &MessageId) -> Result<Vec<Report>, TransportError> {
+        self.read().transport.reports(id)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::transport::p7_driver::{P7Error, P7Sdk, P7Session, UnloadedSdk};
+    use std::sync::mpsc;
+
+    /// Lists `name` as the only message id; blocks `list` until released.
+    struct Named {
+        name: &'static str,
+        gate: Mutex<Option<mpsc::Receiver<()>>>,
+    }
+
+    impl Named {
+        fn new(name: &'static str) -> Self {
+            Self {
+                name,
+                gate: Mutex::new(None),
+            }
+        }
+    }
+
+    impl MessageTransport for Named {
+        fn name(&self) -> &'static str {
+            self.name
+        }
+
+        fn submit(&self, message: Message) -> Result<MessageId, TransportError> {
+            Ok(message.envelope.id)
+        }
+
+        fn fetch(&self, _: &MessageId) -> Result<Option<Message>, TransportError> {
+            Ok(None)
+        }
+
+        fn list(&self, _: &str) -> Result<Vec<Message>, TransportError> {
+            if let Some(gate) = self.gate.lock().unwrap().take() {
+                let _ = gate.recv();
+            }
+            Ok(Vec::new())
+        }
+
+        fn delete(&self, _: &MessageId) -> Result<bool, TransportError> {
+            Ok(false)
+        }
+
+        fn reports(&self, _: &MessageId) -> Result<Vec<Report>, TransportError> {
+            Ok(Vec::new())
+        }
+    }
+
+    /// Refuses every submission as unreachable.
+    struct Down;
+
+    impl MessageTransport for Down {
+        fn name(&self) -> &'static str {
+            "gateway"
+        }
+
+        fn submit(&self, _: Message) -> Result<MessageId, TransportError> {
+            Err(TransportError::Unavailable("connection refused".into()))
+        }
+
+        fn fetch(&self, _: &MessageId) -> Result<Option<Message>, TransportError> {
+            Ok(None)
+        }
+
+        fn list(&self, _: &str) -> Result<Vec<Message>, TransportError> {
+            Ok(Vec::new())
+        }
+
+        fn delete(&self, _: &MessageId) -> Result<bool, TransportError> {
+            Ok(false)
+        }
+
+        fn reports(&self, _: &MessageId) -> Result<Vec<Report>, TransportError> {
+            Ok(Vec::new())
+        }
+    }
+
+    struct BindingSdk;
+
+    impl P7Sdk for BindingSdk {
+        fn bind(&self, profile: &str) -> Result<P7Session, P7Error> {
+            Ok(P7Session {
+                id: "s-1".into(),
+                profile: profile.to_string(),
+                bound_at: DateTime::UNIX_EPOCH,
+            })
+        }
+
+        fn unbind(&self, _: &P7Session) -> Result<(), P7Error> {
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn drains_before_swapping_and_keeps_the_old_transport_on_failure() {
+        let mock = Arc::new(Named::new("mock"));
+        let (release, gate) = mpsc::channel();
+        *mock.gate.lock().unwrap() = Some(gate);
+        let unbound = P7Driver::new(Arc::new(UnloadedSdk), "ops");
+        let switch = TransportSwitch::new("mock", mock, unbound, Arc::new(Named::new("gateway")));
+        assert!(!switch.p7.is_active());
+
+        let refused = switch.switch("ops", "sdk").unwrap_err();
+        assert_eq!(refused.status(), 503);
+        assert_eq!(switch.mode(), "mock");
+        assert!(switch.switch("ops", "x25").is_err());
+
+        let in_flight = {
+            let switch = switch.clone();
+            thread::spawn(move || switch.list("inbox"))
+        };
+        while switch.active.try_write().is_ok() {
+            thread::yield_now();
+        }
+        let switching = {
+            let switch = switch.clone();
+            thread::spawn(move || switch.switch("ops", "gateway"))
+        };
+        thread::sleep(Duration::from_millis(30));
+        assert_eq!(switch.mode(), "mock");
+        release.send(()).unwrap();
+        in_flight.join().unwrap().unwrap();
+        let report = switching.join().unwrap().unwrap();
+        assert!(report.switched && report.drained_ms >= 20);
+        assert_eq!(switch.name(), "gateway");
+        assert!(!switch.switch("ops", "gateway").unwrap().switched);
+
+        let bound = P7Driver::new(Arc::new(BindingSdk), "ops");
+        let switch = TransportSwitch::new(
+            "gateway",
+            Arc::new(Named::new("mock")),
+            bound.clone(),
+            Arc::new(Named::new("gateway")),
+        );
+        switch.switch("ops", "sdk").unwrap();
+        assert_eq!(bound.status().profiles[0].state, ConnectionState::Bound);
+        switch.switch("ops", "mock").unwrap();
+        assert_eq!(bound.status().profiles[0].state, ConnectionState::Unbound);
+        assert!(!bound.is_active());
+    }
+
+    #[test]
+    fn relay_mode_routes_to_the_relay_transport_once_given() {
+        let switch = TransportSwitch::new(
+            "mock",
+            Arc::new(Named::new("mock")),
+            P7Driver::new(Arc::new(UnloadedSdk), "ops"),
+            Arc::new(Named::new("gateway")),
+        );
+        assert_eq!(switch.switch("ops", "relay").unwrap_err().status(), 503);
+        assert_eq!(switch.mode(), "mock");
+
+        let switch = switch.with_relay(Arc::new(Named::new("relay")));
+        assert!(switch.switch("ops", "relay").unwrap().switched);
+        assert_eq!(switch.read().transport.name(), "relay");
+
+        let started = TransportSwitch::new(
+            "relay",
+            Arc::new(Named::new("mock")),
+            P7Driver::new(Arc::new(UnloadedSdk), "ops"),
+            Arc::new(Named::new("gateway")),
+        )
+        .with_relay(Arc::new(Named::new("relay")));
+        assert_eq!(started.read().transport.name(), "relay");
+    }
+
+    #[test]
+    fn screens_submissions_before_any_transport_and_audits_verdicts() {
+        use crate::audit::{AuditLog, AuditQuery};
+        use crate::dlp::{DlpAction, DlpEngine, DlpMatcher, DlpRule};
+        use crate::models::{Address, AuditAction, MessageContent, MessageEnvelope};
+        use crate::trace::TraceManager;
+        use sha2::Digest;
+
+        let store = StoreManager::new();
+        let audit = AuditLog::new(store.clone());
+        let engine = DlpEngine::new(vec![
+            DlpRule::new(
+                "payroll",
+                DlpMatcher::keyword("payroll"),
+                DlpAction::Quarantine,
+            ),
+            DlpRule::new(
+                "secret",
+                DlpMatcher::Marker("[SECRET]".into()),
+                DlpAction::Block,
+            ),
+            DlpRule::new(
+                "restricted",
+                DlpMatcher::Marker("[RESTRICTED]".into()),
+                DlpAction::RequireEncryption,
+            ),
+        ]);
+        let policy = SubmitPolicy::new(TraceManager::new())
+            .with_dlp(engine)
+            .with_audit(audit.clone());
+        let switch = TransportSwitch::new(
+            "gateway",
+            Arc::new(Named::new("mock")),
+            P7Driver::new(Arc::new(UnloadedSdk), "ops"),
+            Arc::new(Named::new("gateway")),
+        )
+        .with_policy(policy, store.clone());
+        let message = |subject: &str| Message {
+            envelope: MessageEnvelope::new(subject, Address::sample(), vec![Address::sample()]),
+            content: MessageContent {
+                body: String::new(),
+                attachments: Vec::new(),
+            },
+        };
+
+        let blocked = message("[SECRET] plans");
+        let blocked_id = blocked.envelope.id.clone();
+        assert_eq!(switch.submit(blocked).unwrap_err().status(), 422);
+        let held = switch.submit(message("Payroll run")).unwrap();
+        assert_eq!(store.get(&held).unwrap().envelope.folder, "quarantine");
+        let plain = message("[RESTRICTED] roster");
+        let plain_id = plain.envelope.id.clone();
+        assert_eq!(switch.submit(plain).unwrap_err().status(), 422);
+        assert!(switch.submit(message("Hello")).is_ok());
+
+        let records: Vec<_> = audit
+            .query(&AuditQuery::default())
+            .into_iter()
+            .map(|record| (record.action, record.target, record.detail))
+            .collect();
+        let detail = |rule: &str, excerpt: &str| {
+            let digest = format!("{:x}", sha2::Sha256::digest(excerpt.as_bytes()));
+            Some(format!(
+                "rule {rule} matched subject, excerpt sha256:{}",
+                &digest[..16]
+            ))
+        };
+        assert_eq!(
+            records,
+            [
+                (
+                    AuditAction::DlpEncryptionRequired,
+                    plain_id.0,
+                    detail("restricted", "[RESTRICTED]")
+                ),
+                (
+                    AuditAction::DlpQuarantine,
+                    held.0,
+                    detail("payroll", "Payroll")
+                ),
+                (
+                    AuditAction::DlpBlock,
+                    blocked_id.0,
+                    detail("secret", "[SECRET]")
+                ),
+            ]
+        );
+        assert!(records
+            .iter()
+            .all(|(_, _, detail)| !detail.as_deref().unwrap().contains("SECRET")));
+    }
+
+    #[test]
+    fn opens_the_breaker_of_a_failing_mode() {
+        use crate::models::{Address, MessageContent, MessageEnvelope};
+        use crate::transport::breaker::BreakerState;
+
+        let breakers = CircuitBreakers::new(2, Duration::from_secs(60));
+        let switch = TransportSwitch::new(
+            "gateway",
+            Arc::new(Named::new("mock")),
+            P7Driver::new(Arc::new(UnloadedSdk), "ops"),
+            Arc::new(Down),
+        )
+        .with_breakers(breakers.clone());
+        let message = || Message {
+            envelope: MessageEnvelope::new("Hello", Address::sample(), vec![Address::sample()]),
+            content: MessageContent {
+                body: String::new(),
+                attachments: Vec::new(),
+            },
+        };
+
+        for _ in 0..2 {
+            assert!(switch
+                .submit(message())
+                .unwrap_err()
+                .to_string()
+                .contains("refused"));
+        }
+        let open = switch.submit(message()).unwrap_err();
+        assert!(
+            open.to_string().contains("circuit open for gateway"),
+            "{open}"
+        );
+        assert_eq!(breakers.status()[0].state, BreakerState::Open);
+
+        switch.switch("ops", "mock").unwrap();
+        assert!(switch.submit(message()).is_ok());
+        assert_eq!(breakers.status()[1].target, "mock");
+        assert_eq!(breakers.status()[1].state, BreakerState::Closed);
+    }
+
+    #[test]
+    fn batches_are_stored_at_once_and_dispatched_through_the_active_mode() {
+        use crate::models::{Address, MessageContent, MessageEnvelope};
+
+        let store = StoreManager::new();
+        let queue = QueueManager::new().with_store(store.clone());
+        let breakers = CircuitBreakers::new(1, Duration::from_secs(60));
+        let switch = TransportSwitch::new(
+            "gateway",
+            Arc::new(Named::new("mock")),
+            P7Driver::new(Arc::new(UnloadedSdk), "ops"),
+            Arc::new(Down),
+        )
+        .with_breakers(breakers)
+        .with_outbox(store.clone(), queue.clone())
+        .with_batch_limit(2);
+        let submission = |deferred_until| BatchSubmission {
+            message: Message {
+                envelope: MessageEnvelope::new("Hello", Address::sample(), vec![Address::sample()]),
+                content: MessageContent {
+                    body: String::new(),
+                    attachments: Vec::new(),
+                },
+            },
+            deferred_until,
+        };
+
+        let results = switch
+            .submit_batch(vec![submission(None), submission(None)])
+            .unwrap();
+        assert!(results
+            .iter()
+            .all(|result| result.outcome == BatchOutcome::Accepted));
+        assert!(results
+            .iter()
+            .all(|result| store.get(&result.message_id).is_some()));
+        assert_eq!(queue.pending().len(), 2);
+        assert_eq!(
+            results[0].error.as_deref(),
+            Some("transport unavailable: connection refused")
+        );
+        let open = results[1].error.as_deref().unwrap();
+        assert!(open.contains("circuit open for gateway"), "{open}");
+        assert_eq!(
+            switch.submit_batch(vec![submission(None); 3]).unwrap_err(),
+            BatchError::TooLarge { size: 3, limit: 2 }
+        );
+
+        switch.switch("ops", "mock").unwrap();
+        let later = switch.clock.now() + chrono::Duration::hours(1);
+        let results = switch
+            .submit_batch(vec![submission(None), submission(Some(later))])
+            .unwrap();
+        assert_eq!(results[0].outcome, BatchOutcome::Accepted);
+        assert_eq!(results[0].error, None);
+        assert_eq!(queue.pending().len(), 2);
+        assert_eq!(results[1].outcome, BatchOutcome::Rejected);
+        assert!(store.get(&results[1].message_id).is_none());
+        assert_eq!(
+            results[1].error.as_deref(),
+            Some("the mock transport does not support scheduled delivery")
+        );
+    }
+}
@@ -0,0 +1,593 @@
+use std::collections::VecDeque;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::mpsc::{self, Receiver, RecvTimeoutError, SyncSender, TrySendError};
+use std::sync::{Arc, Mutex, PoisonError};
+use std::time::Duration;
+
+use chrono::{DateTime, Utc};
+use hmac::{Hmac, Mac};
+use serde::{Deserialize, Serialize};
+use sha2::Sha256;
+use tracing::warn;
+use uuid::Uuid;
+
+use crate::clock::{SharedClock, SharedIds};
+use crate::config::WebhookConfig;
+use crate::models::{Report, ReportKind, WebhookRecord};
+use crate::store::StoreManager;
+use crate::supervisor::Supervisor;
+
+const DELIVERY_LOG_CAPACITY: usize = 256;
+/// Reports waiting for the delivery worker, and retries waiting for their turn.
+const DELIVERY_QUEUE: usize = 1024;
+/// How long the worker waits for a report before checking retries.
+const POLL: Duration = Duration::from_millis(250);
+
+#[derive(Debug, thiserror::Error, PartialEq, Eq)]
+pub enum WebhookError {
+    #[error("webhook URL must use http or https: {0}")]
+    InvalidUrl(String),
+    #[error("webhook subscription {0} not found")]
+    NotFound(Uuid),
+}
+
+/// Subscriber registered through the `/webhooks` API. The signing secret
+/// stays in the store and is never returned.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct WebhookSubscription {
+    pub id: Uuid,
+    pub url: String,
+    /// Report types the subscriber wants; empty means all.
+    pub events: Vec<String>,
+    pub active: bool,
+    pub created_at: DateTime<Utc>,
+}
+
+impl From<WebhookRecord> for WebhookSubscription {
+    fn from(record: WebhookRecord) -> Self {
+        Self {
+            id: record.id,
+            url: record.url,
+            events: record.events,
+            active: record.active,
+            created_at: record.created_at,
+        }
+    }
+}
+
+/// Fields accepted when creating or replacing a subscription.
+#[derive(Clone, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct WebhookRequest {
+    pub url: String,
+    pub secret: String,
+    #[serde(default)]
+    pub events: Vec<String>,
+    #[serde(default = "default_active")]
+    pub active: bool,
+}
+
+fn default_active() -> bool {
+    true
+}
+
+/// Outcome of delivering one event to one subscriber.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct WebhookDelivery {
+    pub delivery_id: Uuid,
+    pub subscription: Uuid,
+    pub event: String,
+    pub attempts: u32,
+    pub status: Option<u16>,
+    pub error: Option<String>,
+    pub delivered: bool,
+}
+
+/// HTTP client abstraction so deliveries can be exercised without a network.
+pub trait WebhookTransport: Send + Sync {
+    /// POST `body` and return the response status, or a transport error.
+    fn post(&self, url: &str, headers: &[(&str, String)], body: &[u8]) -> Result<u16, String>;
+}
+
+/// Blocking transport backed by `ureq`.
+pub struct HttpTransport {
+    agent: ureq::Agent,
+}
+
+impl HttpTransport {
+    pub fn new(timeout: Duration) -> Self {
+        Self {
+            agent: ureq::AgentBuilder::new().timeout(timeout).build(),
+        }
+    }
+}
+
+impl WebhookTransport for HttpTransport {
+    fn post(&self, url: &str, headers: &[(&str, String)], body: &[u8]) -> Result<u16, String> {
+        let mut request = self.agent.post(url);
+        for (name, value) in headers {
+            request = request.set(name, value);
+        }
+        match request.send_bytes(body) {
+            Ok(response) => Ok(response.status()),
+            Err(ureq::Error::Status(status, _)) => Ok(status),
+            Err(ureq::Error::Transport(err)) => Err(err.to_string()),
+        }
+    }
+}
+
+/// A delivery waiting for its next attempt.
+struct PendingDelivery {
+    body: Vec<u8>,
+    outcome: WebhookDelivery,
+    due: DateTime<Utc>,
+}
+
+/// Registry of report subscribers and signed, retried delivery of report events.
+///
+/// Subscriptions live in the store's `webhooks` table. Reports are queued
+/// without blocking the caller and delivered by one supervised worker; failed
+/// attempts wait in a retry list instead of holding the worker up.
+#[derive(Clone)]
+pub struct WebhookManager {
+    config: WebhookConfig,
+    store: StoreManager,
+    outbox: SyncSender<Report>,
+    queued: Arc<Mutex<Receiver<Report>>>,
+    retries: Arc<Mutex<Vec<PendingDelivery>>>,
+    dropped: Arc<AtomicU64>,
+    deliveries: Arc<Mutex<VecDeque<WebhookDelivery>>>,
+    transport: Arc<dyn WebhookTransport>,
+    clock: SharedClock,
+    ids: SharedIds,
+}
+
+impl WebhookManager {
+    pub fn new(config: WebhookConfig) -> Self {
+        let transport = HttpTransport::new(Duration::from_millis(config.timeout_ms));
+        Self::with_transport(config, Arc::new(transport))
+    }
+
+    pub fn with_transport(config: WebhookConfig, transport: Arc<dyn WebhookTransport>) -> Self {
+        let (outbox, queued) = mpsc::sync_channel(DELIVERY_QUEUE);
+        Self {
+            config,
+            store: StoreManager::new(),
+            outbox,
+            queued: Arc::new(Mutex::new(queued)),
+            retries: Arc::new(Mutex::new(Vec::new())),
+            dropped: Arc::new(AtomicU64::new(0)),
+            deliveries: Arc::new(Mutex::new(VecDeque::new())),
+            transport,
+            clock: SharedClock::default(),
+            ids: SharedIds::default(),
+        }
+    }
+
+    /// Keep subscriptions in `store`'s `webhooks` table.
+    pub fn with_store(mut self, store: StoreManager) -> Self {
+        self.store = store;
+        self
+    }
+
+    pub fn with_clock(mut self, clock: SharedClock) -> Self {
+        self.clock = clock;
+        self
+    }
+
+    pub fn with_ids(mut self, ids: SharedIds) -> Self {
+        self.ids = ids;
+        self
+    }
+
+    pub fn create(&self, request: WebhookRequest) -> Result<WebhookSubscription, WebhookError> {
+        validate_url(&request.url)?;
+        let record = WebhookRecord {
+            id: self.ids.uuid(),
+            url: request.url,
+            secret: request.secret,
+            events: request.events,
+            active: request.active,
+            created_at: self.clock.now(),
+        };
+        self.store.put_webhook(record.clone());
+        Ok(record.into())
+    }
+
+    pub fn update(
+        &self,
+        id: Uuid,
+        request: WebhookRequest,
+    ) -> Result<WebhookSubscription, WebhookError> {
+        validate_url(&request.url)?;
+        self.store
+            .update_webhook(id, |record| {
+                record.url = request.url;
+                record.secret = request.secret;
+                record.events = request.events;
+                record.active = request.active;
+            })
+            .map(Into::into)
+            .ok_or(WebhookError::NotFound(id))
+    }
+
+    pub fn get(&self, id: Uuid) -> Option<WebhookSubscription> {
+        self.store.webhook(id).map(Into::into)
+    }
+
+    pub fn list(&self) -> Vec<WebhookSubscription> {
+        let mut items: Vec<WebhookSubscription> =
+            self.store.webhooks().into_iter().map(Into::into).collect();
+        items.sort_by_key(|item| item.created_at);
+        items
+    }
+
+    pub fn delete(&self, id: Uuid) -> bool {
+        self.store.remove_webhook(id).is_some()
+    }
+
+    /// Most recent delivery outcomes, oldest first.
+    pub fn deliveries(&self) -> Vec<WebhookDelivery> {
+        self.deliveries
+            .lock()
+            .map(|log| log.iter().cloned().collect())
+            .unwrap_or_default()
+    }
+
+    /// Reports dropped because the delivery queue was full.
+    pub fn dropped(&self) -> u64 {
+        self.dropped.load(Ordering::Relaxed)
+    }
+
+    /// Queue a report for the delivery worker without blocking; false if it
+    /// was dropped because the queue is full.
+    pub fn notify_async(&self, report: &Report) -> bool {
+        let event = event_name(report.kind);
+        if !self
+            .store
+            .webhooks()
+            .iter()
+            .any(|record| wants(record, event))
+        {
+            return true;
+        }
+        match self.outbox.try_send(report.clone()) {
+            Ok(()) => true,
+            Err(TrySendError::Full(_) | TrySendError::Disconnected(_)) => {
+                self.dropped.fetch_add(1, Ordering::Relaxed);
+                warn!(
+                    target = "webhooks",
+                    message = %report.message_id,
+                    "webhook queue full, report not delivered"
+                );
+                false
+            }
+        }
+    }
+
+    /// Deliver queued reports and due retries on the `webhook-delivery` worker.
+    pub fn spawn(&self, supervisor: &Supervisor) {
+        let manager = self.clone();
+        supervisor.spawn("webhook-delivery", move |context| {
+            while !context.should_stop() {
+                let next = manager
+                    .queued
+                    .lock()
+                    .unwrap_or_else(PoisonError::into_inner)
+                    .recv_timeout(POLL);
+                match next {
+                    Ok(report) => {
+                        manager.notify(&report);
+                    }
+                    Err(RecvTimeoutError::Timeout) => {}
+                    Err(RecvTimeoutError::Disconnected) => return,
+                }
+                manager.retry_due();
+                context.heartbeat();
+            }
+        });
+    }
+
+    /// Make the first attempt at delivering a report to every interested
+    /// subscriber; failures that may succeed later are scheduled for retry.
+    pub fn notify(&self, report: &Report) -> Vec<WebhookDelivery> {
+        let event = event_name(report.kind);
+        let body = serde_json::to_vec(&payload(event, report)).unwrap_or_default();
+        self.store
+            .webhooks()
+            .into_iter()
+            .filter(|record| wants(record, event))
+            .map(|record| {
+                let mut outcome = WebhookDelivery {
+                    delivery_id: self.ids.uuid(),
+                    subscription: record.id,
+                    event: event.to_string(),
+                    attempts: 0,
+                    status: None,
+                    error: None,
+                    delivered: false,
+                };
+                let retry = self.attempt(&record, &body, &mut outcome);
+                self.settle(body.clone(), outcome.clone(), retry);
+                outcome
+            })
+            .collect()
+    }
+
+    /// Attempt every retry whose back-off has elapsed. Retries for
+    /// subscriptions deleted or deactivated meanwhile are dropped.
+    pub fn retry_due(&self) -> Vec<WebhookDelivery> {
+        let now = self.clock.now();
+        let due: Vec<PendingDelivery> = match self.retries.lock() {
+            Ok(mut retries) => {
+                let (due, waiting) = retries.drain(..).partition(|retry| retry.due <= now);
+                *retries = waiting;
+                due
+            }
+            Err(_) => Vec::new(),
+        };
+        due.into_iter()
+            .filter_map(|mut retry| {
+                let record = self
+                    .store
+                    .webhook(retry.outcome.subscription)
+                    .filter(|record| record.active)?;
+                let again = self.attempt(&record, &retry.body, &mut retry.outcome);
+                self.settle(retry.body, retry.outcome.clone(), again);
+                Some(retry.outcome)
+            })
+            .collect()
+    }
+
+    /// POST once; true when the failure is worth another attempt.
+    fn attempt(&self, record: &WebhookRecord, body: &[u8], outcome: &mut WebhookDelivery) -> bool {
+        let headers = [
+            ("Content-Type", "application/json".to_string()),
+            ("X-X400-Event", outcome.event.clone()),
+            ("X-X400-Delivery", outcome.delivery_id.to_string()),
+            ("X-X400-Signature", sign(&record.secret, body)),
+        ];
+        outcome.attempts += 1;
+        match self.transport.post(&record.url, &headers, body) {
+            Ok(status) if (200..300).contains(&status) => {
+                outcome.status = Some(status);
+                outcome.error = None;
+                outcome.delivered = true;
+                false
+            }
+            Ok(status) => {
+                outcome.status = Some(status);
+                outcome.error = Some(format!("subscriber responded with HTTP {status}"));
+                // Client errors other than throttling will not succeed on retry.
+                !(400..500).contains(&status) || status == 429
+            }
+            Err(err) => {
+                outcome.status = None;
+                outcome.error = Some(err);
+                true
+            }
+        }
+    }
+
+    /// Schedule another attempt, or log the final outcome.
+    fn settle(&self, body: Vec<u8>, outcome: WebhookDelivery, retry: bool) {
+        if retry && outcome.attempts < self.config.max_attempts.max(1) {
+            if let Ok(mut retries) = self.retries.lock() {
+                if retries.len() < DELIVERY_QUEUE {
+                    let backoff = self.config.backoff_ms * u64::from(outcome.attempts);
+                    retries.push(PendingDelivery {
+                        body,
+                        due: self.clock.now()
+                            + chrono::Duration::milliseconds(
+                                backoff.try_into().unwrap_or(i64::MAX),
+                            ),
+                        outcome,
+                    });
+                    return;
+                }
+            }
+        }
+        if !outcome.delivered {
+            warn!(
+                target = "webhooks",
+                subscription = %outcome.subscription,
+                attempts = outcome.attempts,
+                "webhook delivery failed"
+            );
+        }
+        if let Ok(mut log) = self.deliveries.lock() {
+            if log.len() >= DELIVERY_LOG_CAPACITY {
+                log.pop_front();
+            }
+            log.push_back(outcome);
+        }
+    }
+}
+
+fn wants(record: &WebhookRecord, event: &str) -> bool {
+    record.active && (record.events.is_empty() || record.events.iter().any(|item| item == event))
+}
+
+fn validate_url(url: &str) -> Result<(), WebhookError> {
+    if url.starts_with("https://") || url.starts_with("http://") {
+        Ok(())
+    } else {
+        Err(WebhookError::InvalidUrl(url.to_string()))
+    }
+}
+
+fn event_name(kind: ReportKind) -> &'static str {
+    match kind {
+        ReportKind::Delivery => "report.delivery",
+        ReportKind::NonDelivery => "report.non_delivery",
+        ReportKind::Read => "report.read",
+    }
+}
+
+fn payload(event: &str, report: &Report) -> serde_json::Value {
+    serde_json::json!({
+        "event": event,
+        "messageId": report.message_id.to_string(),
+        "recipient": report.recipient.as_ref().map(|address| format!(
+            "C={};O={};S={}",
+            address.country, address.organization, address.surname
+        )),
+        "reason": report.reason,
+        "diagnostic": report.diagnostic,
+        "timestamp": report.timestamp.to_rfc3339(),
+    })
+}
+
+/// `sha256=<hex>` HMAC of the request body, verifiable by subscribers.
+pub fn sign(secret: &str, body: &[u8]) -> String {
+    let mut mac =
+        Hmac::<Sha256>::new_from_slice(secret.as_bytes()).expect("HMAC accepts any key length");
+    mac.update(body);
+    let digest = mac.finalize().into_bytes();
+    let hex: String = digest.iter().map(|byte| format!("{byte:02x}")).collect();
+    format!("sha256={hex}")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::models::MessageId;
+
+    type RecordedCall = (String, Vec<(String, String)>, Vec<u8>);
+
+    #[derive(Default)]
+    struct RecordingTransport {
+        statuses: Mutex<VecDeque<Result<u16, String>>>,
+        calls: Mutex<Vec<RecordedCall>>,
+    }
+
+    impl WebhookTransport for RecordingTransport {
+        fn post(&self, url: &str, headers: &[(&str, String)], body: &[u8]) -> Result<u16, String> {
+            self.calls.lock().unwrap().push((
+                url.to_string(),
+                headers
+                    .iter()
+                    .map(|(name, value)| (name.to_string(), value.clone()))
+                    .collect(),
+                body.to_vec(),
+            ));
+            self.statuses.lock().unwrap().pop_front().unwrap_or(Ok(200))
+        }
+    }
+
+    fn report(kind: ReportKind) -> Report {
+        Report {
+            message_id: MessageId("msg-7".into()),
+            kind,
+            recipient: None,
+            reason: None,
+            diagnostic: None,
+            timestamp: Utc::now(),
+        }
+    }
+
+    fn config() -> WebhookConfig {
+        WebhookConfig {
+            max_attempts: 3,
+            backoff_ms: 0,
+            timeout_ms: 1000,
+        }
+    }
+
+    #[test]
+    fn retries_and_signs_deliveries() {
+        let transport = Arc::new(RecordingTransport::default());
+        transport.statuses.lock().unwrap().extend([
+            Err("connection refused".to_string()),
+            Ok(503),
+            Ok(204),
+        ]);
+        let manager = WebhookManager::with_transport(config(), transport.clone());
+        manager
+            .create(WebhookRequest {
+                url: "https://hooks.example.com/x400".into(),
+                secret: "s3cret".into(),
+                events: vec![],
+                active: true,
+            })
+            .unwrap();
+
+        let outcomes = manager.notify(&report(ReportKind::NonDelivery));
+        assert_eq!(outcomes.len(), 1);
+        assert!(!outcomes[0].delivered);
+        assert_eq!(manager.retry_due()[0].status, Some(503));
+        let outcomes = manager.retry_due();
+        assert!(outcomes[0].delivered);
+        assert_eq!(outcomes[0].attempts, 3);
+        assert!(manager.retry_due().is_empty());
+        assert_eq!(manager.deliveries(), outcomes);
+
+        let calls = transport.calls.lock().unwrap();
+        let (_, headers, body) = &calls[2];
+        let signature = headers
+            .iter()
+            .find(|(name, _)| name == "X-X400-Signature")
+            .map(|(_, value)| value.clone())
+            .unwrap();
+        assert_eq!(signature, sign("s3cret", body));
+        let json: serde_json::Value = serde_json::from_slice(body).unwrap();
+        assert_eq!(json["event"], "report.non_delivery");
+    }
+
+    #[test]
+    fn keeps_subscriptions_in_the_store_table() {
+        let temp = tempfile::tempdir().unwrap();
+        let path = temp.path().join("core.webhooks.json");
+        let transport = Arc::new(RecordingTransport::default());
+        let manager = WebhookManager::with_transport(config(), transport.clone())
+            .with_store(StoreManager::new().with_webhook_table(&path));
+        let subscription = manager
+            .create(WebhookRequest {
+                url: "https://hooks.example.com/x400".into(),
+                secret: "s3cret".into(),
+                events: vec![],
+                active: true,
+            })
+            .unwrap();
+        assert!(!serde_json::to_string(&subscription)
+            .unwrap()
+            .contains("s3cret"));
+
+        let reopened = WebhookManager::with_transport(config(), transport.clone())
+            .with_store(StoreManager::new().with_webhook_table(&path));
+        assert_eq!(reopened.list(), vec![subscription]);
+        assert!(reopened.notify_async(&report(ReportKind::Delivery)));
+        let queued = reopened.queued.lock().unwrap().try_recv().unwrap();
+        reopened.notify(&queued);
+        let calls = transport.calls.lock().unwrap();
+        let (_, headers, body) = &calls[0];
+        assert!(headers.contains(&("X-X400-Signature".into(), sign("s3cret", body))));
+    }
+
+    #[test]
+    fn filters_by_event_and_validates_urls() {
+        let transport = Arc::new(RecordingTransport::default());
+        let manager = WebhookManager::with_transport(config(), transport.clone());
+        assert_eq!(
+            manager
+                .create(WebhookRequest {
+                    url: "ftp://example.com".into(),
+                    ..Default::default()
+                })
+                .unwrap_err(),
+            WebhookError::InvalidUrl("ftp://example.com".into())
+        );
+        let subscription = manager
+            .create(WebhookRequest {
+                url: "http://localhost/read".into(),
+                secret: "k".into(),
+                events: vec!["report.read".into()],
+                active: true,
+            })
+            .unwrap();
+
+        assert!(manager.notify(&report(ReportKind::Delivery)).is_empty());
+        assert_eq!(manager.notify(&report(ReportKind::Read)).len(), 1);
+        assert!(manager.delete(subscription.id));
+        assert!(manager.list().is_empty());
+    }
+}
@@ -0,0 +1,382 @@
+//! WebSocket framing (RFC 6455) for the real-time channel.
+//!
+//! A client whose first line is an HTTP `GET` for [`PATH`] is upgraded by
+//! [`accept`] instead of speaking the line protocol, and then exchanges the
+//! same JSON commands and frames as text messages. [`MessageReader`] turns
+//! incoming text messages back into lines, so [`crate::ipc`] handles both
+//! kinds of client alike; it answers pings and close frames itself. Newlines
+//! inside a message are read as spaces, which keeps pretty-printed commands
+//! on one line. Binary messages are refused with close code 1003.
+
+use std::io::{self, BufRead, Read, Write};
+use std::sync::{Arc, Mutex};
+
+use base64::engine::general_purpose::STANDARD;
+use base64::Engine as _;
+use ring::digest;
+
+/// Request path the upgrade is served on.
+pub const PATH: &str = "/ws";
+
+/// Appended to `Sec-WebSocket-Key` before hashing, see RFC 6455 section 4.2.2.
+const ACCEPT_GUID: &str = "258EAFA5-E914-47DA-95CA-C5AB0DC85B11";
+
+/// Longest request line or header of the upgrade request.
+const MAX_HEADER_LINE: u64 = 8 * 1024;
+
+/// Headers read before the upgrade request is refused.
+const MAX_HEADERS: usize = 64;
+
+const OP_CONTINUATION: u8 = 0x0;
+const OP_TEXT: u8 = 0x1;
+const OP_CLOSE: u8 = 0x8;
+const OP_PING: u8 = 0x9;
+const OP_PONG: u8 = 0xA;
+
+/// Close code for a message type the channel does not accept.
+const CLOSE_UNSUPPORTED: u16 = 1003;
+
+/// Outgoing half of a connection, shared by the connection's writer and the
+/// [`MessageReader`] answering control frames.
+pub type SharedWriter = Arc<Mutex<Box<dyn Write + Send>>>;
+
+/// Whether `first` opens an HTTP request rather than a JSON command.
+pub fn is_upgrade(first: &[u8]) -> bool {
+    first.first() == Some(&b'G')
+}
+
+/// Read the upgrade request from `reader` and switch protocols. A request
+/// for another path or without the WebSocket headers is answered with 404
+/// or 400 and returned as an error.
+pub fn accept(reader: &mut impl BufRead, writer: &mut impl Write) -> io::Result<()> {
+    let request_line = read_header_line(reader)?;
+    let mut parts = request_line.split_whitespace();
+    let (method, target) = (parts.next(), parts.next());
+    let mut key = None;
+    let mut upgrade = false;
+    let mut version = None;
+    let mut ended = false;
+    for _ in 0..MAX_HEADERS {
+        let line = read_header_line(reader)?;
+        if line.is_empty() {
+            ended = true;
+            break;
+        }
+        let Some((name, value)) = line.split_once(':') else {
+            continue;
+        };
+        let value = value.trim();
+        match name.trim().to_ascii_lowercase().as_str() {
+            "upgrade" => upgrade = value.eq_ignore_ascii_case("websocket"),
+            "sec-websocket-key" => key = Some(value.to_string()),
+            "sec-websocket-version" => version = Some(value.to_string()),
+            _ => {}
+        }
+    }
+    if method != Some("GET") || target.map(|target| target.split('?').next()) != Some(Some(PATH)) {
+        return refuse(writer, "404 Not Found", "no such endpoint");
+    }
+    let key = match key {
+        Some(key) if ended && upgrade && version.as_deref() == Some("13") => key,
+        _ => return refuse(writer, "400 Bad Request", "not a WebSocket upgrade"),
+    };
+    write!(
+        writer,
+        "HTTP/1.1 101 Switching Protocols\r\nUpgrade: websocket\r\nConnection: Upgrade\r\nSec-WebSocket-Accept: {}\r\n\r\n",
+        accept_key(&key)
+    )?;
+    writer.flush()
+}
+
+fn read_header_line(reader: &mut impl BufRead) -> io::Result<String> {
+    let mut line = String::new();
+    let read = reader.take(MAX_HEADER_LINE).read_line(&mut line)?;
+    if read == 0 || !line.ends_with('\n') {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            "incomplete upgrade request",
+        ));
+    }
+    Ok(line.trim_end().to_string())
+}
+
+fn refuse(writer: &mut impl Write, status: &str, reason: &str) -> io::Result<()> {
+    write!(
+        writer,
+        "HTTP/1.1 {status}\r\nContent-Length: 0\r\nConnection: close\r\n\r\n"
+    )?;
+    writer.flush()?;
+    Err(io::Error::new(io::ErrorKind::InvalidData, reason))
+}
+
+/// `Sec-WebSocket-Accept` answering a client's `Sec-WebSocket-Key`.
+pub fn accept_key(key: &str) -> String {
+    let digest = digest::digest(
+        &digest::SHA1_FOR_LEGACY_USE_ONLY,
+        format!("{key}{ACCEPT_GUID}").as_bytes(),
+    );
+    STANDARD.encode(digest.as_ref())
+}
+
+/// Write `text` as one unmasked text message.
+pub fn write_text(writer: &mut (impl Write + ?Sized), text: &str) -> io::Result<()> {
+    write_frame(writer, OP_TEXT, text.as_bytes())
+}
+
+/// Write a close frame carrying `code`.
+pub fn write_close(writer: &mut (impl Write + ?Sized), code: u16) -> io::Result<()> {
+    write_frame(writer, OP_CLOSE, &code.to_be_bytes())
+}
+
+fn write_frame(writer: &mut (impl Write + ?Sized), opcode: u8, payload: &[u8]) -> io::Result<()> {
+    let mut head = vec![0x80 | opcode];
+    match payload.len() {
+        len @ 0..=125 => head.push(len as u8),
+        len @ 126..=0xFFFF => {
+            head.push(126);
+            head.extend_from_slice(&(len as u16).to_be_bytes());
+        }
+        len => {
+            head.push(127);
+            head.extend_from_slice(&(len as u64).to_be_bytes());
+        }
+    }
+    writer.write_all(&head)?;
+    writer.write_all(payload)?;
+    writer.flush()
+}
+
+fn lock(writer: &SharedWriter) -> io::Result<std::sync::MutexGuard<'_, Box<dyn Write + Send>>> {
+    writer
+        .lock()
+        .map_err(|_| io::Error::other("connection writer lock poisoned"))
+}
+
+/// Text messages from a client, each followed by a newline. Payloads are
+/// unmasked as they are read, so a message is never held in memory whole.
+pub struct MessageReader<R> {
+    reader: R,
+    writer: SharedWriter,
+    /// Payload bytes of the current data frame not read yet.
+    remaining: u64,
+    mask: [u8; 4],
+    offset: usize,
+    /// The current data frame ends its message.
+    last_fragment: bool,
+    in_message: bool,
+    closed: bool,
+}
+
+impl<R: Read> MessageReader<R> {
+    pub fn new(reader: R, writer: SharedWriter) -> Self {
+        Self {
+            reader,
+            writer,
+            remaining: 0,
+            mask: [0; 4],
+            offset: 0,
+            last_fragment: false,
+            in_message: false,
+            closed: false,
+        }
+    }
+
+    /// Read the next frame header, answering control frames on the way.
+    fn next_frame(&mut self) -> io::Result<()> {
+        let mut head = [0u8; 2];
+        match self.reader.read_exact(&mut head) {
+            Err(err) if err.kind() == io::ErrorKind::UnexpectedEof && !self.in_message => {
+                self.closed = true;
+                return Ok(());
+            }
+            result => result?,
+        }
+        let last = head[0] & 0x80 != 0;
+        let opcode = head[0] & 0x0F;
+        if head[1] & 0x80 == 0 {
+            return Err(protocol_error("client frames must be masked"));
+        }
+        let len = match head[1] & 0x7F {
+            126 => {
+                let mut len = [0u8; 2];
+                self.reader.read_exact(&mut len)?;
+                u64::from(u16::from_be_bytes(len))
+            }
+            127 => {
+                let mut len = [0u8; 8];
+                self.reader.read_exact(&mut len)?;
+                u64::from_be_bytes(len)
+            }
+            len => u64::from(len),
+        };
+        let mut mask = [0u8; 4];
+        self.reader.read_exact(&mut mask)?;
+
+        match opcode {
+            OP_TEXT if !self.in_message => self.in_message = true,
+            OP_CONTINUATION if self.in_message => {}
+            OP_CLOSE | OP_PING | OP_PONG => {
+                if !last || len > 125 {
+                    return Err(protocol_error("oversized or fragmented control frame"));
+                }
+                let mut payload = vec![0u8; len as usize];
+                self.reader.read_exact(&mut payload)?;
+                for (index, byte) in payload.iter_mut().enumerate() {
+                    *byte ^= mask[index % 4];
+                }
+                match opcode {
+                    OP_PING => write_frame(&mut **lock(&self.writer)?, OP_PONG, &payload)?,
+                    OP_CLOSE => {
+                        let echoed = payload.get(..2).unwrap_or_default();
+                        write_frame(&mut **lock(&self.writer)?, OP_CLOSE, echoed)?;
+                        self.closed = true;
+                    }
+                    _ => {}
+                }
+                return Ok(());
+            }
+            _ => {
+                let _ = write_close(&mut **lock(&self.writer)?, CLOSE_UNSUPPORTED);
+                return Err(protocol_error("only text messages are accepted"));
+            }
+        }
+        self.remaining = len;
+        self.mask = mask;
+        self.offset = 0;
+        self.last_fragment = last;
+        Ok(())
+    }
+}
+
+impl<R: Read> Read for MessageReader<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        if buf.is_empty() {
+            return Ok(0);
+        }
+        loop {
+            if self.closed {
+                return Ok(0);
+            }
+            if self.remaining > 0 {
+                let want = buf
+                    .len()
+                    .min(usize::try_from(self.remaining).unwrap_or(usize::MAX));
+                let read = self.reader.read(&mut buf[..want])?;
+                if read == 0 {
+                    return Err(io::ErrorKind::UnexpectedEof.into());
+                }
+                for byte in &mut buf[..read] {
+                    *byte ^= self.mask[self.offset % 4];
+                    self.offset += 1;
+                    if *byte == b'\n' {
+                        *byte = b' ';
+                    }
+                }
+                self.remaining -= read as u64;
+                return Ok(read);
+            }
+            if self.in_message && self.last_fragment {
+                self.in_message = false;
+                buf[0] = b'\n';
+                return Ok(1);
+            }
+            self.next_frame()?;
+        }
+    }
+}
+
+fn protocol_error(reason: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, reason.to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::{BufReader, Cursor};
+
+    /// Bytes the reader wrote back, readable after it has been handed over.
+    #[derive(Clone, Default)]
+    struct Sink(Arc<Mutex<Vec<u8>>>);
+
+    impl Write for Sink {
+        fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+            self.0.lock().unwrap().extend_from_slice(buf);
+            Ok(buf.len())
+        }
+
+        fn flush(&mut self) -> io::Result<()> {
+            Ok(())
+        }
+    }
+
+    fn masked(opcode: u8, last: bool, payload: &[u8]) -> Vec<u8> {
+        let mask = [0x37, 0xFA, 0x21, 0x3D];
+        let mut frame = vec![
+            if last { 0x80 } else { 0 } | opcode,
+            0x80 | payload.len() as u8,
+        ];
+        frame.extend_from_slice(&mask);
+        frame.extend(
+            payload
+                .iter()
+                .enumerate()
+                .map(|(i, byte)| byte ^ mask[i % 4]),
+        );
+        frame
+    }
+
+    #[test]
+    fn answers_the_rfc_sample_handshake() {
+        assert_eq!(
+            accept_key("dGhlIHNhbXBsZSBub25jZQ=="),
+            "s3pPLMBiTxaQ9kYGzzhZRbK+xOo="
+        );
+        let request = "GET /ws HTTP/1.1\r\nHost: localhost\r\nUpgrade: websocket\r\nConnection: Upgrade\r\nSec-WebSocket-Key: dGhlIHNhbXBsZSBub25jZQ==\r\nSec-WebSocket-Version: 13\r\n\r\n";
+        let mut reply = Vec::new();
+        accept(&mut Cursor::new(request), &mut reply).unwrap();
+        let reply = String::from_utf8(reply).unwrap();
+        assert!(reply.starts_with("HTTP/1.1 101"), "{reply}");
+        assert!(reply.contains("Sec-WebSocket-Accept: s3pPLMBiTxaQ9kYGzzhZRbK+xOo=\r\n"));
+
+        let mut reply = Vec::new();
+        let request = request.replace("/ws", "/other");
+        assert!(accept(&mut Cursor::new(request), &mut reply).is_err());
+        assert!(String::from_utf8(reply)
+            .unwrap()
+            .starts_with("HTTP/1.1 404"));
+    }
+
+    #[test]
+    fn reads_fragmented_text_as_lines_and_answers_pings() {
+        let mut input = masked(OP_TEXT, false, b"{\"type\":");
+        input.extend(masked(OP_PING, true, b"hi"));
+        input.extend(masked(OP_CONTINUATION, true, b"\n\"ping\"}"));
+        input.extend(masked(OP_TEXT, true, b"{}"));
+        input.extend(masked(OP_CLOSE, true, &1000u16.to_be_bytes()));
+        let sent = Sink::default();
+        let writer: SharedWriter = Arc::new(Mutex::new(Box::new(sent.clone())));
+        let mut reader = BufReader::new(MessageReader::new(Cursor::new(input), writer));
+
+        let mut lines = Vec::new();
+        let mut line = String::new();
+        while reader.read_line(&mut line).unwrap() > 0 {
+            lines.push(std::mem::take(&mut line));
+        }
+        assert_eq!(lines, ["{\"type\": \"ping\"}\n", "{}\n"]);
+        assert_eq!(
+            *sent.0.lock().unwrap(),
+            [0x8A, 2, b'h', b'i', 0x88, 2, 0x03, 0xE8]
+        );
+    }
+
+    #[test]
+    fn refuses_unmasked_and_binary_frames() {
+        let sent: SharedWriter = Arc::new(Mutex::new(Box::new(Vec::new())));
+        let mut unmasked = MessageReader::new(Cursor::new(vec![0x81, 0x00]), sent.clone());
+        assert!(unmasked.read(&mut [0u8; 8]).is_err());
+
+        let binary = masked(0x2, true, b"\x00\x01");
+        let mut reader = MessageReader::new(Cursor::new(binary), sent);
+        assert!(reader.read(&mut [0u8; 8]).is_err());
+    }
+}
@@ -1,4 +1,5 @@
-use core_service::config::{AppConfig, ConfigError};
+use core_service::auth::Role;
+use core_service::config::{AppConfig, ConfigError, ConfigPreset};
 use std::fs;
 use std::path::Path;
 use std::sync::{Mutex, MutexGuard, OnceLock};
@@ -65,6 +66,17 @@ fn rejects_invalid_configuration() {
     assert_eq!(err, ConfigError::InvalidFormat);
 }
 
+#[test]
+fn rejects_unknown_charset_fallback() {
+    let mut config = AppConfig::default();
+    config.migration.charset_fallback = "klingon".into();
+    assert!(
+        matches!(config.validate(), Err(ConfigError::Invalid(message)) if message.contains("charsetFallback"))
+    );
+    config.migration.charset_fallback = "cp437".into();
+    assert!(config.validate().is_ok());
+}
+
 #[test]
 fn missing_file_returns_error() {
     let _guard = env_guard();
@@ -73,3 +85,74 @@ fn missing_file_returns_error() {
     std::env::remove_var("CORE_CONFIG");
     assert_eq!(err, ConfigError::MissingFile);
 }
+
+#[test]
+fn preset_selected_from_environment() {
+    let _guard = env_guard();
+    std::env::remove_var("CORE_CONFIG");
+    std::env::set_var("X400_PRESET", "central-server");
+    let config = AppConfig::load();
+    std::env::set_var("X400_PRESET", "mainframe");
+    let unknown = AppConfig::load().unwrap_err();
+    std::env::remove_var("X400_PRESET");
+
+    let config = config.expect("preset loads");
+    assert_eq!(config.server.host, "0.0.0.0");
+    assert_eq!(config.migration.parallelism, 8);
+    assert!(config.telemetry.enabled);
+    assert_eq!(unknown, ConfigError::UnknownPreset("mainframe".into()));
+}
+
+#[test]
+fn materialized_preset_round_trips() {
+    let _guard = env_guard();
+    let temp = tempfile::tempdir().unwrap();
+    let path = temp.path().join("config/core.cfg");
+    let written =
+        AppConfig::materialize(ConfigPreset::GatewayBridge, &path).expect("preset written");
+    let contents = fs::read_to_string(&path).unwrap();
+    assert!(contents.contains("gateway.smtp.passwordRef=keychain:x400-core/smtp"));
+
+    std::env::set_var("CORE_CONFIG", &path);
+    let loaded = AppConfig::load().expect("materialized file loads");
+    std::env::remove_var("CORE_CONFIG");
+    assert_eq!(loaded, written);
+    assert!(loaded.validate().is_ok());
+}
+
+#[test]
+fn named_api_keys_with_scopes() {
+    let _guard = env_guard();
+    let path = Path::new("/tmp/core-config-keys.cfg");
+    temp_file(
+        path,
+        "security.requireAuth=true\nsecurity.keys.monitoring=mon-secret\nsecurity.scopes.monitoring=messages:read,trace:*\nsecurity.keys.ops=ops-secret\nsecurity.roles.ops=operator,migration-admin\n",
+    );
+    std::env::set_var("CORE_CONFIG", path);
+    let config = AppConfig::load().expect("configuration loads");
+    std::env::remove_var("CORE_CONFIG");
+    assert!(config.security.require_auth);
+    assert_eq!(config.security.keys.len(), 2);
+    assert_eq!(config.security.keys[0].name, "monitoring");
+    assert_eq!(
+        config.security.keys[0].scopes,
+        vec!["messages:read".to_string(), "trace:*".to_string()]
+    );
+    assert!(config.security.keys[1].scopes.is_empty());
+    assert_eq!(
+        config.security.keys[1].roles,
+        vec![Role::Operator, Role::MigrationAdmin]
+    );
+    assert!(config.validate().is_ok());
+}
+
+#[test]
+fn rejects_named_pipe_socket_paths() {
+    let mut config = AppConfig::default();
+    config.server.socket_path = Some(r"\\.\pipe\x400-core".into());
+    assert!(
+        matches!(config.validate(), Err(ConfigError::Invalid(message)) if message.contains("named pipe"))
+    );
+    config.server.socket_path = Some("/run/x400/core.sock".into());
+    assert!(config.validate().is_ok());
+}
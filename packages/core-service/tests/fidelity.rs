@@ -0,0 +1,29 @@
+use std::env;
+use std::path::Path;
+
+use core_service::config::AppConfig;
+use core_service::AppState;
+
+/// Runs the reference corpus in `tests/corpus` through the submit and gateway
+/// paths. Set `FIDELITY_BLESS=1` to regenerate the goldens after an intended
+/// change, then review the diff.
+#[test]
+fn reference_corpus_matches_goldens() {
+    let data = tempfile::tempdir().unwrap();
+    let mut config = AppConfig::default();
+    config.database.path = data.path().join("core.db").to_string_lossy().into_owned();
+    let state = AppState::new(config);
+
+    let corpus = Path::new(env!("CARGO_MANIFEST_DIR")).join("tests/corpus");
+    let bless = env::var_os("FIDELITY_BLESS").is_some();
+    let report = state.fidelity.run(&corpus, bless).expect("corpus runs");
+
+    assert_eq!(report.cases, 5);
+    assert_eq!(report.checks, 10);
+    assert!(
+        report.is_clean(),
+        "fidelity regressions: {:#?}",
+        report.regressions
+    );
+    assert_eq!(state.fidelity.report(report.job_id), Some(report));
+}
@@ -0,0 +1,82 @@
+use std::io::{BufRead, BufReader, Write};
+use std::net::TcpStream;
+
+use core_service::config::GatewaySecurityConfig;
+use core_service::gateway::{GatewayImapClient, LoopbackGateway};
+
+fn expect(reader: &mut impl BufRead, code: &str) {
+    loop {
+        let mut line = String::new();
+        reader.read_line(&mut line).expect("reply");
+        assert!(line.starts_with(code), "expected {code}, got {line}");
+        if line.as_bytes().get(3) != Some(&b'-') {
+            return;
+        }
+    }
+}
+
+#[test]
+fn loopback_accepts_smtp_and_serves_injected_mail() {
+    let gateway = LoopbackGateway::start().expect("loopback starts");
+    let stream = TcpStream::connect(gateway.address()).expect("connect");
+    let mut writer = stream.try_clone().unwrap();
+    let mut reader = BufReader::new(stream);
+
+    expect(&mut reader, "220");
+    for (command, code) in [
+        ("EHLO tests", "250"),
+        ("MAIL FROM:<operator@modern.de.example>", "250"),
+        ("RCPT TO:<user@example.com>", "250"),
+        ("DATA", "354"),
+    ] {
+        writer
+            .write_all(format!("{command}\r\n").as_bytes())
+            .unwrap();
+        expect(&mut reader, code);
+    }
+    writer
+        .write_all(b"Subject: Loopback\r\n\r\n..leading dot\r\n.\r\n")
+        .unwrap();
+    expect(&mut reader, "250");
+    writer.write_all(b"QUIT\r\n").unwrap();
+    expect(&mut reader, "221");
+
+    let received = gateway.received();
+    assert_eq!(received.len(), 1);
+    assert_eq!(received[0].from, "operator@modern.de.example");
+    assert_eq!(received[0].to, vec!["user@example.com".to_string()]);
+    assert!(received[0].data.contains("\n.leading dot\n"));
+    assert_eq!(gateway.smtp_config().port, gateway.address().port());
+
+    let uid =
+        gateway.inject("Subject: Inbound\r\nFrom: Partner <partner@example.com>\r\n\r\nHello");
+    let client = gateway.imap_client().expect("plain-text loopback client");
+    let fetched = client.fetch(10).unwrap();
+    assert_eq!(fetched.len(), 1);
+    assert_eq!(fetched[0].uid, uid.to_string());
+    assert_eq!(fetched[0].subject, "Inbound");
+    assert_eq!(fetched[0].from, "partner@example.com");
+    // The client flagged the message `\Seen`, so it is not fetched twice.
+    assert!(client.fetch(10).unwrap().is_empty());
+    assert_eq!(gateway.folder(LoopbackGateway::INBOX), vec![uid]);
+}
+
+#[test]
+fn loopback_imap_moves_fetched_mail_out_of_the_inbox() {
+    let gateway = LoopbackGateway::start().expect("loopback starts");
+    let first = gateway.inject("Subject: One\r\n\r\nFirst");
+    let second = gateway.inject("Subject: Two\r\n\r\nSecond");
+
+    let mut config = gateway.imap_config();
+    config.move_to = Some("Processed".into());
+    let security = GatewaySecurityConfig {
+        enforce_tls: false,
+        ..Default::default()
+    };
+    let client = GatewayImapClient::from_config(config, &security).unwrap();
+
+    let fetched = client.fetch(10).unwrap();
+    assert_eq!(fetched.len(), 2);
+    assert!(gateway.folder(LoopbackGateway::INBOX).is_empty());
+    assert_eq!(gateway.folder("Processed"), vec![first, second]);
+}
@@ -1,9 +1,16 @@
+use std::time::Duration;
+
 use chrono::Utc;
 use core_service::config::AppConfig;
-use core_service::mock_provider::MockDeliveryProvider;
-use core_service::models::{Address, Message, MessageContent, MessageEnvelope, MessageStatus};
+use core_service::dlp::{DlpAction, DlpEngine, DlpError, DlpMatcher, DlpRule};
+use core_service::mock_provider::{MockDeliveryProvider, MockReportRequest};
+use core_service::models::{
+    Address, Message, MessageAttachment, MessageContent, MessageEnvelope, MessageId, MessageStatus,
+    ReportKind,
+};
 use core_service::queue::QueueManager;
 use core_service::store::StoreManager;
+use core_service::submit::{Screened, SubmitError, SubmitPolicy};
 use core_service::support::{SupportMetadata, SupportStorage};
 use core_service::trace::TraceManager;
 
@@ -30,6 +37,7 @@ fn compose_and_fetch_message_flow() {
         envelope,
         content: MessageContent {
             body: "Hello from tests".into(),
+            attachments: Vec::new(),
         },
     };
 
@@ -39,12 +47,18 @@ fn compose_and_fetch_message_flow() {
     assert_eq!(listed.len(), 1);
     assert_eq!(listed[0].envelope.subject, "Integration message");
 
-    let pending = queue.pending();
-    assert_eq!(pending.len(), 1);
-    assert_eq!(pending[0], message_id);
-
     let stored = store.get(&message_id).expect("message stored");
     assert_eq!(stored.envelope.status, MessageStatus::Read);
+    assert!(
+        queue.pending().is_empty(),
+        "a delivered message leaves the queue"
+    );
+
+    let detail = store.detail(&message_id).expect("message detail");
+    assert_eq!(detail.reports.len(), 2);
+    assert_eq!(detail.reports[0].kind, ReportKind::Delivery);
+    assert_eq!(detail.reports[1].kind, ReportKind::Read);
+    assert_eq!(detail.reports[1].recipient, Some(Address::sample()));
 
     let removed = store.delete(&message_id);
     assert!(removed);
@@ -74,6 +88,7 @@ fn seeding_demo_data_populates_store_and_queue() {
         envelope,
         content: MessageContent {
             body: "trace".into(),
+            attachments: Vec::new(),
         },
     });
 
@@ -92,6 +107,7 @@ fn support_storage_persists_trace_bundle() {
         channel: "ui".into(),
         created_at: Utc::now(),
         notes: Some("integration".into()),
+        upload: None,
     };
     let bundle = vec![1, 2, 3, 4];
     let path = storage.store(&bundle, &metadata).expect("stored");
@@ -99,3 +115,1000 @@ fn support_storage_persists_trace_bundle() {
     let siblings = storage.list().expect("list");
     assert_eq!(siblings.len(), 1);
 }
+
+#[test]
+fn dlp_policy_quarantines_and_blocks_outbound_messages() {
+    let (queue, store, trace) = build_state();
+    let engine = DlpEngine::new(vec![
+        DlpRule::new(
+            "payroll",
+            DlpMatcher::keyword("payroll"),
+            DlpAction::Quarantine,
+        ),
+        DlpRule::new(
+            "secret",
+            DlpMatcher::Marker("[SECRET]".into()),
+            DlpAction::Block,
+        ),
+    ]);
+    let policy = SubmitPolicy::new(trace.clone()).with_dlp(engine);
+
+    let mut held = Message {
+        envelope: MessageEnvelope::new("Payroll run", Address::sample(), vec![Address::sample()]),
+        content: MessageContent {
+            body: "numbers attached".into(),
+            attachments: Vec::new(),
+        },
+    };
+    assert_eq!(policy.screen(&mut held), Ok(Screened::Quarantine));
+    let held_id = policy.quarantine(&store, held);
+    assert_eq!(store.list("quarantine").len(), 1);
+    assert!(queue.pending().is_empty());
+
+    let mut blocked = Message {
+        envelope: MessageEnvelope::new(
+            "[SECRET] plans",
+            Address::sample(),
+            vec![Address::sample()],
+        ),
+        content: MessageContent {
+            body: "payroll".into(),
+            attachments: Vec::new(),
+        },
+    };
+    let blocked_id = blocked.envelope.id.clone();
+    let err = policy.screen(&mut blocked).unwrap_err();
+    assert_eq!(err, SubmitError::Dlp(DlpError::Blocked("secret".into())));
+    assert!(store.get(&blocked_id).is_none());
+
+    let bundle = trace.bundle();
+    assert!(bundle
+        .iter()
+        .any(|entry| entry.event == "dlp.quarantined" && entry.message == held_id));
+    assert!(bundle
+        .iter()
+        .any(|entry| entry.event == "dlp.match:secret" && entry.message == blocked_id));
+}
+
+#[test]
+fn simulated_reports_flow_through_ingestion() {
+    use core_service::clock::{ManualClock, SharedClock};
+    use core_service::reports::ReportError;
+    use core_service::transport::MessageTransport;
+
+    let clock = ManualClock::new(Utc::now());
+    let (queue, store, trace) = build_state();
+    let provider = MockDeliveryProvider::new(queue, store.clone(), trace.clone())
+        .with_clock(SharedClock::new(clock.clone()));
+    let envelope = MessageEnvelope::new("Report me", Address::sample(), vec![Address::sample()]);
+    let id = envelope.id.clone();
+    store.save(Message {
+        envelope,
+        content: MessageContent {
+            body: "report".into(),
+            attachments: Vec::new(),
+        },
+    });
+
+    let report = provider
+        .simulate_report(MockReportRequest {
+            message_id: id.clone(),
+            kind: ReportKind::NonDelivery,
+            recipient: None,
+            reason: None,
+            diagnostic: Some("recipient-unavailable".into()),
+            delay: Duration::ZERO,
+        })
+        .expect("report generated");
+    assert_eq!(report.reason.as_deref(), Some("unable-to-transfer"));
+    assert_eq!(
+        store.get(&id).unwrap().envelope.status,
+        MessageStatus::Failed
+    );
+    assert_eq!(store.reports(&id).len(), 1);
+
+    provider
+        .simulate_report(MockReportRequest {
+            message_id: id.clone(),
+            kind: ReportKind::Read,
+            recipient: None,
+            reason: None,
+            diagnostic: None,
+            delay: Duration::from_millis(20),
+        })
+        .expect("delayed report");
+    assert_eq!(provider.release_due_reports(), 0);
+    clock.advance(chrono::Duration::milliseconds(30));
+    assert_eq!(provider.release_due_reports(), 1);
+    assert_eq!(store.get(&id).unwrap().envelope.status, MessageStatus::Read);
+    assert!(trace
+        .bundle()
+        .iter()
+        .any(|entry| entry.event == "report.read" && entry.message == id));
+
+    let unknown = provider.simulate_report(MockReportRequest {
+        message_id: MessageId::new(),
+        kind: ReportKind::Delivery,
+        recipient: None,
+        reason: None,
+        diagnostic: None,
+        delay: Duration::ZERO,
+    });
+    assert!(unknown.is_err());
+
+    provider.activated("gateway");
+    let inactive = provider.simulate_report(MockReportRequest {
+        message_id: id,
+        kind: ReportKind::Delivery,
+        recipient: None,
+        reason: None,
+        diagnostic: None,
+        delay: Duration::ZERO,
+    });
+    assert_eq!(inactive.unwrap_err(), ReportError::MockInactive("gateway"));
+}
+
+#[test]
+fn folder_stats_follow_store_writes() {
+    let store = StoreManager::new();
+    let ids = store.seed_demo_data();
+    let mut failed = MessageEnvelope::new("Failed", Address::sample(), vec![Address::sample()]);
+    failed.status = MessageStatus::Failed;
+    store.save(Message {
+        envelope: failed,
+        content: MessageContent {
+            body: "boom".into(),
+            attachments: vec![MessageAttachment {
+                id: "0".repeat(64),
+                filename: "trace.bin".into(),
+                mime_type: "application/octet-stream".into(),
+                size: 2048,
+            }],
+        },
+    });
+
+    let stats = store.folder_stats();
+    assert_eq!(stats.len(), 2);
+    let inbox = stats.iter().find(|s| s.folder == "inbox").unwrap();
+    assert_eq!(inbox.total, 3);
+    assert_eq!(inbox.unread, 3);
+    assert!(inbox.oldest_queued_age_seconds.is_some());
+    let outbox = stats.iter().find(|s| s.folder == "outbox").unwrap();
+    assert_eq!(outbox.failed, 1);
+    assert_eq!(
+        outbox.total_bytes,
+        ("Failed".len() + "boom".len()) as u64 + 2048
+    );
+
+    store.update_status(&ids[0], MessageStatus::Read);
+    let inbox = store
+        .folder_stats()
+        .into_iter()
+        .find(|s| s.folder == "inbox")
+        .unwrap();
+    assert_eq!(inbox.unread, 2);
+
+    // Reading a message through its flags counts the same in both views.
+    store.update_flags(&ids[1], |flags| flags.read = true);
+    let inbox = store
+        .folder_stats()
+        .into_iter()
+        .find(|s| s.folder == "inbox")
+        .unwrap();
+    assert_eq!(inbox.unread, 1);
+    assert_eq!(store.flag_counts()["inbox"].unread, 1);
+
+    store.delete(&ids[1]);
+    assert_eq!(store.folder_stats()[0].total, 2);
+}
+
+#[test]
+fn injected_clock_and_ids_make_state_deterministic() {
+    use chrono::TimeZone;
+    use core_service::clock::{ManualClock, SequentialIds, SharedClock, SharedIds};
+    use core_service::webhooks::WebhookRequest;
+    use core_service::AppState;
+
+    let start = Utc.with_ymd_and_hms(2024, 1, 1, 9, 0, 0).unwrap();
+    let clock = ManualClock::new(start);
+    let ids = SharedIds::new(SequentialIds::new());
+    let data = tempfile::tempdir().expect("tempdir");
+    let mut config = AppConfig::default();
+    config.database.path = data.path().join("core.db").to_string_lossy().into_owned();
+    let state = AppState::with_sources(config, SharedClock::new(clock.clone()), ids.clone());
+
+    let subscription = state
+        .webhooks
+        .create(WebhookRequest {
+            url: "https://hooks.example.com/x400".into(),
+            secret: "s3cret".into(),
+            events: Vec::new(),
+            active: true,
+        })
+        .expect("subscription created");
+    assert_eq!(subscription.id, uuid::Uuid::from_u128(1));
+    assert_eq!(subscription.created_at, start);
+
+    let envelope = MessageEnvelope::stamped(
+        "Queued",
+        Address::sample(),
+        vec![Address::sample()],
+        &state.ids,
+        &state.clock,
+    );
+    assert_eq!(envelope.id, MessageId("msg-2".into()));
+    state.store.save(Message {
+        envelope,
+        content: MessageContent {
+            body: String::new(),
+            attachments: Vec::new(),
+        },
+    });
+    clock.advance(chrono::Duration::minutes(5));
+    let stats = state.store.folder_stats();
+    assert_eq!(stats[0].oldest_queued_age_seconds, Some(300));
+    clock.advance(chrono::Duration::minutes(5));
+    let stats = state.store.folder_stats();
+    assert_eq!(
+        stats[0].oldest_queued_age_seconds,
+        Some(600),
+        "a cached entry still ages with the clock"
+    );
+}
+
+#[test]
+fn smtp_status_codes_are_normalized_on_ingestion() {
+    let (queue, store, trace) = build_state();
+    let provider = MockDeliveryProvider::new(queue, store.clone(), trace);
+    let id = provider.dispatch(Message {
+        envelope: MessageEnvelope::new("Bounce", Address::sample(), vec![Address::sample()]),
+        content: MessageContent {
+            body: "Hello".into(),
+            attachments: Vec::new(),
+        },
+    });
+
+    provider
+        .simulate_report(MockReportRequest {
+            message_id: id.clone(),
+            kind: ReportKind::NonDelivery,
+            recipient: None,
+            reason: Some("5.1.1".into()),
+            diagnostic: None,
+            delay: Duration::ZERO,
+        })
+        .expect("report accepted");
+
+    let stored = store.reports(&id).pop().expect("report stored");
+    assert_eq!(stored.reason.as_deref(), Some("unable-to-transfer"));
+    assert_eq!(stored.diagnostic.as_deref(), Some("unrecognised-OR-name"));
+}
+
+#[test]
+fn scheduled_messages_wait_in_outbox_until_send_at() {
+    use chrono::TimeZone;
+    use core_service::clock::{ManualClock, SharedClock};
+
+    let start = Utc.with_ymd_and_hms(2024, 6, 3, 7, 30, 0).unwrap();
+    let clock = ManualClock::new(start);
+    let shared = SharedClock::new(clock.clone());
+    let (queue, store, trace) = build_state();
+    let queue = queue.with_clock(shared.clone());
+    let provider =
+        MockDeliveryProvider::new(queue.clone(), store.clone(), trace).with_clock(shared);
+
+    let message = |subject: &str| Message {
+        envelope: MessageEnvelope::new(subject, Address::sample(), vec![Address::sample()]),
+        content: MessageContent {
+            body: "Board minutes".into(),
+            attachments: Vec::new(),
+        },
+    };
+    let send_at = start + chrono::Duration::hours(1);
+    let morning = provider.schedule(message("Morning"), send_at);
+    let withdrawn = provider.schedule(message("Withdrawn"), send_at);
+
+    let outbox: Vec<_> = queue
+        .scheduled()
+        .into_iter()
+        .map(|queued| queued.message_id)
+        .collect();
+    assert_eq!(outbox, vec![morning.clone(), withdrawn.clone()]);
+    assert_eq!(provider.release_scheduled(), 0);
+    assert_eq!(
+        store.get(&morning).unwrap().envelope.status,
+        MessageStatus::Queued
+    );
+
+    assert!(provider.cancel_scheduled(&withdrawn));
+    let draft = store.get(&withdrawn).unwrap();
+    assert_eq!(draft.envelope.folder, "drafts");
+    assert_eq!(draft.envelope.status, MessageStatus::Draft);
+
+    clock.advance(chrono::Duration::hours(1));
+    assert_eq!(provider.release_scheduled(), 1);
+    assert!(!provider.cancel_scheduled(&morning), "already dispatched");
+    assert_eq!(
+        store.get(&morning).unwrap().envelope.status,
+        MessageStatus::Read
+    );
+    assert!(queue.scheduled().is_empty());
+}
+
+#[test]
+fn batch_submit_reports_per_message_outcomes() {
+    use chrono::TimeZone;
+    use core_service::clock::{ManualClock, SharedClock};
+    use core_service::submit::{BatchError, BatchOutcome, BatchSubmission};
+    use core_service::transport::{P7Driver, TransportSwitch, UnloadedSdk};
+    use std::sync::Arc;
+
+    let start = Utc.with_ymd_and_hms(2024, 6, 3, 7, 30, 0).unwrap();
+    let shared = SharedClock::new(ManualClock::new(start));
+    let (queue, store, trace) = build_state();
+    let queue = queue.with_clock(shared.clone());
+    let engine = DlpEngine::new(vec![DlpRule::new(
+        "secret",
+        DlpMatcher::Marker("[SECRET]".into()),
+        DlpAction::Block,
+    )]);
+    let policy = SubmitPolicy::new(trace.clone()).with_dlp(engine);
+    let provider =
+        MockDeliveryProvider::new(queue.clone(), store.clone(), trace).with_clock(shared.clone());
+    let switch = TransportSwitch::new(
+        "mock",
+        Arc::new(provider.clone()),
+        P7Driver::new(Arc::new(UnloadedSdk), "ops"),
+        Arc::new(provider),
+    )
+    .with_clock(shared)
+    .with_policy(policy, store.clone())
+    .with_outbox(store.clone(), queue.clone())
+    .with_batch_limit(3);
+
+    let submission = |subject: &str, deferred_until| BatchSubmission {
+        message: Message {
+            envelope: MessageEnvelope::new(subject, Address::sample(), vec![Address::sample()]),
+            content: MessageContent {
+                body: "Replayed from legacy archive".into(),
+                attachments: Vec::new(),
+            },
+        },
+        deferred_until,
+    };
+    let later = start + chrono::Duration::hours(4);
+    let results = switch
+        .submit_batch(vec![
+            submission("Invoice 1", None),
+            submission("[SECRET] Invoice 2", None),
+            submission("Invoice 3", Some(later)),
+        ])
+        .unwrap();
+
+    let outcomes: Vec<_> = results.iter().map(|result| result.outcome).collect();
+    assert_eq!(
+        outcomes,
+        vec![
+            BatchOutcome::Accepted,
+            BatchOutcome::Rejected,
+            BatchOutcome::Scheduled
+        ]
+    );
+    assert_eq!(
+        results[1].error.as_deref(),
+        Some("transport rejected the request: message blocked by DLP rule secret")
+    );
+    assert!(store.get(&results[1].message_id).is_none());
+    assert_eq!(
+        store.get(&results[0].message_id).unwrap().envelope.status,
+        MessageStatus::Read
+    );
+    assert_eq!(queue.scheduled()[0].message_id, results[2].message_id);
+
+    let oversized = (0..4).map(|n| submission(&format!("Bulk {n}"), None));
+    assert_eq!(
+        switch.submit_batch(oversized.collect()),
+        Err(BatchError::TooLarge { size: 4, limit: 3 })
+    );
+    assert_eq!(switch.submit_batch(Vec::new()), Err(BatchError::Empty));
+}
+
+#[cfg(unix)]
+#[test]
+fn serves_channel_commands_on_the_local_socket() {
+    use core_service::config::ServerConfig;
+    use core_service::ipc::{self, Listener};
+    use core_service::AppState;
+    use std::io::{BufRead, BufReader, Write};
+    use std::os::unix::net::UnixStream;
+
+    let data = tempfile::tempdir().unwrap();
+    let mut config = AppConfig::default();
+    config.database.path = data.path().join("core.db").to_string_lossy().into_owned();
+    config.security.require_auth = true;
+    config.rate_limit.enabled = true;
+    config.rate_limit.burst = 1;
+    config.rate_limit.per_key_per_minute = 1;
+    config.access_log.enabled = true;
+    config.access_log.path = data.path().join("access").to_string_lossy().into_owned();
+    let socket = data.path().join("core.sock");
+    let state = AppState::new(config);
+    let listener = Listener::bind(&ServerConfig {
+        socket_path: Some(socket.to_string_lossy().into_owned()),
+        ..ServerConfig::default()
+    })
+    .unwrap();
+    let served = state.clone();
+    std::thread::spawn(move || ipc::serve(&listener, &served));
+
+    let stream = UnixStream::connect(&socket).unwrap();
+    let mut writer = stream.try_clone().unwrap();
+    let mut reader = BufReader::new(stream);
+    writer
+        .write_all(b"{\"type\":\"ping\",\"requestId\":\"1\"}\n")
+        .unwrap();
+    let mut reply = String::new();
+    reader.read_line(&mut reply).unwrap();
+    assert_eq!(reply.trim(), "{\"type\":\"pong\",\"requestId\":\"1\"}");
+
+    writer.write_all(b"not json\n").unwrap();
+    reply.clear();
+    reader.read_line(&mut reply).unwrap();
+    assert!(reply.contains("invalid command"), "{reply}");
+
+    writer
+        .write_all(b"{\"type\":\"ping\",\"requestId\":\"3\"}\n")
+        .unwrap();
+    reply.clear();
+    reader.read_line(&mut reply).unwrap();
+    assert!(reply.contains("\"type\":\"rateLimited\""), "{reply}");
+    assert!(reply.contains("\"status\":429"), "{reply}");
+    assert!(reply.contains("\"retryAfter\":"), "{reply}");
+
+    let deadline = std::time::Instant::now() + Duration::from_secs(5);
+    let entries = loop {
+        let entries = state
+            .access_log
+            .query(&core_service::access_log::AccessLogQuery::default())
+            .unwrap();
+        if entries.len() == 3 || std::time::Instant::now() > deadline {
+            break entries;
+        }
+        std::thread::sleep(Duration::from_millis(20));
+    };
+    let logged: Vec<_> = entries
+        .iter()
+        .map(|entry| {
+            (
+                entry.principal.as_deref(),
+                entry.route.as_str(),
+                entry.status,
+            )
+        })
+        .collect();
+    assert_eq!(
+        logged,
+        [
+            (Some("local"), "ping", 429),
+            (Some("local"), "invalid", 400),
+            (Some("local"), "ping", 200),
+        ]
+    );
+    assert_eq!(entries[2].method, "COMMAND");
+    assert_eq!(entries[2].bytes, 32);
+}
+
+#[test]
+fn shutdown_drains_the_queue_through_the_active_transport() {
+    use core_service::AppState;
+
+    let data = tempfile::tempdir().unwrap();
+    let mut config = AppConfig::default();
+    config.database.path = data.path().join("core.db").to_string_lossy().into_owned();
+    // The queued message alone fills the quota; draining must not re-admit it.
+    config.quota.soft_limit_bytes = 1;
+    let state = AppState::new(config);
+
+    let mut envelope =
+        MessageEnvelope::new("Left behind", Address::sample(), vec![Address::sample()]);
+    envelope.status = MessageStatus::Queued;
+    let id = envelope.id.clone();
+    state.store.save(Message {
+        envelope,
+        content: MessageContent {
+            body: "queued before SIGTERM".into(),
+            attachments: Vec::new(),
+        },
+    });
+    state.queue.enqueue(id.clone());
+
+    let report = state.shutdown().unwrap();
+    assert_eq!(report.failed, 0);
+    assert_eq!(report.persisted, 0);
+    assert!(state.queue.pending().is_empty());
+    assert!(state.drain.admit_submit().is_err());
+    assert_eq!(
+        state.store.get(&id).unwrap().envelope.status,
+        MessageStatus::Read
+    );
+}
+
+#[test]
+fn operations_record_themselves_in_the_audit_log() {
+    use core_service::audit::AuditQuery;
+    use core_service::migration::MigrationRequest;
+    use core_service::models::AuditAction;
+    use core_service::AppState;
+
+    let data = tempfile::tempdir().unwrap();
+    let mut config = AppConfig::default();
+    config.database.path = data.path().join("core.db").to_string_lossy().into_owned();
+    let state = AppState::new(config);
+
+    let envelope = MessageEnvelope::new("Doomed", Address::sample(), vec![Address::sample()]);
+    let id = envelope.id.clone();
+    state.store.save(Message {
+        envelope,
+        content: MessageContent {
+            body: String::new(),
+            attachments: Vec::new(),
+        },
+    });
+    assert!(state.transport.delete_as("ops", &id).unwrap());
+    let folder = state.folders.create("Projects", None).unwrap();
+    state.folders.delete("ops", &folder.id).unwrap();
+    state.transport.switch("admin", "gateway").unwrap();
+    let source = data.path().join("legacy");
+    let job = state
+        .migration
+        .import(
+            "migrator",
+            MigrationRequest {
+                path: source.clone(),
+                dry_run: true,
+                ..MigrationRequest::default()
+            },
+        )
+        .unwrap();
+    state.migration.wait(job).unwrap();
+
+    let actions: Vec<_> = state
+        .audit
+        .query(&AuditQuery::default())
+        .into_iter()
+        .map(|record| (record.actor, record.action, record.target))
+        .collect();
+    assert_eq!(
+        actions,
+        [
+            (
+                "migrator".into(),
+                AuditAction::MigrationImport,
+                source.display().to_string()
+            ),
+            (
+                "admin".into(),
+                AuditAction::TransportSwitch,
+                "gateway".into()
+            ),
+            ("ops".into(), AuditAction::FolderDelete, folder.id),
+            ("ops".into(), AuditAction::MessageDelete, id.0),
+        ]
+    );
+    assert!(state.audit.verify().valid);
+}
+
+#[test]
+fn tcp_clients_authenticate_with_an_auth_frame() {
+    use core_service::config::ServerConfig;
+    use core_service::ipc::{self, ListenAddress, Listener};
+    use core_service::AppState;
+    use std::io::{BufRead, BufReader, Write};
+    use std::net::TcpStream;
+
+    let data = tempfile::tempdir().unwrap();
+    let mut config = AppConfig::default();
+    config.database.path = data.path().join("core.db").to_string_lossy().into_owned();
+    config.security.require_auth = true;
+    config.security.api_key = Some("s3cret".into());
+    config.transport.profiles_dir = data.path().to_string_lossy().into_owned();
+    config.access_log.path = data.path().join("access").to_string_lossy().into_owned();
+    std::fs::write(
+        data.path().join("default.p7p"),
+        "[Profile]\nName=default\nORAddress=C=DE;O=Branch;S=Clerk\n\n[MTA]\nEndpoint=mta.example:102\n",
+    )
+    .unwrap();
+    let state = AppState::new(config);
+    let listener = Listener::bind(&ServerConfig {
+        host: "127.0.0.1".into(),
+        port: 0,
+        ..ServerConfig::default()
+    })
+    .unwrap();
+    let ListenAddress::Tcp(address) = listener.local_address().unwrap() else {
+        panic!("expected a TCP listener");
+    };
+    let served = state.clone();
+    std::thread::spawn(move || ipc::serve(&listener, &served));
+
+    // Replies come back in order; pushed events in between are skipped.
+    let exchange = |stream: &TcpStream, line: &str| {
+        let mut writer = stream.try_clone().unwrap();
+        writer.write_all(format!("{line}\n").as_bytes()).unwrap();
+        let mut reader = BufReader::new(stream);
+        loop {
+            let mut reply = String::new();
+            reader.read_line(&mut reply).unwrap();
+            if !reply.starts_with(r#"{"type":"event""#) {
+                return reply;
+            }
+        }
+    };
+
+    let stream = TcpStream::connect(&address).unwrap();
+    let reply = exchange(&stream, r#"{"type":"ping","requestId":"1"}"#);
+    assert!(reply.contains("send an auth frame first"), "{reply}");
+    let reply = exchange(&stream, r#"{"type":"auth","apiKey":"guess"}"#);
+    assert!(reply.contains("invalid credentials"), "{reply}");
+    assert_eq!(exchange(&stream, r#"{"type":"ping"}"#), "");
+
+    let stream = TcpStream::connect(&address).unwrap();
+    let reply = exchange(
+        &stream,
+        r#"{"type":"auth","requestId":"a","authorization":"Bearer s3cret"}"#,
+    );
+    assert_eq!(
+        reply.trim(),
+        r#"{"type":"authenticated","requestId":"a","key":"default"}"#
+    );
+    let reply = exchange(&stream, r#"{"type":"ping","requestId":"2"}"#);
+    assert_eq!(reply.trim(), r#"{"type":"pong","requestId":"2"}"#);
+
+    let reply = exchange(&stream, r#"{"type":"ping"}"#);
+    let pong: serde_json::Value = serde_json::from_str(&reply).unwrap();
+    assert!(pong["requestId"].as_str().is_some_and(|id| !id.is_empty()));
+
+    let reply = exchange(
+        &stream,
+        r#"{"type":"submit","requestId":"corr-1","subject":"Hi","body":"","recipients":[{"country":"DE","organization":"Modern","surname":"Peer"}]}"#,
+    );
+    assert!(reply.contains(r#""requestId":"corr-1""#), "{reply}");
+    let traced = state.trace.query(&core_service::trace::TraceQuery {
+        correlation_id: Some("corr-1".into()),
+        ..Default::default()
+    });
+    assert!(!traced.is_empty());
+
+    // Events reach an idle client without another command.
+    let submitted: serde_json::Value = serde_json::from_str(&reply).unwrap();
+    let id = core_service::models::MessageId(submitted["messageId"].as_str().unwrap().into());
+    state.trace.record("operator.note", id);
+    let mut reader = BufReader::new(&stream);
+    let pushed = loop {
+        let mut line = String::new();
+        reader.read_line(&mut line).unwrap();
+        if line.contains("operator.note") {
+            break line;
+        }
+    };
+    assert!(pushed.contains(r#""type":"event""#), "{pushed}");
+
+    // A line that never ends is cut off at the frame limit.
+    let mut stream = TcpStream::connect(&address).unwrap();
+    stream.write_all(&vec![b'x'; ipc::MAX_FRAME]).unwrap();
+    let mut reply = String::new();
+    BufReader::new(&stream).read_line(&mut reply).unwrap();
+    assert!(reply.contains(r#""status":413"#), "{reply}");
+}
+
+#[test]
+fn websocket_clients_share_the_channel_and_events_need_messages_read() {
+    use core_service::config::{ApiKeyConfig, ServerConfig};
+    use core_service::ipc::{self, ListenAddress, Listener};
+    use core_service::AppState;
+    use std::io::{BufRead, BufReader, Read, Write};
+    use std::net::TcpStream;
+
+    let data = tempfile::tempdir().unwrap();
+    let mut config = AppConfig::default();
+    config.database.path = data.path().join("core.db").to_string_lossy().into_owned();
+    config.access_log.path = data.path().join("access").to_string_lossy().into_owned();
+    config.security.require_auth = true;
+    for (name, secret, scopes) in [
+        ("desk", "d3sk", vec!["*".to_string()]),
+        (
+            "robot",
+            "r0bot",
+            vec!["status:read".into(), "messages:write".into()],
+        ),
+    ] {
+        config.security.keys.push(ApiKeyConfig {
+            name: name.into(),
+            secret: secret.into(),
+            secret_ref: None,
+            scopes,
+            roles: Vec::new(),
+        });
+    }
+    config.transport.profiles_dir = data.path().to_string_lossy().into_owned();
+    std::fs::write(
+        data.path().join("default.p7p"),
+        "[Profile]\nName=default\nORAddress=C=DE;O=Branch;S=Clerk\n\n[MTA]\nEndpoint=mta.example:102\n",
+    )
+    .unwrap();
+    let state = AppState::new(config);
+    let listener = Listener::bind(&ServerConfig {
+        host: "127.0.0.1".into(),
+        port: 0,
+        ..ServerConfig::default()
+    })
+    .unwrap();
+    let ListenAddress::Tcp(address) = listener.local_address().unwrap() else {
+        panic!("expected a TCP listener");
+    };
+    let served = state.clone();
+    std::thread::spawn(move || ipc::serve(&listener, &served));
+
+    let mut socket = TcpStream::connect(&address).unwrap();
+    socket
+        .write_all(
+            b"GET /ws HTTP/1.1\r\nHost: localhost\r\nUpgrade: websocket\r\nConnection: Upgrade\r\nSec-WebSocket-Key: dGhlIHNhbXBsZSBub25jZQ==\r\nSec-WebSocket-Version: 13\r\n\r\n",
+        )
+        .unwrap();
+    let mut websocket = BufReader::new(socket.try_clone().unwrap());
+    let mut handshake = String::new();
+    loop {
+        let mut line = String::new();
+        websocket.read_line(&mut line).unwrap();
+        handshake.push_str(&line);
+        if line == "\r\n" {
+            break;
+        }
+    }
+    assert!(handshake.starts_with("HTTP/1.1 101"), "{handshake}");
+    assert!(
+        handshake.contains("s3pPLMBiTxaQ9kYGzzhZRbK+xOo="),
+        "{handshake}"
+    );
+
+    let mut send = |text: &str| {
+        let mask = [1u8, 2, 3, 4];
+        let mut frame = vec![0x81, 0x80 | 126];
+        frame.extend_from_slice(&(text.len() as u16).to_be_bytes());
+        frame.extend_from_slice(&mask);
+        frame.extend(text.bytes().enumerate().map(|(i, byte)| byte ^ mask[i % 4]));
+        socket.write_all(&frame).unwrap();
+    };
+    let mut receive = || {
+        let mut head = [0u8; 2];
+        websocket.read_exact(&mut head).unwrap();
+        assert_eq!(head[0], 0x81);
+        let len = match head[1] {
+            126 => {
+                let mut len = [0u8; 2];
+                websocket.read_exact(&mut len).unwrap();
+                u16::from_be_bytes(len) as usize
+            }
+            len => len as usize,
+        };
+        let mut payload = vec![0u8; len];
+        websocket.read_exact(&mut payload).unwrap();
+        String::from_utf8(payload).unwrap()
+    };
+    send(r#"{"type":"auth","requestId":"a","apiKey":"d3sk"}"#);
+    assert_eq!(
+        receive(),
+        r#"{"type":"authenticated","requestId":"a","key":"desk"}"#
+    );
+    send("{\n  \"type\": \"ping\",\n  \"requestId\": \"1\"\n}");
+    assert_eq!(receive(), r#"{"type":"pong","requestId":"1"}"#);
+
+    // A key without messages:read may submit but is not told about events.
+    let robot = TcpStream::connect(&address).unwrap();
+    let mut robot_writer = robot.try_clone().unwrap();
+    let mut robot_reader = BufReader::new(robot);
+    let mut robot_exchange = |line: &str| {
+        robot_writer
+            .write_all(format!("{line}\n").as_bytes())
+            .unwrap();
+        let mut reply = String::new();
+        robot_reader.read_line(&mut reply).unwrap();
+        reply
+    };
+    robot_exchange(r#"{"type":"auth","apiKey":"r0bot"}"#);
+    let reply = robot_exchange(
+        r#"{"type":"submit","requestId":"s","subject":"Hi","body":"","recipients":[{"country":"DE","organization":"Modern","surname":"Peer"}]}"#,
+    );
+    let submitted: serde_json::Value = serde_json::from_str(&reply).unwrap();
+    let id = submitted["messageId"].as_str().unwrap().to_string();
+
+    let pushed = loop {
+        let frame = receive();
+        if frame.contains(&id) {
+            break frame;
+        }
+    };
+    assert!(pushed.starts_with(r#"{"type":"event""#), "{pushed}");
+    let reply = robot_exchange(r#"{"type":"ping","requestId":"2"}"#);
+    assert_eq!(reply.trim(), r#"{"type":"pong","requestId":"2"}"#);
+}
+
+#[test]
+fn scheduler_worker_delivers_scheduled_messages_when_due() {
+    use chrono::TimeZone;
+    use core_service::clock::{ManualClock, SharedClock, SharedIds};
+    use core_service::AppState;
+
+    let start = Utc.with_ymd_and_hms(2024, 1, 1, 9, 0, 0).unwrap();
+    let clock = ManualClock::new(start);
+    let data = tempfile::tempdir().unwrap();
+    let mut config = AppConfig::default();
+    config.database.path = data.path().join("core.db").to_string_lossy().into_owned();
+    let state = AppState::with_sources(
+        config,
+        SharedClock::new(clock.clone()),
+        SharedIds::default(),
+    );
+    let _workers = state.start();
+
+    let envelope = MessageEnvelope::new("Later", Address::sample(), vec![Address::sample()]);
+    let id = state.mock.schedule(
+        Message {
+            envelope,
+            content: MessageContent {
+                body: String::new(),
+                attachments: Vec::new(),
+            },
+        },
+        start + chrono::Duration::minutes(5),
+    );
+    assert_eq!(state.queue.scheduled().len(), 1);
+
+    clock.advance(chrono::Duration::minutes(5));
+    let deadline = std::time::Instant::now() + Duration::from_secs(5);
+    while state.store.get(&id).unwrap().envelope.status != MessageStatus::Read {
+        assert!(
+            std::time::Instant::now() < deadline,
+            "scheduled message not delivered"
+        );
+        std::thread::sleep(Duration::from_millis(50));
+    }
+    assert!(state.queue.pending().is_empty());
+}
+
+#[test]
+fn metrics_history_worker_samples_telemetry_on_start() {
+    use core_service::supervisor::WorkerState;
+    use core_service::AppState;
+
+    let data = tempfile::tempdir().unwrap();
+    let mut config = AppConfig::default();
+    config.database.path = data.path().join("core.db").to_string_lossy().into_owned();
+    let state = AppState::new(config);
+    assert!(
+        state.supervisor.status().is_empty(),
+        "building the state starts no workers"
+    );
+    let workers = state.start();
+
+    let deadline = std::time::Instant::now() + Duration::from_secs(5);
+    while state
+        .metrics_history
+        .history("90m")
+        .unwrap()
+        .points
+        .is_empty()
+    {
+        assert!(
+            std::time::Instant::now() < deadline,
+            "metrics history was never sampled"
+        );
+        std::thread::sleep(Duration::from_millis(50));
+    }
+    workers.shutdown();
+    assert!(state
+        .supervisor
+        .status()
+        .iter()
+        .all(|worker| worker.state == WorkerState::Stopped));
+}
+
+#[test]
+fn relay_profiles_route_submissions_through_the_relay_spool() {
+    use core_service::transport::MessageTransport;
+    use core_service::AppState;
+
+    let data = tempfile::tempdir().unwrap();
+    let profiles = data.path().join("profiles");
+    std::fs::create_dir_all(&profiles).unwrap();
+    std::fs::write(
+        profiles.join("branch.p7p"),
+        "[Profile]\nName=branch\n\n[Transport]\nMode=relay\n\n[Relay]\nURL=http://127.0.0.1:1\n",
+    )
+    .unwrap();
+    let mut config = AppConfig::default();
+    config.database.path = data.path().join("core.db").to_string_lossy().into_owned();
+    config.transport.profiles_dir = profiles.to_string_lossy().into_owned();
+    config.transport.mode = "relay".into();
+    let state = AppState::new(config);
+    assert_eq!(state.transport.mode(), "relay");
+
+    let envelope = MessageEnvelope::new("Via HQ", Address::sample(), vec![Address::sample()]);
+    let id = state
+        .transport
+        .submit(Message {
+            envelope,
+            content: MessageContent {
+                body: String::new(),
+                attachments: Vec::new(),
+            },
+        })
+        .unwrap();
+    assert_eq!(
+        state.store.get(&id).unwrap().envelope.status,
+        MessageStatus::Queued
+    );
+    assert!(data.path().join("core.relay").read_dir().unwrap().count() > 0);
+}
+
+#[test]
+fn quota_applies_to_submissions_and_inbound_mail_and_held_content_survives_restarts() {
+    use core_service::models::MessagePriority;
+    use core_service::transport::MessageTransport;
+    use core_service::AppState;
+
+    let data = tempfile::tempdir().unwrap();
+    let mut config = AppConfig::default();
+    config.database.path = data.path().join("core.db").to_string_lossy().into_owned();
+    config.quota.soft_limit_bytes = 300;
+    config.quota.max_message_bytes = 100;
+    let message = |subject: &str, body: &str, priority| {
+        let mut envelope =
+            MessageEnvelope::new(subject, Address::sample(), vec![Address::sample()]);
+        envelope.priority = priority;
+        Message {
+            envelope,
+            content: MessageContent {
+                body: body.into(),
+                attachments: Vec::new(),
+            },
+        }
+    };
+
+    let state = AppState::new(config.clone());
+    let long = "x".repeat(250);
+    let held = state
+        .inbound
+        .ingest_native(message("Inbound", &long, MessagePriority::Normal))
+        .unwrap();
+    assert!(state
+        .store
+        .get(&held)
+        .unwrap()
+        .content
+        .body
+        .contains("held at the gateway"));
+
+    state
+        .store
+        .save(message("Filler", &"y".repeat(200), MessagePriority::Normal));
+    let refused = state
+        .transport
+        .submit(message("Routine", &"z".repeat(50), MessagePriority::Normal))
+        .unwrap_err();
+    assert_eq!(refused.status(), 507);
+    let refused = state
+        .inbound
+        .ingest_native(message("Inbound", "short", MessagePriority::Normal))
+        .unwrap_err();
+    assert!(refused.to_string().contains("quota exceeded"), "{refused}");
+    assert!(state
+        .transport
+        .submit(message("Outage", &"z".repeat(50), MessagePriority::High))
+        .is_ok());
+    drop(state);
+
+    let reopened = AppState::new(config);
+    assert_eq!(reopened.quota.held(&held).unwrap().size, 250);
+    assert_eq!(reopened.quota.retrieve(&held).unwrap().content.body, long);
+}
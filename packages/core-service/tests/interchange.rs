@@ -0,0 +1,25 @@
+use std::env;
+use std::fs;
+use std::path::Path;
+
+use core_service::interchange;
+
+/// The published schema must match what the code accepts. Set
+/// `INTERCHANGE_BLESS=1` to rewrite it after an intended format change, and
+/// bump `INTERCHANGE_VERSION` if the change is not backwards compatible.
+#[test]
+fn published_schema_matches_generated() {
+    let path = Path::new(env!("CARGO_MANIFEST_DIR")).join("api/message-interchange.schema.json");
+    let generated = interchange::schema();
+    if env::var_os("INTERCHANGE_BLESS").is_some() {
+        let json = serde_json::to_string_pretty(&generated).unwrap() + "\n";
+        fs::write(&path, json).unwrap();
+    }
+    let published: serde_json::Value =
+        serde_json::from_str(&fs::read_to_string(&path).expect("schema file is checked in"))
+            .expect("schema file is valid JSON");
+    assert_eq!(
+        published, generated,
+        "api/message-interchange.schema.json is stale; rerun with INTERCHANGE_BLESS=1"
+    );
+}
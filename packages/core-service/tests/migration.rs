@@ -1,13 +1,20 @@
 use std::fs;
-use std::io::Write;
+use std::io::{Read, Write};
 use std::path::{Path, PathBuf};
 
 use chrono::{Duration, Utc};
+use core_service::attachments::AttachmentStore;
+use core_service::config::LdapConfig;
+use core_service::directory::{DirectoryCache, LdapDirectoryClient};
+use core_service::gateway::{AddressMapper, AddressMappingRule};
 use core_service::migration::{
-    parse_fwm, read_fwz, MigrationManager, MigrationMode, MigrationRequest,
+    parse_fwm, parse_fwm_with, read_fwz, EntryCheck, FwmDecoding, FwzLimits, FwzReader,
+    LegacyCharset, MigrationManager, MigrationMode, MigrationRequest, MigrationStatus,
+    QuarantineRecord,
 };
 use core_service::store::StoreManager;
 use encoding_rs::WINDOWS_1252;
+use sha2::{Digest, Sha256};
 use tempfile::tempdir;
 use zip::write::FileOptions;
 use zip::CompressionMethod;
@@ -39,6 +46,56 @@ fn parse_fwm_decodes_cp1252_payloads() {
     assert_eq!(document.body(), "Grüße");
 }
 
+#[test]
+fn jobs_decode_known_legacy_charsets_on_request() {
+    let dir = tempdir().expect("temp dir");
+    let source = dir.path().join("dos");
+    fs::create_dir(&source).unwrap();
+    // "Grüße" in code page 437, which detection alone reads as Windows-1252.
+    fs::write(
+        source.join("greeting.fwm"),
+        b"SUBJECT=Gr\x81\xe1e\nBODY=Hallo\n",
+    )
+    .unwrap();
+
+    let store = StoreManager::new();
+    let manager = MigrationManager::new(store.clone());
+    let err = manager
+        .import(
+            "ops",
+            MigrationRequest {
+                path: source.clone(),
+                encoding: Some("ebcdic".into()),
+                ..MigrationRequest::default()
+            },
+        )
+        .unwrap_err();
+    assert_eq!(err.status(), 400);
+
+    let job_id = manager
+        .import(
+            "ops",
+            MigrationRequest {
+                path: source,
+                encoding: Some("CP437".into()),
+                ..MigrationRequest::default()
+            },
+        )
+        .expect("job id");
+    assert_eq!(manager.wait(job_id).expect("finishes").imported, 1);
+    assert_eq!(store.list("inbox")[0].envelope.subject, "Grüße");
+
+    let euro = parse_fwm_with(
+        b"SUBJECT=Preis 5 \xa4\nBODY=x\n",
+        FwmDecoding {
+            forced: LegacyCharset::for_label("iso-8859-15"),
+            fallback: None,
+        },
+    )
+    .expect("decode");
+    assert_eq!(euro.subject(), "Preis 5 €");
+}
+
 #[test]
 fn read_fwz_extracts_documents_and_attachments() {
     let dir = tempdir().expect("fwz dir");
@@ -53,9 +110,407 @@ fn read_fwz_extracts_documents_and_attachments() {
     let archive = read_fwz(&fwz_path).expect("read archive");
     assert_eq!(archive.documents.len(), 1);
     assert_eq!(archive.attachments.len(), 1);
+    assert_eq!(archive.attachments[0].size, 6);
+    let mut content = String::new();
+    archive.attachments[0]
+        .open()
+        .expect("spooled attachment")
+        .read_to_string(&mut content)
+        .unwrap();
+    assert_eq!(content, "sample");
     assert!(archive.checksum_ok);
 }
 
+#[test]
+fn fwz_extraction_parses_in_parallel_and_refuses_zip_bombs() {
+    let dir = tempdir().expect("fwz dir");
+    let documents: Vec<(String, Vec<u8>)> = (0..24)
+        .map(|n| {
+            (
+                format!("META/{n:02}.fwm"),
+                format!("SUBJECT=Memo {n}\nBODY=Body {n}\n").into_bytes(),
+            )
+        })
+        .collect();
+    let entries: Vec<(&str, &[u8])> = documents
+        .iter()
+        .map(|(name, data)| (name.as_str(), data.as_slice()))
+        .collect();
+    let fwz_path = write_fwz(dir.path(), &entries);
+    let archive = FwzReader::default()
+        .with_parallelism(3)
+        .read(&fwz_path)
+        .expect("read archive");
+    let subjects: Vec<String> = archive.documents.iter().map(|doc| doc.subject()).collect();
+    let expected: Vec<String> = (0..24).map(|n| format!("Memo {n}")).collect();
+    assert_eq!(subjects, expected, "documents keep their archive order");
+
+    // A megabyte of zeros deflates to about a kilobyte.
+    let bomb = dir.path().join("bomb.fwz");
+    let mut writer = zip::ZipWriter::new(fs::File::create(&bomb).unwrap());
+    writer
+        .start_file(
+            "META/memo.fwm",
+            FileOptions::default().compression_method(CompressionMethod::Stored),
+        )
+        .unwrap();
+    writer.write_all(b"SUBJECT=Memo\nBODY=Padding\n").unwrap();
+    writer
+        .start_file(
+            "ATTACH/zeros.bin",
+            FileOptions::default().compression_method(CompressionMethod::Deflated),
+        )
+        .unwrap();
+    writer.write_all(&vec![0; 1024 * 1024]).unwrap();
+    writer.finish().unwrap();
+    assert!(fs::metadata(&bomb).unwrap().len() < 16 * 1024);
+
+    let limits = FwzLimits {
+        max_entry_bytes: 256 * 1024,
+        max_total_bytes: 0,
+    };
+    let err = FwzReader::default()
+        .with_limits(limits)
+        .read(&bomb)
+        .unwrap_err();
+    assert_eq!(err.status(), 413);
+    let limits = FwzLimits {
+        max_entry_bytes: 0,
+        max_total_bytes: 512 * 1024,
+    };
+    let err = FwzReader::default()
+        .with_limits(limits)
+        .read(&bomb)
+        .unwrap_err();
+    assert_eq!(
+        err.to_string(),
+        "archive exceeds the extraction limit: contents are larger than 524288 bytes"
+    );
+
+    let quarantine = tempdir().expect("quarantine dir");
+    let manager = MigrationManager::new(StoreManager::new()).with_archive_limits(limits);
+    let job_id = manager
+        .import(
+            "ops",
+            MigrationRequest {
+                path: bomb,
+                quarantine: Some(quarantine.path().to_path_buf()),
+                ..MigrationRequest::default()
+            },
+        )
+        .expect("job id");
+    let report = manager.wait(job_id).expect("report");
+    assert_eq!(report.quarantined, 1);
+    assert_eq!(report.imported, 0);
+}
+
+#[test]
+fn fwz_attachments_are_stored_and_checked_against_the_manifest() {
+    let dir = tempdir().expect("fwz dir");
+    let report_hash = format!("{:x}", Sha256::digest(b"quarterly figures"));
+    let report: &[u8] = b"SUBJECT=Figures\nBODY=See attached\nATTACH1=report.pdf\n";
+    let photo: &[u8] = b"SUBJECT=Photo\nBODY=Site visit\nATTACH1=ATTACH\\photo.jpg\n";
+    let manifest = format!(
+        "# name size sha256\nMETA/report.fwm {} {:x}\nMETA/photo.fwm {} {:x}\n\
+         ATTACH/report.pdf 17 {report_hash}\nATTACH/photo.jpg 3 {report_hash}\n",
+        report.len(),
+        Sha256::digest(report),
+        photo.len(),
+        Sha256::digest(photo),
+    );
+    let fwz_path = write_fwz(
+        dir.path(),
+        &[
+            ("META/report.fwm", report),
+            ("META/photo.fwm", photo),
+            ("ATTACH/report.pdf", b"quarterly figures"),
+            ("ATTACH/photo.jpg", b"jpg"),
+            ("MANIFEST", manifest.as_bytes()),
+        ],
+    );
+
+    let archive = read_fwz(&fwz_path).expect("read archive");
+    assert_eq!(archive.mismatched, vec!["ATTACH/photo.jpg".to_string()]);
+    assert!(!archive.checksum_ok);
+
+    let store = StoreManager::new();
+    let attachments = AttachmentStore::new(dir.path().join("blobs"));
+    let manager = MigrationManager::new(store.clone()).with_attachments(attachments.clone());
+    let job_id = manager
+        .import(
+            "ops",
+            MigrationRequest {
+                path: fwz_path,
+                ..MigrationRequest::default()
+            },
+        )
+        .expect("job id");
+    let report = manager.wait(job_id).expect("job finishes");
+    assert_eq!(report.imported, 2);
+    assert!(!report.checksum_ok);
+    assert_eq!(
+        report.notes,
+        vec!["Attachment ATTACH/photo.jpg does not match the archive manifest".to_string()]
+    );
+
+    let saved = store.list("inbox");
+    let figures = saved
+        .iter()
+        .find(|message| message.envelope.subject == "Figures")
+        .expect("figures imported");
+    let linked = &figures.content.attachments;
+    assert_eq!(linked.len(), 1);
+    assert_eq!(linked[0].id, report_hash);
+    assert_eq!(linked[0].filename, "report.pdf");
+    assert_eq!(linked[0].mime_type, "application/pdf");
+    let mut content = String::new();
+    attachments
+        .open(&linked[0].id, None)
+        .expect("stored blob")
+        .read_to_string(&mut content)
+        .unwrap();
+    assert_eq!(content, "quarterly figures");
+
+    let photo = saved
+        .iter()
+        .find(|message| message.envelope.subject == "Photo")
+        .expect("photo imported");
+    assert!(photo.content.attachments.is_empty());
+}
+
+#[test]
+fn fwz_documents_that_disagree_with_the_manifest_are_not_imported() {
+    let dir = tempdir().expect("fwz dir");
+    let memo: &[u8] = b"SUBJECT=Memo\nBODY=Approved\n";
+    let order: &[u8] = b"SUBJECT=Order\nBODY=Ship 10 units\n";
+    let manifest = format!(
+        "META/memo.fwm {} {:x}\nMETA/order.fwm {} {:x}\n",
+        memo.len(),
+        Sha256::digest(memo),
+        order.len(),
+        Sha256::digest(order),
+    );
+    let fwz_path = write_fwz(
+        dir.path(),
+        &[
+            ("META/memo.fwm", memo),
+            ("META/order.fwm", b"SUBJECT=Order\nBODY=Ship 99 units\n"),
+            ("META/extra.fwm", b"SUBJECT=Extra\nBODY=Slipped in\n"),
+            ("MANIFEST", manifest.as_bytes()),
+        ],
+    );
+
+    let archive = read_fwz(&fwz_path).expect("read archive");
+    assert_eq!(archive.documents.len(), 1);
+    assert_eq!(archive.documents[0].subject(), "Memo");
+    assert_eq!(archive.rejected, ["META/order.fwm", "META/extra.fwm"]);
+    assert!(!archive.checksum_ok);
+
+    let store = StoreManager::new();
+    let manager = MigrationManager::new(store.clone());
+    let job_id = manager
+        .import(
+            "ops",
+            MigrationRequest {
+                path: fwz_path,
+                ..MigrationRequest::default()
+            },
+        )
+        .expect("job id");
+    let report = manager.wait(job_id).expect("report");
+    assert_eq!((report.imported, report.failed), (1, 2));
+    assert!(!report.checksum_ok);
+    let subjects: Vec<String> = store
+        .list("inbox")
+        .into_iter()
+        .map(|message| message.envelope.subject)
+        .collect();
+    assert_eq!(subjects, ["Memo"]);
+    assert_eq!(report.total, 3);
+    assert!(report
+        .errors
+        .iter()
+        .any(|error| error.path == Path::new("META/order.fwm")
+            && error.message == "META/order.fwm does not match the archive manifest"));
+}
+
+#[test]
+fn fwz_members_are_verified_against_legacy_checksums() {
+    let dir = tempdir().expect("fwz dir");
+    let memo = b"SUBJECT=Memo\nBODY=Checked\n";
+    let trace = b"12:00 sent";
+    let checksums = format!(
+        "META/memo.fwm {:08x}\nTRACE/log.txt {:08X}\nTRACE/gone.txt 0badf00d\n",
+        crc32fast::hash(memo),
+        crc32fast::hash(b"12:00 queued"),
+    );
+    let manifest = format!("META/memo.fwm {} {:x}\n", memo.len(), Sha256::digest(memo));
+    let fwz_path = write_fwz(
+        dir.path(),
+        &[
+            ("META/memo.fwm", memo),
+            ("TRACE/log.txt", trace),
+            ("NOTES.txt", b"unlisted"),
+            ("CHECKSUM", checksums.as_bytes()),
+            ("MANIFEST", manifest.as_bytes()),
+        ],
+    );
+
+    let archive = read_fwz(&fwz_path).expect("read archive");
+    let checks: Vec<(&str, EntryCheck)> = archive
+        .verification
+        .iter()
+        .map(|entry| (entry.name.as_str(), entry.check))
+        .collect();
+    assert_eq!(
+        checks,
+        vec![
+            ("META/memo.fwm", EntryCheck::Verified),
+            ("NOTES.txt", EntryCheck::Unlisted),
+            ("TRACE/gone.txt", EntryCheck::Missing),
+            ("TRACE/log.txt", EntryCheck::Mismatch),
+        ]
+    );
+    let memo_check = &archive.verification[0];
+    assert_eq!(
+        memo_check.expected.as_deref(),
+        Some(format!("sha256:{:x}", Sha256::digest(memo)).as_str())
+    );
+    let log_check = &archive.verification[3];
+    assert_eq!(
+        log_check.actual.as_deref(),
+        Some(format!("crc32:{:08x}", crc32fast::hash(trace)).as_str())
+    );
+    assert_eq!(archive.mismatched, vec!["TRACE/gone.txt", "TRACE/log.txt"]);
+    assert!(!archive.checksum_ok);
+
+    let manager = MigrationManager::new(StoreManager::new());
+    let job_id = manager
+        .import(
+            "ops",
+            MigrationRequest {
+                path: fwz_path,
+                ..MigrationRequest::default()
+            },
+        )
+        .expect("job id");
+    let report = manager.wait(job_id).expect("report");
+    assert_eq!(
+        report.imported, 1,
+        "documents are imported despite the mismatch"
+    );
+    assert!(!report.checksum_ok);
+    assert_eq!(report.verification, archive.verification);
+    assert!(report
+        .notes
+        .contains(&"TRACE/log.txt does not match the archive manifest".to_string()));
+}
+
+#[test]
+fn reimporting_an_fwz_is_idempotent_across_folders() {
+    let dir = tempdir().expect("fwz dir");
+    let fwz_path = write_fwz(
+        dir.path(),
+        &[
+            (
+                "META/notice.fwm",
+                b"SUBJECT=Notice\nBODY=Line one\r\nLine two\nTO=C=DE;O=Org;S=User\n",
+            ),
+            ("META/other.fwm", b"SUBJECT=Notice\nBODY=Different body\n"),
+        ],
+    );
+    let store = StoreManager::new();
+    let manager = MigrationManager::new(store.clone());
+    let request = MigrationRequest {
+        path: fwz_path,
+        ..MigrationRequest::default()
+    };
+
+    let first = manager
+        .import("ops", request.clone())
+        .expect("first import");
+    let report = manager.wait(first).expect("first finishes");
+    assert_eq!((report.imported, report.duplicates), (2, 0));
+
+    let stored = store.list("inbox");
+    let moved = stored
+        .iter()
+        .find(|message| message.content.body.starts_with("Line one"))
+        .expect("notice imported");
+    assert!(store.move_message(&moved.envelope.id, "archive"));
+    assert_eq!(
+        store
+            .find_by_content_hash(&moved.content_hash())
+            .map(|message| message.envelope.folder),
+        Some("archive".to_string())
+    );
+
+    let second = manager.import("ops", request).expect("second import");
+    let report = manager.wait(second).expect("second finishes");
+    assert_eq!((report.imported, report.duplicates), (2, 2));
+    assert_eq!(store.list("inbox").len(), 1);
+    assert_eq!(store.list("archive").len(), 1);
+}
+
+#[test]
+fn resumed_jobs_skip_documents_checkpointed_before_a_restart() {
+    let dir = tempdir().expect("temp dir");
+    let source = dir.path().join("mailbox");
+    fs::create_dir(&source).unwrap();
+    for name in ["a", "b", "c"] {
+        fs::write(
+            source.join(format!("{name}.fwm")),
+            format!("SUBJECT=Letter {name}\nBODY=Body {name}\n"),
+        )
+        .unwrap();
+    }
+    let open_store = || {
+        StoreManager::new()
+            .with_message_table(dir.path().join("core.messages.json"))
+            .with_migration_state_table(dir.path().join("core.migration_state.json"))
+    };
+
+    let first = MigrationManager::new(open_store());
+    let job_id = first
+        .import(
+            "ops",
+            MigrationRequest {
+                path: source.clone(),
+                limit: Some(2),
+                ..MigrationRequest::default()
+            },
+        )
+        .expect("job id");
+    first.wait(job_id).expect("first run finishes");
+    drop(first);
+
+    // A restarted service only has what the tables on disk remember.
+    let store = open_store();
+    assert_eq!(store.migration_checkpoints(job_id).len(), 2);
+
+    fs::write(source.join("b.fwm"), "SUBJECT=Letter b\nBODY=Corrected\n").unwrap();
+    let manager = MigrationManager::new(store.clone());
+    let resumed = manager
+        .import(
+            "ops",
+            MigrationRequest {
+                path: source,
+                resume: Some(job_id),
+                ..MigrationRequest::default()
+            },
+        )
+        .expect("resume");
+    assert_eq!(resumed, job_id);
+    let report = manager.wait(job_id).expect("job finishes");
+    assert_eq!(report.imported, 2, "the edited and the remaining document");
+    assert_eq!(
+        report.notes,
+        vec!["Resumed: skipped 1 documents imported by an earlier run".to_string()]
+    );
+    assert_eq!(store.list("inbox").len(), 4);
+    assert!(store.migration_checkpoints(job_id).is_empty());
+}
+
 #[test]
 fn migration_manager_imports_documents_and_tracks_progress() {
     let dir = tempdir().expect("temp dir");
@@ -70,16 +525,21 @@ fn migration_manager_imports_documents_and_tracks_progress() {
     let manager = MigrationManager::new(store.clone());
 
     let job_id = manager
-        .import(MigrationRequest {
-            path: file_path,
-            mode: MigrationMode::Fwm,
-            dry_run: false,
-            resume: None,
-            limit: None,
-            since: Some(Utc::now() - Duration::days(1)),
-            quarantine: None,
-        })
+        .import(
+            "ops",
+            MigrationRequest {
+                path: file_path,
+                mode: MigrationMode::Fwm,
+                dry_run: false,
+                resume: None,
+                limit: None,
+                since: Some(Utc::now() - Duration::days(1)),
+                quarantine: None,
+                encoding: None,
+            },
+        )
         .expect("job id");
+    manager.wait(job_id).expect("job finishes");
 
     let progress = manager.progress(job_id).expect("progress");
     assert_eq!(progress.imported, 1);
@@ -97,3 +557,310 @@ fn migration_manager_imports_documents_and_tracks_progress() {
     assert_eq!(saved.len(), 1);
     assert_eq!(saved[0].content.body, "Hello");
 }
+
+#[test]
+fn imports_mbox_messages_through_the_address_mapper() {
+    let dir = tempdir().expect("temp dir");
+    let mbox = dir.path().join("archive.mbox");
+    fs::write(
+        &mbox,
+        "From martin@partner.fr.example Fri Mar  1 09:00:00 2024\n\
+         From: Martin <martin@partner.fr.example>\n\
+         To: peer@modern.de.example\n\
+         Subject: Invoice 42\n\
+         Date: Fri, 1 Mar 2024 09:00:00 +0000\n\
+         \n\
+         >From the accounts team.\n\
+         From nobody Fri Mar  1 10:00:00 2024\n\
+         From: stranger@elsewhere.test\n\
+         To: peer@modern.de.example\n\
+         Subject: Unmapped\n\
+         \n\
+         Hi\n",
+    )
+    .expect("write mbox");
+
+    let store = StoreManager::new();
+    let mapper = AddressMapper::new(
+        vec![AddressMappingRule::new("{S}@{O}.{C}.example")],
+        Default::default(),
+    );
+    let manager = MigrationManager::new(store.clone()).with_mapper(mapper);
+    let job_id = manager
+        .import(
+            "ops",
+            MigrationRequest {
+                path: mbox.clone(),
+                ..MigrationRequest::default()
+            },
+        )
+        .expect("job id");
+
+    let report = manager.wait(job_id).expect("report");
+    assert_eq!((report.total, report.imported, report.failed), (2, 1, 1));
+    assert_eq!(
+        report.errors[0].path,
+        PathBuf::from(format!("{}#2", mbox.display()))
+    );
+    assert_eq!(
+        manager.progress(job_id).unwrap().status,
+        MigrationStatus::Failed
+    );
+
+    let saved = store.list("inbox");
+    assert_eq!(saved.len(), 1);
+    let envelope = &saved[0].envelope;
+    assert_eq!(envelope.subject, "Invoice 42");
+    assert_eq!(envelope.sender.surname, "Martin");
+    assert_eq!(envelope.recipients[0].organization, "Modern");
+    assert_eq!(
+        envelope.created_at.to_rfc3339(),
+        "2024-03-01T09:00:00+00:00"
+    );
+    assert_eq!(saved[0].content.body, "From the accounts team.\n");
+}
+
+#[test]
+fn address_books_fill_the_directory_and_resolve_later_mail() {
+    let dir = tempdir().expect("temp dir");
+    let workspace = dir.path().join("workspace");
+    fs::create_dir(&workspace).unwrap();
+    fs::write(
+        workspace.join("contacts.fwa"),
+        "# FileWork address book\n\
+         [ENTRY]\n\
+         ID=0001\n\
+         NAME=Hans Mueller\n\
+         ORADDR=C=DE;O=Bundespost;S=Mueller\n\
+         RFC822=hans@post.example\n\
+         PHONE=+49 228 1234\n\
+         [ENTRY]\n\
+         NAME=Nobody\n\
+         [LIST]\n\
+         ID=ops\n\
+         NAME=Operations\n\
+         MEMBER=0001\n",
+    )
+    .unwrap();
+    fs::write(workspace.join("note.fwm"), "SUBJECT=Note\nBODY=Hi\n").unwrap();
+
+    let directory = LdapDirectoryClient::new(LdapConfig::default(), DirectoryCache::new(60, 8));
+    let store = StoreManager::new();
+    let manager = MigrationManager::new(store.clone()).with_directory(directory.clone());
+    let job_id = manager
+        .import(
+            "ops",
+            MigrationRequest {
+                path: workspace,
+                ..MigrationRequest::default()
+            },
+        )
+        .expect("job id");
+    let report = manager.wait(job_id).expect("finishes");
+    assert_eq!(report.imported, 1);
+    assert!(report.notes[0].ends_with("contacts.fwa: 1 contacts, 1 distribution lists"));
+    assert!(report.notes[1].ends_with("skipped section 2: entry without ORADDR"));
+
+    let hans = directory.get_entry("0001").expect("contact imported");
+    assert_eq!(hans.or_address, "C=DE;O=Bundespost;S=Mueller");
+    assert_eq!(hans.rfc822, "hans@post.example");
+    assert_eq!(hans.attributes["PHONE"], "+49 228 1234");
+    assert_eq!(
+        directory.get_distribution_list("ops").unwrap().members,
+        vec!["0001".to_string()]
+    );
+
+    // No mapping rule covers post.example; the imported alias does.
+    let eml = dir.path().join("reply.eml");
+    fs::write(
+        &eml,
+        "From: Hans <hans@post.example>\nTo: hans@post.example\nSubject: Re: Note\n\nThanks\n",
+    )
+    .unwrap();
+    let job_id = manager
+        .import(
+            "ops",
+            MigrationRequest {
+                path: eml,
+                ..MigrationRequest::default()
+            },
+        )
+        .expect("job id");
+    assert_eq!(manager.wait(job_id).expect("finishes").imported, 1);
+    let reply = store
+        .list("inbox")
+        .into_iter()
+        .find(|message| message.envelope.subject == "Re: Note")
+        .expect("reply imported");
+    assert_eq!(reply.envelope.sender.organization, "Bundespost");
+}
+
+#[test]
+fn dry_runs_report_a_diff_against_the_store() {
+    let dir = tempdir().expect("temp dir");
+    let workspace = dir.path().join("workspace");
+    fs::create_dir(&workspace).unwrap();
+    fs::write(
+        workspace.join("a.fwm"),
+        "SUBJECT=Plans\nBODY=Draft\nFOLDER=projects\n",
+    )
+    .unwrap();
+    fs::write(workspace.join("b.fwm"), "SUBJECT=Minutes\nBODY=Agreed\n").unwrap();
+    fs::write(workspace.join("c.fwm"), "SUBJECT=Memo\nBODY=Read me\n").unwrap();
+
+    let store = StoreManager::new();
+    let manager = MigrationManager::new(store.clone());
+    let seed = manager
+        .import(
+            "ops",
+            MigrationRequest {
+                path: workspace.join("b.fwm"),
+                ..MigrationRequest::default()
+            },
+        )
+        .expect("seed");
+    manager.wait(seed).expect("seeded");
+    let stored = store.list("inbox").remove(0);
+    store.move_message(&stored.envelope.id, "archive");
+
+    let job_id = manager
+        .import(
+            "ops",
+            MigrationRequest {
+                path: workspace.clone(),
+                dry_run: true,
+                ..MigrationRequest::default()
+            },
+        )
+        .expect("dry run");
+    let report = manager.wait(job_id).expect("finishes");
+    let diff = report.diff.expect("dry runs carry a diff");
+    let paths = |paths: Vec<&PathBuf>| -> Vec<String> {
+        paths
+            .into_iter()
+            .map(|path| path.file_name().unwrap().to_string_lossy().into_owned())
+            .collect()
+    };
+    assert_eq!(
+        paths(diff.new_messages.iter().map(|entry| &entry.path).collect()),
+        ["a.fwm", "c.fwm"]
+    );
+    assert_eq!(diff.duplicates.len(), 1);
+    assert_eq!(diff.duplicates[0].existing_id, stored.envelope.id.0);
+    assert_eq!(diff.duplicates[0].existing_folder, "archive");
+    assert_eq!(
+        diff.folder_conflicts
+            .iter()
+            .map(|conflict| (
+                conflict.folder.as_str(),
+                conflict.existing_folder.as_deref()
+            ))
+            .collect::<Vec<_>>(),
+        [("projects", None), ("inbox", Some("archive"))]
+    );
+    let text = report.diff_text.expect("text rendering");
+    assert!(text.starts_with("2 new, 1 duplicates, 2 folder conflicts, 0 unmappable addresses\n"));
+    assert!(text.contains("folder projects does not exist"));
+    assert_eq!(store.list("inbox").len(), 0, "dry runs store nothing");
+
+    let mbox = dir.path().join("archive.mbox");
+    fs::write(
+        &mbox,
+        "From a Fri Mar  1 09:00:00 2024\n\
+         From: stranger@elsewhere.test\n\
+         To: peer@modern.de.example, other@elsewhere.test\n\
+         Subject: Unmapped\n\
+         \n\
+         Hi\n",
+    )
+    .unwrap();
+    let manager = manager.with_mapper(AddressMapper::new(
+        vec![AddressMappingRule::new("{S}@{O}.{C}.example")],
+        Default::default(),
+    ));
+    let job_id = manager
+        .import(
+            "ops",
+            MigrationRequest {
+                path: mbox,
+                dry_run: true,
+                ..MigrationRequest::default()
+            },
+        )
+        .expect("dry run");
+    let diff = manager.wait(job_id).expect("finishes").diff.unwrap();
+    assert_eq!(
+        diff.unmappable_addresses
+            .iter()
+            .map(|entry| entry.address.as_str())
+            .collect::<Vec<_>>(),
+        ["stranger@elsewhere.test", "other@elsewhere.test"]
+    );
+}
+
+#[test]
+fn quarantines_unreadable_artifacts_with_an_error_sidecar() {
+    let source = tempdir().expect("source dir");
+    let quarantine = tempdir().expect("quarantine dir");
+    fs::write(source.path().join("good.fwm"), "SUBJECT=Kept\nBODY=Fine\n").unwrap();
+    fs::write(source.path().join("empty.fwm"), "").unwrap();
+    fs::create_dir(source.path().join("nested")).unwrap();
+    fs::write(
+        source.path().join("nested/comments.fwm"),
+        "# nothing here\n",
+    )
+    .unwrap();
+
+    let store = StoreManager::new();
+    let manager = MigrationManager::new(store.clone());
+    let job_id = manager
+        .import(
+            "ops",
+            MigrationRequest {
+                path: source.path().to_path_buf(),
+                mode: MigrationMode::Fwm,
+                quarantine: Some(quarantine.path().to_path_buf()),
+                ..MigrationRequest::default()
+            },
+        )
+        .expect("job id");
+    let report = manager.wait(job_id).expect("report");
+
+    assert_eq!((report.total, report.imported, report.failed), (3, 1, 2));
+    assert_eq!(report.quarantined, 2);
+    assert_eq!(report.quarantined_bytes, "# nothing here\n".len() as u64);
+    assert_eq!(report.quarantine_dir.as_deref(), Some(quarantine.path()));
+    let job_dir = quarantine.path().join(job_id.to_string());
+    assert!(job_dir.join("empty.fwm").exists());
+    let sidecar: QuarantineRecord =
+        serde_json::from_slice(&fs::read(job_dir.join("nested/comments.fwm.error.json")).unwrap())
+            .unwrap();
+    assert_eq!(sidecar.job_id, job_id);
+    assert_eq!(sidecar.source, source.path().join("nested/comments.fwm"));
+    assert_eq!(sidecar.error, "file does not contain legacy metadata");
+    assert_eq!(store.list("inbox").len(), 1);
+
+    let archive = source.path().join("broken.fwz");
+    fs::write(&archive, b"not a zip").unwrap();
+    let job_id = manager
+        .import(
+            "ops",
+            MigrationRequest {
+                path: archive,
+                quarantine: Some(quarantine.path().to_path_buf()),
+                ..MigrationRequest::default()
+            },
+        )
+        .expect("job id");
+    let report = manager.wait(job_id).expect("report");
+    assert_eq!(report.quarantined, 1);
+    assert_eq!(
+        manager.progress(job_id).unwrap().status,
+        MigrationStatus::Failed
+    );
+    assert!(quarantine
+        .path()
+        .join(job_id.to_string())
+        .join("broken.fwz.error.json")
+        .exists());
+}
@@ -1,6 +1,7 @@
 use core_service::config::TelemetryConfig;
 use core_service::telemetry::TelemetryManager;
-use std::time::Duration;
+use std::fs::{self, File};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
 
 #[test]
 fn telemetry_records_metrics_and_redacts_pii() {
@@ -11,6 +12,7 @@ fn telemetry_records_metrics_and_redacts_pii() {
         local_path: temp.path().to_string_lossy().to_string(),
         sampling: 1.0,
         retention_days: 7,
+        ..TelemetryConfig::default()
     };
     let telemetry = TelemetryManager::from_config(&config);
     telemetry.record_error("contact admin@example.com for help");
@@ -29,3 +31,88 @@ fn telemetry_records_metrics_and_redacts_pii() {
     let bundle = telemetry.bundle().expect("bundle");
     assert!(!bundle.is_empty());
 }
+
+#[test]
+fn telemetry_samples_flow_events_and_prunes_expired_files() {
+    let temp = tempfile::tempdir().expect("temp directory");
+    let month_ago = SystemTime::now() - Duration::from_secs(30 * 24 * 60 * 60);
+    for name in ["remote-1.bin", "snapshot.json", "remote-2.bin"] {
+        fs::write(temp.path().join(name), b"{}").expect("write");
+    }
+    for name in ["remote-1.bin", "snapshot.json"] {
+        let file = File::options()
+            .write(true)
+            .open(temp.path().join(name))
+            .expect("open");
+        file.set_modified(month_ago).expect("set mtime");
+    }
+    let now = SystemTime::now().duration_since(UNIX_EPOCH).unwrap();
+    let fresh = format!(
+        "{{\"flow\":\"queue.submit\",\"timestamp\":{}}}\n",
+        now.as_millis()
+    );
+    fs::write(
+        temp.path().join("trace.jsonl"),
+        format!(
+            "{{\"flow\":\"queue.submit\",\"timestamp\":1000}}\n\
+             {{\"timestamp\":\"2020-01-01T00:00:00Z\",\"level\":\"INFO\"}}\n{fresh}"
+        ),
+    )
+    .expect("write trace");
+
+    let config = TelemetryConfig {
+        enabled: true,
+        local_path: temp.path().to_string_lossy().to_string(),
+        sampling: 0.0,
+        retention_days: 7,
+        ..TelemetryConfig::default()
+    };
+    let telemetry = TelemetryManager::from_config(&config);
+    let mut report = telemetry.prune().expect("prune");
+    report.removed.sort();
+    assert_eq!(report.removed, ["remote-1.bin", "snapshot.json"]);
+    assert!(temp.path().join("remote-2.bin").exists());
+    let trace = fs::read_to_string(temp.path().join("trace.jsonl")).expect("read trace");
+    assert!(trace.starts_with(&fresh));
+
+    telemetry.record_flow("queue.submit", Duration::from_millis(5), true, 0);
+    telemetry.record_flow("queue.submit", Duration::from_millis(5), false, 0);
+    let snapshot = telemetry.snapshot();
+    assert_eq!(snapshot.metrics.messages_sent, 1);
+    assert_eq!(snapshot.metrics.error_count, 1);
+    assert_eq!(snapshot.events.len(), 1);
+    assert!(!snapshot.events[0].success);
+}
+
+#[test]
+fn pruning_the_trace_log_keeps_concurrent_appends() {
+    let temp = tempfile::tempdir().expect("temp directory");
+    let expired: String = (0..20_000)
+        .map(|n| format!("{{\"flow\":\"old.{n}\",\"timestamp\":1000}}\n"))
+        .collect();
+    fs::write(temp.path().join("trace.jsonl"), expired).expect("write trace");
+
+    let config = TelemetryConfig {
+        enabled: true,
+        local_path: temp.path().to_string_lossy().to_string(),
+        sampling: 1.0,
+        retention_days: 7,
+        ..TelemetryConfig::default()
+    };
+    let telemetry = TelemetryManager::from_config(&config);
+    let writer = {
+        let telemetry = telemetry.clone();
+        std::thread::spawn(move || {
+            for _ in 0..500 {
+                telemetry.record_flow("burst.submit", Duration::from_millis(1), true, 0);
+            }
+        })
+    };
+    let report = telemetry.prune().expect("prune");
+    writer.join().unwrap();
+
+    assert!(report.freed_bytes > 0);
+    let trace = fs::read_to_string(temp.path().join("trace.jsonl")).expect("read trace");
+    assert!(!trace.contains("old."));
+    assert_eq!(trace.matches("\"flow\":\"burst.submit\"").count(), 500);
+}
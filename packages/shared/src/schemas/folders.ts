@@ -1,14 +1,31 @@
 import { z } from 'zod';
 
-export const folderIdSchema = z.enum(['inbox', 'outbox', 'failed', 'archive', 'followUp']);
+export const systemFolderIdSchema = z.enum([
+  'inbox',
+  'outbox',
+  'sent',
+  'drafts',
+  'archive',
+  'quarantine',
+]);
+
+// Custom folders carry generated ids, so any non-empty id is accepted.
+export const folderIdSchema = z.string().min(1);
 
 export const folderSchema = z.object({
   id: folderIdSchema,
   name: z.string(),
-  unreadCount: z.number().int().nonnegative()
+  parentId: folderIdSchema.optional(),
+  system: z.boolean().default(false),
+  unreadCount: z.number().int().nonnegative(),
+  totalCount: z.number().int().nonnegative().default(0),
+  flaggedCount: z.number().int().nonnegative().default(0),
+  answeredCount: z.number().int().nonnegative().default(0),
+  labelCounts: z.record(z.number().int().nonnegative()).default({}),
 });
 
 export const folderListSchema = z.array(folderSchema);
 
+export type SystemFolderId = z.infer<typeof systemFolderIdSchema>;
 export type FolderId = z.infer<typeof folderIdSchema>;
 export type Folder = z.infer<typeof folderSchema>;
@@ -20,6 +20,50 @@ export const attachmentSchema = z.object({
   size: z.number().int().nonnegative(),
 });
 
+export const messageFlagsSchema = z.object({
+  read: z.boolean().default(false),
+  flagged: z.boolean().default(false),
+  answered: z.boolean().default(false),
+  labels: z.array(z.string().min(1).max(64)).default([]),
+});
+
+export const flagsPatchSchema = z.object({
+  read: z.boolean().optional(),
+  flagged: z.boolean().optional(),
+  answered: z.boolean().optional(),
+  addLabels: z
+    .array(z.string().regex(/^\S{1,64}$/, 'Labels are 1-64 characters without whitespace'))
+    .optional(),
+  removeLabels: z.array(z.string()).optional(),
+});
+
+export const bulkRequestSchema = z.discriminatedUnion('action', [
+  z.object({ action: z.literal('delete'), ids: z.array(z.string()).min(1).max(1000) }),
+  z.object({
+    action: z.literal('move'),
+    ids: z.array(z.string()).min(1).max(1000),
+    folder_id: z.string(),
+  }),
+  z.object({ action: z.literal('archive'), ids: z.array(z.string()).min(1).max(1000) }),
+  z.object({ action: z.literal('mark-read'), ids: z.array(z.string()).min(1).max(1000) }),
+]);
+
+export const bulkReportSchema = z.object({
+  action: z.enum(['delete', 'move', 'archive', 'mark-read']),
+  updated: z.array(z.string()),
+  missing: z.array(z.string()),
+});
+
+export const exportRequestSchema = z.object({
+  folders: z.array(z.string()).default([]),
+});
+
+export const importReportSchema = z.object({
+  imported: z.number().int().nonnegative(),
+  skipped: z.number().int().nonnegative(),
+  folders: z.array(z.string()),
+});
+
 export const messageEnvelopeSchema = z.object({
   id: z.string(),
   subject: z.string(),
@@ -34,6 +78,9 @@ export const messageEnvelopeSchema = z.object({
   createdAt: z.string(),
   updatedAt: z.string(),
   messageId: z.string(),
+  flags: messageFlagsSchema.default({}),
+  threadId: z.string().optional(),
+  inReplyTo: z.string().optional(),
 });
 
 export const messageContentSchema = z.object({
@@ -47,8 +94,22 @@ export const messageSchema = z.object({
   reports: z.array(reportSchema).default([]),
 });
 
+export const threadSchema = z.object({
+  threadId: z.string(),
+  subject: z.string(),
+  latestAt: z.string(),
+  messages: z.array(messageEnvelopeSchema),
+});
+
 export type MessageStatus = z.infer<typeof messageStatusSchema>;
 export type Attachment = z.infer<typeof attachmentSchema>;
+export type MessageFlags = z.infer<typeof messageFlagsSchema>;
+export type FlagsPatch = z.infer<typeof flagsPatchSchema>;
+export type BulkRequest = z.infer<typeof bulkRequestSchema>;
+export type BulkReport = z.infer<typeof bulkReportSchema>;
+export type ExportRequest = z.infer<typeof exportRequestSchema>;
+export type ImportReport = z.infer<typeof importReportSchema>;
 export type MessageEnvelope = z.infer<typeof messageEnvelopeSchema>;
 export type MessageContent = z.infer<typeof messageContentSchema>;
 export type Message = z.infer<typeof messageSchema>;
+export type Thread = z.infer<typeof threadSchema>;
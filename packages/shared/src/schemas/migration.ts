@@ -1,6 +1,6 @@
 import { z } from 'zod';
 
-export const migrationModeSchema = z.enum(['auto', 'fwm', 'fwz']);
+export const migrationModeSchema = z.enum(['auto', 'fwm', 'fwz', 'eml', 'mbox']);
 
 export const migrationRequestSchema = z.object({
   path: z.string().min(1, 'path is required'),
@@ -10,11 +10,12 @@ export const migrationRequestSchema = z.object({
   limit: z.number().int().positive().optional(),
   since: z.string().datetime().optional(),
   quarantine: z.string().optional(),
+  encoding: z.string().min(1).optional(),
 });
 
 export const migrationProgressSchema = z.object({
   jobId: z.string().uuid(),
-  status: z.enum(['pending', 'running', 'completed', 'failed']),
+  status: z.enum(['pending', 'running', 'paused', 'completed', 'failed', 'cancelled']),
   total: z.number().int().nonnegative(),
   processed: z.number().int().nonnegative(),
   imported: z.number().int().nonnegative(),
@@ -33,6 +34,29 @@ export const migrationErrorRecordSchema = z.object({
   message: z.string(),
 });
 
+export const dryRunDiffSchema = z.object({
+  newMessages: z.array(z.object({ path: z.string(), subject: z.string(), folder: z.string() })),
+  duplicates: z.array(
+    z.object({
+      path: z.string(),
+      subject: z.string(),
+      existingId: z.string(),
+      existingFolder: z.string(),
+    }),
+  ),
+  folderConflicts: z.array(
+    z.object({ path: z.string(), folder: z.string(), existingFolder: z.string().optional() }),
+  ),
+  unmappableAddresses: z.array(z.object({ path: z.string(), address: z.string() })),
+});
+
+export const entryVerificationSchema = z.object({
+  name: z.string(),
+  check: z.enum(['verified', 'mismatch', 'missing', 'unlisted']),
+  expected: z.string().optional(),
+  actual: z.string().optional(),
+});
+
 export const migrationReportSchema = z.object({
   jobId: z.string().uuid(),
   startedAt: z.string().datetime(),
@@ -43,8 +67,14 @@ export const migrationReportSchema = z.object({
   duplicates: z.number().int().nonnegative(),
   dryRun: z.boolean(),
   checksumOk: z.boolean(),
+  quarantined: z.number().int().nonnegative().default(0),
+  quarantinedBytes: z.number().int().nonnegative().default(0),
+  quarantineDir: z.string().nullable().optional(),
   notes: z.array(z.string()).default([]),
   errors: z.array(migrationErrorRecordSchema).default([]),
+  diff: dryRunDiffSchema.optional(),
+  diffText: z.string().optional(),
+  verification: z.array(entryVerificationSchema).default([]),
 });
 
 export type MigrationMode = z.infer<typeof migrationModeSchema>;
@@ -52,3 +82,5 @@ export type MigrationRequest = z.infer<typeof migrationRequestSchema>;
 export type MigrationProgress = z.infer<typeof migrationProgressSchema>;
 export type MigrationReport = z.infer<typeof migrationReportSchema>;
 export type MigrationErrorRecord = z.infer<typeof migrationErrorRecordSchema>;
+export type DryRunDiff = z.infer<typeof dryRunDiffSchema>;
+export type EntryVerification = z.infer<typeof entryVerificationSchema>;